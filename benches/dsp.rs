@@ -0,0 +1,90 @@
+//! Block throughput of the filters in `fullyrustaudio::dsp`, as a baseline
+//! for work on them. Run with `cargo bench --bench dsp`.
+
+use fullyrustaudio::{
+    dsp::{BiquadFilter, FilterChain, FilterType},
+    FREQUENCIES, SHELF_Q,
+};
+use std::{hint::black_box, time::Instant};
+
+const SAMPLE_RATE: u32 = 48_000;
+const BLOCK_FRAMES: usize = 512;
+/// Frames run through each case, about a minute of audio.
+const FRAMES: usize = 60 * SAMPLE_RATE as usize;
+
+/// Noise from a fixed seed, so every run filters the same samples.
+fn noise(samples: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491_u32;
+    (0..samples)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 * 2.0 - 1.0
+        })
+        .collect()
+}
+
+/// The ten-band layout the player starts with: shelves at either end,
+/// peaking bands between.
+fn ten_band() -> Vec<BiquadFilter> {
+    FREQUENCIES
+        .iter()
+        .enumerate()
+        .map(|(i, &frequency)| {
+            let kind = match i {
+                0 => FilterType::LowShelf,
+                9 => FilterType::HighShelf,
+                _ => FilterType::Peaking,
+            };
+            let q = match kind {
+                FilterType::Peaking => 1.41,
+                _ => SHELF_Q,
+            };
+            BiquadFilter::with_type(kind, frequency, q, 3.0, SAMPLE_RATE)
+        })
+        .collect()
+}
+
+/// Runs `process` over blocks of `channels`-channel noise until `FRAMES`
+/// frames have gone through, and prints the rate.
+fn bench(name: &str, channels: usize, mut process: impl FnMut(&mut [f32])) {
+    let source = noise(BLOCK_FRAMES * channels);
+    let mut block = source.clone();
+    let blocks = FRAMES / BLOCK_FRAMES;
+    let started = Instant::now();
+    for _ in 0..blocks {
+        block.copy_from_slice(&source);
+        process(black_box(&mut block));
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let samples = (blocks * BLOCK_FRAMES * channels) as f64;
+    println!(
+        "{name:<28} {:>8.1} Msamples/s {:>8.0}x real time",
+        samples / elapsed / 1e6,
+        (blocks * BLOCK_FRAMES) as f64 / SAMPLE_RATE as f64 / elapsed,
+    );
+}
+
+fn main() {
+    let mut filter = BiquadFilter::new(1000.0, 1.41, 6.0, SAMPLE_RATE);
+    bench("biquad, mono", 1, |block| filter.process_block(block));
+
+    let mut chain = FilterChain::with_filters(1, &ten_band());
+    bench("ten bands, mono", 1, |block| chain.process_block(block));
+
+    let mut chain = FilterChain::with_filters(2, &ten_band());
+    bench("ten bands, stereo", 2, |block| chain.process_block(block));
+
+    let mut chain = FilterChain::with_filters(6, &ten_band());
+    bench("ten bands, 5.1", 6, |block| chain.process_block(block));
+
+    let mut chain = FilterChain::new(2);
+    for i in 0..31 {
+        let frequency = 20.0 * 2f32.powf(i as f32 / 3.0);
+        chain.push(BiquadFilter::new(frequency, 4.32, 2.0, SAMPLE_RATE));
+    }
+    bench("thirty-one bands, stereo", 2, |block| {
+        chain.process_block(block)
+    });
+}
@@ -0,0 +1,48 @@
+//! Plays two files at once on one output device, adjusting each on its own.
+//!
+//! `cargo run --example mix -- <first> <second>`
+
+use fullyrustaudio::{AudioEngine, PlayerError};
+use std::{env, process, thread, time::Duration};
+
+fn main() {
+    let paths = env::args().skip(1).collect::<Vec<_>>();
+    let [first, second] = paths.as_slice() else {
+        eprintln!("usage: mix <first> <second>");
+        process::exit(2);
+    };
+    if let Err(err) = run(first, second) {
+        eprintln!("{err}");
+        process::exit(1);
+    }
+}
+
+fn run(first: &str, second: &str) -> Result<(), PlayerError> {
+    let engine = AudioEngine::new()?;
+    let deck_a = engine.create_player(first)?;
+    let deck_b = engine.create_player(second)?;
+
+    deck_b.set_volume_db(-12.0);
+    deck_b.set_eq_gains(&[-12.0; 10])?;
+    deck_a.play()?;
+    deck_b.play()?;
+    thread::sleep(Duration::from_secs(5));
+
+    // Bring the second deck up while the first one jumps ahead and fades out.
+    deck_b.set_volume_db(0.0);
+    deck_b.set_eq_gains(&[0.0; 10])?;
+    deck_a.seek_forward(Duration::from_secs(30))?;
+    thread::sleep(Duration::from_secs(5));
+    deck_a.pause();
+    println!(
+        "first at {:?} (paused), second at {:?}",
+        deck_a.get_playback_position(),
+        deck_b.get_playback_position()
+    );
+
+    // Dropping the first deck leaves the second one playing.
+    drop(deck_a);
+    engine.set_master_volume_db(-6.0);
+    thread::sleep(Duration::from_secs(5));
+    Ok(())
+}
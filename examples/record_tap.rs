@@ -0,0 +1,73 @@
+//! Records ten seconds of what a player plays, EQ and effects included,
+//! from a PCM tap into a 32-bit float WAV.
+//!
+//! `cargo run --example record_tap -- <input> <output.wav>`
+
+use fullyrustaudio::AudioPlayer;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::{env, error::Error, process, thread, time::Duration};
+
+const SECONDS: usize = 10;
+
+fn main() {
+    let paths = env::args().skip(1).collect::<Vec<_>>();
+    let [input, output] = paths.as_slice() else {
+        eprintln!("usage: record_tap <input> <output.wav>");
+        process::exit(2);
+    };
+    if let Err(err) = run(input, output) {
+        eprintln!("{err}");
+        process::exit(1);
+    }
+}
+
+fn run(input: &str, output: &str) -> Result<(), Box<dyn Error>> {
+    let player = AudioPlayer::open(input)?;
+    player.set_eq_gains(&[6.0, 4.0, 0.0, 0.0, -2.0, -2.0, 0.0, 2.0, 4.0, 6.0])?;
+    // A second of room, read far more often than that.
+    let mut tap = player.pcm_tap(48_000);
+    player.play()?;
+
+    let mut writer = None;
+    let (mut recorded, mut dropped) = (0, 0);
+    let mut samples = Vec::new();
+    while !player.is_finished() || recorded == 0 {
+        thread::sleep(Duration::from_millis(50));
+        let read = tap.read(&mut samples);
+        if read.frames == 0 {
+            continue;
+        }
+        let writer = match &mut writer {
+            Some(writer) => writer,
+            None => {
+                let spec = WavSpec {
+                    channels: read.channels,
+                    sample_rate: read.sample_rate,
+                    bits_per_sample: 32,
+                    sample_format: SampleFormat::Float,
+                };
+                writer.insert(WavWriter::create(output, spec)?)
+            }
+        };
+        let wanted = SECONDS * writer.spec().sample_rate as usize - recorded;
+        let frames = read.frames.min(wanted);
+        for &sample in &samples[..frames * read.channels as usize] {
+            writer.write_sample(sample)?;
+        }
+        recorded += frames;
+        dropped += read.dropped;
+        if frames == wanted {
+            break;
+        }
+    }
+    player.stop();
+    if let Some(writer) = writer {
+        let rate = writer.spec().sample_rate;
+        writer.finalize()?;
+        println!(
+            "wrote {recorded} frames ({:.1} s) to {output}, {dropped} dropped",
+            recorded as f64 / rate as f64
+        );
+    }
+    Ok(())
+}
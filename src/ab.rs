@@ -0,0 +1,101 @@
+use crate::{equalizer::loudness_gain_db, settings::EqSettings, shuffle::Rng};
+use std::fmt;
+
+/// One side of the A/B comparison set with
+/// [`AudioPlayer::set_ab`](crate::AudioPlayer::set_ab).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AB {
+    A,
+    B,
+}
+
+impl AB {
+    pub fn name(self) -> &'static str {
+        match self {
+            AB::A => "A",
+            AB::B => "B",
+        }
+    }
+
+    /// The side switched to from this one.
+    pub fn other(self) -> AB {
+        match self {
+            AB::A => AB::B,
+            AB::B => AB::A,
+        }
+    }
+}
+
+impl fmt::Display for AB {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Two EQ settings matched in loudness, and which of them plays.
+pub(crate) struct AbComparison {
+    /// What plays as A, and as B.
+    sides: [EqSettings; 2],
+    /// Whether A plays the settings given second.
+    swapped: bool,
+    /// Whether the labels were dealt at random and not revealed yet.
+    blind: bool,
+    current: AB,
+}
+
+impl AbComparison {
+    /// Compares `a` and `b`, each given a fixed preamp so both are as loud
+    /// at `sample_rate` as the quieter of them was; `blind`, which plays as
+    /// A is left to chance.
+    pub(crate) fn new(a: EqSettings, b: EqSettings, blind: bool, sample_rate: u32) -> Self {
+        let target_db = loudness_gain_db(&a, sample_rate).min(loudness_gain_db(&b, sample_rate));
+        let a = at_loudness(a, target_db, sample_rate);
+        let b = at_loudness(b, target_db, sample_rate);
+        let swapped = blind && Rng::seeded().next_u64() & 1 == 1;
+        AbComparison {
+            sides: if swapped { [b, a] } else { [a, b] },
+            swapped,
+            blind,
+            current: AB::A,
+        }
+    }
+
+    pub(crate) fn current(&self) -> AB {
+        self.current
+    }
+
+    pub(crate) fn settings(&self) -> &EqSettings {
+        match self.current {
+            AB::A => &self.sides[0],
+            AB::B => &self.sides[1],
+        }
+    }
+
+    /// Switches to the other side, returning it.
+    pub(crate) fn toggle(&mut self) -> AB {
+        self.current = self.current.other();
+        self.current
+    }
+
+    /// Which side plays the settings given first, ending the blindness.
+    pub(crate) fn reveal(&mut self) -> AB {
+        self.blind = false;
+        if self.swapped {
+            AB::B
+        } else {
+            AB::A
+        }
+    }
+
+    pub(crate) fn is_blind(&self) -> bool {
+        self.blind
+    }
+}
+
+/// `settings` with a fixed preamp that brings them to `target_db`.
+fn at_loudness(mut settings: EqSettings, target_db: f32, sample_rate: u32) -> EqSettings {
+    settings.auto_headroom = false;
+    settings.preamp_db = 0.0;
+    settings.preamp_db = target_db - loudness_gain_db(&settings, sample_rate);
+    settings
+}
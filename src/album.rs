@@ -0,0 +1,276 @@
+//! Album playback: queued tracks off the same album, one after another,
+//! play at one gain for the lot, so levels don't step or breathe where a
+//! continuous piece crosses from one track into the next.
+
+use crate::{
+    atomic::AtomicF32,
+    gain::{db_to_linear, GainControls},
+    lock::Lock,
+    loudness::{scan_album, TrackLoudness},
+    metadata::ReplayGainTags,
+    queue::{Origin, Track},
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How long a track takes to glide to a new gain, and from the gain of the
+/// track before.
+const ALBUM_RAMP: Duration = Duration::from_millis(200);
+
+/// The level ReplayGain 2.0 brings tracks to, used to measure an album
+/// whose files aren't tagged.
+pub(crate) const REPLAYGAIN_REFERENCE_LUFS: f32 = -18.0;
+
+/// Where the gain a track plays at comes from; see
+/// [`AudioPlayer::track_gain`](crate::AudioPlayer::track_gain).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GainMode {
+    /// No gain: ReplayGain and normalization are both off.
+    Off,
+    /// The track's own ReplayGain tag.
+    ReplayGainTrack,
+    /// The album ReplayGain tag, of the track or of every track in its
+    /// album run.
+    ReplayGainAlbum,
+    /// The ReplayGain default, for a file without tags.
+    ReplayGainDefault,
+    /// Loudness normalization by the track's own measured loudness, 0 dB
+    /// until it has been measured.
+    Normalized,
+    /// Normalization by the loudness of the whole album run, measured in
+    /// one pass over its files.
+    AlbumNormalized,
+}
+
+impl GainMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            GainMode::Off => "off",
+            GainMode::ReplayGainTrack => "replaygain-track",
+            GainMode::ReplayGainAlbum => "replaygain-album",
+            GainMode::ReplayGainDefault => "replaygain-default",
+            GainMode::Normalized => "normalized",
+            GainMode::AlbumNormalized => "album-normalized",
+        }
+    }
+}
+
+impl fmt::Display for GainMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The gain a track plays at, and where it comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackGain {
+    pub mode: GainMode,
+    pub gain_db: f32,
+}
+
+impl TrackGain {
+    pub(crate) const OFF: TrackGain = TrackGain {
+        mode: GainMode::Off,
+        gain_db: 0.0,
+    };
+
+    /// Whether the gain comes from ReplayGain tags or their default.
+    pub(crate) fn is_replaygain(&self) -> bool {
+        matches!(
+            self.mode,
+            GainMode::ReplayGainTrack | GainMode::ReplayGainAlbum | GainMode::ReplayGainDefault
+        )
+    }
+}
+
+/// Tracks next to each other in the queue with the same album tag.
+pub(crate) struct AlbumRun {
+    /// In queue order.
+    pub(crate) ids: Vec<u64>,
+    paths: Vec<PathBuf>,
+    /// Each track's ReplayGain tags.
+    pub(crate) tags: Vec<ReplayGainTags>,
+    measured: OnceLock<Option<TrackLoudness>>,
+    scanning: AtomicBool,
+}
+
+impl AlbumRun {
+    /// The loudness of the run as a whole, once measured and if it could be.
+    pub(crate) fn measured(&self) -> Option<TrackLoudness> {
+        self.measured.get().copied().flatten()
+    }
+
+    /// Returns whether the run still needs measuring, and marks it as under
+    /// way.
+    pub(crate) fn start_scan(&self) -> bool {
+        self.measured.get().is_none() && !self.scanning.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// One track's gain in album playback, and what it is set to.
+struct Level {
+    controls: Arc<GainControls>,
+    gain: TrackGain,
+}
+
+/// Whether album playback is on, the album runs in the queue and the gain
+/// each track plays at.
+pub(crate) struct AlbumControls {
+    enabled: AtomicBool,
+    /// Runs only form in queue order.
+    shuffled: AtomicBool,
+    runs: Mutex<Vec<Arc<AlbumRun>>>,
+    levels: Mutex<HashMap<u64, Level>>,
+    /// What the last track to play was turned up or down by, which the next
+    /// starts from.
+    handover: Arc<AtomicF32>,
+}
+
+impl AlbumControls {
+    pub(crate) fn new() -> Self {
+        AlbumControls {
+            enabled: AtomicBool::new(false),
+            shuffled: AtomicBool::new(false),
+            runs: Mutex::new(Vec::new()),
+            levels: Mutex::new(HashMap::new()),
+            handover: Arc::new(AtomicF32::new(f32::NAN)),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_shuffled(&self, shuffled: bool) {
+        self.shuffled.store(shuffled, Ordering::Relaxed);
+    }
+
+    /// Groups `tracks`, in queue order, into album runs: two or more files
+    /// in a row with the same album tag. A run made up of the same tracks
+    /// as before is kept, along with its measurement. None form while off or
+    /// shuffled.
+    pub(crate) fn regroup(&self, tracks: &[Track]) -> Vec<Arc<AlbumRun>> {
+        let mut runs = self.runs.locked();
+        let mut grouped = Vec::new();
+        if self.is_enabled() && !self.shuffled.load(Ordering::Relaxed) {
+            let mut start = 0;
+            while start < tracks.len() {
+                let len = match album(&tracks[start]) {
+                    Some(name) => tracks[start..]
+                        .iter()
+                        .take_while(|track| album(track) == Some(name))
+                        .count(),
+                    None => 1,
+                };
+                let members = &tracks[start..start + len];
+                start += len;
+                if len < 2 {
+                    continue;
+                }
+                let ids = members.iter().map(|track| track.id).collect::<Vec<_>>();
+                let run = match runs.iter().find(|run| run.ids == ids) {
+                    Some(run) => run.clone(),
+                    None => {
+                        // Tracks of one cue sheet share a file, measured once.
+                        let mut paths = members
+                            .iter()
+                            .map(|track| track.path.clone())
+                            .collect::<Vec<_>>();
+                        paths.dedup();
+                        Arc::new(AlbumRun {
+                            ids,
+                            paths,
+                            tags: members
+                                .iter()
+                                .map(|track| track.metadata.replay_gain)
+                                .collect(),
+                            measured: OnceLock::new(),
+                            scanning: AtomicBool::new(false),
+                        })
+                    }
+                };
+                grouped.push(run);
+            }
+        }
+        *runs = grouped.clone();
+        grouped
+    }
+
+    /// The album run track `id` is in, if any.
+    pub(crate) fn run_of(&self, id: u64) -> Option<Arc<AlbumRun>> {
+        self.runs
+            .locked()
+            .iter()
+            .find(|run| run.ids.contains(&id))
+            .cloned()
+    }
+
+    /// The gain stage track `id` plays through, set to `gain` if it's new.
+    pub(crate) fn controls(&self, id: u64, gain: TrackGain) -> Arc<GainControls> {
+        let mut levels = self.levels.locked();
+        let level = levels.entry(id).or_insert_with(|| Level {
+            controls: Arc::new(GainControls::new(db_to_linear(gain.gain_db), ALBUM_RAMP)),
+            gain,
+        });
+        level.controls.clone()
+    }
+
+    /// Moves track `id` over to `gain`, gliding if it's playing.
+    pub(crate) fn set_gain(&self, id: u64, gain: TrackGain) {
+        let mut levels = self.levels.locked();
+        if let Some(level) = levels.get_mut(&id) {
+            level.controls.set_target(db_to_linear(gain.gain_db));
+            level.gain = gain;
+        }
+    }
+
+    /// What track `id` is set to play at, once it has been opened.
+    pub(crate) fn gain(&self, id: u64) -> Option<TrackGain> {
+        self.levels.locked().get(&id).map(|level| level.gain)
+    }
+
+    /// Forgets the gains of tracks no longer in `tracks`.
+    pub(crate) fn retain(&self, tracks: &[Track]) {
+        self.levels
+            .locked()
+            .retain(|id, _| tracks.iter().any(|track| track.id == *id));
+    }
+
+    pub(crate) fn handover(&self) -> Arc<AtomicF32> {
+        self.handover.clone()
+    }
+}
+
+/// The album `track` is off, a file's non-empty album tag.
+fn album(track: &Track) -> Option<&str> {
+    Some(track.metadata.album.as_deref()?.trim())
+        .filter(|album| !album.is_empty() && matches!(track.origin, Origin::File))
+}
+
+/// Measures album runs one after another on a thread of their own, calling
+/// `measured` after each. The thread exits once the returned sender is
+/// dropped.
+pub(crate) fn spawn_album_scanner(measured: impl Fn() + Send + 'static) -> Sender<Arc<AlbumRun>> {
+    let (jobs, receiver) = mpsc::channel::<Arc<AlbumRun>>();
+    thread::spawn(move || {
+        for run in receiver {
+            let _ = run.measured.set(scan_album(&run.paths));
+            measured();
+        }
+    });
+    jobs
+}
@@ -0,0 +1,18 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// An `f32` that can be shared between the control and audio threads.
+pub(crate) struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    pub(crate) fn new(value: f32) -> Self {
+        AtomicF32(AtomicU32::new(value.to_bits()))
+    }
+
+    pub(crate) fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
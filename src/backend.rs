@@ -0,0 +1,115 @@
+use crate::error::PlayerError;
+use rodio::cpal::{
+    self,
+    traits::{DeviceTrait, HostTrait},
+    Host,
+};
+use std::{fmt, str::FromStr};
+
+/// The one device [`Backend::Null`] has.
+pub(crate) const NULL_DEVICE: &str = "null";
+
+/// The audio API an [`AudioEngine`] plays through, cpal's hosts by another
+/// name. Which ones exist depends on the platform and on the cpal features
+/// built in: JACK and ASIO need cpal's `jack` and `asio` features.
+///
+/// [`AudioEngine`]: crate::AudioEngine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Backend {
+    /// The platform's usual one: ALSA, CoreAudio or WASAPI.
+    #[default]
+    Default,
+    Alsa,
+    Jack,
+    CoreAudio,
+    Wasapi,
+    Asio,
+    /// No device at all: the mix is played into nothing, at the pace it
+    /// would be heard, on every platform. For running headless, as in tests.
+    Null,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 7] = [
+        Backend::Default,
+        Backend::Alsa,
+        Backend::Jack,
+        Backend::CoreAudio,
+        Backend::Wasapi,
+        Backend::Asio,
+        Backend::Null,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Default => "default",
+            Backend::Alsa => "ALSA",
+            Backend::Jack => "JACK",
+            Backend::CoreAudio => "CoreAudio",
+            Backend::Wasapi => "WASAPI",
+            Backend::Asio => "ASIO",
+            Backend::Null => "null",
+        }
+    }
+
+    /// The backends built in that can be used right now, e.g. leaving out
+    /// JACK while no JACK server is running.
+    pub fn available() -> Vec<Backend> {
+        let hosts = cpal::available_hosts();
+        Backend::ALL
+            .into_iter()
+            .filter(|backend| {
+                matches!(backend, Backend::Default | Backend::Null)
+                    || hosts.iter().any(|host| host.name() == backend.name())
+            })
+            .collect()
+    }
+
+    /// Names of the output devices this backend offers.
+    pub fn output_devices(self) -> Result<Vec<String>, PlayerError> {
+        if self == Backend::Null {
+            return Ok(vec![NULL_DEVICE.to_string()]);
+        }
+        let devices = self
+            .host()?
+            .output_devices()
+            .map_err(|err| PlayerError::Device(err.to_string()))?;
+        Ok(devices.filter_map(|device| device.name().ok()).collect())
+    }
+
+    pub(crate) fn host(self) -> Result<Host, PlayerError> {
+        if self == Backend::Default {
+            return Ok(cpal::default_host());
+        }
+        let unavailable = || PlayerError::Backend {
+            requested: self,
+            available: Backend::available(),
+        };
+        let id = cpal::ALL_HOSTS
+            .iter()
+            .find(|host| host.name() == self.name())
+            .ok_or_else(unavailable)?;
+        cpal::host_from_id(*id).map_err(|_| unavailable())
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    /// Looks a backend up by name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Backend::ALL
+            .into_iter()
+            .find(|backend| backend.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = Backend::ALL.map(Backend::name).join(", ");
+                format!("unknown audio backend '{s}', expected one of {names}")
+            })
+    }
+}
@@ -0,0 +1,180 @@
+use crate::format::{json, Value};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Positions this close to the end of a track count as having finished it.
+const FINISHED_MARGIN: Duration = Duration::from_secs(10);
+
+/// Where a file was left, and which version of the file that was.
+#[derive(Debug, Clone, PartialEq)]
+struct Bookmark {
+    path: PathBuf,
+    size: u64,
+    /// Modification time, in seconds since the Unix epoch.
+    modified: f64,
+    position: Duration,
+    /// When the bookmark was recorded, in seconds since the Unix epoch.
+    saved: f64,
+}
+
+/// The last position in each file played, so long ones such as audiobooks
+/// can pick up where they were left. Kept as JSON in a file of its own.
+///
+/// Files are told apart by path, size and modification time, so one that
+/// was moved or changed starts over rather than at a position that no
+/// longer means anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmarks {
+    path: PathBuf,
+    entries: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    /// `bookmarks.json` in the user's data directory: `$XDG_DATA_HOME` or
+    /// `~/.local/share` on Linux, `~/Library/Application Support` on macOS
+    /// and `%APPDATA%` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join("bookmarks.json"))
+    }
+
+    /// Reads the bookmarks saved at `path`. A missing file is no bookmarks.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let mut bookmarks = Bookmarks {
+            path,
+            entries: Vec::new(),
+        };
+        if text.trim().is_empty() {
+            return Ok(bookmarks);
+        }
+        let value =
+            json::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let entries = value
+            .get("bookmarks")
+            .and_then(Value::as_array)
+            .unwrap_or_default();
+        // Entries that don't parse are dropped rather than failing the lot.
+        bookmarks.entries = entries.iter().filter_map(Bookmark::from_value).collect();
+        Ok(bookmarks)
+    }
+
+    /// Where [`Bookmarks::save`] writes.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where `file` was left, unless it has changed since.
+    pub fn position(&self, file: impl AsRef<Path>) -> Option<Duration> {
+        let (path, size, modified) = identify(file.as_ref())?;
+        self.entries
+            .iter()
+            .find(|entry| entry.path == path && entry.size == size && entry.modified == modified)
+            .map(|entry| entry.position)
+    }
+
+    /// Records `position` in `file`, replacing what was there. Within the
+    /// last ten seconds of `duration` the file counts as finished and its
+    /// bookmark is removed instead. Files that can't be read aren't recorded.
+    pub fn record(
+        &mut self,
+        file: impl AsRef<Path>,
+        position: Duration,
+        duration: Option<Duration>,
+    ) {
+        let Some((path, size, modified)) = identify(file.as_ref()) else {
+            return;
+        };
+        self.entries.retain(|entry| entry.path != path);
+        let finished = duration.is_some_and(|duration| position + FINISHED_MARGIN >= duration);
+        if finished {
+            return;
+        }
+        self.entries.push(Bookmark {
+            path,
+            size,
+            modified,
+            position,
+            saved: seconds(SystemTime::now()),
+        });
+    }
+
+    pub fn remove(&mut self, file: impl AsRef<Path>) {
+        let file = file.as_ref();
+        let path = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        self.entries.retain(|entry| entry.path != path);
+    }
+
+    /// Writes the bookmarks back to [`Bookmarks::path`], creating its directory.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let entries = self
+            .entries
+            .iter()
+            .map(Bookmark::to_value)
+            .collect::<Vec<_>>();
+        fs::write(
+            &self.path,
+            json::to_string(&Value::table().with("bookmarks", entries)),
+        )
+    }
+}
+
+impl Bookmark {
+    fn to_value(&self) -> Value {
+        Value::table()
+            .with("path", self.path.to_string_lossy().into_owned())
+            .with("size", self.size)
+            .with("modified", self.modified)
+            .with("position", self.position.as_secs_f64())
+            .with("saved", self.saved)
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        let number = |key| value.get(key).and_then(Value::as_f64);
+        Some(Bookmark {
+            path: PathBuf::from(value.get("path")?.as_str()?),
+            size: number("size")? as u64,
+            modified: number("modified")?,
+            position: Duration::try_from_secs_f64(number("position")?).ok()?,
+            saved: number("saved").unwrap_or(0.0),
+        })
+    }
+}
+
+/// The program's own directory in the user's data directory.
+pub(crate) fn data_dir() -> Option<PathBuf> {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| home().map(|home| home.join(".local/share")))
+    };
+    dir.map(|dir| dir.join("fullyrustaudio"))
+}
+
+/// The canonical path, size and modification time of `file`.
+fn identify(file: &Path) -> Option<(PathBuf, u64, f64)> {
+    let path = fs::canonicalize(file).ok()?;
+    let metadata = fs::metadata(&path).ok()?;
+    Some((path, metadata.len(), seconds(metadata.modified().ok()?)))
+}
+
+pub(crate) fn seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64())
+}
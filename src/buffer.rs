@@ -0,0 +1,626 @@
+use crate::{
+    events::{PlayerEvent, Signal},
+    lock::Lock,
+    queue::PlaylistControls,
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        mpsc::Sender,
+        Arc, Mutex, OnceLock, Weak,
+    },
+    thread::{self, Thread},
+    time::Duration,
+};
+
+/// How little decoded audio lies ahead before playback holds to buffer,
+/// and how much it buffers before playing on.
+pub const DEFAULT_LOW_WATERMARK: Duration = Duration::from_millis(500);
+pub const DEFAULT_HIGH_WATERMARK: Duration = Duration::from_secs(2);
+
+/// The highest the high watermark goes.
+pub const MAX_WATERMARK: Duration = Duration::from_secs(60);
+
+/// Frames decoded at a time.
+const CHUNK_FRAMES: usize = 1024;
+
+/// Frames decoded and thrown away at a time, getting forward to a seek the
+/// decoder can't make itself.
+const SKIP_FRAMES: usize = 16 * CHUNK_FRAMES;
+
+/// How many tracks are decoded ahead at once, the current one first.
+const TRACKS_AHEAD: usize = 2;
+
+/// How long the decoding thread sleeps with nothing to decode.
+const IDLE: Duration = Duration::from_millis(10);
+
+/// Where playback stands; see
+/// [`AudioPlayer::playback_state`](crate::AudioPlayer::playback_state).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PlaybackState {
+    /// Playback holds for a track's first audio, after opening or a seek,
+    /// up to the low watermark.
+    Opening,
+    /// Playback holds while the buffer fills to the high watermark.
+    Buffering,
+    Playing,
+    Paused,
+    /// Stopped, or played to the end of the queue.
+    Ended,
+}
+
+impl PlaybackState {
+    pub fn name(self) -> &'static str {
+        match self {
+            PlaybackState::Opening => "opening",
+            PlaybackState::Buffering => "buffering",
+            PlaybackState::Playing => "playing",
+            PlaybackState::Paused => "paused",
+            PlaybackState::Ended => "ended",
+        }
+    }
+}
+
+impl fmt::Display for PlaybackState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Decoded samples handed from the decoding thread to the audio thread
+/// without locking; one writes and the other reads.
+struct Ring {
+    samples: Box<[AtomicU32]>,
+    /// Samples written and read so far, counting on past the end.
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+impl Ring {
+    fn new(samples: usize) -> Self {
+        Ring {
+            samples: (0..samples.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    /// Samples written and not read yet.
+    fn filled(&self) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        written.wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+
+    fn slot(&self, position: usize) -> &AtomicU32 {
+        &self.samples[position % self.samples.len()]
+    }
+}
+
+/// One track's decoder and what of it is decoded ahead, shared by its
+/// [`DecodeAhead`] and the decoding thread.
+struct Ahead {
+    track: u64,
+    /// Locked by the decoding thread while it decodes, and by a seek.
+    decoder: Mutex<Box<dyn Source<Item = f32> + Send>>,
+    /// Sized to the high watermark once the track is first decoded ahead.
+    ring: OnceLock<Ring>,
+    channels: usize,
+    sample_rate: u32,
+    /// The decoder has nothing more to give, until a seek.
+    done: AtomicBool,
+    /// The buffer reached the low watermark since opening or the last seek,
+    /// which is all playback waits for to start; a hold after that is for
+    /// buffering.
+    opened: AtomicBool,
+    /// Where in the file, in samples, what the decoder writes next goes,
+    /// past any `skip`. Changed only under the decoder lock.
+    decoded: AtomicUsize,
+    /// Samples still to be decoded and thrown away before writing.
+    skip: AtomicUsize,
+    /// The buffer reached the high watermark, or the end, since it last
+    /// fell below the low one; playback holds until it has.
+    primed: AtomicBool,
+}
+
+impl Ahead {
+    /// The samples of the whole frames in `time`.
+    fn samples(&self, time: Duration) -> usize {
+        (time.as_secs_f64() * self.sample_rate as f64) as usize * self.channels
+    }
+
+    fn level(&self) -> Duration {
+        let frames = self.ring.get().map_or(0, Ring::filled) / self.channels;
+        Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// The most the buffer is filled to.
+    fn target(&self, ring: &Ring, high: Duration) -> usize {
+        self.samples(high).max(1).min(ring.samples.len())
+    }
+
+    /// What the buffer holds at the low watermark, kept below what it holds
+    /// at all, so filling it ever lets playback go on.
+    fn low(&self, ring: Option<&Ring>, low: Duration) -> usize {
+        let room = ring.map_or(usize::MAX, |ring| ring.samples.len() / 2);
+        self.samples(low).min(room).max(self.channels)
+    }
+
+    /// Decodes a chunk if the buffer is below the high watermark. Returns
+    /// whether there was anything to do.
+    fn fill(&self, high: Duration) -> bool {
+        let mut decoder = self.decoder.locked();
+        if self.done.load(Ordering::Acquire) {
+            return false;
+        }
+        let chunk = CHUNK_FRAMES * self.channels;
+        let ring = self
+            .ring
+            .get_or_init(|| Ring::new(self.samples(high) + chunk));
+        let skip = self.skip.load(Ordering::Relaxed);
+        if skip > 0 {
+            let count = skip.min(SKIP_FRAMES * self.channels);
+            let skipped = (&mut *decoder).take(count).count();
+            if skipped < count {
+                self.done.store(true, Ordering::Release);
+            }
+            self.skip.store(skip - count, Ordering::Relaxed);
+            return true;
+        }
+        let filled = ring.filled();
+        if filled >= self.target(ring, high) {
+            return false;
+        }
+        // Whole frames only, so the audio thread never runs out mid-frame.
+        let room = ring.samples.len() - filled;
+        let written = ring.written.load(Ordering::Relaxed);
+        let mut count = 0;
+        while count < chunk.min(room - room % self.channels) {
+            let Some(sample) = decoder.next() else {
+                self.done.store(true, Ordering::Release);
+                break;
+            };
+            ring.slot(written + count)
+                .store(sample.to_bits(), Ordering::Relaxed);
+            count += 1;
+        }
+        ring.written.store(written + count, Ordering::Release);
+        self.decoded.fetch_add(count, Ordering::Relaxed);
+        true
+    }
+
+    /// Marks the buffer primed once it is full, up to the low watermark
+    /// when just opened, or has the rest of the track. Returns whether it is.
+    fn prime(&self, (low, high): (Duration, Duration)) -> bool {
+        if self.primed.load(Ordering::Acquire) {
+            return true;
+        }
+        let ring = self.ring.get();
+        let target = match self.opened.load(Ordering::Acquire) {
+            true => ring.map_or(usize::MAX, |ring| self.target(ring, high)),
+            false => self.low(ring, low),
+        };
+        let filled = ring.map_or(0, Ring::filled);
+        let primed = filled >= target || self.done.load(Ordering::Acquire);
+        if primed {
+            self.opened.store(true, Ordering::Release);
+            self.primed.store(true, Ordering::Release);
+        }
+        primed
+    }
+}
+
+/// The watermarks and the tracks decoded ahead of one player, shared by
+/// its [`DecodeAhead`] stages, its [`Hold`] and the decoding thread.
+pub(crate) struct BufferControls {
+    low_ns: AtomicU64,
+    high_ns: AtomicU64,
+    /// Every track opened through a [`DecodeAhead`], in the order opened,
+    /// which is the order they play in unless the queue moved since.
+    tracks: Mutex<Vec<Weak<Ahead>>>,
+    /// One more than the id of the track playback holds for, or 0.
+    held: AtomicU64,
+    playlist: Arc<PlaylistControls>,
+    worker: OnceLock<Thread>,
+}
+
+impl BufferControls {
+    pub(crate) fn new(playlist: Arc<PlaylistControls>) -> Self {
+        BufferControls {
+            low_ns: AtomicU64::new(DEFAULT_LOW_WATERMARK.as_nanos() as u64),
+            high_ns: AtomicU64::new(DEFAULT_HIGH_WATERMARK.as_nanos() as u64),
+            tracks: Mutex::new(Vec::new()),
+            held: AtomicU64::new(0),
+            playlist,
+            worker: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn watermarks(&self) -> (Duration, Duration) {
+        (
+            Duration::from_nanos(self.low_ns.load(Ordering::Relaxed)),
+            Duration::from_nanos(self.high_ns.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// Takes `low <= high`, with `high` up to [`MAX_WATERMARK`]. A track
+    /// already decoding ahead buffers no more than it had room for.
+    pub(crate) fn set_watermarks(&self, low: Duration, high: Duration) {
+        self.low_ns.store(low.as_nanos() as u64, Ordering::Relaxed);
+        self.high_ns
+            .store(high.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Decodes `decoder`, the file of track `track`, ahead of playback.
+    pub(crate) fn wrap(
+        self: &Arc<Self>,
+        track: u64,
+        decoder: Box<dyn Source<Item = f32> + Send>,
+    ) -> DecodeAhead {
+        let (channels, sample_rate) = (decoder.channels().max(1), decoder.sample_rate());
+        let total_duration = decoder.total_duration();
+        let ahead = Arc::new(Ahead {
+            track,
+            decoder: Mutex::new(decoder),
+            ring: OnceLock::new(),
+            channels: channels as usize,
+            sample_rate,
+            done: AtomicBool::new(false),
+            opened: AtomicBool::new(false),
+            primed: AtomicBool::new(false),
+            decoded: AtomicUsize::new(0),
+            skip: AtomicUsize::new(0),
+        });
+        self.tracks.locked().push(Arc::downgrade(&ahead));
+        self.wake();
+        DecodeAhead {
+            ahead,
+            controls: self.clone(),
+            channels,
+            sample_rate,
+            total_duration,
+            channel: 0,
+            silent: 0,
+        }
+    }
+
+    /// What is decoded ahead of the current track, if it is a file.
+    pub(crate) fn level(&self) -> Option<Duration> {
+        let current = self.playlist.current();
+        let tracks = self.tracks.locked();
+        let ahead = tracks
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|ahead| ahead.track == current)?;
+        Some(ahead.level())
+    }
+
+    /// Whether the current track has its first audio decoded ahead, or
+    /// isn't decoded ahead, so playback could start on it without holding.
+    pub(crate) fn is_ready(&self) -> bool {
+        let current = self.playlist.current();
+        let (low, _) = self.watermarks();
+        self.tracks
+            .locked()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|ahead| ahead.track == current)
+            .all(|ahead| {
+                let ring = ahead.ring.get();
+                ahead.done.load(Ordering::Acquire)
+                    || ring.map_or(0, Ring::filled) >= ahead.low(ring, low)
+            })
+    }
+
+    /// Whether playback holds for a buffer to fill.
+    pub(crate) fn is_holding(&self) -> bool {
+        self.held.load(Ordering::Acquire) != 0
+    }
+
+    /// Whether playback holds for a buffer that ran low, rather than for
+    /// a track's first audio after opening or a seek.
+    pub(crate) fn is_buffering(&self) -> bool {
+        let held = self.held.load(Ordering::Acquire);
+        held != 0
+            && self
+                .tracks
+                .locked()
+                .iter()
+                .filter_map(Weak::upgrade)
+                .any(|ahead| ahead.track == held - 1 && ahead.opened.load(Ordering::Acquire))
+    }
+
+    fn hold(&self, track: u64) {
+        if self.held.swap(track + 1, Ordering::AcqRel) != track + 1 {
+            self.wake();
+        }
+    }
+
+    fn release(&self, track: u64) {
+        let _ = self
+            .held
+            .compare_exchange(track + 1, 0, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    fn wake(&self) {
+        if let Some(worker) = self.worker.get() {
+            worker.unpark();
+        }
+    }
+
+    /// The tracks to decode ahead now: the current one, if it is still
+    /// decoding, and those opened after it.
+    fn candidates(&self) -> Vec<Arc<Ahead>> {
+        let current = self.playlist.current();
+        let mut tracks = self.tracks.locked();
+        tracks.retain(|ahead| ahead.strong_count() > 0);
+        let live = tracks.iter().filter_map(Weak::upgrade).collect::<Vec<_>>();
+        let start = live
+            .iter()
+            .position(|ahead| ahead.track == current)
+            .unwrap_or(0);
+        live.into_iter()
+            .skip(start)
+            .filter(|ahead| !ahead.done.load(Ordering::Acquire))
+            .take(TRACKS_AHEAD)
+            .collect()
+    }
+
+    /// Lets playback go on once the buffer it holds for is primed, or gone.
+    fn check_hold(&self, watermarks: (Duration, Duration)) {
+        let held = self.held.load(Ordering::Acquire);
+        if held == 0 {
+            return;
+        }
+        let track = held - 1;
+        let tracks = self.tracks.locked();
+        let primed = tracks
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|ahead| ahead.track == track)
+            .all(|ahead| ahead.prime(watermarks));
+        if primed || track != self.playlist.current() {
+            self.release(track);
+        }
+    }
+}
+
+/// Starts the thread that decodes ahead for `controls`, reporting
+/// [`PlayerEvent::Buffering`] when playback holds for a buffer that ran low
+/// and [`PlayerEvent::Buffered`] when it goes on. It exits once the
+/// controls are gone.
+pub(crate) fn spawn_decoder(controls: &Arc<BufferControls>, signals: Sender<Signal>) {
+    let weak = Arc::downgrade(controls);
+    let worker = thread::spawn(move || {
+        let controls = weak;
+        let mut was_buffering = false;
+        loop {
+            let Some(controls) = controls.upgrade() else {
+                return;
+            };
+            let watermarks = controls.watermarks();
+            let mut busy = false;
+            for ahead in controls.candidates() {
+                busy |= ahead.fill(watermarks.1);
+                ahead.prime(watermarks);
+            }
+            controls.check_hold(watermarks);
+
+            let buffering = controls.is_buffering() || (was_buffering && controls.is_holding());
+            if buffering != was_buffering {
+                was_buffering = buffering;
+                let event = match buffering {
+                    true => PlayerEvent::Buffering,
+                    false => PlayerEvent::Buffered,
+                };
+                let _ = signals.send(Signal::Event(event));
+            }
+            drop(controls);
+            if !busy {
+                thread::park_timeout(IDLE);
+            }
+        }
+    });
+    let _ = controls.worker.set(worker.thread().clone());
+}
+
+/// Plays a track's file from what the decoding thread has decoded ahead,
+/// so a slow disk stalls that thread instead of the audio one.
+///
+/// Running low, below the low watermark or out altogether, holds playback
+/// through [`Hold`] until the buffer is back up to the high watermark; a
+/// seek starts it over empty. Nothing is heard of the wait but silence.
+pub(crate) struct DecodeAhead {
+    ahead: Arc<Ahead>,
+    controls: Arc<BufferControls>,
+    channels: u16,
+    sample_rate: u32,
+    total_duration: Option<Duration>,
+    channel: u16,
+    /// Samples left of a silent frame.
+    silent: u16,
+}
+
+impl DecodeAhead {
+    /// Whether the next frame can be played, holding playback if not or
+    /// if the buffer fell below the low watermark.
+    fn start_frame(&mut self, ring: Option<&Ring>) -> bool {
+        let filled = ring.map_or(0, Ring::filled);
+        let done = self.ahead.done.load(Ordering::Acquire);
+        let (low, _) = self.controls.watermarks();
+        if !done && filled < self.ahead.low(ring, low) {
+            self.ahead.primed.store(false, Ordering::Release);
+            self.controls.hold(self.ahead.track);
+        }
+        filled >= self.channels as usize || done
+    }
+}
+
+impl Iterator for DecodeAhead {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.silent > 0 {
+            self.silent -= 1;
+            return Some(0.0);
+        }
+        let ahead = self.ahead.clone();
+        let ring = ahead.ring.get();
+        if self.channel == 0 && !self.start_frame(ring) {
+            // A silent frame, while the hold takes over.
+            self.silent = self.channels - 1;
+            return Some(0.0);
+        }
+        let ring = ring?;
+        if ring.filled() == 0 {
+            return None;
+        }
+        let read = ring.read.load(Ordering::Relaxed);
+        let sample = f32::from_bits(ring.slot(read).load(Ordering::Relaxed));
+        ring.read.store(read + 1, Ordering::Release);
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl Source for DecodeAhead {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+
+    /// A decoder that can't seek is still moved forward: within what is
+    /// decoded ahead by skipping it, and past that by decoding up to
+    /// `pos` and throwing that away, which is never more work than
+    /// reopening the file. Only going back fails.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let ahead = self.ahead.clone();
+        let mut decoder = ahead.decoder.locked();
+        let target = ahead.samples(pos);
+        let ring = ahead.ring.get();
+        match decoder.try_seek(pos) {
+            Ok(()) => ahead.skip.store(0, Ordering::Relaxed),
+            Err(SeekError::NotSupported { underlying_source }) => {
+                let decoded = ahead.decoded.load(Ordering::Relaxed);
+                let skip = ahead.skip.load(Ordering::Relaxed);
+                let played = decoded - ring.map_or(0, Ring::filled);
+                if let (Some(ring), true) = (ring, (played..=decoded).contains(&target)) {
+                    ring.read.fetch_add(target - played, Ordering::AcqRel);
+                    self.channel = 0;
+                    self.silent = 0;
+                    return Ok(());
+                }
+                if target < decoded - skip {
+                    return Err(SeekError::NotSupported { underlying_source });
+                }
+                ahead.skip.store(skip + target - decoded, Ordering::Relaxed);
+            }
+            Err(err) => return Err(err),
+        }
+        ahead.decoded.store(target, Ordering::Relaxed);
+        if let Some(ring) = ring {
+            ring.read
+                .store(ring.written.load(Ordering::Acquire), Ordering::Release);
+        }
+        self.ahead.done.store(false, Ordering::Release);
+        self.ahead.opened.store(false, Ordering::Release);
+        self.ahead.primed.store(false, Ordering::Release);
+        self.channel = 0;
+        self.silent = 0;
+        self.controls.hold(self.ahead.track);
+        Ok(())
+    }
+}
+
+impl Drop for DecodeAhead {
+    fn drop(&mut self) {
+        self.controls.release(self.ahead.track);
+    }
+}
+
+/// Plays the playlist, or silence without pulling anything from it while
+/// a [`DecodeAhead`] holds playback, so the position waits too.
+pub(crate) struct Hold<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<BufferControls>,
+    holding: bool,
+    channel: u16,
+    channels: u16,
+}
+
+impl<S> Hold<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<BufferControls>) -> Self {
+        Hold {
+            channels: source.channels().max(1),
+            source,
+            controls,
+            holding: false,
+            channel: 0,
+        }
+    }
+}
+
+impl<S> Iterator for Hold<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            self.holding = self.controls.is_holding();
+            self.channels = self.source.channels().max(1);
+        }
+        let sample = match self.holding {
+            true => 0.0,
+            false => self.source.next()?,
+        };
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Hold<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.channel = 0;
+        Ok(())
+    }
+}
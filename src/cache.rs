@@ -0,0 +1,308 @@
+use crate::recover::{DecodeControls, FaultTolerant};
+use rodio::{source::SeekError, Source};
+use std::{
+    fmt, mem,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+        Arc, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How much longer than its reported length a file may decode to before
+/// caching it is given up on, as a fraction of that length.
+const LENGTH_SLACK: f64 = 0.05;
+
+/// Whether the current track can seek instantly; see
+/// [`AudioPlayer::cache_status`](crate::AudioPlayer::cache_status).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The track plays from its file: caching is off, or it didn't fit.
+    Uncached,
+    /// It plays from its file while being decoded into memory.
+    Filling,
+    /// Seeks are served from memory.
+    Ready,
+}
+
+/// A whole file decoded into memory. Its bytes count against the budget
+/// until it is dropped.
+struct DecodedAudio {
+    samples: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+    controls: Arc<CacheControls>,
+}
+
+impl Drop for DecodedAudio {
+    fn drop(&mut self) {
+        self.controls.release(bytes(self.samples.len()));
+    }
+}
+
+/// One track's cache, filled in the background.
+#[derive(Default)]
+pub(crate) struct TrackCache {
+    /// `None` once filling has been given up on.
+    audio: OnceLock<Option<Arc<DecodedAudio>>>,
+}
+
+impl TrackCache {
+    pub(crate) fn status(&self) -> CacheStatus {
+        match self.audio.get() {
+            None => CacheStatus::Filling,
+            Some(None) => CacheStatus::Uncached,
+            Some(Some(_)) => CacheStatus::Ready,
+        }
+    }
+
+    fn audio(&self) -> Option<&Arc<DecodedAudio>> {
+        self.audio.get()?.as_ref()
+    }
+}
+
+impl fmt::Debug for TrackCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TrackCache").field(&self.status()).finish()
+    }
+}
+
+/// The memory budget for decoded tracks, shared with the thread that fills them.
+pub(crate) struct CacheControls {
+    budget: AtomicUsize,
+    used: AtomicUsize,
+}
+
+impl CacheControls {
+    pub(crate) fn new() -> Self {
+        CacheControls {
+            budget: AtomicUsize::new(0),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// In bytes; 0 turns caching off.
+    pub(crate) fn budget(&self) -> usize {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_budget(&self, bytes: usize) {
+        self.budget.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Bytes held by decoded tracks still queued or playing.
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Whether a track of `duration` at this format would fit in what's left.
+    pub(crate) fn fits(&self, duration: Duration, channels: u16, sample_rate: u32) -> bool {
+        let samples = duration.as_secs_f64() * sample_rate as f64 * channels as f64;
+        let budget = self.budget();
+        budget > 0 && self.used().saturating_add(bytes(samples as usize)) <= budget
+    }
+
+    /// Claims `amount` bytes unless that would go over budget.
+    fn reserve(&self, amount: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                let total = used.checked_add(amount)?;
+                (total <= self.budget()).then_some(total)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, amount: usize) {
+        self.used.fetch_sub(amount, Ordering::AcqRel);
+    }
+}
+
+fn bytes(samples: usize) -> usize {
+    samples.saturating_mul(mem::size_of::<f32>())
+}
+
+/// Starts the thread that decodes files into their caches, one at a time
+/// in the order sent. Each comes with the length it reports. Damage is got
+/// past as it is while playing, so the samples cached are the same.
+pub(crate) fn spawn_filler(
+    controls: Arc<CacheControls>,
+    decoding: Arc<DecodeControls>,
+) -> Sender<(PathBuf, Duration, Arc<TrackCache>)> {
+    let (jobs, receiver) = mpsc::channel::<(PathBuf, Duration, Arc<TrackCache>)>();
+    thread::spawn(move || {
+        for (path, duration, cache) in receiver {
+            // Nobody is left to play it, e.g. because it was taken off the queue.
+            if Arc::strong_count(&cache) == 1 {
+                continue;
+            }
+            let _ = cache
+                .audio
+                .set(fill(&controls, &decoding, &path, duration).map(Arc::new));
+        }
+    });
+    jobs
+}
+
+fn fill(
+    controls: &Arc<CacheControls>,
+    decoding: &Arc<DecodeControls>,
+    path: &Path,
+    duration: Duration,
+) -> Option<DecodedAudio> {
+    let decoder = FaultTolerant::open(path, decoding.clone(), None, None).ok()?;
+    let (channels, sample_rate) = (decoder.channels(), decoder.sample_rate());
+    let expected = duration.as_secs_f64() * sample_rate as f64 * channels as f64;
+    let limit =
+        (expected * (1.0 + LENGTH_SLACK)) as usize + sample_rate as usize * channels as usize;
+    let reserved = bytes(limit);
+    if !controls.reserve(reserved) {
+        return None;
+    }
+    let mut samples = Vec::with_capacity(expected as usize);
+    for sample in decoder {
+        if samples.len() == limit {
+            controls.release(reserved);
+            return None;
+        }
+        samples.push(sample);
+    }
+    // Keep only what the samples actually take.
+    controls.release(reserved - bytes(samples.len()));
+    Some(DecodedAudio {
+        samples: samples.into(),
+        channels: channels.max(1),
+        sample_rate,
+        controls: controls.clone(),
+    })
+}
+
+/// Plays a track from its decoder until its cache is filled, then from
+/// memory, picking up at the same sample. Seeks served from memory land on
+/// the exact frame without touching the file.
+pub(crate) struct CachedSource<S>
+where
+    S: Source<Item = f32>,
+{
+    decoder: Option<S>,
+    cache: Arc<TrackCache>,
+    audio: Option<Arc<DecodedAudio>>,
+    /// Index of the next sample, counted from the start of the file.
+    position: usize,
+}
+
+impl<S> CachedSource<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Without a `decoder` the cache must be ready.
+    pub(crate) fn new(decoder: Option<S>, cache: Arc<TrackCache>) -> Self {
+        let mut source = CachedSource {
+            decoder,
+            cache,
+            audio: None,
+            position: 0,
+        };
+        source.switch_over();
+        source
+    }
+
+    /// Moves to the cache if it is ready. Only call at a frame boundary.
+    fn switch_over(&mut self) {
+        if self.audio.is_none() {
+            self.audio = self.cache.audio().cloned();
+            if self.audio.is_some() {
+                self.decoder = None;
+            }
+        }
+    }
+}
+
+impl<S> Iterator for CachedSource<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.audio.is_none()
+            && self
+                .position
+                .is_multiple_of(self.channels().max(1) as usize)
+        {
+            self.switch_over();
+        }
+        let sample = match (&self.audio, &mut self.decoder) {
+            (Some(audio), _) => audio.samples.get(self.position).copied(),
+            (None, Some(decoder)) => decoder.next(),
+            (None, None) => None,
+        }?;
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for CachedSource<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        match (&self.audio, &self.decoder) {
+            (Some(audio), _) => Some(audio.samples.len().saturating_sub(self.position)),
+            (None, Some(decoder)) => decoder.current_frame_len(),
+            (None, None) => Some(0),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match (&self.audio, &self.decoder) {
+            (Some(audio), _) => audio.channels,
+            (None, Some(decoder)) => decoder.channels(),
+            (None, None) => 1,
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match (&self.audio, &self.decoder) {
+            (Some(audio), _) => audio.sample_rate,
+            (None, Some(decoder)) => decoder.sample_rate(),
+            (None, None) => 44100,
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        match (&self.audio, &self.decoder) {
+            (Some(audio), _) => {
+                let frames = audio.samples.len() / audio.channels as usize;
+                Some(Duration::from_secs_f64(
+                    frames as f64 / audio.sample_rate.max(1) as f64,
+                ))
+            }
+            (None, Some(decoder)) => decoder.total_duration(),
+            (None, None) => None,
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let frame = |channels: u16, sample_rate: u32| {
+            (pos.as_secs_f64() * sample_rate as f64).round() as usize * channels as usize
+        };
+        // A seek lands on a frame boundary, so the cache can take over now.
+        self.switch_over();
+        match (&self.audio, &mut self.decoder) {
+            (Some(audio), _) => {
+                let target = frame(audio.channels, audio.sample_rate);
+                self.position = target.min(audio.samples.len());
+            }
+            (None, Some(decoder)) => {
+                decoder.try_seek(pos)?;
+                self.position = frame(decoder.channels(), decoder.sample_rate());
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
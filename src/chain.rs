@@ -0,0 +1,666 @@
+use crate::{
+    channels::{ChannelMode, WidthPlacement, MAX_STEREO_WIDTH},
+    compressor::CompressorSettings,
+    format::{json, ParseError, Value},
+    limiter::{LIMITER_RELEASE, LIMITER_THRESHOLD_DB},
+    reverb::ReverbSettings,
+    settings::{EqError, EqSettings},
+    tone::{MAX_TONE_DB, MAX_TONE_KNOB_DB},
+};
+use std::{error::Error, fmt, fs, io, path::Path, time::Duration};
+
+/// The stages of a player's effects chain by the names a chain file gives
+/// them, in the order they run with the stereo width after the EQ.
+const STAGES: [&str; 8] = [
+    "vocal",
+    "eq",
+    "width",
+    "tone",
+    "compressor",
+    "channels",
+    "reverb",
+    "limiter",
+];
+
+/// Every effect stage of a player and how it is set up, as one document to
+/// share: see [`AudioPlayer::export_chain`] and
+/// [`AudioPlayer::apply_chain`]. A stage left at `None` is left as it is
+/// when the chain is applied, so a file can set up only some of them.
+///
+/// Kept as JSON, with `version` and the stages' `order` alongside a table
+/// per stage:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "order": ["vocal", "width", "eq", "tone", "compressor", "channels", "reverb", "limiter"],
+///   "eq": { "enabled": true, "preamp_db": -3.0, "bands": [...] },
+///   "compressor": { "enabled": true, "threshold_db": -18.0, "ratio": 3.0 },
+///   "limiter": { "enabled": true, "threshold_db": -0.3, "release": 0.05 }
+/// }
+/// ```
+///
+/// The stages run in a fixed order, except that the stereo width goes on
+/// either side of the EQ, which is what `order` sets. Settings left out of
+/// a stage's table take their defaults, and keys this version doesn't know,
+/// such as those of a later one, are passed over.
+///
+/// [`AudioPlayer::export_chain`]: crate::AudioPlayer::export_chain
+/// [`AudioPlayer::apply_chain`]: crate::AudioPlayer::apply_chain
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChainConfig {
+    /// How far the center is turned down, from 0.0 to 1.0; see
+    /// [`AudioPlayer::set_vocal_reduction`](crate::AudioPlayer::set_vocal_reduction).
+    pub vocal_reduction: Option<f32>,
+    pub eq: Option<EqStage>,
+    pub stereo_width: Option<WidthStage>,
+    pub tone: Option<ToneStage>,
+    pub compressor: Option<CompressorStage>,
+    pub channels: Option<ChannelStage>,
+    pub reverb: Option<ReverbStage>,
+    pub limiter: Option<LimiterStage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EqStage {
+    pub enabled: bool,
+    pub settings: EqSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WidthStage {
+    /// From 0.0 (mono) to [`MAX_STEREO_WIDTH`]; 1.0 leaves it as it is.
+    pub width: f32,
+    pub placement: WidthPlacement,
+}
+
+impl Default for WidthStage {
+    fn default() -> Self {
+        WidthStage {
+            width: 1.0,
+            placement: WidthPlacement::default(),
+        }
+    }
+}
+
+/// The bass and treble shelves after the EQ.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ToneStage {
+    pub bass_db: f32,
+    pub treble_db: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CompressorStage {
+    pub enabled: bool,
+    pub settings: CompressorSettings,
+}
+
+/// Channel routing and balance.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChannelStage {
+    pub mode: ChannelMode,
+    /// From -1.0 (left) to 1.0 (right).
+    pub balance: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ReverbStage {
+    pub enabled: bool,
+    pub settings: ReverbSettings,
+}
+
+/// The peak limiter at the end of the chain. Its attack, which sets the
+/// lookahead, is fixed when the player is made.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterStage {
+    pub enabled: bool,
+    pub threshold_db: f32,
+    pub release: Duration,
+}
+
+impl Default for LimiterStage {
+    fn default() -> Self {
+        LimiterStage {
+            enabled: true,
+            threshold_db: LIMITER_THRESHOLD_DB,
+            release: LIMITER_RELEASE,
+        }
+    }
+}
+
+impl ChainConfig {
+    /// The version of the file format [`ChainConfig::save`] writes. Files
+    /// of any version read: what an older one lacks takes its defaults,
+    /// and what a newer one adds is passed over.
+    pub const VERSION: u64 = 1;
+
+    /// The stages in the order they run, by their names in a chain file.
+    pub fn order(&self) -> Vec<&'static str> {
+        let placement = self
+            .stereo_width
+            .map_or(WidthPlacement::default(), |width| width.placement);
+        let mut order = STAGES.to_vec();
+        if placement == WidthPlacement::BeforeEq {
+            order.swap(1, 2);
+        }
+        order
+    }
+
+    /// Reads a chain saved by [`ChainConfig::save`], checked as
+    /// [`ChainConfig::validate`] checks it.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ChainError> {
+        let chain = Self::parse(&fs::read_to_string(path)?)?;
+        chain.validate()?;
+        Ok(chain)
+    }
+
+    /// Writes the chain to `path` as JSON.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ChainError> {
+        fs::write(path, self.to_json())?;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        json::to_string(&self.to_value())
+    }
+
+    /// Reads a chain from JSON, failing on a setting of the wrong kind; see
+    /// [`ChainConfig::validate`] for one out of range.
+    pub fn parse(text: &str) -> Result<Self, ChainError> {
+        Self::from_value(&json::parse(text)?)
+    }
+
+    /// Checks every setting of every stage, failing on the first that is
+    /// out of range with its stage and name.
+    pub fn validate(&self) -> Result<(), ChainError> {
+        if let Some(amount) = self.vocal_reduction {
+            check("vocal", "amount", amount, 0.0, 1.0)?;
+        }
+        if let Some(eq) = &self.eq {
+            let invalid = |parameter: String, err: EqError| ChainError::Invalid {
+                stage: "eq",
+                parameter: Some(parameter),
+                message: format!("is invalid: {err}"),
+            };
+            for (index, band) in eq.settings.bands.iter().enumerate() {
+                band.validate_shape()
+                    .map_err(|err| invalid(format!("bands.{}", index + 1), err))?;
+            }
+            if !eq.settings.preamp_db.is_finite() {
+                return Err(ChainError::invalid(
+                    "eq",
+                    "preamp_db",
+                    "must be a finite number of dB",
+                ));
+            }
+            let gains = eq.settings.channel_gains.iter().flatten();
+            if !gains.into_iter().all(|gain| gain.is_finite()) {
+                return Err(ChainError::invalid(
+                    "eq",
+                    "channels",
+                    "must all be finite numbers of dB",
+                ));
+            }
+        }
+        if let Some(width) = self.stereo_width {
+            check("width", "width", width.width, 0.0, MAX_STEREO_WIDTH)?;
+        }
+        if let Some(tone) = self.tone {
+            check(
+                "tone",
+                "bass_db",
+                tone.bass_db,
+                -MAX_TONE_KNOB_DB,
+                MAX_TONE_DB,
+            )?;
+            check(
+                "tone",
+                "treble_db",
+                tone.treble_db,
+                -MAX_TONE_DB,
+                MAX_TONE_DB,
+            )?;
+        }
+        if let Some(compressor) = self.compressor {
+            let settings = compressor.settings;
+            check(
+                "compressor",
+                "threshold_db",
+                settings.threshold_db,
+                f32::MIN,
+                0.0,
+            )?;
+            check("compressor", "ratio", settings.ratio, 1.0, f32::MAX)?;
+            check("compressor", "knee_db", settings.knee_db, 0.0, f32::MAX)?;
+            check(
+                "compressor",
+                "makeup_db",
+                settings.makeup_db,
+                f32::MIN,
+                f32::MAX,
+            )?;
+        }
+        if let Some(channels) = self.channels {
+            check("channels", "balance", channels.balance, -1.0, 1.0)?;
+        }
+        if let Some(reverb) = self.reverb {
+            let settings = reverb.settings;
+            check("reverb", "room_size", settings.room_size, 0.0, 1.0)?;
+            check("reverb", "damping", settings.damping, 0.0, 1.0)?;
+            check("reverb", "wet", settings.wet, 0.0, 1.0)?;
+        }
+        if let Some(limiter) = self.limiter {
+            check(
+                "limiter",
+                "threshold_db",
+                limiter.threshold_db,
+                f32::MIN,
+                0.0,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the EQ can be realised at `sample_rate`.
+    pub(crate) fn validate_rate(&self, sample_rate: u32) -> Result<(), ChainError> {
+        match &self.eq {
+            Some(eq) => eq
+                .settings
+                .validate(sample_rate)
+                .map_err(|err| ChainError::Invalid {
+                    stage: "eq",
+                    parameter: None,
+                    message: format!("is invalid: {err}"),
+                }),
+            None => Ok(()),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        let order = self
+            .order()
+            .into_iter()
+            .map(Value::from)
+            .collect::<Vec<_>>();
+        // To the microsecond, as the player keeps times in f32 milliseconds.
+        let seconds = |time: Duration| (time.as_nanos() as f64 / 1e3).round() / 1e6;
+        Value::table()
+            .with("version", ChainConfig::VERSION)
+            .with("order", order)
+            .with(
+                "vocal",
+                self.vocal_reduction
+                    .map(|amount| Value::table().with("amount", amount)),
+            )
+            .with(
+                "eq",
+                self.eq
+                    .as_ref()
+                    .map(|eq| eq.settings.to_value().with("enabled", eq.enabled)),
+            )
+            .with(
+                "width",
+                self.stereo_width
+                    .map(|width| Value::table().with("width", width.width)),
+            )
+            .with(
+                "tone",
+                self.tone.map(|tone| {
+                    Value::table()
+                        .with("bass_db", tone.bass_db)
+                        .with("treble_db", tone.treble_db)
+                }),
+            )
+            .with(
+                "compressor",
+                self.compressor.map(|compressor| {
+                    let settings = compressor.settings;
+                    Value::table()
+                        .with("enabled", compressor.enabled)
+                        .with("threshold_db", settings.threshold_db)
+                        .with("ratio", settings.ratio)
+                        .with("attack", seconds(settings.attack))
+                        .with("release", seconds(settings.release))
+                        .with("knee_db", settings.knee_db)
+                        .with("makeup_db", settings.makeup_db)
+                }),
+            )
+            .with(
+                "channels",
+                self.channels.map(|channels| {
+                    Value::table()
+                        .with("mode", channels.mode.name())
+                        .with("balance", channels.balance)
+                }),
+            )
+            .with(
+                "reverb",
+                self.reverb.map(|reverb| {
+                    Value::table()
+                        .with("enabled", reverb.enabled)
+                        .with("room_size", reverb.settings.room_size)
+                        .with("damping", reverb.settings.damping)
+                        .with("wet", reverb.settings.wet)
+                }),
+            )
+            .with(
+                "limiter",
+                self.limiter.map(|limiter| {
+                    Value::table()
+                        .with("enabled", limiter.enabled)
+                        .with("threshold_db", limiter.threshold_db)
+                        .with("release", seconds(limiter.release))
+                }),
+            )
+    }
+
+    fn from_value(value: &Value) -> Result<Self, ChainError> {
+        if !matches!(value, Value::Table(_)) {
+            return Err(ChainError::Invalid {
+                stage: "chain",
+                parameter: None,
+                message: "must be a table of stages".to_string(),
+            });
+        }
+        if value.get("version").is_some_and(|v| v.as_f64().is_none()) {
+            return Err(ChainError::Invalid {
+                stage: "version",
+                parameter: None,
+                message: "must be a number".to_string(),
+            });
+        }
+
+        let vocal_reduction = match Stage::of(value, "vocal")? {
+            None => None,
+            Some(stage) => Some(stage.number("amount")?.unwrap_or(0.0)),
+        };
+        let eq = match Stage::of(value, "eq")? {
+            None => None,
+            Some(stage) => Some(EqStage {
+                enabled: stage.flag("enabled")?.unwrap_or(true),
+                settings: EqSettings::from_value(stage.value).map_err(|err| {
+                    ChainError::Invalid {
+                        stage: "eq",
+                        parameter: None,
+                        message: format!("is invalid: {err}"),
+                    }
+                })?,
+            }),
+        };
+        // Checked even without a width stage, as it's a mistake either way.
+        let placement = placement(value.get("order"))?;
+        let stereo_width = match Stage::of(value, "width")? {
+            None => None,
+            Some(stage) => Some(WidthStage {
+                width: stage.number("width")?.unwrap_or(1.0),
+                placement,
+            }),
+        };
+        let tone = match Stage::of(value, "tone")? {
+            None => None,
+            Some(stage) => Some(ToneStage {
+                bass_db: stage.number("bass_db")?.unwrap_or(0.0),
+                treble_db: stage.number("treble_db")?.unwrap_or(0.0),
+            }),
+        };
+        let compressor = match Stage::of(value, "compressor")? {
+            None => None,
+            Some(stage) => {
+                let default = CompressorSettings::default();
+                Some(CompressorStage {
+                    enabled: stage.flag("enabled")?.unwrap_or(true),
+                    settings: CompressorSettings {
+                        threshold_db: stage
+                            .number("threshold_db")?
+                            .unwrap_or(default.threshold_db),
+                        ratio: stage.number("ratio")?.unwrap_or(default.ratio),
+                        attack: stage.seconds("attack")?.unwrap_or(default.attack),
+                        release: stage.seconds("release")?.unwrap_or(default.release),
+                        knee_db: stage.number("knee_db")?.unwrap_or(default.knee_db),
+                        makeup_db: stage.number("makeup_db")?.unwrap_or(default.makeup_db),
+                    },
+                })
+            }
+        };
+        let channels = match Stage::of(value, "channels")? {
+            None => None,
+            Some(stage) => {
+                let mode = match stage.value.get("mode") {
+                    None => ChannelMode::default(),
+                    Some(mode) => {
+                        mode.as_str()
+                            .and_then(ChannelMode::from_name)
+                            .ok_or_else(|| {
+                                ChainError::invalid(
+                                    "channels",
+                                    "mode",
+                                    "must be \"stereo\", \"mono\", \"left\", \"right\" or \"swap\"",
+                                )
+                            })?
+                    }
+                };
+                Some(ChannelStage {
+                    mode,
+                    balance: stage.number("balance")?.unwrap_or(0.0),
+                })
+            }
+        };
+        let reverb = match Stage::of(value, "reverb")? {
+            None => None,
+            Some(stage) => {
+                let default = ReverbSettings::default();
+                Some(ReverbStage {
+                    enabled: stage.flag("enabled")?.unwrap_or(true),
+                    settings: ReverbSettings {
+                        room_size: stage.number("room_size")?.unwrap_or(default.room_size),
+                        damping: stage.number("damping")?.unwrap_or(default.damping),
+                        wet: stage.number("wet")?.unwrap_or(default.wet),
+                    },
+                })
+            }
+        };
+        let limiter = match Stage::of(value, "limiter")? {
+            None => None,
+            Some(stage) => {
+                let default = LimiterStage::default();
+                Some(LimiterStage {
+                    enabled: stage.flag("enabled")?.unwrap_or(default.enabled),
+                    threshold_db: stage
+                        .number("threshold_db")?
+                        .unwrap_or(default.threshold_db),
+                    release: stage.seconds("release")?.unwrap_or(default.release),
+                })
+            }
+        };
+
+        Ok(ChainConfig {
+            vocal_reduction,
+            eq,
+            stereo_width,
+            tone,
+            compressor,
+            channels,
+            reverb,
+            limiter,
+        })
+    }
+}
+
+/// Fails unless `value` is a number from `low` to `high`.
+fn check(
+    stage: &'static str,
+    parameter: &str,
+    value: f32,
+    low: f32,
+    high: f32,
+) -> Result<(), ChainError> {
+    if value.is_finite() && (low..=high).contains(&value) {
+        return Ok(());
+    }
+    let message = match (low, high) {
+        (f32::MIN, f32::MAX) => format!("must be a finite number, got {value}"),
+        (f32::MIN, high) => format!("must be at most {high}, got {value}"),
+        (low, f32::MAX) => format!("must be at least {low}, got {value}"),
+        (low, high) => format!("must be from {low} to {high}, got {value}"),
+    };
+    Err(ChainError::invalid(stage, parameter, message))
+}
+
+/// Where `order` puts the stereo width. Names it doesn't know are passed
+/// over, but those it does have to be in the order the stages run.
+fn placement(order: Option<&Value>) -> Result<WidthPlacement, ChainError> {
+    let Some(order) = order else {
+        return Ok(WidthPlacement::default());
+    };
+    let invalid = |message: String| ChainError::Invalid {
+        stage: "order",
+        parameter: None,
+        message,
+    };
+    let names = order
+        .as_array()
+        .and_then(|names| names.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+        .ok_or_else(|| invalid("must be a list of stage names".to_string()))?;
+    let known = names
+        .into_iter()
+        .filter(|name| STAGES.contains(name))
+        .collect::<Vec<_>>();
+    let fixed = known.iter().filter(|&&name| name != "width");
+    let mut expected = STAGES.iter().filter(|&&name| name != "width");
+    for name in fixed {
+        if !expected.any(|stage| stage == name) {
+            return Err(invalid(format!(
+                "has '{name}' out of place; the stages run as {}, with 'width' on either side of 'eq'",
+                STAGES.join(", ")
+            )));
+        }
+    }
+    let position = |stage: &str| known.iter().position(|&name| name == stage);
+    if known.iter().filter(|&&name| name == "width").count() > 1 {
+        return Err(invalid("has 'width' more than once".to_string()));
+    }
+    Ok(match (position("width"), position("eq")) {
+        (Some(width), Some(eq)) if width < eq => WidthPlacement::BeforeEq,
+        _ => WidthPlacement::AfterEq,
+    })
+}
+
+/// A stage's table in a chain file.
+struct Stage<'a> {
+    name: &'static str,
+    value: &'a Value,
+}
+
+impl<'a> Stage<'a> {
+    /// The table of stage `name`, if `chain` has one.
+    fn of(chain: &'a Value, name: &'static str) -> Result<Option<Self>, ChainError> {
+        match chain.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value @ Value::Table(_)) => Ok(Some(Stage { name, value })),
+            Some(_) => Err(ChainError::Invalid {
+                stage: name,
+                parameter: None,
+                message: "must be a table of settings".to_string(),
+            }),
+        }
+    }
+
+    fn number(&self, key: &str) -> Result<Option<f32>, ChainError> {
+        match self.value.get(key) {
+            None => Ok(None),
+            Some(v) => v
+                .as_f32()
+                .map(Some)
+                .ok_or_else(|| ChainError::invalid(self.name, key, "must be a number")),
+        }
+    }
+
+    fn flag(&self, key: &str) -> Result<Option<bool>, ChainError> {
+        match self.value.get(key) {
+            None => Ok(None),
+            Some(v) => v
+                .as_bool()
+                .map(Some)
+                .ok_or_else(|| ChainError::invalid(self.name, key, "must be a boolean")),
+        }
+    }
+
+    fn seconds(&self, key: &str) -> Result<Option<Duration>, ChainError> {
+        match self.value.get(key) {
+            None => Ok(None),
+            Some(v) => v
+                .as_f64()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .map(Some)
+                .ok_or_else(|| {
+                    ChainError::invalid(self.name, key, "must be a number of seconds, at least 0")
+                }),
+        }
+    }
+}
+
+/// Why a [`ChainConfig`] couldn't be read, written or applied.
+#[derive(Debug)]
+pub enum ChainError {
+    Io(io::Error),
+    Parse(ParseError),
+    /// A setting of the wrong kind or out of range, by the stage it is in
+    /// and its name there; `parameter` is `None` for the stage as a whole.
+    Invalid {
+        stage: &'static str,
+        parameter: Option<String>,
+        message: String,
+    },
+}
+
+impl ChainError {
+    fn invalid(stage: &'static str, parameter: &str, message: impl Into<String>) -> Self {
+        ChainError::Invalid {
+            stage,
+            parameter: Some(parameter.to_string()),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::Io(err) => write!(f, "{err}"),
+            ChainError::Parse(err) => write!(f, "{err}"),
+            ChainError::Invalid {
+                stage,
+                parameter: Some(parameter),
+                message,
+            } => write!(f, "'{stage}.{parameter}' {message}"),
+            ChainError::Invalid {
+                stage,
+                parameter: None,
+                message,
+            } => write!(f, "'{stage}' {message}"),
+        }
+    }
+}
+
+impl Error for ChainError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ChainError::Io(err) => Some(err),
+            ChainError::Parse(err) => Some(err),
+            ChainError::Invalid { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ChainError {
+    fn from(err: io::Error) -> Self {
+        ChainError::Io(err)
+    }
+}
+
+impl From<ParseError> for ChainError {
+    fn from(err: ParseError) -> Self {
+        ChainError::Parse(err)
+    }
+}
@@ -0,0 +1,529 @@
+use crate::atomic::AtomicF32;
+use rodio::{source::SeekError, Source};
+use std::{
+    f32::consts::{FRAC_1_SQRT_2, FRAC_PI_4, SQRT_2},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// How long a balance change takes to settle, to avoid zipper noise.
+const BALANCE_RAMP: Duration = Duration::from_millis(10);
+
+/// How long a stereo width change takes to settle.
+const WIDTH_RAMP: Duration = Duration::from_millis(20);
+
+pub const MAX_STEREO_WIDTH: f32 = 2.0;
+
+/// How the front left/right pair is routed to the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    #[default]
+    Stereo,
+    /// Both channels carry the sum of left and right at -3 dB each.
+    Mono,
+    /// Both channels carry the left input.
+    LeftOnly,
+    /// Both channels carry the right input.
+    RightOnly,
+    SwapChannels,
+}
+
+impl ChannelMode {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ChannelMode::Stereo => "stereo",
+            ChannelMode::Mono => "mono",
+            ChannelMode::LeftOnly => "left",
+            ChannelMode::RightOnly => "right",
+            ChannelMode::SwapChannels => "swap",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        [
+            ChannelMode::Stereo,
+            ChannelMode::Mono,
+            ChannelMode::LeftOnly,
+            ChannelMode::RightOnly,
+            ChannelMode::SwapChannels,
+        ]
+        .into_iter()
+        .find(|&mode| mode.name().eq_ignore_ascii_case(name))
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ChannelMode::Mono,
+            2 => ChannelMode::LeftOnly,
+            3 => ChannelMode::RightOnly,
+            4 => ChannelMode::SwapChannels,
+            _ => ChannelMode::Stereo,
+        }
+    }
+}
+
+/// Where the stereo width is applied in each track's chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WidthPlacement {
+    BeforeEq,
+    #[default]
+    AfterEq,
+}
+
+/// Channel routing and balance shared between the player and its
+/// [`ChannelMapper`] and [`Balance`] stages.
+pub(crate) struct ChannelControls {
+    mode: AtomicU8,
+    balance: AtomicF32,
+}
+
+impl ChannelControls {
+    pub(crate) fn new() -> Self {
+        ChannelControls {
+            mode: AtomicU8::new(ChannelMode::Stereo as u8),
+            balance: AtomicF32::new(0.0),
+        }
+    }
+
+    pub(crate) fn balance(&self) -> f32 {
+        self.balance.load()
+    }
+
+    /// Clamped to -1.0 (left) ..= 1.0 (right).
+    pub(crate) fn set_balance(&self, balance: f32) {
+        self.balance.store(balance.clamp(-1.0, 1.0));
+    }
+
+    pub(crate) fn mode(&self) -> ChannelMode {
+        ChannelMode::from_u8(self.mode.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_mode(&self, mode: ChannelMode) {
+        self.mode.store(mode as u8, Ordering::Relaxed);
+    }
+}
+
+/// Re-routes the first two channels of each frame per [`ChannelMode`].
+/// Further channels, and mono sources, pass through untouched, and the
+/// channel count never changes.
+pub(crate) struct ChannelMapper<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<ChannelControls>,
+    /// The front pair of the current frame, once remapped.
+    front: [f32; 2],
+    channel: u16,
+    channels: u16,
+}
+
+impl<S> ChannelMapper<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<ChannelControls>) -> Self {
+        ChannelMapper {
+            source,
+            controls,
+            front: [0.0; 2],
+            channel: 0,
+            channels: 1,
+        }
+    }
+}
+
+impl<S> Iterator for ChannelMapper<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.channels = self.source.channels().max(1);
+            if self.channels < 2 {
+                return self.source.next();
+            }
+            let left = self.source.next()?;
+            let Some(right) = self.source.next() else {
+                return Some(left);
+            };
+            self.front = match self.controls.mode() {
+                ChannelMode::Stereo => [left, right],
+                ChannelMode::Mono => [(left + right) * FRAC_1_SQRT_2; 2],
+                ChannelMode::LeftOnly => [left; 2],
+                ChannelMode::RightOnly => [right; 2],
+                ChannelMode::SwapChannels => [right, left],
+            };
+        }
+
+        let sample = match self.channel {
+            0 | 1 => self.front[self.channel as usize],
+            _ => self.source.next()?,
+        };
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl<S> Source for ChannelMapper<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        // Right after the left sample, the right one has already been read
+        // from the source but not handed out.
+        let pending = usize::from(self.channel == 1);
+        self.source.current_frame_len().map(|len| len + pending)
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+/// Left and right gains for `balance`, on an equal-power law scaled so the
+/// centre is unity: the total power stays the same wherever it points.
+fn balance_gains(balance: f32) -> [f32; 2] {
+    if balance == 0.0 {
+        // Exactly unity, so a centred balance leaves the signal bit for bit.
+        return [1.0, 1.0];
+    }
+    let angle = (balance + 1.0) * FRAC_PI_4;
+    [
+        (angle.cos() * SQRT_2).max(0.0),
+        (angle.sin() * SQRT_2).max(0.0),
+    ]
+}
+
+/// Pans the front left/right pair per the shared balance. A mono source is
+/// spread into a stereo pair first; channels past the front pair pass through.
+pub(crate) struct Balance<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<ChannelControls>,
+    gains: [f32; 2],
+    front: [f32; 2],
+    channel: u16,
+    channels: u16,
+    mono: bool,
+}
+
+impl<S> Balance<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<ChannelControls>) -> Self {
+        Balance {
+            gains: balance_gains(controls.balance()),
+            source,
+            controls,
+            front: [0.0; 2],
+            channel: 0,
+            channels: 2,
+            mono: false,
+        }
+    }
+
+    /// Moves the gains one frame closer to the current balance.
+    fn update_gains(&mut self) {
+        let target = balance_gains(self.controls.balance());
+        if target == self.gains {
+            return;
+        }
+        let step = SQRT_2 / (BALANCE_RAMP.as_secs_f32() * self.source.sample_rate().max(1) as f32);
+        for (gain, target) in self.gains.iter_mut().zip(target) {
+            *gain += (target - *gain).clamp(-step, step);
+        }
+    }
+}
+
+impl<S> Iterator for Balance<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            let channels = self.source.channels().max(1);
+            self.mono = channels == 1;
+            self.channels = channels.max(2);
+            self.update_gains();
+
+            let left = self.source.next()?;
+            let right = if self.mono {
+                left
+            } else {
+                match self.source.next() {
+                    Some(right) => right,
+                    None => return Some(left * self.gains[0]),
+                }
+            };
+            self.front = [left * self.gains[0], right * self.gains[1]];
+        }
+
+        let sample = match self.channel {
+            0 | 1 => self.front[self.channel as usize],
+            _ => self.source.next()?,
+        };
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Balance<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        // Right after the left sample, the right one is already computed.
+        let pending = usize::from(self.channel == 1);
+        let len = self.source.current_frame_len()?;
+        if self.source.channels() == 1 {
+            Some(len * 2 + pending)
+        } else {
+            Some(len + pending)
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels().max(2)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+/// Stereo width shared between the player and each track's pair of
+/// [`StereoWidth`] stages, only one of which is in use at a time.
+pub(crate) struct WidthControls {
+    width: AtomicF32,
+    placement: AtomicU8,
+}
+
+impl WidthControls {
+    pub(crate) fn new() -> Self {
+        WidthControls {
+            width: AtomicF32::new(1.0),
+            placement: AtomicU8::new(WidthPlacement::AfterEq as u8),
+        }
+    }
+
+    pub(crate) fn width(&self) -> f32 {
+        self.width.load()
+    }
+
+    /// Clamped to 0.0 (mono) ..= [`MAX_STEREO_WIDTH`].
+    pub(crate) fn set_width(&self, width: f32) {
+        self.width.store(width.clamp(0.0, MAX_STEREO_WIDTH));
+    }
+
+    pub(crate) fn placement(&self) -> WidthPlacement {
+        match self.placement.load(Ordering::Relaxed) {
+            0 => WidthPlacement::BeforeEq,
+            _ => WidthPlacement::AfterEq,
+        }
+    }
+
+    pub(crate) fn set_placement(&self, placement: WidthPlacement) {
+        self.placement.store(placement as u8, Ordering::Relaxed);
+    }
+}
+
+/// Widens or narrows a stereo pair by scaling its side (difference) signal
+/// against the mid (sum). The output is turned down by as much as the width
+/// goes over 1, so no sample can clip.
+///
+/// At a width of 1, and for sources that aren't exactly two channels, the
+/// signal passes through untouched. Moving the width, or the stage it is
+/// applied at, glides over [`WIDTH_RAMP`].
+pub(crate) struct StereoWidth<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<WidthControls>,
+    placement: WidthPlacement,
+    width: f32,
+    /// The right sample of the current frame, once computed.
+    right: Option<f32>,
+}
+
+impl<S> StereoWidth<S>
+where
+    S: Source<Item = f32>,
+{
+    /// A stage that applies the width while the controls place it at `placement`.
+    pub(crate) fn new(source: S, controls: Arc<WidthControls>, placement: WidthPlacement) -> Self {
+        let mut stage = StereoWidth {
+            source,
+            controls,
+            placement,
+            width: 1.0,
+            right: None,
+        };
+        stage.width = stage.target();
+        stage
+    }
+
+    fn target(&self) -> f32 {
+        match self.controls.placement() == self.placement {
+            true => self.controls.width(),
+            false => 1.0,
+        }
+    }
+}
+
+impl<S> Iterator for StereoWidth<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        // Anything but a stereo pair passes through. Pairs are read whole,
+        // so a change of format always falls between them.
+        if self.source.channels() != 2 {
+            return self.source.next();
+        }
+
+        let target = self.target();
+        if target != self.width {
+            let step = MAX_STEREO_WIDTH
+                / (WIDTH_RAMP.as_secs_f32() * self.source.sample_rate().max(1) as f32);
+            self.width += (target - self.width).clamp(-step, step);
+        }
+        let left = self.source.next()?;
+        let Some(right) = self.source.next() else {
+            return Some(left);
+        };
+        if self.width == 1.0 {
+            self.right = Some(right);
+            return Some(left);
+        }
+        let mid = (left + right) * 0.5;
+        let side = (left - right) * 0.5 * self.width;
+        let gain = 1.0 / self.width.max(1.0);
+        self.right = Some((mid - side) * gain);
+        Some((mid + side) * gain)
+    }
+}
+
+impl<S> Source for StereoWidth<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        let pending = usize::from(self.right.is_some());
+        self.source.current_frame_len().map(|len| len + pending)
+    }
+
+    fn channels(&self) -> u16 {
+        match self.right {
+            Some(_) => 2,
+            None => self.source.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.right = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{collect, noise};
+    use rodio::buffer::SamplesBuffer;
+
+    /// A second of noise, different on the left and the right.
+    fn wide() -> Vec<f32> {
+        let left = noise(1, 44100, 1).collect::<Vec<_>>();
+        let right = noise(2, 44100, 1);
+        left.into_iter()
+            .zip(right)
+            .flat_map(|(left, right)| [left, right])
+            .collect()
+    }
+
+    fn widened(samples: &[f32], channels: u16, width: f32) -> Vec<f32> {
+        let controls = Arc::new(WidthControls::new());
+        controls.set_width(width);
+        let source = SamplesBuffer::new(channels, 44100, samples.to_vec());
+        collect(StereoWidth::new(source, controls, WidthPlacement::AfterEq)).collect()
+    }
+
+    #[test]
+    fn a_width_of_zero_makes_both_channels_the_same() {
+        let output = widened(&wide(), 2, 0.0);
+        assert_eq!(output.len(), 2 * 44100);
+        for frame in output.chunks(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+    }
+
+    #[test]
+    fn a_width_of_one_leaves_every_bit_alone() {
+        let input = wide();
+        let output = widened(&input, 2, 1.0);
+        let bits = |samples: &[f32]| {
+            samples
+                .iter()
+                .map(|sample| sample.to_bits())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bits(&output), bits(&input));
+    }
+
+    #[test]
+    fn mono_passes_through_at_any_width() {
+        let input = noise(3, 44100, 1).collect::<Vec<_>>();
+        assert_eq!(widened(&input, 1, 0.0), input);
+        assert_eq!(widened(&input, 1, MAX_STEREO_WIDTH), input);
+    }
+}
@@ -0,0 +1,381 @@
+use crate::{
+    atomic::AtomicF32,
+    cue::CueSheet,
+    lock::Lock,
+    looping::LoopControls,
+    player::chapter_at,
+    queue::{PlaylistControls, Track},
+    silence::SilenceControls,
+    tempo::TempoControls,
+};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Playback position, derived from the frames the playlist has pulled
+/// through the chain, shared between the player and its event thread.
+///
+/// Counting frames in source time keeps it from drifting during underruns
+/// and makes it independent of the playback speed and tempo. Loop wraps
+/// are taken back out and skipped silence added back in. In a file split by a cue
+/// sheet, [`Clock::position`] is within the sheet's current track.
+///
+/// [`Clock::position`] never waits. Locks are always taken in field order,
+/// and `tracks` last.
+pub(crate) struct Clock {
+    /// The handover count, loop wraps and skipped silence when the current
+    /// track took over, in nanoseconds for the last two.
+    handovers: AtomicU64,
+    rewound_ns: AtomicU64,
+    skipped_ns: AtomicU64,
+    /// The last position worked out, for while the track list is busy.
+    last: LastPosition,
+    /// Handover count and cue track index last seen by [`Clock::cue_advanced`].
+    cue: Mutex<(u64, Option<usize>)>,
+    /// Track id and chapter last seen by [`Clock::chapter_changed`].
+    chapter: Mutex<(u64, Option<usize>)>,
+    wall: Mutex<WallTime>,
+    playing: AtomicBool,
+    /// Times the clock has started or stopped.
+    transitions: AtomicU64,
+    speed: AtomicF32,
+    tracks: Arc<Mutex<Vec<Track>>>,
+    playlist: Arc<PlaylistControls>,
+    looping: Arc<LoopControls>,
+    tempo: Arc<TempoControls>,
+    silence: Arc<SilenceControls>,
+}
+
+impl Clock {
+    pub(crate) fn new(
+        tracks: Arc<Mutex<Vec<Track>>>,
+        playlist: Arc<PlaylistControls>,
+        looping: Arc<LoopControls>,
+        tempo: Arc<TempoControls>,
+        silence: Arc<SilenceControls>,
+    ) -> Self {
+        Clock {
+            handovers: AtomicU64::new(0),
+            rewound_ns: AtomicU64::new(0),
+            skipped_ns: AtomicU64::new(0),
+            last: LastPosition::new(),
+            cue: Mutex::new((0, None)),
+            chapter: Mutex::new((0, None)),
+            wall: Mutex::new(WallTime {
+                track: (u64::MAX, 0),
+                played: Duration::ZERO,
+                since: None,
+            }),
+            playing: AtomicBool::new(false),
+            transitions: AtomicU64::new(0),
+            speed: AtomicF32::new(1.0),
+            tracks,
+            playlist,
+            looping,
+            tempo,
+            silence,
+        }
+    }
+
+    pub(crate) fn position(&self) -> Duration {
+        let handovers = self.playlist.handovers();
+        let counted = self.counted();
+        // The queue is being changed; carry on from the last position.
+        let Some(tracks) = self.tracks.try_locked() else {
+            return self.last.since(handovers, counted).unwrap_or(counted);
+        };
+        let (position, cue) = self.locate_in(&tracks, counted);
+        let position = match cue {
+            Some(cue) => position.saturating_sub(cue.bounds(cue.track_at(position)).0),
+            None => position,
+        };
+        // Still holding the lock, so no other call writes at the same time.
+        self.last.store(handovers, counted, position);
+        position
+    }
+
+    /// The current track's cue sheet, if it has one, and the index of the
+    /// sheet's track playing now.
+    pub(crate) fn cue_track(&self) -> Option<(Arc<CueSheet>, usize)> {
+        let (position, cue) = self.locate();
+        cue.map(|cue| {
+            let index = cue.track_at(position);
+            (cue, index)
+        })
+    }
+
+    /// How long until the next cue track takes over, at the current speed and tempo.
+    pub(crate) fn until_cue_boundary(&self) -> Option<Duration> {
+        if !self.is_playing() {
+            return None;
+        }
+        let (position, cue) = self.locate();
+        let cue = cue?;
+        let (_, end) = cue.bounds(cue.track_at(position));
+        Some(end?.saturating_sub(position).div_f32(self.rate()))
+    }
+
+    /// Returns the chapter playing now if that has changed since the last
+    /// call, by playing on, seeking or moving to another track.
+    pub(crate) fn chapter_changed(&self) -> Option<usize> {
+        let track = self.current_track()?;
+        let chapter = chapter_at(&track.chapters, self.file_position());
+        let mut seen = self.chapter.locked();
+        let changed = *seen != (track.id, chapter);
+        *seen = (track.id, chapter);
+        chapter.filter(|_| changed)
+    }
+
+    /// How long until the next chapter starts, at the current speed and tempo.
+    pub(crate) fn until_chapter_boundary(&self) -> Option<Duration> {
+        if !self.is_playing() {
+            return None;
+        }
+        let track = self.current_track()?;
+        let position = self.file_position();
+        let next = track
+            .chapters
+            .iter()
+            .find(|chapter| chapter.start > position)?;
+        Some((next.start - position).div_f32(self.rate()))
+    }
+
+    /// How much source time passes per second of playback.
+    pub(crate) fn rate(&self) -> f32 {
+        (self.speed() * self.tempo.ratio()).max(f32::EPSILON)
+    }
+
+    /// See [`PlaylistControls::played`].
+    pub(crate) fn played(&self) -> Duration {
+        self.playlist.played()
+    }
+
+    pub(crate) fn playthroughs(&self) -> u64 {
+        self.playlist.playthroughs()
+    }
+
+    /// Returns the path of the current file if playback has run on into the
+    /// next track of its cue sheet since the last call. Moving there through
+    /// [`Clock::set`] or a new track doesn't count.
+    pub(crate) fn cue_advanced(&self) -> Option<PathBuf> {
+        let handovers = self.playlist.handovers();
+        let index = self.cue_track().map(|(_, index)| index);
+        let mut seen = self.cue.locked();
+        let advanced = match (*seen, index) {
+            ((seen_handovers, Some(seen)), Some(index)) => {
+                seen_handovers == handovers && index == seen + 1
+            }
+            _ => false,
+        };
+        *seen = (handovers, index);
+        drop(seen);
+        advanced
+            .then(|| self.track(self.playlist.current()))
+            .flatten()
+            .map(|track| track.path)
+    }
+
+    /// The position in the current file, ignoring any cue sheet.
+    pub(crate) fn file_position(&self) -> Duration {
+        self.locate().0
+    }
+
+    pub(crate) fn current_track(&self) -> Option<Track> {
+        self.track(self.playlist.current())
+    }
+
+    /// The file position and cue sheet of the current track.
+    fn locate(&self) -> (Duration, Option<Arc<CueSheet>>) {
+        let counted = self.counted();
+        self.locate_in(&self.tracks.locked(), counted)
+    }
+
+    /// The frames the playlist has counted in the current track, less loop
+    /// wraps and plus skipped silence, from atomics alone.
+    fn counted(&self) -> Duration {
+        let handovers = self.playlist.handovers();
+        let rewound = self.looping.rewound();
+        let skipped = self.silence.skipped();
+        if handovers != self.handovers.load(Ordering::Acquire) {
+            // A new track, or the same one again, hasn't wrapped any loop
+            // or skipped any silence yet. Calls racing here store the same.
+            self.rewound_ns
+                .store(rewound.as_nanos() as u64, Ordering::Relaxed);
+            self.skipped_ns
+                .store(skipped.as_nanos() as u64, Ordering::Relaxed);
+            self.handovers.store(handovers, Ordering::Release);
+        }
+        let seen_rewound = Duration::from_nanos(self.rewound_ns.load(Ordering::Relaxed));
+        let seen_skipped = Duration::from_nanos(self.skipped_ns.load(Ordering::Relaxed));
+        // The frame count runs on through loop wraps, so take those back
+        // out, and leaves out skipped silence.
+        self.playlist
+            .position()
+            .saturating_sub(rewound.saturating_sub(seen_rewound))
+            + skipped.saturating_sub(seen_skipped)
+    }
+
+    /// The file position `counted` comes to in the current track of
+    /// `tracks`, and the track's cue sheet.
+    fn locate_in(&self, tracks: &[Track], position: Duration) -> (Duration, Option<Arc<CueSheet>>) {
+        let current = self.playlist.current();
+        let Some(track) = tracks.iter().find(|entry| entry.id == current) else {
+            return (position, None);
+        };
+        // Trimmed leading silence never reaches the playlist.
+        let position = position + track.lead;
+        let position = match track.duration {
+            Some(duration) => position.min(duration),
+            None => position,
+        };
+        (position, track.cue.clone())
+    }
+
+    /// Restarts the count at `position` in the playlist's current track.
+    pub(crate) fn set(&self, position: Duration) {
+        self.rewound_ns
+            .store(self.looping.rewound().as_nanos() as u64, Ordering::Relaxed);
+        self.skipped_ns
+            .store(self.silence.skipped().as_nanos() as u64, Ordering::Relaxed);
+        self.handovers
+            .store(self.playlist.handovers(), Ordering::Release);
+        let mut seen_cue = self.cue.locked();
+        let tracks = self.tracks.locked();
+        let track = tracks
+            .iter()
+            .find(|track| track.id == self.playlist.current());
+        let lead = track.map_or(Duration::ZERO, |track| track.lead);
+        self.playlist.set_position(position.saturating_sub(lead));
+        let index = track
+            .and_then(|track| track.cue.as_ref())
+            .map(|cue| cue.track_at(position));
+        drop(tracks);
+        *seen_cue = (self.playlist.handovers(), index);
+    }
+
+    pub(crate) fn track(&self, id: u64) -> Option<Track> {
+        let tracks = self.tracks.locked();
+        tracks.iter().find(|track| track.id == id).cloned()
+    }
+
+    pub(crate) fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the clock was already running.
+    pub(crate) fn set_playing(&self, playing: bool) -> bool {
+        let mut wall = self.wall.locked();
+        let was_playing = self.playing.swap(playing, Ordering::Relaxed);
+        if was_playing != playing {
+            self.transitions.fetch_add(1, Ordering::Relaxed);
+            match wall.since.take() {
+                Some(since) => wall.played += since.elapsed(),
+                None => wall.since = playing.then(Instant::now),
+            }
+        }
+        was_playing
+    }
+
+    /// How long the current track has played for by the wall clock, pauses
+    /// left out. Seeks and changes of rate move the position but not this.
+    pub(crate) fn elapsed_wall_time(&self) -> Duration {
+        let track = (self.playlist.current(), self.playlist.handovers());
+        let mut wall = self.wall.locked();
+        if wall.track != track {
+            // The track took over since last asked, about as long ago as
+            // what it has played takes at the rate now.
+            wall.track = track;
+            wall.played = self.counted().div_f32(self.rate());
+            wall.since = self.is_playing().then(Instant::now);
+        }
+        wall.played + wall.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Takes `counted`, the part of a suspend of the system that [`Instant`]
+    /// went on through, out of the wall time played.
+    pub(crate) fn suspended(&self, counted: Duration) {
+        let mut wall = self.wall.locked();
+        if let Some(since) = wall.since.as_mut() {
+            *since += counted.min(since.elapsed());
+        }
+        self.transitions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many times the clock has started or stopped, or the system was
+    /// suspended, so time spent paused can be told from a gap in playback.
+    pub(crate) fn transitions(&self) -> u64 {
+        self.transitions.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn speed(&self) -> f32 {
+        self.speed.load()
+    }
+
+    pub(crate) fn set_speed(&self, speed: f32) {
+        self.speed.store(speed);
+    }
+}
+
+/// The wall-clock time one track has played for.
+struct WallTime {
+    /// The track's id, and the handover count when it took over.
+    track: (u64, u64),
+    /// Time played up to `since`.
+    played: Duration,
+    /// When playing last started, while it plays.
+    since: Option<Instant>,
+}
+
+/// A position and the count it was worked out from, written by one thread
+/// at a time and read by any without waiting: a read that overlaps a write
+/// sees the sequence number change and gives up instead.
+struct LastPosition {
+    /// Odd while a write is under way.
+    sequence: AtomicU64,
+    handovers: AtomicU64,
+    counted_ns: AtomicU64,
+    position_ns: AtomicU64,
+}
+
+impl LastPosition {
+    fn new() -> Self {
+        LastPosition {
+            sequence: AtomicU64::new(0),
+            handovers: AtomicU64::new(u64::MAX),
+            counted_ns: AtomicU64::new(0),
+            position_ns: AtomicU64::new(0),
+        }
+    }
+
+    fn store(&self, handovers: u64, counted: Duration, position: Duration) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.handovers.store(handovers, Ordering::Relaxed);
+        self.counted_ns
+            .store(counted.as_nanos() as u64, Ordering::Relaxed);
+        self.position_ns
+            .store(position.as_nanos() as u64, Ordering::Relaxed);
+        self.sequence.fetch_add(1, Ordering::Release);
+    }
+
+    /// The last position moved on by what has been counted since, if it was
+    /// worked out for the same track and could be read whole.
+    fn since(&self, handovers: u64, counted: Duration) -> Option<Duration> {
+        let before = self.sequence.load(Ordering::Acquire);
+        let stored = (
+            self.handovers.load(Ordering::Relaxed),
+            Duration::from_nanos(self.counted_ns.load(Ordering::Relaxed)),
+            Duration::from_nanos(self.position_ns.load(Ordering::Relaxed)),
+        );
+        fence(Ordering::Acquire);
+        let after = self.sequence.load(Ordering::Relaxed);
+        let (stored_handovers, stored_counted, position) = stored;
+        (before.is_multiple_of(2) && before == after && stored_handovers == handovers)
+            .then(|| (position + counted).saturating_sub(stored_counted))
+    }
+}
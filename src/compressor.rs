@@ -0,0 +1,405 @@
+use crate::{
+    atomic::AtomicF32,
+    gain::{db_to_linear, linear_to_db},
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Time constant of the glide to new settings, so changing them doesn't click.
+const GLIDE: Duration = Duration::from_millis(20);
+
+/// Below this much gain change, in dB, a compressor that is off passes the
+/// source straight through.
+const SETTLED_DB: f32 = 0.01;
+
+/// How a [`Compressor`] turns loud passages down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorSettings {
+    /// Level in dBFS above which the gain comes down.
+    pub threshold_db: f32,
+    /// How many dB over the threshold in make one dB over it out; at least 1.
+    pub ratio: f32,
+    pub attack: Duration,
+    pub release: Duration,
+    /// Width in dB of the soft knee around the threshold; 0 is a hard knee.
+    pub knee_db: f32,
+    /// Gain applied afterwards to make up for the reduction.
+    pub makeup_db: f32,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        CompressorSettings {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            attack: Duration::from_millis(10),
+            release: Duration::from_millis(100),
+            knee_db: 6.0,
+            makeup_db: 0.0,
+        }
+    }
+}
+
+/// Settings shared between a [`Compressor`] and the thread adjusting it.
+pub struct CompressorControls {
+    enabled: AtomicBool,
+    threshold_db: AtomicF32,
+    ratio: AtomicF32,
+    attack_ms: AtomicF32,
+    release_ms: AtomicF32,
+    knee_db: AtomicF32,
+    makeup_db: AtomicF32,
+    reduction_db: AtomicF32,
+}
+
+impl CompressorControls {
+    /// Controls for `settings`, or for a compressor that is off with `None`.
+    pub fn new(settings: Option<CompressorSettings>) -> Self {
+        let controls = CompressorControls {
+            enabled: AtomicBool::new(false),
+            threshold_db: AtomicF32::new(0.0),
+            ratio: AtomicF32::new(1.0),
+            attack_ms: AtomicF32::new(0.0),
+            release_ms: AtomicF32::new(0.0),
+            knee_db: AtomicF32::new(0.0),
+            makeup_db: AtomicF32::new(0.0),
+            reduction_db: AtomicF32::new(0.0),
+        };
+        controls.store(settings.unwrap_or_default());
+        controls
+            .enabled
+            .store(settings.is_some(), Ordering::Relaxed);
+        controls
+    }
+
+    /// The current settings, or `None` while the compressor is off.
+    pub fn settings(&self) -> Option<CompressorSettings> {
+        self.enabled
+            .load(Ordering::Relaxed)
+            .then(|| CompressorSettings {
+                threshold_db: self.threshold_db.load(),
+                ratio: self.ratio.load(),
+                attack: Duration::from_secs_f32(self.attack_ms.load() / 1000.0),
+                release: Duration::from_secs_f32(self.release_ms.load() / 1000.0),
+                knee_db: self.knee_db.load(),
+                makeup_db: self.makeup_db.load(),
+            })
+    }
+
+    /// Changes the settings, which glide into place, or lets the gain
+    /// recover and turns the compressor off with `None`.
+    pub fn set_settings(&self, settings: Option<CompressorSettings>) {
+        if let Some(settings) = settings {
+            self.store(settings);
+        }
+        self.enabled.store(settings.is_some(), Ordering::Relaxed);
+    }
+
+    fn store(&self, settings: CompressorSettings) {
+        self.threshold_db.store(settings.threshold_db.min(0.0));
+        self.ratio.store(settings.ratio.max(1.0));
+        self.attack_ms.store(settings.attack.as_secs_f32() * 1000.0);
+        self.release_ms
+            .store(settings.release.as_secs_f32() * 1000.0);
+        self.knee_db.store(settings.knee_db.max(0.0));
+        self.makeup_db.store(settings.makeup_db);
+    }
+
+    /// How far the compressor is currently pulling the level down, in
+    /// positive dB, before the make-up gain.
+    pub fn current_gain_reduction(&self) -> f32 {
+        self.reduction_db.load()
+    }
+}
+
+/// The settings a [`Compressor`] is gliding towards, in the form it uses.
+#[derive(Clone, Copy)]
+struct Curve {
+    threshold_db: f32,
+    /// One over the ratio, which glides more evenly than the ratio does.
+    slope: f32,
+    knee_db: f32,
+    makeup_db: f32,
+}
+
+impl Curve {
+    /// Gain in dB, at most 0, for a level of `level_db`.
+    fn gain_db(&self, level_db: f32) -> f32 {
+        let over = level_db - self.threshold_db;
+        let half_knee = self.knee_db / 2.0;
+        if over <= -half_knee {
+            0.0
+        } else if over < half_knee {
+            let into = over + half_knee;
+            (self.slope - 1.0) * into * into / (2.0 * self.knee_db)
+        } else {
+            (self.slope - 1.0) * over
+        }
+    }
+
+    fn glide(&mut self, target: &Curve, amount: f32) {
+        self.threshold_db += (target.threshold_db - self.threshold_db) * amount;
+        self.slope += (target.slope - self.slope) * amount;
+        self.knee_db += (target.knee_db - self.knee_db) * amount;
+        self.makeup_db += (target.makeup_db - self.makeup_db) * amount;
+    }
+}
+
+/// Feed-forward compressor with a soft knee.
+///
+/// The level is taken from the loudest channel of each frame and all of
+/// them get the same gain, so the stereo image stays put. The gain follows
+/// with the attack time going down and the release time coming back up.
+/// Turned off, the gain recovers and the source then passes through
+/// untouched.
+pub struct Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<CompressorControls>,
+    curve: Curve,
+    /// Peak level, falling off at the release rate between peaks so the
+    /// gain holds steady through each cycle of a low note.
+    level: f32,
+    /// Gain reduction being applied, in dB at most 0.
+    envelope_db: f32,
+    active: bool,
+    channels: u16,
+    sample_rate: u32,
+    frame: Vec<f32>,
+    position: usize,
+    /// Channel of the next sample while passing the source through.
+    channel: u16,
+}
+
+impl<S> Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, settings: CompressorSettings) -> Self {
+        Self::with_controls(source, Arc::new(CompressorControls::new(Some(settings))))
+    }
+
+    pub fn with_controls(source: S, controls: Arc<CompressorControls>) -> Self {
+        let channels = source.channels().max(1);
+        Compressor {
+            channels,
+            sample_rate: source.sample_rate(),
+            source,
+            curve: Self::target(&controls),
+            controls,
+            level: 0.0,
+            envelope_db: 0.0,
+            active: false,
+            frame: Vec::with_capacity(channels as usize),
+            position: 0,
+            channel: 0,
+        }
+    }
+
+    pub fn controls(&self) -> Arc<CompressorControls> {
+        self.controls.clone()
+    }
+
+    /// The curve the controls ask for: flat while the compressor is off.
+    fn target(controls: &CompressorControls) -> Curve {
+        match controls.enabled.load(Ordering::Relaxed) {
+            true => Curve {
+                threshold_db: controls.threshold_db.load(),
+                slope: 1.0 / controls.ratio.load(),
+                knee_db: controls.knee_db.load(),
+                makeup_db: controls.makeup_db.load(),
+            },
+            false => Self::flat(controls),
+        }
+    }
+
+    fn flat(controls: &CompressorControls) -> Curve {
+        Curve {
+            threshold_db: controls.threshold_db.load(),
+            slope: 1.0,
+            knee_db: controls.knee_db.load(),
+            makeup_db: 0.0,
+        }
+    }
+
+    /// One-pole coefficient for a time constant of `ms`.
+    fn coefficient(&self, ms: f32) -> f32 {
+        let frames = ms / 1000.0 * self.sample_rate as f32;
+        match frames > 0.0 {
+            true => (-1.0 / frames).exp(),
+            false => 0.0,
+        }
+    }
+
+    /// Reads and compresses one frame. Returns `false` once the source has ended.
+    fn process_frame(&mut self) -> bool {
+        self.channels = self.source.channels().max(1);
+        self.sample_rate = self.source.sample_rate();
+        self.frame.clear();
+        self.position = 0;
+        for _ in 0..self.channels {
+            match self.source.next() {
+                Some(sample) => self.frame.push(sample),
+                None => break,
+            }
+        }
+        if self.frame.is_empty() {
+            return false;
+        }
+
+        let glide = 1.0 - self.coefficient(GLIDE.as_secs_f32() * 1000.0);
+        self.curve.glide(&Self::target(&self.controls), glide);
+        let peak = self
+            .frame
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        let (attack_ms, release_ms) = (
+            self.controls.attack_ms.load(),
+            self.controls.release_ms.load(),
+        );
+        let release = self.coefficient(release_ms);
+        self.level = peak.max(self.level * release);
+        let target_db = self.curve.gain_db(linear_to_db(self.level.max(1e-9)));
+        let coefficient = match target_db < self.envelope_db {
+            true => self.coefficient(attack_ms),
+            false => release,
+        };
+        self.envelope_db = target_db + (self.envelope_db - target_db) * coefficient;
+        self.controls.reduction_db.store(-self.envelope_db);
+
+        let gain = db_to_linear(self.envelope_db + self.curve.makeup_db);
+        for sample in &mut self.frame {
+            *sample *= gain;
+        }
+
+        if !self.controls.enabled.load(Ordering::Relaxed)
+            && -self.envelope_db < SETTLED_DB
+            && self.curve.makeup_db.abs() < SETTLED_DB
+            && 1.0 - self.curve.slope < 1e-4
+        {
+            self.level = 0.0;
+            self.envelope_db = 0.0;
+            self.curve = Self::flat(&self.controls);
+            self.controls.reduction_db.store(0.0);
+            self.active = false;
+            self.channel = 0;
+        }
+        true
+    }
+}
+
+impl<S> Iterator for Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(&sample) = self.frame.get(self.position) {
+                self.position += 1;
+                return Some(sample);
+            }
+            if self.active {
+                if !self.process_frame() {
+                    return None;
+                }
+                continue;
+            }
+            if self.channel == 0 && self.controls.enabled.load(Ordering::Relaxed) {
+                self.active = true;
+                continue;
+            }
+
+            let sample = self.source.next()?;
+            self.channel = (self.channel + 1) % self.source.channels().max(1);
+            return Some(sample);
+        }
+    }
+}
+
+impl<S> Source for Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        let waiting = self.frame.len() - self.position;
+        self.source.current_frame_len().map(|len| len + waiting)
+    }
+
+    fn channels(&self) -> u16 {
+        match self.position < self.frame.len() {
+            true => self.channels,
+            false => self.source.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self.position < self.frame.len() {
+            true => self.sample_rate,
+            false => self.source.sample_rate(),
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    /// Keeps the current gain, so the level doesn't jump back up for a moment.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.frame.clear();
+        self.position = 0;
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generators::{GeneratorSettings, SineWave},
+        testing::{channel, collect, peak, second},
+    };
+
+    #[test]
+    fn a_sine_over_the_threshold_comes_out_at_the_textbook_level() {
+        let settings = GeneratorSettings {
+            amplitude: db_to_linear(-6.0),
+            ..second(48000, 2)
+        };
+        let compressor = Compressor::new(
+            SineWave::new(1000.0, settings),
+            CompressorSettings {
+                threshold_db: -20.0,
+                ratio: 4.0,
+                knee_db: 0.0,
+                ..CompressorSettings::default()
+            },
+        );
+        let controls = compressor.controls();
+        let output = collect(compressor).collect::<Vec<_>>();
+        // 14 dB over at 4:1 comes out 3.5 dB over.
+        let expected_db = -20.0 + 14.0 / 4.0;
+        // Well past the 10 ms attack.
+        let settled = &output[output.len() / 2..];
+        for index in 0..2 {
+            let level_db = linear_to_db(peak(&channel(settled, 2, index)));
+            assert!(
+                (level_db - expected_db).abs() < 0.2,
+                "channel {index}: {level_db} dBFS"
+            );
+        }
+        let reduction = controls.current_gain_reduction();
+        assert!((reduction - 10.5).abs() < 0.2, "reduced by {reduction} dB");
+    }
+}
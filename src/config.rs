@@ -0,0 +1,524 @@
+use crate::{
+    backend::Backend,
+    decode::DecoderBackend,
+    dither::Dither,
+    equalizer::{BAND_COUNT, THIRD_OCTAVE_BAND_COUNT},
+    error::PlayerError,
+    format::{toml, ParseError, Value},
+    output::{Latency, OutputConfig, OutputFormat},
+    player::AudioPlayer,
+    preset::EqPreset,
+    queue::RepeatMode,
+    replaygain::ReplayGainMode,
+    resample::ResampleQuality,
+    settings::EqSettings,
+};
+use std::{
+    env,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Defaults for a player and its output, kept in a TOML file so they don't
+/// have to be given every time:
+///
+/// ```toml
+/// [eq]
+/// preset = "Rock"        # or gains = [10 or 31 gains in dB]
+/// q = [0.707, 1.41, ...] # per band, in the same order
+/// preamp_db = -3.0
+///
+/// [output]
+/// backend = "jack"
+/// device = "system:playback"
+/// latency = "low"        # low, default or safe
+/// buffer_frames = 256    # over latency
+/// sample_rate = 96000    # else the first file's, where the device goes at it
+/// sample_format = "i24"  # f32, i16, i24 or i32
+/// resample = "high"      # fast, medium or high, for files at another rate
+/// dither = "tpdf"        # off, tpdf or shaped; for 24-bit output and below
+/// volume_db = -6.0
+///
+/// [playback]
+/// crossfade = 2.0        # seconds
+/// repeat = "all"         # off, one or all
+/// shuffle = true
+/// replaygain = "album"   # off, track or album
+/// replaygain_preamp_db = 3.0
+/// replaygain_default_db = -6.0 # for files without tags
+/// decoder = "auto"      # auto or rodio, or symphonia where built in
+///
+/// [effects]
+/// chain = "my_setup.json" # a ChainConfig, beside this file unless absolute
+///
+/// [eq_profiles]
+/// write_back = true      # keep EQ changes in the playing device's profile
+///
+/// [eq_profiles.default]  # for other devices; as in an EQ settings file
+/// preamp_db = -2.0
+/// bands = [{ type = "low_shelf", frequency = 105.0, gain_db = 2.0, q = 0.707 }]
+///
+/// [eq_profiles.devices."Built-in Speakers"]
+/// bands = [{ type = "peaking", frequency = 3000.0, gain_db = -4.0, q = 1.41 }]
+/// ```
+///
+/// Every setting is optional, and those left out keep the player's own.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PlayerConfig {
+    /// Ten octave-band or 31 third-octave gains in dB.
+    pub eq_gains: Option<Vec<f32>>,
+    /// A built-in curve, in place of `eq_gains`.
+    pub eq_preset: Option<EqPreset>,
+    /// The Q of each band, over those of the layout or preset.
+    pub eq_q: Option<Vec<f32>>,
+    /// A fixed preamp in dB, in place of automatic headroom.
+    pub preamp_db: Option<f32>,
+    pub backend: Option<Backend>,
+    pub device: Option<String>,
+    pub latency: Option<Latency>,
+    /// Frames per output buffer, over `latency`.
+    pub buffer_frames: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub sample_format: Option<OutputFormat>,
+    pub resample: Option<ResampleQuality>,
+    pub dither: Option<Dither>,
+    pub volume_db: Option<f32>,
+    pub crossfade: Option<Duration>,
+    pub repeat: Option<RepeatMode>,
+    pub shuffle: Option<bool>,
+    pub replaygain: Option<ReplayGainMode>,
+    pub replaygain_preamp_db: Option<f32>,
+    /// For files without ReplayGain tags.
+    pub replaygain_default_db: Option<f32>,
+    pub decoder: Option<DecoderBackend>,
+    /// EQ settings to play on each output device, by name.
+    pub device_profiles: Vec<(String, EqSettings)>,
+    /// For output devices not in `device_profiles`.
+    pub default_profile: Option<EqSettings>,
+    /// Keep changes made to the EQ in the profile of the device they were
+    /// made on.
+    pub profile_write_back: Option<bool>,
+    /// A [`ChainConfig`](crate::ChainConfig) file to set up the EQ and
+    /// effects from.
+    pub chain: Option<PathBuf>,
+}
+
+impl PlayerConfig {
+    /// `config.toml` in the user's config directory: `$XDG_CONFIG_HOME` or
+    /// `~/.config` on Linux, `~/Library/Application Support` on macOS and
+    /// `%APPDATA%` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let home = || env::var_os("HOME").map(PathBuf::from);
+        let dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            home().map(|home| home.join("Library/Application Support"))
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| home().map(|home| home.join(".config")))
+        };
+        dir.map(|dir| dir.join("fullyrustaudio").join("config.toml"))
+    }
+
+    /// Reads the file at [`PlayerConfig::default_path`]. No file, or no
+    /// telling where it would be, is an empty config.
+    pub fn load() -> Result<Self, ConfigError> {
+        match Self::default_path() {
+            Some(path) => Self::load_from(path),
+            None => Ok(PlayerConfig::default()),
+        }
+    }
+
+    /// Reads the config at `path`; a missing file is an empty config.
+    /// Unknown keys and values of the wrong kind fail with their line.
+    /// A relative `chain` path is taken from the config's directory.
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let mut config = Self::parse(&text)?;
+                if let (Some(chain), Some(dir)) = (&mut config.chain, path.parent()) {
+                    *chain = dir.join(&*chain);
+                }
+                Ok(config)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(PlayerConfig::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the config to `path` as TOML, creating its directory, in a form
+    /// [`PlayerConfig::load_from`] reads back the same.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string(&self.to_value()))?;
+        Ok(())
+    }
+
+    /// What to ask the output device for, as
+    /// [`AudioEngine::with_output_config`](crate::AudioEngine::with_output_config)
+    /// takes it.
+    pub fn output_config(&self) -> OutputConfig {
+        let config = OutputConfig {
+            buffer_frames: self.buffer_frames,
+            latency: self.latency.unwrap_or_default(),
+            sample_rate: self.sample_rate,
+            dither: self.dither.unwrap_or_default(),
+            ..OutputConfig::default()
+        };
+        match self.sample_format {
+            Some(format) => config.with_format(format),
+            None => config,
+        }
+    }
+
+    /// Sets up `player` with every setting the config has. The backend,
+    /// device and buffering can't change on a player that is already open;
+    /// they are for
+    /// [`AudioEngine::with_output_config`](crate::AudioEngine::with_output_config).
+    ///
+    /// Fails, changing nothing, if `eq_gains` are neither ten nor 31 gains.
+    pub fn apply(&self, player: &AudioPlayer) -> Result<(), PlayerError> {
+        if let Some(gains) = &self.eq_gains {
+            let mut settings = player.eq_settings();
+            settings.bands = EqSettings::for_gains(gains)?.bands;
+            player.set_eq_settings(settings);
+        }
+        if let Some(preset) = self.eq_preset {
+            player.apply_preset(preset);
+        }
+        if let Some(q) = &self.eq_q {
+            let mut settings = player.eq_settings();
+            settings.set_q(q);
+            player.set_eq_settings(settings);
+        }
+        if let Some(preamp_db) = self.preamp_db {
+            player.set_auto_headroom(false);
+            player.set_preamp_db(preamp_db);
+        }
+        if let Some(volume_db) = self.volume_db {
+            player.set_volume_db(volume_db);
+        }
+        if let Some(crossfade) = self.crossfade {
+            player.set_crossfade(crossfade);
+        }
+        if let Some(repeat) = self.repeat {
+            player.set_repeat(repeat);
+        }
+        if let Some(shuffle) = self.shuffle {
+            player.set_shuffle(shuffle);
+        }
+        if let Some(preamp_db) = self.replaygain_preamp_db {
+            player.set_replaygain_preamp(preamp_db);
+        }
+        if let Some(gain_db) = self.replaygain_default_db {
+            player.set_replaygain_default(gain_db);
+        }
+        if let Some(backend) = self.decoder {
+            player.set_decoder_backend(backend);
+        }
+        if let Some(quality) = self.resample {
+            player.set_resample_quality(quality);
+        }
+        if let Some(mode) = self.replaygain {
+            player.set_replaygain(mode);
+        }
+        if let Some(enabled) = self.profile_write_back {
+            player.set_profile_write_back(enabled);
+        }
+        for (device, settings) in &self.device_profiles {
+            player.set_device_profile(device, settings.clone());
+        }
+        if let Some(settings) = &self.default_profile {
+            player.set_default_profile(Some(settings.clone()));
+        }
+        Ok(())
+    }
+
+    fn to_value(&self) -> Value {
+        let list = |values: &Option<Vec<f32>>| {
+            values
+                .as_ref()
+                .map(|values| values.iter().map(|&v| Value::from(v)).collect::<Vec<_>>())
+        };
+        let eq = Value::table()
+            .with("gains", list(&self.eq_gains))
+            .with("preset", self.eq_preset.map(EqPreset::name))
+            .with("q", list(&self.eq_q))
+            .with("preamp_db", self.preamp_db);
+        let output = Value::table()
+            .with("backend", self.backend.map(Backend::name))
+            .with("device", self.device.clone())
+            .with("latency", self.latency.map(Latency::name))
+            .with("buffer_frames", self.buffer_frames.map(u64::from))
+            .with("sample_rate", self.sample_rate.map(u64::from))
+            .with("sample_format", self.sample_format.map(OutputFormat::name))
+            .with("resample", self.resample.map(ResampleQuality::name))
+            .with("dither", self.dither.map(Dither::name))
+            .with("volume_db", self.volume_db);
+        let playback = Value::table()
+            .with(
+                "crossfade",
+                self.crossfade.map(|crossfade| crossfade.as_secs_f64()),
+            )
+            .with("repeat", self.repeat.map(RepeatMode::name))
+            .with("shuffle", self.shuffle)
+            .with("replaygain", self.replaygain.map(ReplayGainMode::name))
+            .with("replaygain_preamp_db", self.replaygain_preamp_db)
+            .with("replaygain_default_db", self.replaygain_default_db)
+            .with("decoder", self.decoder.map(DecoderBackend::name));
+        let devices = self
+            .device_profiles
+            .iter()
+            .map(|(device, settings)| (device.clone(), settings.to_value()))
+            .collect::<Vec<_>>();
+        let eq_profiles = Value::table()
+            .with("write_back", self.profile_write_back)
+            .with(
+                "default",
+                self.default_profile.as_ref().map(EqSettings::to_value),
+            )
+            .with(
+                "devices",
+                (!devices.is_empty()).then_some(Value::Table(devices)),
+            );
+        let effects = Value::table().with(
+            "chain",
+            self.chain
+                .as_ref()
+                .map(|chain| chain.to_string_lossy().into_owned()),
+        );
+        // Sections with nothing set are left out rather than written empty.
+        let section = |table: Value| match &table {
+            Value::Table(entries) if entries.iter().all(|(_, v)| *v == Value::Null) => Value::Null,
+            _ => table,
+        };
+        Value::table()
+            .with("eq", section(eq))
+            .with("output", section(output))
+            .with("playback", section(playback))
+            .with("effects", section(effects))
+            .with("eq_profiles", section(eq_profiles))
+    }
+
+    fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut config = PlayerConfig::default();
+        let Value::Table(sections) = toml::parse(text)? else {
+            return Ok(config);
+        };
+        for (section, entries) in &sections {
+            let Value::Table(entries) = entries else {
+                let line = toml::key_line(text, "", section).unwrap_or(1);
+                return Err(ParseError::new(
+                    line,
+                    format!("'{section}' isn't a known setting"),
+                ));
+            };
+            for (key, value) in entries {
+                let invalid = |message: &str| {
+                    let line = toml::key_line(text, section, key).unwrap_or(1);
+                    ParseError::new(line, format!("'{section}.{key}' {message}"))
+                };
+                let number = || value.as_f32().ok_or_else(|| invalid("must be a number"));
+                let string = || value.as_str().ok_or_else(|| invalid("must be a string"));
+                // A list of numbers for every band of either layout.
+                let bands = |what: &str| {
+                    let values = value
+                        .as_array()
+                        .and_then(|values| {
+                            values.iter().map(Value::as_f32).collect::<Option<Vec<_>>>()
+                        })
+                        .ok_or_else(|| invalid("must be a list of numbers"))?;
+                    if !matches!(values.len(), BAND_COUNT | THIRD_OCTAVE_BAND_COUNT) {
+                        return Err(invalid(&format!(
+                            "must have {BAND_COUNT} or {THIRD_OCTAVE_BAND_COUNT} {what}, not {}",
+                            values.len()
+                        )));
+                    }
+                    Ok(values)
+                };
+                match (section.as_str(), key.as_str()) {
+                    ("eq", "gains") => config.eq_gains = Some(bands("gains")?),
+                    ("eq", "q") => {
+                        let q = bands("values")?;
+                        if !q.iter().all(|q| *q > 0.0 && q.is_finite()) {
+                            return Err(invalid("must all be above 0"));
+                        }
+                        config.eq_q = Some(q);
+                    }
+                    ("eq", "preset") => {
+                        let preset = string()?
+                            .parse()
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.eq_preset = Some(preset);
+                    }
+                    ("eq", "preamp_db") => config.preamp_db = Some(number()?),
+                    ("output", "backend") => {
+                        let backend = string()?
+                            .parse()
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.backend = Some(backend);
+                    }
+                    ("output", "device") => config.device = Some(string()?.to_string()),
+                    ("output", "latency") => {
+                        let latency = string()?
+                            .parse()
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.latency = Some(latency);
+                    }
+                    ("output", "buffer_frames") => {
+                        let frames = value
+                            .as_f64()
+                            .filter(|frames| frames.fract() == 0.0 && *frames >= 1.0)
+                            .and_then(|frames| u32::try_from(frames as u64).ok())
+                            .ok_or_else(|| {
+                                invalid("must be a whole number of frames, at least 1")
+                            })?;
+                        config.buffer_frames = Some(frames);
+                    }
+                    ("output", "sample_rate") => {
+                        let rate = value
+                            .as_f64()
+                            .filter(|rate| rate.fract() == 0.0 && *rate >= 1.0)
+                            .and_then(|rate| u32::try_from(rate as u64).ok())
+                            .ok_or_else(|| invalid("must be a whole number of Hz, at least 1"))?;
+                        config.sample_rate = Some(rate);
+                    }
+                    ("output", "sample_format") => {
+                        let format = string()?
+                            .parse()
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.sample_format = Some(format);
+                    }
+                    ("output", "resample") => {
+                        let quality = string()?
+                            .parse()
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.resample = Some(quality);
+                    }
+                    ("output", "dither") => {
+                        let dither = string()?
+                            .parse()
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.dither = Some(dither);
+                    }
+                    ("output", "volume_db") => config.volume_db = Some(number()?),
+                    ("playback", "crossfade") => {
+                        let crossfade = value
+                            .as_f64()
+                            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                            .ok_or_else(|| invalid("must be a number of seconds, at least 0"))?;
+                        config.crossfade = Some(crossfade);
+                    }
+                    ("playback", "repeat") => {
+                        let repeat = RepeatMode::from_name(string()?)
+                            .ok_or_else(|| invalid("must be \"off\", \"one\" or \"all\""))?;
+                        config.repeat = Some(repeat);
+                    }
+                    ("playback", "shuffle") => {
+                        let shuffle = value
+                            .as_bool()
+                            .ok_or_else(|| invalid("must be a boolean"))?;
+                        config.shuffle = Some(shuffle);
+                    }
+                    ("playback", "replaygain") => {
+                        let mode = string()?
+                            .parse()
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.replaygain = Some(mode);
+                    }
+                    ("playback", "replaygain_preamp_db") => {
+                        config.replaygain_preamp_db = Some(number()?);
+                    }
+                    ("playback", "replaygain_default_db") => {
+                        config.replaygain_default_db = Some(number()?);
+                    }
+                    ("playback", "decoder") => {
+                        let backend = string()?
+                            .parse()
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.decoder = Some(backend);
+                    }
+                    ("effects", "chain") => config.chain = Some(PathBuf::from(string()?)),
+                    ("eq_profiles", "write_back") => {
+                        let enabled = value
+                            .as_bool()
+                            .ok_or_else(|| invalid("must be a boolean"))?;
+                        config.profile_write_back = Some(enabled);
+                    }
+                    ("eq_profiles", "default") => {
+                        let settings = EqSettings::from_value(value)
+                            .map_err(|err| invalid(&format!("is invalid: {err}")))?;
+                        config.default_profile = Some(settings);
+                    }
+                    ("eq_profiles", "devices") => {
+                        let Value::Table(devices) = value else {
+                            return Err(invalid("must be a table of profiles by device"));
+                        };
+                        for (device, settings) in devices {
+                            let settings = EqSettings::from_value(settings).map_err(|err| {
+                                invalid(&format!("has an invalid profile for '{device}': {err}"))
+                            })?;
+                            config.device_profiles.push((device.clone(), settings));
+                        }
+                    }
+                    _ => return Err(invalid("isn't a known setting")),
+                }
+            }
+        }
+        if config.eq_gains.is_some() && config.eq_preset.is_some() {
+            let line = toml::key_line(text, "eq", "preset").unwrap_or(1);
+            return Err(ParseError::new(
+                line,
+                "'eq.preset' and 'eq.gains' can't both be set",
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// Why a [`PlayerConfig`] couldn't be read or written.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    /// The file isn't valid TOML, or has a setting that doesn't make sense;
+    /// the message names the key.
+    Parse(ParseError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{err}"),
+            ConfigError::Parse(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            ConfigError::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<ParseError> for ConfigError {
+    fn from(err: ParseError) -> Self {
+        ConfigError::Parse(err)
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{
+    error::PlayerError,
+    format::{json, Value},
+    now_playing::NowPlaying,
+    player::AudioPlayer,
+};
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use {
+    crate::lock::Lock,
+    std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex, Weak,
+        },
+    },
+};
+
+/// How long a command waits for a seek in flight to land before it is
+/// turned away.
+const SEEK_WAIT: Duration = Duration::from_secs(5);
+
+const SEEK_POLL: Duration = Duration::from_millis(5);
+
+/// One request of the control protocol. On the wire each is a JSON object
+/// on a line of its own, such as `{"command": "seek", "position": 83.0}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    Play,
+    Pause,
+    /// Pauses while playing, otherwise plays.
+    Toggle,
+    Stop,
+    Next,
+    Previous,
+    Seek(Duration),
+    VolumeDb(f32),
+    /// Sets the gain in dB of one band, by index into
+    /// [`AudioPlayer::eq_gains`].
+    SetEq {
+        band: usize,
+        gain_db: f32,
+    },
+    EqGains(Vec<f32>),
+    Enqueue(PathBuf),
+    /// Removes the queue entry with this [`QueueItem::id`](crate::QueueItem::id).
+    Remove(u64),
+    /// Jumps to the queue entry with this id.
+    PlayItem(u64),
+    /// Replies with the queue as well as the status.
+    Queue,
+    Status,
+}
+
+impl ControlCommand {
+    /// The request as a line of the protocol, without the newline.
+    pub fn to_json(&self) -> String {
+        let command = |name: &str| Value::table().with("command", name);
+        let value = match self {
+            ControlCommand::Play => command("play"),
+            ControlCommand::Pause => command("pause"),
+            ControlCommand::Toggle => command("toggle"),
+            ControlCommand::Stop => command("stop"),
+            ControlCommand::Next => command("next"),
+            ControlCommand::Previous => command("previous"),
+            ControlCommand::Seek(position) => {
+                command("seek").with("position", position.as_secs_f64())
+            }
+            ControlCommand::VolumeDb(db) => command("volume").with("db", *db),
+            ControlCommand::SetEq { band, gain_db } => command("set_eq")
+                .with("band", *band)
+                .with("gain_db", *gain_db),
+            ControlCommand::EqGains(gains) => command("eq_gains").with(
+                "gains",
+                gains
+                    .iter()
+                    .map(|&gain| Value::from(gain))
+                    .collect::<Vec<_>>(),
+            ),
+            ControlCommand::Enqueue(path) => {
+                command("enqueue").with("path", path.to_string_lossy().into_owned())
+            }
+            ControlCommand::Remove(id) => command("remove").with("id", *id),
+            ControlCommand::PlayItem(id) => command("play_item").with("id", *id),
+            ControlCommand::Queue => command("queue"),
+            ControlCommand::Status => command("status"),
+        };
+        json::to_line(&value)
+    }
+
+    fn from_json(line: &str) -> Result<Self, String> {
+        let value = json::parse(line).map_err(|err| err.to_string())?;
+        let name = value
+            .get("command")
+            .and_then(Value::as_str)
+            .ok_or("missing 'command'")?;
+        let number = |key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_f64)
+                .ok_or_else(|| format!("'{name}' needs a number '{key}'"))
+        };
+        let index = |key: &str| {
+            number(key).and_then(|n| match n >= 0.0 && n.fract() == 0.0 {
+                true => Ok(n as u64),
+                false => Err(format!("'{key}' must be a whole number, at least 0")),
+            })
+        };
+        Ok(match name {
+            "play" => ControlCommand::Play,
+            "pause" => ControlCommand::Pause,
+            "toggle" => ControlCommand::Toggle,
+            "stop" => ControlCommand::Stop,
+            "next" => ControlCommand::Next,
+            "previous" => ControlCommand::Previous,
+            "seek" => ControlCommand::Seek(
+                Duration::try_from_secs_f64(number("position")?)
+                    .map_err(|_| "'position' must be a number of seconds, at least 0")?,
+            ),
+            "volume" => ControlCommand::VolumeDb(number("db")? as f32),
+            "set_eq" => ControlCommand::SetEq {
+                band: index("band")? as usize,
+                gain_db: number("gain_db")? as f32,
+            },
+            "eq_gains" => ControlCommand::EqGains(
+                value
+                    .get("gains")
+                    .and_then(Value::as_array)
+                    .and_then(|gains| gains.iter().map(Value::as_f32).collect())
+                    .ok_or("'eq_gains' needs a list of numbers 'gains'")?,
+            ),
+            "enqueue" => ControlCommand::Enqueue(PathBuf::from(
+                value
+                    .get("path")
+                    .and_then(Value::as_str)
+                    .ok_or("'enqueue' needs a string 'path'")?,
+            )),
+            "remove" => ControlCommand::Remove(index("id")?),
+            "play_item" => ControlCommand::PlayItem(index("id")?),
+            "queue" => ControlCommand::Queue,
+            "status" => ControlCommand::Status,
+            _ => return Err(format!("unknown command '{name}'")),
+        })
+    }
+}
+
+/// Where [`ControlServer::start`] listens unless told otherwise:
+/// `fullyrustaudio.sock` in `$XDG_RUNTIME_DIR`, or in the temporary
+/// directory with the user's name in it.
+pub fn default_socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir.join("fullyrustaudio.sock"),
+        _ => {
+            let user = env::var("USER").unwrap_or_default();
+            env::temp_dir().join(format!("fullyrustaudio-{user}.sock"))
+        }
+    }
+}
+
+/// Sends `command` to the server listening at `socket` and returns its
+/// reply, a JSON object on one line with `"ok"` and, on success, the
+/// player's status.
+pub fn send_command(socket: impl AsRef<Path>, command: &ControlCommand) -> io::Result<String> {
+    #[cfg(unix)]
+    {
+        let mut stream = UnixStream::connect(socket)?;
+        writeln!(stream, "{}", command.to_json())?;
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+        Ok(reply.trim_end().to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (socket, command);
+        Err(unsupported())
+    }
+}
+
+/// Lets other processes drive a player over a Unix domain socket, with the
+/// line-delimited JSON protocol of [`ControlCommand`].
+///
+/// Commands run one at a time, in the order they arrive from every client
+/// together. One that arrives while a seek is in flight waits for it to
+/// land, and is turned away with an error if that takes longer than five
+/// seconds; a seek then never acts on a position that is about to change.
+/// Dropping the server stops it and removes the socket.
+pub struct ControlServer {
+    path: PathBuf,
+    #[cfg(unix)]
+    closed: Arc<AtomicBool>,
+}
+
+impl ControlServer {
+    /// Listens at `path`, replacing a socket left behind by a server that
+    /// is no longer running. Fails if one still is. Not available on
+    /// Windows yet.
+    pub fn start(player: &Arc<AudioPlayer>, path: impl AsRef<Path>) -> io::Result<ControlServer> {
+        let path = path.as_ref().to_path_buf();
+        #[cfg(unix)]
+        {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("a player is already listening at {}", path.display()),
+                ));
+            }
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+            let listener = UnixListener::bind(&path)?;
+            let closed = Arc::new(AtomicBool::new(false));
+            let player = Arc::downgrade(player);
+            let turn = Arc::new(Mutex::new(()));
+            let stop = closed.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Acquire) {
+                        return;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let (player, turn) = (player.clone(), turn.clone());
+                    thread::spawn(move || serve(stream, &player, &turn));
+                }
+            });
+            Ok(ControlServer { path, closed })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (player, path);
+            Err(unsupported())
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            self.closed.store(true, Ordering::Release);
+            // Wakes the accept loop so it sees the flag.
+            let _ = UnixStream::connect(&self.path);
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the control socket needs Unix domain sockets",
+    )
+}
+
+/// Answers each line from `stream` until the client hangs up.
+#[cfg(unix)]
+fn serve(stream: UnixStream, player: &Weak<AudioPlayer>, turn: &Mutex<()>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match player.upgrade() {
+            Some(player) => {
+                let _turn = turn.locked();
+                reply(&player, &line)
+            }
+            None => failure("the player has closed"),
+        };
+        if writeln!(writer, "{}", json::to_line(&reply)).is_err() {
+            return;
+        }
+    }
+}
+
+fn reply(player: &AudioPlayer, line: &str) -> Value {
+    let command = match ControlCommand::from_json(line) {
+        Ok(command) => command,
+        Err(err) => return failure(&err),
+    };
+    let started = Instant::now();
+    while player.is_seeking() {
+        if started.elapsed() >= SEEK_WAIT {
+            return failure("a seek is still in progress");
+        }
+        thread::sleep(SEEK_POLL);
+    }
+    let mut reply = match run(player, &command) {
+        Ok(()) => status(player),
+        Err(err) => return failure(&err),
+    };
+    if command == ControlCommand::Queue {
+        let queue = player
+            .queue()
+            .into_iter()
+            .map(|item| {
+                Value::table()
+                    .with("id", item.id)
+                    .with("path", item.path.to_string_lossy().into_owned())
+                    .with("duration", item.duration.map(|d| d.as_secs_f64()))
+                    .with("title", item.title)
+                    .with("artist", item.artist)
+            })
+            .collect::<Vec<_>>();
+        reply = reply.with("queue", queue);
+    }
+    reply
+}
+
+fn run(player: &AudioPlayer, command: &ControlCommand) -> Result<(), String> {
+    let found = |id: u64, found: bool| match found {
+        true => Ok(()),
+        false => Err(PlayerError::InvalidArgument(format!(
+            "no queue entry with id {id}"
+        ))),
+    };
+    let result = match command {
+        ControlCommand::Play => player.play(),
+        ControlCommand::Toggle if !player.is_playing() => player.play(),
+        ControlCommand::Pause | ControlCommand::Toggle => {
+            player.pause();
+            Ok(())
+        }
+        ControlCommand::Stop => {
+            player.stop();
+            Ok(())
+        }
+        ControlCommand::Next => player.next().map(drop),
+        ControlCommand::Previous => player.previous().map(drop),
+        ControlCommand::Seek(position) => player.seek(*position),
+        ControlCommand::VolumeDb(db) => {
+            player.set_volume_db(*db);
+            Ok(())
+        }
+        ControlCommand::SetEq { band, gain_db } => {
+            let mut gains = player.eq_gains();
+            let Some(gain) = gains.get_mut(*band) else {
+                return Err(format!("no EQ band {band}; there are {}", gains.len()));
+            };
+            *gain = *gain_db;
+            player.set_eq_gains(&gains)
+        }
+        ControlCommand::EqGains(gains) => player.set_eq_gains(gains),
+        ControlCommand::Enqueue(path) => player.enqueue(path),
+        ControlCommand::Remove(id) => player.remove(*id).and_then(|done| found(*id, done)),
+        ControlCommand::PlayItem(id) => player.play_item(*id).and_then(|done| found(*id, done)),
+        ControlCommand::Queue | ControlCommand::Status => Ok(()),
+    };
+    result.map_err(|err| err.to_string())
+}
+
+fn status(player: &AudioPlayer) -> Value {
+    let now = player.now_playing();
+    let eq = Value::table()
+        .with("enabled", player.eq_enabled())
+        .with("preamp_db", player.eq_settings().preamp_db)
+        .with(
+            "gains",
+            player
+                .eq_gains()
+                .into_iter()
+                .map(Value::from)
+                .collect::<Vec<_>>(),
+        );
+    Value::table()
+        .with("ok", true)
+        .with("playing", player.is_playing())
+        .with(
+            "position",
+            now.as_ref().map_or(0.0, |now| now.position.as_secs_f64()),
+        )
+        .with(
+            "duration",
+            now.as_ref()
+                .and_then(|now| now.duration)
+                .map(|d| d.as_secs_f64()),
+        )
+        .with(
+            "track",
+            now.as_ref()
+                .map(|now| now.item.path.to_string_lossy().into_owned()),
+        )
+        .with("title", now.as_ref().and_then(NowPlaying::title))
+        .with(
+            "artist",
+            now.as_ref()
+                .and_then(|now| now.artist().map(str::to_string)),
+        )
+        .with("volume_db", player.volume_db())
+        .with("muted", player.is_muted())
+        .with("eq", eq)
+}
+
+fn failure(message: &str) -> Value {
+    Value::table().with("ok", false).with("error", message)
+}
@@ -0,0 +1,272 @@
+use crate::format::{read_text, ParseError};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// CD frames per second, the unit of the last field of an `INDEX` time.
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// Tried in turn when the file a sheet names is missing, since rips are
+/// often re-encoded without updating the sheet.
+const FALLBACK_EXTENSIONS: [&str; 6] = ["flac", "wav", "mp3", "ogg", "opus", "ape"];
+
+/// One track of a [`CueSheet`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueTrack {
+    /// The number after `TRACK`.
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Where `INDEX 01` puts the track in the file.
+    pub start: Duration,
+}
+
+/// A cue sheet splitting one audio file into tracks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueSheet {
+    /// The audio file, resolved against the sheet's directory by
+    /// [`CueSheet::load`].
+    pub file: PathBuf,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// In file order, each starting after the one before.
+    pub tracks: Vec<CueTrack>,
+    /// Lines that were skipped, and why.
+    pub warnings: Vec<ParseError>,
+}
+
+impl CueSheet {
+    /// Reads and parses the sheet at `path`. Fails if it can't be read or
+    /// has no `FILE` with at least one playable track; malformed lines are
+    /// skipped and listed in [`CueSheet::warnings`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut sheet = CueSheet::parse(&read_text(path)?);
+        if sheet.file.as_os_str().is_empty() {
+            return Err(invalid("the cue sheet names no FILE"));
+        }
+        if sheet.tracks.is_empty() {
+            return Err(invalid("the cue sheet has no playable tracks"));
+        }
+        let dir = path.parent().unwrap_or(Path::new(""));
+        sheet.file = resolve(dir, &sheet.file);
+        Ok(sheet)
+    }
+
+    /// Parses sheet text, leaving [`CueSheet::file`] as written.
+    pub fn parse(text: &str) -> Self {
+        let mut sheet = CueSheet {
+            file: PathBuf::new(),
+            title: None,
+            performer: None,
+            tracks: Vec::new(),
+            warnings: Vec::new(),
+        };
+        // Only the first FILE is played; tracks of any later one are skipped.
+        let mut later_file = false;
+        // The track being read, or `None` between tracks and in a skipped one.
+        let mut track: Option<Pending> = None;
+        let mut in_track = false;
+        let mut warnings = Vec::new();
+
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let warn = |message: String| ParseError::new(line_number, message);
+            let line = line.trim();
+            let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            match command.to_ascii_uppercase().as_str() {
+                "" | "REM" | "FLAGS" | "ISRC" | "CATALOG" | "SONGWRITER" | "PREGAP" | "POSTGAP"
+                | "CDTEXTFILE" => {}
+                "FILE" => {
+                    let Some((name, _)) = quoted(rest) else {
+                        warnings.push(warn("FILE without a file name".into()));
+                        continue;
+                    };
+                    if sheet.file.as_os_str().is_empty() {
+                        sheet.file = PathBuf::from(name);
+                    } else {
+                        warnings.push(warn(format!(
+                            "only the first FILE is played; skipping {name}"
+                        )));
+                        later_file = true;
+                    }
+                }
+                "TRACK" => {
+                    finish_track(&mut sheet.tracks, track.take(), &mut warnings);
+                    in_track = true;
+                    if sheet.file.as_os_str().is_empty() {
+                        warnings.push(warn("TRACK before any FILE".into()));
+                        continue;
+                    }
+                    if later_file {
+                        continue;
+                    }
+                    let mut fields = rest.split_whitespace();
+                    let Some(number) = fields.next().and_then(|number| number.parse().ok()) else {
+                        warnings.push(warn(format!("invalid TRACK '{rest}'")));
+                        continue;
+                    };
+                    match fields.next() {
+                        Some(kind) if kind.eq_ignore_ascii_case("AUDIO") => {}
+                        _ => {
+                            warnings
+                                .push(warn(format!("skipping track {number}, which is not audio")));
+                            continue;
+                        }
+                    }
+                    track = Some(Pending {
+                        track: CueTrack {
+                            number,
+                            title: None,
+                            performer: None,
+                            start: Duration::ZERO,
+                        },
+                        start: None,
+                        line: line_number,
+                    });
+                }
+                "INDEX" => {
+                    let mut fields = rest.split_whitespace();
+                    let number = fields.next().and_then(|number| number.parse::<u32>().ok());
+                    let time = fields.next().and_then(parse_time);
+                    let (Some(number), Some(time)) = (number, time) else {
+                        warnings.push(warn(format!("invalid INDEX '{rest}'")));
+                        continue;
+                    };
+                    // INDEX 00 marks the pregap, which belongs to the track before.
+                    if let (1, Some(pending)) = (number, &mut track) {
+                        pending.start = Some(time);
+                    }
+                }
+                "TITLE" | "PERFORMER" => {
+                    let Some((value, _)) = quoted(rest) else {
+                        warnings.push(warn(format!("{command} without a value")));
+                        continue;
+                    };
+                    let value = Some(value.to_string());
+                    let is_title = command.eq_ignore_ascii_case("TITLE");
+                    match (&mut track, in_track) {
+                        (Some(pending), _) if is_title => pending.track.title = value,
+                        (Some(pending), _) => pending.track.performer = value,
+                        // Belongs to a skipped track.
+                        (None, true) => {}
+                        (None, false) if is_title => sheet.title = value,
+                        (None, false) => sheet.performer = value,
+                    }
+                }
+                _ => warnings.push(warn(format!("unknown command '{command}'"))),
+            }
+        }
+        finish_track(&mut sheet.tracks, track, &mut warnings);
+        sheet.warnings = warnings;
+        sheet
+    }
+
+    /// Index into [`CueSheet::tracks`] of the track playing at `position` in
+    /// the file. Anything before the first track counts as the first.
+    pub fn track_at(&self, position: Duration) -> usize {
+        self.tracks
+            .iter()
+            .rposition(|track| track.start <= position)
+            .unwrap_or(0)
+    }
+
+    /// Where track `index` starts in the file, and where the next one takes
+    /// over; `None` for the last track, which runs to the end of the file.
+    pub fn bounds(&self, index: usize) -> (Duration, Option<Duration>) {
+        let start = self
+            .tracks
+            .get(index)
+            .map_or(Duration::ZERO, |track| track.start);
+        let end = self.tracks.get(index + 1).map(|track| track.start);
+        (start, end)
+    }
+}
+
+/// A track whose `INDEX 01` may not have come up yet.
+struct Pending {
+    track: CueTrack,
+    start: Option<Duration>,
+    /// Of its `TRACK` command, for warnings.
+    line: usize,
+}
+
+/// Adds a finished track, unless it never got an `INDEX 01` or starts
+/// before the one it follows.
+fn finish_track(
+    tracks: &mut Vec<CueTrack>,
+    pending: Option<Pending>,
+    warnings: &mut Vec<ParseError>,
+) {
+    let Some(Pending {
+        mut track,
+        start,
+        line,
+    }) = pending
+    else {
+        return;
+    };
+    let Some(start) = start else {
+        let message = format!("skipping track {}, which has no INDEX 01", track.number);
+        warnings.push(ParseError::new(line, message));
+        return;
+    };
+    if let Some(last) = tracks.last().filter(|last| last.start >= start) {
+        let message = format!(
+            "skipping track {}, which starts before track {} ends",
+            track.number, last.number
+        );
+        warnings.push(ParseError::new(line, message));
+        return;
+    }
+    track.start = start;
+    tracks.push(track);
+}
+
+/// A `"quoted value"`, or the first word if unquoted, and what follows it.
+fn quoted(text: &str) -> Option<(&str, &str)> {
+    let (value, rest) = match text.strip_prefix('"') {
+        Some(text) => text.split_once('"')?,
+        None => text.split_once(char::is_whitespace).unwrap_or((text, "")),
+    };
+    (!value.is_empty()).then_some((value, rest.trim_start()))
+}
+
+/// `mm:ss:ff`, with frames in 1/75 s.
+fn parse_time(text: &str) -> Option<Duration> {
+    let mut fields = text.split(':').map(|field| field.parse::<u64>().ok());
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return None;
+    };
+    if seconds >= 60 || frames >= FRAMES_PER_SECOND {
+        return None;
+    }
+    let frames = (minutes * 60 + seconds) * FRAMES_PER_SECOND + frames;
+    Some(Duration::from_nanos(
+        frames * 1_000_000_000 / FRAMES_PER_SECOND,
+    ))
+}
+
+/// `file` relative to `dir`, or a file next to it with the same stem and
+/// another audio extension if `file` itself is missing.
+fn resolve(dir: &Path, file: &Path) -> PathBuf {
+    let path = dir.join(file);
+    if path.is_file() {
+        return path;
+    }
+    FALLBACK_EXTENSIONS
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|candidate| candidate.is_file())
+        .unwrap_or(path)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
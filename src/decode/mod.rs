@@ -0,0 +1,162 @@
+//! Opening files for decoding, through rodio's decoders or, built with the
+//! `symphonia` feature, through Symphonia for the containers it recognizes.
+
+#[cfg(feature = "symphonia")]
+mod symphonia;
+
+use crate::{error::PlayerError, metadata::TrackMetadata};
+use rodio::{decoder::DecoderError, Decoder, Source};
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+pub(crate) type FileDecoder = Box<dyn Source<Item = f32> + Send>;
+
+/// Which decoder a file is opened with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DecoderBackend {
+    /// Symphonia for any container it recognizes, when built in, and rodio's
+    /// decoders for everything else.
+    #[default]
+    Auto,
+    /// rodio's decoders: WAV, FLAC, Ogg Vorbis and MP3.
+    Rodio,
+    /// Symphonia, for whichever formats its features turn on.
+    #[cfg(feature = "symphonia")]
+    Symphonia,
+}
+
+impl DecoderBackend {
+    #[cfg(feature = "symphonia")]
+    pub const ALL: [DecoderBackend; 3] = [
+        DecoderBackend::Auto,
+        DecoderBackend::Rodio,
+        DecoderBackend::Symphonia,
+    ];
+    #[cfg(not(feature = "symphonia"))]
+    pub const ALL: [DecoderBackend; 2] = [DecoderBackend::Auto, DecoderBackend::Rodio];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DecoderBackend::Auto => "auto",
+            DecoderBackend::Rodio => "rodio",
+            #[cfg(feature = "symphonia")]
+            DecoderBackend::Symphonia => "symphonia",
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            1 => DecoderBackend::Rodio,
+            #[cfg(feature = "symphonia")]
+            2 => DecoderBackend::Symphonia,
+            _ => DecoderBackend::Auto,
+        }
+    }
+
+    /// The backend the file at `path` is opened with: for
+    /// [`DecoderBackend::Auto`], Symphonia if it recognizes the container.
+    pub(crate) fn resolve(self, path: &Path) -> DecoderBackend {
+        match self {
+            #[cfg(feature = "symphonia")]
+            DecoderBackend::Auto if symphonia::recognizes(path) => DecoderBackend::Symphonia,
+            DecoderBackend::Auto => {
+                let _ = path;
+                DecoderBackend::Rodio
+            }
+            backend => backend,
+        }
+    }
+}
+
+impl fmt::Display for DecoderBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DecoderBackend {
+    type Err = String;
+
+    /// Looks a backend up by name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if cfg!(not(feature = "symphonia")) && s.eq_ignore_ascii_case("symphonia") {
+            return Err(
+                "the Symphonia decoder isn't built in; it needs the `symphonia` feature".into(),
+            );
+        }
+        DecoderBackend::ALL
+            .into_iter()
+            .find(|backend| backend.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = DecoderBackend::ALL.map(DecoderBackend::name).join(", ");
+                format!("unknown decoder '{s}', expected one of {names}")
+            })
+    }
+}
+
+/// Opens the file at `path` with `backend`.
+pub(crate) fn open_file(path: &Path, backend: DecoderBackend) -> Result<FileDecoder, PlayerError> {
+    let file = File::open(path)?;
+    Ok(open(file, path, backend.resolve(path))?)
+}
+
+/// Opens `reader`, the file at `path` or a stand-in for it, with `backend`,
+/// already resolved for `path`.
+pub(crate) fn open<R>(
+    reader: R,
+    path: &Path,
+    backend: DecoderBackend,
+) -> Result<FileDecoder, DecoderError>
+where
+    R: Read + Seek + Send + Sync + 'static,
+{
+    match backend {
+        #[cfg(feature = "symphonia")]
+        DecoderBackend::Symphonia => Ok(Box::new(symphonia::SymphoniaDecoder::open(
+            reader,
+            path.extension().and_then(|extension| extension.to_str()),
+        )?)),
+        _ => {
+            let _ = path;
+            let decoder = Decoder::new(BufReader::new(reader))?;
+            Ok(Box::new(decoder.convert_samples::<f32>()))
+        }
+    }
+}
+
+/// What Symphonia makes of the first audio track in a file from its headers.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(not(feature = "symphonia"), allow(dead_code))]
+pub(crate) struct TrackInfo {
+    pub(crate) codec: Option<&'static str>,
+    pub(crate) bits_per_sample: Option<u16>,
+    pub(crate) duration: Option<Duration>,
+}
+
+/// Symphonia's [`TrackInfo`] for the file at `path`, if built in and it
+/// recognizes the container.
+pub(crate) fn track_info(path: &Path) -> Option<TrackInfo> {
+    #[cfg(feature = "symphonia")]
+    return symphonia::track_info(path);
+    #[cfg(not(feature = "symphonia"))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Fills in whatever of the title, artist, album and track number
+/// `metadata` lacks from the tags Symphonia reads in the file at `path`, if
+/// built in.
+pub(crate) fn fill_tags(path: &Path, metadata: &mut TrackMetadata) {
+    #[cfg(feature = "symphonia")]
+    symphonia::fill_tags(path, metadata);
+    #[cfg(not(feature = "symphonia"))]
+    let _ = (path, metadata);
+}
@@ -0,0 +1,359 @@
+use super::TrackInfo;
+use crate::metadata::TrackMetadata;
+use rodio::{decoder::DecoderError, source::SeekError, Source};
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+use symphonia::{
+    core::{
+        audio::{SampleBuffer, SignalSpec},
+        codecs::{
+            self, CodecParameters, CodecType, DecoderOptions, CODEC_TYPE_AAC, CODEC_TYPE_ALAC,
+            CODEC_TYPE_FLAC, CODEC_TYPE_MP3, CODEC_TYPE_NULL, CODEC_TYPE_OPUS, CODEC_TYPE_VORBIS,
+        },
+        errors::Error,
+        formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track},
+        io::{MediaSource, MediaSourceStream},
+        meta::{MetadataOptions, MetadataRevision, StandardTagKey, Value},
+        probe::{Hint, ProbeResult},
+        units::{Time, TimeBase},
+    },
+    default::{get_codecs, get_probe},
+};
+
+/// How far ahead of where a seek lands decoding starts, so a decoder that
+/// builds on earlier frames, as MP3's does, has them by then.
+const SEEK_PRIMING: Duration = Duration::from_millis(100);
+
+/// Packets in a row that may fail to decode, each skipped, before the
+/// stream counts as ended there.
+const MAX_DECODE_RETRIES: u32 = 3;
+
+/// Decodes the first audio track Symphonia finds in a container.
+pub(crate) struct SymphoniaDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn codecs::Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    spec: SignalSpec,
+    buffer: Option<SampleBuffer<f32>>,
+    /// How much of `buffer` has been played.
+    offset: usize,
+    duration: Option<Duration>,
+}
+
+impl SymphoniaDecoder {
+    /// Opens `reader`, with the file's `extension` as a hint to the format.
+    pub(crate) fn open<R>(reader: R, extension: Option<&str>) -> Result<Self, DecoderError>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        let probed = probe(Media::new(reader), extension).map_err(decoder_error)?;
+        let format = probed.format;
+        let track = audio_track(format.as_ref()).ok_or(DecoderError::NoStreams)?;
+        let params = track.codec_params.clone();
+        let decoder = get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .map_err(decoder_error)?;
+        let mut decoder = SymphoniaDecoder {
+            track_id: track.id,
+            time_base: params.time_base,
+            spec: SignalSpec::new(
+                params.sample_rate.unwrap_or(0),
+                params.channels.unwrap_or_default(),
+            ),
+            duration: duration(&params),
+            format,
+            decoder,
+            buffer: None,
+            offset: 0,
+        };
+        // The first packet settles the format where the headers leave it out.
+        if !decoder.decode_next() {
+            return Err(DecoderError::NoStreams);
+        }
+        Ok(decoder)
+    }
+
+    /// Whatever of the buffer is left.
+    fn remaining(&self) -> usize {
+        self.buffer
+            .as_ref()
+            .map_or(0, |buffer| buffer.len().saturating_sub(self.offset))
+    }
+
+    /// Decodes packets until one gives samples, returning `false` at the
+    /// end of the stream.
+    fn decode_next(&mut self) -> bool {
+        let mut failures = 0;
+        loop {
+            let Ok(packet) = self.format.next_packet() else {
+                return false;
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            match self.decoder.decode(&packet) {
+                Ok(decoded) if decoded.frames() == 0 => {}
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    let samples = decoded.capacity() * spec.channels.count();
+                    let buffer = match self.buffer.take() {
+                        Some(buffer) if spec == self.spec && buffer.capacity() >= samples => buffer,
+                        _ => SampleBuffer::new(decoded.capacity() as u64, spec),
+                    };
+                    self.buffer.insert(buffer).copy_interleaved_ref(decoded);
+                    self.spec = spec;
+                    self.offset = 0;
+                    return true;
+                }
+                Err(Error::DecodeError(_)) if failures < MAX_DECODE_RETRIES => failures += 1,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+impl Iterator for SymphoniaDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.remaining() == 0 && !self.decode_next() {
+            return None;
+        }
+        let sample = self.buffer.as_ref()?.samples()[self.offset];
+        self.offset += 1;
+        Some(sample)
+    }
+}
+
+impl Source for SymphoniaDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.remaining())
+    }
+
+    fn channels(&self) -> u16 {
+        self.spec.channels.count() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.spec.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// Seeks the container to a little before `pos`, then decodes up to the
+    /// exact frame.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let pos = match self.duration {
+            // Some formats can't seek to the very end.
+            Some(duration) if pos >= duration => duration.saturating_sub(Duration::from_millis(1)),
+            _ => pos,
+        };
+        let start = pos.saturating_sub(SEEK_PRIMING);
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(start),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|err| SeekError::Other(Box::new(err)))?;
+        self.decoder.reset();
+        let short = match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(seeked.required_ts.saturating_sub(seeked.actual_ts));
+                Duration::from_secs_f64(time.seconds as f64 + time.frac)
+            }
+            None => Duration::ZERO,
+        };
+        let rate = f64::from(self.spec.rate);
+        let mut skip = ((short + pos - start).as_secs_f64() * rate).round() as usize;
+        self.buffer = None;
+        self.offset = 0;
+        while self.decode_next() {
+            let channels = self.spec.channels.count().max(1);
+            let frames = self.remaining() / channels;
+            if skip < frames {
+                self.offset = skip * channels;
+                return Ok(());
+            }
+            skip -= frames;
+        }
+        Ok(())
+    }
+}
+
+/// Whether Symphonia recognizes the container in the file at `path`, with
+/// an audio track it has a decoder for.
+pub(crate) fn recognizes(path: &Path) -> bool {
+    open_probe(path).is_some_and(|probed| {
+        audio_track(probed.format.as_ref())
+            .is_some_and(|track| get_codecs().get_codec(track.codec_params.codec).is_some())
+    })
+}
+
+pub(crate) fn track_info(path: &Path) -> Option<TrackInfo> {
+    let probed = open_probe(path)?;
+    let params = &audio_track(probed.format.as_ref())?.codec_params;
+    Some(TrackInfo {
+        codec: codec_name(params.codec),
+        bits_per_sample: params.bits_per_sample.map(|bits| bits as u16),
+        duration: duration(params),
+    })
+}
+
+pub(crate) fn fill_tags(path: &Path, metadata: &mut TrackMetadata) {
+    let Some(mut probed) = open_probe(path) else {
+        return;
+    };
+    let mut fill = |revision: &MetadataRevision| {
+        for tag in revision.tags() {
+            let text = || Some(tag.value.to_string()).filter(|text| !text.trim().is_empty());
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) if metadata.title.is_none() => {
+                    metadata.title = text();
+                }
+                Some(StandardTagKey::Artist) if metadata.artist.is_none() => {
+                    metadata.artist = text();
+                }
+                Some(StandardTagKey::Album) if metadata.album.is_none() => {
+                    metadata.album = text();
+                }
+                Some(StandardTagKey::TrackNumber) if metadata.track_number.is_none() => {
+                    metadata.track_number = track_number(&tag.value);
+                }
+                _ => {}
+            }
+        }
+    };
+    // Tags may be in front of the container, or in it.
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        fill(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        fill(revision);
+    }
+}
+
+/// A track number, from "3" or "3/12" as well.
+fn track_number(value: &Value) -> Option<u32> {
+    match value {
+        Value::UnsignedInt(number) => u32::try_from(*number).ok(),
+        value => value.to_string().split('/').next()?.trim().parse().ok(),
+    }
+}
+
+fn open_probe(path: &Path) -> Option<ProbeResult> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    probe(Media::new(File::open(path).ok()?), extension).ok()
+}
+
+fn probe(media: io::Result<Media>, extension: Option<&str>) -> Result<ProbeResult, Error> {
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(media?), Default::default());
+    let format = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+    get_probe().format(&hint, stream, &format, &MetadataOptions::default())
+}
+
+fn audio_track(format: &dyn FormatReader) -> Option<&Track> {
+    format
+        .default_track()
+        .filter(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .or_else(|| {
+            format
+                .tracks()
+                .iter()
+                .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        })
+}
+
+fn duration(params: &CodecParameters) -> Option<Duration> {
+    let time = params.time_base?.calc_time(params.n_frames?);
+    Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
+}
+
+/// The names [`StreamInfo`](crate::StreamInfo) uses, or Symphonia's own
+/// for other codecs it has a decoder for.
+fn codec_name(codec: CodecType) -> Option<&'static str> {
+    let name = match codec {
+        CODEC_TYPE_FLAC => "FLAC",
+        CODEC_TYPE_MP3 => "MP3",
+        CODEC_TYPE_VORBIS => "Vorbis",
+        CODEC_TYPE_OPUS => "Opus",
+        CODEC_TYPE_AAC => "AAC",
+        CODEC_TYPE_ALAC => "ALAC",
+        codec => match get_codecs().get_codec(codec)?.short_name {
+            name if name.starts_with("pcm_f") => "IEEE float",
+            name if name.starts_with("pcm_") => "PCM",
+            name => name,
+        },
+    };
+    Some(name)
+}
+
+fn decoder_error(err: Error) -> DecoderError {
+    match err {
+        Error::IoError(err) => DecoderError::IoError(err.to_string()),
+        Error::DecodeError(err) => DecoderError::DecodeError(err),
+        Error::LimitError(err) => DecoderError::LimitError(err),
+        Error::ResetRequired => DecoderError::ResetRequired,
+        Error::Unsupported(_) | Error::SeekError(_) => DecoderError::UnrecognizedFormat,
+    }
+}
+
+/// A reader as Symphonia takes it, seekable and of known length.
+struct Media {
+    reader: Box<dyn ReadSeek>,
+    length: u64,
+}
+
+trait ReadSeek: Read + Seek + Send + Sync {}
+
+impl<R: Read + Seek + Send + Sync> ReadSeek for R {}
+
+impl Media {
+    fn new(mut reader: impl Read + Seek + Send + Sync + 'static) -> io::Result<Self> {
+        let length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        Ok(Media {
+            reader: Box::new(reader),
+            length,
+        })
+    }
+}
+
+impl Read for Media {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Seek for Media {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+impl MediaSource for Media {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.length)
+    }
+}
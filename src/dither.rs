@@ -0,0 +1,197 @@
+use crate::shuffle::Rng;
+use std::{fmt, str::FromStr};
+
+/// How far the shaped error may run, in steps, so a clipped sample can't
+/// keep feeding back into the ones after it.
+const MAX_SHAPED_ERROR: f32 = 2.0;
+
+/// What is added before a sample is rounded to fewer bits, so the rounding
+/// error is noise rather than distortion that follows the signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Dither {
+    /// Plain rounding.
+    #[default]
+    Off,
+    /// Triangular noise two steps wide, which leaves the error a steady hiss
+    /// whatever the signal.
+    Tpdf,
+    /// [`Dither::Tpdf`], with each sample's error taken off the next so the
+    /// hiss moves up towards the top of the band, where it is heard least:
+    /// quieter below about a sixth of the sample rate, louder above.
+    Shaped,
+}
+
+impl Dither {
+    pub const ALL: [Dither; 3] = [Dither::Off, Dither::Tpdf, Dither::Shaped];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dither::Off => "off",
+            Dither::Tpdf => "tpdf",
+            Dither::Shaped => "shaped",
+        }
+    }
+}
+
+impl fmt::Display for Dither {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    /// Looks a dither up by name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Dither::ALL
+            .into_iter()
+            .find(|dither| dither.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = Dither::ALL.map(Dither::name).join(", ");
+                format!("unknown dither '{s}', expected one of {names}")
+            })
+    }
+}
+
+/// Rounds interleaved samples to steps of `1 / scale`, dithered as asked,
+/// with noise of its own for each channel. The same seed always gives the
+/// same noise.
+pub(crate) struct Ditherer {
+    dither: Dither,
+    scale: f32,
+    channels: Vec<ChannelDither>,
+    channel: usize,
+}
+
+struct ChannelDither {
+    rng: Rng,
+    /// What rounding the last sample added, in steps, for [`Dither::Shaped`].
+    error: f32,
+}
+
+impl Ditherer {
+    pub(crate) fn new(dither: Dither, scale: f32, channels: u16, seed: u64) -> Self {
+        let mut seeds = Rng::with_seed(seed);
+        Ditherer {
+            dither,
+            scale,
+            channels: (0..channels.max(1))
+                .map(|_| ChannelDither {
+                    rng: Rng::with_seed(seeds.next_u64()),
+                    error: 0.0,
+                })
+                .collect(),
+            channel: 0,
+        }
+    }
+
+    /// `sample` as a whole number of steps, from `-scale` to `scale`.
+    pub(crate) fn quantize(&mut self, sample: f32) -> i32 {
+        let channel = self.channel;
+        self.channel = (channel + 1) % self.channels.len();
+        let state = &mut self.channels[channel];
+        let scaled = sample * self.scale;
+        let range = |value: f32| value.clamp(-self.scale, self.scale);
+        let quantized = match self.dither {
+            Dither::Off => range(scaled.round()),
+            Dither::Tpdf => range((scaled + tpdf(&mut state.rng)).round()),
+            Dither::Shaped => {
+                let wanted = scaled - state.error;
+                let quantized = range((wanted + tpdf(&mut state.rng)).round());
+                state.error = (quantized - wanted).clamp(-MAX_SHAPED_ERROR, MAX_SHAPED_ERROR);
+                quantized
+            }
+        };
+        quantized as i32
+    }
+
+    /// The highest sample on the grid that a signed format holds, a step
+    /// below full scale.
+    pub(crate) fn ceiling(&self) -> f32 {
+        (self.scale - 1.0) / self.scale
+    }
+
+    /// Like [`Ditherer::quantize`], back as a sample on the grid.
+    pub(crate) fn dither(&mut self, sample: f32) -> f32 {
+        self.quantize(sample) as f32 / self.scale
+    }
+}
+
+/// From -1.0 to 1.0, likeliest at 0.0: the sum of two uniform draws, taken
+/// from either half of one number.
+fn tpdf(rng: &mut Rng) -> f32 {
+    const HALF: u64 = (1 << 24) - 1;
+    let bits = rng.next_u64();
+    let first = (bits >> 40) as f32 / HALF as f32;
+    let second = (bits & HALF) as f32 / HALF as f32;
+    first + second - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::{GeneratorSettings, SineWave};
+    use std::time::Duration;
+
+    /// 16-bit steps.
+    const SCALE: f32 = 32768.0;
+    /// Samples per cycle of a 1 kHz sine at 48 kHz.
+    const PERIOD: usize = 48;
+
+    /// How much of the rounding error of each dither on a quiet sine
+    /// repeats with the sine, found by averaging the error over its
+    /// cycles: all of it where the error follows the signal, and next to
+    /// none where it is noise.
+    fn repeating_share(dither: Dither) -> f32 {
+        let sine = SineWave::new(
+            1000.0,
+            GeneratorSettings {
+                sample_rate: 48_000,
+                channels: 1,
+                // Two and a half steps: a signal rounding mangles.
+                amplitude: 2.5 / SCALE,
+                duration: Some(Duration::from_secs(1)),
+            },
+        );
+        let mut ditherer = Ditherer::new(dither, SCALE, 1, 5);
+        let errors = sine
+            .map(|sample| ditherer.quantize(sample) as f32 - sample * SCALE)
+            .collect::<Vec<_>>();
+        let cycles = errors.len() / PERIOD;
+        let mut average = [0.0; PERIOD];
+        for (index, error) in errors.iter().enumerate() {
+            average[index % PERIOD] += error / cycles as f32;
+        }
+        let power = |errors: &[f32]| {
+            errors.iter().map(|error| error * error).sum::<f32>() / errors.len() as f32
+        };
+        power(&average) / power(&errors)
+    }
+
+    #[test]
+    fn dithered_error_doesnt_follow_the_signal_as_rounding_does() {
+        let rounded = repeating_share(Dither::Off);
+        assert!(rounded > 0.99, "{rounded} of the rounding error repeats");
+        for dither in [Dither::Tpdf, Dither::Shaped] {
+            let share = repeating_share(dither);
+            assert!(share < 0.01, "{share} of the {dither} error repeats");
+        }
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_noise_on_each_channel_its_own() {
+        let quantized = |seed| {
+            let mut ditherer = Ditherer::new(Dither::Tpdf, SCALE, 2, seed);
+            (0..2048)
+                .map(|_| ditherer.quantize(0.0))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(quantized(1), quantized(1));
+        assert_ne!(quantized(1), quantized(2));
+        let noise = quantized(1);
+        let (left, right): (Vec<_>, Vec<_>) =
+            noise.chunks(2).map(|frame| (frame[0], frame[1])).unzip();
+        assert_ne!(left, right);
+    }
+}
@@ -0,0 +1,456 @@
+//! The filters behind the EQ, tone and vocal stages, for running over
+//! buffers decoded elsewhere: no [`Source`](rodio::Source), player or output
+//! device is involved.
+//!
+//! A [`BiquadFilter`] works on one channel; a [`FilterChain`] runs a
+//! cascade per channel over interleaved blocks.
+
+use std::{f32::consts::PI, str::FromStr};
+
+/// What a [`BiquadFilter`] multiplies by, divided through by `a0` so that
+/// `y[n] = b0·x[n] + b1·x[n-1] + b2·x[n-2] - a1·y[n-1] - a2·y[n-2]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiquadCoefficients {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl BiquadCoefficients {
+    /// Passes everything through unchanged.
+    pub const FLAT: BiquadCoefficients = BiquadCoefficients {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+}
+
+/// A second-order IIR filter in direct form I, with the history of one
+/// channel.
+#[derive(Debug, Clone)]
+pub struct BiquadFilter {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+/// Response shape of a [`BiquadFilter`], following the RBJ audio EQ cookbook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FilterType {
+    #[default]
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+    Notch,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 6] = [
+        FilterType::Peaking,
+        FilterType::LowShelf,
+        FilterType::HighShelf,
+        FilterType::LowPass,
+        FilterType::HighPass,
+        FilterType::Notch,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterType::Peaking => "peaking",
+            FilterType::LowShelf => "low_shelf",
+            FilterType::HighShelf => "high_shelf",
+            FilterType::LowPass => "low_pass",
+            FilterType::HighPass => "high_pass",
+            FilterType::Notch => "notch",
+        }
+    }
+}
+
+impl FromStr for FilterType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FilterType::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown filter type '{s}'"))
+    }
+}
+
+impl BiquadFilter {
+    /// A peaking filter, as used by the graphic EQ bands.
+    pub fn new(frequency: f32, q: f32, gain: f32, sample_rate: u32) -> Self {
+        Self::with_type(FilterType::Peaking, frequency, q, gain, sample_rate)
+    }
+
+    pub fn with_type(
+        kind: FilterType,
+        frequency: f32,
+        q: f32,
+        gain_db: f32,
+        sample_rate: u32,
+    ) -> Self {
+        let mut filter = BiquadFilter::flat();
+        filter.set_params(kind, frequency, q, gain_db, sample_rate);
+        filter
+    }
+
+    /// Recomputes the coefficients, keeping the filter history intact.
+    /// `gain_db` only affects the peaking and shelf types.
+    pub fn set_params(
+        &mut self,
+        kind: FilterType,
+        frequency: f32,
+        q: f32,
+        gain_db: f32,
+        sample_rate: u32,
+    ) {
+        let omega = 2.0 * PI * frequency / sample_rate as f32;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+        let a = 10.0f32.powf(gain_db / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            FilterType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+            FilterType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+        };
+
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+
+    /// Passes everything through unchanged.
+    pub fn flat() -> Self {
+        BiquadFilter {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    /// Whether both poles sit inside the unit circle, and far enough in for
+    /// `f32` to keep them there. A very high Q, or a big boost, at a
+    /// frequency low for the sample rate puts them so close to it that
+    /// rounding makes the filter ring on or blow up.
+    pub fn is_stable(&self) -> bool {
+        const MARGIN: f64 = 1e-6;
+        // Worked out in f64 from the coefficients as they are rounded.
+        let (a1, a2) = (f64::from(self.a1), f64::from(self.a2));
+        let discriminant = a1 * a1 - 4.0 * a2;
+        let radius = if discriminant < 0.0 {
+            a2.sqrt()
+        } else {
+            (a1.abs() + discriminant.sqrt()) / 2.0
+        };
+        radius < 1.0 - MARGIN
+    }
+
+    /// Clears the filter history, as if no samples had been processed yet.
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.x2 = 0.0;
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+
+    /// Magnitude response in dB at `frequency`.
+    pub fn magnitude_db(&self, frequency: f32, sample_rate: u32) -> f32 {
+        let omega = 2.0 * PI * frequency / sample_rate as f32;
+        let (cos1, sin1) = (omega.cos(), omega.sin());
+        let (cos2, sin2) = ((2.0 * omega).cos(), (2.0 * omega).sin());
+        let num_re = self.b0 + self.b1 * cos1 + self.b2 * cos2;
+        let num_im = -(self.b1 * sin1 + self.b2 * sin2);
+        let den_re = 1.0 + self.a1 * cos1 + self.a2 * cos2;
+        let den_im = -(self.a1 * sin1 + self.a2 * sin2);
+        let power = (num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im);
+        10.0 * power.log10()
+    }
+
+    /// Filters one sample. Should the state ever blow up to infinity or NaN,
+    /// the filter resets and lets that sample through, rather than going
+    /// silent for good.
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        if !output.is_finite() {
+            self.reset();
+            return input;
+        }
+        self.x2 = self.x1;
+        self.x1 = input;
+        self.y2 = self.y1;
+        self.y1 = output;
+        output
+    }
+
+    /// Filters `samples` in place, one after another as [`BiquadFilter::process`]
+    /// does, for a single channel.
+    pub fn process_block(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            *sample = self.process(*sample);
+        }
+    }
+
+    pub fn coefficients(&self) -> BiquadCoefficients {
+        BiquadCoefficients {
+            b0: self.b0,
+            b1: self.b1,
+            b2: self.b2,
+            a1: self.a1,
+            a2: self.a2,
+        }
+    }
+
+    /// Takes `coefficients` as they are, keeping the filter history, for a
+    /// response [`FilterType`] doesn't cover. Check [`BiquadFilter::is_stable`]
+    /// after.
+    pub fn set_coefficients(&mut self, coefficients: BiquadCoefficients) {
+        self.b0 = coefficients.b0;
+        self.b1 = coefficients.b1;
+        self.b2 = coefficients.b2;
+        self.a1 = coefficients.a1;
+        self.a2 = coefficients.a2;
+    }
+}
+
+/// A cascade of [`BiquadFilter`]s for each channel of interleaved audio,
+/// each channel with filters and history of its own.
+#[derive(Debug, Clone, Default)]
+pub struct FilterChain {
+    chains: Vec<Vec<BiquadFilter>>,
+}
+
+impl From<Vec<Vec<BiquadFilter>>> for FilterChain {
+    /// One cascade per channel, in channel order.
+    fn from(chains: Vec<Vec<BiquadFilter>>) -> Self {
+        FilterChain { chains }
+    }
+}
+
+impl FilterChain {
+    /// `channels` empty cascades, which pass audio through until filters
+    /// are added.
+    pub fn new(channels: u16) -> Self {
+        FilterChain {
+            chains: vec![Vec::new(); usize::from(channels)],
+        }
+    }
+
+    /// `filters`, in order, on each of `channels`.
+    pub fn with_filters(channels: u16, filters: &[BiquadFilter]) -> Self {
+        FilterChain {
+            chains: vec![filters.to_vec(); usize::from(channels)],
+        }
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.chains.len() as u16
+    }
+
+    /// Appends `filter` to every channel's cascade, each with a history of
+    /// its own.
+    pub fn push(&mut self, filter: BiquadFilter) {
+        for chain in &mut self.chains {
+            chain.push(filter.clone());
+        }
+    }
+
+    /// Channel `channel`'s cascade, empty for a channel there isn't.
+    pub fn channel(&self, channel: usize) -> &[BiquadFilter] {
+        self.chains.get(channel).map_or(&[], Vec::as_slice)
+    }
+
+    /// Channel `channel`'s cascade, to add, remove or retune filters on it.
+    /// Panics if there is no such channel.
+    pub fn channel_mut(&mut self, channel: usize) -> &mut Vec<BiquadFilter> {
+        &mut self.chains[channel]
+    }
+
+    /// Every channel's cascade, in channel order.
+    pub fn iter(&self) -> impl Iterator<Item = &[BiquadFilter]> {
+        self.chains.iter().map(Vec::as_slice)
+    }
+
+    /// Every channel's cascade, to change them all.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Vec<BiquadFilter>> {
+        self.chains.iter_mut()
+    }
+
+    /// Every filter on every channel.
+    pub fn filters_mut(&mut self) -> impl Iterator<Item = &mut BiquadFilter> {
+        self.chains.iter_mut().flatten()
+    }
+
+    /// Runs `sample` through channel `channel`'s cascade. Panics if there is
+    /// no such channel.
+    pub fn process(&mut self, channel: usize, sample: f32) -> f32 {
+        self.chains[channel]
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample))
+    }
+
+    /// Filters `samples` in place, interleaved across the channels and
+    /// starting on the first; a partial frame at the end is filtered on the
+    /// channels it has. Does nothing without channels.
+    pub fn process_block(&mut self, samples: &mut [f32]) {
+        let channels = self.chains.len();
+        if channels == 0 {
+            return;
+        }
+        for frame in samples.chunks_mut(channels) {
+            for (sample, chain) in frame.iter_mut().zip(&mut self.chains) {
+                *sample = chain
+                    .iter_mut()
+                    .fold(*sample, |sample, filter| filter.process(sample));
+            }
+        }
+    }
+
+    /// Clears every filter's history, as if nothing had been processed.
+    pub fn reset(&mut self) {
+        self.filters_mut().for_each(BiquadFilter::reset);
+    }
+
+    /// The gain in dB of channel `channel`'s cascade at `frequency`; 0 for
+    /// an empty one, or a channel there isn't.
+    pub fn magnitude_db(&self, channel: usize, frequency: f32, sample_rate: u32) -> f32 {
+        self.channel(channel)
+            .iter()
+            .map(|filter| filter.magnitude_db(frequency, sample_rate))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const NYQUIST: f32 = SAMPLE_RATE as f32 / 2.0;
+
+    /// Well below this counts as blocked.
+    const STOP_DB: f32 = -60.0;
+
+    /// The level in dB `filter` settles at for a constant signal, and for
+    /// one flipping sign every sample, as it runs over them.
+    fn settled_gains_db(filter: &BiquadFilter) -> (f32, f32) {
+        let settle = |nyquist: bool| {
+            let mut filter = filter.clone();
+            let mut input = vec![0.5f32; 8192];
+            if nyquist {
+                input.iter_mut().skip(1).step_by(2).for_each(|x| *x = -*x);
+            }
+            filter.process_block(&mut input);
+            let output = input[input.len() - 64..]
+                .iter()
+                .fold(0.0f32, |peak, x| peak.max(x.abs()));
+            20.0 * (output / 0.5).log10()
+        };
+        (settle(false), settle(true))
+    }
+
+    #[test]
+    fn each_type_passes_or_stops_dc_and_nyquist() {
+        // Expected gain at DC and at Nyquist, `None` for blocked.
+        let cases = [
+            (FilterType::Peaking, Some(0.0), Some(0.0)),
+            (FilterType::LowShelf, Some(6.0), Some(0.0)),
+            (FilterType::HighShelf, Some(0.0), Some(6.0)),
+            (FilterType::LowPass, Some(0.0), None),
+            (FilterType::HighPass, None, Some(0.0)),
+            (FilterType::Notch, Some(0.0), Some(0.0)),
+        ];
+        for (kind, dc, nyquist) in cases {
+            let filter = BiquadFilter::with_type(kind, 1000.0, 0.707, 6.0, SAMPLE_RATE);
+            let (settled_dc, settled_nyquist) = settled_gains_db(&filter);
+            let checks = [
+                ("DC", filter.magnitude_db(0.0, SAMPLE_RATE), settled_dc, dc),
+                (
+                    "Nyquist",
+                    filter.magnitude_db(NYQUIST, SAMPLE_RATE),
+                    settled_nyquist,
+                    nyquist,
+                ),
+            ];
+            for (at, magnitude, settled, expected) in checks {
+                for gain in [magnitude, settled] {
+                    match expected {
+                        Some(expected) => assert!(
+                            (gain - expected).abs() < 0.01,
+                            "{kind:?} at {at}: {gain} dB, not {expected}"
+                        ),
+                        None => assert!(gain < STOP_DB, "{kind:?} at {at}: {gain} dB"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_notch_stops_its_center() {
+        let filter = BiquadFilter::with_type(FilterType::Notch, 1000.0, 0.707, 0.0, SAMPLE_RATE);
+        assert!(filter.magnitude_db(1000.0, SAMPLE_RATE) < STOP_DB);
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{
+    atomic::AtomicF32,
+    backend::Backend,
+    error::PlayerError,
+    events::Signal,
+    gain::{db_to_linear, Gain, GainControls},
+    lock::Lock,
+    output::{device_format, negotiated, Output, OutputConfig, StreamConfig},
+    player::{AudioPlayer, MAX_VOLUME_DB, MIN_VOLUME_DB},
+    resample::{ResampleQuality, Resampler},
+    schedule::{MixClock, MixCount},
+    secondary::{Delay, SecondaryControls, Tee},
+    settings::EqSettings,
+};
+use rodio::{
+    dynamic_mixer::{self, DynamicMixerController},
+    source::Zero,
+    Source,
+};
+use std::{
+    path::Path,
+    sync::{atomic::AtomicU64, mpsc::Sender, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long a master volume change takes to settle.
+const MASTER_RAMP: Duration = Duration::from_millis(20);
+
+struct Engine {
+    output: Output,
+    backend: Backend,
+    mixer: Arc<DynamicMixerController<f32>>,
+    master: Arc<GainControls>,
+    master_db: AtomicF32,
+    /// The rate the players are mixed at, and their channels.
+    sample_rate: u32,
+    channels: u16,
+    config: OutputConfig,
+    /// The second output, its volume and what it shares with the first.
+    secondary: Mutex<Option<Output>>,
+    secondary_gain: Arc<GainControls>,
+    secondary_db: AtomicF32,
+    outputs: Arc<SecondaryControls>,
+    /// Which frame of the mix is heard when, for scheduled starts.
+    clock: Arc<MixClock>,
+}
+
+/// One output device shared by any number of [`AudioPlayer`]s, which play
+/// at the same time, mixed together.
+///
+/// Each player keeps its own queue, EQ, volume, position and events, and
+/// pausing, seeking or dropping one leaves the others alone. The engine
+/// follows the default device as a single player does, and carries on for
+/// as long as it or any of its players is around; clones share it.
+#[derive(Clone)]
+pub struct AudioEngine {
+    engine: Arc<Engine>,
+}
+
+impl AudioEngine {
+    /// Opens the default output device, mixing at its own format.
+    pub fn new() -> Result<AudioEngine, PlayerError> {
+        Self::with_backend(Backend::Default, None)
+    }
+
+    /// Opens `backend`'s output device named `device`, or its default one
+    /// with `None`, mixing at that device's format. Fails listing what there
+    /// is when either isn't available; see [`Backend::output_devices`].
+    pub fn with_backend(
+        backend: Backend,
+        device: Option<&str>,
+    ) -> Result<AudioEngine, PlayerError> {
+        Self::with_output_config(backend, device, OutputConfig::default())
+    }
+
+    /// Like [`AudioEngine::with_backend`], asking the device for the buffer
+    /// size, sample rate and sample format in `config`, and mixing at the
+    /// rate it agrees to. What it can't do falls back to its own, with a
+    /// [`PlayerEvent::Warning`] to each player; [`AudioEngine::stream_config`]
+    /// tells what it settled on.
+    ///
+    /// [`PlayerEvent::Warning`]: crate::PlayerEvent::Warning
+    pub fn with_output_config(
+        backend: Backend,
+        device: Option<&str>,
+        config: OutputConfig,
+    ) -> Result<AudioEngine, PlayerError> {
+        let (channels, sample_rate) = device_format(backend, device, config)?;
+        let (mixer, mixed) = dynamic_mixer::mixer(channels, sample_rate);
+        // The mixer ends once it has nothing to play, so give it silence
+        // that never does.
+        mixer.add(Zero::<f32>::new(channels, sample_rate));
+        let master = Arc::new(GainControls::new(1.0, MASTER_RAMP));
+        let outputs = Arc::new(SecondaryControls::new());
+        let count = Arc::new(AtomicU64::new(0));
+        let teed = Tee::new(MixCount::new(mixed, count.clone()), outputs.clone());
+        let output = Output::open(
+            Gain::new(Delay::new(teed, outputs.clone(), true), master.clone()),
+            backend,
+            device.map(str::to_string),
+            config,
+        )?;
+        let clock = Arc::new(MixClock::new(
+            count,
+            channels,
+            sample_rate,
+            output.timing(),
+            outputs.clone(),
+        ));
+        Ok(AudioEngine {
+            engine: Arc::new(Engine {
+                output,
+                backend,
+                mixer,
+                master,
+                master_db: AtomicF32::new(0.0),
+                sample_rate,
+                channels,
+                config,
+                secondary: Mutex::new(None),
+                secondary_gain: Arc::new(GainControls::new(1.0, MASTER_RAMP)),
+                secondary_db: AtomicF32::new(0.0),
+                outputs,
+                clock,
+            }),
+        })
+    }
+
+    /// The format [`AudioEngine::with_output_config`] would open the same
+    /// device at, asked of it without opening it; `None` if there is no
+    /// such device or it can't tell.
+    pub fn output_format(
+        backend: Backend,
+        device: Option<&str>,
+        config: OutputConfig,
+    ) -> Option<StreamConfig> {
+        negotiated(backend, device, config).ok()?
+    }
+
+    /// Opens `path` on a player of its own, mixed in with the engine's
+    /// others. The player starts paused.
+    pub fn create_player(&self, path: impl AsRef<Path>) -> Result<AudioPlayer, PlayerError> {
+        let player = self.new_player()?;
+        player.enqueue(path)?;
+        Ok(player)
+    }
+
+    /// An empty player, mixed in with the engine's others, for queueing
+    /// files, streams or cue sheets on. It starts paused.
+    pub fn new_player(&self) -> Result<AudioPlayer, PlayerError> {
+        AudioPlayer::on_engine(self.clone(), EqSettings::default())
+    }
+
+    /// Starts each of `players` as [`AudioPlayer::play_at`] does, all on
+    /// the same frame of the mix, so they stay sample-aligned. Each is
+    /// decoded ahead before any is let go. Fails, starting none, if one
+    /// isn't on this engine or can't start.
+    pub fn play_all_at(
+        &self,
+        players: &[&AudioPlayer],
+        instant: Instant,
+    ) -> Result<(), PlayerError> {
+        if let Some(index) = players
+            .iter()
+            .position(|player| !Arc::ptr_eq(&player.engine().engine, &self.engine))
+        {
+            return Err(PlayerError::InvalidArgument(format!(
+                "player {index} is on another engine"
+            )));
+        }
+        for (prepared, player) in players.iter().enumerate() {
+            if let Err(err) = player.prepare_start(instant) {
+                for player in &players[..prepared] {
+                    player.cancel_play_at();
+                }
+                return Err(err);
+            }
+        }
+        let frame = self.engine.clock.frame_at(instant);
+        for player in players {
+            player.release_start(frame, instant);
+        }
+        Ok(())
+    }
+
+    /// Sets the volume of the whole mix on the output device in dB, clamped
+    /// to `MIN_VOLUME_DB..=MAX_VOLUME_DB`, on top of each player's own. A
+    /// secondary output has a volume of its own instead; see
+    /// [`AudioEngine::set_secondary_volume_db`].
+    pub fn set_master_volume_db(&self, db: f32) {
+        let db = db.clamp(MIN_VOLUME_DB, MAX_VOLUME_DB);
+        self.engine.master_db.store(db);
+        self.engine.master.set_target(db_to_linear(db));
+    }
+
+    pub fn master_volume_db(&self) -> f32 {
+        self.engine.master_db.load()
+    }
+
+    /// Name of the output device playing right now.
+    pub fn output_device(&self) -> Option<String> {
+        self.engine.output.device()
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.engine.backend
+    }
+
+    /// The format the output device plays at right now, which may not be
+    /// what was asked for; see [`AudioEngine::with_output_config`].
+    pub fn stream_config(&self) -> Option<StreamConfig> {
+        self.engine.output.config()
+    }
+
+    /// Plays a copy of the mix on the device named `device` as well, at its
+    /// own volume and delay, in place of any secondary output before. The
+    /// copy is resampled to the device's rate where it plays at another.
+    /// Should the device go away, the primary output carries on alone and
+    /// each player is sent [`PlayerEvent::SecondaryOutputLost`]. Fails
+    /// listing what there is when there is no such device.
+    ///
+    /// [`PlayerEvent::SecondaryOutputLost`]: crate::PlayerEvent::SecondaryOutputLost
+    pub fn add_secondary_output(&self, device: &str) -> Result<(), PlayerError> {
+        let engine = &self.engine;
+        let mut secondary = engine.secondary.locked();
+        *secondary = None;
+        engine.outputs.disconnect();
+
+        let config = OutputConfig {
+            latency: engine.config.latency,
+            source_rate: Some(engine.sample_rate),
+            ..OutputConfig::default()
+        };
+        let rate = negotiated(engine.backend, Some(device), config)?
+            .map_or(engine.sample_rate, |config| config.sample_rate);
+        let copy = Gain::new(
+            engine.outputs.connect(engine.channels, engine.sample_rate),
+            engine.secondary_gain.clone(),
+        );
+        let opened = match rate == engine.sample_rate {
+            true => Output::open_only(
+                copy,
+                engine.backend,
+                device.to_string(),
+                config,
+                engine.output.listeners(),
+            ),
+            false => Output::open_only(
+                Resampler::new(copy, ResampleQuality::default(), rate),
+                engine.backend,
+                device.to_string(),
+                config,
+                engine.output.listeners(),
+            ),
+        };
+        match opened {
+            Ok(output) => {
+                *secondary = Some(output);
+                Ok(())
+            }
+            Err(err) => {
+                engine.outputs.disconnect();
+                Err(err)
+            }
+        }
+    }
+
+    /// Stops playing on the secondary output, if there is one.
+    pub fn remove_secondary_output(&self) {
+        let mut secondary = self.engine.secondary.locked();
+        self.engine.outputs.disconnect();
+        *secondary = None;
+    }
+
+    /// Name of the secondary output device, while it plays.
+    pub fn secondary_output(&self) -> Option<String> {
+        self.engine.secondary.locked().as_ref()?.device()
+    }
+
+    /// Sets the volume of the secondary output in dB, clamped to
+    /// `MIN_VOLUME_DB..=MAX_VOLUME_DB`, in place of the master volume.
+    pub fn set_secondary_volume_db(&self, db: f32) {
+        let db = db.clamp(MIN_VOLUME_DB, MAX_VOLUME_DB);
+        self.engine.secondary_db.store(db);
+        self.engine.secondary_gain.set_target(db_to_linear(db));
+    }
+
+    pub fn secondary_volume_db(&self) -> f32 {
+        self.engine.secondary_db.load()
+    }
+
+    /// Holds the primary output back by `delay`, up to [`MAX_OUTPUT_DELAY`],
+    /// to line it up with the secondary.
+    ///
+    /// [`MAX_OUTPUT_DELAY`]: crate::MAX_OUTPUT_DELAY
+    pub fn set_primary_delay(&self, delay: Duration) {
+        self.engine.outputs.set_primary_delay(delay);
+    }
+
+    pub fn primary_delay(&self) -> Duration {
+        self.engine.outputs.primary_delay()
+    }
+
+    /// Holds the secondary output back by `delay`, up to
+    /// [`MAX_OUTPUT_DELAY`], to line it up with the primary.
+    ///
+    /// [`MAX_OUTPUT_DELAY`]: crate::MAX_OUTPUT_DELAY
+    pub fn set_secondary_delay(&self, delay: Duration) {
+        self.engine.outputs.set_secondary_delay(delay);
+    }
+
+    pub fn secondary_delay(&self) -> Duration {
+        self.engine.outputs.secondary_delay()
+    }
+
+    /// Mixes `source` in until it ends, telling `signals` about device changes.
+    pub(crate) fn add(
+        &self,
+        source: impl Source<Item = f32> + Send + 'static,
+        signals: Sender<Signal>,
+    ) {
+        self.engine.mixer.add(source);
+        self.engine.output.subscribe(signals);
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.engine.sample_rate
+    }
+
+    pub(crate) fn channels(&self) -> u16 {
+        self.engine.channels
+    }
+
+    pub(crate) fn clock(&self) -> Arc<MixClock> {
+        self.engine.clock.clone()
+    }
+
+    /// Mixes `source` in until it ends.
+    pub(crate) fn mix(&self, source: impl Source<Item = f32> + Send + 'static) {
+        self.engine.mixer.add(source);
+    }
+
+    pub(crate) fn fallback_device(&self) -> Option<String> {
+        self.engine.output.fallback()
+    }
+
+    pub(crate) fn set_fallback_device(&self, name: Option<String>) {
+        self.engine.output.set_fallback(name);
+    }
+}
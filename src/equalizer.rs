@@ -0,0 +1,1172 @@
+use crate::{
+    dsp::{BiquadCoefficients, BiquadFilter, FilterChain, FilterType},
+    gain::{db_to_linear, linear_to_db},
+    lock::Lock,
+    mailbox::{Broadcast, Mailbox},
+    preset::EqPreset,
+    settings::{EqBand, EqError, EqSettings},
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    f32::consts::PI,
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+pub const BAND_COUNT: usize = 10;
+
+pub const FREQUENCIES: [f32; BAND_COUNT] = [
+    32.0, 64.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+pub const DEFAULT_GAINS: [f32; BAND_COUNT] = [4.6, 8.0, 4.6, 0.9, 0.0, 3.0, 0.9, 0.0, 0.0, 0.0];
+
+pub const DEFAULT_Q: f32 = 1.41;
+
+/// How long switching the EQ in or out crossfades between the filtered and dry signal.
+pub const EQ_BYPASS_FADE: Duration = Duration::from_millis(30);
+
+/// How long a change to the bands glides from the old settings to the new,
+/// so dragging a gain doesn't step audibly.
+pub const EQ_SMOOTHING: Duration = Duration::from_millis(30);
+
+/// Q of the shelves at either end of the ten-band layout (a Butterworth slope).
+pub const SHELF_Q: f32 = 0.707;
+
+pub const THIRD_OCTAVE_BAND_COUNT: usize = 31;
+
+/// ISO 266 third-octave centers, 20 Hz to 20 kHz.
+pub const THIRD_OCTAVE_FREQUENCIES: [f32; THIRD_OCTAVE_BAND_COUNT] = [
+    20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0,
+    500.0, 630.0, 800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0,
+    8000.0, 10000.0, 12500.0, 16000.0, 20000.0,
+];
+
+/// Q of a third of an octave, so each band reaches about to its neighbours' centers.
+pub const THIRD_OCTAVE_Q: f32 = 4.32;
+
+pub struct Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    chains: FilterChain,
+    /// The rate the filters are tuned for.
+    sample_rate: u32,
+    channel: usize,
+    settings: EqSettings,
+    /// What each channel's filters are tuned to right now, on the way to
+    /// its bands in `settings` while a change glides in.
+    tuned: Vec<Vec<EqBand>>,
+    /// Frames until `tuned` reaches the settings; 0 when nothing is changing.
+    glide_left: u32,
+    preamp: f32,
+    /// Where `preamp` is gliding to.
+    target_preamp: f32,
+    controls: Arc<EqControls>,
+    /// Where changes to the settings arrive.
+    updates: Arc<Mailbox<EqChange>>,
+    /// The chains of the profile being faded out, kept between fades so
+    /// starting one doesn't allocate.
+    outgoing: FilterChain,
+    outgoing_preamp: f32,
+    /// How far the crossfade from `outgoing` to `chains` has come, 0.0 to
+    /// 1.0; 1.0 when none is under way.
+    fade: f32,
+    fade_step: f32,
+    /// The equal-power gains of `outgoing` and `chains` at `fade`.
+    fade_gains: (f32, f32),
+    enabled: bool,
+    /// How much of the filtered signal is heard: 0.0 bypassed, 1.0 fully on.
+    mix: f32,
+    mix_step: f32,
+}
+
+/// Settings shared between an [`Equalizer`] and whoever adjusts it while it plays.
+///
+/// The audio side never takes the lock: every change is posted to a
+/// mailbox per equalizer, which it looks in each frame, so a busy control
+/// thread never stalls playback.
+pub struct EqControls {
+    settings: Mutex<EqSettings>,
+    updates: Broadcast<EqChange>,
+    enabled: AtomicBool,
+    bypass_fade_ns: AtomicU64,
+    smoothing_ns: AtomicU64,
+    /// Of the audio last filtered; 0 until something plays.
+    sample_rate: AtomicU32,
+    channels: AtomicU16,
+}
+
+impl EqControls {
+    pub fn new(settings: EqSettings) -> Self {
+        EqControls {
+            settings: Mutex::new(settings),
+            updates: Broadcast::new(),
+            enabled: AtomicBool::new(true),
+            bypass_fade_ns: AtomicU64::new(EQ_BYPASS_FADE.as_nanos() as u64),
+            smoothing_ns: AtomicU64::new(EQ_SMOOTHING.as_nanos() as u64),
+            sample_rate: AtomicU32::new(0),
+            channels: AtomicU16::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// A disabled equalizer passes audio through untouched, without
+    /// restarting anything. The switch crossfades over [`EqControls::bypass_fade`].
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn bypass_fade(&self) -> Duration {
+        Duration::from_nanos(self.bypass_fade_ns.load(Ordering::Relaxed))
+    }
+
+    /// Sets the crossfade used by [`EqControls::set_enabled`]. Zero switches instantly.
+    pub fn set_bypass_fade(&self, fade: Duration) {
+        self.bypass_fade_ns
+            .store(fade.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn smoothing(&self) -> Duration {
+        Duration::from_nanos(self.smoothing_ns.load(Ordering::Relaxed))
+    }
+
+    /// Sets how long gain, frequency and Q changes take to glide in. Filters
+    /// are recomputed every frame on the way, from the parameters rather
+    /// than by blending coefficients, so each step is a stable filter. Zero
+    /// applies changes at once.
+    pub fn set_smoothing(&self, smoothing: Duration) {
+        self.smoothing_ns
+            .store(smoothing.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn settings(&self) -> EqSettings {
+        self.settings.locked().clone()
+    }
+
+    pub fn set_settings(&self, settings: EqSettings) {
+        let mut current = self.settings.locked();
+        *current = settings;
+        self.post(&current);
+    }
+
+    /// Switches to `settings` by running the filters of both side by side
+    /// for `transition` and crossfading between them at equal power, each
+    /// with its own preamp. Applied again mid-fade, the fade heads for the
+    /// new settings from wherever it has got to. Zero switches at once.
+    pub fn apply_profile(&self, settings: &EqSettings, transition: Duration) {
+        let mut current = self.settings.locked();
+        current.clone_from(settings);
+        self.updates.post(&EqChange {
+            settings: Arc::new(current.clone()),
+            transition: Some(transition),
+        });
+    }
+
+    pub fn gains(&self) -> Vec<f32> {
+        self.settings.locked().gains()
+    }
+
+    pub fn set_preamp_db(&self, preamp_db: f32) {
+        let mut settings = self.settings.locked();
+        settings.preamp_db = preamp_db;
+        self.post(&settings);
+    }
+
+    /// While enabled the preamp follows the EQ curve instead of `preamp_db`.
+    pub fn set_auto_headroom(&self, enabled: bool) {
+        let mut settings = self.settings.locked();
+        settings.auto_headroom = enabled;
+        self.post(&settings);
+    }
+
+    /// Updates the gain of each band in order. Fails, changing nothing,
+    /// unless there is exactly one gain per band.
+    pub fn set_gains(&self, gains: &[f32]) -> Result<(), EqError> {
+        let mut settings = self.settings.locked();
+        if gains.len() != settings.bands.len() {
+            return Err(EqError::Invalid(format!(
+                "expected {} EQ gains, one per band, got {}",
+                settings.bands.len(),
+                gains.len()
+            )));
+        }
+        for (band, &gain) in settings.bands.iter_mut().zip(gains) {
+            band.gain_db = gain;
+        }
+        self.post(&settings);
+        Ok(())
+    }
+
+    pub fn is_linked(&self) -> bool {
+        self.settings.locked().linked
+    }
+
+    /// Linked, every channel takes the shared gains; unlinked, the channels
+    /// given gains of their own with [`EqControls::set_channel_gains`] take
+    /// those again. Either way the change glides in.
+    pub fn set_linked(&self, linked: bool) {
+        let mut settings = self.settings.locked();
+        settings.linked = linked;
+        self.post(&settings);
+    }
+
+    /// The gains channel `channel` hears, one per band.
+    pub fn channel_gains(&self, channel: usize) -> Vec<f32> {
+        self.settings.locked().channel_gains(channel)
+    }
+
+    /// Gives channel `channel` gains of its own, one per band in order, and
+    /// unlinks the channels. Bands left out keep the shared gain; so do the
+    /// other channels, until they are given theirs.
+    pub fn set_channel_gains(&self, channel: usize, gains: &[f32]) -> Result<(), EqError> {
+        if channel >= usize::from(u16::MAX) {
+            return Err(EqError::Invalid(format!("no channel {channel}")));
+        }
+        let mut settings = self.settings.locked();
+        // Checked on a copy, so gains that don't pass leave things as they were.
+        let mut updated = settings.clone();
+        if updated.channel_gains.len() <= channel {
+            updated.channel_gains.resize(channel + 1, Vec::new());
+        }
+        updated.channel_gains[channel] = gains.iter().copied().take(updated.bands.len()).collect();
+        updated.linked = false;
+        updated
+            .channel_bands(channel)
+            .iter()
+            .try_for_each(|band| self.validate(band))?;
+        *settings = updated;
+        self.post(&settings);
+        Ok(())
+    }
+
+    pub fn bands(&self) -> Vec<EqBand> {
+        self.settings.locked().bands.clone()
+    }
+
+    /// Replaces band `index`. Only its filter's coefficients change, gliding
+    /// over [`EqControls::smoothing`]; its state carries on, so the change
+    /// doesn't click.
+    pub fn set_band(&self, index: usize, band: EqBand) -> Result<(), EqError> {
+        self.validate(&band)?;
+        let mut settings = self.settings.locked();
+        let len = settings.bands.len();
+        let slot = settings
+            .bands
+            .get_mut(index)
+            .ok_or_else(|| no_band(index, len))?;
+        *slot = band;
+        self.post(&settings);
+        Ok(())
+    }
+
+    /// Changes the Q of band `index` alone, checked like
+    /// [`EqControls::set_band`].
+    pub fn set_band_q(&self, index: usize, q: f32) -> Result<(), EqError> {
+        let bands = self.bands();
+        let band = *bands
+            .get(index)
+            .ok_or_else(|| no_band(index, bands.len()))?;
+        self.set_band(index, EqBand { q, ..band })
+    }
+
+    /// Appends a band, returning its index.
+    pub fn add_band(&self, band: EqBand) -> Result<usize, EqError> {
+        self.validate(&band)?;
+        let mut settings = self.settings.locked();
+        settings.bands.push(band);
+        self.post(&settings);
+        Ok(settings.bands.len() - 1)
+    }
+
+    /// Removes band `index`; the bands after it move down one.
+    pub fn remove_band(&self, index: usize) -> Result<EqBand, EqError> {
+        let mut settings = self.settings.locked();
+        if index >= settings.bands.len() {
+            return Err(no_band(index, settings.bands.len()));
+        }
+        let band = settings.bands.remove(index);
+        for gains in &mut settings.channel_gains {
+            if index < gains.len() {
+                gains.remove(index);
+            }
+        }
+        self.post(&settings);
+        Ok(band)
+    }
+
+    /// The response of [`EqControls::settings`] on channel `channel`, as
+    /// [`Equalizer::frequency_response`] but without needing the equalizer:
+    /// filters are set up from the settings at the sample rate playing now,
+    /// or 44.1 kHz before anything has played. Channels the audio doesn't
+    /// have show channel 0's, which is what a mono source plays with.
+    pub fn frequency_response(&self, channel: usize, points: usize) -> Vec<(f32, f32)> {
+        let sample_rate = self.response_rate();
+        if !self.is_enabled() {
+            return response(&[], 0.0, sample_rate, points);
+        }
+        let settings = self.settings();
+        let channels = match self.channels.load(Ordering::Relaxed) {
+            0 => settings.channel_gains.len().max(1),
+            channels => usize::from(channels),
+        };
+        let chains = tune_chains(&settings, channels, sample_rate);
+        let preamp_db = preamp_db(&settings, &chains, sample_rate);
+        let channel = match channel < channels {
+            true => channel,
+            false => 0,
+        };
+        response(chains.channel(channel), preamp_db, sample_rate, points)
+    }
+
+    /// The rate [`EqControls::frequency_response`] is worked out at.
+    pub(crate) fn response_rate(&self) -> u32 {
+        match self.sample_rate.load(Ordering::Relaxed) {
+            0 => 44100,
+            sample_rate => sample_rate,
+        }
+    }
+
+    /// Passes the settings, as they are now, to every equalizer, in the
+    /// order they were changed in since it is done under the lock.
+    fn post(&self, settings: &EqSettings) {
+        self.updates.post(&EqChange {
+            settings: Arc::new(settings.clone()),
+            transition: None,
+        });
+    }
+
+    /// Every change to the settings from now on, for stages on the audio
+    /// thread that follow the EQ.
+    pub(crate) fn subscribe(&self) -> Arc<Mailbox<EqChange>> {
+        self.updates.subscribe()
+    }
+
+    /// The loudest point of `settings` as the EQ plays them at
+    /// `sample_rate`, preamp included, over every channel: alone, and with
+    /// `extra` after it. Flat while `enabled` is off.
+    pub(crate) fn peaks_with(
+        &self,
+        settings: &EqSettings,
+        enabled: bool,
+        extra: &[BiquadFilter],
+        sample_rate: u32,
+    ) -> (f32, f32) {
+        if !enabled {
+            return (0.0, peak_gain_db(extra, sample_rate));
+        }
+        let channels = match self.channels.load(Ordering::Relaxed) {
+            0 => settings.channel_gains.len().max(1),
+            channels => usize::from(channels),
+        };
+        let chains = tune_chains(settings, channels, sample_rate);
+        let preamp_db = preamp_db(settings, &chains, sample_rate);
+        let peak = |with_extra: bool| {
+            chains
+                .iter()
+                .map(|chain| match with_extra {
+                    true => peak_gain_db(chain.iter().chain(extra), sample_rate),
+                    false => peak_gain_db(chain, sample_rate),
+                })
+                .fold(f32::NEG_INFINITY, f32::max)
+                + preamp_db
+        };
+        (peak(false), peak(true))
+    }
+
+    /// Checks `band` against the sample rate playing now, once there is one.
+    fn validate(&self, band: &EqBand) -> Result<(), EqError> {
+        match self.sample_rate.load(Ordering::Relaxed) {
+            0 => band.validate_shape(),
+            sample_rate => band.validate(sample_rate),
+        }
+    }
+}
+
+/// A change to the EQ settings, as posted to the stages that follow them.
+#[derive(Clone)]
+pub(crate) struct EqChange {
+    pub(crate) settings: Arc<EqSettings>,
+    /// The crossfade of a profile applied with
+    /// [`EqControls::apply_profile`]; `None` glides the change in.
+    pub(crate) transition: Option<Duration>,
+}
+
+fn no_band(index: usize, len: usize) -> EqError {
+    EqError::Invalid(format!("no EQ band {index}; there are {len}"))
+}
+
+impl<S> Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Ten-band equalizer over the standard octave centers, with shelves for
+    /// the lowest and highest band. `q` sets the bands' Q in order; bands it
+    /// leaves out keep [`DEFAULT_Q`], or [`SHELF_Q`] for the shelves. Use
+    /// [`Equalizer::with_bands`] for any other layout.
+    pub fn new(source: S, gains: [f32; BAND_COUNT], q: Option<&[f32]>) -> Self {
+        let mut settings = EqSettings::from_gains(&gains);
+        if let Some(q) = q {
+            settings.set_q(q);
+        }
+        Self::with_controls(source, Arc::new(EqControls::new(settings)))
+    }
+
+    /// Thirty-one peaking bands over the third-octave centers, for finer
+    /// corrections than the octave layout allows.
+    pub fn thirty_one_band(source: S, gains: [f32; THIRD_OCTAVE_BAND_COUNT]) -> Self {
+        Self::with_controls(
+            source,
+            Arc::new(EqControls::new(EqSettings::third_octave(&gains))),
+        )
+    }
+
+    pub fn with_preset(source: S, preset: EqPreset) -> Self {
+        Self::new(source, preset.gains(), Some(&preset.q()))
+    }
+
+    /// Builds an equalizer from `(frequency, gain_db, q)` triples, one per band.
+    pub fn with_bands(source: S, bands: &[(f32, f32, f32)]) -> Result<Self, EqError> {
+        let settings = EqSettings {
+            bands: bands
+                .iter()
+                .map(|&(frequency, gain_db, q)| EqBand {
+                    kind: FilterType::Peaking,
+                    frequency,
+                    gain_db,
+                    q,
+                })
+                .collect(),
+            ..EqSettings::default()
+        };
+        Self::from_settings(source, settings)
+    }
+
+    /// Builds an equalizer from `settings`, rejecting bands the source's sample rate can't carry.
+    pub fn from_settings(source: S, settings: EqSettings) -> Result<Self, EqError> {
+        settings.validate(source.sample_rate())?;
+        Ok(Self::with_controls(
+            source,
+            Arc::new(EqControls::new(settings)),
+        ))
+    }
+
+    /// Builds an equalizer that follows changes made through `controls`.
+    pub fn with_controls(source: S, controls: Arc<EqControls>) -> Self {
+        // Subscribed first, so a change made meanwhile is still seen.
+        let updates = controls.subscribe();
+        let settings = controls.settings();
+        let mut equalizer = Equalizer {
+            source,
+            chains: FilterChain::default(),
+            sample_rate: 0,
+            channel: 0,
+            tuned: Vec::new(),
+            glide_left: 0,
+            preamp: 1.0,
+            target_preamp: 1.0,
+            settings,
+            enabled: controls.is_enabled(),
+            mix: if controls.is_enabled() { 1.0 } else { 0.0 },
+            mix_step: 1.0,
+            controls,
+            updates,
+            outgoing: FilterChain::default(),
+            outgoing_preamp: 1.0,
+            fade: 1.0,
+            fade_step: 1.0,
+            fade_gains: (0.0, 1.0),
+        };
+        equalizer.rebuild_chains();
+        equalizer.update_preamp();
+        equalizer
+    }
+
+    pub fn controls(&self) -> Arc<EqControls> {
+        self.controls.clone()
+    }
+
+    /// Allocates one independent filter chain per channel of the source,
+    /// each tuned to that channel's bands. Bands at or above the Nyquist
+    /// frequency are left flat.
+    fn rebuild_chains(&mut self) {
+        let sample_rate = self.source.sample_rate();
+        self.sample_rate = sample_rate;
+        self.controls
+            .sample_rate
+            .store(sample_rate, Ordering::Relaxed);
+        let channels = self.source.channels().max(1);
+        self.controls.channels.store(channels, Ordering::Relaxed);
+        self.tuned = (0..channels as usize)
+            .map(|channel| self.settings.channel_bands(channel))
+            .collect();
+        self.glide_left = 0;
+        self.chains = self
+            .tuned
+            .iter()
+            .map(|bands| tune_chain(bands, sample_rate))
+            .collect::<Vec<_>>()
+            .into();
+        self.outgoing = self
+            .chains
+            .iter()
+            .map(|chain| Vec::with_capacity(chain.len()))
+            .collect::<Vec<_>>()
+            .into();
+        self.fade = 1.0;
+    }
+
+    /// Follows the enabled flag, moving the dry/filtered mix one frame's
+    /// worth towards it while a switch is under way.
+    fn update_mix(&mut self) {
+        let enabled = self.controls.is_enabled();
+        if enabled != self.enabled {
+            self.enabled = enabled;
+            if enabled && self.mix == 0.0 {
+                // Filter history from before the bypass no longer matches
+                // the signal and would thump.
+                self.chains.reset();
+            }
+            let frames =
+                self.controls.bypass_fade().as_secs_f32() * self.source.sample_rate() as f32;
+            self.mix_step = if frames < 1.0 { 1.0 } else { 1.0 / frames };
+        }
+        if enabled && self.mix < 1.0 {
+            self.mix = (self.mix + self.mix_step).min(1.0);
+        } else if !enabled && self.mix > 0.0 {
+            self.mix = (self.mix - self.mix_step).max(0.0);
+        }
+    }
+
+    fn update_settings(&mut self) {
+        let Some(change) = self.updates.take() else {
+            return;
+        };
+        self.settings.clone_from(&change.settings);
+
+        let sample_rate = self.source.sample_rate();
+        let frames = |duration: Duration| duration.as_secs_f32() * sample_rate as f32;
+        let retuned = self.sample_rate != sample_rate;
+        match change.transition {
+            // Bypassed, there is nothing to hear fade.
+            Some(transition) if frames(transition) >= 1.0 && self.mix > 0.0 && !retuned => {
+                self.crossfade(frames(transition));
+            }
+            Some(_) => {
+                self.fade = 1.0;
+                self.retune();
+            }
+            None if frames(self.controls.smoothing()) < 1.0 || retuned => self.retune(),
+            None => self.glide(frames(self.controls.smoothing()) as u32),
+        }
+    }
+
+    /// Starts crossfading over `frames` from the filters playing to ones
+    /// tuned to the settings. The spare chains take the settings, so no
+    /// third set is needed: mid-fade, whichever side is louder carries on
+    /// fading out and the other is retuned, its state carrying on.
+    fn crossfade(&mut self, frames: f32) {
+        if self.fade >= 1.0 {
+            mem::swap(&mut self.chains, &mut self.outgoing);
+            self.outgoing_preamp = self.preamp;
+            // History left from the last fade doesn't match the signal.
+            self.chains.reset();
+            self.fade = 0.0;
+        } else if self.fade > 0.5 {
+            mem::swap(&mut self.chains, &mut self.outgoing);
+            mem::swap(&mut self.preamp, &mut self.outgoing_preamp);
+            self.fade = 1.0 - self.fade;
+        }
+        self.retune();
+        self.fade_step = (1.0 - self.fade) / frames;
+    }
+
+    /// Moves a crossfade one frame on, ending it while bypassed.
+    fn step_fade(&mut self) {
+        self.fade = if self.mix == 0.0 {
+            1.0
+        } else {
+            (self.fade + self.fade_step).min(1.0)
+        };
+        let angle = self.fade * PI / 2.0;
+        self.fade_gains = (angle.cos(), angle.sin());
+    }
+
+    /// Starts moving the filters from where they are to the settings over
+    /// `frames`. Bands that can't glide, having been added or changed type,
+    /// take their new settings at once, as does the preamp's target.
+    fn glide(&mut self, frames: u32) {
+        let sample_rate = self.sample_rate;
+        for (channel, (chain, tuned)) in self.chains.iter_mut().zip(&mut self.tuned).enumerate() {
+            let targets = self.settings.channel_bands(channel);
+            tuned.truncate(targets.len());
+            chain.truncate(targets.len());
+            for (i, target) in targets.iter().enumerate() {
+                match tuned.get_mut(i) {
+                    Some(tuned) if can_glide(tuned, target) => continue,
+                    Some(tuned) => *tuned = *target,
+                    None => {
+                        tuned.push(*target);
+                        chain.push(BiquadFilter::flat());
+                    }
+                }
+                set_band(&mut chain[i], target, sample_rate);
+            }
+        }
+
+        let channels = usize::from(self.chains.channels());
+        let targets = tune_chains(&self.settings, channels, sample_rate);
+        self.target_preamp = db_to_linear(preamp_db(&self.settings, &targets, sample_rate));
+        self.glide_left = frames;
+    }
+
+    /// Moves every gliding band one frame's worth towards its settings:
+    /// gain in dB in a straight line, frequency and Q on a log scale.
+    fn step_glide(&mut self) {
+        let left = self.glide_left as f32;
+        for (channel, (chain, tuned)) in self.chains.iter_mut().zip(&mut self.tuned).enumerate() {
+            for (i, (filter, tuned)) in chain.iter_mut().zip(tuned).enumerate() {
+                let Some(target) = self.settings.channel_band(channel, i) else {
+                    continue;
+                };
+                if *tuned == target {
+                    continue;
+                }
+                if self.glide_left == 1 {
+                    *tuned = target;
+                } else {
+                    tuned.gain_db += (target.gain_db - tuned.gain_db) / left;
+                    tuned.frequency *= (target.frequency / tuned.frequency).powf(left.recip());
+                    tuned.q *= (target.q / tuned.q).powf(left.recip());
+                }
+                set_band(filter, tuned, self.sample_rate);
+            }
+        }
+        self.preamp += (self.target_preamp - self.preamp) / left;
+        self.glide_left -= 1;
+    }
+
+    /// Recomputes every filter for the current settings and sample rate at
+    /// once, ending any glide. Bands added or removed don't disturb the
+    /// state of the others.
+    fn retune(&mut self) {
+        let sample_rate = self.source.sample_rate();
+        self.sample_rate = sample_rate;
+        self.controls
+            .sample_rate
+            .store(sample_rate, Ordering::Relaxed);
+        for (channel, (chain, tuned)) in self.chains.iter_mut().zip(&mut self.tuned).enumerate() {
+            *tuned = self.settings.channel_bands(channel);
+            chain.resize_with(tuned.len(), BiquadFilter::flat);
+            for (filter, band) in chain.iter_mut().zip(tuned.iter()) {
+                set_band(filter, band, sample_rate);
+            }
+        }
+        self.glide_left = 0;
+        self.update_preamp();
+    }
+
+    fn update_preamp(&mut self) {
+        self.preamp = db_to_linear(preamp_db(
+            &self.settings,
+            &self.chains,
+            self.source.sample_rate(),
+        ));
+        self.target_preamp = self.preamp;
+    }
+
+    /// The gain in dB of channel `channel`'s filters and the preamp at
+    /// `points` frequencies, log-spaced from 20 Hz to the Nyquist frequency,
+    /// worked out from the filter coefficients alone. It follows runtime
+    /// changes once the equalizer has picked them up, and is flat while it
+    /// is bypassed. Channels the source doesn't have show channel 0's.
+    pub fn frequency_response(&self, channel: usize, points: usize) -> Vec<(f32, f32)> {
+        let sample_rate = self.sample_rate.max(1);
+        if !self.enabled {
+            return response(&[], 0.0, sample_rate, points);
+        }
+        let channel = match channel < usize::from(self.chains.channels()) {
+            true => channel,
+            false => 0,
+        };
+        response(
+            self.chains.channel(channel),
+            linear_to_db(self.preamp),
+            sample_rate,
+            points,
+        )
+    }
+}
+
+/// A chain of filters tuned to `bands`.
+fn tune_chain(bands: &[EqBand], sample_rate: u32) -> Vec<BiquadFilter> {
+    bands
+        .iter()
+        .map(|band| {
+            let mut filter = BiquadFilter::flat();
+            set_band(&mut filter, band, sample_rate);
+            filter
+        })
+        .collect()
+}
+
+/// A chain for each of `channels`, tuned to its bands in `settings`.
+fn tune_chains(settings: &EqSettings, channels: usize, sample_rate: u32) -> FilterChain {
+    (0..channels)
+        .map(|channel| tune_chain(&settings.channel_bands(channel), sample_rate))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Has `filter` follow `band`, or go flat if `sample_rate` can't carry it,
+/// which would otherwise make the filter unstable.
+fn set_band(filter: &mut BiquadFilter, band: &EqBand, sample_rate: u32) {
+    if band.frequency > 0.0 && band.frequency < sample_rate as f32 / 2.0 {
+        filter.set_params(band.kind, band.frequency, band.q, band.gain_db, sample_rate);
+    }
+    if !filter.is_stable() {
+        filter.set_coefficients(BiquadCoefficients::FLAT);
+    }
+}
+
+/// Whether `from` can move smoothly to `to`: the same type of filter, with
+/// frequencies and Q a log scale can run between.
+fn can_glide(from: &EqBand, to: &EqBand) -> bool {
+    from.kind == to.kind
+        && [from.frequency, to.frequency, from.q, to.q]
+            .iter()
+            .all(|&value| value > 0.0 && value.is_finite())
+}
+
+/// With auto headroom on, the preamp cancels the loudest point of the
+/// composed response so boosted bands can't push a full-scale signal over 0 dBFS.
+/// It is the same on every channel, from the loudest of them, so it doesn't
+/// move the balance.
+fn preamp_db(settings: &EqSettings, chains: &FilterChain, sample_rate: u32) -> f32 {
+    match settings.auto_headroom {
+        true => -chains
+            .iter()
+            .map(|chain| peak_gain_db(chain, sample_rate))
+            .fold(0.0, f32::max),
+        false => settings.preamp_db,
+    }
+}
+
+/// The gain of `chain` after `preamp_db` at `points` frequencies, log-spaced
+/// from 20 Hz to the Nyquist frequency.
+pub(crate) fn response(
+    chain: &[BiquadFilter],
+    preamp_db: f32,
+    sample_rate: u32,
+    points: usize,
+) -> Vec<(f32, f32)> {
+    let low = 20.0f32.ln();
+    let high = (sample_rate as f32 / 2.0).ln();
+    (0..points)
+        .map(|i| {
+            let at = i as f32 / (points - 1).max(1) as f32;
+            let frequency = (low + (high - low) * at).exp();
+            let gain_db = chain
+                .iter()
+                .map(|filter| filter.magnitude_db(frequency, sample_rate))
+                .sum::<f32>();
+            (frequency, gain_db + preamp_db)
+        })
+        .collect()
+}
+
+/// The gain `settings` give, preamp included, to a signal with as much
+/// power in each octave as the next, as music has about: each channel's
+/// power gain averaged on a log grid from 20 Hz to 20 kHz, or Nyquist if
+/// lower, then over the channels.
+pub(crate) fn loudness_gain_db(settings: &EqSettings, sample_rate: u32) -> f32 {
+    const POINTS: usize = 256;
+    let chains = tune_chains(settings, settings.channel_gains.len().max(1), sample_rate);
+    let preamp_db = preamp_db(settings, &chains, sample_rate);
+    let low = 20.0f32.ln();
+    let high = (sample_rate as f32 / 2.0).min(20_000.0).ln();
+    let power = chains
+        .iter()
+        .flat_map(|chain| {
+            (0..POINTS).map(move |i| {
+                let frequency = (low + (high - low) * i as f32 / (POINTS - 1) as f32).exp();
+                let gain_db = chain
+                    .iter()
+                    .map(|filter| filter.magnitude_db(frequency, sample_rate))
+                    .sum::<f32>();
+                10.0f32.powf(gain_db / 10.0)
+            })
+        })
+        .sum::<f32>()
+        / (POINTS * usize::from(chains.channels())) as f32;
+    10.0 * power.log10() + preamp_db
+}
+
+/// Highest gain of the cascaded `chain`, sampled on a log grid from 10 Hz to Nyquist.
+pub(crate) fn peak_gain_db<'a>(
+    chain: impl IntoIterator<Item = &'a BiquadFilter> + Clone,
+    sample_rate: u32,
+) -> f32 {
+    const POINTS: usize = 512;
+    let low = 10.0f32.ln();
+    let high = (sample_rate as f32 / 2.0).ln();
+    (0..POINTS)
+        .map(|i| {
+            let frequency = (low + (high - low) * i as f32 / (POINTS - 1) as f32).exp();
+            chain
+                .clone()
+                .into_iter()
+                .map(|filter| filter.magnitude_db(frequency, sample_rate))
+                .sum::<f32>()
+        })
+        .fold(f32::NEG_INFINITY, f32::max)
+}
+
+impl<S> Iterator for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            if self.source.channels().max(1) != self.chains.channels() {
+                self.rebuild_chains();
+                self.update_preamp();
+            } else if self.source.sample_rate() != self.sample_rate {
+                // The bands would sit at the wrong frequencies, or above Nyquist.
+                self.fade = 1.0;
+                self.retune();
+            }
+            self.update_settings();
+            if self.glide_left > 0 {
+                self.step_glide();
+            }
+            self.update_mix();
+            if self.fade < 1.0 {
+                self.step_fade();
+            }
+        }
+
+        let sample = self.source.next()?;
+        let channel = self.channel;
+        self.channel = (channel + 1) % usize::from(self.chains.channels());
+        if self.mix == 0.0 {
+            return Some(sample);
+        }
+
+        let mut filtered = self.chains.process(channel, sample * self.preamp);
+        if self.fade < 1.0 {
+            let outgoing = self
+                .outgoing
+                .process(channel, sample * self.outgoing_preamp);
+            filtered = outgoing * self.fade_gains.0 + filtered * self.fade_gains.1;
+        }
+        if self.mix == 1.0 {
+            Some(filtered)
+        } else {
+            Some(sample + (filtered - sample) * self.mix)
+        }
+    }
+}
+
+impl<S> Source for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+
+        // History from before the jump would otherwise ring into the new position.
+        self.chains.reset();
+        self.outgoing.reset();
+        self.channel = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generators::{GeneratorSettings, SineWave, WhiteNoise},
+        testing::{channel, collect, gain_db, noise, peak, second, sine, SETTLE},
+    };
+    use std::{sync::atomic::AtomicBool, thread};
+
+    #[test]
+    fn a_band_boosts_its_center_and_leaves_a_decade_below_alone() {
+        let bands = [(1000.0, 6.0, DEFAULT_Q)];
+        for (frequency, expected) in [(1000.0, 6.0), (100.0, 0.0)] {
+            let input = sine(frequency, 44_100, 1);
+            let dry = input.clone().collect::<Vec<_>>();
+            let wet = Equalizer::with_bands(input, &bands)
+                .unwrap()
+                .collect::<Vec<_>>();
+            let gain = gain_db(&dry, &wet, SETTLE);
+            assert!(
+                (gain - expected).abs() < 0.1,
+                "{frequency} Hz came out {gain:.2} dB"
+            );
+        }
+    }
+
+    #[test]
+    fn the_response_peaks_at_a_band_and_is_flat_two_octaves_off() {
+        let equalizer =
+            Equalizer::with_bands(sine(1000.0, 44_100, 1), &[(1000.0, 6.0, DEFAULT_Q)]).unwrap();
+        let response = equalizer.frequency_response(0, 512);
+        let at = |frequency: f32| {
+            response
+                .iter()
+                .min_by(|a, b| {
+                    (a.0 / frequency)
+                        .ln()
+                        .abs()
+                        .total_cmp(&(b.0 / frequency).ln().abs())
+                })
+                .map(|&(_, gain_db)| gain_db)
+                .unwrap()
+        };
+        assert!(
+            (at(1000.0) - 6.0).abs() < 0.05,
+            "{} dB at the center",
+            at(1000.0)
+        );
+        for frequency in [250.0, 4000.0] {
+            let gain_db = at(frequency);
+            assert!(gain_db.abs() < 0.5, "{gain_db} dB at {frequency} Hz");
+        }
+    }
+
+    /// The largest third difference between neighbouring samples, which
+    /// grows with the cube of the frequency: tiny for a low tone, and large
+    /// for anything a click sets ringing at a band well above it.
+    fn largest_jerk(samples: &[f32]) -> f32 {
+        samples.windows(4).fold(0.0, |jerk, window| {
+            let third = window[3] - 3.0 * window[2] + 3.0 * window[1] - window[0];
+            third.abs().max(jerk)
+        })
+    }
+
+    #[test]
+    fn sweeping_a_band_from_cut_to_boost_adds_no_discontinuities() {
+        let input = sine(200.0, 44_100, 1);
+        let dry = input.clone().collect::<Vec<_>>();
+        let band = |gain_db| EqBand {
+            kind: FilterType::Peaking,
+            frequency: 1000.0,
+            gain_db,
+            q: DEFAULT_Q,
+        };
+        let mut equalizer = Equalizer::with_bands(input, &[(1000.0, -12.0, DEFAULT_Q)]).unwrap();
+        let controls = equalizer.controls();
+        let mut wet = equalizer.by_ref().take(4410).collect::<Vec<_>>();
+        // A slider dragged from -12 to +12 dB over 100 ms, a step at a time.
+        for step in 1..=10 {
+            controls
+                .set_band(0, band(-12.0 + 2.4 * step as f32))
+                .unwrap();
+            wet.extend(equalizer.by_ref().take(441));
+        }
+        wet.extend(equalizer);
+        // Two octaves below, the band lifts the tone by about 1 dB at most.
+        let allowed = largest_jerk(&dry) * db_to_linear(2.0);
+        let jerk = largest_jerk(&wet[SETTLE..]);
+        assert!(jerk <= allowed, "{jerk} against the tone's {allowed}");
+    }
+
+    #[test]
+    fn gain_counts_that_fit_no_layout_fail() {
+        for len in [BAND_COUNT - 2, BAND_COUNT + 2, THIRD_OCTAVE_BAND_COUNT - 1] {
+            assert!(
+                EqSettings::for_gains(&vec![0.0; len]).is_err(),
+                "{len} gains"
+            );
+        }
+        let layouts = [BAND_COUNT, THIRD_OCTAVE_BAND_COUNT];
+        for len in layouts {
+            let settings = EqSettings::for_gains(&vec![1.0; len]).unwrap();
+            assert_eq!(settings.gains(), vec![1.0; len]);
+        }
+
+        let controls = EqControls::new(EqSettings::default());
+        assert!(controls.set_gains(&[3.0; BAND_COUNT - 2]).is_err());
+        assert!(controls.set_gains(&[3.0; BAND_COUNT + 2]).is_err());
+        assert_eq!(controls.gains(), DEFAULT_GAINS);
+        controls.set_gains(&[3.0; BAND_COUNT]).unwrap();
+        assert_eq!(controls.gains(), [3.0; BAND_COUNT]);
+    }
+
+    #[test]
+    fn each_channel_of_dual_mono_comes_out_as_mono_does() {
+        let mono = Equalizer::new(noise(7, 44_100, 1), DEFAULT_GAINS, None).collect::<Vec<_>>();
+        let stereo = Equalizer::new(noise(7, 44_100, 2), DEFAULT_GAINS, None).collect::<Vec<_>>();
+        assert_eq!(channel(&stereo, 2, 0), mono);
+        assert_eq!(channel(&stereo, 2, 1), mono);
+    }
+
+    #[test]
+    fn changes_from_other_threads_drop_and_repeat_no_samples() {
+        let input = collect(WhiteNoise::new(
+            11,
+            GeneratorSettings {
+                duration: Some(Duration::from_secs(2)),
+                ..second(44_100, 2)
+            },
+        ));
+        let len = input.clone().count();
+        let mut equalizer = Equalizer::new(input, DEFAULT_GAINS, None);
+        let controls = equalizer.controls();
+        let done = Arc::new(AtomicBool::new(false));
+        let changers = (0..4u32)
+            .map(|thread| {
+                let (controls, done) = (controls.clone(), done.clone());
+                thread::spawn(move || {
+                    let mut changes = 0u32;
+                    while !done.load(Ordering::Relaxed) {
+                        let gain = ((thread * 7 + changes) % 25) as f32 - 12.0;
+                        controls.set_gains(&[gain; BAND_COUNT]).unwrap();
+                        controls.frequency_response(0, 64);
+                        changes += 1;
+                    }
+                    changes
+                })
+            })
+            .collect::<Vec<_>>();
+        let output = equalizer.by_ref().collect::<Vec<_>>();
+        done.store(true, Ordering::Relaxed);
+        let changes = changers
+            .into_iter()
+            .map(|changer| changer.join().unwrap())
+            .sum::<u32>();
+
+        assert!(changes > 100, "only {changes} changes were made");
+        assert_eq!(output.len(), len);
+        // Both channels carry the same noise and take the same gains, so a
+        // sample dropped or played twice would set them apart from there on.
+        for (index, frame) in output.chunks(2).enumerate() {
+            assert_eq!(frame[0], frame[1], "frame {index}");
+        }
+    }
+
+    #[test]
+    fn a_seek_starts_the_filters_afresh() {
+        let input = sine(440.0, 44_100, 2);
+        let at = Duration::from_millis(250);
+        let mut played = Equalizer::new(input.clone(), DEFAULT_GAINS, None);
+        // Far enough in for the filters to be ringing with the signal.
+        played.by_ref().take(22_050).for_each(drop);
+        played.try_seek(at).unwrap();
+
+        let mut fresh = Equalizer::new(input, DEFAULT_GAINS, None);
+        fresh.try_seek(at).unwrap();
+        assert!(played.take(2048).eq(fresh.take(2048)));
+    }
+
+    #[test]
+    fn auto_headroom_keeps_a_boosted_full_scale_sine_below_0_dbfs() {
+        // The built-in curve's biggest boost, +8 dB at 64 Hz.
+        let full_scale = collect(SineWave::new(
+            64.0,
+            GeneratorSettings {
+                amplitude: 1.0,
+                ..second(44_100, 2)
+            },
+        ));
+        let play = |auto_headroom| {
+            let settings = EqSettings {
+                auto_headroom,
+                ..EqSettings::default()
+            };
+            let wet = Equalizer::from_settings(full_scale.clone(), settings)
+                .unwrap()
+                .collect::<Vec<_>>();
+            peak(&wet[SETTLE..])
+        };
+        assert!(play(false) > 2.0, "the boost should clip without headroom");
+        let peak = play(true);
+        assert!(peak < 1.0, "peaked at {peak}");
+    }
+
+    #[test]
+    fn a_rebuilt_equalizer_keeps_the_gains_set_while_playing() {
+        let controls = Arc::new(EqControls::new(EqSettings::default()));
+        let mut playing = Equalizer::with_controls(sine(440.0, 44_100, 2), controls.clone());
+        playing.by_ref().take(4410).for_each(drop);
+        let gains = [-3.0, 2.0, 0.0, 1.5, -6.0, 4.0, 0.0, -1.0, 2.5, 3.0];
+        controls.set_gains(&gains).unwrap();
+        // Long enough for the change to glide all the way in.
+        playing.by_ref().take(8820).for_each(drop);
+
+        // A seek builds the track again on the same controls.
+        let rebuilt = Equalizer::with_controls(sine(440.0, 44_100, 2), controls.clone());
+        assert_eq!(controls.gains(), gains);
+        assert_eq!(rebuilt.settings.gains(), gains);
+        let (heard, rebuilt) = (
+            playing.frequency_response(0, 64),
+            rebuilt.frequency_response(0, 64),
+        );
+        for ((frequency, heard), (_, rebuilt)) in heard.into_iter().zip(rebuilt) {
+            assert!(
+                (heard - rebuilt).abs() < 1e-3,
+                "{frequency} Hz: {heard} dB playing, {rebuilt} dB rebuilt"
+            );
+        }
+    }
+
+    #[test]
+    fn a_drop_in_sample_rate_retunes_the_bands() {
+        let bands = [(1000.0, 6.0, DEFAULT_Q)];
+        let input = rodio::source::from_iter([sine(1000.0, 44_100, 1), sine(1000.0, 22_050, 1)]);
+        let mut equalizer = Equalizer::with_bands(input, &bands).unwrap();
+        let first = equalizer.by_ref().take(44_100).collect::<Vec<_>>();
+        let second = equalizer.by_ref().collect::<Vec<_>>();
+        assert_eq!(
+            equalizer.controls().sample_rate.load(Ordering::Relaxed),
+            22_050
+        );
+
+        // Still tuned for 44.1 kHz, the band would sit an octave low, at 500 Hz.
+        for (sample_rate, wet) in [(44_100, first), (22_050, second)] {
+            let dry = sine(1000.0, sample_rate, 1).collect::<Vec<_>>();
+            let gain = gain_db(&dry, &wet, SETTLE);
+            assert!((gain - 6.0).abs() < 0.1, "{sample_rate} Hz: {gain:.2} dB");
+        }
+        let (peak_frequency, _) = equalizer.frequency_response(0, 256).into_iter().fold(
+            (0.0, f32::MIN),
+            |peak, point| match point.1 > peak.1 {
+                true => point,
+                false => peak,
+            },
+        );
+        assert!(
+            (peak_frequency / 1000.0f32).log2().abs() < 0.05,
+            "peaks at {peak_frequency} Hz"
+        );
+    }
+}
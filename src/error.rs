@@ -0,0 +1,127 @@
+use crate::{
+    backend::Backend, chain::ChainError, http::CurlMissing, settings::EqError,
+    stdin::UnseekableSource,
+};
+use rodio::{decoder::DecoderError, source::SeekError};
+use std::{error::Error, fmt, io};
+
+/// Everything [`AudioPlayer`](crate::AudioPlayer) can fail with.
+#[derive(Debug)]
+pub enum PlayerError {
+    /// A file, stream or standard input couldn't be read.
+    Io(io::Error),
+    /// An `https://` URL, which is fetched through the system's `curl`,
+    /// with no `curl` installed.
+    CurlMissing,
+    /// The audio couldn't be decoded, e.g. because the format isn't supported.
+    Decode(DecoderError),
+    /// No output device could be opened, or it refused the stream.
+    Device(String),
+    /// The audio backend asked for isn't built in, or can't be reached,
+    /// such as JACK without a server running.
+    Backend {
+        requested: Backend,
+        available: Vec<Backend>,
+    },
+    /// The source can only be read once, like standard input, so it can't
+    /// seek or play again.
+    UnsupportedSeek,
+    /// The track is a live stream, such as internet radio, which has no
+    /// position to seek to.
+    LiveStream,
+    /// The decoder failed to seek.
+    Seek(SeekError),
+    /// EQ settings or a band that would make the filters unstable.
+    InvalidEq(EqError),
+    /// An effects chain with a stage set up out of range; see
+    /// [`AudioPlayer::apply_chain`](crate::AudioPlayer::apply_chain).
+    InvalidChain(ChainError),
+    /// A cue sheet or playlist with nothing that can be played.
+    NothingToPlay(String),
+    /// An argument out of range, such as a loop that ends before it starts,
+    /// or a call that needs a track when none is queued.
+    InvalidArgument(String),
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerError::Io(err) => write!(f, "{err}"),
+            PlayerError::CurlMissing => write!(f, "{CurlMissing}"),
+            PlayerError::Decode(err) => write!(f, "can't decode the audio: {err}"),
+            PlayerError::Device(message) => write!(f, "audio output failed: {message}"),
+            PlayerError::Backend {
+                requested,
+                available,
+            } => {
+                let available = available
+                    .iter()
+                    .copied()
+                    .map(Backend::name)
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "the {requested} audio backend isn't available; available: {}",
+                    available.join(", ")
+                )
+            }
+            PlayerError::UnsupportedSeek => write!(f, "{UnseekableSource}"),
+            PlayerError::LiveStream => write!(f, "the stream is live, so it can't seek"),
+            PlayerError::Seek(err) => write!(f, "seek failed: {err}"),
+            PlayerError::InvalidEq(err) => write!(f, "{err}"),
+            PlayerError::InvalidChain(err) => write!(f, "{err}"),
+            PlayerError::NothingToPlay(message) | PlayerError::InvalidArgument(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+impl Error for PlayerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PlayerError::Io(err) => Some(err),
+            PlayerError::Decode(err) => Some(err),
+            PlayerError::Seek(err) => Some(err),
+            PlayerError::InvalidEq(err) => Some(err),
+            PlayerError::InvalidChain(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PlayerError {
+    fn from(err: io::Error) -> Self {
+        match err.get_ref().is_some_and(|inner| inner.is::<CurlMissing>()) {
+            true => PlayerError::CurlMissing,
+            false => PlayerError::Io(err),
+        }
+    }
+}
+
+impl From<DecoderError> for PlayerError {
+    fn from(err: DecoderError) -> Self {
+        PlayerError::Decode(err)
+    }
+}
+
+impl From<SeekError> for PlayerError {
+    fn from(err: SeekError) -> Self {
+        match err {
+            SeekError::NotSupported { .. } => PlayerError::UnsupportedSeek,
+            err => PlayerError::Seek(err),
+        }
+    }
+}
+
+impl From<EqError> for PlayerError {
+    fn from(err: EqError) -> Self {
+        PlayerError::InvalidEq(err)
+    }
+}
+
+impl From<ChainError> for PlayerError {
+    fn from(err: ChainError) -> Self {
+        PlayerError::InvalidChain(err)
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    ab::AB, buffer::PlaybackState, clock::Clock, lock::Lock, profile::EqProfile,
+    scrobble::ScrobbleControls, suspend::Suspend,
+};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How far past a cue track boundary or scrobble threshold the event thread
+/// wakes to report it.
+const CUE_SLACK: Duration = Duration::from_millis(2);
+
+/// Something that happened during playback, delivered through [`AudioPlayer::subscribe`].
+///
+/// [`AudioPlayer::subscribe`]: crate::AudioPlayer::subscribe
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerEvent {
+    TrackStarted(PathBuf),
+    /// Sent at the progress interval while playing.
+    Progress(Duration),
+    Paused,
+    Resumed,
+    /// A seek to this position was asked for; [`PlayerEvent::Seeked`]
+    /// follows once playback is there. Seeks replaced by a later one before
+    /// they started only get this.
+    Seeking(Duration),
+    Seeked(Duration),
+    /// The track's source ran out, as opposed to being skipped or stopped.
+    /// In a file split by a cue sheet, also sent with the file's path when
+    /// one of the sheet's tracks plays into the next, followed by
+    /// [`PlayerEvent::TrackStarted`].
+    TrackEnded(PathBuf),
+    /// The track has played long enough to count as listened to; see
+    /// [`AudioPlayer::set_scrobble_threshold`]. Sent once per playthrough,
+    /// so again for each repeat.
+    ///
+    /// [`AudioPlayer::set_scrobble_threshold`]: crate::AudioPlayer::set_scrobble_threshold
+    TrackPlayed {
+        path: PathBuf,
+        /// How long it actually played, seeks left out.
+        played: Duration,
+        duration: Duration,
+    },
+    /// Something was added to, removed from or moved within the queue; see
+    /// [`AudioPlayer::queue`].
+    ///
+    /// [`AudioPlayer::queue`]: crate::AudioPlayer::queue
+    QueueChanged,
+    /// Playback moved into the chapter at this index of
+    /// [`AudioPlayer::chapters`], by playing on, seeking or starting a track.
+    ///
+    /// [`AudioPlayer::chapters`]: crate::AudioPlayer::chapters
+    ChapterChanged(usize),
+    /// Playback moved to another output device, named here.
+    DeviceChanged(String),
+    /// The secondary output device, named here, went away; the primary
+    /// output plays on. See [`AudioEngine::add_secondary_output`].
+    ///
+    /// [`AudioEngine::add_secondary_output`]: crate::AudioEngine::add_secondary_output
+    SecondaryOutputLost(String),
+    /// Silence from `from` to `to` in the track was skipped; see
+    /// [`AudioPlayer::set_skip_silence`].
+    ///
+    /// [`AudioPlayer::set_skip_silence`]: crate::AudioPlayer::set_skip_silence
+    SilenceSkipped {
+        from: Duration,
+        to: Duration,
+    },
+    /// Decoding the file failed at `position`, for the reason in `detail`,
+    /// which also says whether playback went on past the damage; see
+    /// [`AudioPlayer::set_strict_decoding`].
+    ///
+    /// [`AudioPlayer::set_strict_decoding`]: crate::AudioPlayer::set_strict_decoding
+    DecodeError {
+        position: Duration,
+        detail: String,
+    },
+    /// The track that just started plays `gain_db` louder by its
+    /// ReplayGain tags; see [`AudioPlayer::set_replaygain`].
+    ///
+    /// [`AudioPlayer::set_replaygain`]: crate::AudioPlayer::set_replaygain
+    ReplayGainApplied {
+        gain_db: f32,
+    },
+    /// A live stream's now-playing text changed, as its `StreamTitle`
+    /// metadata reached playback.
+    StreamTitleChanged(String),
+    /// What [`AudioPlayer::now_playing`] shows changed with more than the
+    /// position playing on: a track started, a live stream's now-playing
+    /// text came in, or a seek landed. Follows the event saying which.
+    ///
+    /// [`AudioPlayer::now_playing`]: crate::AudioPlayer::now_playing
+    NowPlayingChanged,
+    /// A live stream's connection dropped, and this is try `attempt` at
+    /// making it again.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// The output fell `gap` behind while playing, as when the device ran
+    /// out of audio, which is heard as a stutter. Counted in
+    /// [`PlayerStats::underruns`](crate::PlayerStats::underruns).
+    Underrun {
+        gap: Duration,
+    },
+    /// A network stream ran dry, or a file fell below the low watermark of
+    /// what is decoded ahead; silence plays until [`PlayerEvent::Buffered`].
+    /// See [`AudioPlayer::set_buffer_watermarks`].
+    ///
+    /// [`AudioPlayer::set_buffer_watermarks`]: crate::AudioPlayer::set_buffer_watermarks
+    Buffering,
+    Buffered,
+    /// Playback went into this state; see [`AudioPlayer::playback_state`].
+    ///
+    /// [`AudioPlayer::playback_state`]: crate::AudioPlayer::playback_state
+    StateChanged(PlaybackState),
+    /// A start [`AudioPlayer::play_at`] scheduled for `requested` was heard
+    /// at `heard`, by the device's reckoning; `error_secs` is how late, or
+    /// negative, how early.
+    ///
+    /// [`AudioPlayer::play_at`]: crate::AudioPlayer::play_at
+    ScheduledStart {
+        requested: Instant,
+        heard: Instant,
+        error_secs: f64,
+    },
+    /// The system was suspended for about this long. Playback held where
+    /// it was, and the output device was checked on waking, reopening it
+    /// if it went away; [`PlayerEvent::Resumed`] follows if it was playing.
+    Suspended(Duration),
+    /// The sleep timer went off; its action follows.
+    SleepTimerFired,
+    /// The file [`AudioPlayer::watch_eq_file`] watches changed, and the EQ
+    /// plays its new settings.
+    ///
+    /// [`AudioPlayer::watch_eq_file`]: crate::AudioPlayer::watch_eq_file
+    EqReloaded,
+    /// The watched EQ file changed but couldn't be read, for this reason;
+    /// the EQ keeps the settings it had.
+    EqReloadFailed(String),
+    /// Playback moved to a device with another EQ profile than the last,
+    /// or the profiles changed, and the EQ plays this one; see
+    /// [`AudioPlayer::set_device_profile`].
+    ///
+    /// [`AudioPlayer::set_device_profile`]: crate::AudioPlayer::set_device_profile
+    EqProfileChanged(EqProfile),
+    /// A file [`AudioPlayer::watch_folder`] queued was deleted, and taken
+    /// off the queue.
+    ///
+    /// [`AudioPlayer::watch_folder`]: crate::AudioPlayer::watch_folder
+    WatchedFileDeleted(PathBuf),
+    /// [`AudioPlayer::toggle_ab`] switched the EQ to side `label` at `at`,
+    /// `position` into the track.
+    ///
+    /// [`AudioPlayer::toggle_ab`]: crate::AudioPlayer::toggle_ab
+    AbToggled {
+        label: AB,
+        at: SystemTime,
+        position: Duration,
+    },
+    /// Something was skipped, such as a playlist entry that couldn't be opened.
+    Warning(String),
+    Error(String),
+}
+
+/// What the player and the audio thread report to the event thread.
+pub(crate) enum Signal {
+    Started(u64),
+    Ended(u64),
+    Event(PlayerEvent),
+    /// The output found the system was suspended.
+    Suspended(Suspend),
+}
+
+/// Fans events out to subscribers from a thread of its own, so neither the
+/// audio thread nor the player ever waits on a slow receiver.
+pub(crate) struct Events {
+    signals: Sender<Signal>,
+    subscribers: Arc<Mutex<Vec<Sender<PlayerEvent>>>>,
+    interval_ns: Arc<AtomicU64>,
+}
+
+impl Events {
+    /// For parts of the chain that report events from the audio side.
+    pub(crate) fn signals(&self) -> Sender<Signal> {
+        self.signals.clone()
+    }
+
+    /// The thread exits once `clock` is gone. `on_started` runs on it each
+    /// time a track starts, before subscribers hear about it.
+    pub(crate) fn spawn(
+        signals: Sender<Signal>,
+        receiver: Receiver<Signal>,
+        clock: Weak<Clock>,
+        scrobble: Arc<ScrobbleControls>,
+        state: impl Fn() -> PlaybackState + Send + 'static,
+        on_started: impl Fn(u64) + Send + 'static,
+    ) -> Self {
+        let events = Events {
+            signals,
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            interval_ns: Arc::new(AtomicU64::new(DEFAULT_PROGRESS_INTERVAL.as_nanos() as u64)),
+        };
+        let subscribers = events.subscribers.clone();
+        let interval_ns = events.interval_ns.clone();
+        thread::spawn(move || {
+            let mut next_progress = Instant::now();
+            let mut last_state = state();
+            loop {
+                let mut resumed = false;
+                let mut timeout = next_progress.saturating_duration_since(Instant::now());
+                if let Some(boundary) = clock.upgrade().and_then(|clock| clock.until_cue_boundary())
+                {
+                    // Wake just past the next cue track's start to report it on time.
+                    timeout = timeout.min(boundary + CUE_SLACK);
+                }
+                if let Some(boundary) = clock
+                    .upgrade()
+                    .and_then(|clock| clock.until_chapter_boundary())
+                {
+                    timeout = timeout.min(boundary + CUE_SLACK);
+                }
+                if let Some(due) = clock.upgrade().and_then(|clock| scrobble.until_due(&clock)) {
+                    timeout = timeout.min(due + CUE_SLACK);
+                }
+                let event = match receiver.recv_timeout(timeout) {
+                    Ok(Signal::Event(event)) => Some(event),
+                    Ok(Signal::Started(id)) => {
+                        on_started(id);
+                        path_of(&clock, id).map(PlayerEvent::TrackStarted)
+                    }
+                    Ok(Signal::Ended(id)) => path_of(&clock, id).map(PlayerEvent::TrackEnded),
+                    Ok(Signal::Suspended(suspend)) => {
+                        let Some(clock) = clock.upgrade() else {
+                            return;
+                        };
+                        clock.suspended(suspend.counted);
+                        resumed = clock.is_playing();
+                        Some(PlayerEvent::Suspended(suspend.length))
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        let Some(clock) = clock.upgrade() else {
+                            return;
+                        };
+                        next_progress = Instant::now()
+                            + Duration::from_nanos(interval_ns.load(Ordering::Relaxed));
+                        clock
+                            .is_playing()
+                            .then(|| PlayerEvent::Progress(clock.position()))
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+                let mut events = Vec::from_iter(event);
+                if resumed {
+                    events.push(PlayerEvent::Resumed);
+                }
+                if let Some(clock) = clock.upgrade() {
+                    if let Some(path) = clock.cue_advanced() {
+                        events.push(PlayerEvent::TrackEnded(path.clone()));
+                        events.push(PlayerEvent::TrackStarted(path));
+                    }
+                    events.extend(clock.chapter_changed().map(PlayerEvent::ChapterChanged));
+                    events.extend(scrobble.check(&clock));
+                }
+                let changed = events.iter().any(|event| {
+                    matches!(
+                        event,
+                        PlayerEvent::TrackStarted(_)
+                            | PlayerEvent::StreamTitleChanged(_)
+                            | PlayerEvent::Seeked(_)
+                    )
+                });
+                if changed {
+                    events.push(PlayerEvent::NowPlayingChanged);
+                }
+                let state = state();
+                if state != last_state {
+                    last_state = state;
+                    events.push(PlayerEvent::StateChanged(state));
+                }
+                for event in events {
+                    subscribers
+                        .locked()
+                        .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+                }
+            }
+        });
+        events
+    }
+
+    pub(crate) fn emit(&self, event: PlayerEvent) {
+        let _ = self.signals.send(Signal::Event(event));
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<PlayerEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.locked().push(sender);
+        receiver
+    }
+
+    pub(crate) fn progress_interval(&self) -> Duration {
+        Duration::from_nanos(self.interval_ns.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_progress_interval(&self, interval: Duration) {
+        self.interval_ns
+            .store(interval.as_nanos().max(1) as u64, Ordering::Relaxed);
+    }
+}
+
+fn path_of(clock: &Weak<Clock>, id: u64) -> Option<PathBuf> {
+    clock.upgrade()?.track(id).map(|track| track.path)
+}
@@ -0,0 +1,500 @@
+//! The subset of TOML needed for settings files: tables, arrays of tables,
+//! dotted keys, strings, numbers, booleans, arrays and inline tables.
+//! Dates and times are not supported.
+
+use super::{ParseError, Value};
+use std::fmt::Write;
+
+pub(crate) fn parse(input: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    let mut root = Value::table();
+    let mut current: Vec<String> = Vec::new();
+
+    loop {
+        parser.skip_blank();
+        match parser.peek() {
+            None => return Ok(root),
+            Some('[') => {
+                parser.bump();
+                let array = parser.peek() == Some('[');
+                if array {
+                    parser.bump();
+                }
+                parser.skip_spaces();
+                let path = parser.key_path()?;
+                parser.skip_spaces();
+                parser.expect(']')?;
+                if array {
+                    parser.expect(']')?;
+                }
+                let line = parser.line;
+                parser.end_of_line()?;
+
+                if array {
+                    let (last, parents) = path.split_last().expect("key path is never empty");
+                    let parent =
+                        table_at(&mut root, parents).map_err(|m| ParseError::new(line, m))?;
+                    match entry(parent, last) {
+                        Some(Value::Array(items)) => items.push(Value::table()),
+                        Some(_) => {
+                            return Err(ParseError::new(
+                                line,
+                                format!("'{last}' is not an array of tables"),
+                            ))
+                        }
+                        None => parent.push((last.clone(), Value::Array(vec![Value::table()]))),
+                    }
+                } else {
+                    table_at(&mut root, &path).map_err(|m| ParseError::new(line, m))?;
+                }
+                current = path;
+            }
+            Some(_) => {
+                let path = parser.key_path()?;
+                parser.skip_spaces();
+                parser.expect('=')?;
+                parser.skip_spaces();
+                let value = parser.value()?;
+                let line = parser.line;
+                parser.end_of_line()?;
+
+                let (last, parents) = path.split_last().expect("key path is never empty");
+                let full: Vec<String> = current.iter().chain(parents).cloned().collect();
+                let table = table_at(&mut root, &full).map_err(|m| ParseError::new(line, m))?;
+                if entry(table, last).is_some() {
+                    return Err(ParseError::new(line, format!("duplicate key '{last}'")));
+                }
+                table.push((last.clone(), value));
+            }
+        }
+    }
+}
+
+/// The line `key` is set on under the `[table]` header, for reporting a
+/// value that parsed but doesn't make sense. Only plain, top-level headers
+/// are followed; `table` is empty for keys before the first header.
+pub(crate) fn key_line(input: &str, table: &str, key: &str) -> Option<usize> {
+    let mut current = "";
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.split(']').next())
+        {
+            current = header.trim();
+        } else if let Some((name, _)) = line.split_once('=') {
+            if current == table && name.trim().trim_matches('"') == key {
+                return Some(index + 1);
+            }
+        }
+    }
+    None
+}
+
+fn entry<'a>(table: &'a mut [(String, Value)], key: &str) -> Option<&'a mut Value> {
+    table.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Walks (and creates) nested tables along `path`. Arrays of tables resolve
+/// to their last element, as in `[[fruit]]` followed by `[fruit.color]`.
+fn table_at<'a>(
+    root: &'a mut Value,
+    path: &[String],
+) -> Result<&'a mut Vec<(String, Value)>, String> {
+    let mut node = root;
+    for key in path {
+        let Value::Table(entries) = node else {
+            return Err(format!("'{key}' is not inside a table"));
+        };
+        if entry(entries, key).is_none() {
+            entries.push((key.clone(), Value::table()));
+        }
+        node = entry(entries, key).expect("entry was just inserted");
+        if let Value::Array(items) = node {
+            node = items
+                .last_mut()
+                .ok_or_else(|| format!("'{key}' is an empty array"))?;
+        }
+    }
+    match node {
+        Value::Table(entries) => Ok(entries),
+        _ => Err("key is already defined as a value".to_string()),
+    }
+}
+
+pub(crate) fn to_string(value: &Value) -> String {
+    let mut out = String::new();
+    if let Value::Table(entries) = value {
+        write_table(&mut out, &[], entries);
+    }
+    out
+}
+
+fn is_table_array(value: &Value) -> bool {
+    matches!(value, Value::Array(items) if !items.is_empty() && items.iter().all(|v| matches!(v, Value::Table(_))))
+}
+
+fn write_table(out: &mut String, path: &[String], entries: &[(String, Value)]) {
+    for (key, value) in entries {
+        if matches!(value, Value::Table(_) | Value::Null) || is_table_array(value) {
+            continue;
+        }
+        let _ = writeln!(out, "{} = {}", format_key(key), inline(value));
+    }
+
+    for (key, value) in entries {
+        let mut child = path.to_vec();
+        child.push(key.clone());
+        let header = child
+            .iter()
+            .map(|k| format_key(k))
+            .collect::<Vec<_>>()
+            .join(".");
+        match value {
+            Value::Table(sub) => {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let _ = writeln!(out, "[{header}]");
+                write_table(out, &child, sub);
+            }
+            Value::Array(items) if is_table_array(value) => {
+                for item in items {
+                    if !out.is_empty() {
+                        out.push('\n');
+                    }
+                    let _ = writeln!(out, "[[{header}]]");
+                    if let Value::Table(sub) = item {
+                        write_table(out, &child, sub);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn format_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Null => "\"\"".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) if n.is_nan() => "nan".to_string(),
+        Value::Number(n) if n.is_infinite() => if *n > 0.0 { "inf" } else { "-inf" }.to_string(),
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => format!("{n:.1}"),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => quote(s),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(inline).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Table(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .filter(|(_, v)| !matches!(v, Value::Null))
+                .map(|(k, v)| format!("{} = {}", format_key(k), inline(v)))
+                .collect();
+            if entries.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", entries.join(", "))
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError::new(self.line, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("expected '{expected}', found '{c}'"))),
+            None => Err(self.error(format!("expected '{expected}', found end of input"))),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    /// Skips whitespace, newlines and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some('\n' | '\r') => {
+                    self.bump();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_spaces();
+        self.skip_comment();
+        match self.peek() {
+            None => Ok(()),
+            Some('\r') | Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("unexpected '{c}' after value"))),
+        }
+    }
+
+    fn key_path(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut path = vec![self.key()?];
+        loop {
+            self.skip_spaces();
+            if self.peek() != Some('.') {
+                return Ok(path);
+            }
+            self.bump();
+            self.skip_spaces();
+            path.push(self.key()?);
+        }
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    self.bump();
+                }
+                if start == self.pos {
+                    return Err(self.error("expected a key"));
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => self.bare_value(),
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    fn bare_value(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+' | '.'))
+        {
+            self.bump();
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.as_str() {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            "inf" | "+inf" => return Ok(Value::Number(f64::INFINITY)),
+            "-inf" => return Ok(Value::Number(f64::NEG_INFINITY)),
+            "nan" | "+nan" | "-nan" => return Ok(Value::Number(f64::NAN)),
+            _ => {}
+        }
+        let digits = text.replace('_', "");
+        let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).map(|n| n as f64).ok()
+        } else {
+            digits.parse::<f64>().ok()
+        };
+        parsed
+            .map(Value::Number)
+            .ok_or_else(|| self.error(format!("invalid value '{text}'")))
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let multiline = self.peek() == Some('"') && self.chars.get(self.pos + 1) == Some(&'"');
+        if multiline {
+            self.bump();
+            self.bump();
+            if self.peek() == Some('\n') {
+                self.bump();
+            }
+        }
+
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                Some('"') if !multiline => return Ok(out),
+                Some('"')
+                    if self.peek() == Some('"') && self.chars.get(self.pos + 1) == Some(&'"') =>
+                {
+                    self.bump();
+                    self.bump();
+                    return Ok(out);
+                }
+                Some('\\') => match self.bump() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some(kind @ ('u' | 'U')) => {
+                        let len = if kind == 'u' { 4 } else { 8 };
+                        let digits: String = (0..len).filter_map(|_| self.bump()).collect();
+                        let c = u32::from_str_radix(&digits, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error("invalid unicode escape"))?;
+                        out.push(c);
+                    }
+                    Some('\n') if multiline => {
+                        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+                            self.bump();
+                        }
+                    }
+                    _ => return Err(self.error("invalid escape sequence")),
+                },
+                Some('\n') if !multiline => return Err(self.error("unterminated string")),
+                Some(c) => out.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.expect('\'')?;
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                Some('\'') => return Ok(out),
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_blank();
+            match self.bump() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, ParseError> {
+        self.expect('{')?;
+        let mut table = Value::table();
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(table);
+        }
+        loop {
+            self.skip_spaces();
+            let path = self.key_path()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+
+            let line = self.line;
+            let (last, parents) = path.split_last().expect("key path is never empty");
+            let target = table_at(&mut table, parents).map_err(|m| ParseError::new(line, m))?;
+            if entry(target, last).is_some() {
+                return Err(self.error(format!("duplicate key '{last}'")));
+            }
+            target.push((last.clone(), value));
+
+            self.skip_spaces();
+            match self.bump() {
+                Some(',') => continue,
+                Some('}') => return Ok(table),
+                _ => return Err(self.error("expected ',' or '}' in inline table")),
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::atomic::AtomicF32;
+use rodio::{source::SeekError, Source};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+pub fn linear_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
+
+/// Target level for a [`Gain`] stage, adjustable while it plays.
+pub struct GainControls {
+    target: AtomicF32,
+    ramp_ns: AtomicU64,
+}
+
+impl GainControls {
+    pub fn new(gain: f32, ramp: Duration) -> Self {
+        GainControls {
+            target: AtomicF32::new(gain),
+            ramp_ns: AtomicU64::new(ramp.as_nanos() as u64),
+        }
+    }
+
+    pub fn ramp(&self) -> Duration {
+        Duration::from_nanos(self.ramp_ns.load(Ordering::Relaxed))
+    }
+
+    /// Sets how long later target changes take; a ramp already running keeps its pace.
+    pub fn set_ramp(&self, ramp: Duration) {
+        self.ramp_ns
+            .store(ramp.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target.load()
+    }
+
+    pub fn set_target(&self, gain: f32) {
+        self.target.store(gain);
+    }
+}
+
+/// Applies a linear gain, ramping towards new targets instead of jumping.
+pub struct Gain<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<GainControls>,
+    current: f32,
+    ramp_target: f32,
+    step: f32,
+    channel: u16,
+    channels: u16,
+    /// The level handed over from the stage before and on to the next; see
+    /// [`Gain::handing_over`].
+    handover: Option<Arc<AtomicF32>>,
+    started: bool,
+}
+
+impl<S> Gain<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, controls: Arc<GainControls>) -> Self {
+        let current = controls.target();
+        Gain {
+            source,
+            controls,
+            current,
+            ramp_target: current,
+            step: 0.0,
+            channel: 0,
+            channels: 1,
+            handover: None,
+            started: false,
+        }
+    }
+
+    /// Starts from the gain last left in `level`, as by the stage of the
+    /// track before, ramping from there to this stage's target, and leaves
+    /// its own gain there as it plays. A `level` never set starts at the
+    /// target.
+    pub(crate) fn handing_over(mut self, level: Arc<AtomicF32>) -> Self {
+        self.handover = Some(level);
+        self
+    }
+
+    pub fn controls(&self) -> Arc<GainControls> {
+        self.controls.clone()
+    }
+
+    fn advance_ramp(&mut self) {
+        let target = self.controls.target();
+        if target != self.ramp_target {
+            let frames = self.controls.ramp().as_secs_f32() * self.source.sample_rate() as f32;
+            self.step = (target - self.current) / frames.max(1.0);
+            self.ramp_target = target;
+        }
+
+        if self.current != target {
+            let next = self.current + self.step;
+            let overshot =
+                (self.step >= 0.0 && next >= target) || (self.step < 0.0 && next <= target);
+            self.current = if overshot { target } else { next };
+        }
+    }
+}
+
+impl<S> Iterator for Gain<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.channels = self.source.channels().max(1);
+            if let Some(level) = self.handover.as_ref().filter(|_| !self.started) {
+                let last = level.load();
+                if last.is_finite() && last >= 0.0 {
+                    self.current = last;
+                    // Ramps to the target from here.
+                    self.ramp_target = f32::NAN;
+                }
+            }
+            self.started = true;
+            self.advance_ramp();
+            if let Some(level) = &self.handover {
+                level.store(self.current);
+            }
+        }
+        let sample = self.source.next()?;
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample * self.current)
+    }
+}
+
+impl<S> Source for Gain<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.channel = 0;
+        Ok(())
+    }
+}
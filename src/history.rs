@@ -0,0 +1,217 @@
+use crate::{
+    bookmark::{data_dir, seconds},
+    format::{json, Value},
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How many entries a [`History`] keeps unless told otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 1000;
+
+/// One track off the playback history, or the last run of plays of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// When it last counted as played.
+    pub played_at: SystemTime,
+    /// How long it actually played the last time, seeks left out.
+    pub played: Duration,
+    /// Times in a row it was played, as by repeating it.
+    pub plays: u32,
+    /// Whether the file is gone since.
+    pub missing: bool,
+}
+
+/// The tracks played, newest last, each once it crossed the scrobble
+/// threshold. Kept as JSON in a file of its own, up to a limit of entries,
+/// the oldest dropped past it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    path: PathBuf,
+    entries: Vec<HistoryEntry>,
+    limit: usize,
+}
+
+impl History {
+    /// `history.json` in the user's data directory, next to the bookmarks;
+    /// see [`Bookmarks::default_path`](crate::Bookmarks::default_path).
+    pub fn default_path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join("history.json"))
+    }
+
+    /// Reads the history saved at `path`, keeping up to
+    /// [`DEFAULT_HISTORY_LIMIT`] entries. A missing file is no history.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let mut history = History {
+            path,
+            entries: Vec::new(),
+            limit: DEFAULT_HISTORY_LIMIT,
+        };
+        if text.trim().is_empty() {
+            return Ok(history);
+        }
+        let value =
+            json::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let entries = value
+            .get("history")
+            .and_then(Value::as_array)
+            .unwrap_or_default();
+        // Entries that don't parse are dropped rather than failing the lot.
+        history.entries = entries
+            .iter()
+            .filter_map(HistoryEntry::from_value)
+            .collect();
+        history.trim();
+        Ok(history)
+    }
+
+    /// Where [`History::save`] writes.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Keeps no more than `limit` entries, at least one, dropping the oldest.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(1);
+        self.trim();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Up to `limit` entries, newest first, those whose file is gone
+    /// flagged [`HistoryEntry::missing`].
+    pub fn recent(&self, limit: usize) -> Vec<HistoryEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|entry| HistoryEntry {
+                missing: !entry.path.exists(),
+                ..entry.clone()
+            })
+            .collect()
+    }
+
+    /// The entry `index` places back from the newest, as
+    /// [`History::recent`] counts.
+    pub fn get(&self, index: usize) -> Option<HistoryEntry> {
+        self.recent(index + 1).into_iter().nth(index)
+    }
+
+    /// Adds a play of `path`, `played` long so far. Another play of the
+    /// newest entry's track counts towards that entry instead.
+    pub fn record(
+        &mut self,
+        path: impl Into<PathBuf>,
+        title: Option<String>,
+        artist: Option<String>,
+        played: Duration,
+    ) {
+        let path = path.into();
+        let played_at = SystemTime::now();
+        match self.entries.last_mut() {
+            Some(last) if last.path == path => {
+                last.plays += 1;
+                last.played_at = played_at;
+                last.played = played;
+                last.title = title.or(last.title.take());
+                last.artist = artist.or(last.artist.take());
+            }
+            _ => self.entries.push(HistoryEntry {
+                path,
+                title,
+                artist,
+                played_at,
+                played,
+                plays: 1,
+                missing: false,
+            }),
+        }
+        self.trim();
+    }
+
+    /// Sets how long the newest entry has played, as it plays on past the
+    /// threshold.
+    pub fn set_played(&mut self, played: Duration) {
+        if let Some(last) = self.entries.last_mut() {
+            last.played = played;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Writes the history back to [`History::path`], creating its directory.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let entries = self
+            .entries
+            .iter()
+            .map(HistoryEntry::to_value)
+            .collect::<Vec<_>>();
+        fs::write(
+            &self.path,
+            json::to_string(&Value::table().with("history", entries)),
+        )
+    }
+
+    fn trim(&mut self) {
+        let over = self.entries.len().saturating_sub(self.limit);
+        self.entries.drain(..over);
+    }
+}
+
+impl HistoryEntry {
+    fn to_value(&self) -> Value {
+        let mut value = Value::table()
+            .with("path", self.path.to_string_lossy().into_owned())
+            .with("played_at", seconds(self.played_at))
+            .with("played", self.played.as_secs_f64())
+            .with("plays", u64::from(self.plays));
+        if let Some(title) = &self.title {
+            value = value.with("title", title.as_str());
+        }
+        if let Some(artist) = &self.artist {
+            value = value.with("artist", artist.as_str());
+        }
+        value
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        let number = |key| value.get(key).and_then(Value::as_f64);
+        let text = |key| Some(value.get(key)?.as_str()?.to_string());
+        Some(HistoryEntry {
+            path: PathBuf::from(value.get("path")?.as_str()?),
+            title: text("title"),
+            artist: text("artist"),
+            played_at: UNIX_EPOCH + Duration::try_from_secs_f64(number("played_at")?).ok()?,
+            played: Duration::try_from_secs_f64(number("played")?).ok()?,
+            plays: number("plays").map_or(1, |plays| plays.max(1.0) as u32),
+            missing: false,
+        })
+    }
+}
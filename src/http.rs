@@ -0,0 +1,1214 @@
+//! Playback from `http://` and `https://` URLs.
+//!
+//! Plain HTTP is spoken here over a socket. HTTPS is handed to the system's
+//! `curl`, which does the TLS and certificate checks and passes the
+//! response through untouched, so the two read the same from there on. No
+//! TLS crate is built in, so without `curl` on the `PATH` an `https://` URL
+//! fails with [`PlayerError::CurlMissing`](crate::PlayerError::CurlMissing).
+//!
+//! A [`Download`] fetches the resource on a thread of its own and keeps
+//! everything received, so re-opening the track after a skip or seek reads
+//! from memory. Decoding happens on another thread ahead of playback, so a
+//! slow network stalls that thread instead of the audio one.
+//!
+//! Internet radio, a live stream answered with `icy-` headers, is handled
+//! apart: only the last few megabytes are kept, the `StreamTitle` carried in
+//! the stream's metadata blocks is reported as it changes, and a dropped
+//! connection is made again with backoff while the decoder carries on.
+
+use crate::{
+    events::{PlayerEvent, Signal},
+    lock::Lock,
+    probe::{duration_from_header, mp3_duration, HEADER_BYTES},
+};
+use rodio::{source::SeekError, Decoder, Sample, Source};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::TcpStream,
+    process::{Child, ChildStderr, ChildStdout, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender, TryRecvError},
+        Arc, Condvar, Mutex, PoisonError, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Bytes that must have arrived before a stream starts playing.
+pub const DEFAULT_PREFETCH: usize = 256 * 1024;
+
+const MAX_REDIRECTS: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A read this far past the downloaded data starts a new ranged request
+/// there, when the server allows, instead of waiting for the download.
+const RESTART_DISTANCE: u64 = 512 * 1024;
+
+/// Frames per decoded chunk, and how many chunks are decoded ahead.
+const CHUNK_FRAMES: usize = 2048;
+const CHUNKS_AHEAD: usize = 64;
+
+/// Bytes a live stream waits for in place of the prefetch, so a station
+/// starts playing within a second or two.
+const LIVE_PREFETCH: usize = 32 * 1024;
+
+/// Most of a live stream kept in memory; a reader that falls further
+/// behind, as while paused, skips ahead to what is left.
+const LIVE_BUFFER: usize = 4 * 1024 * 1024;
+
+/// Wait before getting a dropped live stream back, doubled after each try
+/// that fails up to the maximum, and how many tries before it ends.
+const RECONNECT_DELAY: Duration = Duration::from_millis(250);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const MAX_RECONNECTS: u32 = 10;
+
+struct Url {
+    https: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> io::Result<Self> {
+        let (https, rest) = match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+            (Some(rest), _) => (false, rest),
+            (_, Some(rest)) => (true, rest),
+            _ => return Err(invalid(format!("not an http:// or https:// URL: {url}"))),
+        };
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| invalid(format!("invalid port in {url}")))?,
+            ),
+            None => (authority, default_port(https)),
+        };
+        if host.is_empty() {
+            return Err(invalid(format!("no host in {url}")));
+        }
+        Ok(Url {
+            https,
+            host: host.to_string(),
+            port,
+            path: if path.is_empty() { "/" } else { path }.to_string(),
+        })
+    }
+
+    /// Resolves a `Location` header against this URL.
+    fn join(&self, location: &str) -> io::Result<Self> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            return Url::parse(location);
+        }
+        let path = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            let base = &self.path[..self.path.rfind('/').map_or(0, |index| index + 1)];
+            format!("{base}{location}")
+        };
+        Ok(Url {
+            https: self.https,
+            host: self.host.clone(),
+            port: self.port,
+            path,
+        })
+    }
+}
+
+fn default_port(https: bool) -> u16 {
+    match https {
+        true => 443,
+        false => 80,
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+fn unsupported(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.into())
+}
+
+/// Response headers plus the body, with chunked transfer encoding undone.
+struct Response {
+    status: u16,
+    content_length: Option<u64>,
+    accepts_ranges: bool,
+    location: Option<String>,
+    /// Audio bytes between metadata blocks, when the server interleaves them.
+    icy_metaint: Option<usize>,
+    icy_name: Option<String>,
+    /// Whether any `icy-` header came back, marking a live stream.
+    live: bool,
+    body: Body,
+}
+
+/// The request headers but `Host`, from byte `from` on.
+fn headers(from: u64) -> Vec<String> {
+    let mut headers = [
+        "User-Agent: fullyrustaudio",
+        "Accept: */*",
+        "Icy-MetaData: 1",
+        "Connection: close",
+    ]
+    .map(String::from)
+    .to_vec();
+    if from > 0 {
+        headers.push(format!("Range: bytes={from}-"));
+    }
+    headers
+}
+
+/// Sends the request over a socket of its own, for the response to be read
+/// from it.
+fn connect(url: &Url, from: u64) -> io::Result<Connection> {
+    let stream = TcpStream::connect((url.host.as_str(), url.port))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let host = if url.port == default_port(url.https) {
+        url.host.clone()
+    } else {
+        format!("{}:{}", url.host, url.port)
+    };
+    let mut head = format!("GET {} HTTP/1.1\r\nHost: {host}\r\n", url.path);
+    for header in headers(from) {
+        head.push_str(&header);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    (&stream).write_all(head.as_bytes())?;
+    Ok(Connection::Plain(stream))
+}
+
+/// Has `curl` make the request, writing the response as it came: status
+/// line, headers and a body still in its transfer encoding.
+fn connect_tls(url: &Url, from: u64) -> io::Result<Connection> {
+    let address = format!("https://{}:{}{}", url.host, url.port, url.path);
+    let mut command = Command::new("curl");
+    command
+        .args([
+            "--silent",
+            "--show-error",
+            "--include",
+            "--raw",
+            "--http1.1",
+        ])
+        .arg("--suppress-connect-headers")
+        .args(["--connect-timeout", &TIMEOUT.as_secs().to_string()])
+        // Gives up as a read on a socket times out.
+        .args([
+            "--speed-limit",
+            "1",
+            "--speed-time",
+            &TIMEOUT.as_secs().to_string(),
+        ]);
+    for header in headers(from) {
+        command.arg("--header").arg(header);
+    }
+    let mut child = spawn(
+        command
+            .arg("--url")
+            .arg(address)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        unreachable!("curl's output is piped");
+    };
+    Ok(Connection::Curl {
+        child,
+        stdout,
+        stderr,
+    })
+}
+
+/// Starts `command`, running `curl`, failing with [`CurlMissing`] if there
+/// is no such program.
+fn spawn(command: &mut Command) -> io::Result<Child> {
+    command.spawn().map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, CurlMissing),
+        _ => err,
+    })
+}
+
+/// Returned for an `https://` URL when `curl`, which fetches those, isn't
+/// installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CurlMissing;
+
+impl fmt::Display for CurlMissing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("https:// URLs are fetched through curl, which isn't installed")
+    }
+}
+
+impl Error for CurlMissing {}
+
+/// Where a response is read from.
+enum Connection {
+    Plain(TcpStream),
+    Curl {
+        child: Child,
+        stdout: ChildStdout,
+        stderr: ChildStderr,
+    },
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (child, stdout, stderr) = match self {
+            Connection::Plain(stream) => return stream.read(buf),
+            Connection::Curl {
+                child,
+                stdout,
+                stderr,
+            } => (child, stdout, stderr),
+        };
+        let read = stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            // Cut short, as by a failed connection or certificate check,
+            // the response ends in what curl makes of it.
+            if !child.wait()?.success() {
+                let mut message = String::new();
+                stderr.read_to_string(&mut message)?;
+                // The first line says what went wrong, after its exit code.
+                let message = message.lines().next().unwrap_or_default();
+                let message = message.trim_start_matches("curl: ");
+                let message = match message.split_once(") ") {
+                    Some((code, rest)) if code.starts_with('(') => rest,
+                    _ => message,
+                };
+                return Err(io::Error::other(match message.is_empty() {
+                    true => "curl failed".to_string(),
+                    false => message.to_string(),
+                }));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Else a live stream would download on for good, to no one.
+        if let Connection::Curl { child, .. } = self {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn request(url: &Url, from: u64) -> io::Result<Response> {
+    let connection = match url.https {
+        false => connect(url, from)?,
+        true => connect_tls(url, from)?,
+    };
+    let mut reader = BufReader::new(connection);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+
+    let mut response = Response {
+        status,
+        content_length: None,
+        accepts_ranges: false,
+        location: None,
+        icy_metaint: None,
+        icy_name: None,
+        live: false,
+        body: Body {
+            chunk_left: 0,
+            chunked: false,
+            finished: false,
+            reader,
+        },
+    };
+    loop {
+        line.clear();
+        if response.body.reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let name = name.trim().to_ascii_lowercase();
+        response.live |= name.starts_with("icy-");
+        match name.as_str() {
+            "content-length" => response.content_length = value.parse().ok(),
+            "accept-ranges" => response.accepts_ranges = value.eq_ignore_ascii_case("bytes"),
+            "content-range" => response.accepts_ranges = true,
+            "transfer-encoding" => {
+                response.body.chunked = value.to_ascii_lowercase().contains("chunked")
+            }
+            "location" => response.location = Some(value.to_string()),
+            "icy-metaint" => response.icy_metaint = value.parse().ok().filter(|&len| len > 0),
+            "icy-name" if !value.is_empty() => response.icy_name = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    // A live stream can't be read from anywhere but where it is now.
+    response.accepts_ranges &= !response.live;
+    Ok(response)
+}
+
+/// Makes the first request for `url`, following redirects.
+fn open(url: &str) -> io::Result<(Url, Response)> {
+    let mut url = Url::parse(url)?;
+    for _ in 0..=MAX_REDIRECTS {
+        let response = request(&url, 0)?;
+        match (response.status, &response.location) {
+            (301 | 302 | 303 | 307 | 308, Some(location)) => url = url.join(location)?,
+            (200..=299, _) => return Ok((url, response)),
+            (status, _) => {
+                return Err(io::Error::other(format!("server answered HTTP {status}")));
+            }
+        }
+    }
+    Err(io::Error::other("too many redirects"))
+}
+
+struct Body {
+    reader: BufReader<Connection>,
+    chunked: bool,
+    chunk_left: u64,
+    finished: bool,
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.chunked {
+            return self.reader.read(buf);
+        }
+        if self.finished {
+            return Ok(0);
+        }
+        if self.chunk_left == 0 {
+            let mut line = String::new();
+            self.reader.read_line(&mut line)?;
+            if line.trim().is_empty() {
+                // The blank line that ends the previous chunk.
+                line.clear();
+                self.reader.read_line(&mut line)?;
+            }
+            let size = line.trim().split(';').next().unwrap_or_default();
+            self.chunk_left = u64::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed chunk size"))?;
+            if self.chunk_left == 0 {
+                self.finished = true;
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.chunk_left as usize);
+        let read = self.reader.read(&mut buf[..len])?;
+        self.chunk_left -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Splits the metadata blocks a server interleaves every `metaint` bytes
+/// out of the audio.
+struct IcyDemuxer {
+    metaint: usize,
+    state: IcyState,
+}
+
+enum IcyState {
+    /// This many audio bytes are left before the next block.
+    Audio(usize),
+    /// The next byte is the block's length, in 16-byte units.
+    Length,
+    Meta {
+        left: usize,
+        block: Vec<u8>,
+    },
+}
+
+impl IcyDemuxer {
+    fn new(metaint: usize) -> Self {
+        IcyDemuxer {
+            metaint,
+            state: IcyState::Audio(metaint),
+        }
+    }
+
+    /// Adds the audio in `bytes` to `audio`, calling `on_block` with each
+    /// metadata block that ends in them and the length of `audio` at it.
+    fn push(
+        &mut self,
+        mut bytes: &[u8],
+        audio: &mut Vec<u8>,
+        mut on_block: impl FnMut(usize, &[u8]),
+    ) {
+        while !bytes.is_empty() {
+            match &mut self.state {
+                IcyState::Audio(left) => {
+                    let len = (*left).min(bytes.len());
+                    audio.extend_from_slice(&bytes[..len]);
+                    bytes = &bytes[len..];
+                    *left -= len;
+                    if *left == 0 {
+                        self.state = IcyState::Length;
+                    }
+                }
+                IcyState::Length => {
+                    let left = usize::from(bytes[0]) * 16;
+                    bytes = &bytes[1..];
+                    self.state = if left == 0 {
+                        IcyState::Audio(self.metaint)
+                    } else {
+                        IcyState::Meta {
+                            left,
+                            block: Vec::with_capacity(left),
+                        }
+                    };
+                }
+                IcyState::Meta { left, block } => {
+                    let len = (*left).min(bytes.len());
+                    block.extend_from_slice(&bytes[..len]);
+                    bytes = &bytes[len..];
+                    *left -= len;
+                    if *left == 0 {
+                        on_block(audio.len(), block);
+                        self.state = IcyState::Audio(self.metaint);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The `StreamTitle` in a metadata block such as
+/// `StreamTitle='Artist - Title';StreamUrl='';`, read as UTF-8 or else as
+/// Latin-1, as older servers send it.
+fn stream_title(block: &[u8]) -> Option<String> {
+    let block = &block[..block
+        .iter()
+        .rposition(|&byte| byte != 0)
+        .map_or(0, |end| end + 1)];
+    let text = match std::str::from_utf8(block) {
+        Ok(text) => text.to_string(),
+        Err(_) => block.iter().map(|&byte| char::from(byte)).collect(),
+    };
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &text[start..];
+    let title = match rest.find("';") {
+        Some(end) => &rest[..end],
+        None => rest.strip_suffix('\'').unwrap_or(rest),
+    };
+    Some(title.trim().to_string()).filter(|title| !title.is_empty())
+}
+
+struct State {
+    /// Offset in the resource of `data[0]`.
+    start: u64,
+    data: Vec<u8>,
+    done: bool,
+    error: Option<String>,
+    /// Bumped whenever the download restarts, retiring the old fetch thread.
+    generation: u64,
+    /// The furthest any reader has got.
+    read_to: u64,
+    /// Titles of a live stream yet to be reached by a reader, by the offset
+    /// they start at.
+    titles: VecDeque<(u64, String)>,
+    /// The title of what a reader has got to.
+    title: Option<String>,
+    /// Where the data from the latest reconnect starts.
+    reconnected_at: Option<u64>,
+}
+
+impl State {
+    fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+}
+
+/// One remote resource being fetched in the background.
+pub(crate) struct Download {
+    url: Url,
+    content_length: Option<u64>,
+    accepts_ranges: bool,
+    live: bool,
+    name: Option<String>,
+    state: Mutex<State>,
+    changed: Condvar,
+    signals: Sender<Signal>,
+}
+
+impl fmt::Debug for Download {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Download")
+            .field("host", &self.url.host)
+            .field("path", &self.url.path)
+            .field("content_length", &self.content_length)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Download {
+    /// Starts fetching `url` and blocks until `prefetch` bytes, or the whole
+    /// resource if smaller, have arrived; for a live stream, no more than
+    /// [`LIVE_PREFETCH`].
+    pub(crate) fn open(
+        url: &str,
+        prefetch: usize,
+        signals: Sender<Signal>,
+    ) -> io::Result<Arc<Self>> {
+        let (url, response) = open(url)?;
+        let prefetch = if response.live {
+            prefetch.min(LIVE_PREFETCH)
+        } else {
+            prefetch
+        };
+        let download = Arc::new(Download {
+            url,
+            content_length: response.content_length.filter(|_| !response.live),
+            accepts_ranges: response.accepts_ranges,
+            live: response.live,
+            name: response.icy_name,
+            state: Mutex::new(State {
+                start: 0,
+                data: Vec::new(),
+                done: false,
+                error: None,
+                generation: 0,
+                read_to: 0,
+                titles: VecDeque::new(),
+                title: None,
+                reconnected_at: None,
+            }),
+            changed: Condvar::new(),
+            signals,
+        });
+        download.spawn_fetch(response.body, response.icy_metaint, 0);
+
+        let state = download.state.locked();
+        let state = download
+            .changed
+            .wait_while(state, |state| !state.done && state.data.len() < prefetch)
+            .unwrap_or_else(PoisonError::into_inner);
+        if let (Some(error), true) = (&state.error, state.data.is_empty()) {
+            return Err(io::Error::other(error.clone()));
+        }
+        drop(state);
+        Ok(download)
+    }
+
+    /// Fetches `body` into the state on a thread that ends with the
+    /// download, or once a restart retires `generation`.
+    fn spawn_fetch(self: &Arc<Self>, mut body: Body, metaint: Option<usize>, generation: u64) {
+        let weak = Arc::downgrade(self);
+        let live = self.live;
+        thread::spawn(move || {
+            let mut buf = vec![0; 64 * 1024];
+            let mut icy = metaint.map(IcyDemuxer::new);
+            let mut reconnects = 0;
+            loop {
+                let mut read = body.read(&mut buf);
+                let dropped = match &read {
+                    Ok(read) => *read == 0,
+                    Err(err) => err.kind() != io::ErrorKind::Interrupted,
+                };
+                // A live stream never ends, so one that does has dropped.
+                if live && dropped {
+                    match reconnect(&weak, generation, &mut reconnects) {
+                        Ok(response) => {
+                            if let Some(download) = weak.upgrade() {
+                                let mut state = download.state.locked();
+                                state.reconnected_at = Some(state.end());
+                            }
+                            body = response.body;
+                            icy = response.icy_metaint.map(IcyDemuxer::new);
+                            continue;
+                        }
+                        Err(err) => read = Err(err),
+                    }
+                }
+                let Some(download) = weak.upgrade() else {
+                    return;
+                };
+                let mut state = download.state.locked();
+                if state.generation != generation {
+                    return;
+                }
+                match read {
+                    Ok(0) => state.done = true,
+                    Ok(read) => {
+                        reconnects = 0;
+                        download.append(&mut state, &buf[..read], icy.as_mut());
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        state.error = Some(err.to_string());
+                        state.done = true;
+                    }
+                }
+                let done = state.done;
+                drop(state);
+                download.changed.notify_all();
+                if done {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Adds fetched `bytes` to the data, taking out the metadata blocks, and
+    /// keeps a live stream to the last [`LIVE_BUFFER`] bytes.
+    fn append(&self, state: &mut State, bytes: &[u8], icy: Option<&mut IcyDemuxer>) {
+        match icy {
+            Some(icy) => {
+                let State {
+                    start,
+                    data,
+                    titles,
+                    title,
+                    ..
+                } = state;
+                icy.push(bytes, data, |len, block| {
+                    let Some(new) = stream_title(block) else {
+                        return;
+                    };
+                    let latest = titles.back().map(|(_, title)| title).or(title.as_ref());
+                    if latest != Some(&new) {
+                        titles.push_back((*start + len as u64, new));
+                    }
+                });
+            }
+            None => state.data.extend_from_slice(bytes),
+        }
+        if self.live && state.data.len() > LIVE_BUFFER {
+            let excess = state.data.len() - LIVE_BUFFER;
+            state.data.drain(..excess);
+            state.start += excess as u64;
+        }
+    }
+
+    /// Drops what has been downloaded and fetches again from `from`.
+    fn restart(self: &Arc<Self>, state: &mut State, from: u64) -> io::Result<()> {
+        state.generation += 1;
+        state.start = from;
+        state.data.clear();
+        state.done = false;
+        state.error = None;
+        let response = request(&self.url, from)?;
+        if response.status != 206 {
+            return Err(unsupported("server ignored the range request"));
+        }
+        self.spawn_fetch(response.body, response.icy_metaint, state.generation);
+        Ok(())
+    }
+
+    /// A reader from the start, or for a live stream, from where the last
+    /// reader got to.
+    pub(crate) fn reader(self: &Arc<Self>) -> HttpReader {
+        let position = if self.live {
+            let state = self.state.locked();
+            state.read_to.max(state.start)
+        } else {
+            0
+        };
+        self.reader_at(position)
+    }
+
+    fn reader_at(self: &Arc<Self>, position: u64) -> HttpReader {
+        HttpReader {
+            download: self.clone(),
+            position,
+        }
+    }
+
+    /// Whether this is a live stream, such as internet radio, which has no
+    /// length and can't seek.
+    pub(crate) fn is_live(&self) -> bool {
+        self.live
+    }
+
+    /// The station's name, from the `icy-name` header.
+    pub(crate) fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    /// The `StreamTitle` of what has been played up to, on a live stream
+    /// that sends one.
+    pub(crate) fn title(&self) -> Option<String> {
+        self.state.locked().title.clone()
+    }
+
+    /// Where a decoder that ran out at `opened_at` or after picks up: where
+    /// the latest reconnect's data starts, if it hasn't tried that yet, or
+    /// else past anything read so far.
+    fn resume_at(&self, opened_at: u64) -> Option<u64> {
+        let state = self.state.locked();
+        if state.done {
+            return None;
+        }
+        let at = match state.reconnected_at {
+            Some(at) if at > opened_at => at,
+            _ => state.read_to,
+        };
+        Some(at.max(state.start))
+    }
+
+    /// Length worked out from the first bytes and the `Content-Length`, if
+    /// the server sent one.
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        self.read_header(duration_from_header)
+    }
+
+    /// The length, given the one the decoder `reported`: an MP3's from its
+    /// headers first, as for a file.
+    pub(crate) fn decoded_duration(&self, reported: Option<Duration>) -> Option<Duration> {
+        self.read_header(mp3_duration)
+            .or(reported)
+            .or_else(|| self.duration())
+    }
+
+    fn read_header(&self, of: fn(&[u8], u64) -> Option<Duration>) -> Option<Duration> {
+        let total = self.content_length?;
+        let state = self.state.locked();
+        if state.start != 0 {
+            return None;
+        }
+        of(
+            &state.data[..state.data.len().min(HEADER_BYTES as usize)],
+            total,
+        )
+    }
+}
+
+/// Connects to a dropped live stream again, waiting longer before each try
+/// and counting them in `reconnects`, until it answers or
+/// [`MAX_RECONNECTS`] have failed in a row.
+fn reconnect(
+    download: &Weak<Download>,
+    generation: u64,
+    reconnects: &mut u32,
+) -> io::Result<Response> {
+    loop {
+        *reconnects += 1;
+        let delay = RECONNECT_DELAY
+            .saturating_mul(1 << (*reconnects - 1).min(16))
+            .min(MAX_RECONNECT_DELAY);
+        let Some(current) = download.upgrade() else {
+            return Err(io::Error::other("the stream was closed"));
+        };
+        if current.state.locked().generation != generation {
+            return Err(io::Error::other("the stream was restarted"));
+        }
+        let _ = current
+            .signals
+            .send(Signal::Event(PlayerEvent::Reconnecting {
+                attempt: *reconnects,
+            }));
+        drop(current);
+        thread::sleep(delay);
+        let Some(current) = download.upgrade() else {
+            return Err(io::Error::other("the stream was closed"));
+        };
+        let err = match request(&current.url, 0) {
+            Ok(response) if (200..=299).contains(&response.status) => return Ok(response),
+            Ok(response) => io::Error::other(format!("server answered HTTP {}", response.status)),
+            Err(err) => err,
+        };
+        if *reconnects >= MAX_RECONNECTS {
+            let _ = current
+                .signals
+                .send(Signal::Event(PlayerEvent::Error(format!(
+                    "the stream dropped and couldn't be reconnected: {err}"
+                ))));
+            return Err(err);
+        }
+    }
+}
+
+/// A seekable view of a [`Download`], blocking until the bytes it needs
+/// have arrived.
+pub(crate) struct HttpReader {
+    download: Arc<Download>,
+    position: u64,
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let download = &self.download;
+        if download
+            .content_length
+            .is_some_and(|len| self.position >= len)
+        {
+            return Ok(0);
+        }
+        let mut state = download.state.locked();
+        loop {
+            // What a live stream kept has moved on; carry on from there.
+            if download.live && self.position < state.start {
+                self.position = state.start;
+            }
+            let end = state.end();
+            let behind = self.position < state.start;
+            let far_ahead = self.position > end + RESTART_DISTANCE;
+            if (behind || far_ahead) && download.accepts_ranges {
+                download.restart(&mut state, self.position)?;
+                continue;
+            }
+            if behind {
+                return Err(unsupported("server does not accept range requests"));
+            }
+            if self.position < end {
+                let offset = (self.position - state.start) as usize;
+                let len = buf.len().min(state.data.len() - offset);
+                buf[..len].copy_from_slice(&state.data[offset..offset + len]);
+                self.position += len as u64;
+                state.read_to = state.read_to.max(self.position);
+                download.reach(&mut state, self.position);
+                return Ok(len);
+            }
+            if state.done {
+                return match &state.error {
+                    Some(error) => Err(io::Error::other(error.clone())),
+                    None => Ok(0),
+                };
+            }
+            state = download
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl Download {
+    /// Makes current the titles that start at or before `position`,
+    /// reporting the last of them.
+    fn reach(&self, state: &mut State, position: u64) {
+        let mut reached = None;
+        while state.titles.front().is_some_and(|&(at, _)| at <= position) {
+            reached = state.titles.pop_front().map(|(_, title)| title);
+        }
+        if let Some(title) = reached {
+            state.title = Some(title.clone());
+            let _ = self
+                .signals
+                .send(Signal::Event(PlayerEvent::StreamTitleChanged(title)));
+        }
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let len = self
+                    .download
+                    .content_length
+                    .ok_or_else(|| unsupported("server did not report the length of the stream"))?;
+                len.checked_add_signed(offset)
+            }
+        };
+        self.position = target.ok_or_else(|| invalid("seek before the start of the stream"))?;
+        Ok(self.position)
+    }
+}
+
+/// Decodes a [`Download`] on a thread of its own, a few seconds ahead of
+/// playback. If the network falls behind, silence fills in while
+/// [`PlayerEvent::Buffering`] is reported; the position keeps counting
+/// through it.
+pub(crate) struct StreamSource {
+    chunks: Receiver<(u64, Vec<f32>)>,
+    seeks: Sender<Duration>,
+    signals: Sender<Signal>,
+    chunk: std::vec::IntoIter<f32>,
+    generation: u64,
+    /// Samples of silence left to finish the current frame of a stall.
+    silence: u16,
+    stalled: bool,
+    channels: u16,
+    sample_rate: u32,
+    total_duration: Option<Duration>,
+    accepts_ranges: bool,
+    live: bool,
+    finished: bool,
+}
+
+impl StreamSource {
+    pub(crate) fn open(download: &Arc<Download>) -> Result<Self, rodio::decoder::DecoderError> {
+        let reader = download.reader();
+        let opened_at = reader.position;
+        let decoder = Decoder::new(reader)?;
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+        // A live stream's first frame may still tell the length of a file.
+        let total_duration = if download.live {
+            None
+        } else {
+            download.decoded_duration(decoder.total_duration())
+        };
+
+        let (chunk_sender, chunks) = mpsc::sync_channel(CHUNKS_AHEAD);
+        let (seeks, seek_receiver) = mpsc::channel();
+        let signals = download.signals.clone();
+        thread::spawn({
+            let download = download.clone();
+            let signals = signals.clone();
+            move || {
+                decode(
+                    decoder,
+                    opened_at,
+                    download,
+                    chunk_sender,
+                    seek_receiver,
+                    signals,
+                )
+            }
+        });
+        // Hold the first chunk back so playback doesn't open on a stall.
+        let (chunk, finished) = match chunks.recv() {
+            Ok((_, chunk)) => (chunk, false),
+            Err(_) => (Vec::new(), true),
+        };
+
+        Ok(StreamSource {
+            chunks,
+            seeks,
+            signals,
+            chunk: chunk.into_iter(),
+            generation: 0,
+            silence: 0,
+            stalled: false,
+            channels,
+            sample_rate,
+            total_duration,
+            accepts_ranges: download.accepts_ranges,
+            live: download.live,
+            finished,
+        })
+    }
+}
+
+fn decode(
+    mut decoder: Decoder<HttpReader>,
+    mut opened_at: u64,
+    download: Arc<Download>,
+    chunks: SyncSender<(u64, Vec<f32>)>,
+    seeks: Receiver<Duration>,
+    signals: Sender<Signal>,
+) {
+    let mut generation = 0;
+    let (channels, sample_rate) = (decoder.channels(), decoder.sample_rate());
+    let chunk_samples = CHUNK_FRAMES * channels.max(1) as usize;
+    loop {
+        while let Ok(position) = seeks.try_recv() {
+            generation += 1;
+            if let Err(err) = decoder.try_seek(position) {
+                let _ = signals.send(Signal::Event(PlayerEvent::Error(format!(
+                    "seeking the stream failed: {err}"
+                ))));
+            }
+        }
+        let chunk = decoder
+            .by_ref()
+            .take(chunk_samples)
+            .map(|sample| sample.to_f32())
+            .collect::<Vec<_>>();
+        if chunk.is_empty() {
+            // A live stream goes on past a reconnect or a bad patch with a
+            // new decoder, if it comes out the same as the old one.
+            let Some(at) = download
+                .live
+                .then(|| download.resume_at(opened_at))
+                .flatten()
+            else {
+                return;
+            };
+            match Decoder::new(download.reader_at(at)) {
+                Ok(next) if (next.channels(), next.sample_rate()) == (channels, sample_rate) => {
+                    decoder = next;
+                    opened_at = at;
+                    continue;
+                }
+                _ => return,
+            }
+        }
+        if chunks.send((generation, chunk)).is_err() {
+            return;
+        }
+    }
+}
+
+impl StreamSource {
+    fn set_stalled(&mut self, stalled: bool) {
+        if self.stalled != stalled {
+            self.stalled = stalled;
+            let event = if stalled {
+                PlayerEvent::Buffering
+            } else {
+                PlayerEvent::Buffered
+            };
+            let _ = self.signals.send(Signal::Event(event));
+        }
+    }
+}
+
+impl Iterator for StreamSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.silence > 0 {
+            self.silence -= 1;
+            return Some(0.0);
+        }
+        loop {
+            if let Some(sample) = self.chunk.next() {
+                return Some(sample);
+            }
+            if self.finished {
+                return None;
+            }
+            match self.chunks.try_recv() {
+                Ok((generation, chunk)) => {
+                    // Chunks decoded before the last seek are stale.
+                    if generation == self.generation {
+                        self.chunk = chunk.into_iter();
+                        self.set_stalled(false);
+                    }
+                }
+                Err(TryRecvError::Empty) => {
+                    // Chunks hold whole frames, so a stall always starts on
+                    // a frame boundary; fill whole frames too.
+                    self.silence = self.channels.max(1) - 1;
+                    self.set_stalled(true);
+                    return Some(0.0);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = true;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+impl Source for StreamSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        if self.live {
+            return Err(SeekError::Other(Box::new(unsupported(
+                "the stream is live, so it can't seek",
+            ))));
+        }
+        if !self.accepts_ranges {
+            return Err(SeekError::Other(Box::new(unsupported(
+                "the server does not accept range requests, so this stream can't seek",
+            ))));
+        }
+        if self.seeks.send(pos).is_err() {
+            return Err(SeekError::Other(Box::new(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the download has stopped, so the stream can't seek",
+            ))));
+        }
+        self.generation += 1;
+        self.chunk = Vec::new().into_iter();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlayerError;
+    use std::net::TcpListener;
+
+    #[test]
+    fn https_urls_default_to_port_443() {
+        let url = Url::parse("https://radio.example/live.mp3").unwrap();
+        assert!(url.https);
+        assert_eq!((url.host.as_str(), url.port), ("radio.example", 443));
+        let url = Url::parse("http://radio.example:8000").unwrap();
+        assert!(!url.https);
+        assert_eq!((url.port, url.path.as_str()), (8000, "/"));
+        assert!(Url::parse("ftp://radio.example/live.mp3").is_err());
+    }
+
+    #[test]
+    fn redirects_keep_the_scheme_unless_given_one() {
+        let url = Url::parse("https://radio.example/a/live.mp3").unwrap();
+        let relative = url.join("other.mp3").unwrap();
+        assert!(relative.https);
+        assert_eq!(relative.path, "/a/other.mp3");
+        let absolute = url.join("http://mirror.example/live.mp3").unwrap();
+        assert!(!absolute.https);
+        assert_eq!(
+            (absolute.host.as_str(), absolute.port),
+            ("mirror.example", 80)
+        );
+    }
+
+    #[test]
+    fn a_missing_curl_fails_saying_so() {
+        let err = spawn(&mut Command::new("fullyrustaudio-no-such-curl")).unwrap_err();
+        let err = PlayerError::from(err);
+        assert!(matches!(err, PlayerError::CurlMissing), "{err:?}");
+        assert_eq!(
+            err.to_string(),
+            "https:// URLs are fetched through curl, which isn't installed"
+        );
+        // Any other failure to read stays one.
+        let other = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        assert!(matches!(PlayerError::from(other), PlayerError::Io(_)));
+    }
+
+    #[test]
+    fn chunked_bodies_are_joined() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                      5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+                )
+                .unwrap();
+        });
+        let (_, mut response) = open(&format!("http://127.0.0.1:{port}/")).unwrap();
+        let mut body = String::new();
+        response.body.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello world");
+        server.join().unwrap();
+    }
+}
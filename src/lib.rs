@@ -0,0 +1,163 @@
+mod ab;
+mod album;
+mod atomic;
+mod backend;
+mod bookmark;
+mod buffer;
+mod cache;
+mod chain;
+mod channels;
+mod clock;
+mod compressor;
+mod config;
+mod control;
+mod cue;
+mod decode;
+mod dither;
+pub mod dsp;
+mod engine;
+mod equalizer;
+mod error;
+mod events;
+mod format;
+mod gain;
+mod generators;
+mod history;
+mod http;
+mod limiter;
+mod lock;
+mod looping;
+mod loudness;
+mod mailbox;
+#[cfg(all(feature = "media-keys", any(windows, target_os = "macos")))]
+mod media_keys;
+mod metadata;
+mod meter;
+#[cfg(all(feature = "mpris", target_os = "linux"))]
+mod mpris;
+mod now_playing;
+mod output;
+mod overlay;
+mod pcm;
+mod pitch;
+mod player;
+mod playlist;
+mod png;
+mod position;
+mod preset;
+mod probe;
+mod profile;
+mod queue;
+mod recover;
+pub mod render;
+mod replaygain;
+mod resample;
+mod reverb;
+mod schedule;
+mod scrobble;
+mod secondary;
+mod seeker;
+mod segment;
+mod settings;
+mod shuffle;
+mod silence;
+mod sleep;
+pub mod spectrogram;
+mod spectrum;
+mod state;
+mod stats;
+mod stdin;
+mod suspend;
+mod tempo;
+#[cfg(test)]
+mod testing;
+mod tone;
+mod trim;
+mod vocal;
+mod watch;
+pub mod waveform;
+
+pub use ab::AB;
+pub use album::{GainMode, TrackGain};
+pub use backend::Backend;
+pub use bookmark::Bookmarks;
+pub use buffer::{PlaybackState, DEFAULT_HIGH_WATERMARK, DEFAULT_LOW_WATERMARK, MAX_WATERMARK};
+pub use cache::CacheStatus;
+pub use chain::{
+    ChainConfig, ChainError, ChannelStage, CompressorStage, EqStage, LimiterStage, ReverbStage,
+    ToneStage, WidthStage,
+};
+pub use channels::{ChannelMode, WidthPlacement, MAX_STEREO_WIDTH};
+pub use compressor::{Compressor, CompressorControls, CompressorSettings};
+pub use config::{ConfigError, PlayerConfig};
+pub use control::{default_socket_path, send_command, ControlCommand, ControlServer};
+pub use cue::{CueSheet, CueTrack};
+pub use decode::DecoderBackend;
+pub use dither::Dither;
+pub use dsp::{BiquadFilter, FilterType};
+pub use engine::AudioEngine;
+pub use equalizer::{
+    EqControls, Equalizer, BAND_COUNT, DEFAULT_GAINS, DEFAULT_Q, EQ_BYPASS_FADE, EQ_SMOOTHING,
+    FREQUENCIES, SHELF_Q, THIRD_OCTAVE_BAND_COUNT, THIRD_OCTAVE_FREQUENCIES, THIRD_OCTAVE_Q,
+};
+pub use error::PlayerError;
+pub use events::{PlayerEvent, DEFAULT_PROGRESS_INTERVAL};
+pub use format::ParseError;
+pub use gain::{db_to_linear, linear_to_db, Gain, GainControls};
+pub use generators::{GeneratorSettings, PinkNoise, SineWave, SweptSine, WhiteNoise};
+pub use history::{History, HistoryEntry, DEFAULT_HISTORY_LIMIT};
+pub use http::DEFAULT_PREFETCH;
+pub use limiter::{
+    Limiter, LimiterControls, LIMITER_ATTACK, LIMITER_RELEASE, LIMITER_THRESHOLD_DB,
+};
+pub use loudness::{analyze, LoudnessReport, TrackLoudness};
+#[cfg(all(feature = "media-keys", any(windows, target_os = "macos")))]
+pub use media_keys::MediaKeys;
+pub use metadata::{Chapter, CoverArt, LoopPoints, ReplayGainTags, TrackMetadata};
+pub use meter::{
+    ChannelLevel, Meter, MeterControls, METER_CORRELATION_WINDOW, METER_FLOOR_DB,
+    METER_MAX_CHANNELS, METER_PEAK_DECAY,
+};
+#[cfg(all(feature = "mpris", target_os = "linux"))]
+pub use mpris::MprisServer;
+pub use now_playing::NowPlaying;
+pub use output::{Latency, OutputConfig, OutputFormat, StreamConfig};
+pub use overlay::{DuckGuard, OVERLAY_DUCK_RAMP};
+pub use pcm::{PcmRead, PcmTapReceiver};
+pub use player::{
+    AudioPlayer, CHAPTER_RESTART, DEFAULT_FADE, MAX_PITCH_SEMITONES, MAX_SPEED, MAX_TEMPO,
+    MAX_VOLUME_DB, MIN_SPEED, MIN_TEMPO, MIN_VOLUME_DB, SCRUB_PREVIEW, SCRUB_SETTLE,
+};
+pub use playlist::{Playlist, PlaylistEntry};
+pub use position::Position;
+pub use preset::EqPreset;
+pub use probe::{probe, StreamInfo};
+pub use profile::EqProfile;
+pub use queue::{QueueItem, RepeatMode};
+pub use replaygain::ReplayGainMode;
+pub use resample::ResampleQuality;
+pub use reverb::{Reverb, ReverbControls, ReverbSettings};
+pub use rodio::cpal::SampleFormat;
+pub use scrobble::{DEFAULT_SCROBBLE_CAP, DEFAULT_SCROBBLE_FRACTION};
+pub use secondary::MAX_OUTPUT_DELAY;
+pub use settings::{EqBand, EqError, EqSettings};
+pub use silence::{
+    DEFAULT_SILENCE_THRESHOLD_DB, DEFAULT_SILENCE_WINDOW, DEFAULT_TRIM_THRESHOLD_DB,
+};
+pub use sleep::SleepAction;
+pub use spectrum::{SPECTRUM_FLOOR_DB, SPECTRUM_SIZE};
+pub use state::PlayerState;
+pub use stats::PlayerStats;
+pub use stdin::STDIN_PATH;
+pub use tone::{BASS_FREQUENCY, MAX_TONE_DB, MAX_TONE_KNOB_DB, TREBLE_FREQUENCY};
+pub use trim::TrackRange;
+pub use vocal::{VOCAL_HIGH_FREQUENCY, VOCAL_LOW_FREQUENCY};
+pub use watch::{FolderOrder, FolderWatchOptions};
+
+// Players and engines are made to be shared between threads; this keeps a
+// change from quietly taking that away.
+const _: () = {
+    const fn send_sync<T: Send + Sync>() {}
+    send_sync::<AudioPlayer>();
+    send_sync::<AudioEngine>();
+};
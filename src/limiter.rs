@@ -0,0 +1,342 @@
+use crate::{
+    atomic::AtomicF32,
+    gain::{db_to_linear, linear_to_db},
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+pub const LIMITER_THRESHOLD_DB: f32 = -0.3;
+pub const LIMITER_ATTACK: Duration = Duration::from_millis(1);
+pub const LIMITER_RELEASE: Duration = Duration::from_millis(50);
+
+/// Settings shared between a [`Limiter`] and the thread adjusting it.
+///
+/// The attack time sets the lookahead and is fixed once the limiter is built.
+pub struct LimiterControls {
+    enabled: AtomicBool,
+    threshold_db: AtomicF32,
+    attack: Duration,
+    release_ms: AtomicF32,
+    reduction_db: AtomicF32,
+}
+
+impl LimiterControls {
+    pub fn new(threshold_db: f32, attack: Duration, release: Duration) -> Self {
+        LimiterControls {
+            enabled: AtomicBool::new(true),
+            threshold_db: AtomicF32::new(threshold_db),
+            attack,
+            release_ms: AtomicF32::new(release.as_secs_f32() * 1000.0),
+            reduction_db: AtomicF32::new(0.0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// A disabled limiter passes audio through unchanged, apart from its lookahead delay.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn threshold_db(&self) -> f32 {
+        self.threshold_db.load()
+    }
+
+    pub fn set_threshold_db(&self, threshold_db: f32) {
+        self.threshold_db.store(threshold_db.min(0.0));
+    }
+
+    pub fn attack(&self) -> Duration {
+        self.attack
+    }
+
+    pub fn release(&self) -> Duration {
+        Duration::from_secs_f32(self.release_ms.load() / 1000.0)
+    }
+
+    pub fn set_release(&self, release: Duration) {
+        self.release_ms.store(release.as_secs_f32() * 1000.0);
+    }
+
+    /// How far the limiter is currently pulling the level down, in positive dB.
+    pub fn gain_reduction_db(&self) -> f32 {
+        self.reduction_db.load()
+    }
+}
+
+impl Default for LimiterControls {
+    fn default() -> Self {
+        LimiterControls::new(LIMITER_THRESHOLD_DB, LIMITER_ATTACK, LIMITER_RELEASE)
+    }
+}
+
+/// Lookahead peak limiter that keeps every sample at or below the threshold.
+///
+/// Gain reduction is computed per frame from the loudest channel, so all
+/// channels are turned down together and the stereo image stays put.
+pub struct Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<LimiterControls>,
+    channels: usize,
+    sample_rate: u32,
+    lookahead: usize,
+    delay: VecDeque<f32>,
+    minimums: VecDeque<(u64, f32)>,
+    window: VecDeque<f32>,
+    window_sum: f64,
+    frame: u64,
+    envelope: f32,
+    priming: usize,
+    flush: usize,
+    output: Vec<f32>,
+    position: usize,
+}
+
+impl<S> Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S) -> Self {
+        Self::with_controls(source, Arc::new(LimiterControls::default()))
+    }
+
+    pub fn with_controls(source: S, controls: Arc<LimiterControls>) -> Self {
+        let mut limiter = Limiter {
+            channels: source.channels().max(1) as usize,
+            sample_rate: source.sample_rate(),
+            source,
+            controls,
+            lookahead: 1,
+            delay: VecDeque::new(),
+            minimums: VecDeque::new(),
+            window: VecDeque::new(),
+            window_sum: 0.0,
+            frame: 0,
+            envelope: 1.0,
+            priming: 0,
+            flush: 0,
+            output: Vec::new(),
+            position: 0,
+        };
+        limiter.reset();
+        limiter
+    }
+
+    pub fn controls(&self) -> Arc<LimiterControls> {
+        self.controls.clone()
+    }
+
+    /// Drops all buffered audio and starts over with the source's current format.
+    fn reset(&mut self) {
+        self.channels = self.source.channels().max(1) as usize;
+        self.sample_rate = self.source.sample_rate();
+        self.lookahead = ((self.controls.attack.as_secs_f32() * self.sample_rate as f32).round()
+            as usize)
+            .max(1);
+
+        // The delay is one frame shorter than the gain smoothing window, which
+        // lines every peak up with the point where the window fully covers it.
+        self.delay.clear();
+        self.delay.resize((self.lookahead - 1) * self.channels, 0.0);
+        self.minimums.clear();
+        self.window.clear();
+        self.window.resize(self.lookahead, 1.0);
+        self.window_sum = self.lookahead as f64;
+        self.frame = 0;
+        self.envelope = 1.0;
+        self.priming = self.lookahead - 1;
+        self.flush = self.lookahead - 1;
+        self.output.clear();
+        self.position = 0;
+    }
+
+    /// Reads one frame from the source and emits the frame leaving the delay line.
+    fn process_frame(&mut self) -> bool {
+        if self.source.channels().max(1) as usize != self.channels
+            || self.source.sample_rate() != self.sample_rate
+        {
+            self.reset();
+        }
+
+        let start = self.delay.len();
+        for _ in 0..self.channels {
+            match self.source.next() {
+                Some(sample) => self.delay.push_back(sample),
+                None => break,
+            }
+        }
+        let read = self.delay.len() - start;
+        if read == 0 {
+            if self.flush == 0 {
+                return false;
+            }
+            self.flush -= 1;
+        }
+        self.delay.resize(start + self.channels, 0.0);
+
+        let enabled = self.controls.is_enabled();
+        let threshold = db_to_linear(self.controls.threshold_db());
+        let peak = self
+            .delay
+            .range(start..)
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        let required = if enabled && peak > threshold {
+            threshold / peak
+        } else {
+            1.0
+        };
+
+        while self
+            .minimums
+            .back()
+            .is_some_and(|&(_, gain)| gain >= required)
+        {
+            self.minimums.pop_back();
+        }
+        self.minimums.push_back((self.frame, required));
+        while self
+            .minimums
+            .front()
+            .is_some_and(|&(frame, _)| frame + self.lookahead as u64 <= self.frame)
+        {
+            self.minimums.pop_front();
+        }
+        let minimum = self.minimums.front().map_or(1.0, |&(_, gain)| gain);
+
+        self.window.push_back(minimum);
+        self.window_sum += minimum as f64 - self.window.pop_front().unwrap_or(1.0) as f64;
+        if self.frame.is_multiple_of(4096) {
+            self.window_sum = self.window.iter().map(|&gain| gain as f64).sum();
+        }
+        self.frame += 1;
+        let smoothed = (self.window_sum / self.lookahead as f64) as f32;
+
+        self.envelope = if smoothed < self.envelope {
+            smoothed
+        } else {
+            let frames = self.controls.release_ms.load() / 1000.0 * self.sample_rate as f32;
+            let release = (-1.0 / frames.max(1.0)).exp();
+            smoothed + (self.envelope - smoothed) * release
+        };
+        self.controls
+            .reduction_db
+            .store(-linear_to_db(self.envelope).min(0.0));
+
+        self.output.clear();
+        self.position = 0;
+        if self.priming > 0 {
+            // Only the silence the delay line started with is leaving it.
+            self.priming -= 1;
+            self.delay.drain(..self.channels);
+            return true;
+        }
+        for _ in 0..self.channels {
+            let sample = self.delay.pop_front().unwrap_or(0.0) * self.envelope;
+            // Rounding in the smoothing window can leave a sample a hair over.
+            self.output.push(if enabled {
+                sample.clamp(-threshold, threshold)
+            } else {
+                sample
+            });
+        }
+        true
+    }
+}
+
+impl<S> Iterator for Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position == self.output.len() {
+            if !self.process_frame() {
+                return None;
+            }
+        }
+        let sample = self.output[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        let buffered = self.delay.len() + self.output.len() - self.position;
+        self.source.current_frame_len().map(|len| len + buffered)
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.reset();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generators::{GeneratorSettings, SineWave},
+        testing::{collect, peak, second},
+    };
+
+    /// The biggest step from one sample to the next on any channel.
+    fn largest_step(samples: &[f32], channels: usize) -> f32 {
+        samples
+            .iter()
+            .zip(&samples[channels..])
+            .fold(0.0, |largest, (a, b)| largest.max((b - a).abs()))
+    }
+
+    #[test]
+    fn a_sine_6_db_over_never_passes_the_ceiling_or_jumps() {
+        let ceiling = db_to_linear(LIMITER_THRESHOLD_DB);
+        let input = collect(SineWave::new(
+            200.0,
+            GeneratorSettings {
+                amplitude: ceiling * db_to_linear(6.0),
+                ..second(44_100, 2)
+            },
+        ));
+        let dry = input.clone().collect::<Vec<_>>();
+        let limited = Limiter::new(input).collect::<Vec<_>>();
+        assert_eq!(limited.len(), dry.len());
+        let loudest = peak(&limited);
+        assert!(loudest <= ceiling, "peaked at {loudest}, over {ceiling}");
+        // Steps no bigger than the signal's own mean the gain never jumped.
+        let (dry_step, limited_step) = (largest_step(&dry, 2), largest_step(&limited, 2));
+        assert!(
+            limited_step <= dry_step,
+            "stepped {limited_step} against {dry_step}"
+        );
+    }
+}
@@ -0,0 +1,25 @@
+use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
+
+/// Locking that carries on after another thread panicked while holding the
+/// lock. Everything the player keeps behind a mutex stays consistent between
+/// statements, so a panic elsewhere is no reason to bring playback down too.
+pub(crate) trait Lock<T> {
+    fn locked(&self) -> MutexGuard<'_, T>;
+
+    /// The lock if it is free, without waiting, poisoned or not.
+    fn try_locked(&self) -> Option<MutexGuard<'_, T>>;
+}
+
+impl<T> Lock<T> for Mutex<T> {
+    fn locked(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn try_locked(&self) -> Option<MutexGuard<'_, T>> {
+        match self.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use crate::{
+    lock::Lock,
+    mailbox::{Broadcast, Mailbox},
+    metadata::LoopPoints,
+    queue::PlaylistControls,
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Longest loop from a track's tags that is kept in memory to replay; the
+/// tags of a longer one are left alone.
+const MAX_TAGGED_LOOP: Duration = Duration::from_secs(10 * 60);
+
+/// A pre-decoded A–B section, so wrapping back to `start` needs no seek.
+pub(crate) struct LoopBuffer {
+    track: u64,
+    pub(crate) start: Duration,
+    pub(crate) end: Duration,
+    pub(crate) start_frame: u64,
+    pub(crate) end_frame: u64,
+    channels: u16,
+    samples: Vec<f32>,
+}
+
+impl LoopBuffer {
+    /// A loop from frame `start_frame` up to `end_frame`, of `samples`
+    /// from the first.
+    pub(crate) fn new(
+        track: u64,
+        start_frame: u64,
+        end_frame: u64,
+        channels: u16,
+        sample_rate: u32,
+        mut samples: Vec<f32>,
+    ) -> Self {
+        let channels = channels.max(1);
+        samples.truncate(samples.len() - samples.len() % channels as usize);
+        let time = |frame: u64| Duration::from_secs_f64(frame as f64 / sample_rate.max(1) as f64);
+        LoopBuffer {
+            track,
+            start: time(start_frame),
+            end: time(end_frame),
+            start_frame,
+            end_frame,
+            channels,
+            samples,
+        }
+    }
+
+    fn length(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// The active loop region, shared between the player and its [`Looper`].
+pub(crate) struct LoopControls {
+    region: Mutex<Option<Arc<LoopBuffer>>>,
+    /// Each [`Looper`] is handed the region through a mailbox of its own.
+    updates: Broadcast<Option<Arc<LoopBuffer>>>,
+    rewound_ns: AtomicU64,
+    tagged: AtomicBool,
+    /// Told which track a tagged loop holds, so it isn't crossfaded out.
+    playlist: Arc<PlaylistControls>,
+}
+
+impl LoopControls {
+    pub(crate) fn new(playlist: Arc<PlaylistControls>) -> Self {
+        LoopControls {
+            region: Mutex::new(None),
+            updates: Broadcast::new(),
+            rewound_ns: AtomicU64::new(0),
+            tagged: AtomicBool::new(false),
+            playlist,
+        }
+    }
+
+    /// Whether tracks loop between the loop points in their tags.
+    pub(crate) fn tagged(&self) -> bool {
+        self.tagged.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_tagged(&self, enabled: bool) {
+        self.tagged.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) fn region(&self) -> Option<Arc<LoopBuffer>> {
+        self.region.locked().clone()
+    }
+
+    pub(crate) fn set_region(&self, region: Option<LoopBuffer>) {
+        let mut current = self.region.locked();
+        *current = region.map(Arc::new);
+        self.updates.post(&current);
+    }
+
+    /// Total track time skipped backwards by loop wraps so far.
+    pub(crate) fn rewound(&self) -> Duration {
+        Duration::from_nanos(self.rewound_ns.load(Ordering::Acquire))
+    }
+
+    fn rewind(&self, length: Duration) {
+        self.rewound_ns
+            .fetch_add(length.as_nanos() as u64, Ordering::Release);
+    }
+}
+
+/// A loop from the track's tags, recorded the first time it plays through
+/// from its start so that each wrap replays it from memory.
+struct TaggedLoop {
+    start: u64,
+    end: u64,
+    recorded: Vec<f32>,
+    /// Whether `recorded` runs unbroken from the loop's first frame.
+    recording: bool,
+    buffer: Option<Arc<LoopBuffer>>,
+}
+
+/// Plays the decoder, replaying the loop region from memory each time
+/// playback reaches its end.
+///
+/// While looping the decoder sits untouched at the loop end, so clearing
+/// the region lets the current pass finish and carries on from there.
+///
+/// With looping from tags on and no region set on the track, the loop
+/// points in its tags loop the same way. A pass that didn't play the loop
+/// from its start has nothing recorded to replay, so it seeks the decoder
+/// back instead.
+pub(crate) struct Looper<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<LoopControls>,
+    track: u64,
+    region: Option<Arc<LoopBuffer>>,
+    updates: Arc<Mailbox<Option<Arc<LoopBuffer>>>>,
+    looping: Option<(Arc<LoopBuffer>, usize)>,
+    tagged: Option<TaggedLoop>,
+    /// Whether the playlist was told the tagged loop holds the track.
+    held: bool,
+    frame: u64,
+    channel: u16,
+    channels: u16,
+}
+
+impl<S> Looper<S>
+where
+    S: Source<Item = f32>,
+{
+    /// `track` is the queue id of the decoded track; regions set on other tracks are ignored.
+    /// `points` are the loop points in its tags, in frames of `source`.
+    pub(crate) fn new(
+        source: S,
+        controls: Arc<LoopControls>,
+        track: u64,
+        points: Option<LoopPoints>,
+    ) -> Self {
+        let longest = MAX_TAGGED_LOOP.as_secs() * source.sample_rate() as u64;
+        let tagged = points
+            .filter(|points| points.end > points.start && points.end - points.start <= longest)
+            .map(|points| TaggedLoop {
+                start: points.start,
+                end: points.end,
+                recorded: Vec::new(),
+                recording: false,
+                buffer: None,
+            });
+        Looper {
+            channels: source.channels().max(1),
+            source,
+            // Subscribed first, so a region set meanwhile is still seen.
+            updates: controls.updates.subscribe(),
+            region: controls.region(),
+            controls,
+            track,
+            looping: None,
+            tagged,
+            held: false,
+            frame: 0,
+            channel: 0,
+        }
+    }
+
+    fn update_region(&mut self) {
+        if let Some(region) = self.updates.take() {
+            self.region = region;
+        }
+    }
+
+    /// Starts replaying `region` from its first sample if the decoder can hand over to it.
+    fn enter_loop(&mut self, ended: bool) -> bool {
+        let Some(region) = &self.region else {
+            return false;
+        };
+        let at_end = self.frame == region.end_frame || (ended && self.frame > region.start_frame);
+        if region.track != self.track
+            || !at_end
+            || region.samples.is_empty()
+            || region.channels != self.source.channels().max(1)
+        {
+            return false;
+        }
+        self.controls.rewind(region.length());
+        self.looping = Some((region.clone(), 0));
+        true
+    }
+
+    /// Whether the tagged loop, if any, is to loop: looping from tags is on
+    /// and no region stands in for it.
+    fn tagged_active(&self) -> bool {
+        self.tagged.is_some()
+            && self.controls.tagged()
+            && self
+                .region
+                .as_ref()
+                .is_none_or(|region| region.track != self.track)
+    }
+
+    /// Starts or stops recording the tagged loop, and wraps from its end
+    /// back to its start. Returns whether it wrapped.
+    fn tagged_frame(&mut self, ended: bool) -> bool {
+        let active = self.tagged_active();
+        let (channels, sample_rate) = (self.source.channels().max(1), self.source.sample_rate());
+        let Some(tagged) = &mut self.tagged else {
+            return false;
+        };
+        if !active {
+            tagged.recording = false;
+            return false;
+        }
+        if tagged.buffer.is_none() && self.frame == tagged.start && !ended {
+            tagged.recorded.clear();
+            // Once per track, the loop's room is taken in one go.
+            let length = (tagged.end - tagged.start) as usize * channels as usize;
+            tagged.recorded.reserve_exact(length);
+            tagged.recording = true;
+            return false;
+        }
+        // A file shorter than its tags say wraps where it ends.
+        let at_end = self.frame == tagged.end
+            || (ended && (tagged.start + 1..tagged.end).contains(&self.frame));
+        if !at_end {
+            return false;
+        }
+        let length =
+            Duration::from_secs_f64((self.frame - tagged.start) as f64 / sample_rate.max(1) as f64);
+        if tagged.recording {
+            tagged.recording = false;
+            tagged.buffer = Some(Arc::new(LoopBuffer::new(
+                self.track,
+                tagged.start,
+                self.frame,
+                channels,
+                sample_rate,
+                mem::take(&mut tagged.recorded),
+            )));
+        }
+        match &tagged.buffer {
+            Some(buffer) => {
+                self.controls.rewind(buffer.length());
+                self.looping = Some((buffer.clone(), 0));
+            }
+            None => {
+                let start = tagged.start as f64 / sample_rate.max(1) as f64;
+                if self
+                    .source
+                    .try_seek(Duration::from_secs_f64(start))
+                    .is_err()
+                {
+                    return false;
+                }
+                self.controls.rewind(length);
+                self.frame = tagged.start;
+                tagged.recorded.clear();
+                tagged.recording = true;
+            }
+        }
+        true
+    }
+
+    /// Tells the playlist whether the tagged loop holds the track, so that
+    /// it plays on past its length.
+    fn update_held(&mut self) {
+        let held = self.tagged_active()
+            && self
+                .tagged
+                .as_ref()
+                .is_some_and(|tagged| self.frame <= tagged.end);
+        if held != self.held {
+            self.held = held;
+            self.controls.playlist.hold(self.track, held);
+        }
+    }
+
+    fn next_frame(&mut self) {
+        self.update_region();
+        let tagged_active = self.tagged_active();
+        if let Some((buffer, index)) = &mut self.looping {
+            if *index < buffer.samples.len() {
+                return;
+            }
+            let again = match (&self.region, &self.tagged) {
+                (Some(region), _) if Arc::ptr_eq(region, buffer) => true,
+                (
+                    _,
+                    Some(TaggedLoop {
+                        buffer: Some(tagged),
+                        ..
+                    }),
+                ) if Arc::ptr_eq(tagged, buffer) => tagged_active,
+                _ => false,
+            };
+            if again {
+                self.controls.rewind(buffer.length());
+                *index = 0;
+                return;
+            }
+            self.looping = None;
+        }
+        if !self.enter_loop(false) {
+            self.tagged_frame(false);
+        }
+        self.update_held();
+    }
+}
+
+impl<S> Drop for Looper<S>
+where
+    S: Source<Item = f32>,
+{
+    fn drop(&mut self) {
+        if self.held {
+            self.controls.playlist.hold(self.track, false);
+        }
+    }
+}
+
+impl<S> Iterator for Looper<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.next_frame();
+        }
+
+        let sample = match &mut self.looping {
+            Some((buffer, index)) => {
+                self.channels = buffer.channels;
+                let sample = buffer.samples[*index];
+                *index += 1;
+                sample
+            }
+            None => {
+                if self.channel == 0 {
+                    self.channels = self.source.channels().max(1);
+                }
+                match self.source.next() {
+                    Some(sample) => {
+                        if let Some(tagged) = self.tagged.as_mut().filter(|tagged| tagged.recording)
+                        {
+                            tagged.recorded.push(sample);
+                        }
+                        sample
+                    }
+                    None if self.channel == 0
+                        && (self.enter_loop(true) || self.tagged_frame(true)) =>
+                    {
+                        return self.next()
+                    }
+                    None => return None,
+                }
+            }
+        };
+
+        self.channel += 1;
+        if self.channel >= self.channels {
+            self.channel = 0;
+            if self.looping.is_none() {
+                self.frame += 1;
+            }
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for Looper<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        match &self.looping {
+            Some((buffer, index)) => Some(buffer.samples.len() - index),
+            None => self.source.current_frame_len(),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match &self.looping {
+            Some((buffer, _)) => buffer.channels,
+            None => self.source.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let frame = (pos.as_secs_f64() * self.source.sample_rate() as f64).round() as u64;
+        let active = self.tagged_active();
+        if let Some(tagged) = &mut self.tagged {
+            tagged.recording = false;
+            // Into a loop already recorded: replay it from there, with the
+            // decoder waiting at its end as on a wrap.
+            if let Some(buffer) = tagged.buffer.as_ref().filter(|_| active) {
+                if (tagged.start..tagged.end).contains(&frame) {
+                    self.source.try_seek(buffer.end)?;
+                    let index = (frame - tagged.start) as usize * buffer.channels as usize;
+                    self.looping = Some((buffer.clone(), index));
+                    self.frame = tagged.end;
+                    self.channel = 0;
+                    return Ok(());
+                }
+            }
+        }
+        self.source.try_seek(pos)?;
+        self.looping = None;
+        self.frame = frame;
+        self.channel = 0;
+        Ok(())
+    }
+}
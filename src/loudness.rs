@@ -0,0 +1,549 @@
+use crate::{
+    decode::{self, DecoderBackend},
+    error::PlayerError,
+    format::{json, Value},
+    gain::{db_to_linear, linear_to_db, GainControls},
+    lock::Lock,
+};
+use rodio::Source;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How quickly a new normalization gain is faded in, e.g. when a scan
+/// finishes after its track has started.
+const NORMALIZE_RAMP: Duration = Duration::from_millis(200);
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Short-term blocks below the mean by more than this are left out of the
+/// loudness range (EBU Tech 3342).
+const RANGE_GATE_LU: f64 = -20.0;
+/// 100 ms hops in a 3 s short-term block.
+const SHORT_TERM_HOPS: usize = 30;
+
+/// Oversampling factor and taps per phase of the true-peak interpolator.
+const TRUE_PEAK_FACTOR: usize = 4;
+const TRUE_PEAK_TAPS: usize = 12;
+
+/// Measured loudness of one track.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackLoudness {
+    /// Gated integrated loudness per EBU R128.
+    pub integrated_lufs: f32,
+    /// Highest sample magnitude in dBFS.
+    pub peak_db: f32,
+}
+
+/// Loudness of one file as [`analyze`] measures it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessReport {
+    /// Gated integrated loudness per EBU R128.
+    pub integrated_lufs: f32,
+    /// Spread of the short-term loudness per EBU Tech 3342, in LU. Zero for
+    /// files too short for a 3 s block.
+    pub loudness_range_lu: f32,
+    /// Highest sample magnitude in dBFS.
+    pub sample_peak_db: f32,
+    /// Highest magnitude between samples too, found by oversampling four
+    /// times, in dBTP.
+    pub true_peak_db: f32,
+}
+
+impl LoudnessReport {
+    /// The report as a JSON object; silence, with no loudness to speak of,
+    /// comes out as null.
+    pub fn to_json(&self) -> String {
+        let value = Value::table()
+            .with("integrated_lufs", self.integrated_lufs)
+            .with("loudness_range_lu", self.loudness_range_lu)
+            .with("sample_peak_db", self.sample_peak_db)
+            .with("true_peak_db", self.true_peak_db);
+        json::to_string(&value)
+    }
+}
+
+/// What normalization needs, so a report made ahead of time can stand in
+/// for a scan; see [`AudioPlayer::set_track_loudness`].
+///
+/// [`AudioPlayer::set_track_loudness`]: crate::AudioPlayer::set_track_loudness
+impl From<LoudnessReport> for TrackLoudness {
+    fn from(report: LoudnessReport) -> Self {
+        TrackLoudness {
+            integrated_lufs: report.integrated_lufs,
+            peak_db: report.sample_peak_db,
+        }
+    }
+}
+
+/// Decodes the file at `path` and measures its loudness, range and peaks,
+/// faster than real time and without any audio output.
+pub fn analyze(path: impl AsRef<Path>) -> Result<LoudnessReport, PlayerError> {
+    let decoder = decode::open_file(path.as_ref(), DecoderBackend::Auto)?;
+    let mut meter = LoudnessMeter::new(decoder.channels(), decoder.sample_rate());
+    let mut true_peak = TruePeak::new(decoder.channels());
+    for sample in decoder {
+        meter.push(sample);
+        true_peak.push(sample);
+    }
+    let loudness = meter.loudness();
+    Ok(LoudnessReport {
+        integrated_lufs: loudness.integrated_lufs,
+        loudness_range_lu: meter.loudness_range() as f32,
+        sample_peak_db: loudness.peak_db,
+        true_peak_db: linear_to_db(true_peak.peak()),
+    })
+}
+
+impl TrackLoudness {
+    /// Decodes the file at `path` and measures it, faster than real time and
+    /// without any audio output.
+    pub fn measure(path: impl AsRef<Path>) -> Result<Self, PlayerError> {
+        let decoder = decode::open_file(path.as_ref(), DecoderBackend::Auto)?;
+        let mut meter = LoudnessMeter::new(decoder.channels(), decoder.sample_rate());
+        for sample in decoder {
+            meter.push(sample);
+        }
+        Ok(meter.loudness())
+    }
+
+    /// Gain that brings the track to `target_lufs`, lowered if needed so its
+    /// peak stays at or below full scale.
+    pub fn gain_to(&self, target_lufs: f32) -> f32 {
+        if !self.integrated_lufs.is_finite() {
+            return 0.0;
+        }
+        let gain = target_lufs - self.integrated_lufs;
+        if self.peak_db.is_finite() {
+            gain.min(-self.peak_db)
+        } else {
+            gain
+        }
+    }
+}
+
+/// Gated, K-weighted loudness measurement (ITU-R BS.1770) over 400 ms blocks
+/// with 75% overlap.
+pub(crate) struct LoudnessMeter {
+    filters: Vec<[Stage; 2]>,
+    weights: Vec<f64>,
+    hop_frames: usize,
+    hop: Vec<f64>,
+    hop_fill: usize,
+    hops: [f64; 4],
+    hop_count: usize,
+    blocks: Vec<f64>,
+    /// Mean power of every hop so far, for the short-term blocks.
+    hop_powers: Vec<f64>,
+    total: f64,
+    total_frames: usize,
+    channel: usize,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub(crate) fn new(channels: u16, sample_rate: u32) -> Self {
+        let mut meter = LoudnessMeter {
+            filters: Vec::new(),
+            weights: Vec::new(),
+            hop_frames: 1,
+            hop: Vec::new(),
+            hop_fill: 0,
+            hops: [0.0; 4],
+            hop_count: 0,
+            blocks: Vec::new(),
+            hop_powers: Vec::new(),
+            total: 0.0,
+            total_frames: 0,
+            channel: 0,
+            peak: 0.0,
+        };
+        meter.start_file(channels, sample_rate);
+        meter
+    }
+
+    /// Carries on measuring in another file, of `channels` at
+    /// `sample_rate`, so the loudness comes out as that of the files
+    /// together, as for an album. No block spans the two.
+    pub(crate) fn start_file(&mut self, channels: u16, sample_rate: u32) {
+        let channels = channels.max(1) as usize;
+        let k_weighting = || Stage::k_weighting(sample_rate as f64);
+        // Surround channels count extra and the LFE not at all, in 5.0, 5.1
+        // and 7.1 as WAV and FLAC order them.
+        let weights = (0..channels)
+            .map(|channel| match (channels, channel) {
+                (6 | 8, 3) => 0.0,
+                (5, 3 | 4) | (6, 4 | 5) | (8, 4..=7) => 1.41,
+                _ => 1.0,
+            })
+            .collect();
+        self.filters = (0..channels).map(|_| k_weighting()).collect();
+        self.weights = weights;
+        self.hop_frames = (sample_rate as usize / 10).max(1);
+        self.hop = vec![0.0; channels];
+        self.hop_fill = 0;
+        self.hop_count = 0;
+        self.channel = 0;
+    }
+
+    /// Takes interleaved samples, one at a time.
+    pub(crate) fn push(&mut self, sample: f32) {
+        self.peak = self.peak.max(sample.abs());
+        let [shelf, high_pass] = &mut self.filters[self.channel];
+        let weighted = high_pass.process(shelf.process(sample as f64));
+        self.hop[self.channel] += weighted * weighted;
+
+        self.channel += 1;
+        if self.channel < self.filters.len() {
+            return;
+        }
+        self.channel = 0;
+        self.hop_fill += 1;
+        if self.hop_fill < self.hop_frames {
+            return;
+        }
+
+        let power = self
+            .hop
+            .iter()
+            .zip(&self.weights)
+            .map(|(sum, weight)| sum * weight)
+            .sum::<f64>();
+        self.total += power;
+        self.total_frames += self.hop_fill;
+        self.hop.fill(0.0);
+        self.hop_fill = 0;
+
+        self.hops[self.hop_count % 4] = power / self.hop_frames as f64;
+        self.hop_powers.push(power / self.hop_frames as f64);
+        self.hop_count += 1;
+        if self.hop_count >= 4 {
+            self.blocks.push(self.hops.iter().sum::<f64>() / 4.0);
+        }
+    }
+
+    /// Integrated loudness so far. Input shorter than one gating block is
+    /// measured as a whole instead.
+    pub(crate) fn loudness(&self) -> TrackLoudness {
+        let integrated = if self.blocks.is_empty() {
+            let partial = self
+                .hop
+                .iter()
+                .zip(&self.weights)
+                .map(|(sum, weight)| sum * weight)
+                .sum::<f64>();
+            let frames = self.total_frames + self.hop_fill;
+            if frames == 0 {
+                f64::NEG_INFINITY
+            } else {
+                lufs((self.total + partial) / frames as f64)
+            }
+        } else {
+            let gated_mean = |threshold: f64| {
+                let gated = self
+                    .blocks
+                    .iter()
+                    .filter(|&&power| lufs(power) > threshold)
+                    .collect::<Vec<_>>();
+                if gated.is_empty() {
+                    None
+                } else {
+                    Some(gated.iter().copied().sum::<f64>() / gated.len() as f64)
+                }
+            };
+            match gated_mean(ABSOLUTE_GATE_LUFS) {
+                Some(mean) => {
+                    gated_mean(lufs(mean) + RELATIVE_GATE_LU).map_or(f64::NEG_INFINITY, lufs)
+                }
+                None => f64::NEG_INFINITY,
+            }
+        };
+        TrackLoudness {
+            integrated_lufs: integrated as f32,
+            peak_db: linear_to_db(self.peak),
+        }
+    }
+
+    /// Loudness range so far in LU: the spread between the 10th and 95th
+    /// percentile of the gated 3 s short-term loudness.
+    pub(crate) fn loudness_range(&self) -> f64 {
+        let mut short_term = self
+            .hop_powers
+            .windows(SHORT_TERM_HOPS)
+            .map(|hops| hops.iter().sum::<f64>() / SHORT_TERM_HOPS as f64)
+            .filter(|&power| lufs(power) > ABSOLUTE_GATE_LUFS)
+            .collect::<Vec<_>>();
+        if short_term.is_empty() {
+            return 0.0;
+        }
+        let mean = short_term.iter().sum::<f64>() / short_term.len() as f64;
+        let gate = lufs(mean) + RANGE_GATE_LU;
+        short_term.retain(|&power| lufs(power) > gate);
+        short_term.sort_by(f64::total_cmp);
+        let percentile = |p: f64| {
+            let index = (p * (short_term.len() - 1) as f64).round() as usize;
+            lufs(short_term[index])
+        };
+        percentile(0.95) - percentile(0.10)
+    }
+}
+
+/// Peak of the signal reconstructed between samples (ITU-R BS.1770 annex
+/// 2), per channel through a windowed-sinc polyphase interpolator.
+struct TruePeak {
+    phases: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_FACTOR],
+    /// The last samples of each channel, newest first.
+    history: Vec<[f32; TRUE_PEAK_TAPS]>,
+    channel: usize,
+    peak: f32,
+}
+
+impl TruePeak {
+    fn new(channels: u16) -> Self {
+        let length = TRUE_PEAK_FACTOR * TRUE_PEAK_TAPS;
+        let center = (length - 1) as f64 / 2.0;
+        let mut phases = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_FACTOR];
+        for n in 0..length {
+            let t = (n as f64 - center) / TRUE_PEAK_FACTOR as f64;
+            let sinc = if t == 0.0 {
+                1.0
+            } else {
+                (std::f64::consts::PI * t).sin() / (std::f64::consts::PI * t)
+            };
+            let x = 2.0 * std::f64::consts::PI * n as f64 / (length - 1) as f64;
+            let blackman = 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos();
+            phases[n % TRUE_PEAK_FACTOR][n / TRUE_PEAK_FACTOR] = (sinc * blackman) as f32;
+        }
+        // Each phase passes DC at unity, so a constant level reads as itself.
+        for phase in &mut phases {
+            let sum = phase.iter().sum::<f32>();
+            phase.iter_mut().for_each(|tap| *tap /= sum);
+        }
+        TruePeak {
+            phases,
+            history: vec![[0.0; TRUE_PEAK_TAPS]; channels.max(1) as usize],
+            channel: 0,
+            peak: 0.0,
+        }
+    }
+
+    /// Takes interleaved samples, one at a time.
+    fn push(&mut self, sample: f32) {
+        let history = &mut self.history[self.channel];
+        history.copy_within(..TRUE_PEAK_TAPS - 1, 1);
+        history[0] = sample;
+        self.peak = self.peak.max(sample.abs());
+        for phase in &self.phases {
+            let value = phase
+                .iter()
+                .zip(history.iter())
+                .map(|(a, b)| a * b)
+                .sum::<f32>();
+            self.peak = self.peak.max(value.abs());
+        }
+        self.channel = (self.channel + 1) % self.history.len();
+    }
+
+    fn peak(&self) -> f32 {
+        self.peak
+    }
+}
+
+/// One biquad of the K-weighting filter, in double precision since the
+/// high-pass pole sits very close to the unit circle.
+#[derive(Clone, Copy)]
+struct Stage {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Stage {
+    /// The BS.1770 pre-filter and RLB high-pass, derived for `sample_rate`
+    /// from their analog prototypes.
+    fn k_weighting(sample_rate: f64) -> [Stage; 2] {
+        let stage = |b: [f64; 3], a: [f64; 2]| Stage {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        };
+
+        let (frequency, gain_db, q) = (
+            1_681.974_450_955_533,
+            3.999_843_853_973_347,
+            0.707_175_236_955_419_6,
+        );
+        let k = (std::f64::consts::PI * frequency / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = stage(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let (frequency, q) = (38.135_470_876_024_44, 0.500_327_037_323_877_3);
+        let k = (std::f64::consts::PI * frequency / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = stage(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+        [shelf, high_pass]
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+fn lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// [`TrackLoudness::measure`] for the scan thread, which has no use for why
+/// a file couldn't be measured.
+pub(crate) fn scan(path: &PathBuf) -> Option<TrackLoudness> {
+    TrackLoudness::measure(path).ok()
+}
+
+/// The loudness of the files at `paths` played one after another, in one
+/// pass; `None` if any of them can't be read.
+pub(crate) fn scan_album(paths: &[PathBuf]) -> Option<TrackLoudness> {
+    let mut meter: Option<LoudnessMeter> = None;
+    for path in paths {
+        let decoder = decode::open_file(path, DecoderBackend::Auto).ok()?;
+        let (channels, sample_rate) = (decoder.channels(), decoder.sample_rate());
+        match &mut meter {
+            Some(meter) => meter.start_file(channels, sample_rate),
+            None => meter = Some(LoudnessMeter::new(channels, sample_rate)),
+        }
+        let meter = meter.as_mut()?;
+        for sample in decoder {
+            meter.push(sample);
+        }
+    }
+    Some(meter?.loudness())
+}
+
+struct Normalized {
+    measured: Option<TrackLoudness>,
+    scanning: bool,
+    gain: Arc<GainControls>,
+}
+
+/// Normalization target and per-track measurements, shared between the
+/// player, its scan thread and each track's gain stage.
+pub(crate) struct LoudnessControls {
+    target: Mutex<Option<f32>>,
+    tracks: Mutex<HashMap<u64, Normalized>>,
+}
+
+impl LoudnessControls {
+    pub(crate) fn new() -> Self {
+        LoudnessControls {
+            target: Mutex::new(None),
+            tracks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn target(&self) -> Option<f32> {
+        *self.target.locked()
+    }
+
+    pub(crate) fn set_target(&self, target: Option<f32>) {
+        *self.target.locked() = target;
+        for track in self.tracks.locked().values() {
+            track
+                .gain
+                .set_target(db_to_linear(gain_db(target, track.measured)));
+        }
+    }
+
+    /// The gain stage for track `id`; normalization is applied through it
+    /// as soon as the track has been measured.
+    pub(crate) fn gain(&self, id: u64) -> Arc<GainControls> {
+        self.entry(id, |track| track.gain.clone())
+    }
+
+    pub(crate) fn measured(&self, id: u64) -> Option<TrackLoudness> {
+        self.entry(id, |track| track.measured)
+    }
+
+    pub(crate) fn gain_db(&self, id: u64) -> f32 {
+        let target = self.target();
+        self.entry(id, |track| gain_db(target, track.measured))
+    }
+
+    /// Returns whether track `id` still needs a scan, and marks it as under way.
+    pub(crate) fn start_scan(&self, id: u64) -> bool {
+        self.entry(id, |track| {
+            let start = track.measured.is_none() && !track.scanning;
+            track.scanning |= start;
+            start
+        })
+    }
+
+    pub(crate) fn set_measured(&self, id: u64, measured: Option<TrackLoudness>) {
+        let target = self.target();
+        self.entry(id, |track| {
+            track.measured = measured;
+            track.scanning = false;
+            track
+                .gain
+                .set_target(db_to_linear(gain_db(target, measured)));
+        });
+    }
+
+    fn entry<T>(&self, id: u64, f: impl FnOnce(&mut Normalized) -> T) -> T {
+        let mut tracks = self.tracks.locked();
+        f(tracks.entry(id).or_insert_with(|| Normalized {
+            measured: None,
+            scanning: false,
+            gain: Arc::new(GainControls::new(1.0, NORMALIZE_RAMP)),
+        }))
+    }
+}
+
+fn gain_db(target: Option<f32>, measured: Option<TrackLoudness>) -> f32 {
+    match (target, measured) {
+        (Some(target), Some(measured)) => measured.gain_to(target),
+        _ => 0.0,
+    }
+}
+
+/// Measures queued files one after another on a thread of their own,
+/// calling `measured` after each. The thread exits once the returned sender
+/// is dropped.
+pub(crate) fn spawn_scanner(
+    controls: Arc<LoudnessControls>,
+    measured: impl Fn() + Send + 'static,
+) -> Sender<(u64, PathBuf)> {
+    let (jobs, receiver) = mpsc::channel::<(u64, PathBuf)>();
+    thread::spawn(move || {
+        for (id, path) in receiver {
+            controls.set_measured(id, scan(&path));
+            measured();
+        }
+    });
+    jobs
+}
@@ -0,0 +1,191 @@
+use crate::lock::Lock;
+use std::{
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+/// Hands the latest of a value to one reader on the audio thread without
+/// either side ever waiting: a bounded channel of one, where a value posted
+/// before the last was taken replaces it.
+///
+/// Each value is boxed, and the box belongs to whichever side last swapped
+/// it in or out of the slot, so no reader can be left holding one that is
+/// being freed.
+pub(crate) struct Mailbox<T> {
+    slot: AtomicPtr<T>,
+}
+
+// SAFETY: the mailbox only moves values of `T` from one thread to another,
+// each owned by exactly one side at a time.
+unsafe impl<T: Send> Send for Mailbox<T> {}
+unsafe impl<T: Send> Sync for Mailbox<T> {}
+
+impl<T> Mailbox<T> {
+    pub(crate) fn new() -> Self {
+        Mailbox {
+            slot: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Leaves `value` for the reader, dropping any it hadn't taken yet.
+    pub(crate) fn post(&self, value: T) {
+        self.post_boxed(Box::new(value));
+    }
+
+    /// Like [`Mailbox::post`], for a value already boxed, so the audio side
+    /// can post without allocating.
+    pub(crate) fn post_boxed(&self, value: Box<T>) {
+        let old = self.slot.swap(Box::into_raw(value), Ordering::AcqRel);
+        // SAFETY: a non-null pointer in the slot came from `Box::into_raw`,
+        // and swapping it out made it ours alone.
+        drop(unsafe { unbox(old) });
+    }
+
+    /// The value posted last, if there has been one since the last call.
+    pub(crate) fn take(&self) -> Option<T> {
+        self.take_boxed().map(|value| *value)
+    }
+
+    /// Like [`Mailbox::take`], keeping the box to post again. Only looking
+    /// costs nothing more than a load while the mailbox is empty.
+    pub(crate) fn take_boxed(&self) -> Option<Box<T>> {
+        if self.slot.load(Ordering::Acquire).is_null() {
+            return None;
+        }
+        let value = self.slot.swap(ptr::null_mut(), Ordering::AcqRel);
+        // SAFETY: as in `post_boxed`.
+        unsafe { unbox(value) }
+    }
+}
+
+impl<T> Drop for Mailbox<T> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+/// # Safety
+///
+/// `value` must be null, or from `Box::into_raw` and owned by the caller.
+unsafe fn unbox<T>(value: *mut T) -> Option<Box<T>> {
+    (!value.is_null()).then(|| Box::from_raw(value))
+}
+
+/// Posts every change to a [`Mailbox`] per audio-side stage following it,
+/// such as each track's equalizer. Only the posting side takes its lock.
+pub(crate) struct Broadcast<T> {
+    mailboxes: Mutex<Vec<Weak<Mailbox<T>>>>,
+}
+
+impl<T: Clone> Broadcast<T> {
+    pub(crate) fn new() -> Self {
+        Broadcast {
+            mailboxes: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A mailbox for the changes posted from now on, until it is dropped.
+    pub(crate) fn subscribe(&self) -> Arc<Mailbox<T>> {
+        let mailbox = Arc::new(Mailbox::new());
+        self.mailboxes.locked().push(Arc::downgrade(&mailbox));
+        mailbox
+    }
+
+    pub(crate) fn post(&self, value: &T) {
+        self.mailboxes
+            .locked()
+            .retain(|mailbox| match mailbox.upgrade() {
+                Some(mailbox) => {
+                    mailbox.post(value.clone());
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicBool, AtomicUsize},
+        thread,
+    };
+
+    const WRITERS: usize = 4;
+    const POSTS: u64 = 20_000;
+
+    /// Which writer posted it and how many it had posted before, counting
+    /// itself going once dropped.
+    struct Posted {
+        writer: usize,
+        sequence: u64,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    impl Drop for Posted {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn a_reader_sees_each_writer_in_order_and_every_value_is_dropped_once() {
+        let mailbox = Arc::new(Mailbox::<Posted>::new());
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (mailbox, done) = (mailbox.clone(), done.clone());
+            thread::spawn(move || {
+                let mut last = [None; WRITERS];
+                let mut taken = 0;
+                loop {
+                    let finished = done.load(Ordering::Acquire);
+                    while let Some(posted) = mailbox.take_boxed() {
+                        let last = &mut last[posted.writer];
+                        assert!(
+                            last.is_none_or(|last| posted.sequence > last),
+                            "writer {} went back to {} from {last:?}",
+                            posted.writer,
+                            posted.sequence
+                        );
+                        *last = Some(posted.sequence);
+                        taken += 1;
+                    }
+                    if finished {
+                        return (last, taken);
+                    }
+                }
+            })
+        };
+        let writers = (0..WRITERS)
+            .map(|writer| {
+                let (mailbox, dropped) = (mailbox.clone(), dropped.clone());
+                thread::spawn(move || {
+                    for sequence in 0..POSTS {
+                        mailbox.post(Posted {
+                            writer,
+                            sequence,
+                            dropped: dropped.clone(),
+                        });
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        writers
+            .into_iter()
+            .for_each(|writer| writer.join().unwrap());
+        done.store(true, Ordering::Release);
+        let (last, taken) = reader.join().unwrap();
+
+        // Whatever the reader missed was replaced, not lost for good: the
+        // last post of one writer or another is always left to be taken.
+        assert!(taken > 0);
+        assert!(last.contains(&Some(POSTS - 1)), "{last:?}");
+        drop(mailbox);
+        assert_eq!(dropped.load(Ordering::Relaxed), WRITERS * POSTS as usize);
+    }
+}
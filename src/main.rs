@@ -1,229 +1,1489 @@
-use rodio::{Decoder, OutputStream, Sink, Source};
+use fullyrustaudio::{
+    analyze, db_to_linear, default_socket_path, probe,
+    render::{self, BatchOptions, BatchOutcome, RenderOptions},
+    send_command,
+    spectrogram::{self, SpectrogramOptions},
+    AudioEngine, AudioPlayer, Backend, Bookmarks, ChainConfig, ControlCommand, ControlServer,
+    CueSheet, DecoderBackend, Dither, EqSettings, GeneratorSettings, History, HistoryEntry,
+    Latency, OutputConfig, OutputFormat, PinkNoise, PlayerConfig, PlayerError, Playlist,
+    ReplayGainMode, ResampleQuality, SineWave, StreamConfig, SweptSine, TrackMetadata, WhiteNoise,
+    BAND_COUNT, DEFAULT_HISTORY_LIMIT, STDIN_PATH, THIRD_OCTAVE_BAND_COUNT,
+};
+use rodio::decoder::DecoderError;
 use std::{
+    env,
     error::Error,
-    f32::consts::PI,
-    fs::File,
-    io::BufReader,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
-    },
-    time::{Instant, Duration},
+    fmt,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-struct AudioPlayer {
-    sink: Arc<Mutex<Sink>>,
-    duration: Duration,
-    progress: Arc<Mutex<Duration>>,
-    eq_enabled: Arc<AtomicBool>,
-    is_playing: Arc<AtomicBool>,
-    last_update: Arc<Mutex<Instant>>,
-}
-
-impl AudioPlayer {
-    fn get_playback_position(&self) -> Duration {
-        let mut progress = self.progress.lock().unwrap();
-        let mut last_update = self.last_update.lock().unwrap();
-        
-        if self.is_playing.load(Ordering::Relaxed) {
-            let now = Instant::now();
-            let elapsed = now.duration_since(*last_update);
-            *progress += elapsed;
-            *last_update = now;
-        }
-        
-        *progress
-    }
-
-    fn play(&self) {
-        self.sink.lock().unwrap().play();
-        self.is_playing.store(true, Ordering::Relaxed);
-        *self.last_update.lock().unwrap() = Instant::now();
-    }
-
-    fn pause(&self) {
-        self.sink.lock().unwrap().pause();
-        self.is_playing.store(false, Ordering::Relaxed);
-        self.get_playback_position(); 
-    }
-}
-
-struct Equalizer<S>
-where
-    S: Source<Item = f32>,
-{
-    source: S,
-    filters: Vec<BiquadFilter>,
-}
-
-struct BiquadFilter {
-    b0: f32,
-    b1: f32,
-    b2: f32,
-    a1: f32,
-    a2: f32,
-    x1: f32,
-    x2: f32,
-    y1: f32,
-    y2: f32,
-}
-
-impl BiquadFilter {
-    fn new(frequency: f32, q: f32, gain: f32, sample_rate: u32) -> Self {
-        let omega = 2.0 * PI * frequency / sample_rate as f32;
-        let alpha = omega.sin() / (2.0 * q);
-        let a = 10.0f32.powf(gain / 40.0);
-
-        let b0 = 1.0 + alpha * a;
-        let b1 = -2.0 * omega.cos();
-        let b2 = 1.0 - alpha * a;
-        let a0 = 1.0 + alpha / a;
-        let a1 = -2.0 * omega.cos();
-        let a2 = 1.0 - alpha / a;
-
-        BiquadFilter {
-            b0: b0 / a0,
-            b1: b1 / a0,
-            b2: b2 / a0,
-            a1: a1 / a0,
-            a2: a2 / a0,
-            x1: 0.0,
-            x2: 0.0,
-            y1: 0.0,
-            y2: 0.0,
-        }
-    }
-
-    fn process(&mut self, input: f32) -> f32 {
-        let output = self.b0 * input + self.b1 * self.x1 + self.b2 * self.x2
-            - self.a1 * self.y1
-            - self.a2 * self.y2;
-        self.x2 = self.x1;
-        self.x1 = input;
-        self.y2 = self.y1;
-        self.y1 = output;
-        output
-    }
-}
-
-impl<S> Equalizer<S>
-where
-    S: Source<Item = f32>,
-{
-    fn new(source: S, gains: Vec<f32>) -> Self {
-        let sample_rate = source.sample_rate();
-        let frequencies = [
-            32.0, 64.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
-        ];
-        let filters = frequencies
-            .iter()
-            .zip(gains.iter())
-            .map(|(&freq, &gain)| BiquadFilter::new(freq, 1.41, gain, sample_rate))
-            .collect();
-
-        Equalizer { source, filters }
-    }
-}
-
-impl<S> Iterator for Equalizer<S>
-where
-    S: Source<Item = f32>,
-{
-    type Item = f32;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.source.next().map(|sample| {
-            self.filters
-                .iter_mut()
-                .fold(sample, |s, filter| filter.process(s))
-        })
+mod status;
+mod terminal;
+
+const USAGE: &str = "usage: fullyrustaudio play <path>... [--eq \"g1,g2,...,g10\" | --eq-file <eq.toml|eq.json|ParametricEQ.txt>] [--chain <chain.json>] [--volume <dB>] [--backend <name>] [--device <name>] [--latency <mode>] [--buffer-frames <n>] [--sample-rate <Hz>] [--sample-format <name>] [--resample <quality>] [--dither <mode>] [--decoder <name>] [--replaygain <mode>] [--resume] [--bookmarks <file>] [--history <file>] [--history-limit <n>] [--config <file>] [--write-config] [--control] [--socket <file>] [--stats] [--quiet]
+       fullyrustaudio info <file> [--json]
+       fullyrustaudio render <input> <output.wav> [--eq ... | --eq-file ...] [--volume <dB>] [--bits 16|24] [--dither <mode>] [--decoder <name>] [--limit] [--force]
+       fullyrustaudio render --batch <input-dir> <output-dir> [--recursive] [--jobs <n>] [<render flags>]
+       fullyrustaudio analyze <file> [--json]
+       fullyrustaudio spectrogram <file> <output.png> [--fft <n>] [--hop <n>] [--window <name>] [--width <px>] [--height <px>] [--floor <dB>] [--no-labels] [--decoder <name>]
+       fullyrustaudio tone <Hz>|white|pink [--to <Hz>] [--seconds <n>] [--db <dBFS>] [--eq ... | --eq-file ...] [--volume <dB>] [<output flags>] [--quiet]
+       fullyrustaudio history [--limit <n>] [--history <file>]
+       fullyrustaudio ctl [--socket <file>] <command> [<args>]
+
+`play` can be left out when the first argument is a path.
+<path> is an audio file, a .cue sheet, an .m3u/.m3u8 playlist, an http:// or https:// URL (through curl), or - for stdin
+  an internet radio stream plays with its now-playing title in the status line, and reconnects if it drops
+info reads the headers only, and asks the output device the format it would play the file at without opening it;
+  --json prints the file's format as JSON
+analyze measures loudness per EBU R128, its range and the sample and true peak; --json prints them as JSON
+spectrogram draws time against log frequency, downmixed to mono, with --fft samples (2048 by default) per transform
+  every --hop samples (512), shaped by a hann, hamming, blackman or rectangular --window, into a --width by --height
+  image (1200 by 600) shading from --floor dB (-120) up to full scale; --no-labels leaves out the axes
+tone plays a sine, swept up or down to --to if given, or noise, for --seconds (5 by default) peaking at --db (-12 by default),
+  through --eq or --eq-file or else no EQ; the output flags are --backend, --device, --latency, --buffer-frames,
+  --sample-rate, --sample-format, --resample and --dither
+render --batch renders every flac, mp3, ogg and wav file into a WAV of the same name, <n> at a time, one per CPU by default;
+  outputs that exist are skipped unless --force (or --overwrite) is given
+--eq takes 10 octave-band gains or 31 third-octave ones, in dB; --volume is in dB too
+--eq-file reads EQ settings as TOML or JSON, or an AutoEq parametric profile from a .txt file
+--chain sets up the EQ and every effect from a JSON chain file, over the config's; --eq and --eq-file take the EQ's place in it
+--backend picks the audio API: alsa, jack, coreaudio, wasapi or asio, where built in, or null to play into nothing; --device names an output device on it
+--latency asks the device for low (about 5 ms), default or safe (about 50 ms) buffering; --buffer-frames asks for <n> frames per buffer
+--sample-rate asks the device for a rate, or else it plays at the first file's where it can, so that isn't resampled;
+  --sample-format asks for f32, i16, i24 (in 32-bit samples) or i32
+--resample converts files at another rate than the device's fast, medium or high (the default, down 120 dB)
+--dither adds off, tpdf or shaped (noise-shaped) dither before samples are rounded to 24 bits or fewer;
+  renders use tpdf unless told otherwise, and output devices off, as float devices always are
+--decoder opens files with rodio's decoders, or with symphonia where built in; auto, the default, picks symphonia for any file it recognizes
+--replaygain plays files by their track or album ReplayGain tags, or off
+--resume picks the first file up where it was left; positions are kept in --bookmarks, by default in the user data directory
+--history keeps each file played past the scrobble threshold, by default in the user data directory, up to
+  --history-limit entries (1000 by default); `history` prints the --limit (20 by default) newest, files since gone marked
+--config reads defaults from another file than config.toml in the user config directory; flags override them
+--write-config saves the settings in effect, config and flags together, to that file and exits
+--control lets `fullyrustaudio ctl` drive the player through --socket, by default in the user runtime directory
+--quiet leaves out the status line shown while playing, as it is whenever standard output isn't a terminal
+--stats prints the first file's format against the device's to standard error, and playback statistics as JSON
+  once playback ends: frames decoded and output, underruns, decode errors, seeks and their latency, and wall-clock
+  play time against audio time
+
+ctl commands: play, pause, toggle, stop, next, previous, seek <[h:]m:ss|seconds>, volume <dB>,
+set-eq <band> <dB>, eq <g1,g2,...>, enqueue <path>, remove <id>, jump <id>, queue, status
+
+exit codes: 0 success, 1 any other failure, 2 bad arguments, 3 file not found, 4 undecodable audio, 5 audio output failure";
+
+const SUBCOMMANDS: [&str; 8] = [
+    "play",
+    "info",
+    "render",
+    "analyze",
+    "spectrogram",
+    "tone",
+    "history",
+    "ctl",
+];
+
+// Exit codes, so scripts can tell failures apart.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_FOUND: i32 = 3;
+const EXIT_DECODE: i32 = 4;
+const EXIT_DEVICE: i32 = 5;
+
+/// Why the program stops early, and the code it exits with.
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Failure {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// `err` after `context`, with the exit code for what kind of error it
+    /// is or was caused by.
+    fn of(context: impl fmt::Display, err: &(dyn Error + 'static)) -> Self {
+        Failure::new(exit_code(err), format!("{context}: {err}"))
+    }
+
+    fn not_found(path: &Path) -> Self {
+        Failure::new(
+            EXIT_NOT_FOUND,
+            format!("file not found: {}", path.display()),
+        )
     }
 }
 
-impl<S> Source for Equalizer<S>
-where
-    S: Source<Item = f32>,
-{
-    fn current_frame_len(&self) -> Option<usize> {
-        self.source.current_frame_len()
+/// Bad arguments.
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure::new(EXIT_USAGE, message)
     }
+}
 
-    fn channels(&self) -> u16 {
-        self.source.channels()
+impl From<&str> for Failure {
+    fn from(message: &str) -> Self {
+        Failure::new(EXIT_USAGE, message)
     }
+}
 
-    fn sample_rate(&self) -> u32 {
-        self.source.sample_rate()
+fn exit_code(err: &(dyn Error + 'static)) -> i32 {
+    let mut next = Some(err);
+    while let Some(err) = next {
+        if let Some(PlayerError::Device(_) | PlayerError::Backend { .. }) = err.downcast_ref() {
+            return EXIT_DEVICE;
+        }
+        if err.is::<DecoderError>() {
+            return EXIT_DECODE;
+        }
+        let missing = err
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::NotFound);
+        if missing {
+            return EXIT_NOT_FOUND;
+        }
+        next = err.source();
     }
+    EXIT_FAILURE
+}
 
-    fn total_duration(&self) -> Option<Duration> {
-        self.source.total_duration()
+struct PlayArgs {
+    paths: Vec<PathBuf>,
+    config: PlayerConfig,
+    /// From `--eq-file`, in place of the config's EQ.
+    eq_file: Option<EqSettings>,
+    /// From `--chain` or the config, set up after the rest of the config.
+    chain: Option<ChainConfig>,
+    resume: bool,
+    bookmarks: Option<PathBuf>,
+    history: Option<PathBuf>,
+    history_limit: Option<usize>,
+    /// Where to listen for `ctl` commands, with `--control`.
+    control: Option<PathBuf>,
+    /// Whether to print [`AudioPlayer::stats`] once playback ends.
+    stats: bool,
+    quiet: bool,
+}
+
+enum Command {
+    Play(Box<PlayArgs>),
+    Info {
+        path: PathBuf,
+        json: bool,
+    },
+    Render {
+        input: PathBuf,
+        output: PathBuf,
+        eq: EqSettings,
+        options: RenderOptions,
+    },
+    RenderBatch {
+        input: PathBuf,
+        output: PathBuf,
+        eq: EqSettings,
+        options: BatchOptions,
+    },
+    Analyze {
+        path: PathBuf,
+        json: bool,
+    },
+    Spectrogram {
+        input: PathBuf,
+        output: PathBuf,
+        options: SpectrogramOptions,
+    },
+    Tone {
+        signal: Signal,
+        seconds: Duration,
+        level_db: f32,
+        /// Only the output, EQ and volume.
+        config: PlayerConfig,
+        eq_file: Option<EqSettings>,
+        quiet: bool,
+    },
+    History {
+        path: PathBuf,
+        limit: usize,
+    },
+    Control {
+        socket: PathBuf,
+        command: ControlCommand,
+    },
+    WriteConfig {
+        path: PathBuf,
+        config: PlayerConfig,
+    },
+}
+
+/// What `tone` plays.
+#[derive(Clone, Copy)]
+enum Signal {
+    Sine(f32),
+    /// From the first frequency to the second.
+    Sweep(f32, f32),
+    White,
+    Pink,
+}
+
+fn parse_args() -> Result<Command, Failure> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let (name, rest) = match args.split_first() {
+        Some((first, rest)) if SUBCOMMANDS.contains(&first.as_str()) => (first.as_str(), rest),
+        Some((first, _)) if first == "-h" || first == "--help" => return Err(USAGE.into()),
+        // Playing is all the program did before it had subcommands.
+        Some((first, _)) if first.starts_with('-') || looks_like_path(first) => ("play", &args[..]),
+        Some((first, _)) => return Err(format!("unknown subcommand '{first}'\n{USAGE}").into()),
+        None => return Err(USAGE.into()),
+    };
+    let rest = rest.to_vec();
+    match name {
+        "play" => parse_play(rest),
+        "info" => parse_file("info", rest).map(|(path, json)| Command::Info { path, json }),
+        "render" => parse_render(rest),
+        "analyze" => {
+            parse_file("analyze", rest).map(|(path, json)| Command::Analyze { path, json })
+        }
+        "spectrogram" => parse_spectrogram(rest),
+        "tone" => parse_tone(rest),
+        "history" => parse_history(rest),
+        _ => parse_ctl(rest),
+    }
+}
+
+/// Whether `arg` reads as a file or URL rather than a misspelt subcommand.
+fn looks_like_path(arg: &str) -> bool {
+    arg == STDIN_PATH || arg.contains(['.', '/', '\\']) || Path::new(arg).exists()
+}
+
+/// The flags of `play` that `render` has too.
+#[derive(Default)]
+struct SharedFlags {
+    eq_gains: Option<Vec<f32>>,
+    eq_file: Option<EqSettings>,
+    volume_db: Option<f32>,
+    backend: Option<Backend>,
+    device: Option<String>,
+    latency: Option<Latency>,
+    buffer_frames: Option<u32>,
+    sample_rate: Option<u32>,
+    sample_format: Option<OutputFormat>,
+    resample: Option<ResampleQuality>,
+    dither: Option<Dither>,
+    decoder: Option<DecoderBackend>,
+}
+
+impl SharedFlags {
+    /// Takes `arg`, and its value from `args`, if it's one of the shared
+    /// flags.
+    fn parse(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = String>,
+    ) -> Result<bool, Failure> {
+        match arg {
+            "--eq" => {
+                let value = args.next().ok_or("--eq requires a value")?;
+                self.eq_gains = Some(parse_gains(&value)?);
+            }
+            "--eq-file" => {
+                let value = args.next().ok_or("--eq-file requires a path")?;
+                let eq = EqSettings::load(&value)
+                    .map_err(|err| Failure::of(format_args!("failed to load {value}"), &err))?;
+                self.eq_file = Some(eq);
+            }
+            "--volume" => {
+                let value = args.next().ok_or("--volume requires a value in dB")?;
+                let volume_db = value
+                    .parse()
+                    .map_err(|_| format!("invalid volume '{value}'"))?;
+                self.volume_db = Some(volume_db);
+            }
+            "--backend" => {
+                let value = args.next().ok_or("--backend requires a name")?;
+                self.backend = Some(value.parse()?);
+            }
+            "--device" => self.device = Some(args.next().ok_or("--device requires a name")?),
+            "--latency" => {
+                let value = args
+                    .next()
+                    .ok_or("--latency requires low, default or safe")?;
+                self.latency = Some(value.parse()?);
+            }
+            "--buffer-frames" => {
+                let value = args.next().ok_or("--buffer-frames requires a number")?;
+                let frames = value
+                    .parse()
+                    .ok()
+                    .filter(|&frames| frames > 0)
+                    .ok_or_else(|| format!("invalid buffer size '{value}'"))?;
+                self.buffer_frames = Some(frames);
+            }
+            "--sample-rate" => {
+                let value = args.next().ok_or("--sample-rate requires a rate in Hz")?;
+                let rate = value
+                    .parse()
+                    .ok()
+                    .filter(|&rate| rate > 0)
+                    .ok_or_else(|| format!("invalid sample rate '{value}'"))?;
+                self.sample_rate = Some(rate);
+            }
+            "--sample-format" => {
+                let value = args
+                    .next()
+                    .ok_or("--sample-format requires f32, i16, i24 or i32")?;
+                self.sample_format = Some(value.parse()?);
+            }
+            "--resample" => {
+                let value = args
+                    .next()
+                    .ok_or("--resample requires fast, medium or high")?;
+                self.resample = Some(value.parse()?);
+            }
+            "--dither" => {
+                let value = args.next().ok_or("--dither requires off, tpdf or shaped")?;
+                self.dither = Some(value.parse()?);
+            }
+            "--decoder" => {
+                let value = args.next().ok_or("--decoder requires a name")?;
+                self.decoder = Some(value.parse()?);
+            }
+            _ => return Ok(false),
+        }
+        if self.eq_gains.is_some() && self.eq_file.is_some() {
+            return Err("--eq and --eq-file can't both be given".into());
+        }
+        Ok(true)
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let (_stream, stream_handle) =
-        OutputStream::try_default().expect("Failed to get default output device");
-    let sink = Sink::try_new(&stream_handle).expect("Failed to create sink");
-    let sink = Arc::new(Mutex::new(sink));
+fn parse_play(args: Vec<String>) -> Result<Command, Failure> {
+    let mut shared = SharedFlags::default();
+    let mut paths = Vec::new();
+    let mut resume = false;
+    let mut bookmarks = None;
+    let mut history = None;
+    let mut history_limit = None;
+    let mut config_path = None;
+    let mut chain_path = None;
+    let mut write_config = false;
+    let mut control = false;
+    let mut socket = None;
+    let mut stats = false;
+    let mut quiet = false;
+    let mut replaygain = None;
 
-    let file = BufReader::new(File::open("outaspace.flac").expect("Failed to open file"));
-    let decoder = Decoder::new(file)
-        .expect("Failed to create decoder")
-        .convert_samples::<f32>();
-    let duration = decoder.total_duration().unwrap_or(Duration::from_secs(0));
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if shared.parse(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--resume" => resume = true,
+            "--replaygain" => {
+                let value = args
+                    .next()
+                    .ok_or("--replaygain requires off, track or album")?;
+                replaygain = Some(value.parse::<ReplayGainMode>()?);
+            }
+            "--bookmarks" => {
+                let value = args.next().ok_or("--bookmarks requires a path")?;
+                bookmarks = Some(PathBuf::from(value));
+            }
+            "--history" => {
+                let value = args.next().ok_or("--history requires a path")?;
+                history = Some(PathBuf::from(value));
+            }
+            "--history-limit" => {
+                let value = args.next().ok_or("--history-limit requires a number")?;
+                let limit = value.parse().ok().filter(|&limit| limit > 0);
+                history_limit =
+                    Some(limit.ok_or_else(|| format!("invalid history limit '{value}'"))?);
+            }
+            "--config" => {
+                let value = args.next().ok_or("--config requires a path")?;
+                config_path = Some(PathBuf::from(value));
+            }
+            "--chain" => {
+                let value = args.next().ok_or("--chain requires a path")?;
+                chain_path = Some(PathBuf::from(value));
+            }
+            "--write-config" => write_config = true,
+            "--control" => control = true,
+            "--socket" => {
+                let value = args.next().ok_or("--socket requires a path")?;
+                socket = Some(PathBuf::from(value));
+            }
+            "--stats" => stats = true,
+            "--quiet" => quiet = true,
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if arg.starts_with('-') && arg != STDIN_PATH => {
+                return Err(format!("unexpected argument '{arg}'\n{USAGE}").into())
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let config_path = config_path.or_else(PlayerConfig::default_path);
+    let mut config = match &config_path {
+        Some(path) => PlayerConfig::load_from(path)
+            .map_err(|err| Failure::of(format_args!("failed to load {}", path.display()), &err))?,
+        None => PlayerConfig::default(),
+    };
+    // Either EQ flag stands in for the config's EQ, and the chain's.
+    let eq_flag = shared.eq_gains.is_some() || shared.eq_file.is_some();
+    if shared.eq_gains.is_some() {
+        config.eq_preset = None;
+    }
+    let config = PlayerConfig {
+        eq_gains: shared.eq_gains.or(config.eq_gains),
+        backend: shared.backend.or(config.backend),
+        device: shared.device.or(config.device),
+        latency: shared.latency.or(config.latency),
+        buffer_frames: shared.buffer_frames.or(config.buffer_frames),
+        sample_rate: shared.sample_rate.or(config.sample_rate),
+        sample_format: shared.sample_format.or(config.sample_format),
+        resample: shared.resample.or(config.resample),
+        dither: shared.dither.or(config.dither),
+        volume_db: shared.volume_db.or(config.volume_db),
+        replaygain: replaygain.or(config.replaygain),
+        decoder: shared.decoder.or(config.decoder),
+        chain: chain_path.or(config.chain),
+        ..config
+    };
+    if write_config {
+        let path = config_path.ok_or("no user config directory to write to; pass --config")?;
+        return Ok(Command::WriteConfig { path, config });
+    }
+
+    if paths.is_empty() {
+        return Err(USAGE.into());
+    }
+    if let Some(path) = paths
+        .iter()
+        .find(|path| url(path).is_none() && *path != Path::new(STDIN_PATH) && !path.is_file())
+    {
+        return Err(Failure::not_found(path));
+    }
+    let chain = match &config.chain {
+        Some(path) => {
+            let mut chain = ChainConfig::load(path).map_err(|err| {
+                Failure::of(format_args!("failed to load {}", path.display()), &err)
+            })?;
+            if eq_flag {
+                chain.eq = None;
+            }
+            Some(chain)
+        }
+        None => None,
+    };
+
+    Ok(Command::Play(Box::new(PlayArgs {
+        paths,
+        config,
+        eq_file: shared.eq_file,
+        chain,
+        resume,
+        bookmarks: bookmarks.or_else(Bookmarks::default_path),
+        history: history.or_else(History::default_path),
+        history_limit,
+        control: control.then(|| socket.unwrap_or_else(default_socket_path)),
+        stats,
+        quiet,
+    })))
+}
+
+fn parse_render(args: Vec<String>) -> Result<Command, Failure> {
+    let mut shared = SharedFlags::default();
+    let mut options = RenderOptions::default();
+    let mut paths = Vec::new();
+    let mut batch = false;
+    let mut batch_options = BatchOptions::default();
+    let mut batch_flag = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if shared.parse(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--bits" => {
+                let value = args.next().ok_or("--bits requires a value")?;
+                options.bits_per_sample = match value.as_str() {
+                    "16" => 16,
+                    "24" => 24,
+                    _ => {
+                        return Err(format!("invalid bit depth '{value}', expected 16 or 24").into())
+                    }
+                };
+            }
+            "--limit" => options.limiter = true,
+            "--force" | "--overwrite" => options.overwrite = true,
+            "--batch" => batch = true,
+            "--recursive" => {
+                batch_options.recursive = true;
+                batch_flag = Some("--recursive");
+            }
+            "--jobs" => {
+                let value = args.next().ok_or("--jobs requires a number")?;
+                batch_options.jobs = value
+                    .parse()
+                    .ok()
+                    .filter(|&jobs| jobs > 0)
+                    .ok_or_else(|| format!("invalid job count '{value}'"))?;
+                batch_flag = Some("--jobs");
+            }
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if arg.starts_with('-') => {
+                return Err(format!("unexpected argument '{arg}'\n{USAGE}").into())
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if shared.backend.is_some()
+        || shared.device.is_some()
+        || shared.latency.is_some()
+        || shared.buffer_frames.is_some()
+    {
+        return Err(
+            "render writes a file, so --backend, --device, --latency and --buffer-frames don't apply"
+                .into(),
+        );
+    }
+
+    if let Some(flag) = batch_flag.filter(|_| !batch) {
+        return Err(format!("{flag} only applies with --batch").into());
+    }
+
+    let [input, output] = <[PathBuf; 2]>::try_from(paths).map_err(|_| match batch {
+        true => format!("render --batch takes an input and an output directory\n{USAGE}"),
+        false => format!("render takes an input and an output path\n{USAGE}"),
+    })?;
+    if batch && !input.is_dir() {
+        return Err(Failure::new(
+            EXIT_NOT_FOUND,
+            format!("directory not found: {}", input.display()),
+        ));
+    }
+    if !batch && !input.is_file() {
+        return Err(Failure::not_found(&input));
+    }
+    // The built-in curve boosts the low end hard, so leave it headroom unless
+    // a settings file says otherwise.
+    let mut eq = EqSettings {
+        auto_headroom: true,
+        ..EqSettings::default()
+    };
+    if let Some(gains) = shared.eq_gains {
+        eq.bands = EqSettings::for_gains(&gains)
+            .map_err(|err| err.to_string())?
+            .bands;
+    }
+    options.volume_db = shared.volume_db.unwrap_or(0.0);
+    options.dither = shared.dither.unwrap_or(options.dither);
+    options.decoder = shared.decoder.unwrap_or(options.decoder);
+    let eq = shared.eq_file.unwrap_or(eq);
+    if batch {
+        return Ok(Command::RenderBatch {
+            input,
+            output,
+            eq,
+            options: BatchOptions {
+                render: options,
+                ..batch_options
+            },
+        });
+    }
+    Ok(Command::Render {
+        input,
+        output,
+        eq,
+        options,
+    })
+}
+
+fn parse_spectrogram(args: Vec<String>) -> Result<Command, Failure> {
+    let mut options = SpectrogramOptions::default();
+    let mut paths = Vec::new();
+    let count = |flag: &str, value: Option<String>| -> Result<u32, Failure> {
+        let value = value.ok_or_else(|| format!("{flag} requires a number"))?;
+        value
+            .parse()
+            .ok()
+            .filter(|&count| count > 0)
+            .ok_or_else(|| format!("invalid value '{value}' for {flag}").into())
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fft" => options.fft_size = count("--fft", args.next())? as usize,
+            "--hop" => options.hop = count("--hop", args.next())? as usize,
+            "--width" => options.width = count("--width", args.next())?,
+            "--height" => options.height = count("--height", args.next())?,
+            "--window" => {
+                let value = args.next().ok_or("--window requires a name")?;
+                options.window = value.parse()?;
+            }
+            "--floor" => {
+                let value = args.next().ok_or("--floor requires a value in dB")?;
+                options.floor_db = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|&db| db < 0.0 && db.is_finite())
+                    .ok_or_else(|| format!("invalid dB floor '{value}', expected below 0"))?;
+            }
+            "--no-labels" => options.labels = false,
+            "--decoder" => {
+                let value = args.next().ok_or("--decoder requires a name")?;
+                options.decoder = value.parse()?;
+            }
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if arg.starts_with('-') => {
+                return Err(format!("unexpected argument '{arg}'\n{USAGE}").into())
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if !options.fft_size.is_power_of_two() {
+        return Err(format!(
+            "invalid FFT size {}, expected a power of two",
+            options.fft_size
+        )
+        .into());
+    }
+    let [input, output] = <[PathBuf; 2]>::try_from(paths)
+        .map_err(|_| format!("spectrogram takes an input file and an output path\n{USAGE}"))?;
+    if !input.is_file() {
+        return Err(Failure::not_found(&input));
+    }
+    Ok(Command::Spectrogram {
+        input,
+        output,
+        options,
+    })
+}
+
+fn parse_tone(args: Vec<String>) -> Result<Command, Failure> {
+    let mut shared = SharedFlags::default();
+    let mut signal = None;
+    let mut to = None;
+    let mut seconds = Duration::from_secs(5);
+    let mut level_db = -12.0;
+    let mut quiet = false;
+
+    let frequency = |value: &str| {
+        value
+            .parse::<f32>()
+            .ok()
+            .filter(|&hz| hz > 0.0 && hz.is_finite())
+            .ok_or_else(|| format!("invalid frequency '{value}'"))
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if shared.parse(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--to" => {
+                let value = args.next().ok_or("--to requires a frequency in Hz")?;
+                to = Some(frequency(&value)?);
+            }
+            "--seconds" => {
+                let value = args.next().ok_or("--seconds requires a value")?;
+                seconds = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|&seconds| seconds > 0.0)
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| format!("invalid length '{value}'"))?;
+            }
+            "--db" => {
+                let value = args.next().ok_or("--db requires a level in dBFS")?;
+                level_db = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|&db| db <= 0.0)
+                    .ok_or_else(|| format!("invalid level '{value}', expected 0 dBFS or below"))?;
+            }
+            "--quiet" => quiet = true,
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if arg.starts_with('-') && arg.parse::<f32>().is_err() => {
+                return Err(format!("unexpected argument '{arg}'\n{USAGE}").into())
+            }
+            _ if signal.is_some() => return Err(format!("tone takes one signal\n{USAGE}").into()),
+            "white" => signal = Some(Signal::White),
+            "pink" => signal = Some(Signal::Pink),
+            _ => signal = Some(Signal::Sine(frequency(&arg)?)),
+        }
+    }
+    let signal = match (signal, to) {
+        (None, _) => return Err(format!("tone needs a frequency, white or pink\n{USAGE}").into()),
+        (Some(Signal::Sine(from)), Some(to)) => Signal::Sweep(from, to),
+        (Some(_), Some(_)) => return Err("--to only applies to a frequency".into()),
+        (Some(signal), None) => signal,
+    };
+    let config = PlayerConfig {
+        eq_gains: shared.eq_gains,
+        backend: shared.backend,
+        device: shared.device,
+        latency: shared.latency,
+        buffer_frames: shared.buffer_frames,
+        sample_rate: shared.sample_rate,
+        sample_format: shared.sample_format,
+        resample: shared.resample,
+        dither: shared.dither,
+        volume_db: shared.volume_db,
+        ..PlayerConfig::default()
+    };
+    Ok(Command::Tone {
+        signal,
+        seconds,
+        level_db,
+        config,
+        eq_file: shared.eq_file,
+        quiet,
+    })
+}
+
+/// The one file `info` and `analyze` take, and whether `--json` was given.
+fn parse_file(name: &str, args: Vec<String>) -> Result<(PathBuf, bool), Failure> {
+    let mut path = None;
+    let mut as_json = false;
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(USAGE.into()),
+            "--json" => as_json = true,
+            _ if arg.starts_with('-') => {
+                return Err(format!("unexpected argument '{arg}'\n{USAGE}").into())
+            }
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("{name} takes one file\n{USAGE}").into()),
+        }
+    }
+    let path = path.ok_or_else(|| format!("{name} takes one file\n{USAGE}"))?;
+    if !path.is_file() {
+        return Err(Failure::not_found(&path));
+    }
+    Ok((path, as_json))
+}
 
-    let db_gains = vec![4.6, 8.0, 4.6, 0.9, 0.0, 3.0, 0.9, 0.0, 0.0, 0.0];
-    let equalizer = Equalizer::new(decoder, db_gains);
-    sink.lock().unwrap().append(equalizer);
+fn parse_history(args: Vec<String>) -> Result<Command, Failure> {
+    let mut path = None;
+    let mut limit = 20;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--limit" => {
+                let value = args.next().ok_or("--limit requires a number")?;
+                limit = value
+                    .parse()
+                    .ok()
+                    .filter(|&limit| limit > 0)
+                    .ok_or_else(|| format!("invalid limit '{value}'"))?;
+            }
+            "--history" => {
+                let value = args.next().ok_or("--history requires a path")?;
+                path = Some(PathBuf::from(value));
+            }
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ => return Err(format!("unexpected argument '{arg}'\n{USAGE}").into()),
+        }
+    }
+    let path = path
+        .or_else(History::default_path)
+        .ok_or("no user data directory to read the history from; pass --history")?;
+    Ok(Command::History { path, limit })
+}
 
-    let audio_player = AudioPlayer {
-        sink,
-        duration,
-        progress: Arc::new(Mutex::new(Duration::from_secs(0))),
-        eq_enabled: Arc::new(AtomicBool::new(true)),
-        is_playing: Arc::new(AtomicBool::new(false)),
-        last_update: Arc::new(Mutex::new(Instant::now())),
+fn parse_ctl(args: Vec<String>) -> Result<Command, Failure> {
+    let mut args = args.into_iter().peekable();
+    let mut socket = default_socket_path();
+    if args.next_if(|arg| arg == "--socket").is_some() {
+        socket = PathBuf::from(args.next().ok_or("--socket requires a path")?);
+    }
+    let name = args.next().ok_or(USAGE)?;
+    let args = args.collect::<Vec<_>>();
+    let arg = |index: usize, what: &str| {
+        args.get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("{name} requires {what}"))
     };
+    let number = |index: usize, what: &str| {
+        let value = arg(index, what)?;
+        value
+            .parse::<f32>()
+            .map_err(|_| format!("invalid {what} '{value}'"))
+    };
+    let id = |what: &str| {
+        let value = arg(0, what)?;
+        value
+            .parse::<u64>()
+            .map_err(|_| format!("invalid {what} '{value}'"))
+    };
+    let command = match name.as_str() {
+        "play" => ControlCommand::Play,
+        "pause" => ControlCommand::Pause,
+        "toggle" => ControlCommand::Toggle,
+        "stop" => ControlCommand::Stop,
+        "next" => ControlCommand::Next,
+        "previous" => ControlCommand::Previous,
+        "seek" => ControlCommand::Seek(parse_time(arg(0, "a position")?)?),
+        "volume" => ControlCommand::VolumeDb(number(0, "a volume in dB")?),
+        "set-eq" => {
+            let band = arg(0, "a band")?;
+            ControlCommand::SetEq {
+                band: band.parse().map_err(|_| format!("invalid band '{band}'"))?,
+                gain_db: number(1, "a gain in dB")?,
+            }
+        }
+        "eq" => ControlCommand::EqGains(parse_gains(arg(0, "gains")?)?),
+        "enqueue" => ControlCommand::Enqueue(PathBuf::from(arg(0, "a path")?)),
+        "remove" => ControlCommand::Remove(id("queue id")?),
+        "jump" => ControlCommand::PlayItem(id("queue id")?),
+        "queue" => ControlCommand::Queue,
+        "status" => ControlCommand::Status,
+        _ => return Err(format!("unknown ctl command '{name}'\n{USAGE}").into()),
+    };
+    Ok(Command::Control { socket, command })
+}
+
+/// Parses `h:mm:ss`, `m:ss` or plain seconds, any part with a fraction.
+fn parse_time(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid position '{value}', expected [h:]m:ss or seconds");
+    let seconds = value.split(':').try_fold(0.0, |total: f64, part| {
+        part.parse::<f64>()
+            .ok()
+            .filter(|part| *part >= 0.0)
+            .map(|part| total * 60.0 + part)
+            .ok_or_else(invalid)
+    })?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+fn parse_gains(value: &str) -> Result<Vec<f32>, String> {
+    let gains = value
+        .split(',')
+        .map(|gain| {
+            gain.trim()
+                .parse::<f32>()
+                .map_err(|_| format!("invalid EQ gain '{}'", gain.trim()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    audio_player.play();
+    if gains.len() != BAND_COUNT && gains.len() != THIRD_OCTAVE_BAND_COUNT {
+        return Err(format!(
+            "expected {BAND_COUNT} or {THIRD_OCTAVE_BAND_COUNT} EQ gains, got {}",
+            gains.len()
+        ));
+    }
 
-    std::thread::sleep(duration);
+    Ok(gains)
+}
 
+fn main() {
+    if let Err(failure) = parse_args().and_then(run) {
+        eprintln!("{}", failure.message);
+        process::exit(failure.code);
+    }
+}
+
+fn run(command: Command) -> Result<(), Failure> {
+    match command {
+        Command::Play(args) => play(*args),
+        Command::Info { path, json } => info(&path, json),
+        Command::Render {
+            input,
+            output,
+            eq,
+            options,
+        } => {
+            let stats = render::to_wav_with(&input, &output, &eq, options).map_err(|err| {
+                Failure::of(format_args!("failed to render {}", input.display()), &*err)
+            })?;
+            println!(
+                "wrote {} samples to {} (peak {:.1} dBFS)",
+                stats.samples_written,
+                output.display(),
+                stats.peak_db
+            );
+            Ok(())
+        }
+        Command::RenderBatch {
+            input,
+            output,
+            eq,
+            options,
+        } => render_batch(&input, &output, &eq, options),
+        Command::Analyze { path, json } => {
+            let report = analyze(&path).map_err(|err| {
+                Failure::of(format_args!("failed to analyze {}", path.display()), &err)
+            })?;
+            if json {
+                println!("{}", report.to_json());
+                return Ok(());
+            }
+            println!("integrated loudness: {:.1} LUFS", report.integrated_lufs);
+            println!("loudness range:      {:.1} LU", report.loudness_range_lu);
+            println!("sample peak:         {:.1} dBFS", report.sample_peak_db);
+            println!("true peak:           {:.1} dBTP", report.true_peak_db);
+            Ok(())
+        }
+        Command::Spectrogram {
+            input,
+            output,
+            options,
+        } => {
+            let image = spectrogram::render(&input, options).map_err(|err| {
+                Failure::of(format_args!("failed to analyze {}", input.display()), &*err)
+            })?;
+            image.save_png(&output).map_err(|err| {
+                Failure::of(format_args!("failed to write {}", output.display()), &err)
+            })?;
+            println!(
+                "wrote a {}x{} spectrogram to {}",
+                image.width,
+                image.height,
+                output.display()
+            );
+            Ok(())
+        }
+        Command::Tone {
+            signal,
+            seconds,
+            level_db,
+            config,
+            eq_file,
+            quiet,
+        } => tone(signal, seconds, level_db, &config, eq_file, quiet),
+        Command::History { path, limit } => {
+            let history = History::load(&path).map_err(|err| {
+                Failure::of(format_args!("failed to read {}", path.display()), &err)
+            })?;
+            for (index, entry) in history.recent(limit).iter().enumerate() {
+                print_history_entry(index, entry);
+            }
+            Ok(())
+        }
+        Command::Control { socket, command } => {
+            let reply = send_command(&socket, &command).map_err(|err| {
+                Failure::new(
+                    EXIT_FAILURE,
+                    format!("failed to reach a player at {}: {err}", socket.display()),
+                )
+            })?;
+            println!("{reply}");
+            // The reply says why already.
+            if reply.starts_with(r#"{"ok":false"#) {
+                process::exit(EXIT_FAILURE);
+            }
+            Ok(())
+        }
+        Command::WriteConfig { path, config } => {
+            config.save(&path).map_err(|err| {
+                Failure::of(format_args!("failed to write {}", path.display()), &err)
+            })?;
+            println!("wrote {}", path.display());
+            Ok(())
+        }
+    }
+}
+
+/// Prints the file's format and tags, or the format alone as JSON.
+fn render_batch(
+    input: &Path,
+    output: &Path,
+    eq: &EqSettings,
+    options: BatchOptions,
+) -> Result<(), Failure> {
+    let summary = render::batch(input, output, eq, options, |done, total, file| {
+        let outcome = match &file.outcome {
+            BatchOutcome::Rendered(stats) => format!("peak {:.1} dBFS", stats.peak_db),
+            BatchOutcome::Skipped => "skipped, output exists".to_string(),
+            BatchOutcome::Failed(err) => format!("failed: {err}"),
+        };
+        eprintln!("[{done}/{total}] {}: {outcome}", file.input.display());
+    })
+    .map_err(|err| Failure::of(format_args!("failed to render {}", input.display()), &*err))?;
+    println!(
+        "rendered {}, skipped {}, failed {} of {} files into {}",
+        summary.rendered(),
+        summary.skipped(),
+        summary.failed(),
+        summary.files.len(),
+        output.display()
+    );
+    if summary.failed() > 0 {
+        return Err(Failure::new(
+            EXIT_FAILURE,
+            format!(
+                "{} of {} files failed to render",
+                summary.failed(),
+                summary.files.len()
+            ),
+        ));
+    }
     Ok(())
 }
 
-fn seek(audio_player: &AudioPlayer, position: Duration, toggle_eq: bool) {
-    let sink = audio_player.sink.lock().unwrap();
-    let was_playing = audio_player.is_playing.load(Ordering::Relaxed);
+fn info(path: &Path, json: bool) -> Result<(), Failure> {
+    let stream = probe(path).map_err(|err| match err {
+        PlayerError::Decode(_) => Failure::new(
+            EXIT_DECODE,
+            format!("{}: unsupported format", path.display()),
+        ),
+        err => Failure::of(format_args!("can't read {}", path.display()), &err),
+    })?;
+    if json {
+        println!("{}", stream.to_json());
+        return Ok(());
+    }
+    // Tags are a bonus; a file the decoder reads plays without them.
+    let tags = TrackMetadata::read(path).unwrap_or_default();
+    println!("file:        {}", path.display());
+    let format = match (stream.container, stream.codec) {
+        (Some(container), Some(codec)) if container != codec => {
+            Some(format!("{container}, {codec}"))
+        }
+        (Some(name), _) | (None, Some(name)) => Some(name.to_owned()),
+        (None, None) => None,
+    };
+    if let Some(format) = format {
+        println!("format:      {format}");
+    }
+    println!("sample rate: {} Hz", stream.sample_rate);
+    println!("channels:    {}", stream.channels);
+    if let Some(bits) = stream.bits_per_sample {
+        println!("bit depth:   {bits}");
+    }
+    if let Some(duration) = stream.duration {
+        println!("duration:    {}", format_time(duration));
+    }
+    println!("size:        {}", format_size(stream.file_size));
+    if let Some(bitrate) = stream.bitrate {
+        println!("bitrate:     {} kb/s", (bitrate + 500) / 1000);
+    }
+    // As `play` would open the output for it; a device that can't be asked
+    // is left out.
+    let config = PlayerConfig::load().unwrap_or_default();
+    let output = OutputConfig {
+        source_rate: Some(stream.sample_rate),
+        ..config.output_config()
+    };
+    let backend = config.backend.unwrap_or_default();
+    if let Some(device) = AudioEngine::output_format(backend, config.device.as_deref(), output) {
+        println!(
+            "playback:    {}",
+            playback_path(stream.sample_rate, stream.bits_per_sample, &device)
+        );
+    }
+    let fields = [
+        ("title:      ", tags.title),
+        ("artist:     ", tags.artist),
+        ("album:      ", tags.album),
+        (
+            "track:      ",
+            tags.track_number.map(|number| number.to_string()),
+        ),
+    ];
+    for (label, value) in fields {
+        if let Some(value) = value {
+            println!("{label} {value}");
+        }
+    }
+    if let Some(cover) = &tags.cover {
+        println!(
+            "cover:       {}, {} bytes",
+            cover.mime_type,
+            cover.data.len()
+        );
+    }
+    if !tags.chapters.is_empty() {
+        println!("chapters:    {}", tags.chapters.len());
+    }
+    let replay_gain = [
+        (
+            "track gain: ",
+            tags.replay_gain.track_gain_db,
+            tags.replay_gain.track_peak,
+        ),
+        (
+            "album gain: ",
+            tags.replay_gain.album_gain_db,
+            tags.replay_gain.album_peak,
+        ),
+    ];
+    for (label, gain_db, peak) in replay_gain {
+        match (gain_db, peak) {
+            (Some(gain_db), Some(peak)) => println!("{label} {gain_db:+.2} dB, peak {peak}"),
+            (Some(gain_db), None) => println!("{label} {gain_db:+.2} dB"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-    sink.stop();
-    audio_player.is_playing.store(false, Ordering::Relaxed);
+/// Bytes in the largest unit that keeps a number of at least one.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} bytes");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
 
-    let file = BufReader::new(File::open("outaspace.flac").expect("Failed to open file"));
-    let decoder = Decoder::new(file)
-        .expect("Failed to create decoder")
-        .convert_samples::<f32>();
+/// `source 96 kHz/24-bit → device 96 kHz/24-bit`, showing whether a file is
+/// resampled or narrowed on its way to the device.
+fn playback_path(rate: u32, bits: Option<u16>, device: &StreamConfig) -> String {
+    let format = |rate: u32, bits: Option<u16>, float: bool| {
+        let mut format = format!("{} kHz", rate as f64 / 1000.0);
+        if let Some(bits) = bits {
+            format += &format!("/{bits}-bit");
+        }
+        if float {
+            format += " float";
+        }
+        format
+    };
+    format!(
+        "source {} → device {}",
+        format(rate, bits, false),
+        format(
+            device.sample_rate,
+            Some(device.bits_per_sample),
+            device.sample_format.is_float()
+        )
+    )
+}
+
+/// `m:ss`, or `h:mm:ss` from an hour on, as `parse_time` reads it.
+/// One line of `history`: when, what and for how long, newest at 0.
+fn print_history_entry(index: usize, entry: &HistoryEntry) {
+    let name = match (&entry.artist, &entry.title) {
+        (Some(artist), Some(title)) => format!("{artist} - {title}"),
+        (None, Some(title)) => title.clone(),
+        _ => entry.path.display().to_string(),
+    };
+    let plays = match entry.plays {
+        1 => String::new(),
+        plays => format!(", {plays} plays"),
+    };
+    let missing = if entry.missing { " [missing]" } else { "" };
+    println!(
+        "{index:>4}  {}  {name} ({} played{plays}){missing}",
+        format_date(entry.played_at),
+        format_time(entry.played),
+    );
+    if entry.title.is_some() {
+        println!("      {}", entry.path.display());
+    }
+}
+
+/// `time` as a UTC date and time to the minute.
+fn format_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, minutes) = (seconds / 86400, seconds % 86400 / 60);
+    // Days since 1970-01-01 to a civil date, counting in 400-year eras
+    // from 0000-03-01.
+    let days = days + 719_468;
+    let (era, day) = (days / 146_097, days % 146_097);
+    let year = (day - day / 1460 + day / 36524 - day / 146_096) / 365;
+    let day_of_year = day - (365 * year + year / 4 - year / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year}-{month:02}-{day_of_month:02} {:02}:{:02}",
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+fn format_time(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match hours {
+        0 => format!("{minutes}:{seconds:02}"),
+        _ => format!("{hours}:{minutes:02}:{seconds:02}"),
+    }
+}
+
+fn play(args: PlayArgs) -> Result<(), Failure> {
+    let PlayArgs {
+        paths,
+        config,
+        eq_file,
+        chain,
+        resume,
+        bookmarks,
+        history,
+        history_limit,
+        control,
+        stats,
+        quiet,
+    } = args;
+    let paths = expand_playlists(&paths)?;
+    if paths.is_empty() {
+        return Err(Failure::new(EXIT_FAILURE, "nothing to play"));
+    }
+    // The output goes at the first file's rate where it can, so that isn't
+    // resampled.
+    let source = paths
+        .iter()
+        .find(|path| url(path).is_none() && !is_cue(path) && *path != Path::new(STDIN_PATH))
+        .and_then(|path| probe(path).ok());
+    let output = OutputConfig {
+        source_rate: source.as_ref().map(|source| source.sample_rate),
+        ..config.output_config()
+    };
+    let backend = config.backend.unwrap_or_default();
+    let audio_player = AudioEngine::with_output_config(backend, config.device.as_deref(), output)
+        .and_then(|engine| engine.new_player())
+        .map_err(|err| Failure::of("failed to open audio output", &err))?;
+    if let (true, Some(source), Some(device)) = (stats, &source, audio_player.stream_config()) {
+        eprintln!(
+            "{}",
+            playback_path(source.sample_rate, source.bits_per_sample, &device)
+        );
+    }
+    // Headroom for the built-in curve, as for render.
+    audio_player.set_eq_settings(EqSettings {
+        auto_headroom: true,
+        ..EqSettings::default()
+    });
+    // Before anything is opened, which the rest of the config can wait for.
+    if let Some(decoder) = config.decoder {
+        audio_player.set_decoder_backend(decoder);
+    }
+    for path in &paths {
+        let queued = match url(path) {
+            Some(url) => audio_player.enqueue_url(url),
+            None if is_cue(path) => CueSheet::load(path).map_err(Into::into).and_then(|sheet| {
+                print_cue_warnings(path, &sheet);
+                audio_player.enqueue_cue(sheet)
+            }),
+            None => audio_player.enqueue(path),
+        };
+        queued
+            .map_err(|err| Failure::of(format_args!("failed to queue {}", path.display()), &err))?;
+    }
+    config
+        .apply(&audio_player)
+        .map_err(|err| Failure::of("failed to apply the config", &err))?;
+    if let Some(chain) = chain {
+        audio_player
+            .apply_chain(chain)
+            .map_err(|err| Failure::of("failed to set up the chain", &err))?;
+    }
+    if let Some(eq) = eq_file {
+        audio_player.set_eq_settings(eq);
+    }
+
+    match bookmarks.as_deref().map(Bookmarks::load) {
+        Some(Ok(bookmarks)) => audio_player.set_bookmarks(bookmarks),
+        Some(Err(err)) => eprintln!("failed to load bookmarks: {err}"),
+        None => {}
+    }
+    match history.as_deref().map(History::load) {
+        Some(Ok(mut history)) => {
+            history.set_limit(history_limit.unwrap_or(DEFAULT_HISTORY_LIMIT));
+            audio_player.set_history(history);
+        }
+        Some(Err(err)) => eprintln!("failed to load history: {err}"),
+        None => {}
+    }
+    if resume {
+        if let Err(err) = audio_player.resume() {
+            eprintln!("failed to resume {}: {err}", paths[0].display());
+        }
+    }
 
-    let db_gains = vec![6.0, 3.0, 0.0, 0.0, -3.0, -6.0, 0.0, 3.0, 6.0, 3.0];
+    let audio_player = Arc::new(audio_player);
+    // Held until playback ends; dropping it removes the socket.
+    let _server = control
+        .as_deref()
+        .map(|socket| {
+            ControlServer::start(&audio_player, socket).map_err(|err| {
+                Failure::new(
+                    EXIT_FAILURE,
+                    format!("failed to listen at {}: {err}", socket.display()),
+                )
+            })
+        })
+        .transpose()?;
+    // Media keys are a nicety; playback goes on without a session bus.
+    #[cfg(all(feature = "mpris", target_os = "linux"))]
+    let _mpris = fullyrustaudio::MprisServer::start(&audio_player)
+        .map_err(|err| eprintln!("warning: MPRIS isn't available: {err}"))
+        .ok();
+    #[cfg(all(feature = "media-keys", any(windows, target_os = "macos")))]
+    let media_keys = fullyrustaudio::MediaKeys::start(&audio_player)
+        .map_err(|err| eprintln!("warning: media keys aren't available: {err}"))
+        .ok();
+    // Called from the main thread's waits, which is where the media keys'
+    // commands arrive on macOS.
+    let idle = || {
+        #[cfg(all(feature = "media-keys", any(windows, target_os = "macos")))]
+        if let Some(media_keys) = &media_keys {
+            media_keys.pump();
+        }
+    };
+
+    audio_player
+        .play()
+        .map_err(|err| Failure::of("failed to start playback", &err))?;
 
-    if toggle_eq {
-        let current_state = audio_player.eq_enabled.load(Ordering::Relaxed);
-        audio_player.eq_enabled.store(!current_state, Ordering::Relaxed);
+    #[cfg(all(feature = "media-keys", any(windows, target_os = "macos")))]
+    let pumping = media_keys.is_some();
+    #[cfg(not(all(feature = "media-keys", any(windows, target_os = "macos"))))]
+    let pumping = false;
+    let played = follow(audio_player.clone(), quiet, pumping, idle);
+    if stats {
+        eprint!("{}", audio_player.stats().to_json());
     }
+    played
+}
 
-    let source = if audio_player.eq_enabled.load(Ordering::Relaxed) {
-        Box::new(Equalizer::new(decoder, db_gains)) as Box<dyn Source<Item = f32> + Send>
+/// Waits for `player` to finish, under the keyboard controls while standard
+/// input is a terminal, with the status line unless `quiet`. Without the
+/// controls, `idle` is only called if `pumping`.
+fn follow(
+    player: Arc<AudioPlayer>,
+    quiet: bool,
+    pumping: bool,
+    idle: impl Fn(),
+) -> Result<(), Failure> {
+    let mut status = if quiet {
+        None
     } else {
-        Box::new(decoder) as Box<dyn Source<Item = f32> + Send>
+        status::StatusLine::new()
     };
+    if !io::stdin().is_terminal() {
+        while (pumping || status.is_some()) && !player.is_finished() {
+            idle();
+            if let Some(status) = &mut status {
+                let _ = status.draw(&player);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        drop(status);
+        player.wait_until_end();
+        return Ok(());
+    }
+    println!("{}", terminal::KEYS);
+    terminal::run(player, status, idle)
+        .map_err(|err| Failure::new(EXIT_FAILURE, format!("terminal input failed: {err}")))
+}
 
-    sink.append(source.skip_duration(position));
-    *audio_player.progress.lock().unwrap() = position;
-    *audio_player.last_update.lock().unwrap() = Instant::now();
+/// Plays `signal` for `seconds` peaking at `level_db` dBFS, through the EQ
+/// given with the flags, and flat without.
+fn tone(
+    signal: Signal,
+    seconds: Duration,
+    level_db: f32,
+    config: &PlayerConfig,
+    eq_file: Option<EqSettings>,
+    quiet: bool,
+) -> Result<(), Failure> {
+    let backend = config.backend.unwrap_or_default();
+    let audio_player =
+        AudioEngine::with_output_config(backend, config.device.as_deref(), config.output_config())
+            .and_then(|engine| engine.new_player())
+            .map_err(|err| Failure::of("failed to open audio output", &err))?;
+    let eq = match (eq_file, &config.eq_gains) {
+        (Some(eq), _) => eq,
+        (None, Some(gains)) => EqSettings::for_gains(gains).map_err(|err| err.to_string())?,
+        (None, None) => EqSettings::from_gains(&[0.0; BAND_COUNT]),
+    };
+    audio_player.set_eq_settings(eq);
+    audio_player.set_volume_db(config.volume_db.unwrap_or(0.0));
 
-    if was_playing {
-        sink.play();
-        audio_player.is_playing.store(true, Ordering::Relaxed);
+    // At the output's own rate, so nothing is resampled.
+    let stream = audio_player.stream_config();
+    let settings = GeneratorSettings {
+        sample_rate: stream.map_or(44_100, |stream| stream.sample_rate),
+        channels: stream.map_or(2, |stream| stream.channels),
+        amplitude: db_to_linear(level_db),
+        duration: Some(seconds),
+    };
+    let queued = match signal {
+        Signal::Sine(frequency) => {
+            audio_player.play_source("sine", SineWave::new(frequency, settings))
+        }
+        Signal::Sweep(from, to) => {
+            let sweep = SweptSine::new(from, to, seconds, settings);
+            audio_player.play_source("sweep", sweep)
+        }
+        Signal::White => audio_player.play_source("white noise", WhiteNoise::new(0, settings)),
+        Signal::Pink => audio_player.play_source("pink noise", PinkNoise::new(0, settings)),
+    };
+    queued.map_err(|err| Failure::of("failed to play the tone", &err))?;
+    audio_player
+        .play()
+        .map_err(|err| Failure::of("failed to start playback", &err))?;
+    follow(Arc::new(audio_player), quiet, false, || {})
+}
+
+/// `path` as a URL, if it names a stream rather than a file.
+fn url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|path| path.contains("://"))
+}
+
+fn is_cue(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"))
+}
+
+fn print_cue_warnings(path: &Path, sheet: &CueSheet) {
+    for warning in &sheet.warnings {
+        eprintln!("{}: {warning}", path.display());
+    }
+}
+
+/// Replaces `.m3u` and `.m3u8` paths with their entries, leaving out files
+/// that don't exist.
+fn expand_playlists(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Failure> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        let is_playlist = path.extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("m3u") || extension.eq_ignore_ascii_case("m3u8")
+        });
+        if !is_playlist {
+            expanded.push(path.clone());
+            continue;
+        }
+        let playlist = Playlist::load(path)
+            .map_err(|err| Failure::of(format_args!("failed to load {}", path.display()), &err))?;
+        for warning in &playlist.warnings {
+            eprintln!("{}: {warning}", path.display());
+        }
+        for entry in playlist.entries {
+            if url(&entry.path).is_none() && !entry.path.is_file() {
+                eprintln!(
+                    "{}: skipping missing {}",
+                    path.display(),
+                    entry.path.display()
+                );
+            } else {
+                expanded.push(entry.path);
+            }
+        }
     }
+    Ok(expanded)
 }
@@ -0,0 +1,259 @@
+//! MPRemoteCommandCenter and MPNowPlayingInfoCenter through the Objective-C
+//! runtime.
+
+use super::{Command, Shown, Status};
+use std::{
+    ffi::{c_char, c_void, CStr},
+    io, mem,
+    sync::Arc,
+    time::Duration,
+};
+
+type Id = *mut c_void;
+type Sel = *const c_void;
+type Handler = Box<dyn Fn(Command) -> bool + Send + Sync>;
+/// Reads the command out of an `MPRemoteCommandEvent`.
+type ToCommand = fn(Id) -> Command;
+
+#[link(name = "objc")]
+extern "C" {
+    fn objc_getClass(name: *const c_char) -> Id;
+    fn sel_registerName(name: *const c_char) -> Sel;
+    fn objc_msgSend();
+    fn objc_autoreleasePoolPush() -> *mut c_void;
+    fn objc_autoreleasePoolPop(pool: *mut c_void);
+}
+
+#[link(name = "Foundation", kind = "framework")]
+extern "C" {}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    static kCFRunLoopDefaultMode: Id;
+    fn CFRunLoopRunInMode(mode: Id, seconds: f64, return_after_source_handled: u8) -> i32;
+}
+
+#[link(name = "MediaPlayer", kind = "framework")]
+extern "C" {
+    static MPMediaItemPropertyTitle: Id;
+    static MPMediaItemPropertyArtist: Id;
+    static MPMediaItemPropertyAlbumTitle: Id;
+    static MPMediaItemPropertyPlaybackDuration: Id;
+    static MPNowPlayingInfoPropertyElapsedPlaybackTime: Id;
+    static MPNowPlayingInfoPropertyPlaybackRate: Id;
+}
+
+extern "C" {
+    /// The class of blocks that need no copying, from libSystem.
+    static _NSConcreteGlobalBlock: c_void;
+}
+
+// MPNowPlayingPlaybackState and MPRemoteCommandHandlerStatus values.
+const STATE_PLAYING: usize = 1;
+const STATE_PAUSED: usize = 2;
+const STATE_STOPPED: usize = 3;
+const HANDLER_SUCCESS: isize = 0;
+const HANDLER_FAILED: isize = 200;
+
+const NS_UTF8_STRING_ENCODING: usize = 4;
+
+/// Sends `$selector` to `$receiver`, with arguments of the types given.
+macro_rules! send {
+    ($receiver:expr, $selector:expr $(, $arg:expr => $ty:ty)* ; $ret:ty) => {{
+        let send: unsafe extern "C" fn(Id, Sel $(, $ty)*) -> $ret =
+            mem::transmute(objc_msgSend as unsafe extern "C" fn());
+        send($receiver, selector($selector) $(, $arg)*)
+    }};
+}
+
+unsafe fn class(name: &CStr) -> Id {
+    objc_getClass(name.as_ptr())
+}
+
+unsafe fn selector(name: &CStr) -> Sel {
+    sel_registerName(name.as_ptr())
+}
+
+/// Runs whatever the main thread's run loop has waiting, without blocking.
+pub(super) fn run_pending() {
+    unsafe { CFRunLoopRunInMode(kCFRunLoopDefaultMode, 0.0, 1) };
+}
+
+/// The remote command targets added for a player, and the Now Playing
+/// entry it shows.
+pub(super) struct Session {
+    targets: Vec<(Id, Id)>,
+}
+
+// SAFETY: the command center and Now Playing center are process-wide and
+// may be called from any thread.
+unsafe impl Send for Session {}
+
+impl Session {
+    pub(super) fn start(handler: Handler) -> io::Result<Session> {
+        let handler = Arc::new(handler);
+        unsafe {
+            let pool = objc_autoreleasePoolPush();
+            let center = send!(class(c"MPRemoteCommandCenter"), c"sharedCommandCenter"; Id);
+            if center.is_null() {
+                objc_autoreleasePoolPop(pool);
+                return Err(io::Error::other("MPRemoteCommandCenter isn't available"));
+            }
+            let commands: [(&CStr, ToCommand); 7] = [
+                (c"playCommand", |_| Command::Play),
+                (c"pauseCommand", |_| Command::Pause),
+                (c"togglePlayPauseCommand", |_| Command::PlayPause),
+                (c"stopCommand", |_| Command::Stop),
+                (c"nextTrackCommand", |_| Command::Next),
+                (c"previousTrackCommand", |_| Command::Previous),
+                (c"changePlaybackPositionCommand", |event| {
+                    let seconds = send!(event, c"positionTime"; f64);
+                    Command::SeekTo(Duration::try_from_secs_f64(seconds).unwrap_or_default())
+                }),
+            ];
+            let mut targets = Vec::new();
+            for (name, command) in commands {
+                let remote = send!(center, name; Id);
+                send!(remote, c"setEnabled:", true => bool; ());
+                let block = Block::new(handler.clone(), command);
+                let target = send!(remote, c"addTargetWithHandler:", block => *const Block; Id);
+                targets.push((remote, target));
+            }
+            objc_autoreleasePoolPop(pool);
+            Ok(Session { targets })
+        }
+    }
+
+    pub(super) fn update(&self, now: &Shown, position: Duration) {
+        unsafe {
+            let pool = objc_autoreleasePoolPush();
+            let info = send!(class(c"NSMutableDictionary"), c"dictionary"; Id);
+            let strings = [
+                (MPMediaItemPropertyTitle, &now.title),
+                (MPMediaItemPropertyArtist, &now.artist),
+                (MPMediaItemPropertyAlbumTitle, &now.album),
+            ];
+            for (key, value) in strings {
+                if let Some(value) = value {
+                    set(info, key, string(value));
+                }
+            }
+            let rate = match now.status {
+                Status::Playing => now.speed.into(),
+                Status::Paused | Status::Stopped => 0.0,
+            };
+            let mut numbers = vec![
+                (
+                    MPNowPlayingInfoPropertyElapsedPlaybackTime,
+                    position.as_secs_f64(),
+                ),
+                (MPNowPlayingInfoPropertyPlaybackRate, rate),
+            ];
+            if let Some(duration) = now.duration {
+                numbers.push((MPMediaItemPropertyPlaybackDuration, duration.as_secs_f64()));
+            }
+            for (key, value) in numbers {
+                let number = send!(class(c"NSNumber"), c"numberWithDouble:", value => f64; Id);
+                set(info, key, number);
+            }
+
+            let center = send!(class(c"MPNowPlayingInfoCenter"), c"defaultCenter"; Id);
+            send!(center, c"setNowPlayingInfo:", info => Id; ());
+            let state = match now.status {
+                Status::Playing => STATE_PLAYING,
+                Status::Paused => STATE_PAUSED,
+                Status::Stopped => STATE_STOPPED,
+            };
+            send!(center, c"setPlaybackState:", state => usize; ());
+            objc_autoreleasePoolPop(pool);
+        }
+    }
+}
+
+impl Drop for Session {
+    /// Takes the handlers off the commands and clears Now Playing, which
+    /// would otherwise go on showing the last track.
+    fn drop(&mut self) {
+        unsafe {
+            let pool = objc_autoreleasePoolPush();
+            for &(remote, target) in &self.targets {
+                send!(remote, c"removeTarget:", target => Id; ());
+                send!(remote, c"setEnabled:", false => bool; ());
+            }
+            let center = send!(class(c"MPNowPlayingInfoCenter"), c"defaultCenter"; Id);
+            send!(center, c"setNowPlayingInfo:", std::ptr::null_mut() => Id; ());
+            send!(center, c"setPlaybackState:", STATE_STOPPED => usize; ());
+            objc_autoreleasePoolPop(pool);
+        }
+    }
+}
+
+unsafe fn set(dictionary: Id, key: Id, value: Id) {
+    send!(dictionary, c"setObject:forKey:", value => Id, key => Id; ());
+}
+
+/// An autoreleased `NSString`.
+unsafe fn string(text: &str) -> Id {
+    let string = send!(class(c"NSString"), c"alloc"; Id);
+    let string = send!(
+        string,
+        c"initWithBytes:length:encoding:",
+        text.as_ptr() => *const u8,
+        text.len() => usize,
+        NS_UTF8_STRING_ENCODING => usize;
+        Id
+    );
+    send!(string, c"autorelease"; Id)
+}
+
+const BLOCK_IS_GLOBAL: i32 = 1 << 28;
+
+/// A block taking the `MPRemoteCommandEvent` and returning the handler
+/// status. Marked global so the command center keeps it as it is instead
+/// of copying it, with what it captures after the block header.
+#[repr(C)]
+struct Block {
+    isa: *const c_void,
+    flags: i32,
+    reserved: i32,
+    invoke: unsafe extern "C" fn(*const Block, Id) -> isize,
+    descriptor: *const BlockDescriptor,
+    handler: Arc<Handler>,
+    command: ToCommand,
+}
+
+#[repr(C)]
+struct BlockDescriptor {
+    reserved: usize,
+    size: usize,
+}
+
+static DESCRIPTOR: BlockDescriptor = BlockDescriptor {
+    reserved: 0,
+    size: mem::size_of::<Block>(),
+};
+
+impl Block {
+    /// A block that is never freed: the command center may still be calling
+    /// it on the main thread while the session is dropped elsewhere. Only
+    /// the handler's weak reference to the player outlives the session.
+    fn new(handler: Arc<Handler>, command: ToCommand) -> *const Block {
+        Box::leak(Box::new(Block {
+            isa: unsafe { &_NSConcreteGlobalBlock },
+            flags: BLOCK_IS_GLOBAL,
+            reserved: 0,
+            invoke: Block::invoke,
+            descriptor: &DESCRIPTOR,
+            handler,
+            command,
+        }))
+    }
+
+    unsafe extern "C" fn invoke(block: *const Block, event: Id) -> isize {
+        let block = &*block;
+        match (block.handler)((block.command)(event)) {
+            true => HANDLER_SUCCESS,
+            false => HANDLER_FAILED,
+        }
+    }
+}
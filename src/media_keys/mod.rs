@@ -0,0 +1,182 @@
+//! The operating system's media controls on Windows and macOS: the media
+//! keys, and the overlay or Now Playing widget showing the current track.
+//! Linux has the same through [`MprisServer`](crate::MprisServer).
+
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(target_os = "macos")]
+use macos as platform;
+#[cfg(windows)]
+use windows as platform;
+
+use crate::{error::PlayerError, player::AudioPlayer, PlayerEvent};
+use platform::Session;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How often the track shown is compared with the player's, for changes
+/// that come without an event, like a track being stopped.
+const POLL: Duration = Duration::from_millis(250);
+
+/// Hands the player to the system media controls: SystemMediaTransportControls
+/// on Windows, MPRemoteCommandCenter and MPNowPlayingInfoCenter on macOS.
+///
+/// Play, pause, stop, next, previous and seeks from the media keys or the
+/// overlay go to the player, and the overlay shows its track and position,
+/// updated on every seek and pause. The session ends, leaving nothing on
+/// screen, when this is dropped or the player is.
+pub struct MediaKeys {
+    closed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MediaKeys {
+    pub fn start(player: &Arc<AudioPlayer>) -> io::Result<MediaKeys> {
+        let weak = Arc::downgrade(player);
+        let session = Session::start(Box::new(move |command| {
+            weak.upgrade()
+                .is_some_and(|player| apply(&player, command).is_ok())
+        }))?;
+
+        let events = player.subscribe();
+        let weak = Arc::downgrade(player);
+        let closed = Arc::new(AtomicBool::new(false));
+        let stop = closed.clone();
+        // The session lives on this thread, so it's gone as soon as either
+        // side is.
+        let thread = thread::spawn(move || {
+            let mut shown = None;
+            while !stop.load(Ordering::Acquire) {
+                let moved = match events.recv_timeout(POLL) {
+                    Ok(event) => matches!(
+                        event,
+                        PlayerEvent::Seeked(_) | PlayerEvent::Paused | PlayerEvent::Resumed
+                    ),
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                let Some(player) = weak.upgrade() else {
+                    break;
+                };
+                let (now, position) = Shown::of(&player);
+                if moved || shown.as_ref() != Some(&now) {
+                    session.update(&now, position);
+                    shown = Some(now);
+                }
+            }
+            drop(session);
+        });
+
+        Ok(MediaKeys {
+            closed,
+            thread: Some(thread),
+        })
+    }
+
+    /// Delivers the commands waiting on the main thread's run loop, which is
+    /// where macOS sends them. A program whose main thread doesn't run the
+    /// run loop otherwise calls this from it every now and then; elsewhere
+    /// it does nothing.
+    pub fn pump(&self) {
+        #[cfg(target_os = "macos")]
+        platform::run_pending();
+    }
+}
+
+impl Drop for MediaKeys {
+    /// Waits for the session to be taken down.
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// What the media keys ask of the player.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Play,
+    Pause,
+    PlayPause,
+    Stop,
+    Next,
+    Previous,
+    SeekTo(Duration),
+}
+
+fn apply(player: &AudioPlayer, command: Command) -> Result<(), PlayerError> {
+    match command {
+        Command::PlayPause if player.is_playing() => player.pause(),
+        Command::Play | Command::PlayPause => return player.play(),
+        Command::Pause => player.pause(),
+        Command::Stop => player.stop(),
+        Command::Next => return player.next().map(drop),
+        Command::Previous => return player.previous().map(drop),
+        Command::SeekTo(position) => return player.seek(position),
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/// The track as the media controls show it, less the position, which moves
+/// on its own.
+#[derive(Debug, Clone, PartialEq)]
+struct Shown {
+    status: Status,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    duration: Option<Duration>,
+    speed: f32,
+}
+
+impl Shown {
+    /// What to show of the player, and the position to show with it.
+    fn of(player: &AudioPlayer) -> (Shown, Duration) {
+        let Some(now) = player.now_playing() else {
+            let shown = Shown {
+                status: Status::Stopped,
+                title: None,
+                artist: None,
+                album: None,
+                duration: None,
+                speed: player.speed(),
+            };
+            return (shown, Duration::ZERO);
+        };
+        let status = if player.is_playing() {
+            Status::Playing
+        } else if player.is_stopped() {
+            Status::Stopped
+        } else {
+            Status::Paused
+        };
+        let shown = Shown {
+            status,
+            title: now.title(),
+            artist: now.artist().map(str::to_string),
+            album: now.album().map(str::to_string),
+            duration: now.duration,
+            speed: player.speed(),
+        };
+        (shown, now.position)
+    }
+}
@@ -0,0 +1,693 @@
+//! SystemMediaTransportControls through its COM interfaces. A console
+//! program has no window of its own to ask for the controls with, so a
+//! hidden one is made on a thread that also makes every call on them.
+
+use super::{Command, Shown, Status};
+use std::{
+    ffi::c_void,
+    io, mem,
+    ptr::{self, null_mut},
+    sync::{
+        atomic::{fence, AtomicU32, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+type Hresult = i32;
+type Hstring = *mut c_void;
+type Hwnd = *mut c_void;
+/// An interface pointer.
+type Object = *mut c_void;
+type Handler = Box<dyn Fn(Command) -> bool + Send + Sync>;
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Guid(u32, u16, u16, [u8; 8]);
+
+const IUNKNOWN: Guid = Guid(0, 0, 0, [0xc0, 0, 0, 0, 0, 0, 0, 0x46]);
+const IAGILE_OBJECT: Guid = Guid(
+    0x94ea2b94,
+    0xe9cc,
+    0x49e0,
+    [0xc0, 0xff, 0xee, 0x64, 0xca, 0x8f, 0x5b, 0x90],
+);
+const ISMTC_INTEROP: Guid = Guid(
+    0xddb0472d,
+    0xc911,
+    0x4a1f,
+    [0x86, 0xd9, 0xdc, 0x3d, 0x71, 0xa9, 0x5f, 0x5a],
+);
+const ISMTC: Guid = Guid(
+    0x99fa3ff4,
+    0x1742,
+    0x42a6,
+    [0x90, 0x2e, 0x08, 0x7d, 0x41, 0xf9, 0x65, 0xec],
+);
+const ISMTC2: Guid = Guid(
+    0xea98d2f6,
+    0x7f3c,
+    0x4af2,
+    [0xa5, 0x86, 0x72, 0x88, 0x98, 0x08, 0xef, 0xb1],
+);
+const ITIMELINE_PROPERTIES: Guid = Guid(
+    0x5125316a,
+    0xc3a2,
+    0x475b,
+    [0x85, 0x07, 0x93, 0x53, 0x4d, 0xc8, 0x8f, 0x15],
+);
+/// `TypedEventHandler<SystemMediaTransportControls,
+/// SystemMediaTransportControlsButtonPressedEventArgs>`.
+const BUTTON_PRESSED_HANDLER: Guid = Guid(
+    0x0557e996,
+    0x7b23,
+    0x5bae,
+    [0xaa, 0x81, 0xea, 0x0d, 0x67, 0x11, 0x43, 0xa4],
+);
+/// `TypedEventHandler<SystemMediaTransportControls,
+/// PlaybackPositionChangeRequestedEventArgs>`.
+const POSITION_REQUESTED_HANDLER: Guid = Guid(
+    0x44e34f15,
+    0xbdc0,
+    0x50a7,
+    [0xac, 0xe4, 0x39, 0xe9, 0x1f, 0xb7, 0x53, 0xf1],
+);
+
+// Vtable slots, counting the six of IUnknown and IInspectable, in the
+// order the SDK's headers declare the methods in.
+const QUERY_INTERFACE: usize = 0;
+const RELEASE: usize = 2;
+const INTEROP_GET_FOR_WINDOW: usize = 6;
+const SMTC_PUT_PLAYBACK_STATUS: usize = 7;
+const SMTC_GET_DISPLAY_UPDATER: usize = 8;
+const SMTC_PUT_IS_ENABLED: usize = 11;
+const SMTC_PUT_IS_PLAY_ENABLED: usize = 13;
+const SMTC_PUT_IS_STOP_ENABLED: usize = 15;
+const SMTC_PUT_IS_PAUSE_ENABLED: usize = 17;
+const SMTC_PUT_IS_PREVIOUS_ENABLED: usize = 25;
+const SMTC_PUT_IS_NEXT_ENABLED: usize = 27;
+const SMTC_ADD_BUTTON_PRESSED: usize = 32;
+const SMTC_REMOVE_BUTTON_PRESSED: usize = 33;
+const SMTC2_PUT_PLAYBACK_RATE: usize = 11;
+const SMTC2_UPDATE_TIMELINE_PROPERTIES: usize = 12;
+const SMTC2_ADD_POSITION_REQUESTED: usize = 13;
+const SMTC2_REMOVE_POSITION_REQUESTED: usize = 14;
+const UPDATER_PUT_TYPE: usize = 7;
+const UPDATER_GET_MUSIC_PROPERTIES: usize = 12;
+const UPDATER_CLEAR_ALL: usize = 16;
+const UPDATER_UPDATE: usize = 17;
+const MUSIC_PUT_TITLE: usize = 7;
+const MUSIC_PUT_ARTIST: usize = 11;
+const TIMELINE_PUT_START_TIME: usize = 7;
+const TIMELINE_PUT_END_TIME: usize = 9;
+const TIMELINE_PUT_MIN_SEEK_TIME: usize = 11;
+const TIMELINE_PUT_MAX_SEEK_TIME: usize = 13;
+const TIMELINE_PUT_POSITION: usize = 15;
+const BUTTON_ARGS_GET_BUTTON: usize = 6;
+const POSITION_ARGS_GET_POSITION: usize = 6;
+
+// MediaPlaybackStatus, MediaPlaybackType and
+// SystemMediaTransportControlsButton values.
+const STATUS_CLOSED: i32 = 0;
+const STATUS_STOPPED: i32 = 2;
+const STATUS_PLAYING: i32 = 3;
+const STATUS_PAUSED: i32 = 4;
+const TYPE_MUSIC: i32 = 1;
+const BUTTON_PLAY: i32 = 0;
+const BUTTON_PAUSE: i32 = 1;
+const BUTTON_STOP: i32 = 2;
+const BUTTON_NEXT: i32 = 6;
+const BUTTON_PREVIOUS: i32 = 7;
+
+const RO_INIT_MULTITHREADED: u32 = 1;
+const E_NOINTERFACE: Hresult = 0x8000_4002_u32 as i32;
+const E_FAIL: Hresult = 0x8000_4005_u32 as i32;
+const WM_APP: u32 = 0x8000;
+/// Posted to the window thread when there's an update to show.
+const WM_UPDATE: u32 = WM_APP;
+/// Posted to the window thread to take the session down.
+const WM_CLOSE_SESSION: u32 = WM_APP + 1;
+
+#[repr(C)]
+struct WndClass {
+    style: u32,
+    window_proc: unsafe extern "system" fn(Hwnd, u32, usize, isize) -> isize,
+    class_extra: i32,
+    window_extra: i32,
+    instance: *mut c_void,
+    icon: *mut c_void,
+    cursor: *mut c_void,
+    background: *mut c_void,
+    menu_name: *const u16,
+    class_name: *const u16,
+}
+
+#[repr(C)]
+struct Msg {
+    window: Hwnd,
+    message: u32,
+    wparam: usize,
+    lparam: isize,
+    time: u32,
+    point: [i32; 2],
+}
+
+#[link(name = "runtimeobject")]
+extern "system" {
+    fn RoInitialize(kind: u32) -> Hresult;
+    fn RoUninitialize();
+    fn RoGetActivationFactory(class: Hstring, iid: *const Guid, factory: *mut Object) -> Hresult;
+    fn RoActivateInstance(class: Hstring, instance: *mut Object) -> Hresult;
+    fn WindowsCreateString(text: *const u16, len: u32, string: *mut Hstring) -> Hresult;
+    fn WindowsDeleteString(string: Hstring) -> Hresult;
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn RegisterClassW(class: *const WndClass) -> u16;
+    fn UnregisterClassW(name: *const u16, instance: *mut c_void) -> i32;
+    fn CreateWindowExW(
+        ex_style: u32,
+        class: *const u16,
+        title: *const u16,
+        style: u32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        parent: Hwnd,
+        menu: *mut c_void,
+        instance: *mut c_void,
+        param: *mut c_void,
+    ) -> Hwnd;
+    fn DestroyWindow(window: Hwnd) -> i32;
+    fn DefWindowProcW(window: Hwnd, message: u32, wparam: usize, lparam: isize) -> isize;
+    fn GetMessageW(msg: *mut Msg, window: Hwnd, min: u32, max: u32) -> i32;
+    fn TranslateMessage(msg: *const Msg) -> i32;
+    fn DispatchMessageW(msg: *const Msg) -> isize;
+    fn PostThreadMessageW(thread: u32, message: u32, wparam: usize, lparam: isize) -> i32;
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetModuleHandleW(name: *const u16) -> *mut c_void;
+    fn GetCurrentThreadId() -> u32;
+}
+
+/// The media controls of a hidden window, run on a thread of their own.
+pub(super) struct Session {
+    thread_id: u32,
+    updates: mpsc::Sender<(Shown, Duration)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    pub(super) fn start(handler: Handler) -> io::Result<Session> {
+        let (started, ready) = mpsc::channel();
+        let (updates, pending) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            // SAFETY: every pointer passed to Windows is valid for the call,
+            // and the controls are only touched from this thread.
+            let controls = match unsafe { Controls::open(handler) } {
+                Ok(controls) => controls,
+                Err(err) => {
+                    let _ = started.send(Err(err));
+                    return;
+                }
+            };
+            let _ = started.send(Ok(unsafe { GetCurrentThreadId() }));
+            let mut msg = unsafe { mem::zeroed::<Msg>() };
+            while unsafe { GetMessageW(&mut msg, null_mut(), 0, 0) } > 0 {
+                match msg.message {
+                    WM_UPDATE => {
+                        if let Some((now, position)) = pending.try_iter().last() {
+                            unsafe { controls.show(&now, position) };
+                        }
+                    }
+                    WM_CLOSE_SESSION => break,
+                    _ => unsafe {
+                        TranslateMessage(&msg);
+                        DispatchMessageW(&msg);
+                    },
+                }
+            }
+            unsafe { controls.close() };
+        });
+        let thread_id = ready
+            .recv()
+            .map_err(|_| io::Error::other("the media controls thread exited"))??;
+        Ok(Session {
+            thread_id,
+            updates,
+            thread: Some(thread),
+        })
+    }
+
+    pub(super) fn update(&self, now: &Shown, position: Duration) {
+        if self.updates.send((now.clone(), position)).is_ok() {
+            unsafe { PostThreadMessageW(self.thread_id, WM_UPDATE, 0, 0) };
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe { PostThreadMessageW(self.thread_id, WM_CLOSE_SESSION, 0, 0) };
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The window and the interfaces taken from it.
+struct Controls {
+    window: Hwnd,
+    smtc: Object,
+    smtc2: Object,
+    updater: Object,
+    music: Object,
+    tokens: [(Object, usize, i64); 2],
+}
+
+impl Controls {
+    unsafe fn open(handler: Handler) -> io::Result<Controls> {
+        // Already initialized, in either apartment, is just as good.
+        RoInitialize(RO_INIT_MULTITHREADED);
+        let window = create_window()?;
+        let mut controls = Controls {
+            window,
+            smtc: null_mut(),
+            smtc2: null_mut(),
+            updater: null_mut(),
+            music: null_mut(),
+            tokens: [(null_mut(), 0, 0); 2],
+        };
+        // On failure, dropping `controls` releases whatever was obtained.
+        let interop =
+            activation_factory("Windows.Media.SystemMediaTransportControls", &ISMTC_INTEROP)?;
+        let get_for_window: unsafe extern "system" fn(
+            Object,
+            Hwnd,
+            *const Guid,
+            *mut Object,
+        ) -> Hresult = method(interop, INTEROP_GET_FOR_WINDOW);
+        let result = get_for_window(interop, window, &ISMTC, &mut controls.smtc);
+        release(interop);
+        check(result)?;
+        controls.smtc2 = query_interface(controls.smtc, &ISMTC2)?;
+        controls.updater = get_object(controls.smtc, SMTC_GET_DISPLAY_UPDATER)?;
+        check(put_i32(controls.updater, UPDATER_PUT_TYPE, TYPE_MUSIC))?;
+        controls.music = get_object(controls.updater, UPDATER_GET_MUSIC_PROPERTIES)?;
+        for slot in [
+            SMTC_PUT_IS_ENABLED,
+            SMTC_PUT_IS_PLAY_ENABLED,
+            SMTC_PUT_IS_PAUSE_ENABLED,
+            SMTC_PUT_IS_STOP_ENABLED,
+            SMTC_PUT_IS_NEXT_ENABLED,
+            SMTC_PUT_IS_PREVIOUS_ENABLED,
+        ] {
+            check(put_bool(controls.smtc, slot, true))?;
+        }
+
+        let handler = Arc::new(handler);
+        let buttons = Delegate::create(BUTTON_PRESSED_HANDLER, {
+            let handler = handler.clone();
+            Box::new(move |args| {
+                let command = match get_i32(args, BUTTON_ARGS_GET_BUTTON)? {
+                    BUTTON_PLAY => Command::Play,
+                    BUTTON_PAUSE => Command::Pause,
+                    BUTTON_STOP => Command::Stop,
+                    BUTTON_NEXT => Command::Next,
+                    BUTTON_PREVIOUS => Command::Previous,
+                    _ => return Ok(()),
+                };
+                handler(command);
+                Ok(())
+            })
+        });
+        controls.tokens[0] = add_handler(
+            controls.smtc,
+            SMTC_ADD_BUTTON_PRESSED,
+            SMTC_REMOVE_BUTTON_PRESSED,
+            buttons,
+        )?;
+        let seeks = Delegate::create(
+            POSITION_REQUESTED_HANDLER,
+            Box::new(move |args| {
+                let ticks = get_i64(args, POSITION_ARGS_GET_POSITION)?;
+                handler(Command::SeekTo(from_ticks(ticks)));
+                Ok(())
+            }),
+        );
+        controls.tokens[1] = add_handler(
+            controls.smtc2,
+            SMTC2_ADD_POSITION_REQUESTED,
+            SMTC2_REMOVE_POSITION_REQUESTED,
+            seeks,
+        )?;
+        Ok(controls)
+    }
+
+    unsafe fn show(&self, now: &Shown, position: Duration) {
+        let status = match now.status {
+            Status::Playing => STATUS_PLAYING,
+            Status::Paused => STATUS_PAUSED,
+            Status::Stopped => STATUS_STOPPED,
+        };
+        put_i32(self.smtc, SMTC_PUT_PLAYBACK_STATUS, status);
+        let put_f64: unsafe extern "system" fn(Object, f64) -> Hresult =
+            method(self.smtc2, SMTC2_PUT_PLAYBACK_RATE);
+        put_f64(self.smtc2, now.speed.into());
+
+        for (slot, text) in [
+            (MUSIC_PUT_TITLE, &now.title),
+            (MUSIC_PUT_ARTIST, &now.artist),
+        ] {
+            let text = HString::new(text.as_deref().unwrap_or_default());
+            let put: unsafe extern "system" fn(Object, Hstring) -> Hresult =
+                method(self.music, slot);
+            put(self.music, text.0);
+        }
+        call(self.updater, UPDATER_UPDATE);
+
+        if let Ok(timeline) = activate(
+            "Windows.Media.SystemMediaTransportControlsTimelineProperties",
+        )
+        .and_then(|instance| {
+            let timeline = query_interface(instance, &ITIMELINE_PROPERTIES);
+            release(instance);
+            timeline
+        }) {
+            let end = to_ticks(now.duration.unwrap_or_default());
+            let position = to_ticks(position).min(end);
+            for (slot, ticks) in [
+                (TIMELINE_PUT_START_TIME, 0),
+                (TIMELINE_PUT_END_TIME, end),
+                (TIMELINE_PUT_MIN_SEEK_TIME, 0),
+                (TIMELINE_PUT_MAX_SEEK_TIME, end),
+                (TIMELINE_PUT_POSITION, position),
+            ] {
+                put_i64(timeline, slot, ticks);
+            }
+            let update: unsafe extern "system" fn(Object, Object) -> Hresult =
+                method(self.smtc2, SMTC2_UPDATE_TIMELINE_PROPERTIES);
+            update(self.smtc2, timeline);
+            release(timeline);
+        }
+    }
+
+    /// Takes the session off the overlay before letting go of it.
+    unsafe fn close(self) {
+        put_i32(self.smtc, SMTC_PUT_PLAYBACK_STATUS, STATUS_CLOSED);
+        call(self.updater, UPDATER_CLEAR_ALL);
+        call(self.updater, UPDATER_UPDATE);
+        put_bool(self.smtc, SMTC_PUT_IS_ENABLED, false);
+    }
+}
+
+impl Drop for Controls {
+    fn drop(&mut self) {
+        unsafe {
+            for (object, remove, token) in self.tokens {
+                if !object.is_null() {
+                    let remove: unsafe extern "system" fn(Object, i64) -> Hresult =
+                        method(object, remove);
+                    remove(object, token);
+                }
+            }
+            for object in [self.music, self.updater, self.smtc2, self.smtc] {
+                if !object.is_null() {
+                    release(object);
+                }
+            }
+            DestroyWindow(self.window);
+            UnregisterClassW(CLASS_NAME.as_ptr(), GetModuleHandleW(ptr::null()));
+            RoUninitialize();
+        }
+    }
+}
+
+const CLASS: &str = "FullyRustAudioMediaKeys";
+/// [`CLASS`] as a NUL-terminated UTF-16 string.
+const CLASS_NAME: [u16; CLASS.len() + 1] = wide(CLASS);
+
+const fn wide<const N: usize>(text: &str) -> [u16; N] {
+    let bytes = text.as_bytes();
+    let mut wide = [0; N];
+    let mut i = 0;
+    while i < bytes.len() {
+        wide[i] = bytes[i] as u16;
+        i += 1;
+    }
+    wide
+}
+
+unsafe extern "system" fn window_proc(
+    window: Hwnd,
+    message: u32,
+    wparam: usize,
+    lparam: isize,
+) -> isize {
+    DefWindowProcW(window, message, wparam, lparam)
+}
+
+/// A window that is never shown, for the controls to belong to.
+unsafe fn create_window() -> io::Result<Hwnd> {
+    let instance = GetModuleHandleW(ptr::null());
+    let class = WndClass {
+        style: 0,
+        window_proc,
+        class_extra: 0,
+        window_extra: 0,
+        instance,
+        icon: null_mut(),
+        cursor: null_mut(),
+        background: null_mut(),
+        menu_name: ptr::null(),
+        class_name: CLASS_NAME.as_ptr(),
+    };
+    // Fails harmlessly when an earlier session registered it.
+    RegisterClassW(&class);
+    let window = CreateWindowExW(
+        0,
+        CLASS_NAME.as_ptr(),
+        CLASS_NAME.as_ptr(),
+        0,
+        0,
+        0,
+        0,
+        0,
+        null_mut(),
+        null_mut(),
+        instance,
+        null_mut(),
+    );
+    match window.is_null() {
+        true => Err(io::Error::last_os_error()),
+        false => Ok(window),
+    }
+}
+
+fn check(result: Hresult) -> io::Result<()> {
+    match result >= 0 {
+        true => Ok(()),
+        false => Err(io::Error::from_raw_os_error(result)),
+    }
+}
+
+/// The function in `slot` of `object`'s vtable, as `F`.
+unsafe fn method<F: Copy>(object: Object, slot: usize) -> F {
+    let vtable = *(object as *const *const usize);
+    mem::transmute_copy(&*vtable.add(slot))
+}
+
+unsafe fn release(object: Object) {
+    let release: unsafe extern "system" fn(Object) -> u32 = method(object, RELEASE);
+    release(object);
+}
+
+unsafe fn call(object: Object, slot: usize) -> Hresult {
+    let call: unsafe extern "system" fn(Object) -> Hresult = method(object, slot);
+    call(object)
+}
+
+unsafe fn query_interface(object: Object, iid: &Guid) -> io::Result<Object> {
+    let query: unsafe extern "system" fn(Object, *const Guid, *mut Object) -> Hresult =
+        method(object, QUERY_INTERFACE);
+    let mut result = null_mut();
+    check(query(object, iid, &mut result))?;
+    Ok(result)
+}
+
+unsafe fn get_object(object: Object, slot: usize) -> io::Result<Object> {
+    let get: unsafe extern "system" fn(Object, *mut Object) -> Hresult = method(object, slot);
+    let mut result = null_mut();
+    check(get(object, &mut result))?;
+    Ok(result)
+}
+
+unsafe fn get_i32(object: Object, slot: usize) -> io::Result<i32> {
+    let get: unsafe extern "system" fn(Object, *mut i32) -> Hresult = method(object, slot);
+    let mut result = 0;
+    check(get(object, &mut result))?;
+    Ok(result)
+}
+
+unsafe fn get_i64(object: Object, slot: usize) -> io::Result<i64> {
+    let get: unsafe extern "system" fn(Object, *mut i64) -> Hresult = method(object, slot);
+    let mut result = 0;
+    check(get(object, &mut result))?;
+    Ok(result)
+}
+
+unsafe fn put_i32(object: Object, slot: usize, value: i32) -> Hresult {
+    let put: unsafe extern "system" fn(Object, i32) -> Hresult = method(object, slot);
+    put(object, value)
+}
+
+unsafe fn put_i64(object: Object, slot: usize, value: i64) -> Hresult {
+    let put: unsafe extern "system" fn(Object, i64) -> Hresult = method(object, slot);
+    put(object, value)
+}
+
+unsafe fn put_bool(object: Object, slot: usize, value: bool) -> Hresult {
+    let put: unsafe extern "system" fn(Object, u8) -> Hresult = method(object, slot);
+    put(object, value.into())
+}
+
+/// Registers `delegate` with the `add` slot of `object`, returning what the
+/// `remove` slot needs to unregister it.
+unsafe fn add_handler(
+    object: Object,
+    add: usize,
+    remove: usize,
+    delegate: Object,
+) -> io::Result<(Object, usize, i64)> {
+    let add: unsafe extern "system" fn(Object, Object, *mut i64) -> Hresult = method(object, add);
+    let mut token = 0;
+    let result = add(object, delegate, &mut token);
+    // The controls hold their own reference now.
+    release(delegate);
+    check(result)?;
+    Ok((object, remove, token))
+}
+
+unsafe fn activation_factory(class: &str, iid: &Guid) -> io::Result<Object> {
+    let class = HString::new(class);
+    let mut factory = null_mut();
+    check(RoGetActivationFactory(class.0, iid, &mut factory))?;
+    Ok(factory)
+}
+
+unsafe fn activate(class: &str) -> io::Result<Object> {
+    let class = HString::new(class);
+    let mut instance = null_mut();
+    check(RoActivateInstance(class.0, &mut instance))?;
+    Ok(instance)
+}
+
+/// WinRT's TimeSpan counts 100 ns ticks.
+fn to_ticks(duration: Duration) -> i64 {
+    (duration.as_nanos() / 100).try_into().unwrap_or(i64::MAX)
+}
+
+fn from_ticks(ticks: i64) -> Duration {
+    Duration::from_nanos((ticks.max(0) as u64).saturating_mul(100))
+}
+
+struct HString(Hstring);
+
+impl HString {
+    fn new(text: &str) -> HString {
+        let wide = text.encode_utf16().collect::<Vec<_>>();
+        let mut string = null_mut();
+        // An empty string is a null HSTRING, which is also what's left on
+        // failure.
+        unsafe { WindowsCreateString(wide.as_ptr(), wide.len() as u32, &mut string) };
+        HString(string)
+    }
+}
+
+impl Drop for HString {
+    fn drop(&mut self) {
+        unsafe { WindowsDeleteString(self.0) };
+    }
+}
+
+/// A `TypedEventHandler` implemented in Rust, the only COM object the
+/// controls need from us. `args` is the event's arguments.
+#[repr(C)]
+struct Delegate {
+    vtable: *const DelegateVtable,
+    references: AtomicU32,
+    iid: Guid,
+    invoke: Box<dyn Fn(Object) -> io::Result<()> + Send + Sync>,
+}
+
+#[repr(C)]
+struct DelegateVtable {
+    query_interface: unsafe extern "system" fn(Object, *const Guid, *mut Object) -> Hresult,
+    add_ref: unsafe extern "system" fn(Object) -> u32,
+    release: unsafe extern "system" fn(Object) -> u32,
+    invoke: unsafe extern "system" fn(Object, Object, Object) -> Hresult,
+}
+
+static DELEGATE_VTABLE: DelegateVtable = DelegateVtable {
+    query_interface: Delegate::query_interface,
+    add_ref: Delegate::add_ref,
+    release: Delegate::release,
+    invoke: Delegate::invoke,
+};
+
+impl Delegate {
+    /// A new delegate with one reference, for the caller to release.
+    fn create(iid: Guid, invoke: Box<dyn Fn(Object) -> io::Result<()> + Send + Sync>) -> Object {
+        Box::into_raw(Box::new(Delegate {
+            vtable: &DELEGATE_VTABLE,
+            references: AtomicU32::new(1),
+            iid,
+            invoke,
+        }))
+        .cast()
+    }
+
+    unsafe extern "system" fn query_interface(
+        this: Object,
+        iid: *const Guid,
+        result: *mut Object,
+    ) -> Hresult {
+        let delegate = &*(this as *const Delegate);
+        if [IUNKNOWN, IAGILE_OBJECT, delegate.iid].contains(&*iid) {
+            Delegate::add_ref(this);
+            *result = this;
+            0
+        } else {
+            *result = null_mut();
+            E_NOINTERFACE
+        }
+    }
+
+    unsafe extern "system" fn add_ref(this: Object) -> u32 {
+        let delegate = &*(this as *const Delegate);
+        delegate.references.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    unsafe extern "system" fn release(this: Object) -> u32 {
+        let delegate = &*(this as *const Delegate);
+        let left = delegate.references.fetch_sub(1, Ordering::Release) - 1;
+        if left == 0 {
+            fence(Ordering::Acquire);
+            drop(Box::from_raw(this as *mut Delegate));
+        }
+        left
+    }
+
+    unsafe extern "system" fn invoke(this: Object, _sender: Object, args: Object) -> Hresult {
+        let delegate = &*(this as *const Delegate);
+        match (delegate.invoke)(args) {
+            Ok(()) => 0,
+            Err(_) => E_FAIL,
+        }
+    }
+}
@@ -0,0 +1,896 @@
+use crate::{decode, position::Position, probe::header_duration};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+/// Largest tag block read into memory; anything bigger is treated as malformed.
+const MAX_BLOCK: usize = 64 * 1024 * 1024;
+
+/// How many Ogg pages are searched for the comment header.
+const MAX_OGG_PAGES: usize = 64;
+
+/// An embedded picture, usually the front cover.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverArt {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// A named point in a long track, such as an audiobook chapter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    pub title: String,
+    /// Where the chapter starts in the file. It runs until the next one
+    /// starts, or to the end of the file.
+    pub start: Duration,
+}
+
+impl Chapter {
+    /// Where the chapter starts, in frames of a file at `sample_rate`.
+    pub fn start_at(&self, sample_rate: u32) -> Position {
+        Position::from_duration(self.start, sample_rate)
+    }
+}
+
+/// ReplayGain tags: how far to turn a track, or the album it is on, up or
+/// down to play at the reference level, and how high it peaks.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGainTags {
+    pub track_gain_db: Option<f32>,
+    /// Highest sample magnitude, linear: 1.0 is full scale.
+    pub track_peak: Option<f32>,
+    pub album_gain_db: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGainTags {
+    /// Takes `value` if `key`, in upper case, is one of the ReplayGain tags.
+    fn apply(&mut self, key: &str, value: &str) -> bool {
+        // "-3.60 dB", usually.
+        let gain = || {
+            value
+                .trim_end_matches(|c: char| c.is_ascii_alphabetic() || c.is_whitespace())
+                .trim()
+                .parse()
+                .ok()
+                .filter(|gain: &f32| gain.is_finite())
+        };
+        let peak = || {
+            value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|peak: &f32| peak.is_finite() && *peak > 0.0)
+        };
+        match key {
+            "REPLAYGAIN_TRACK_GAIN" => self.track_gain_db = gain(),
+            "REPLAYGAIN_TRACK_PEAK" => self.track_peak = peak(),
+            "REPLAYGAIN_ALBUM_GAIN" => self.album_gain_db = gain(),
+            "REPLAYGAIN_ALBUM_PEAK" => self.album_peak = peak(),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A loop tagged in sample frames, as game and tracker music carries it:
+/// the first frame of the loop and the frame after its last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopPoints {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// `LOOPSTART` and `LOOPLENGTH` as they turn up, in either order.
+#[derive(Default)]
+struct LoopTags {
+    start: Option<u64>,
+    length: Option<u64>,
+}
+
+impl LoopTags {
+    /// Takes `value` if `key`, in upper case, is one of the loop tags.
+    fn apply(&mut self, key: &str, value: &str) -> bool {
+        let frames = value.trim().parse().ok();
+        match key {
+            "LOOPSTART" => self.start = frames,
+            "LOOPLENGTH" => self.length = frames.filter(|&length| length > 0),
+            _ => return false,
+        }
+        true
+    }
+
+    fn points(&self) -> Option<LoopPoints> {
+        let start = self.start?;
+        Some(LoopPoints {
+            start,
+            end: start.checked_add(self.length?)?,
+        })
+    }
+}
+
+/// Tags read from a file. Fields the file doesn't carry, or carries in a
+/// form that can't be parsed, are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    /// Length according to the tags or container headers, without decoding.
+    pub duration: Option<Duration>,
+    pub cover: Option<CoverArt>,
+    /// Chapters in order of their start.
+    pub chapters: Vec<Chapter>,
+    pub replay_gain: ReplayGainTags,
+    /// From a WAV's `smpl` chunk, or `LOOPSTART` and `LOOPLENGTH` comments.
+    pub loop_points: Option<LoopPoints>,
+}
+
+impl TrackMetadata {
+    /// Reads FLAC Vorbis comments and pictures, ID3v2 (falling back to
+    /// ID3v1) or Ogg Vorbis/Opus comments. Chapters come from `CHAPTERxxx`
+    /// comments, ID3v2 `CHAP` frames or the Nero chapter list of an MP4,
+    /// ReplayGain from `REPLAYGAIN_*` comments or ID3v2 `TXXX` frames, and
+    /// loop points from the first loop of a WAV's `smpl` chunk or from
+    /// `LOOPSTART` and `LOOPLENGTH` comments or `TXXX` frames.
+    /// Built with the `symphonia` feature, the tags Symphonia reads fill in
+    /// whatever of the title, artist, album and track number those leave out.
+    ///
+    /// The file gets a handle of its own, so this is safe to call while the
+    /// same file plays. Only failing to open or read it is an error.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        let read = file.read(&mut magic)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut metadata = TrackMetadata::default();
+        if magic.get(4..read) == Some(b"ftyp") {
+            metadata.read_mp4(&mut file)?;
+        } else {
+            match &magic[..read.min(4)] {
+                b"fLaC" => metadata.read_flac(&mut file)?,
+                b"OggS" => metadata.read_ogg(&mut file)?,
+                b"RIFF" => {
+                    metadata.read_riff(&mut file)?;
+                    metadata.read_id3v1(&mut file)?;
+                }
+                [b'I', b'D', b'3', ..] => {
+                    metadata.read_id3v2(&mut file)?;
+                    if metadata.title.is_none() {
+                        metadata.read_id3v1(&mut file)?;
+                    }
+                }
+                _ => metadata.read_id3v1(&mut file)?,
+            }
+        }
+        if metadata.title.is_none() || metadata.artist.is_none() {
+            decode::fill_tags(path, &mut metadata);
+        }
+        if metadata.duration.is_none() {
+            metadata.duration =
+                header_duration(path).or_else(|| decode::track_info(path)?.duration);
+        }
+        Ok(metadata)
+    }
+
+    /// "Artist – Title" when both are known, otherwise whichever is.
+    pub fn display_title(&self) -> Option<String> {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => Some(format!("{artist} – {title}")),
+            (None, Some(title)) => Some(title.clone()),
+            (Some(artist), None) => Some(artist.clone()),
+            (None, None) => None,
+        }
+    }
+
+    fn read_flac(&mut self, file: &mut (impl Read + Seek)) -> io::Result<()> {
+        file.seek(SeekFrom::Start(4))?;
+        let mut pictures = Vec::new();
+        loop {
+            let mut header = [0; 4];
+            if file.read_exact(&mut header).is_err() {
+                break;
+            }
+            let last = header[0] & 0x80 != 0;
+            let kind = header[0] & 0x7f;
+            let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            match kind {
+                4 | 6 => {
+                    let Some(block) = read_block(file, length)? else {
+                        break;
+                    };
+                    if kind == 4 {
+                        self.apply_vorbis_comments(&block, &mut pictures);
+                    } else if let Some(picture) = flac_picture(&block) {
+                        pictures.push(picture);
+                    }
+                }
+                _ => {
+                    file.seek(SeekFrom::Current(length as i64))?;
+                }
+            }
+            if last {
+                break;
+            }
+        }
+        self.cover = pick_cover(pictures);
+        Ok(())
+    }
+
+    fn read_ogg(&mut self, file: &mut (impl Read + Seek)) -> io::Result<()> {
+        let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+        let mut serial = None;
+        for _ in 0..MAX_OGG_PAGES {
+            let Some(page) = OggPage::read(file)? else {
+                break;
+            };
+            if *serial.get_or_insert(page.serial) != page.serial {
+                continue;
+            }
+            for (segment, complete) in page.segments() {
+                packets.last_mut().unwrap().extend_from_slice(segment);
+                if complete {
+                    packets.push(Vec::new());
+                }
+            }
+            if packets.len() > 2 {
+                break;
+            }
+        }
+
+        let ident = packets.first().map(Vec::as_slice).unwrap_or_default();
+        let (rate, pre_skip) = if ident.starts_with(b"\x01vorbis") {
+            (ident.get(12..16).map(le_u32), 0)
+        } else if ident.starts_with(b"OpusHead") {
+            let pre_skip = ident
+                .get(10..12)
+                .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as u64);
+            (Some(48000), pre_skip)
+        } else {
+            (None, 0)
+        };
+
+        let mut pictures = Vec::new();
+        if let Some(comments) = packets.get(1) {
+            if let Some(body) = comments
+                .strip_prefix(b"\x03vorbis")
+                .or_else(|| comments.strip_prefix(b"OpusTags"))
+            {
+                self.apply_vorbis_comments(body, &mut pictures);
+            }
+        }
+        self.cover = pick_cover(pictures);
+
+        if let (Some(rate), Some(serial)) = (rate.filter(|&rate| rate > 0), serial) {
+            if let Some(granule) = last_granule(file, serial)? {
+                let samples = granule.saturating_sub(pre_skip);
+                self.duration = Some(Duration::from_secs_f64(samples as f64 / rate as f64));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_id3v2(&mut self, file: &mut (impl Read + Seek)) -> io::Result<()> {
+        let Some(header) = read_block(file, 10)? else {
+            return Ok(());
+        };
+        let version = header[3];
+        let flags = header[5];
+        let size = syncsafe(&header[6..10]) as usize;
+        let Some(mut tag) = read_block(file, size)? else {
+            return Ok(());
+        };
+        if !(2..=4).contains(&version) {
+            return Ok(());
+        }
+        if flags & 0x80 != 0 && version < 4 {
+            tag = unsynchronize(&tag);
+        }
+
+        let mut at = 0;
+        if flags & 0x40 != 0 && version > 2 {
+            let Some(bytes) = tag.get(..4) else {
+                return Ok(());
+            };
+            at = if version == 3 {
+                be_u32(bytes) as usize + 4
+            } else {
+                syncsafe(bytes) as usize
+            };
+        }
+
+        let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+        let mut pictures = Vec::new();
+        let mut chapters = Vec::new();
+        let mut loop_tags = LoopTags::default();
+        while let Some(frame_header) = tag.get(at..at + header_len) {
+            let id = &frame_header[..id_len];
+            if id[0] == 0 {
+                break;
+            }
+            let size = match version {
+                2 => u32::from_be_bytes([0, frame_header[3], frame_header[4], frame_header[5]]),
+                3 => be_u32(&frame_header[4..8]),
+                _ => syncsafe(&frame_header[4..8]),
+            } as usize;
+            let start = at + header_len;
+            let Some(body) = tag.get(start..start + size) else {
+                break;
+            };
+            at = start + size;
+
+            let mut body = body.to_vec();
+            if version == 4 {
+                let format = frame_header[9];
+                if format & 0x02 != 0 {
+                    body = unsynchronize(&body);
+                }
+                if format & 0x01 != 0 {
+                    // Data length indicator.
+                    body.drain(..body.len().min(4));
+                }
+            }
+
+            match id {
+                b"TIT2" | b"TT2" => self.title = id3_text(&body),
+                b"TPE1" | b"TP1" => self.artist = id3_text(&body),
+                b"TALB" | b"TAL" => self.album = id3_text(&body),
+                b"TRCK" | b"TRK" => self.track_number = id3_text(&body).and_then(track_number),
+                b"TLEN" | b"TLE" => {
+                    self.duration = id3_text(&body)
+                        .and_then(|ms| ms.trim().parse::<u64>().ok())
+                        .filter(|&ms| ms > 0)
+                        .map(Duration::from_millis);
+                }
+                b"APIC" => pictures.extend(id3_picture(&body, false)),
+                b"PIC" => pictures.extend(id3_picture(&body, true)),
+                b"CHAP" => chapters.extend(id3_chapter(&body, version, chapters.len())),
+                b"TXXX" | b"TXX" => {
+                    if let Some((key, value)) = id3_user_text(&body) {
+                        let key = key.to_ascii_uppercase();
+                        if !self.replay_gain.apply(&key, &value) {
+                            loop_tags.apply(&key, &value);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.cover = pick_cover(pictures);
+        if !chapters.is_empty() {
+            chapters.sort_by_key(|chapter| chapter.start);
+            self.chapters = chapters;
+        }
+        self.loop_points = self.loop_points.or(loop_tags.points());
+        Ok(())
+    }
+
+    /// Walks the chunks of a WAV for the `smpl` chunk, whose first loop
+    /// counts its start and its inclusive end in frames.
+    fn read_riff(&mut self, file: &mut (impl Read + Seek)) -> io::Result<()> {
+        file.seek(SeekFrom::Start(12))?;
+        loop {
+            let Some(header) = read_block(file, 8)? else {
+                return Ok(());
+            };
+            let length = le_u32(&header[4..8]) as usize;
+            if &header[..4] != b"smpl" {
+                // Chunks are padded to an even length.
+                file.seek(SeekFrom::Current((length + length % 2) as i64))?;
+                continue;
+            }
+            let Some(body) = read_block(file, length)? else {
+                return Ok(());
+            };
+            // 36 bytes of header, its loop count at 28, then 24 bytes a loop.
+            let first = body.get(36..60).filter(|_| le_u32(&body[28..32]) > 0);
+            self.loop_points = first.and_then(|first| {
+                let (start, end) = (le_u32(&first[8..12]) as u64, le_u32(&first[12..16]) as u64);
+                (end >= start).then_some(LoopPoints {
+                    start,
+                    end: end + 1,
+                })
+            });
+            return Ok(());
+        }
+    }
+
+    fn read_mp4(&mut self, file: &mut (impl Read + Seek)) -> io::Result<()> {
+        let end = file.seek(SeekFrom::End(0))?;
+        let mut scope = (0, end);
+        for kind in [b"moov", b"udta", b"chpl"] {
+            match find_box(file, scope, kind)? {
+                Some(found) => scope = found,
+                None => return Ok(()),
+            }
+        }
+        let (start, end) = scope;
+        file.seek(SeekFrom::Start(start))?;
+        if let Some(body) = read_block(file, (end - start) as usize)? {
+            self.chapters = nero_chapters(&body);
+            self.chapters.sort_by_key(|chapter| chapter.start);
+        }
+        Ok(())
+    }
+
+    fn read_id3v1(&mut self, file: &mut (impl Read + Seek)) -> io::Result<()> {
+        let len = file.seek(SeekFrom::End(0))?;
+        if len < 128 {
+            return Ok(());
+        }
+        file.seek(SeekFrom::End(-128))?;
+        let mut tag = [0; 128];
+        file.read_exact(&mut tag)?;
+        if &tag[..3] != b"TAG" {
+            return Ok(());
+        }
+        let field = |bytes: &[u8]| {
+            let text = latin1(bytes.split(|&byte| byte == 0).next().unwrap_or_default());
+            Some(text.trim().to_string()).filter(|text| !text.is_empty())
+        };
+        // Only fills in what an ID3v2 tag left out.
+        self.title = self.title.take().or_else(|| field(&tag[3..33]));
+        self.artist = self.artist.take().or_else(|| field(&tag[33..63]));
+        self.album = self.album.take().or_else(|| field(&tag[63..93]));
+        // ID3v1.1 keeps the track number in the last byte of the comment.
+        if self.track_number.is_none() && tag[125] == 0 && tag[126] != 0 {
+            self.track_number = Some(tag[126] as u32);
+        }
+        Ok(())
+    }
+
+    fn apply_vorbis_comments(&mut self, block: &[u8], pictures: &mut Vec<(u32, CoverArt)>) {
+        let mut reader = block;
+        let Some(vendor_len) = take_u32_le(&mut reader) else {
+            return;
+        };
+        let Some(rest) = reader.get(vendor_len as usize..) else {
+            return;
+        };
+        reader = rest;
+        let Some(count) = take_u32_le(&mut reader) else {
+            return;
+        };
+        // CHAPTER001=00:01:02.500 and CHAPTER001NAME=..., by number.
+        let mut chapters = BTreeMap::<u32, (Option<Duration>, Option<String>)>::new();
+        let mut loop_tags = LoopTags::default();
+        for _ in 0..count {
+            let Some(len) = take_u32_le(&mut reader) else {
+                return;
+            };
+            let Some(comment) = reader.get(..len as usize) else {
+                return;
+            };
+            reader = &reader[len as usize..];
+
+            let comment = String::from_utf8_lossy(comment);
+            let Some((key, value)) = comment.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            let key = key.to_ascii_uppercase();
+            if self.replay_gain.apply(&key, value) || loop_tags.apply(&key, value) {
+                continue;
+            }
+            match key.as_str() {
+                "TITLE" => self.title = Some(value.to_string()),
+                "ARTIST" => self.artist = Some(value.to_string()),
+                "ALBUM" => self.album = Some(value.to_string()),
+                "TRACKNUMBER" => self.track_number = track_number(value.to_string()),
+                "METADATA_BLOCK_PICTURE" => {
+                    pictures.extend(base64_decode(value).as_deref().and_then(flac_picture));
+                }
+                key => {
+                    let Some(chapter) = key.strip_prefix("CHAPTER") else {
+                        continue;
+                    };
+                    let (number, name) = match chapter.strip_suffix("NAME") {
+                        Some(number) => (number, true),
+                        None => (chapter, false),
+                    };
+                    let Ok(number) = number.parse() else {
+                        continue;
+                    };
+                    let entry = chapters.entry(number).or_default();
+                    match name {
+                        true => entry.1 = Some(value.to_string()),
+                        false => entry.0 = timestamp(value),
+                    }
+                }
+            }
+        }
+        if !chapters.is_empty() {
+            self.chapters = chapters
+                .into_iter()
+                .filter_map(|(number, (start, title))| {
+                    Some(Chapter {
+                        title: title.unwrap_or_else(|| format!("Chapter {number}")),
+                        start: start?,
+                    })
+                })
+                .collect();
+            self.chapters.sort_by_key(|chapter| chapter.start);
+        }
+        self.loop_points = self.loop_points.or(loop_tags.points());
+    }
+}
+
+/// Reads `length` bytes, or returns `None` if the block is implausibly large
+/// or the file ends first.
+fn read_block(file: &mut impl Read, length: usize) -> io::Result<Option<Vec<u8>>> {
+    if length > MAX_BLOCK {
+        return Ok(None);
+    }
+    let mut block = vec![0; length];
+    match file.read_exact(&mut block) {
+        Ok(()) => Ok(Some(block)),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn take_u32_le(reader: &mut &[u8]) -> Option<u32> {
+    let value = le_u32(reader.get(..4)?);
+    *reader = &reader[4..];
+    Some(value)
+}
+
+fn take_u32_be(reader: &mut &[u8]) -> Option<u32> {
+    let value = be_u32(reader.get(..4)?);
+    *reader = &reader[4..];
+    Some(value)
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| (value << 7) | (byte & 0x7f) as u32)
+}
+
+/// Undoes ID3 unsynchronisation, which inserts a zero after every 0xFF.
+fn unsynchronize(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut previous = 0;
+    for &byte in bytes {
+        if !(previous == 0xff && byte == 0) {
+            out.push(byte);
+        }
+        previous = byte;
+    }
+    out
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+/// Decodes ID3 text in `encoding` up to the first terminator, returning the
+/// text and whatever follows the terminator.
+fn id3_decode(encoding: u8, bytes: &[u8]) -> (String, &[u8]) {
+    match encoding {
+        1 | 2 => {
+            let end = bytes
+                .chunks_exact(2)
+                .position(|pair| pair == [0, 0])
+                .map_or(bytes.len() & !1, |index| index * 2);
+            let rest = bytes.get(end + 2..).unwrap_or_default();
+            let mut units = bytes[..end]
+                .chunks_exact(2)
+                .map(|pair| [pair[0], pair[1]])
+                .peekable();
+            let little_endian = match units.peek() {
+                Some([0xff, 0xfe]) => {
+                    units.next();
+                    true
+                }
+                Some([0xfe, 0xff]) => {
+                    units.next();
+                    false
+                }
+                _ => false,
+            };
+            let units = units
+                .map(|pair| {
+                    if little_endian {
+                        u16::from_le_bytes(pair)
+                    } else {
+                        u16::from_be_bytes(pair)
+                    }
+                })
+                .collect::<Vec<_>>();
+            (String::from_utf16_lossy(&units), rest)
+        }
+        _ => {
+            let end = bytes
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(bytes.len());
+            let rest = bytes.get(end + 1..).unwrap_or_default();
+            let text = if encoding == 3 {
+                String::from_utf8_lossy(&bytes[..end]).into_owned()
+            } else {
+                latin1(&bytes[..end])
+            };
+            (text, rest)
+        }
+    }
+}
+
+fn id3_text(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+    let (text, _) = id3_decode(encoding, text);
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// A `TXXX` frame: a description, then the text it labels.
+fn id3_user_text(body: &[u8]) -> Option<(String, String)> {
+    let (&encoding, rest) = body.split_first()?;
+    let (description, value) = id3_decode(encoding, rest);
+    let (value, _) = id3_decode(encoding, value);
+    Some((description.trim().to_string(), value.trim().to_string()))
+}
+
+/// A `CHAP` frame: an element id, start and end in milliseconds, byte
+/// offsets, then frames of its own, of which the title is used.
+fn id3_chapter(body: &[u8], version: u8, index: usize) -> Option<Chapter> {
+    let id_end = body.iter().position(|&byte| byte == 0)?;
+    let mut rest = body.get(id_end + 1..)?;
+    let start = take_u32_be(&mut rest)?;
+    // The end time and the byte offsets aren't needed.
+    let mut frames = rest.get(12..)?;
+    let mut title = None;
+    while let Some(header) = frames.get(..10) {
+        let size = match version {
+            3 => be_u32(&header[4..8]),
+            _ => syncsafe(&header[4..8]),
+        } as usize;
+        let Some(frame) = frames.get(10..10 + size) else {
+            break;
+        };
+        if &header[..4] == b"TIT2" {
+            title = id3_text(frame);
+        }
+        frames = &frames[10 + size..];
+    }
+    Some(Chapter {
+        title: title.unwrap_or_else(|| format!("Chapter {}", index + 1)),
+        start: Duration::from_millis(start as u64),
+    })
+}
+
+/// `HH:MM:SS.mmm`, as chapter comments give their start.
+fn timestamp(text: &str) -> Option<Duration> {
+    let mut parts = text.split(':').rev();
+    let seconds = parts.next()?.trim().parse::<f64>().ok()?;
+    let minutes = parts
+        .next()
+        .map_or(Some(0), |part| part.trim().parse::<u64>().ok())?;
+    let hours = parts
+        .next()
+        .map_or(Some(0), |part| part.trim().parse::<u64>().ok())?;
+    if parts.next().is_some() || !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Looks for a box of `kind` between the offsets of `scope` and returns
+/// where its body starts and ends.
+fn find_box(
+    file: &mut (impl Read + Seek),
+    scope: (u64, u64),
+    kind: &[u8; 4],
+) -> io::Result<Option<(u64, u64)>> {
+    let (mut at, end) = scope;
+    while at + 8 <= end {
+        file.seek(SeekFrom::Start(at))?;
+        let Some(header) = read_block(file, 8)? else {
+            return Ok(None);
+        };
+        let (size, header_len) = match be_u32(&header) {
+            0 => (end - at, 8),
+            1 => {
+                let Some(large) = read_block(file, 8)? else {
+                    return Ok(None);
+                };
+                (u64::from_be_bytes(large.try_into().unwrap()), 16)
+            }
+            size => (size as u64, 8),
+        };
+        if size < header_len || at + size > end {
+            return Ok(None);
+        }
+        if &header[4..8] == kind {
+            return Ok(Some((at + header_len, at + size)));
+        }
+        at += size;
+    }
+    Ok(None)
+}
+
+/// The body of a `chpl` box: version and flags, a count, then each
+/// chapter's start in units of 100 ns and its title.
+fn nero_chapters(body: &[u8]) -> Vec<Chapter> {
+    let skip = if body.first() == Some(&0) { 4 } else { 8 };
+    let Some((&count, mut rest)) = body.get(skip..).and_then(<[u8]>::split_first) else {
+        return Vec::new();
+    };
+    let mut chapters = Vec::new();
+    for _ in 0..count {
+        let Some(start) = rest.get(..8) else {
+            break;
+        };
+        let start = u64::from_be_bytes(start.try_into().unwrap());
+        let Some(&len) = rest.get(8) else {
+            break;
+        };
+        let Some(title) = rest.get(9..9 + len as usize) else {
+            break;
+        };
+        chapters.push(Chapter {
+            title: String::from_utf8_lossy(title).into_owned(),
+            start: Duration::from_nanos(start.saturating_mul(100)),
+        });
+        rest = &rest[9 + len as usize..];
+    }
+    chapters
+}
+
+fn id3_picture(body: &[u8], v22: bool) -> Option<(u32, CoverArt)> {
+    let (&encoding, rest) = body.split_first()?;
+    let (mime_type, rest) = if v22 {
+        let format = rest.get(..3)?;
+        let mime = match &format.to_ascii_uppercase()[..] {
+            b"PNG" => "image/png".to_string(),
+            b"JPG" => "image/jpeg".to_string(),
+            other => format!("image/{}", latin1(other).to_lowercase()),
+        };
+        (mime, &rest[3..])
+    } else {
+        id3_decode(0, rest)
+    };
+    let (&kind, rest) = rest.split_first()?;
+    let (_, data) = id3_decode(encoding, rest);
+    (!data.is_empty()).then(|| {
+        (
+            kind as u32,
+            CoverArt {
+                mime_type,
+                data: data.to_vec(),
+            },
+        )
+    })
+}
+
+fn flac_picture(block: &[u8]) -> Option<(u32, CoverArt)> {
+    let mut reader = block;
+    let kind = take_u32_be(&mut reader)?;
+    let mime_len = take_u32_be(&mut reader)? as usize;
+    let mime_type = String::from_utf8_lossy(reader.get(..mime_len)?).into_owned();
+    reader = &reader[mime_len..];
+    let description_len = take_u32_be(&mut reader)? as usize;
+    reader = reader.get(description_len + 16..)?;
+    let data_len = take_u32_be(&mut reader)? as usize;
+    let data = reader.get(..data_len)?.to_vec();
+    Some((kind, CoverArt { mime_type, data }))
+}
+
+/// Prefers the front cover (picture type 3), then whatever came first.
+fn pick_cover(pictures: Vec<(u32, CoverArt)>) -> Option<CoverArt> {
+    let front = pictures
+        .iter()
+        .position(|(kind, _)| *kind == 3)
+        .unwrap_or(0);
+    pictures.into_iter().nth(front).map(|(_, cover)| cover)
+}
+
+/// "3" and "3/12" both give 3.
+fn track_number(text: String) -> Option<u32> {
+    text.split('/').next()?.trim().parse().ok()
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            b'\r' | b'\n' | b' ' => continue,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+struct OggPage {
+    serial: u32,
+    lacing: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl OggPage {
+    fn read(file: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut header = [0; 27];
+        if file.read_exact(&mut header).is_err() || &header[..4] != b"OggS" {
+            return Ok(None);
+        }
+        let serial = le_u32(&header[14..18]);
+        let Some(lacing) = read_block(file, header[26] as usize)? else {
+            return Ok(None);
+        };
+        let body_len = lacing.iter().map(|&len| len as usize).sum();
+        let Some(body) = read_block(file, body_len)? else {
+            return Ok(None);
+        };
+        Ok(Some(OggPage {
+            serial,
+            lacing,
+            body,
+        }))
+    }
+
+    /// Packet pieces on this page, each with whether it ends its packet.
+    fn segments(&self) -> Vec<(&[u8], bool)> {
+        let mut segments = Vec::new();
+        let mut start = 0;
+        let mut end = 0;
+        for &len in &self.lacing {
+            end += len as usize;
+            if len < 255 {
+                segments.push((&self.body[start..end], true));
+                start = end;
+            }
+        }
+        if start < end {
+            segments.push((&self.body[start..end], false));
+        }
+        segments
+    }
+}
+
+/// Granule position of the last page of stream `serial`, found by scanning
+/// the end of the file.
+fn last_granule(file: &mut (impl Read + Seek), serial: u32) -> io::Result<Option<u64>> {
+    const TAIL: u64 = 64 * 1024;
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+
+    let granule = (0..tail.len().saturating_sub(27))
+        .rev()
+        .filter(|&at| &tail[at..at + 4] == b"OggS")
+        .find(|&at| le_u32(&tail[at + 14..at + 18]) == serial)
+        .map(|at| u64::from_le_bytes(tail[at + 6..at + 14].try_into().unwrap()));
+    Ok(granule.filter(|&granule| granule > 0 && granule != u64::MAX))
+}
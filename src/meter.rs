@@ -0,0 +1,294 @@
+use crate::{atomic::AtomicF32, gain::linear_to_db};
+use rodio::{source::SeekError, Source};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Level reported for silence, and for everything while nothing is playing.
+pub const METER_FLOOR_DB: f32 = -90.0;
+
+/// Default fall rate of the peak reading, in dB per second.
+pub const METER_PEAK_DECAY: f32 = 20.0;
+
+/// Channels beyond this many are passed through but not metered.
+pub const METER_MAX_CHANNELS: usize = 8;
+
+/// How much recent audio the phase correlation is taken over, and how fast
+/// it falls back to 0.0 once audio stops.
+pub const METER_CORRELATION_WINDOW: Duration = Duration::from_millis(400);
+
+const RMS_WINDOW: Duration = Duration::from_millis(300);
+
+/// Channel power below this counts as silence for the correlation, which is
+/// meaningless without something in both channels. The floor, squared.
+const CORRELATION_SILENCE: f32 = 1e-9;
+
+/// Frames between updates of the shared readings.
+const PUBLISH_FRAMES: u32 = 256;
+
+/// Readings are considered stale, and drop to the floor, after this long.
+const STALE_AFTER: Duration = Duration::from_millis(200);
+
+/// Level of one output channel, in dBFS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelLevel {
+    pub peak_db: f32,
+    pub rms_db: f32,
+}
+
+/// Readings published by a [`Meter`], plus its peak decay setting.
+pub struct MeterControls {
+    peaks: [AtomicF32; METER_MAX_CHANNELS],
+    rms: [AtomicF32; METER_MAX_CHANNELS],
+    channels: AtomicUsize,
+    clipped: AtomicBool,
+    correlation: AtomicF32,
+    peak_decay: AtomicF32,
+    epoch: Instant,
+    updated_ns: AtomicU64,
+}
+
+impl MeterControls {
+    pub fn new(peak_decay: f32) -> Self {
+        MeterControls {
+            peaks: std::array::from_fn(|_| AtomicF32::new(0.0)),
+            rms: std::array::from_fn(|_| AtomicF32::new(0.0)),
+            channels: AtomicUsize::new(0),
+            clipped: AtomicBool::new(false),
+            correlation: AtomicF32::new(0.0),
+            peak_decay: AtomicF32::new(peak_decay),
+            epoch: Instant::now(),
+            updated_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Current peak and RMS level of each channel. All channels read
+    /// [`METER_FLOOR_DB`] once audio has stopped flowing, e.g. while paused.
+    pub fn levels(&self) -> Vec<ChannelLevel> {
+        let updated = Duration::from_nanos(self.updated_ns.load(Ordering::Acquire));
+        let stale = self.epoch.elapsed().saturating_sub(updated) > STALE_AFTER;
+        let db = |level: f32| {
+            if stale {
+                METER_FLOOR_DB
+            } else {
+                linear_to_db(level).max(METER_FLOOR_DB)
+            }
+        };
+        let channels = self.channels.load(Ordering::Acquire);
+        (0..channels)
+            .map(|channel| ChannelLevel {
+                peak_db: db(self.peaks[channel].load()),
+                rms_db: db(self.rms[channel].load()),
+            })
+            .collect()
+    }
+
+    /// Phase correlation of the first two channels over
+    /// [`METER_CORRELATION_WINDOW`], from -1.0 (opposite, cancelling in mono)
+    /// through 0.0 (unrelated) to 1.0 (the same, mono already). `None` for a
+    /// single channel. While either channel is silent it reads 0.0, and once
+    /// audio stops it falls back to 0.0 over the window.
+    pub fn correlation(&self) -> Option<f32> {
+        if self.channels.load(Ordering::Acquire) < 2 {
+            return None;
+        }
+        let updated = Duration::from_nanos(self.updated_ns.load(Ordering::Acquire));
+        let stale = self
+            .epoch
+            .elapsed()
+            .saturating_sub(updated)
+            .saturating_sub(STALE_AFTER);
+        let decay = (-stale.as_secs_f32() / METER_CORRELATION_WINDOW.as_secs_f32()).exp();
+        Some(self.correlation.load() * decay)
+    }
+
+    /// Whether any sample has reached full scale since the last [`MeterControls::reset_clip`].
+    pub fn clipped(&self) -> bool {
+        self.clipped.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_clip(&self) {
+        self.clipped.store(false, Ordering::Relaxed);
+    }
+
+    pub fn peak_decay(&self) -> f32 {
+        self.peak_decay.load()
+    }
+
+    /// Sets how fast the peak reading falls back, in dB per second.
+    pub fn set_peak_decay(&self, db_per_second: f32) {
+        self.peak_decay.store(db_per_second.max(0.0));
+    }
+}
+
+impl Default for MeterControls {
+    fn default() -> Self {
+        MeterControls::new(METER_PEAK_DECAY)
+    }
+}
+
+/// Passes audio through unchanged while measuring per-channel peak and RMS
+/// levels, and the phase correlation of the first two channels.
+pub struct Meter<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<MeterControls>,
+    peaks: [f32; METER_MAX_CHANNELS],
+    squares: [f32; METER_MAX_CHANNELS],
+    /// Running means of left squared, right squared and left times right.
+    products: [f32; 3],
+    left: f32,
+    peak_falloff: f32,
+    rms_alpha: f32,
+    correlation_alpha: f32,
+    sample_rate: u32,
+    frames: u32,
+    channel: u16,
+    channels: u16,
+}
+
+impl<S> Meter<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, controls: Arc<MeterControls>) -> Self {
+        Meter {
+            source,
+            controls,
+            peaks: [0.0; METER_MAX_CHANNELS],
+            squares: [0.0; METER_MAX_CHANNELS],
+            products: [0.0; 3],
+            left: 0.0,
+            peak_falloff: 1.0,
+            rms_alpha: 1.0,
+            correlation_alpha: 1.0,
+            sample_rate: 0,
+            frames: 0,
+            channel: 0,
+            channels: 0,
+        }
+    }
+
+    pub fn controls(&self) -> Arc<MeterControls> {
+        self.controls.clone()
+    }
+
+    fn next_frame(&mut self) {
+        let channels = self.source.channels().max(1);
+        let sample_rate = self.source.sample_rate().max(1);
+        if channels != self.channels {
+            // Readings from the old layout don't map onto the new one.
+            self.peaks = [0.0; METER_MAX_CHANNELS];
+            self.squares = [0.0; METER_MAX_CHANNELS];
+            self.products = [0.0; 3];
+            self.channels = channels;
+        }
+        if sample_rate != self.sample_rate || self.frames == 0 {
+            self.sample_rate = sample_rate;
+            let decay_per_frame = self.controls.peak_decay() / sample_rate as f32;
+            self.peak_falloff = 10.0f32.powf(-decay_per_frame / 20.0);
+            let alpha =
+                |window: Duration| 1.0 - (-1.0 / (window.as_secs_f32() * sample_rate as f32)).exp();
+            self.rms_alpha = alpha(RMS_WINDOW);
+            self.correlation_alpha = alpha(METER_CORRELATION_WINDOW);
+        }
+
+        self.frames += 1;
+        if self.frames >= PUBLISH_FRAMES {
+            self.frames = 0;
+            self.publish();
+        }
+    }
+
+    fn publish(&self) {
+        let metered = (self.channels as usize).min(METER_MAX_CHANNELS);
+        for channel in 0..metered {
+            self.controls.peaks[channel].store(self.peaks[channel]);
+            self.controls.rms[channel].store(self.squares[channel].sqrt());
+        }
+        let [left, right, both] = self.products;
+        let correlation = if left < CORRELATION_SILENCE || right < CORRELATION_SILENCE {
+            0.0
+        } else {
+            (both / (left * right).sqrt()).clamp(-1.0, 1.0)
+        };
+        self.controls.correlation.store(correlation);
+        self.controls.channels.store(metered, Ordering::Release);
+        let now = self.controls.epoch.elapsed().as_nanos() as u64;
+        self.controls.updated_ns.store(now, Ordering::Release);
+    }
+}
+
+impl<S> Iterator for Meter<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.next_frame();
+        }
+        let sample = self.source.next()?;
+
+        let channel = self.channel as usize;
+        if channel < METER_MAX_CHANNELS {
+            let level = sample.abs();
+            self.peaks[channel] = level.max(self.peaks[channel] * self.peak_falloff);
+            self.squares[channel] += (sample * sample - self.squares[channel]) * self.rms_alpha;
+            if level >= 1.0 {
+                self.controls.clipped.store(true, Ordering::Relaxed);
+            }
+        }
+        match channel {
+            0 => self.left = sample,
+            1 => {
+                let alpha = self.correlation_alpha;
+                let left = self.left;
+                for (mean, product) in
+                    self.products
+                        .iter_mut()
+                        .zip([left * left, sample * sample, left * sample])
+                {
+                    *mean += (product - *mean) * alpha;
+                }
+            }
+            _ => {}
+        }
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Meter<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.channel = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,663 @@
+//! Just enough of the D-Bus wire protocol for the MPRIS service: the session
+//! bus over a Unix socket, EXTERNAL authentication, and marshalling of the
+//! basic, array, struct, dict entry and variant types.
+
+use crate::lock::Lock;
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::unix::{fs::MetadataExt, net::UnixStream},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+};
+
+/// Refuses messages bigger than this, well past anything MPRIS sends.
+const MAX_MESSAGE: usize = 1 << 20;
+
+/// Reply flag telling the receiver not to answer.
+pub(crate) const NO_REPLY_EXPECTED: u8 = 0x1;
+
+/// A value and its D-Bus type.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Arg {
+    Byte(u8),
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    F64(f64),
+    Str(String),
+    Path(String),
+    Signature(String),
+    /// The element signature, kept so empty arrays can be written.
+    Array(String, Vec<Arg>),
+    Struct(Vec<Arg>),
+    Entry(Box<Arg>, Box<Arg>),
+    Variant(Box<Arg>),
+}
+
+impl Arg {
+    /// An `a{sv}` dictionary, as properties and metadata are sent.
+    pub(crate) fn dict(entries: Vec<(&str, Arg)>) -> Arg {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| {
+                Arg::Entry(
+                    Box::new(Arg::Str(key.to_string())),
+                    Box::new(Arg::Variant(Box::new(value))),
+                )
+            })
+            .collect();
+        Arg::Array("{sv}".to_string(), entries)
+    }
+
+    pub(crate) fn strings<S: Into<String>>(items: impl IntoIterator<Item = S>) -> Arg {
+        let items = items
+            .into_iter()
+            .map(|item| Arg::Str(item.into()))
+            .collect();
+        Arg::Array("s".to_string(), items)
+    }
+
+    fn signature(&self) -> String {
+        match self {
+            Arg::Byte(_) => "y".to_string(),
+            Arg::Bool(_) => "b".to_string(),
+            Arg::I32(_) => "i".to_string(),
+            Arg::U32(_) => "u".to_string(),
+            Arg::I64(_) => "x".to_string(),
+            Arg::F64(_) => "d".to_string(),
+            Arg::Str(_) => "s".to_string(),
+            Arg::Path(_) => "o".to_string(),
+            Arg::Signature(_) => "g".to_string(),
+            Arg::Array(element, _) => format!("a{element}"),
+            Arg::Struct(fields) => {
+                let fields: String = fields.iter().map(Arg::signature).collect();
+                format!("({fields})")
+            }
+            Arg::Entry(key, value) => format!("{{{}{}}}", key.signature(), value.signature()),
+            Arg::Variant(_) => "v".to_string(),
+        }
+    }
+
+    /// Looks through variants.
+    fn inner(&self) -> &Arg {
+        match self {
+            Arg::Variant(inner) => inner.inner(),
+            arg => arg,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self.inner() {
+            Arg::Str(s) | Arg::Path(s) | Arg::Signature(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        match *self.inner() {
+            Arg::Byte(n) => Some(n.into()),
+            Arg::I32(n) => Some(n.into()),
+            Arg::U32(n) => Some(n.into()),
+            Arg::I64(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match *self.inner() {
+            Arg::F64(n) => Some(n),
+            _ => self.as_i64().map(|n| n as f64),
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match *self.inner() {
+            Arg::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    MethodCall = 1,
+    MethodReturn = 2,
+    Error = 3,
+    Signal = 4,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Message {
+    pub(crate) kind: Kind,
+    pub(crate) flags: u8,
+    pub(crate) serial: u32,
+    pub(crate) path: Option<String>,
+    pub(crate) interface: Option<String>,
+    pub(crate) member: Option<String>,
+    pub(crate) error_name: Option<String>,
+    pub(crate) reply_serial: Option<u32>,
+    pub(crate) destination: Option<String>,
+    pub(crate) sender: Option<String>,
+    pub(crate) body: Vec<Arg>,
+}
+
+impl Message {
+    fn new(kind: Kind) -> Self {
+        Message {
+            kind,
+            flags: 0,
+            serial: 0,
+            path: None,
+            interface: None,
+            member: None,
+            error_name: None,
+            reply_serial: None,
+            destination: None,
+            sender: None,
+            body: Vec::new(),
+        }
+    }
+
+    pub(crate) fn call(destination: &str, path: &str, interface: &str, member: &str) -> Self {
+        Message {
+            destination: Some(destination.to_string()),
+            path: Some(path.to_string()),
+            interface: Some(interface.to_string()),
+            member: Some(member.to_string()),
+            ..Message::new(Kind::MethodCall)
+        }
+    }
+
+    pub(crate) fn signal(path: &str, interface: &str, member: &str) -> Self {
+        Message {
+            path: Some(path.to_string()),
+            interface: Some(interface.to_string()),
+            member: Some(member.to_string()),
+            ..Message::new(Kind::Signal)
+        }
+    }
+
+    /// The answer to this call, to be filled with its return values.
+    pub(crate) fn reply(&self) -> Self {
+        Message {
+            reply_serial: Some(self.serial),
+            destination: self.sender.clone(),
+            ..Message::new(Kind::MethodReturn)
+        }
+    }
+
+    pub(crate) fn error(&self, name: &str, text: &str) -> Self {
+        Message {
+            kind: Kind::Error,
+            error_name: Some(name.to_string()),
+            body: vec![Arg::Str(text.to_string())],
+            ..self.reply()
+        }
+    }
+
+    pub(crate) fn with(mut self, arg: Arg) -> Self {
+        self.body.push(arg);
+        self
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Writer::default();
+        for arg in &self.body {
+            body.write(arg);
+        }
+        let signature: String = self.body.iter().map(Arg::signature).collect();
+
+        let field = |code: u8, value: Arg| {
+            Arg::Struct(vec![Arg::Byte(code), Arg::Variant(Box::new(value))])
+        };
+        let text = |code: u8, value: &Option<String>, wrap: fn(String) -> Arg| {
+            value.clone().map(|value| field(code, wrap(value)))
+        };
+        let fields = [
+            text(1, &self.path, Arg::Path),
+            text(2, &self.interface, Arg::Str),
+            text(3, &self.member, Arg::Str),
+            text(4, &self.error_name, Arg::Str),
+            self.reply_serial.map(|serial| field(5, Arg::U32(serial))),
+            text(6, &self.destination, Arg::Str),
+            (!signature.is_empty()).then(|| field(8, Arg::Signature(signature))),
+        ];
+
+        let mut message = Writer::default();
+        message.bytes(&[b'l', self.kind as u8, self.flags, 1]);
+        message.write(&Arg::U32(body.buf.len() as u32));
+        message.write(&Arg::U32(self.serial));
+        message.write(&Arg::Array(
+            "(yv)".to_string(),
+            fields.into_iter().flatten().collect(),
+        ));
+        message.pad(8);
+        message.bytes(&body.buf);
+        message.buf
+    }
+
+    fn decode(header: &[u8], body: &[u8]) -> io::Result<Self> {
+        let big_endian = header[0] == b'B';
+        let kind = match header[1] {
+            1 => Kind::MethodCall,
+            2 => Kind::MethodReturn,
+            3 => Kind::Error,
+            4 => Kind::Signal,
+            kind => return Err(invalid(format!("unknown message type {kind}"))),
+        };
+        let mut reader = Reader {
+            buf: header,
+            pos: 8,
+            big_endian,
+        };
+        let serial = reader.u32()?;
+        let mut message = Message {
+            flags: header[2],
+            serial,
+            ..Message::new(kind)
+        };
+        let mut signature = String::new();
+        let Arg::Array(_, fields) = reader.read("a(yv)")? else {
+            unreachable!("an array signature reads as an array");
+        };
+        for field in fields {
+            let Arg::Struct(parts) = field else {
+                continue;
+            };
+            let (Some(code), Some(value)) = (parts.first().and_then(Arg::as_i64), parts.get(1))
+            else {
+                continue;
+            };
+            let text = value.as_str().map(str::to_string);
+            match code {
+                1 => message.path = text,
+                2 => message.interface = text,
+                3 => message.member = text,
+                4 => message.error_name = text,
+                5 => message.reply_serial = value.as_i64().map(|serial| serial as u32),
+                6 => message.destination = text,
+                7 => message.sender = text,
+                8 => signature = text.unwrap_or_default(),
+                _ => {}
+            }
+        }
+
+        let mut reader = Reader {
+            buf: body,
+            pos: 0,
+            big_endian,
+        };
+        let mut rest = signature.as_str();
+        while !rest.is_empty() {
+            let (first, after) = split_type(rest)?;
+            message.body.push(reader.read(first)?);
+            rest = after;
+        }
+        Ok(message)
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn pad(&mut self, alignment: usize) {
+        while !self.buf.len().is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.write(&Arg::U32(s.len() as u32));
+        self.bytes(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn write(&mut self, arg: &Arg) {
+        self.pad(alignment(&arg.signature()));
+        match arg {
+            Arg::Byte(n) => self.buf.push(*n),
+            Arg::Bool(b) => self.bytes(&u32::from(*b).to_le_bytes()),
+            Arg::I32(n) => self.bytes(&n.to_le_bytes()),
+            Arg::U32(n) => self.bytes(&n.to_le_bytes()),
+            Arg::I64(n) => self.bytes(&n.to_le_bytes()),
+            Arg::F64(n) => self.bytes(&n.to_le_bytes()),
+            Arg::Str(s) | Arg::Path(s) => self.string(s),
+            Arg::Signature(s) => {
+                self.buf.push(s.len() as u8);
+                self.bytes(s.as_bytes());
+                self.buf.push(0);
+            }
+            Arg::Array(element, items) => {
+                let len_at = self.buf.len();
+                self.bytes(&[0; 4]);
+                // The length doesn't count the padding before the first element.
+                self.pad(alignment(element));
+                let start = self.buf.len();
+                for item in items {
+                    self.write(item);
+                }
+                let len = (self.buf.len() - start) as u32;
+                self.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+            }
+            Arg::Struct(fields) => {
+                for field in fields {
+                    self.write(field);
+                }
+            }
+            Arg::Entry(key, value) => {
+                self.write(key);
+                self.write(value);
+            }
+            Arg::Variant(inner) => {
+                self.write(&Arg::Signature(inner.signature()));
+                self.write(inner);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        self.pos = self.pos.next_multiple_of(N.min(8));
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| invalid("message ends early"))?;
+        self.pos += N;
+        let mut bytes: [u8; N] = bytes.try_into().expect("slice has N bytes");
+        if self.big_endian {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn text(&mut self, len: usize) -> io::Result<String> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid("message ends early"))?;
+        // Skips the terminating nul as well.
+        self.pos += len + 1;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("string isn't UTF-8"))
+    }
+
+    /// Reads one value of the single complete type `signature`.
+    fn read(&mut self, signature: &str) -> io::Result<Arg> {
+        let code = signature.as_bytes()[0];
+        Ok(match code {
+            b'y' => Arg::Byte(self.take::<1>()?[0]),
+            b'b' => Arg::Bool(self.u32()? != 0),
+            b'n' => Arg::I32(i16::from_le_bytes(self.take()?).into()),
+            b'q' => Arg::U32(u16::from_le_bytes(self.take()?).into()),
+            b'i' => Arg::I32(i32::from_le_bytes(self.take()?)),
+            b'u' | b'h' => Arg::U32(self.u32()?),
+            b'x' | b't' => Arg::I64(i64::from_le_bytes(self.take()?)),
+            b'd' => Arg::F64(f64::from_le_bytes(self.take()?)),
+            b's' | b'o' => {
+                let len = self.u32()? as usize;
+                let text = self.text(len)?;
+                match code {
+                    b'o' => Arg::Path(text),
+                    _ => Arg::Str(text),
+                }
+            }
+            b'g' => {
+                let len = self.take::<1>()?[0] as usize;
+                Arg::Signature(self.text(len)?)
+            }
+            b'v' => {
+                let len = self.take::<1>()?[0] as usize;
+                let inner = self.text(len)?;
+                let (first, _) = split_type(&inner)?;
+                Arg::Variant(Box::new(self.read(first)?))
+            }
+            b'a' => {
+                let len = self.u32()? as usize;
+                let element = &signature[1..];
+                self.pos = self.pos.next_multiple_of(alignment(element));
+                let end = self.pos + len;
+                if end > self.buf.len() {
+                    return Err(invalid("array runs past the message"));
+                }
+                let mut items = Vec::new();
+                while self.pos < end {
+                    items.push(self.read(element)?);
+                }
+                Arg::Array(element.to_string(), items)
+            }
+            b'(' | b'{' => {
+                self.pos = self.pos.next_multiple_of(8);
+                let mut rest = &signature[1..signature.len() - 1];
+                let mut fields = Vec::new();
+                while !rest.is_empty() {
+                    let (first, after) = split_type(rest)?;
+                    fields.push(self.read(first)?);
+                    rest = after;
+                }
+                if code == b'(' {
+                    Arg::Struct(fields)
+                } else {
+                    let [key, value] = <[Arg; 2]>::try_from(fields)
+                        .map_err(|_| invalid("dict entry without two fields"))?;
+                    Arg::Entry(Box::new(key), Box::new(value))
+                }
+            }
+            _ => return Err(invalid(format!("unsupported type '{}'", code as char))),
+        })
+    }
+}
+
+/// Splits the first complete type off `signature`.
+fn split_type(signature: &str) -> io::Result<(&str, &str)> {
+    let bytes = signature.as_bytes();
+    let mut end = 0;
+    while bytes.get(end) == Some(&b'a') {
+        end += 1;
+    }
+    match bytes.get(end) {
+        Some(b'(' | b'{') => {
+            let mut depth = 0;
+            for (index, byte) in bytes.iter().enumerate().skip(end) {
+                match byte {
+                    b'(' | b'{' => depth += 1,
+                    b')' | b'}' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return Ok(signature.split_at(index + 1));
+                }
+            }
+            Err(invalid(format!("unbalanced signature '{signature}'")))
+        }
+        Some(_) => Ok(signature.split_at(end + 1)),
+        None => Err(invalid(format!("incomplete signature '{signature}'"))),
+    }
+}
+
+fn alignment(signature: &str) -> usize {
+    match signature.as_bytes().first() {
+        Some(b'n' | b'q') => 2,
+        Some(b'b' | b'i' | b'u' | b'h' | b's' | b'o' | b'a') => 4,
+        Some(b'x' | b't' | b'd' | b'(' | b'{') => 8,
+        _ => 1,
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// A connection to the session bus. Any thread can send; one reads.
+pub(crate) struct Connection {
+    writer: Mutex<UnixStream>,
+    reader: Mutex<UnixStream>,
+    serial: AtomicU32,
+}
+
+impl Connection {
+    /// Connects and authenticates to the bus named by
+    /// `$DBUS_SESSION_BUS_ADDRESS`, and says hello to it.
+    pub(crate) fn session() -> io::Result<Connection> {
+        let address = env::var("DBUS_SESSION_BUS_ADDRESS")
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "no D-Bus session bus"))?;
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no usable D-Bus address");
+        for address in address.split(';') {
+            match connect(address) {
+                Ok(stream) => return Connection::authenticate(stream),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn authenticate(mut stream: UnixStream) -> io::Result<Connection> {
+        let uid = std::fs::metadata("/proc/self")?.uid();
+        let hex: String = uid
+            .to_string()
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        stream.write_all(format!("\0AUTH EXTERNAL {hex}\r\n").as_bytes())?;
+        // Read a byte at a time so nothing past the line is taken.
+        let mut line = Vec::new();
+        while !line.ends_with(b"\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte)?;
+            line.push(byte[0]);
+        }
+        if !line.starts_with(b"OK ") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the session bus refused EXTERNAL authentication",
+            ));
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+
+        let connection = Connection {
+            writer: Mutex::new(stream.try_clone()?),
+            reader: Mutex::new(stream),
+            serial: AtomicU32::new(1),
+        };
+        connection.call(Message::call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+        ))?;
+        Ok(connection)
+    }
+
+    /// Sends `message` with a serial of its own, returning the serial.
+    pub(crate) fn send(&self, mut message: Message) -> io::Result<u32> {
+        let mut writer = self.writer.locked();
+        message.serial = self.serial.fetch_add(1, Ordering::Relaxed);
+        writer.write_all(&message.encode())?;
+        Ok(message.serial)
+    }
+
+    /// Sends a method call and waits for its answer, dropping anything that
+    /// comes in meanwhile. Only for use before messages are read elsewhere.
+    pub(crate) fn call(&self, message: Message) -> io::Result<Message> {
+        let serial = self.send(message)?;
+        loop {
+            let reply = self.receive()?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            if reply.kind == Kind::Error {
+                let text = reply.body.first().and_then(Arg::as_str).unwrap_or_default();
+                let name = reply.error_name.unwrap_or_default();
+                return Err(io::Error::other(format!("{name}: {text}")));
+            }
+            return Ok(reply);
+        }
+    }
+
+    /// Waits for the next message.
+    pub(crate) fn receive(&self) -> io::Result<Message> {
+        let mut reader = self.reader.locked();
+        let mut fixed = [0; 16];
+        reader.read_exact(&mut fixed)?;
+        let number = |at: usize| {
+            let bytes: [u8; 4] = fixed[at..at + 4].try_into().expect("four bytes");
+            match fixed[0] {
+                b'B' => u32::from_be_bytes(bytes),
+                _ => u32::from_le_bytes(bytes),
+            }
+        };
+        let body_len = number(4) as usize;
+        let fields_len = number(12) as usize;
+        let header_len = (16 + fields_len).next_multiple_of(8);
+        if header_len + body_len > MAX_MESSAGE {
+            return Err(invalid("message too big"));
+        }
+        let mut header = fixed.to_vec();
+        header.resize(header_len, 0);
+        reader.read_exact(&mut header[16..])?;
+        let mut body = vec![0; body_len];
+        reader.read_exact(&mut body)?;
+        Message::decode(&header, &body)
+    }
+
+    /// Makes a blocked [`Connection::receive`] return with an error.
+    pub(crate) fn shutdown(&self) {
+        let _ = self.writer.locked().shutdown(Shutdown::Both);
+    }
+}
+
+fn connect(address: &str) -> io::Result<UnixStream> {
+    let options = address
+        .strip_prefix("unix:")
+        .ok_or_else(|| invalid(format!("unsupported D-Bus address '{address}'")))?;
+    for option in options.split(',') {
+        match option.split_once('=') {
+            Some(("path", path)) => return UnixStream::connect(unescape(path)),
+            #[cfg(target_os = "linux")]
+            Some(("abstract", name)) => {
+                use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+                let address = SocketAddr::from_abstract_name(unescape(name).as_bytes())?;
+                return UnixStream::connect_addr(&address);
+            }
+            _ => {}
+        }
+    }
+    Err(invalid(format!("unsupported D-Bus address '{address}'")))
+}
+
+/// Undoes the `%xx` escapes of a D-Bus address value.
+fn unescape(value: &str) -> String {
+    let mut out = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        let escaped = (byte == b'%')
+            .then(|| {
+                let hex = [bytes.next()?, bytes.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()
+            })
+            .flatten();
+        out.push(escaped.unwrap_or(byte));
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,493 @@
+//! The MPRIS D-Bus interface, so desktop media keys, sound menus and
+//! `playerctl` can drive a player.
+
+mod dbus;
+
+use crate::{
+    gain::{db_to_linear, linear_to_db},
+    player::{AudioPlayer, MAX_SPEED, MIN_SPEED, MIN_VOLUME_DB},
+    queue::RepeatMode,
+    PlayerEvent,
+};
+use dbus::{Arg, Connection, Kind, Message, NO_REPLY_EXPECTED};
+use std::{
+    io, process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.fullyrustaudio";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT: &str = "org.mpris.MediaPlayer2";
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+/// How often properties are compared for changes made outside MPRIS, such
+/// as a volume change from the terminal UI.
+const POLL: Duration = Duration::from_millis(250);
+
+/// The track id MPRIS reserves for having no track.
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="xml" type="s" direction="out"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get"><arg name="interface" type="s" direction="in"/><arg name="property" type="s" direction="in"/><arg name="value" type="v" direction="out"/></method>
+    <method name="GetAll"><arg name="interface" type="s" direction="in"/><arg name="properties" type="a{sv}" direction="out"/></method>
+    <method name="Set"><arg name="interface" type="s" direction="in"/><arg name="property" type="s" direction="in"/><arg name="value" type="v" direction="in"/></method>
+    <signal name="PropertiesChanged"><arg name="interface" type="s"/><arg name="changed" type="a{sv}"/><arg name="invalidated" type="as"/></signal>
+  </interface>
+  <interface name="org.mpris.MediaPlayer2">
+    <method name="Raise"/>
+    <method name="Quit"/>
+    <property name="CanQuit" type="b" access="read"/>
+    <property name="CanRaise" type="b" access="read"/>
+    <property name="HasTrackList" type="b" access="read"/>
+    <property name="Identity" type="s" access="read"/>
+    <property name="SupportedUriSchemes" type="as" access="read"/>
+    <property name="SupportedMimeTypes" type="as" access="read"/>
+  </interface>
+  <interface name="org.mpris.MediaPlayer2.Player">
+    <method name="Next"/>
+    <method name="Previous"/>
+    <method name="Pause"/>
+    <method name="PlayPause"/>
+    <method name="Stop"/>
+    <method name="Play"/>
+    <method name="Seek"><arg name="Offset" type="x" direction="in"/></method>
+    <method name="SetPosition"><arg name="TrackId" type="o" direction="in"/><arg name="Position" type="x" direction="in"/></method>
+    <method name="OpenUri"><arg name="Uri" type="s" direction="in"/></method>
+    <signal name="Seeked"><arg name="Position" type="x"/></signal>
+    <property name="PlaybackStatus" type="s" access="read"/>
+    <property name="LoopStatus" type="s" access="readwrite"/>
+    <property name="Rate" type="d" access="readwrite"/>
+    <property name="Shuffle" type="b" access="readwrite"/>
+    <property name="Metadata" type="a{sv}" access="read"/>
+    <property name="Volume" type="d" access="readwrite"/>
+    <property name="Position" type="x" access="read"/>
+    <property name="MinimumRate" type="d" access="read"/>
+    <property name="MaximumRate" type="d" access="read"/>
+    <property name="CanGoNext" type="b" access="read"/>
+    <property name="CanGoPrevious" type="b" access="read"/>
+    <property name="CanPlay" type="b" access="read"/>
+    <property name="CanPause" type="b" access="read"/>
+    <property name="CanSeek" type="b" access="read"/>
+    <property name="CanControl" type="b" access="read"/>
+  </interface>
+</node>
+"#;
+
+/// Publishes a player on the D-Bus session bus as
+/// `org.mpris.MediaPlayer2.fullyrustaudio`, with a `.instance<pid>` suffix
+/// when another player has that name already.
+///
+/// Calls through MPRIS map onto the player's own methods. `Seeked` follows
+/// [`PlayerEvent::Seeked`], and `PropertiesChanged` announces every change
+/// to the status, metadata, volume and modes, however it came about.
+/// Dropping the server takes the player off the bus.
+pub struct MprisServer {
+    connection: Arc<Connection>,
+    closed: Arc<AtomicBool>,
+}
+
+impl MprisServer {
+    pub fn start(player: &Arc<AudioPlayer>) -> io::Result<MprisServer> {
+        let connection = Arc::new(Connection::session()?);
+        let name = match request_name(&connection, BUS_NAME)? {
+            true => BUS_NAME.to_string(),
+            false => format!("{BUS_NAME}.instance{}", process::id()),
+        };
+        if name != BUS_NAME && !request_name(&connection, &name)? {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{name} is taken on the session bus"),
+            ));
+        }
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let weak = Arc::downgrade(player);
+        let calls = connection.clone();
+        thread::spawn(move || {
+            while let Ok(message) = calls.receive() {
+                if message.kind != Kind::MethodCall {
+                    continue;
+                }
+                let Some(player) = weak.upgrade() else {
+                    return;
+                };
+                let reply = handle(&player, &message);
+                if message.flags & NO_REPLY_EXPECTED == 0 && calls.send(reply).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let events = player.subscribe();
+        let weak = Arc::downgrade(player);
+        let (signals, stop) = (connection.clone(), closed.clone());
+        thread::spawn(move || {
+            let mut announced = weak.upgrade().map(|player| watched(&player));
+            loop {
+                let event = match events.recv_timeout(POLL) {
+                    Ok(event) => Some(event),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+                let Some(player) = weak.upgrade().filter(|_| !stop.load(Ordering::Acquire)) else {
+                    return;
+                };
+                if let Some(PlayerEvent::Seeked(position)) = event {
+                    let signal = Message::signal(OBJECT_PATH, PLAYER, "Seeked")
+                        .with(Arg::I64(micros(position)));
+                    let _ = signals.send(signal);
+                }
+                let now = watched(&player);
+                if let Some(before) = &announced {
+                    announce(&signals, before, &now);
+                }
+                announced = Some(now);
+            }
+        });
+
+        Ok(MprisServer { connection, closed })
+    }
+}
+
+impl Drop for MprisServer {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+        self.connection.shutdown();
+    }
+}
+
+fn request_name(connection: &Connection, name: &str) -> io::Result<bool> {
+    // DO_NOT_QUEUE: fail straight away rather than wait for the name.
+    let call = Message::call(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        "RequestName",
+    )
+    .with(Arg::Str(name.to_string()))
+    .with(Arg::U32(4));
+    let reply = connection.call(call)?;
+    // 1 is the primary owner, 4 already the owner.
+    Ok(matches!(
+        reply.body.first().and_then(Arg::as_i64),
+        Some(1 | 4)
+    ))
+}
+
+/// Sends `PropertiesChanged` for whichever properties differ.
+fn announce(connection: &Connection, before: &[(&'static str, Arg)], now: &[(&'static str, Arg)]) {
+    let changed = now
+        .iter()
+        .zip(before)
+        .filter(|((_, new), (_, old))| new != old)
+        .map(|((name, value), _)| (*name, value.clone()))
+        .collect::<Vec<_>>();
+    if changed.is_empty() {
+        return;
+    }
+    let signal = Message::signal(OBJECT_PATH, PROPERTIES, "PropertiesChanged")
+        .with(Arg::Str(PLAYER.to_string()))
+        .with(Arg::dict(changed))
+        .with(Arg::strings(Vec::<String>::new()));
+    let _ = connection.send(signal);
+}
+
+/// The player properties that announce their changes; `Position` is left
+/// to `Seeked`, as the specification has it.
+fn watched(player: &AudioPlayer) -> Vec<(&'static str, Arg)> {
+    let has_track = player.current_track().is_some();
+    vec![
+        (
+            "PlaybackStatus",
+            Arg::Str(playback_status(player).to_string()),
+        ),
+        (
+            "LoopStatus",
+            Arg::Str(loop_status(player.repeat()).to_string()),
+        ),
+        ("Rate", Arg::F64(player.speed().into())),
+        ("Shuffle", Arg::Bool(player.is_shuffled())),
+        ("Metadata", metadata(player)),
+        ("Volume", Arg::F64(volume(player))),
+        ("CanGoNext", Arg::Bool(has_track)),
+        ("CanGoPrevious", Arg::Bool(has_track)),
+        ("CanPlay", Arg::Bool(has_track)),
+        ("CanPause", Arg::Bool(has_track)),
+        ("CanSeek", Arg::Bool(has_track)),
+    ]
+}
+
+fn player_properties(player: &AudioPlayer) -> Vec<(&'static str, Arg)> {
+    let mut properties = watched(player);
+    properties.extend([
+        ("Position", Arg::I64(micros(position(player)))),
+        ("MinimumRate", Arg::F64(MIN_SPEED.into())),
+        ("MaximumRate", Arg::F64(MAX_SPEED.into())),
+        ("CanControl", Arg::Bool(true)),
+    ]);
+    properties
+}
+
+fn root_properties() -> Vec<(&'static str, Arg)> {
+    vec![
+        ("CanQuit", Arg::Bool(false)),
+        ("CanRaise", Arg::Bool(false)),
+        ("HasTrackList", Arg::Bool(false)),
+        ("Identity", Arg::Str("FullyRustAudio".to_string())),
+        ("SupportedUriSchemes", Arg::strings(["file", "http"])),
+        (
+            "SupportedMimeTypes",
+            Arg::strings(["audio/mpeg", "audio/flac", "audio/ogg", "audio/wav"]),
+        ),
+    ]
+}
+
+fn playback_status(player: &AudioPlayer) -> &'static str {
+    if player.is_playing() {
+        "Playing"
+    } else if player.is_stopped() {
+        "Stopped"
+    } else {
+        "Paused"
+    }
+}
+
+fn loop_status(repeat: RepeatMode) -> &'static str {
+    match repeat {
+        RepeatMode::Off => "None",
+        RepeatMode::One => "Track",
+        RepeatMode::All => "Playlist",
+    }
+}
+
+/// The volume as MPRIS has it, a linear factor with 1.0 at 0 dB.
+fn volume(player: &AudioPlayer) -> f64 {
+    match player.is_muted() {
+        true => 0.0,
+        false => db_to_linear(player.volume_db()).into(),
+    }
+}
+
+/// Where playback is, as the metadata's track has it.
+fn position(player: &AudioPlayer) -> Duration {
+    player
+        .now_playing()
+        .map_or(Duration::ZERO, |now| now.position)
+}
+
+fn track_path(id: Option<u64>) -> String {
+    match id {
+        Some(id) => format!("/org/fullyrustaudio/track/{id}"),
+        None => NO_TRACK.to_string(),
+    }
+}
+
+fn metadata(player: &AudioPlayer) -> Arg {
+    let now = player.now_playing();
+    let id = track_path(now.as_ref().map(|now| now.item.id));
+    let mut entries = vec![("mpris:trackid", Arg::Path(id))];
+    let Some(now) = now else {
+        return Arg::dict(entries);
+    };
+    if let Some(duration) = now.duration {
+        entries.push(("mpris:length", Arg::I64(micros(duration))));
+    }
+    if let Some(title) = now.title() {
+        entries.push(("xesam:title", Arg::Str(title)));
+    }
+    if let Some(artist) = now.artist() {
+        entries.push(("xesam:artist", Arg::strings([artist])));
+    }
+    if let Some(album) = now.album() {
+        entries.push(("xesam:album", Arg::Str(album.to_string())));
+    }
+    if let Some(number) = now.metadata.track_number {
+        entries.push(("xesam:trackNumber", Arg::I32(number as i32)));
+    }
+    let path = &now.item.path;
+    let url = match path.to_str() {
+        Some(url) if url.contains("://") => url.to_string(),
+        _ => format!("file://{}", path.display()),
+    };
+    entries.push(("xesam:url", Arg::Str(url)));
+    Arg::dict(entries)
+}
+
+fn micros(duration: Duration) -> i64 {
+    duration.as_micros().try_into().unwrap_or(i64::MAX)
+}
+
+fn from_micros(micros: i64) -> Duration {
+    Duration::from_micros(micros.max(0) as u64)
+}
+
+/// Answers one method call.
+fn handle(player: &AudioPlayer, call: &Message) -> Message {
+    let interface = call.interface.as_deref().unwrap_or_default();
+    let member = call.member.as_deref().unwrap_or_default();
+    let arg = |index: usize| call.body.get(index);
+    let result = match (interface, member) {
+        ("org.freedesktop.DBus.Introspectable", "Introspect") => {
+            return call.reply().with(Arg::Str(INTROSPECTION.to_string()));
+        }
+        ("org.freedesktop.DBus.Peer", "Ping") => Ok(()),
+        (PROPERTIES, "Get") => {
+            let (Some(interface), Some(name)) =
+                (arg(0).and_then(Arg::as_str), arg(1).and_then(Arg::as_str))
+            else {
+                return invalid_args(call);
+            };
+            return match properties(player, interface)
+                .into_iter()
+                .find(|(property, _)| *property == name)
+            {
+                Some((_, value)) => call.reply().with(Arg::Variant(Box::new(value))),
+                None => call.error(
+                    "org.freedesktop.DBus.Error.UnknownProperty",
+                    &format!("no property {name} on {interface}"),
+                ),
+            };
+        }
+        (PROPERTIES, "GetAll") => {
+            let Some(interface) = arg(0).and_then(Arg::as_str) else {
+                return invalid_args(call);
+            };
+            return call.reply().with(Arg::dict(properties(player, interface)));
+        }
+        (PROPERTIES, "Set") => {
+            let (Some(name), Some(value)) = (arg(1).and_then(Arg::as_str), arg(2)) else {
+                return invalid_args(call);
+            };
+            match set_property(player, name, value) {
+                Some(()) => Ok(()),
+                None => {
+                    return call.error(
+                        "org.freedesktop.DBus.Error.PropertyReadOnly",
+                        &format!("can't set {name} to that"),
+                    )
+                }
+            }
+        }
+        (ROOT, "Raise" | "Quit") => Ok(()),
+        (PLAYER, "Next") => player.next().map(drop),
+        (PLAYER, "Previous") => player.previous().map(drop),
+        (PLAYER, "Pause") => {
+            player.pause();
+            Ok(())
+        }
+        (PLAYER, "PlayPause") if player.is_playing() => {
+            player.pause();
+            Ok(())
+        }
+        (PLAYER, "Play" | "PlayPause") => player.play(),
+        (PLAYER, "Stop") => {
+            player.stop();
+            Ok(())
+        }
+        (PLAYER, "Seek") => {
+            let Some(offset) = arg(0).and_then(Arg::as_i64) else {
+                return invalid_args(call);
+            };
+            let now = player.now_playing();
+            let position = now.as_ref().map_or(Duration::ZERO, |now| now.position);
+            let position = micros(position).saturating_add(offset);
+            match now.and_then(|now| now.duration) {
+                Some(duration) if position > micros(duration) => player.next().map(drop),
+                _ => player.seek(from_micros(position)),
+            }
+        }
+        (PLAYER, "SetPosition") => {
+            let (Some(track), Some(position)) =
+                (arg(0).and_then(Arg::as_str), arg(1).and_then(Arg::as_i64))
+            else {
+                return invalid_args(call);
+            };
+            // Stale track ids and positions out of range are ignored.
+            let now = player.now_playing();
+            let fits = now
+                .as_ref()
+                .and_then(|now| now.duration)
+                .is_none_or(|duration| position <= micros(duration));
+            let current = track_path(now.map(|now| now.item.id));
+            match track == current && position >= 0 && fits {
+                true => player.seek(from_micros(position)),
+                false => Ok(()),
+            }
+        }
+        (PLAYER, "OpenUri") => {
+            let Some(uri) = arg(0).and_then(Arg::as_str) else {
+                return invalid_args(call);
+            };
+            let path = uri.strip_prefix("file://").unwrap_or(uri);
+            let at = player.queue().len();
+            player
+                .insert_at(at, path)
+                .and_then(|id| player.play_item(id))
+                .and_then(|_| player.play())
+        }
+        _ => {
+            return call.error(
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                &format!("no method {member} on {interface}"),
+            )
+        }
+    };
+    match result {
+        Ok(()) => call.reply(),
+        Err(err) => call.error("org.freedesktop.DBus.Error.Failed", &err.to_string()),
+    }
+}
+
+fn properties(player: &AudioPlayer, interface: &str) -> Vec<(&'static str, Arg)> {
+    match interface {
+        ROOT => root_properties(),
+        PLAYER => player_properties(player),
+        _ => Vec::new(),
+    }
+}
+
+/// Sets one of the writable player properties, or returns `None` for a
+/// property that isn't one, or a value it can't take.
+fn set_property(player: &AudioPlayer, name: &str, value: &Arg) -> Option<()> {
+    match name {
+        "LoopStatus" => {
+            let repeat = match value.as_str()? {
+                "None" => RepeatMode::Off,
+                "Track" => RepeatMode::One,
+                "Playlist" => RepeatMode::All,
+                _ => return None,
+            };
+            player.set_repeat(repeat);
+        }
+        "Rate" => player.set_speed(value.as_f64()? as f32),
+        "Shuffle" => player.set_shuffle(value.as_bool()?),
+        "Volume" => {
+            let volume = value.as_f64()?.max(0.0) as f32;
+            player.set_volume_db(match volume > 0.0 {
+                true => linear_to_db(volume),
+                false => MIN_VOLUME_DB,
+            });
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+fn invalid_args(call: &Message) -> Message {
+    call.error(
+        "org.freedesktop.DBus.Error.InvalidArgs",
+        "wrong arguments for the method",
+    )
+}
@@ -0,0 +1,56 @@
+use crate::{
+    metadata::{CoverArt, TrackMetadata},
+    queue::QueueItem,
+};
+use std::{path::Path, sync::Arc, time::Duration};
+
+/// What is playing, all in one snapshot; see [`AudioPlayer::now_playing`].
+/// The control socket, MPRIS and the media controls show the track from
+/// this, so they agree on it.
+///
+/// [`AudioPlayer::now_playing`]: crate::AudioPlayer::now_playing
+#[derive(Debug, Clone, PartialEq)]
+pub struct NowPlaying {
+    /// The queue entry playing.
+    pub item: QueueItem,
+    /// Its tags and cover art, as [`AudioPlayer::metadata`] has them. Shared
+    /// with the player, which reads them once per track.
+    ///
+    /// [`AudioPlayer::metadata`]: crate::AudioPlayer::metadata
+    pub metadata: Arc<TrackMetadata>,
+    /// A live stream's now-playing text, if it sends one.
+    pub stream_title: Option<String>,
+    pub duration: Option<Duration>,
+    /// Where playback is, never past `duration`.
+    pub position: Duration,
+    pub eq_enabled: bool,
+    pub volume_db: f32,
+    pub muted: bool,
+}
+
+impl NowPlaying {
+    /// The title to show: a live stream's now-playing text, else the tagged
+    /// title, else the file name.
+    pub fn title(&self) -> Option<String> {
+        self.stream_title
+            .clone()
+            .or_else(|| self.metadata.title.clone())
+            .or_else(|| {
+                Path::new(&self.item.path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            })
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.metadata.artist.as_deref()
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.metadata.album.as_deref()
+    }
+
+    pub fn cover(&self) -> Option<&CoverArt> {
+        self.metadata.cover.as_ref()
+    }
+}
@@ -0,0 +1,1140 @@
+use crate::{
+    backend::{Backend, NULL_DEVICE},
+    dither::{Dither, Ditherer},
+    error::PlayerError,
+    events::{PlayerEvent, Signal},
+    lock::Lock,
+    suspend::{SuspendWatch, WallClock},
+};
+use rodio::{
+    cpal::{
+        self,
+        traits::{DeviceTrait, HostTrait, StreamTrait},
+        BufferSize, BuildStreamError, Device, FromSample, Host, SampleFormat, SizedSample, Stream,
+        SupportedBufferSize, SupportedStreamConfigRange,
+    },
+    dynamic_mixer::{self, DynamicMixer},
+    Source,
+};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the output thread looks for a new default device.
+const DEVICE_POLL: Duration = Duration::from_millis(500);
+
+/// How long the device may stop pulling samples before it counts as gone.
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Samples moved per lock of the shared source; bounds what a device swap drops.
+const RELAY_BATCH: usize = 512;
+
+/// How often [`Backend::Null`] plays a buffer where none is asked for.
+const NULL_PERIOD: Duration = Duration::from_millis(10);
+
+/// How long a buffer lasts with [`Latency::Low`] and [`Latency::Safe`].
+const LOW_LATENCY: Duration = Duration::from_millis(5);
+const SAFE_LATENCY: Duration = Duration::from_millis(50);
+
+type SharedSource = Arc<Mutex<Box<dyn Source<Item = f32> + Send>>>;
+
+pub(crate) type Listeners = Arc<Mutex<Vec<Sender<Signal>>>>;
+
+/// How much the output buffers ahead, trading delay before a change is
+/// heard against the risk of dropouts on a busy system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Latency {
+    /// Buffers of about 5 ms.
+    Low,
+    /// Whatever the driver picks.
+    #[default]
+    Default,
+    /// Buffers of about 50 ms.
+    Safe,
+}
+
+impl Latency {
+    pub const ALL: [Latency; 3] = [Latency::Low, Latency::Default, Latency::Safe];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Latency::Low => "low",
+            Latency::Default => "default",
+            Latency::Safe => "safe",
+        }
+    }
+
+    /// Frames per buffer at `sample_rate`, or `None` to leave it to the driver.
+    pub fn buffer_frames(self, sample_rate: u32) -> Option<u32> {
+        let length = match self {
+            Latency::Low => LOW_LATENCY,
+            Latency::Default => return None,
+            Latency::Safe => SAFE_LATENCY,
+        };
+        Some((length.as_secs_f64() * sample_rate as f64).round() as u32)
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Latency {
+    type Err = String;
+
+    /// Looks a latency up by name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Latency::ALL
+            .into_iter()
+            .find(|latency| latency.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = Latency::ALL.map(Latency::name).join(", ");
+                format!("unknown latency '{s}', expected one of {names}")
+            })
+    }
+}
+
+/// A sample format to ask the output device for, by its usual name, as
+/// [`OutputConfig::with_format`] takes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    F32,
+    I16,
+    /// 24-bit samples in the top of 32-bit ones, as most 24-bit DACs take
+    /// them.
+    I24,
+    I32,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 4] = [
+        OutputFormat::F32,
+        OutputFormat::I16,
+        OutputFormat::I24,
+        OutputFormat::I32,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::F32 => "f32",
+            OutputFormat::I16 => "i16",
+            OutputFormat::I24 => "i24",
+            OutputFormat::I32 => "i32",
+        }
+    }
+
+    /// The format the samples go to the device in.
+    pub fn sample_format(self) -> SampleFormat {
+        match self {
+            OutputFormat::F32 => SampleFormat::F32,
+            OutputFormat::I16 => SampleFormat::I16,
+            OutputFormat::I24 | OutputFormat::I32 => SampleFormat::I32,
+        }
+    }
+
+    /// Bits of each sample that carry the signal.
+    pub fn bits_per_sample(self) -> u16 {
+        match self {
+            OutputFormat::F32 | OutputFormat::I32 => 32,
+            OutputFormat::I16 => 16,
+            OutputFormat::I24 => 24,
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    /// Looks a format up by name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OutputFormat::ALL
+            .into_iter()
+            .find(|format| format.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = OutputFormat::ALL.map(OutputFormat::name).join(", ");
+                format!("unknown sample format '{s}', expected one of {names}")
+            })
+    }
+}
+
+/// What an [`AudioEngine`] asks of its output device. Each setting left
+/// out is the device's own; one the device can't do falls back to that,
+/// with a [`PlayerEvent::Warning`] saying so.
+///
+/// [`AudioEngine`]: crate::AudioEngine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputConfig {
+    /// Frames per buffer, over what `latency` asks for.
+    pub buffer_frames: Option<u32>,
+    pub latency: Latency,
+    pub sample_rate: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+    /// How many bits of an integer `sample_format`, from the top, carry the
+    /// signal, as for a 24-bit DAC taking 32-bit samples; those below are
+    /// left zero. `None` uses them all.
+    pub bits_per_sample: Option<u16>,
+    /// The rate of what is about to play, taken over the device's own where
+    /// the device goes at it in the format asked for, so it isn't
+    /// resampled. `sample_rate` comes first.
+    pub source_rate: Option<u32>,
+    /// What is added before rounding to an integer format of 24 bits or
+    /// fewer; float and wider formats are left alone.
+    pub dither: Dither,
+}
+
+impl OutputConfig {
+    /// The config asking for `format`.
+    pub fn with_format(self, format: OutputFormat) -> Self {
+        OutputConfig {
+            sample_format: Some(format.sample_format()),
+            bits_per_sample: Some(format.bits_per_sample()),
+            ..self
+        }
+    }
+}
+
+/// The format an output stream was opened with, as agreed with the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamConfig {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub sample_format: SampleFormat,
+    /// Bits of each sample that carry the signal, fewer than those of
+    /// `sample_format` for 24 bits in 32.
+    pub bits_per_sample: u16,
+    /// Frames per buffer, or `None` where the driver picks.
+    pub buffer_frames: Option<u32>,
+    /// [`Dither::Off`] unless the format narrows the samples.
+    pub dither: Dither,
+}
+
+impl StreamConfig {
+    /// How long one buffer lasts, where that is known.
+    pub fn buffer_duration(&self) -> Option<Duration> {
+        self.buffer_frames
+            .map(|frames| Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64))
+    }
+
+    fn cpal(&self) -> cpal::StreamConfig {
+        cpal::StreamConfig {
+            channels: self.channels,
+            sample_rate: cpal::SampleRate(self.sample_rate),
+            buffer_size: self
+                .buffer_frames
+                .map_or(BufferSize::Default, BufferSize::Fixed),
+        }
+    }
+}
+
+/// Keeps the sink's output playing on whichever device is current.
+///
+/// The output stream can't leave the thread it was opened on, so it lives
+/// on a thread of its own. That thread reopens the stream when the default
+/// device changes or the current one stops pulling samples, and moves the
+/// sink's output over with at most one batch lost. A device asked for by
+/// name takes the default's place while it's there.
+pub(crate) struct Output {
+    _commands: Sender<()>,
+    device: Arc<Mutex<Option<String>>>,
+    fallback: Arc<Mutex<Option<String>>>,
+    stream: Arc<Mutex<Option<Opened>>>,
+    /// Told about device changes; those that have gone away are dropped.
+    listeners: Listeners,
+    timing: Arc<OutputTiming>,
+}
+
+/// When what the device is handed will be heard, as of its last callback,
+/// for lining audio up with the wall clock.
+pub(crate) struct OutputTiming {
+    epoch: Instant,
+    /// Samples of the source handed to the device so far, across streams.
+    delivered: AtomicU64,
+    /// Odd while the callback writes the two below: `delivered` as the
+    /// callback started, and when the first of those samples is heard, in
+    /// nanoseconds from `epoch`.
+    sequence: AtomicU64,
+    anchor_samples: AtomicU64,
+    anchor_ns: AtomicU64,
+}
+
+impl OutputTiming {
+    fn new() -> Self {
+        OutputTiming {
+            epoch: Instant::now(),
+            delivered: AtomicU64::new(0),
+            sequence: AtomicU64::new(0),
+            anchor_samples: AtomicU64::new(0),
+            anchor_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Notes that the next sample is heard after `latency`.
+    fn mark(&self, latency: Duration) {
+        let heard = (Instant::now() + latency).duration_since(self.epoch);
+        self.sequence.fetch_add(1, Ordering::AcqRel);
+        self.anchor_samples
+            .store(self.delivered.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.anchor_ns
+            .store(heard.as_nanos() as u64, Ordering::Relaxed);
+        self.sequence.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// How many samples had been handed to the device, and when the next is
+    /// heard, as of its last callback; `None` before the first.
+    pub(crate) fn anchor(&self) -> Option<(u64, Instant)> {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if sequence % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let samples = self.anchor_samples.load(Ordering::Relaxed);
+            let ns = self.anchor_ns.load(Ordering::Relaxed);
+            if self.sequence.load(Ordering::Acquire) == sequence {
+                return (sequence > 0).then(|| (samples, self.epoch + Duration::from_nanos(ns)));
+            }
+        }
+    }
+}
+
+/// What the current stream was opened with, and where that falls short of
+/// the [`OutputConfig`].
+struct Opened {
+    config: StreamConfig,
+    warnings: Vec<String>,
+}
+
+impl Output {
+    /// Plays `source` through `backend`, on the device named `preferred`
+    /// or else the default one, as near to `config` as each device goes.
+    pub(crate) fn open(
+        source: impl Source<Item = f32> + Send + 'static,
+        backend: Backend,
+        preferred: Option<String>,
+        config: OutputConfig,
+    ) -> Result<Self, PlayerError> {
+        Self::spawn(source, backend, preferred, config, Arc::default(), false)
+    }
+
+    /// Plays `source` through `backend` on the device named `device` and no
+    /// other: once it is gone, or stops pulling samples and won't reopen,
+    /// the stream closes and `listeners` are sent
+    /// [`PlayerEvent::SecondaryOutputLost`].
+    pub(crate) fn open_only(
+        source: impl Source<Item = f32> + Send + 'static,
+        backend: Backend,
+        device: String,
+        config: OutputConfig,
+        listeners: Listeners,
+    ) -> Result<Self, PlayerError> {
+        Self::spawn(source, backend, Some(device), config, listeners, true)
+    }
+
+    fn spawn(
+        source: impl Source<Item = f32> + Send + 'static,
+        backend: Backend,
+        preferred: Option<String>,
+        config: OutputConfig,
+        listeners: Listeners,
+        only: bool,
+    ) -> Result<Self, PlayerError> {
+        let source: SharedSource = Arc::new(Mutex::new(Box::new(source)));
+        let device = Arc::new(Mutex::new(None));
+        let fallback = Arc::new(Mutex::new(None));
+        let opened_with = Arc::new(Mutex::new(None));
+        let (commands, receiver) = mpsc::channel();
+        let (opened, result) = mpsc::channel();
+        let timing = Arc::new(OutputTiming::new());
+
+        let output = Output {
+            _commands: commands,
+            device: device.clone(),
+            fallback: fallback.clone(),
+            stream: opened_with.clone(),
+            listeners: listeners.clone(),
+            timing: timing.clone(),
+        };
+        thread::spawn(move || {
+            let generation = Arc::new(AtomicU64::new(0));
+            let pulled = Arc::new(AtomicU64::new(0));
+            let relay = |generation: &Arc<AtomicU64>| {
+                Relay::new(&source, generation, &pulled, timing.clone())
+            };
+
+            if backend == Backend::Null {
+                if let Some(name) = preferred.as_deref().filter(|&name| name != NULL_DEVICE) {
+                    let _ = opened.send(Err(PlayerError::Device(format!(
+                        "no output device named '{name}' on {backend}; available: {NULL_DEVICE}"
+                    ))));
+                    return;
+                }
+                let with = null_config(config);
+                *device.locked() = Some(NULL_DEVICE.to_string());
+                *opened_with.locked() = Some(Opened {
+                    config: with,
+                    warnings: Vec::new(),
+                });
+                let _ = opened.send(Ok(()));
+                let suspends = (!only).then_some(&listeners);
+                play_null(
+                    relay(&generation),
+                    with,
+                    &receiver,
+                    SuspendWatch::new(),
+                    suspends,
+                );
+                return;
+            }
+
+            let host = match backend.host() {
+                Ok(host) => host,
+                Err(err) => {
+                    let _ = opened.send(Err(err));
+                    return;
+                }
+            };
+            if let Some(name) = preferred.as_deref() {
+                if find_device(&host, name).is_none() {
+                    let names = host.output_devices().map_or_else(
+                        |_| Vec::new(),
+                        |devices| devices.filter_map(|device| device.name().ok()).collect(),
+                    );
+                    let names = match names.is_empty() {
+                        true => "none".to_string(),
+                        false => names.join(", "),
+                    };
+                    let _ = opened.send(Err(PlayerError::Device(format!(
+                        "no output device named '{name}' on {backend}; available: {names}"
+                    ))));
+                    return;
+                }
+            }
+
+            let mut stream = match open_stream(
+                &host,
+                preferred.as_deref(),
+                None,
+                only,
+                config,
+                relay(&generation),
+            ) {
+                Ok((stream, name, with)) => {
+                    *device.locked() = Some(name);
+                    *opened_with.locked() = Some(with);
+                    let _ = opened.send(Ok(()));
+                    stream
+                }
+                Err(err) => {
+                    let _ = opened.send(Err(err));
+                    return;
+                }
+            };
+
+            let mut last_pulled = pulled.load(Ordering::Relaxed);
+            let mut last_progress = Instant::now();
+            let mut watch = SuspendWatch::new();
+            loop {
+                match receiver.recv_timeout(DEVICE_POLL) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+                if let Some(suspend) = watch.check(DEVICE_POLL) {
+                    // The stream gets as long to come back as after a
+                    // reopen, then the device is checked for as usual.
+                    last_progress = Instant::now();
+                    if !only {
+                        listeners
+                            .locked()
+                            .retain(|signals| signals.send(Signal::Suspended(suspend)).is_ok());
+                    }
+                }
+
+                let now_pulled = pulled.load(Ordering::Relaxed);
+                if now_pulled != last_pulled {
+                    last_pulled = now_pulled;
+                    last_progress = Instant::now();
+                }
+                let stalled = last_progress.elapsed() >= STALL_TIMEOUT;
+                let current = device.locked().clone();
+                let wanted = match only {
+                    true => preferred
+                        .as_deref()
+                        .and_then(|name| find_device(&host, name))
+                        .and_then(|device| device.name().ok()),
+                    false => wanted_device_name(&host, preferred.as_deref()),
+                };
+                if !stalled && wanted == current {
+                    continue;
+                }
+
+                let fallback = fallback.locked().clone();
+                let reopened = open_stream(
+                    &host,
+                    preferred.as_deref(),
+                    fallback.as_deref(),
+                    only,
+                    config,
+                    relay(&generation),
+                );
+                if only {
+                    let Ok((new_stream, _, with)) = reopened else {
+                        *device.locked() = None;
+                        *opened_with.locked() = None;
+                        let name = current.or(preferred).unwrap_or_default();
+                        listeners.locked().retain(|signals| {
+                            let event = PlayerEvent::SecondaryOutputLost(name.clone());
+                            signals.send(Signal::Event(event)).is_ok()
+                        });
+                        return;
+                    };
+                    drop(std::mem::replace(&mut stream, new_stream));
+                    last_progress = Instant::now();
+                    *opened_with.locked() = Some(with);
+                } else if let Ok((new_stream, name, with)) = reopened {
+                    // Dropping the old stream after the new one is up keeps
+                    // the gap down to the new device's startup time.
+                    drop(std::mem::replace(&mut stream, new_stream));
+                    *device.locked() = Some(name.clone());
+                    last_progress = Instant::now();
+                    // Under the listeners' lock, so one subscribing now hears
+                    // either this stream's warnings or is told them after.
+                    let mut listeners = listeners.locked();
+                    listeners.retain(|signals| {
+                        let event = PlayerEvent::DeviceChanged(name.clone());
+                        with.warnings.iter().all(|warning| {
+                            let event = PlayerEvent::Warning(warning.clone());
+                            signals.send(Signal::Event(event)).is_ok()
+                        }) && signals.send(Signal::Event(event)).is_ok()
+                    });
+                    *opened_with.locked() = Some(with);
+                }
+                // Opening can take long enough to look like a suspend.
+                watch = SuspendWatch::new();
+            }
+        });
+
+        match result.recv() {
+            Ok(Ok(())) => Ok(output),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(PlayerError::Device(
+                "output thread exited before opening a device".into(),
+            )),
+        }
+    }
+
+    /// Sends [`PlayerEvent::DeviceChanged`] to `signals` from now on, with a
+    /// [`PlayerEvent::Warning`] for each way the stream falls short of the
+    /// [`OutputConfig`], starting with the current one.
+    pub(crate) fn subscribe(&self, signals: Sender<Signal>) {
+        let mut listeners = self.listeners.locked();
+        if let Some(opened) = &*self.stream.locked() {
+            for warning in &opened.warnings {
+                let event = PlayerEvent::Warning(warning.clone());
+                let _ = signals.send(Signal::Event(event));
+            }
+        }
+        listeners.push(signals);
+    }
+
+    /// Who is told about device changes, for a second output to tell too.
+    pub(crate) fn listeners(&self) -> Listeners {
+        self.listeners.clone()
+    }
+
+    pub(crate) fn timing(&self) -> Arc<OutputTiming> {
+        self.timing.clone()
+    }
+
+    /// The format of the stream playing right now.
+    pub(crate) fn config(&self) -> Option<StreamConfig> {
+        self.stream.locked().as_ref().map(|opened| opened.config)
+    }
+
+    /// Name of the device currently playing.
+    pub(crate) fn device(&self) -> Option<String> {
+        self.device.locked().clone()
+    }
+
+    pub(crate) fn fallback(&self) -> Option<String> {
+        self.fallback.locked().clone()
+    }
+
+    pub(crate) fn set_fallback(&self, name: Option<String>) {
+        *self.fallback.locked() = name;
+    }
+}
+
+/// Channels and sample rate `backend`'s device named `preferred`, or else
+/// its default one, plays at for `config`, or stereo at 44.1 kHz if there
+/// is no telling. Fails if `backend` isn't available.
+pub(crate) fn device_format(
+    backend: Backend,
+    preferred: Option<&str>,
+    config: OutputConfig,
+) -> Result<(u16, u32), PlayerError> {
+    Ok(negotiated(backend, preferred, config)?
+        .map_or((2, 44100), |config| (config.channels, config.sample_rate)))
+}
+
+/// The format `backend`'s device named `preferred`, or else its default
+/// one, would be opened at for `config`, asked without opening it; `None`
+/// if there is no such device or it can't tell. Fails if `backend` isn't
+/// available.
+pub(crate) fn negotiated(
+    backend: Backend,
+    preferred: Option<&str>,
+    config: OutputConfig,
+) -> Result<Option<StreamConfig>, PlayerError> {
+    if backend == Backend::Null {
+        let others = preferred.is_some_and(|name| name != NULL_DEVICE);
+        return Ok((!others).then(|| null_config(config)));
+    }
+    let host = backend.host()?;
+    Ok(preferred
+        .and_then(|name| find_device(&host, name))
+        .or_else(|| host.default_output_device())
+        .and_then(|device| negotiate(&device, config).ok())
+        .map(|(config, _)| config))
+}
+
+/// What [`Backend::Null`] plays at for `config`: stereo, and otherwise as
+/// asked, at the source's rate where no rate is.
+fn null_config(config: OutputConfig) -> StreamConfig {
+    let sample_rate = config.sample_rate.or(config.source_rate).unwrap_or(44100);
+    let sample_format = config.sample_format.unwrap_or(SampleFormat::F32);
+    let size = sample_format.sample_size() as u16 * 8;
+    let bits_per_sample = config
+        .bits_per_sample
+        .filter(|bits| (1..=size).contains(bits) && !sample_format.is_float())
+        .unwrap_or(size);
+    StreamConfig {
+        channels: 2,
+        sample_rate,
+        sample_format,
+        bits_per_sample,
+        buffer_frames: config
+            .buffer_frames
+            .or_else(|| config.latency.buffer_frames(sample_rate)),
+        dither: match sample_format.is_float() || bits_per_sample > 24 {
+            true => Dither::Off,
+            false => config.dither,
+        },
+    }
+}
+
+/// Plays `relay` into nothing, a buffer at a time, as soon as a device
+/// opened at `config` would take each, until `commands` hangs up. A suspend
+/// of the system `watch` finds is told to `suspends`, and what a device
+/// would have played through it is left unplayed.
+fn play_null(
+    mut relay: Relay,
+    config: StreamConfig,
+    commands: &Receiver<()>,
+    mut watch: SuspendWatch<impl WallClock>,
+    suspends: Option<&Listeners>,
+) {
+    let period = config.buffer_duration().unwrap_or(NULL_PERIOD);
+    let timing = relay.timing();
+    let mut started = watch.checked_at();
+    // Seconds of the relay played so far, whatever its format on the way.
+    let mut played = 0.0;
+    loop {
+        if let Some(suspend) = watch.check(period) {
+            // Nothing would have played through it, so none is caught up.
+            started += suspend.counted;
+            if let Some(listeners) = suspends {
+                listeners
+                    .locked()
+                    .retain(|signals| signals.send(Signal::Suspended(suspend)).is_ok());
+            }
+        }
+        timing.mark(Duration::ZERO);
+        let due = (watch.checked_at() - started + period).as_secs_f64();
+        while played < due {
+            let channels = relay.channels().max(1);
+            let sample_rate = relay.sample_rate().max(1);
+            for _ in 0..channels {
+                relay.next();
+            }
+            played += 1.0 / sample_rate as f64;
+        }
+        match commands.recv_timeout(period) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return,
+        }
+    }
+}
+
+fn find_device(host: &Host, name: &str) -> Option<Device> {
+    host.output_devices()
+        .ok()?
+        .find(|device| device.name().is_ok_and(|device| device == name))
+}
+
+/// The device that should be playing: `preferred` while it's there, else
+/// the default.
+fn wanted_device_name(host: &Host, preferred: Option<&str>) -> Option<String> {
+    preferred
+        .and_then(|name| find_device(host, name))
+        .or_else(|| host.default_output_device())
+        .and_then(|device| device.name().ok())
+}
+
+/// Opens the device named `preferred`, the default device or `fallback`,
+/// the first of them that works, as near to `config` as it goes, and starts
+/// `relay` on it. `only`, no device but `preferred` is tried.
+fn open_stream(
+    host: &Host,
+    preferred: Option<&str>,
+    fallback: Option<&str>,
+    only: bool,
+    config: OutputConfig,
+    relay: Relay,
+) -> Result<(Stream, String, Opened), PlayerError> {
+    let preferred = preferred.and_then(|name| find_device(host, name));
+    let fallback = fallback.and_then(|name| find_device(host, name));
+    let mut last_err = "no output device".to_string();
+    let others = (!only).then(|| host.default_output_device().into_iter().chain(fallback));
+    let devices = preferred.into_iter().chain(others.into_iter().flatten());
+    // The relay moves into the stream that works, so each try gets a mixer
+    // of its own and only the last one carries it.
+    let mut relay = Some(relay);
+    for device in devices {
+        match open_device(&device, config, &mut relay) {
+            Ok((stream, opened)) => {
+                let name = device.name().unwrap_or_default();
+                return Ok((stream, name, opened));
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    Err(PlayerError::Device(last_err))
+}
+
+/// Opens `device` for `config`, or failing that at its defaults, and plays
+/// `relay` on it once it is up.
+fn open_device(
+    device: &Device,
+    config: OutputConfig,
+    relay: &mut Option<Relay>,
+) -> Result<(Stream, Opened), String> {
+    let (wanted, mut warnings) = negotiate(device, config)?;
+    let (stream, config) = match build_stream(device, &wanted, relay) {
+        Ok(stream) => (stream, wanted),
+        Err(err) => {
+            let defaults = OutputConfig {
+                dither: config.dither,
+                ..OutputConfig::default()
+            };
+            let (fallback, _) = negotiate(device, defaults)?;
+            if fallback == wanted {
+                return Err(err.to_string());
+            }
+            warnings.push(format!(
+                "the output device failed to open as asked ({err}); it plays at its own format"
+            ));
+            let stream = build_stream(device, &fallback, relay).map_err(|err| err.to_string())?;
+            (stream, fallback)
+        }
+    };
+    stream.play().map_err(|err| err.to_string())?;
+    Ok((stream, Opened { config, warnings }))
+}
+
+/// The format of `device` nearest to `config`: the device's own channel
+/// count, and its own sample rate and format where `config` has none or
+/// one it can't do, each of those last with a warning. The source's rate
+/// goes before the device's own, without a warning.
+fn negotiate(device: &Device, config: OutputConfig) -> Result<(StreamConfig, Vec<String>), String> {
+    let default = device
+        .default_output_config()
+        .map_err(|err| err.to_string())?;
+    let channels = default.channels();
+    // No list of what the device supports means trying what was asked.
+    let ranges: Vec<SupportedStreamConfigRange> = device
+        .supported_output_configs()
+        .map(|ranges| {
+            ranges
+                .filter(|range| range.channels() == channels)
+                .collect()
+        })
+        .unwrap_or_default();
+    let plays = |format: Option<SampleFormat>, rate: u32| {
+        ranges.is_empty()
+            || ranges.iter().any(|range| {
+                format.is_none_or(|format| range.sample_format() == format)
+                    && plays_rate(range, rate)
+            })
+    };
+    let mut warnings = Vec::new();
+
+    let mut sample_rate = default.sample_rate().0;
+    if let Some(rate) = config.sample_rate {
+        if plays(None, rate) {
+            sample_rate = rate;
+        } else {
+            warnings.push(format!(
+                "the output device can't play at {rate} Hz; it plays at {sample_rate} Hz"
+            ));
+        }
+    } else if let Some(rate) = config.source_rate {
+        if plays(config.sample_format, rate) {
+            sample_rate = rate;
+        }
+    }
+    let mut sample_format = default.sample_format();
+    let mut bits = config.bits_per_sample;
+    if let Some(format) = config.sample_format {
+        if plays(Some(format), sample_rate) {
+            sample_format = format;
+        } else {
+            // The depth was for the format asked for.
+            bits = None;
+            warnings.push(format!(
+                "the output device can't play {format} samples at {sample_rate} Hz; \
+                 it plays {sample_format}"
+            ));
+        }
+    }
+    // The device's own format may not go at a rate asked for.
+    if !plays(Some(sample_format), sample_rate) {
+        if let Some(range) = ranges.iter().find(|range| plays_rate(range, sample_rate)) {
+            sample_format = range.sample_format();
+        }
+    }
+
+    let size = sample_format.sample_size() as u16 * 8;
+    let bits_per_sample = match bits {
+        Some(bits) if (1..=size).contains(&bits) && !sample_format.is_float() => bits,
+        Some(bits) if bits != size => {
+            warnings.push(format!(
+                "the output device plays {sample_format} samples, which don't carry {bits} bits; \
+                 it plays {size}"
+            ));
+            size
+        }
+        _ => size,
+    };
+
+    let supported = ranges
+        .iter()
+        .find(|range| range.sample_format() == sample_format && plays_rate(range, sample_rate))
+        .map_or(
+            default.buffer_size(),
+            SupportedStreamConfigRange::buffer_size,
+        );
+    let buffer_frames = config
+        .buffer_frames
+        .or_else(|| config.latency.buffer_frames(sample_rate))
+        .map(|frames| match *supported {
+            SupportedBufferSize::Range { min, max } if !(min..=max).contains(&frames) => {
+                let near = frames.clamp(min, max);
+                warnings.push(format!(
+                    "the output device can't buffer {frames} frames; it buffers {near}"
+                ));
+                near
+            }
+            _ => frames,
+        });
+
+    let config = StreamConfig {
+        channels,
+        sample_rate,
+        sample_format,
+        bits_per_sample,
+        buffer_frames,
+        dither: match sample_format.is_float() || bits_per_sample > 24 {
+            true => Dither::Off,
+            false => config.dither,
+        },
+    };
+    Ok((config, warnings))
+}
+
+/// Whether `range` goes at `rate`.
+fn plays_rate(range: &SupportedStreamConfigRange, rate: u32) -> bool {
+    (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&rate)
+}
+
+/// Builds a paused stream for `config` on `device`, taking `relay` into it
+/// if that works.
+fn build_stream(
+    device: &Device,
+    config: &StreamConfig,
+    relay: &mut Option<Relay>,
+) -> Result<Stream, BuildStreamError> {
+    // The mixer converts the relay to the device's format.
+    let (mixer, mixed) = dynamic_mixer::mixer(config.channels, config.sample_rate);
+    let build = match config.sample_format {
+        SampleFormat::I8 => build_output::<i8>,
+        SampleFormat::I16 => build_output::<i16>,
+        SampleFormat::I32 => build_output::<i32>,
+        SampleFormat::I64 => build_output::<i64>,
+        SampleFormat::U8 => build_output::<u8>,
+        SampleFormat::U16 => build_output::<u16>,
+        SampleFormat::U32 => build_output::<u32>,
+        SampleFormat::U64 => build_output::<u64>,
+        SampleFormat::F32 => build_output::<f32>,
+        SampleFormat::F64 => build_output::<f64>,
+        _ => return Err(BuildStreamError::StreamConfigNotSupported),
+    };
+    // Fewer bits than the format has are rounded to, dithered or not, so
+    // those below them come out zero.
+    let narrows = config.bits_per_sample < config.sample_format.sample_size() as u16 * 8;
+    let ditherer = (config.dither != Dither::Off || narrows).then(|| {
+        // Whole steps of this come out of the conversion exactly.
+        let scale = (1u64 << (config.bits_per_sample - 1)) as f32;
+        Ditherer::new(config.dither, scale, config.channels, 0)
+    });
+    let timing = relay.as_ref().map(Relay::timing);
+    let stream = build(device, &config.cpal(), mixed, ditherer, timing)?;
+    if let Some(relay) = relay.take() {
+        mixer.add(relay);
+    }
+    Ok(stream)
+}
+
+fn build_output<T>(
+    device: &Device,
+    config: &cpal::StreamConfig,
+    mut mixed: DynamicMixer<f32>,
+    mut ditherer: Option<Ditherer>,
+    timing: Option<RelayTiming>,
+) -> Result<Stream, BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            if let Some(timing) = &timing {
+                let stamp = info.timestamp();
+                let latency = stamp.playback.duration_since(&stamp.callback);
+                timing.mark(latency.unwrap_or_default());
+            }
+            for sample in data {
+                let next = mixed.next();
+                let next = match &mut ditherer {
+                    Some(ditherer) => {
+                        next.map(|next| ditherer.dither(next).min(ditherer.ceiling()))
+                    }
+                    None => next,
+                };
+                *sample = next.map_or(T::EQUILIBRIUM, T::from_sample);
+            }
+        },
+        // A stream that stops pulling samples is reopened anyway, so there
+        // is nothing more to do about its errors.
+        |_| {},
+        None,
+    )
+}
+
+/// Feeds one output stream from the shared source until a newer relay takes over.
+struct Relay {
+    source: SharedSource,
+    generation: u64,
+    current: Arc<AtomicU64>,
+    pulled: Arc<AtomicU64>,
+    timing: Arc<OutputTiming>,
+    buffer: Vec<f32>,
+    index: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Relay {
+    /// Takes over from every earlier relay on the same source.
+    fn new(
+        source: &SharedSource,
+        current: &Arc<AtomicU64>,
+        pulled: &Arc<AtomicU64>,
+        timing: Arc<OutputTiming>,
+    ) -> Self {
+        let mut relay = Relay {
+            source: source.clone(),
+            generation: current.fetch_add(1, Ordering::AcqRel) + 1,
+            current: current.clone(),
+            pulled: pulled.clone(),
+            timing,
+            buffer: Vec::with_capacity(RELAY_BATCH),
+            index: 0,
+            channels: 1,
+            sample_rate: 44100,
+        };
+        relay.refill();
+        relay
+    }
+
+    /// What the stream playing this relay reports its timing through.
+    fn timing(&self) -> RelayTiming {
+        RelayTiming {
+            timing: self.timing.clone(),
+            generation: self.generation,
+            current: self.current.clone(),
+        }
+    }
+
+    /// Loads the next batch. The format always describes the batch ahead,
+    /// so the mixer sees a change before the first sample in it.
+    fn refill(&mut self) {
+        self.buffer.clear();
+        self.index = 0;
+        if self.current.load(Ordering::Acquire) != self.generation {
+            return;
+        }
+        let mut source = self.source.locked();
+        self.channels = source.channels();
+        self.sample_rate = source.sample_rate();
+        // Stop at the end of the current frame so a batch never spans a
+        // format change.
+        let batch = RELAY_BATCH - RELAY_BATCH % self.channels.max(1) as usize;
+        let len = source
+            .current_frame_len()
+            .map_or(batch, |len| len.clamp(1, batch));
+        self.buffer.extend(source.by_ref().take(len));
+        self.pulled.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Iterator for Relay {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = *self.buffer.get(self.index)?;
+        self.index += 1;
+        self.timing.delivered.fetch_add(1, Ordering::Relaxed);
+        if self.index >= self.buffer.len() {
+            self.refill();
+        }
+        Some(sample)
+    }
+}
+
+/// The [`OutputTiming`] a stream marks, for as long as its relay is the
+/// current one.
+struct RelayTiming {
+    timing: Arc<OutputTiming>,
+    generation: u64,
+    current: Arc<AtomicU64>,
+}
+
+impl RelayTiming {
+    fn mark(&self, latency: Duration) {
+        if self.current.load(Ordering::Acquire) == self.generation {
+            self.timing.mark(latency);
+        }
+    }
+}
+
+impl Source for Relay {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.buffer.len() - self.index)
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::JumpingClock;
+    use rodio::source::Zero;
+
+    #[test]
+    fn the_null_backend_plays_at_the_pace_it_would_be_heard() {
+        let delivered = {
+            let output = Output::open(
+                Zero::<f32>::new(2, 48000),
+                Backend::Null,
+                None,
+                OutputConfig {
+                    source_rate: Some(48000),
+                    ..OutputConfig::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(output.device().as_deref(), Some(NULL_DEVICE));
+            assert_eq!(
+                output.config().map(|config| config.sample_rate),
+                Some(48000)
+            );
+            thread::sleep(Duration::from_millis(500));
+            output.timing().delivered.load(Ordering::Relaxed)
+        };
+        let seconds = delivered as f64 / (2.0 * 48000.0);
+        assert!((0.4..0.7).contains(&seconds), "played {seconds} s in 0.5 s");
+    }
+
+    #[test]
+    fn the_null_backend_plays_nothing_through_a_suspend() {
+        let (signals, suspended) = mpsc::channel();
+        let listeners: Listeners = Arc::new(Mutex::new(vec![signals]));
+        let clock = JumpingClock::default();
+        let timing = Arc::new(OutputTiming::new());
+        let (commands, receiver) = mpsc::channel();
+        let player = {
+            let source: SharedSource = Arc::new(Mutex::new(Box::new(Zero::<f32>::new(2, 48000))));
+            let relay = Relay::new(&source, &Arc::default(), &Arc::default(), timing.clone());
+            let config = null_config(OutputConfig {
+                source_rate: Some(48000),
+                ..OutputConfig::default()
+            });
+            let (watch, listeners) = (SuspendWatch::with_clock(clock.clone()), listeners.clone());
+            thread::spawn(move || play_null(relay, config, &receiver, watch, Some(&listeners)))
+        };
+        thread::sleep(Duration::from_millis(250));
+        // An hour asleep, on a platform whose monotonic clock goes on.
+        clock.jump(Duration::from_secs(3600), Duration::from_secs(3600));
+        thread::sleep(Duration::from_millis(250));
+        drop(commands);
+        player.join().unwrap();
+
+        let seconds = timing.delivered.load(Ordering::Relaxed) as f64 / (2.0 * 48000.0);
+        assert!((0.4..0.7).contains(&seconds), "played {seconds} s in 0.5 s");
+        match suspended.try_recv() {
+            Ok(Signal::Suspended(suspend)) => {
+                assert!(suspend.length >= Duration::from_secs(3600));
+                assert!(suspend.counted >= Duration::from_secs(3600));
+            }
+            _ => panic!("the suspend wasn't signalled"),
+        }
+    }
+
+    #[test]
+    fn the_null_backend_has_no_other_devices() {
+        let opened = Output::open(
+            Zero::<f32>::new(2, 48000),
+            Backend::Null,
+            Some("speakers".to_string()),
+            OutputConfig::default(),
+        );
+        assert!(matches!(opened, Err(PlayerError::Device(_))));
+    }
+}
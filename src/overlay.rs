@@ -0,0 +1,249 @@
+use crate::{
+    atomic::AtomicF32,
+    gain::{db_to_linear, GainControls},
+    lock::Lock,
+};
+use rodio::Source;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// How long the music takes to duck under an overlay and to come back.
+pub const OVERLAY_DUCK_RAMP: Duration = Duration::from_millis(100);
+
+/// Ducks the music while any overlay is playing, or while anyone holds a
+/// [`DuckGuard`]; as far as the deepest of them asks.
+pub(crate) struct DuckControls {
+    gain: Arc<GainControls>,
+    duck_db: AtomicF32,
+    ducks: Mutex<Ducks>,
+}
+
+#[derive(Default)]
+struct Ducks {
+    /// How many overlays are playing.
+    overlays: usize,
+    requests: Vec<DuckRequest>,
+    next_id: u64,
+}
+
+struct DuckRequest {
+    id: u64,
+    db: f32,
+    release: Duration,
+}
+
+impl DuckControls {
+    pub(crate) fn new() -> Self {
+        DuckControls {
+            gain: Arc::new(GainControls::new(1.0, OVERLAY_DUCK_RAMP)),
+            duck_db: AtomicF32::new(0.0),
+            ducks: Mutex::default(),
+        }
+    }
+
+    /// The gain the music goes through.
+    pub(crate) fn gain(&self) -> Arc<GainControls> {
+        self.gain.clone()
+    }
+
+    pub(crate) fn duck_db(&self) -> f32 {
+        self.duck_db.load()
+    }
+
+    /// How far, in positive dB, to turn the music down under overlays. Takes
+    /// effect at once if one is playing.
+    pub(crate) fn set_duck_db(&self, db: f32) {
+        self.update(
+            |_| {
+                self.duck_db.store(db.max(0.0));
+                OVERLAY_DUCK_RAMP
+            },
+            OVERLAY_DUCK_RAMP,
+        );
+    }
+
+    /// Ducks by `db` until the returned guard is dropped, going down over
+    /// `attack` and back over `release`.
+    pub(crate) fn duck(
+        self: &Arc<Self>,
+        db: f32,
+        attack: Duration,
+        release: Duration,
+    ) -> DuckGuard {
+        let mut id = 0;
+        self.update(
+            |ducks| {
+                id = ducks.next_id;
+                ducks.next_id += 1;
+                ducks.requests.push(DuckRequest { id, db, release });
+                release
+            },
+            attack,
+        );
+        DuckGuard {
+            controls: self.clone(),
+            id,
+        }
+    }
+
+    /// Lets go of every duck made through [`DuckControls::duck`], each
+    /// guard's drop doing nothing after.
+    pub(crate) fn unduck(&self) {
+        self.update(
+            |ducks| {
+                let release = ducks.requests.iter().map(|request| request.release).max();
+                ducks.requests.clear();
+                release.unwrap_or_default()
+            },
+            Duration::ZERO,
+        );
+    }
+
+    /// How far, in positive dB, the music is ducked now, or is on its way to.
+    pub(crate) fn ducked_db(&self) -> f32 {
+        self.attenuation(&self.ducks.locked())
+    }
+
+    fn release(&self, id: u64) {
+        self.update(
+            |ducks| {
+                let index = ducks.requests.iter().position(|request| request.id == id);
+                index.map_or(Duration::ZERO, |index| ducks.requests.remove(index).release)
+            },
+            Duration::ZERO,
+        );
+    }
+
+    fn attenuation(&self, ducks: &Ducks) -> f32 {
+        let overlays = match ducks.overlays {
+            0 => 0.0,
+            _ => self.duck_db.load(),
+        };
+        ducks
+            .requests
+            .iter()
+            .map(|request| request.db)
+            .fold(overlays, f32::max)
+    }
+
+    /// Applies `change` to the ducks in effect, then ducks or restores to
+    /// suit: over `attack` if the music goes further down, otherwise over the
+    /// release `change` returns.
+    fn update(&self, change: impl FnOnce(&mut Ducks) -> Duration, attack: Duration) {
+        let mut ducks = self.ducks.locked();
+        let before = self.attenuation(&ducks);
+        let release = change(&mut ducks);
+        let after = self.attenuation(&ducks);
+        if after != before {
+            self.gain
+                .set_ramp(if after > before { attack } else { release });
+        }
+        self.gain.set_target(db_to_linear(-after));
+    }
+}
+
+/// Holds the music down for as long as it lives; see
+/// [`AudioPlayer::duck`](crate::AudioPlayer::duck).
+#[must_use = "the music comes back up as soon as the guard is dropped"]
+pub struct DuckGuard {
+    controls: Arc<DuckControls>,
+    id: u64,
+}
+
+impl Drop for DuckGuard {
+    fn drop(&mut self) {
+        self.controls.release(self.id);
+    }
+}
+
+/// A clip played over the music. The music stays ducked from when it is
+/// made until it ends or is dropped.
+pub(crate) struct Overlay<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    volume: f32,
+    ducking: Option<Arc<DuckControls>>,
+}
+
+impl<S> Overlay<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, volume: f32, ducking: Arc<DuckControls>) -> Self {
+        ducking.update(
+            |ducks| {
+                ducks.overlays += 1;
+                OVERLAY_DUCK_RAMP
+            },
+            OVERLAY_DUCK_RAMP,
+        );
+        Overlay {
+            source,
+            volume,
+            ducking: Some(ducking),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(ducking) = self.ducking.take() {
+            ducking.update(
+                |ducks| {
+                    ducks.overlays -= 1;
+                    OVERLAY_DUCK_RAMP
+                },
+                OVERLAY_DUCK_RAMP,
+            );
+        }
+    }
+}
+
+impl<S> Iterator for Overlay<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.source.next() {
+            Some(sample) => Some(sample * self.volume),
+            None => {
+                self.finish();
+                None
+            }
+        }
+    }
+}
+
+impl<S> Source for Overlay<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Drop for Overlay<S>
+where
+    S: Source<Item = f32>,
+{
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
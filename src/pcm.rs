@@ -0,0 +1,300 @@
+use crate::{
+    lock::Lock,
+    mailbox::{Broadcast, Mailbox},
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// The last of what played for one [`PcmTapReceiver`], written by the
+/// audio thread without locking or allocating. A reader that falls behind
+/// loses the oldest samples, never holding the writer up.
+struct PcmRing {
+    samples: Box<[AtomicU32]>,
+    /// Samples written so far, counting on past the end; only whole frames.
+    written: AtomicUsize,
+    /// The channels in the top half and the rate in the bottom, zero before
+    /// the first write, and the sample the format starts at.
+    format: AtomicU64,
+    format_start: AtomicUsize,
+    /// Cleared when the receiver is dropped, so nothing is written for it.
+    active: AtomicBool,
+}
+
+impl PcmRing {
+    fn new(samples: usize) -> Self {
+        PcmRing {
+            samples: (0..samples.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            format: AtomicU64::new(0),
+            format_start: AtomicUsize::new(0),
+            active: AtomicBool::new(true),
+        }
+    }
+
+    fn slot(&self, position: usize) -> &AtomicU32 {
+        &self.samples[position % self.samples.len()]
+    }
+
+    /// The format and where it starts, read so the two go together.
+    fn format(&self) -> (u64, usize) {
+        loop {
+            let format = self.format.load(Ordering::Acquire);
+            let start = self.format_start.load(Ordering::Acquire);
+            if self.format.load(Ordering::Acquire) == format {
+                return (format, start);
+            }
+        }
+    }
+}
+
+fn pack(channels: u16, sample_rate: u32) -> u64 {
+    (channels as u64) << 32 | sample_rate as u64
+}
+
+fn unpack(format: u64) -> (u16, u32) {
+    ((format >> 32) as u16, format as u32)
+}
+
+/// What a [`PcmTapReceiver::read`] got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmRead {
+    /// Frames read, interleaved in the samples.
+    pub frames: usize,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Frames lost since the read before, overwritten before they were read.
+    pub dropped: usize,
+}
+
+/// Pulls what a player plays, after its EQ and effects, as interleaved
+/// `f32` samples; see [`AudioPlayer::pcm_tap`](crate::AudioPlayer::pcm_tap).
+/// Dropping it stops the player writing for it.
+pub struct PcmTapReceiver {
+    ring: Arc<PcmRing>,
+    /// The sample read next, and the format it is in, from `start` on.
+    read: usize,
+    format: u64,
+    start: usize,
+}
+
+impl PcmTapReceiver {
+    /// Replaces `samples` with what played since the last read, oldest
+    /// first, in one format; a change of channels or rate comes at the
+    /// start of the next read. Read nothing, `channels` and `sample_rate`
+    /// are zero until the player has played.
+    pub fn read(&mut self, samples: &mut Vec<f32>) -> PcmRead {
+        samples.clear();
+        let len = self.ring.samples.len();
+        let written = self.ring.written.load(Ordering::Acquire);
+        let (format, start) = self.ring.format();
+        let mut dropped = 0;
+        if self.format == 0 || (self.format != format && self.read >= start) {
+            self.format = format;
+            self.start = start;
+        } else if self.format == format && self.start != start {
+            // The format went and came back since, what came between lost.
+            let channels = unpack(format).0.max(1) as usize;
+            dropped += start.saturating_sub(self.read) / channels;
+            self.read = self.read.max(start);
+            self.start = start;
+        }
+        // The rest of the format before, or else all there is.
+        let end = match self.format == format {
+            true => written,
+            false => start,
+        };
+
+        let channels = unpack(self.format).0.max(1) as usize;
+        // The first sample still there, on a frame.
+        let first = |written: usize| {
+            let lost = written.saturating_sub(len);
+            match lost > self.start {
+                true => self.start + (lost - self.start).div_ceil(channels) * channels,
+                false => self.start,
+            }
+        };
+        let from = first(written).min(end);
+        if self.read < from {
+            dropped += (from - self.read) / channels;
+            self.read = from;
+        }
+        samples.extend(
+            (self.read..end)
+                .map(|position| f32::from_bits(self.ring.slot(position).load(Ordering::Relaxed))),
+        );
+        // What the writer went over while this was read can't be trusted.
+        let overwritten = first(self.ring.written.load(Ordering::Acquire))
+            .min(end)
+            .saturating_sub(self.read);
+        if overwritten > 0 {
+            samples.drain(..overwritten);
+            dropped += overwritten / channels;
+        }
+        self.read = end;
+
+        let (channels, sample_rate) = unpack(self.format);
+        if self.format != format {
+            self.format = format;
+            self.start = start;
+        }
+        PcmRead {
+            frames: samples.len() / channels.max(1) as usize,
+            channels,
+            sample_rate,
+            dropped,
+        }
+    }
+}
+
+impl Drop for PcmTapReceiver {
+    fn drop(&mut self) {
+        self.ring.active.store(false, Ordering::Relaxed);
+    }
+}
+
+/// The taps of one player, handed to its [`PcmTap`] stage.
+pub(crate) struct PcmTaps {
+    rings: Mutex<Vec<Arc<PcmRing>>>,
+    updates: Broadcast<Vec<Arc<PcmRing>>>,
+}
+
+impl PcmTaps {
+    pub(crate) fn new() -> Self {
+        PcmTaps {
+            rings: Mutex::new(Vec::new()),
+            updates: Broadcast::new(),
+        }
+    }
+
+    /// A new tap holding `samples`, and those dropped since let go of.
+    pub(crate) fn add(&self, samples: usize) -> PcmTapReceiver {
+        let ring = Arc::new(PcmRing::new(samples));
+        let mut rings = self.rings.locked();
+        rings.retain(|ring| ring.active.load(Ordering::Relaxed));
+        rings.push(ring.clone());
+        self.updates.post(&rings);
+        PcmTapReceiver {
+            ring,
+            read: 0,
+            format: 0,
+            start: 0,
+        }
+    }
+}
+
+/// Passes audio through unchanged while copying it into each live tap.
+pub(crate) struct PcmTap<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    updates: Arc<Mailbox<Vec<Arc<PcmRing>>>>,
+    rings: Vec<Arc<PcmRing>>,
+    /// Whether any tap is live, as of the frame's start.
+    writing: bool,
+    channel: u16,
+    channels: u16,
+}
+
+impl<S> PcmTap<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, taps: &PcmTaps) -> Self {
+        // Subscribed first, so a tap added meanwhile is still seen.
+        let updates = taps.updates.subscribe();
+        PcmTap {
+            rings: taps.rings.locked().clone(),
+            updates,
+            source,
+            writing: false,
+            channel: 0,
+            channels: 1,
+        }
+    }
+
+    /// Notes the format of the frame about to start in each tap.
+    fn start_frame(&mut self) {
+        if let Some(rings) = self.updates.take() {
+            self.rings = rings;
+        }
+        self.channels = self.source.channels().max(1);
+        let format = pack(self.channels, self.source.sample_rate());
+        self.writing = false;
+        for ring in &self.rings {
+            if !ring.active.load(Ordering::Relaxed) {
+                continue;
+            }
+            self.writing = true;
+            if ring.format.load(Ordering::Relaxed) != format {
+                let written = ring.written.load(Ordering::Relaxed);
+                ring.format_start.store(written, Ordering::Release);
+                ring.format.store(format, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl<S> Iterator for PcmTap<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.start_frame();
+        }
+        let sample = self.source.next()?;
+        if self.writing {
+            let last = self.channel + 1 == self.channels;
+            for ring in &self.rings {
+                if !ring.active.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let written = ring.written.load(Ordering::Relaxed);
+                ring.slot(written + self.channel as usize)
+                    .store(sample.to_bits(), Ordering::Relaxed);
+                if last {
+                    ring.written
+                        .store(written + self.channels as usize, Ordering::Release);
+                }
+            }
+        }
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl<S> Source for PcmTap<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.channel = 0;
+        Ok(())
+    }
+}
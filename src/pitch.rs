@@ -0,0 +1,249 @@
+use crate::tempo::TempoControls;
+use rodio::{source::SeekError, Source};
+use std::{sync::Arc, time::Duration};
+
+/// Time constant of the glide to a new pitch, short enough to feel immediate
+/// without the click of a jump.
+const GLIDE: Duration = Duration::from_millis(30);
+
+/// Most a read step may exceed one frame while settling back onto whole
+/// source frames at 0 semitones; about 7 cents, for under 6 ms.
+const SETTLE_STEP: f64 = 1.0 / 256.0;
+
+/// Shifts pitch by reading the source faster or slower than it plays,
+/// interpolating between frames. It sits after the [`TimeStretch`], which
+/// has already stretched the audio by as much, so the tempo stays put.
+///
+/// At 0 semitones the source passes through untouched; going there or away
+/// glides. The format reported is always the source's.
+///
+/// [`TimeStretch`]: crate::tempo::TimeStretch
+pub(crate) struct PitchShift<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<TempoControls>,
+    resampling: bool,
+    channels: u16,
+    sample_rate: u32,
+    /// Four interleaved source frames; the read position lies between the
+    /// second and third.
+    frames: Vec<f32>,
+    /// Fraction of the way from the second frame to the third.
+    offset: f64,
+    /// Source frames read per output frame, gliding towards the pitch factor.
+    step: f64,
+    /// How many of `frames`, from the end, are silence past the end of the
+    /// source or its current format.
+    padding: usize,
+    /// Whether the source has run out, so there is nothing left to shift.
+    ended: bool,
+    output: Vec<f32>,
+    output_index: usize,
+    /// Channel of the next sample while passing the source through.
+    channel: u16,
+}
+
+impl<S> PitchShift<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<TempoControls>) -> Self {
+        PitchShift {
+            channels: source.channels().max(1),
+            sample_rate: source.sample_rate(),
+            source,
+            controls,
+            resampling: false,
+            frames: Vec::new(),
+            offset: 0.0,
+            step: 1.0,
+            padding: 0,
+            ended: false,
+            output: Vec::new(),
+            output_index: 0,
+            channel: 0,
+        }
+    }
+
+    /// Starts resampling from the source's current position, in its current format.
+    fn start(&mut self) {
+        self.channels = self.source.channels().max(1);
+        self.sample_rate = self.source.sample_rate();
+        let channels = self.channels as usize;
+        self.frames.clear();
+        self.frames.resize(4 * channels, 0.0);
+        self.padding = 0;
+        self.offset = 0.0;
+        self.resampling = true;
+        // Read onto the next source frame; the one before it has already
+        // played, so it is stood in for by the next one.
+        for _ in 0..3 {
+            self.shift();
+        }
+        self.frames.copy_within(channels..2 * channels, 0);
+    }
+
+    /// Moves on by one source frame.
+    fn shift(&mut self) {
+        let channels = self.channels as usize;
+        self.frames.copy_within(channels.., 0);
+        let last = &mut self.frames[3 * channels..];
+        if self.padding == 0
+            && self.source.channels().max(1) == self.channels
+            && self.source.sample_rate() == self.sample_rate
+        {
+            for slot in last.iter_mut() {
+                match self.source.next() {
+                    Some(sample) => *slot = sample,
+                    None => {
+                        self.ended = true;
+                        // A partial frame is dropped.
+                        last.fill(0.0);
+                        self.padding = 1;
+                        return;
+                    }
+                }
+            }
+            return;
+        }
+        last.fill(0.0);
+        self.padding += 1;
+    }
+
+    /// Interpolates the next output frame, or goes back to passing the
+    /// source through once it is at 0 semitones on a whole frame.
+    fn resample(&mut self) {
+        let channels = self.channels as usize;
+        // Nothing of the source's left past the read position.
+        if self.padding >= 3 {
+            self.resampling = false;
+            self.channel = 0;
+            return;
+        }
+
+        let target = self.controls.pitch_factor() as f64;
+        let glide = 1.0 - (-1.0 / (GLIDE.as_secs_f64() * self.sample_rate as f64)).exp();
+        self.step *= (target / self.step).powf(glide);
+        if (self.step / target - 1.0).abs() < 1e-5 {
+            self.step = target;
+        }
+        let settling = target == 1.0 && self.step == 1.0;
+        if settling && self.offset == 0.0 {
+            // Hand over the frames already read, starting with the one at
+            // the read position.
+            let real = 4 - self.padding;
+            self.output
+                .extend_from_slice(&self.frames[channels..real * channels]);
+            self.resampling = false;
+            self.channel = 0;
+            return;
+        }
+
+        let t = self.offset as f32;
+        for channel in 0..channels {
+            let y = |frame: usize| self.frames[frame * channels + channel];
+            self.output.push(hermite(y(0), y(1), y(2), y(3), t));
+        }
+
+        let (whole, offset) = if settling && 1.0 - self.offset <= SETTLE_STEP {
+            (2, 0.0)
+        } else {
+            let step = match settling {
+                true => 1.0 + SETTLE_STEP,
+                false => self.step,
+            };
+            let next = self.offset + step;
+            (next.floor() as usize, next.fract())
+        };
+        self.offset = offset;
+        for _ in 0..whole {
+            self.shift();
+        }
+    }
+}
+
+/// Catmull-Rom interpolation `t` of the way from `y1` to `y2`.
+fn hermite(y0: f32, y1: f32, y2: f32, y3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+    ((c3 * t + c2) * t + c1) * t + y1
+}
+
+impl<S> Iterator for PitchShift<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(&sample) = self.output.get(self.output_index) {
+                self.output_index += 1;
+                return Some(sample);
+            }
+            self.output.clear();
+            self.output_index = 0;
+
+            if self.resampling {
+                self.resample();
+                continue;
+            }
+            if self.channel == 0 {
+                if (self.controls.pitch_factor() != 1.0 || self.step != 1.0) && !self.ended {
+                    self.start();
+                    continue;
+                }
+                self.channels = self.source.channels().max(1);
+            }
+            let sample = self.source.next()?;
+            self.channel = (self.channel + 1) % self.channels;
+            return Some(sample);
+        }
+    }
+}
+
+impl<S> Source for PitchShift<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        match (self.output.len() - self.output_index, self.resampling) {
+            (0, false) => self.source.current_frame_len(),
+            // At least one more frame comes in the same format.
+            (0, true) => Some(self.channels as usize),
+            (waiting, _) => Some(waiting),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self.resampling || self.output_index < self.output.len() {
+            true => self.channels,
+            false => self.source.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self.resampling || self.output_index < self.output.len() {
+            true => self.sample_rate,
+            false => self.source.sample_rate(),
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    /// Keeps the current pitch rather than gliding to it again.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.output.clear();
+        self.output_index = 0;
+        self.resampling = false;
+        self.channel = 0;
+        self.ended = false;
+        Ok(())
+    }
+}
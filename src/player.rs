@@ -0,0 +1,4383 @@
+use crate::{
+    ab::{AbComparison, AB},
+    album::{
+        spawn_album_scanner, AlbumControls, AlbumRun, GainMode, TrackGain,
+        REPLAYGAIN_REFERENCE_LUFS,
+    },
+    atomic::AtomicF32,
+    backend::Backend,
+    bookmark::Bookmarks,
+    buffer::{spawn_decoder, BufferControls, Hold, PlaybackState, MAX_WATERMARK},
+    cache::{spawn_filler, CacheControls, CacheStatus, CachedSource, TrackCache},
+    chain::{
+        ChainConfig, ChannelStage, CompressorStage, EqStage, LimiterStage, ReverbStage, ToneStage,
+        WidthStage,
+    },
+    channels::{
+        Balance, ChannelControls, ChannelMapper, ChannelMode, StereoWidth, WidthControls,
+        WidthPlacement,
+    },
+    clock::Clock,
+    compressor::{Compressor, CompressorControls, CompressorSettings},
+    cue::{CueSheet, CueTrack},
+    decode::{self, DecoderBackend},
+    engine::AudioEngine,
+    equalizer::{EqControls, Equalizer, DEFAULT_GAINS, FREQUENCIES},
+    error::PlayerError,
+    events::{Events, PlayerEvent, Signal},
+    gain::{db_to_linear, Gain, GainControls},
+    history::{History, HistoryEntry},
+    http::{Download, StreamSource, DEFAULT_PREFETCH},
+    limiter::{Limiter, LimiterControls},
+    lock::Lock,
+    looping::{LoopBuffer, LoopControls, Looper},
+    loudness::{spawn_scanner, LoudnessControls, TrackLoudness},
+    metadata::{Chapter, TrackMetadata},
+    meter::{ChannelLevel, Meter, MeterControls},
+    now_playing::NowPlaying,
+    output::{OutputConfig, StreamConfig},
+    overlay::{DuckControls, DuckGuard, Overlay},
+    pcm::{PcmTap, PcmTapReceiver, PcmTaps},
+    pitch::PitchShift,
+    playlist::{self, is_url},
+    position::Position,
+    preset::EqPreset,
+    probe::{file_duration, probe},
+    profile::{DeviceProfiles, EqProfile},
+    queue::{
+        Origin, Playlist, PlaylistControls, QueueItem, RepeatMode, Replayable, Track, TrackSource,
+    },
+    recover::{DecodeControls, FaultTolerant},
+    replaygain::{ReplayGainControls, ReplayGainMode},
+    resample::{ResampleControls, ResampleQuality, Resampler},
+    reverb::{Reverb, ReverbControls, ReverbSettings},
+    schedule::{StartControls, StartGate},
+    scrobble::ScrobbleControls,
+    seeker::Seeker,
+    segment::{Segment, SegmentControls, SegmentEnd},
+    settings::{EqBand, EqSettings},
+    shuffle::Shuffle,
+    silence::{leading_silence, SilenceControls, SilenceSkip},
+    sleep::{SleepAction, SleepTimer},
+    spectrum::{SpectrumTap, Tap},
+    state::PlayerState,
+    stats::{DecodeCounter, OutputCounter, PlayerStats, StatsControls},
+    stdin::STDIN_PATH,
+    tempo::{TempoControls, TimeStretch},
+    tone::{Tone, ToneControls, MAX_TONE_DB, MAX_TONE_KNOB_DB},
+    trim::{TrackRange, Trim},
+    vocal::{VocalControls, VocalReduction},
+    watch::{FileWatch, FolderWatch, FolderWatchOptions},
+};
+use rodio::{source::SeekError, Sink, Source};
+use std::{
+    collections::HashMap,
+    iter,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+pub const MIN_VOLUME_DB: f32 = -60.0;
+pub const MAX_VOLUME_DB: f32 = 6.0;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2.0;
+pub const MIN_TEMPO: f32 = 0.5;
+pub const MAX_TEMPO: f32 = 2.0;
+pub const MAX_PITCH_SEMITONES: f32 = 12.0;
+
+pub const DEFAULT_FADE: Duration = Duration::from_millis(150);
+
+const VOLUME_RAMP: Duration = Duration::from_millis(20);
+
+const END_POLL: Duration = Duration::from_millis(10);
+
+/// How often [`AudioPlayer::play_at`] checks whether the track is decoded
+/// ahead enough to start.
+const PREROLL_POLL: Duration = Duration::from_millis(1);
+
+/// How far into a chapter [`AudioPlayer::previous_chapter`] goes back to its
+/// start rather than to the chapter before.
+pub const CHAPTER_RESTART: Duration = Duration::from_secs(3);
+
+/// How long [`AudioPlayer::scrub`] waits for the next position before
+/// seeking to the last one.
+pub const SCRUB_SETTLE: Duration = Duration::from_millis(50);
+
+/// How much of the track a scrub plays where it settles while paused; see
+/// [`AudioPlayer::set_scrub_preview`].
+pub const SCRUB_PREVIEW: Duration = Duration::from_millis(150);
+
+/// The fade in and out of a scrub preview.
+const SCRUB_PREVIEW_FADE: Duration = Duration::from_millis(15);
+
+/// How often the position is bookmarked while playing.
+const BOOKMARK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the state is autosaved while playing.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Plays a queue of files and streams through the EQ and effects, on an
+/// output device of its own or one an [`AudioEngine`] shares.
+///
+/// # Threads
+///
+/// A player is `Send` and `Sync`: share it as an `Arc<AudioPlayer>` and
+/// call any method from any number of threads at once, a UI thread and an
+/// async task say. Calls don't deadlock with one another or with the
+/// player's own threads, and one that races another, such as two seeks,
+/// simply takes effect after it.
+///
+/// How long a call can take:
+///
+/// - Reading where playback is, through [`AudioPlayer::get_playback_position`],
+///   [`AudioPlayer::position`], [`AudioPlayer::is_playing`] and
+///   [`AudioPlayer::is_seeking`], never waits for a lock. Reading or
+///   changing the volume, EQ and effects waits at most for another change
+///   to the same setting.
+/// - Reading the queue and what's playing, as [`AudioPlayer::queue`],
+///   [`AudioPlayer::now_playing`] and [`AudioPlayer::metadata`] do, waits
+///   at most for another thread to copy or change the list, never for a
+///   file to be read.
+/// - [`AudioPlayer::seek`] returns straight away and does its work on the
+///   seek thread.
+/// - Calls that change the queue or what plays, such as
+///   [`AudioPlayer::play`], [`AudioPlayer::pause`], [`AudioPlayer::next`],
+///   [`AudioPlayer::remove`], [`AudioPlayer::set_shuffle`] and
+///   [`AudioPlayer::set_speed`], take turns, so each waits for one under
+///   way on another thread. Most are quick, but those that restart
+///   playback open the files of the tracks to come, and decode up to the
+///   position where the file can't seek.
+/// - Queueing, as [`AudioPlayer::enqueue`] and
+///   [`AudioPlayer::restore_state`] do, opens and probes the files, and a
+///   stream waits for its prefetch; [`AudioPlayer::play_at`] waits until
+///   the time given, or until enough is decoded.
+pub struct AudioPlayer {
+    engine: AudioEngine,
+    /// Held while the queue or what plays changes. A call that needs more
+    /// than one lock takes them in this order: `sink`, `shuffle`, then the
+    /// clock's and any others, and `tracks` last, only ever briefly.
+    sink: Arc<Mutex<Sink>>,
+    tracks: Arc<Mutex<Vec<Track>>>,
+    next_id: AtomicU64,
+    playlist: Arc<PlaylistControls>,
+    clock: Arc<Clock>,
+    events: Events,
+    eq: Arc<EqControls>,
+    volume: Arc<GainControls>,
+    fade: Arc<GainControls>,
+    fade_in: Mutex<Duration>,
+    fade_out: Arc<Mutex<Duration>>,
+    limiter: Arc<LimiterControls>,
+    reverb: Arc<ReverbControls>,
+    ducking: Arc<DuckControls>,
+    channels: Arc<ChannelControls>,
+    spectrum: Arc<SpectrumTap>,
+    pcm_taps: Arc<PcmTaps>,
+    meter: Arc<MeterControls>,
+    looping: Arc<LoopControls>,
+    tempo: Arc<TempoControls>,
+    builder: TrackBuilder,
+    scans: Sender<(u64, PathBuf)>,
+    album_scans: Sender<Arc<AlbumRun>>,
+    cache: Arc<CacheControls>,
+    fills: Sender<(PathBuf, Duration, Arc<TrackCache>)>,
+    volume_db: Arc<AtomicF32>,
+    muted: Arc<AtomicBool>,
+    shuffle: Arc<Mutex<Shuffle>>,
+    prefetch: AtomicUsize,
+    is_stopped: Arc<AtomicBool>,
+    sleep_timer: SleepTimer,
+    seeker: Seeker,
+    scrub_preview: AtomicBool,
+    bookmarks: Arc<Mutex<Option<Bookmarks>>>,
+    history: Arc<Mutex<Option<History>>>,
+    autosave: Arc<Mutex<Option<PathBuf>>>,
+    eq_watch: Mutex<Option<FileWatch>>,
+    folder_watch: Mutex<Option<FolderWatch>>,
+    ab: Mutex<Option<AbComparison>>,
+    profiles: Arc<DeviceProfiles>,
+    scrobble: Arc<ScrobbleControls>,
+    start: Arc<StartControls>,
+    /// The metadata of the cue track last shown, by track id and index.
+    cue_metadata: Mutex<Option<(u64, usize, Arc<TrackMetadata>)>>,
+}
+
+impl AudioPlayer {
+    /// Opens `path` on the default output device, at the file's own sample
+    /// rate where the device goes at it. The player starts paused.
+    pub fn open(path: impl AsRef<Path>) -> Result<AudioPlayer, PlayerError> {
+        Self::with_eq(path, DEFAULT_GAINS.to_vec())
+    }
+
+    /// Like [`AudioPlayer::open`], but with the given per-band EQ gains in dB:
+    /// ten for the octave layout or 31 for the third-octave one. Any other
+    /// number fails with [`PlayerError::InvalidEq`].
+    pub fn with_eq(path: impl AsRef<Path>, eq_gains: Vec<f32>) -> Result<AudioPlayer, PlayerError> {
+        Self::with_settings(path, EqSettings::for_gains(&eq_gains)?)
+    }
+
+    /// Like [`AudioPlayer::open`], but with a full EQ setup such as one loaded
+    /// through [`EqSettings::load`].
+    pub fn with_settings(
+        path: impl AsRef<Path>,
+        settings: EqSettings,
+    ) -> Result<AudioPlayer, PlayerError> {
+        Self::with_backend(path, settings, Backend::Default, None)
+    }
+
+    /// Like [`AudioPlayer::with_settings`], but playing through `backend` on
+    /// its device named `device`, or its default one with `None`, as
+    /// [`AudioEngine::with_backend`] opens them.
+    pub fn with_backend(
+        path: impl AsRef<Path>,
+        settings: EqSettings,
+        backend: Backend,
+        device: Option<&str>,
+    ) -> Result<AudioPlayer, PlayerError> {
+        let path = path.as_ref();
+        let config = OutputConfig {
+            source_rate: probe(path).ok().map(|stream| stream.sample_rate),
+            ..OutputConfig::default()
+        };
+        let engine = AudioEngine::with_output_config(backend, device, config)?;
+        let player = Self::on_engine(engine, settings)?;
+        player.enqueue(path)?;
+        Ok(player)
+    }
+
+    /// Opens the `http://` or `https://` stream at `url` on the default output
+    /// device, prefetching [`DEFAULT_PREFETCH`] bytes before it returns.
+    /// The player starts paused.
+    pub fn open_url(url: &str) -> Result<AudioPlayer, PlayerError> {
+        Self::open_url_with_prefetch(url, DEFAULT_PREFETCH)
+    }
+
+    /// Like [`AudioPlayer::open_url`], buffering `prefetch` bytes before this
+    /// and any later [`AudioPlayer::enqueue_url`] returns.
+    pub fn open_url_with_prefetch(url: &str, prefetch: usize) -> Result<AudioPlayer, PlayerError> {
+        let player = Self::new(EqSettings::from_gains(&DEFAULT_GAINS))?;
+        player.prefetch.store(prefetch, Ordering::Relaxed);
+        player.enqueue_url(url)?;
+        Ok(player)
+    }
+
+    fn new(settings: EqSettings) -> Result<AudioPlayer, PlayerError> {
+        Self::on_engine(AudioEngine::new()?, settings)
+    }
+
+    /// An empty player mixed into `engine`'s output.
+    pub(crate) fn on_engine(
+        engine: AudioEngine,
+        settings: EqSettings,
+    ) -> Result<AudioPlayer, PlayerError> {
+        let (sink, queue) = Sink::new_idle();
+        sink.pause();
+
+        let limiter = LimiterControls::default();
+        limiter.set_enabled(false);
+
+        let (signals, receiver) = mpsc::channel();
+        // Dropping the sink ends its queue, which takes it out of the mix.
+        engine.add(queue, signals.clone());
+        let tracks = Arc::new(Mutex::new(Vec::new()));
+        let playlist = Arc::new(PlaylistControls::new(signals.clone()));
+        let looping = Arc::new(LoopControls::new(playlist.clone()));
+        let tempo = Arc::new(TempoControls::new());
+        let silence = Arc::new(SilenceControls::new());
+        let clock = Arc::new(Clock::new(
+            tracks.clone(),
+            playlist.clone(),
+            looping.clone(),
+            tempo.clone(),
+            silence.clone(),
+        ));
+        let start = Arc::new(StartControls::new(
+            engine.clock(),
+            clock.clone(),
+            signals.clone(),
+        ));
+        let buffering = Arc::new(BufferControls::new(playlist.clone()));
+        spawn_decoder(&buffering, signals.clone());
+        let builder = TrackBuilder {
+            eq: Arc::new(EqControls::new(settings)),
+            compressor: Arc::new(CompressorControls::new(None)),
+            tone: Arc::new(ToneControls::new()),
+            width: Arc::new(WidthControls::new()),
+            vocal: Arc::new(VocalControls::new()),
+            looping: looping.clone(),
+            segments: Arc::new(SegmentControls::new()),
+            loudness: Arc::new(LoudnessControls::new()),
+            replay_gain: Arc::new(ReplayGainControls::new()),
+            albums: Arc::new(AlbumControls::new()),
+            silence,
+            decoding: Arc::new(DecodeControls::new()),
+            resampling: Arc::new(ResampleControls::new(engine.sample_rate())),
+            stats: Arc::new(StatsControls::new()),
+            buffering,
+            signals: signals.clone(),
+        };
+        let shuffle = Arc::new(Mutex::new(Shuffle::new()));
+        let cache = Arc::new(CacheControls::new());
+        // Both scanners move the queue over to what they measured.
+        let relevel = || {
+            let (builder, tracks) = (builder.clone(), tracks.clone());
+            move || builder.relevel(&tracks)
+        };
+        let (scans, album_scans) = (
+            spawn_scanner(builder.loudness.clone(), relevel()),
+            spawn_album_scanner(relevel()),
+        );
+        let scrobble = Arc::new(ScrobbleControls::new());
+        let sink = Arc::new(Mutex::new(sink));
+        let is_stopped = Arc::new(AtomicBool::new(false));
+        let events = Events::spawn(
+            signals,
+            receiver,
+            Arc::downgrade(&clock),
+            scrobble.clone(),
+            {
+                let (sink, is_stopped) = (Arc::downgrade(&sink), is_stopped.clone());
+                let (playlist, clock) = (playlist.clone(), Arc::downgrade(&clock));
+                let buffering = builder.buffering.clone();
+                move || {
+                    let (Some(sink), Some(clock)) = (sink.upgrade(), clock.upgrade()) else {
+                        return PlaybackState::Ended;
+                    };
+                    playback_state(&is_stopped, &playlist, &sink, &clock, &buffering)
+                }
+            },
+            {
+                let (tracks, playlist, builder) =
+                    (tracks.clone(), playlist.clone(), builder.clone());
+                let (shuffle, sink, is_stopped) =
+                    (shuffle.clone(), Arc::downgrade(&sink), is_stopped.clone());
+                move |id| {
+                    shuffle.locked().started(id);
+                    if let Some(sink) = sink.upgrade() {
+                        // Under the sink's lock, so nothing rebuilds the
+                        // playlist meanwhile; a track that has been
+                        // rebuilt away since has nothing to line up.
+                        let _sink = sink.locked();
+                        let stopped = is_stopped.load(Ordering::Relaxed);
+                        if !stopped && playlist.last_started() == Some(id) {
+                            builder.line_up(&tracks, &playlist, &shuffle, id, None, false);
+                        }
+                    }
+                    builder.prepare_repeat(&tracks, &playlist, &shuffle, id);
+                    if let Some(gain_db) = builder.replay_gain.applied(id) {
+                        let event = PlayerEvent::ReplayGainApplied { gain_db };
+                        let _ = builder.signals.send(Signal::Event(event));
+                    }
+                }
+            },
+        );
+
+        Ok(AudioPlayer {
+            engine,
+            sink,
+            tracks,
+            next_id: AtomicU64::new(0),
+            playlist,
+            clock,
+            events,
+            eq: builder.eq.clone(),
+            volume: Arc::new(GainControls::new(1.0, VOLUME_RAMP)),
+            fade: Arc::new(GainControls::new(0.0, DEFAULT_FADE)),
+            fade_in: Mutex::new(DEFAULT_FADE),
+            fade_out: Arc::new(Mutex::new(DEFAULT_FADE)),
+            limiter: Arc::new(limiter),
+            reverb: Arc::new(ReverbControls::new(None)),
+            ducking: Arc::new(DuckControls::new()),
+            channels: Arc::new(ChannelControls::new()),
+            spectrum: Arc::new(SpectrumTap::new()),
+            pcm_taps: Arc::new(PcmTaps::new()),
+            meter: Arc::new(MeterControls::default()),
+            looping,
+            tempo,
+            volume_db: Arc::new(AtomicF32::new(0.0)),
+            muted: Arc::default(),
+            scans,
+            album_scans,
+            cache: cache.clone(),
+            fills: spawn_filler(cache, builder.decoding.clone()),
+            builder,
+            shuffle,
+            prefetch: AtomicUsize::new(DEFAULT_PREFETCH),
+            is_stopped,
+            sleep_timer: SleepTimer::default(),
+            seeker: Seeker::new(),
+            scrub_preview: AtomicBool::new(false),
+            bookmarks: Arc::default(),
+            history: Arc::default(),
+            autosave: Arc::default(),
+            eq_watch: Mutex::new(None),
+            folder_watch: Mutex::new(None),
+            ab: Mutex::new(None),
+            cue_metadata: Mutex::new(None),
+            profiles: Arc::default(),
+            scrobble,
+            start,
+        })
+    }
+
+    /// Adds `path` to the end of the queue. Its decoder is only kept open
+    /// while it plays or is next, so it follows the previous track without a
+    /// gap; any queued further on is opened as its turn comes.
+    ///
+    /// [`STDIN_PATH`] reads the track from standard input. Its length is
+    /// unknown and it can't seek or play a second time; those fail with
+    /// [`PlayerError::UnsupportedSeek`].
+    pub fn enqueue(&self, path: impl AsRef<Path>) -> Result<(), PlayerError> {
+        self.enqueue_path(path.as_ref().to_path_buf(), None)
+            .map(drop)
+    }
+
+    /// Queues `path` as [`AudioPlayer::enqueue`] does, but at `index` in
+    /// [`AudioPlayer::queue`], or last if that is past the end. Returns the
+    /// new entry's id. While shuffled it plays at a random point, as
+    /// enqueued tracks do.
+    pub fn insert_at(&self, index: usize, path: impl AsRef<Path>) -> Result<u64, PlayerError> {
+        self.enqueue_path(path.as_ref().to_path_buf(), Some(index))
+    }
+
+    fn enqueue_path(&self, path: PathBuf, at: Option<usize>) -> Result<u64, PlayerError> {
+        if path == Path::new(STDIN_PATH) {
+            let origin = Origin::Stdin(Arc::default());
+            let decoder = open_decoder(&path, &origin, self.builder.decoding.backend())?;
+            let duration = decoder.total_duration();
+            let rate = decoder.sample_rate();
+            let track = self.new_track(path, origin, duration, rate, TrackMetadata::default());
+            return self.push_track(track, decoder, at);
+        }
+        self.enqueue_file(path, None, None, at)
+    }
+
+    /// Queues only the part of the file at `path` from `start` to `end`, or
+    /// to the end of the file for `None`, to skip an intro or outro every
+    /// time it plays. The entry plays as if the file held nothing else: it
+    /// starts at `start`, ends at `end` with the usual handover to the next
+    /// track, and its duration, position and seeks are all within the range.
+    /// Fails with [`PlayerError::InvalidArgument`] if the range is empty or
+    /// goes past the end of the file.
+    pub fn enqueue_with_range(
+        &self,
+        path: impl AsRef<Path>,
+        start: Duration,
+        end: Option<Duration>,
+    ) -> Result<(), PlayerError> {
+        let path = path.as_ref();
+        if path == Path::new(STDIN_PATH) {
+            return Err(PlayerError::InvalidArgument(
+                "standard input can't be queued with a range".into(),
+            ));
+        }
+        let range = TrackRange { start, end };
+        self.enqueue_file(path.to_path_buf(), None, Some(range), None)
+            .map(drop)
+    }
+
+    /// Opens the file referenced by the cue sheet at `path` on the default
+    /// output device. The player starts paused.
+    ///
+    /// The file is decoded once, and its tracks make up a virtual playlist:
+    /// `next` and `previous` move between them, and the position, duration,
+    /// events and metadata are all per track. Lines of the sheet that can't
+    /// be parsed are skipped; see [`CueSheet::warnings`].
+    pub fn open_cue(path: impl AsRef<Path>) -> Result<AudioPlayer, PlayerError> {
+        let player = Self::new(EqSettings::from_gains(&DEFAULT_GAINS))?;
+        player.enqueue_cue(CueSheet::load(path)?)?;
+        Ok(player)
+    }
+
+    /// Adds the file of `sheet`, as loaded by [`CueSheet::load`], to the end
+    /// of the queue, split into the sheet's tracks as described for
+    /// [`AudioPlayer::open_cue`].
+    pub fn enqueue_cue(&self, sheet: CueSheet) -> Result<(), PlayerError> {
+        if sheet.tracks.is_empty() {
+            return Err(PlayerError::NothingToPlay(
+                "the cue sheet has no playable tracks".into(),
+            ));
+        }
+        self.enqueue_file(sheet.file.clone(), Some(Arc::new(sheet)), None, None)
+            .map(drop)
+    }
+
+    /// Adds the entries of `playlist` to the end of the queue, in order.
+    /// Entries that can't be opened, such as missing files, are skipped with
+    /// a [`PlayerEvent::Warning`]; this only fails if none could be.
+    pub fn enqueue_playlist(&self, playlist: &playlist::Playlist) -> Result<(), PlayerError> {
+        let mut queued_any = false;
+        for entry in &playlist.entries {
+            let queued = match entry.path.to_str().filter(|_| is_url(&entry.path)) {
+                Some(url) => self.enqueue_url(url),
+                None => self.enqueue(&entry.path),
+            };
+            match queued {
+                Ok(()) => queued_any = true,
+                Err(err) => self.events.emit(PlayerEvent::Warning(format!(
+                    "skipping {}: {err}",
+                    entry.path.display()
+                ))),
+            }
+        }
+        if !queued_any {
+            return Err(PlayerError::NothingToPlay(
+                "none of the playlist's entries could be opened".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn enqueue_file(
+        &self,
+        path: PathBuf,
+        cue: Option<Arc<CueSheet>>,
+        range: Option<TrackRange>,
+        at: Option<usize>,
+    ) -> Result<u64, PlayerError> {
+        let mut decoder = self.builder.open_decoder(&path, &Origin::File)?;
+        let duration = file_duration(&path, decoder.total_duration());
+        if let Some(range) = range {
+            check_range(range, duration)?;
+        }
+        let metadata = TrackMetadata::read(&path).unwrap_or_default();
+        let rate = decoder.sample_rate();
+        let mut track = self.new_track(path, Origin::File, duration, rate, metadata);
+        if let Some(duration) = duration.filter(|&duration| {
+            self.cache
+                .fits(duration, decoder.channels(), decoder.sample_rate())
+        }) {
+            let cache = Arc::new(TrackCache::default());
+            let _ = self
+                .fills
+                .send((track.path.clone(), duration, cache.clone()));
+            track.cache = Some(cache.clone());
+            decoder = Box::new(CachedSource::new(Some(decoder), cache));
+        } else {
+            decoder = Box::new(self.builder.buffering.wrap(track.id, decoder));
+        }
+        if let Some(range) = range {
+            decoder = Box::new(Trim::new(decoder, range));
+            track.duration = range.length(duration);
+            track.range = Some(range);
+        }
+        let silence = &self.builder.silence;
+        if silence.is_trimming() {
+            let threshold_db = silence.trim_threshold_db();
+            track.lead = open_decoder(&track.path, &track.origin, self.builder.decoding.backend())
+                .map_or(Duration::ZERO, |scan| match range {
+                    Some(range) => leading_silence(Trim::new(scan, range), threshold_db),
+                    None => leading_silence(scan, threshold_db),
+                });
+        }
+        track.cue = cue;
+        self.push_track(track, decoder, at)
+    }
+
+    /// Adds the `http://` or `https://` stream at `url` to the end of the queue,
+    /// once the prefetch set by [`AudioPlayer::open_url_with_prefetch`] has
+    /// arrived. The download carries on in the background.
+    ///
+    /// Seeking needs a server that accepts range requests; otherwise it
+    /// fails with an error saying so. `https://` URLs are fetched through
+    /// the system's `curl`, as no TLS crate is built in, and fail with
+    /// [`PlayerError::CurlMissing`] if it isn't installed.
+    ///
+    /// Internet radio, a live stream answered with `icy-` headers, has no
+    /// duration and can't seek. Its `StreamTitle` is reported by
+    /// [`PlayerEvent::StreamTitleChanged`] and [`AudioPlayer::stream_title`],
+    /// and a dropped connection is made again with backoff, reported by
+    /// [`PlayerEvent::Reconnecting`], leaving a gap of silence.
+    pub fn enqueue_url(&self, url: &str) -> Result<(), PlayerError> {
+        self.enqueue_stream(url).map(drop)
+    }
+
+    /// Queues the audio files in the folder at `path`, in `options.order`,
+    /// then keeps watching it: a file that appears is queued once it has
+    /// stayed the same for `options.settle`, and one deleted while still in
+    /// the queue is taken off it with a [`PlayerEvent::WatchedFileDeleted`].
+    /// The folder is polled. A file that won't open is skipped with a
+    /// [`PlayerEvent::Warning`]. Replaces any folder watched before; fails,
+    /// watching nothing, if the folder can't be read.
+    pub fn watch_folder(
+        self: &Arc<Self>,
+        path: impl Into<PathBuf>,
+        options: FolderWatchOptions,
+    ) -> Result<(), PlayerError> {
+        self.unwatch_folder();
+        let player = Arc::downgrade(self);
+        let queued = Arc::new(Mutex::new(HashMap::new()));
+        let signals = self.events.signals();
+        let found = {
+            let (player, queued, signals) = (player.clone(), queued.clone(), signals.clone());
+            move |file: &Path| {
+                let Some(player) = player.upgrade() else {
+                    return;
+                };
+                match player.enqueue_path(file.to_path_buf(), None) {
+                    Ok(id) => {
+                        queued.locked().insert(file.to_path_buf(), id);
+                    }
+                    Err(err) => {
+                        let message = format!("skipping {}: {err}", file.display());
+                        let _ = signals.send(Signal::Event(PlayerEvent::Warning(message)));
+                    }
+                }
+            }
+        };
+        let gone = move |file: &Path| {
+            let Some(id) = queued.locked().remove(file) else {
+                return;
+            };
+            if player
+                .upgrade()
+                .is_some_and(|player| matches!(player.remove(id), Ok(true)))
+            {
+                let event = PlayerEvent::WatchedFileDeleted(file.to_path_buf());
+                let _ = signals.send(Signal::Event(event));
+            }
+        };
+        let watch = FolderWatch::spawn(path.into(), options, found, gone)?;
+        *self.folder_watch.locked() = Some(watch);
+        Ok(())
+    }
+
+    /// Stops watching the folder [`AudioPlayer::watch_folder`] watches. What
+    /// it queued stays queued.
+    pub fn unwatch_folder(&self) {
+        drop(self.folder_watch.locked().take());
+    }
+
+    /// The folder [`AudioPlayer::watch_folder`] watches, if any.
+    pub fn watched_folder(&self) -> Option<PathBuf> {
+        self.folder_watch
+            .locked()
+            .as_ref()
+            .map(|watch| watch.path().to_path_buf())
+    }
+
+    /// Adds `source`, such as a [`SineWave`] or [`PinkNoise`], to the end of
+    /// the queue as a track of its own, called `name` in
+    /// [`AudioPlayer::queue`], and played through the same EQ and effects
+    /// as a file. Returns the new entry's id. A copy is started for each
+    /// time it plays, and for seeks it can't make itself.
+    ///
+    /// [`SineWave`]: crate::SineWave
+    /// [`PinkNoise`]: crate::PinkNoise
+    pub fn play_source<S>(&self, name: &str, source: S) -> Result<u64, PlayerError>
+    where
+        S: Source<Item = f32> + Clone + Send + 'static,
+    {
+        let origin = Origin::Generated(Replayable::new(source));
+        let decoder = open_decoder(Path::new(name), &origin, DecoderBackend::Auto)?;
+        let duration = decoder.total_duration();
+        let rate = decoder.sample_rate();
+        let track = self.new_track(
+            name.into(),
+            origin,
+            duration,
+            rate,
+            TrackMetadata::default(),
+        );
+        self.push_track(track, decoder, None)
+    }
+
+    fn enqueue_stream(&self, url: &str) -> Result<u64, PlayerError> {
+        let prefetch = self.prefetch.load(Ordering::Relaxed);
+        let download = Download::open(url, prefetch, self.events.signals())?;
+        let origin = Origin::Http(download.clone());
+        let path = PathBuf::from(url);
+        let decoder = open_decoder(&path, &origin, self.builder.decoding.backend())?;
+        let duration = download.decoded_duration(decoder.total_duration());
+        let metadata = TrackMetadata {
+            title: download.name(),
+            ..TrackMetadata::default()
+        };
+        let track = self.new_track(path, origin, duration, decoder.sample_rate(), metadata);
+        self.push_track(track, decoder, None)
+    }
+
+    fn new_track(
+        &self,
+        path: PathBuf,
+        origin: Origin,
+        duration: Option<Duration>,
+        sample_rate: u32,
+        metadata: TrackMetadata,
+    ) -> Track {
+        Track {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            path,
+            duration,
+            sample_rate,
+            chapters: metadata.chapters.clone().into(),
+            metadata: Arc::new(metadata),
+            origin,
+            lead: Duration::ZERO,
+            cue: None,
+            range: None,
+            cache: None,
+        }
+    }
+
+    /// Adds `track` to the queue, at `at` or last, and returns its id.
+    fn push_track(
+        &self,
+        track: Track,
+        decoder: DecodedSource,
+        at: Option<usize>,
+    ) -> Result<u64, PlayerError> {
+        let sink = self.sink.locked();
+        let stopped = self.is_stopped.load(Ordering::Relaxed);
+        let id = track.id;
+        let mut opened = None;
+        if !stopped && sink.empty() {
+            // Nothing is left playing, so this track starts a new run.
+            let source = self.build_track(decoder, &track);
+            sink.append(self.chain().build_output(Playlist::new(
+                track.id,
+                source,
+                track.duration,
+                Duration::ZERO,
+                self.playlist.clone(),
+            )));
+            self.clock.set(Duration::ZERO);
+        } else {
+            self.shuffle.locked().insert(track.id);
+            // Kept in case it plays next; otherwise it is dropped and opened
+            // again once its turn comes.
+            opened = Some((track.id, decoder));
+        }
+        if self.loudness_target().is_some() {
+            self.request_scan(&track);
+        }
+        {
+            let mut tracks = self.tracks.locked();
+            match at {
+                Some(at) => {
+                    let at = at.min(tracks.len());
+                    tracks.insert(at, track);
+                }
+                None => tracks.push(track),
+            }
+        }
+        self.regroup_albums();
+        if !stopped {
+            let current = self.playlist.current();
+            self.builder.line_up(
+                &self.tracks,
+                &self.playlist,
+                &self.shuffle,
+                current,
+                opened,
+                false,
+            );
+            if at.is_some() {
+                self.playlist.clear_wrap();
+                self.builder
+                    .prepare_repeat(&self.tracks, &self.playlist, &self.shuffle, current);
+            }
+        }
+        self.events.emit(PlayerEvent::QueueChanged);
+        Ok(id)
+    }
+
+    /// Normalizes every track to `target` LUFS, or turns normalization off
+    /// with `None`.
+    ///
+    /// Tracks are measured in the background on first use; until then they
+    /// play at their own level. The gain is capped so peaks stay below full
+    /// scale, and applies before the EQ, so its preamp and headroom work on
+    /// top of it unchanged.
+    pub fn set_loudness_target(&self, target: Option<f32>) {
+        self.builder.loudness.set_target(target);
+        if target.is_some() {
+            let tracks = self.tracks.locked().clone();
+            for track in &tracks {
+                self.request_scan(track);
+            }
+        }
+        self.regroup_albums();
+    }
+
+    pub fn loudness_target(&self) -> Option<f32> {
+        self.builder.loudness.target()
+    }
+
+    /// Measured loudness of the current track, once its scan has finished.
+    pub fn track_loudness(&self) -> Option<TrackLoudness> {
+        self.builder.loudness.measured(self.playlist.current())
+    }
+
+    /// Normalizes entry `id` by `loudness`, such as a report from
+    /// [`analyze`](crate::analyze) turned [`Into`] one, instead of scanning
+    /// it. Returns `false` if no entry has that id.
+    pub fn set_track_loudness(&self, id: u64, loudness: impl Into<TrackLoudness>) -> bool {
+        if !self.tracks.locked().iter().any(|track| track.id == id) {
+            return false;
+        }
+        self.builder
+            .loudness
+            .set_measured(id, Some(loudness.into()));
+        true
+    }
+
+    /// Gain in dB that normalization applies to the current track.
+    pub fn normalization_gain_db(&self) -> f32 {
+        self.builder.loudness.gain_db(self.playlist.current())
+    }
+
+    /// Plays files by their ReplayGain tags, as a fixed gain set when each
+    /// is opened: by track or by album gain, each falling back to the other,
+    /// plus [`AudioPlayer::set_replaygain_preamp`] but never so much that the
+    /// tagged peak goes over full scale. A file without either gets
+    /// [`AudioPlayer::set_replaygain_default`] instead, unless loudness
+    /// normalization is on, which then measures it as usual.
+    ///
+    /// Tracks already queued are opened again, and so is the one playing,
+    /// where it is, if its gain changes.
+    pub fn set_replaygain(&self, mode: ReplayGainMode) {
+        self.builder.replay_gain.set_mode(mode);
+        self.reopen_for_replaygain();
+    }
+
+    pub fn replaygain(&self) -> ReplayGainMode {
+        self.builder.replay_gain.mode()
+    }
+
+    /// Gain in dB added to the tagged one, 0 by default.
+    pub fn set_replaygain_preamp(&self, preamp_db: f32) {
+        self.builder.replay_gain.set_preamp_db(preamp_db);
+        self.reopen_for_replaygain();
+    }
+
+    pub fn replaygain_preamp(&self) -> f32 {
+        self.builder.replay_gain.preamp_db()
+    }
+
+    /// Gain in dB for files without ReplayGain tags, 0 by default.
+    pub fn set_replaygain_default(&self, gain_db: f32) {
+        self.builder.replay_gain.set_default_gain_db(gain_db);
+        self.reopen_for_replaygain();
+    }
+
+    pub fn replaygain_default(&self) -> f32 {
+        self.builder.replay_gain.default_gain_db()
+    }
+
+    /// Gain in dB that ReplayGain applies to the current track, or `None`
+    /// if it applies none; also sent as [`PlayerEvent::ReplayGainApplied`]
+    /// when a track starts.
+    pub fn replaygain_db(&self) -> Option<f32> {
+        self.builder.replay_gain.applied(self.playlist.current())
+    }
+
+    /// Opens the queued tracks again with the ReplayGain settings now in
+    /// effect, and the current one too if its gain changes and its file can
+    /// be read again. In album playback, where gains glide, they are moved
+    /// over instead.
+    fn reopen_for_replaygain(&self) {
+        if self.builder.albums.is_enabled() {
+            self.regroup_albums();
+            return;
+        }
+        let sink = self.sink.locked();
+        if self.is_stopped.load(Ordering::Relaxed) || sink.empty() {
+            return;
+        }
+        let Some((index, track)) = self.current_entry() else {
+            return;
+        };
+        let normalizing = self.loudness_target().is_some();
+        let gain_db = self
+            .builder
+            .replay_gain
+            .gain_db(&track.metadata.replay_gain, normalizing);
+        if !matches!(track.origin, Origin::File)
+            || gain_db == self.builder.replay_gain.applied(track.id)
+        {
+            self.requeue(&sink);
+            return;
+        }
+        let position = self.cue_start() + self.get_playback_position();
+        // A track that fails to open again is reported as an error.
+        let _ = self.rebuild_at(&sink, index, position);
+    }
+
+    /// Plays tracks next to each other in the queue off the same album at
+    /// one gain, so a piece running from one into the next doesn't step or
+    /// breathe in level there; the crossing stays gapless.
+    ///
+    /// The gain is the album ReplayGain where every track of the run has
+    /// one and ReplayGain is on. Otherwise, with ReplayGain or normalization
+    /// on, the run is measured as a whole, in one pass in the background,
+    /// and played at the normalization target, or else at the ReplayGain
+    /// reference of -18 LUFS plus its preamp; until then each track plays
+    /// at its own gain. Runs only form with shuffle off. Every change of
+    /// gain, into or out of a run or as a measurement comes in, glides over
+    /// 200 ms, from where the track before left off.
+    ///
+    /// The current track is opened again, where it is, to move it over.
+    pub fn set_album_mode(&self, enabled: bool) {
+        if self.builder.albums.is_enabled() == enabled {
+            return;
+        }
+        self.builder.albums.set_enabled(enabled);
+        self.regroup_albums();
+        let sink = self.sink.locked();
+        if self.is_stopped.load(Ordering::Relaxed) || sink.empty() {
+            return;
+        }
+        let Some((index, track)) = self.current_entry() else {
+            return;
+        };
+        if !matches!(track.origin, Origin::File) {
+            self.requeue(&sink);
+            return;
+        }
+        let position = self.cue_start() + self.get_playback_position();
+        // A track that fails to open again is reported as an error.
+        let _ = self.rebuild_at(&sink, index, position);
+    }
+
+    pub fn album_mode(&self) -> bool {
+        self.builder.albums.is_enabled()
+    }
+
+    /// The gain entry `id` plays at, from ReplayGain, normalization or its
+    /// album run, and which of those it comes from. `None` if no entry has
+    /// that id.
+    pub fn track_gain(&self, id: u64) -> Option<TrackGain> {
+        let track = self
+            .tracks
+            .locked()
+            .iter()
+            .find(|track| track.id == id)
+            .cloned()?;
+        if self.builder.albums.is_enabled() {
+            return Some(
+                self.builder
+                    .albums
+                    .gain(id)
+                    .unwrap_or_else(|| self.builder.album_gain(&track)),
+            );
+        }
+        let normalizing = self.loudness_target().is_some();
+        Some(match self.builder.replay_gain.applied_gain(id) {
+            Some(gain) => gain,
+            None if normalizing => TrackGain {
+                mode: GainMode::Normalized,
+                gain_db: self.builder.loudness.gain_db(id),
+            },
+            None => TrackGain::OFF,
+        })
+    }
+
+    /// Groups the queue into album runs afresh, measuring any new one that
+    /// needs it, and moves every track over to its gain.
+    fn regroup_albums(&self) {
+        let tracks = self.tracks.locked().clone();
+        for run in self.builder.albums.regroup(&tracks) {
+            if self.builder.measures(&run) && run.start_scan() {
+                let _ = self.album_scans.send(run);
+            }
+        }
+        self.builder.albums.retain(&tracks);
+        self.builder.relevel(&self.tracks);
+    }
+
+    fn request_scan(&self, track: &Track) {
+        // Streams would have to be read twice to measure them ahead of time.
+        if !matches!(track.origin, Origin::File) {
+            return;
+        }
+        if self.builder.loudness.start_scan(track.id) {
+            let _ = self.scans.send((track.id, track.path.clone()));
+        }
+    }
+
+    /// Decodes files queued from now on whole into memory, in the
+    /// background, as long as they fit in `bytes` alongside those already
+    /// held; 0, the default, turns this off. A cached track seeks to the
+    /// exact frame without going back to the file. It starts playing from
+    /// the file straight away and moves over once its cache is filled.
+    ///
+    /// Streams, standard input and files of unknown length aren't cached.
+    /// Memory is given back once a track has left the queue and stopped
+    /// playing.
+    pub fn set_cache_budget(&self, bytes: usize) {
+        self.cache.set_budget(bytes);
+    }
+
+    pub fn cache_budget(&self) -> usize {
+        self.cache.budget()
+    }
+
+    /// Bytes taken up by decoded tracks right now.
+    pub fn cache_usage(&self) -> usize {
+        self.cache.used()
+    }
+
+    /// Whether the current track seeks from memory yet.
+    pub fn cache_status(&self) -> CacheStatus {
+        self.current_entry()
+            .and_then(|(_, track)| track.cache)
+            .map_or(CacheStatus::Uncached, |cache| cache.status())
+    }
+
+    /// Name of the output device playing right now. Playback follows the
+    /// system default device, or the one the engine was opened on, and
+    /// moves on by itself when a device goes away.
+    pub fn output_device(&self) -> Option<String> {
+        self.engine.output_device()
+    }
+
+    /// The format, buffer size included, the output device plays at; see
+    /// [`AudioEngine::with_output_config`].
+    pub fn stream_config(&self) -> Option<StreamConfig> {
+        self.engine.stream_config()
+    }
+
+    /// Names a device to switch to when the default one can't be opened.
+    /// Every player on the same [`AudioEngine`] shares it.
+    pub fn set_fallback_device(&self, name: Option<String>) {
+        self.engine.set_fallback_device(name);
+    }
+
+    pub fn fallback_device(&self) -> Option<String> {
+        self.engine.fallback_device()
+    }
+
+    /// Plays a copy of what the engine plays on the device named `device`
+    /// too; see [`AudioEngine::add_secondary_output`]. Every player on the
+    /// same engine shares it.
+    pub fn add_secondary_output(&self, device: &str) -> Result<(), PlayerError> {
+        self.engine.add_secondary_output(device)
+    }
+
+    pub fn remove_secondary_output(&self) {
+        self.engine.remove_secondary_output();
+    }
+
+    pub fn secondary_output(&self) -> Option<String> {
+        self.engine.secondary_output()
+    }
+
+    /// The engine this player is mixed into, for creating more players on
+    /// the same device.
+    pub fn engine(&self) -> &AudioEngine {
+        &self.engine
+    }
+
+    /// Sets what happens when a track ends. A change applies from the next
+    /// track boundary; repeated tracks follow on without a gap.
+    pub fn set_repeat(&self, repeat: RepeatMode) {
+        self.playlist.set_repeat(repeat);
+        self.builder.prepare_repeat(
+            &self.tracks,
+            &self.playlist,
+            &self.shuffle,
+            self.playlist.current(),
+        );
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.playlist.repeat()
+    }
+
+    /// Overlaps consecutive tracks by `crossfade` with an equal-power fade.
+    /// Zero, the default, plays them back to back.
+    pub fn set_crossfade(&self, crossfade: Duration) {
+        self.playlist.set_crossfade(crossfade);
+    }
+
+    pub fn crossfade(&self) -> Duration {
+        self.playlist.crossfade()
+    }
+
+    /// Duration of the current track. Formats whose decoder can't tell are
+    /// probed when queued; `None` means even that failed.
+    pub fn duration(&self) -> Option<Duration> {
+        let (_, track) = self.current_entry()?;
+        match self.clock.cue_track() {
+            Some((cue, index)) => {
+                let (start, end) = cue.bounds(index);
+                end.or(track.duration).map(|end| end.saturating_sub(start))
+            }
+            None => track.duration,
+        }
+    }
+
+    /// [`AudioPlayer::duration`] in frames of the current track.
+    pub fn exact_duration(&self) -> Option<Position> {
+        let rate = self.track_sample_rate()?;
+        self.duration()
+            .map(|duration| Position::from_duration(duration, rate))
+    }
+
+    /// The sample rate of the current track's file, which the positions in
+    /// it, such as [`AudioPlayer::exact_position`], count frames at. Convert
+    /// a position from another track with [`Position::at_rate`].
+    pub fn track_sample_rate(&self) -> Option<u32> {
+        self.current_entry().map(|(_, track)| track.sample_rate)
+    }
+
+    /// The `StreamTitle` of what a live stream, such as internet radio, is
+    /// playing now, if it sends one.
+    pub fn stream_title(&self) -> Option<String> {
+        match self.current_entry()?.1.origin {
+            Origin::Http(download) => download.title(),
+            _ => None,
+        }
+    }
+
+    pub fn current_track(&self) -> Option<PathBuf> {
+        self.current_entry().map(|(_, track)| track.path)
+    }
+
+    /// The [`QueueItem::id`] of the current track.
+    pub fn current_id(&self) -> Option<u64> {
+        self.current_entry().map(|(_, track)| track.id)
+    }
+
+    /// Tags and cover art of the current track, read when it was queued. In
+    /// a file split by a cue sheet, the sheet's title, performer and number
+    /// for the track take precedence.
+    pub fn metadata(&self) -> Option<TrackMetadata> {
+        let (_, track) = self.current_entry()?;
+        Some(TrackMetadata::clone(&self.shown_metadata(&track).0))
+    }
+
+    /// The current track at a glance: its queue entry, tags, duration and
+    /// position, with the EQ and volume settings. Cheap enough to ask for
+    /// every frame of a display: nothing is read from the file, and cover
+    /// art is shared rather than copied. [`PlayerEvent::NowPlayingChanged`]
+    /// tells when to ask again.
+    pub fn now_playing(&self) -> Option<NowPlaying> {
+        let (_, track) = self.current_entry()?;
+        let (metadata, duration) = self.shown_metadata(&track);
+        let position = self.get_playback_position();
+        Some(NowPlaying {
+            item: QueueItem::from(&track),
+            stream_title: match &track.origin {
+                Origin::Http(download) => download.title(),
+                _ => None,
+            },
+            duration,
+            position: duration.map_or(position, |duration| position.min(duration)),
+            metadata,
+            eq_enabled: self.eq.is_enabled(),
+            volume_db: self.volume_db(),
+            muted: self.is_muted(),
+        })
+    }
+
+    /// [`AudioPlayer::metadata`] and [`AudioPlayer::duration`] of `track`,
+    /// the first kept from the last call while the same cue track plays.
+    fn shown_metadata(&self, track: &Track) -> (Arc<TrackMetadata>, Option<Duration>) {
+        let Some((cue, index)) = self.clock.cue_track() else {
+            return (track.metadata.clone(), track.duration);
+        };
+        let mut cached = self.cue_metadata.locked();
+        if let Some((id, at, metadata)) = cached.as_ref() {
+            if (*id, *at) == (track.id, index) {
+                return (metadata.clone(), metadata.duration);
+            }
+        }
+        let mut metadata = TrackMetadata::clone(&track.metadata);
+        let cue_track = &cue.tracks[index];
+        metadata.title = cue_track.title.clone();
+        metadata.artist = cue_track
+            .performer
+            .clone()
+            .or_else(|| cue.performer.clone())
+            .or(metadata.artist);
+        metadata.album = cue.title.clone().or(metadata.album);
+        metadata.track_number = Some(cue_track.number);
+        let (start, end) = cue.bounds(index);
+        metadata.duration = end.or(track.duration).map(|end| end.saturating_sub(start));
+        let metadata = Arc::new(metadata);
+        *cached = Some((track.id, index, metadata.clone()));
+        (metadata.clone(), metadata.duration)
+    }
+
+    /// The cue sheet splitting the current file, if it was queued through
+    /// [`AudioPlayer::enqueue_cue`].
+    pub fn cue_sheet(&self) -> Option<CueSheet> {
+        self.clock.cue_track().map(|(cue, _)| CueSheet::clone(&cue))
+    }
+
+    /// The track of [`AudioPlayer::cue_sheet`] playing now.
+    pub fn cue_track(&self) -> Option<CueTrack> {
+        self.clock
+            .cue_track()
+            .map(|(cue, index)| cue.tracks[index].clone())
+    }
+
+    /// Skips to the next queued track, or the next track of the current cue
+    /// sheet. Returns `false` if this is the last one.
+    pub fn next(&self) -> Result<bool, PlayerError> {
+        if let Some((cue, index)) = self.clock.cue_track() {
+            if let Some(next) = cue.tracks.get(index + 1) {
+                self.jump_in_cue(next.start)?;
+                return Ok(true);
+            }
+        }
+        let sink = self.sink.locked();
+        let index = self.current_entry().map_or(0, |(index, _)| index);
+        let next = {
+            let shuffle = self.shuffle.locked();
+            let tracks = self.tracks.locked();
+            if shuffle.is_enabled() {
+                let next = shuffle.upcoming().first();
+                next.and_then(|&id| tracks.iter().position(|track| track.id == id))
+            } else {
+                (index + 1 < tracks.len()).then_some(index + 1)
+            }
+        };
+        let Some(next) = next else {
+            return Ok(false);
+        };
+        self.rebuild_at(&sink, next, Duration::ZERO)?;
+        Ok(true)
+    }
+
+    /// Goes back one track, or restarts the first one. Returns `false` in the latter case.
+    ///
+    /// While shuffled this follows the order tracks actually played in.
+    /// Within a cue sheet it goes back one of the sheet's tracks, and into the
+    /// last track of the sheet before.
+    pub fn previous(&self) -> Result<bool, PlayerError> {
+        let index = self.current_entry().map_or(0, |(index, _)| index);
+        let (shuffled, has_previous) = {
+            let shuffle = self.shuffle.locked();
+            match shuffle.is_enabled() {
+                true => (true, shuffle.has_previous()),
+                false => (false, index > 0),
+            }
+        };
+        if let Some((cue, cue_index)) = self.clock.cue_track() {
+            if cue_index > 0 || !has_previous {
+                self.jump_in_cue(cue.tracks[cue_index.saturating_sub(1)].start)?;
+                return Ok(cue_index > 0);
+            }
+        }
+
+        let sink = self.sink.locked();
+        let previous = match shuffled {
+            true => self.shuffle.locked().back().and_then(|id| {
+                let tracks = self.tracks.locked();
+                tracks.iter().position(|track| track.id == id)
+            }),
+            false => index.checked_sub(1),
+        };
+        let Some(previous) = previous else {
+            self.rebuild_at(&sink, index, Duration::ZERO)?;
+            return Ok(false);
+        };
+        let start = self
+            .tracks
+            .locked()
+            .get(previous)
+            .and_then(|track| track.cue.as_ref()?.tracks.last())
+            .map_or(Duration::ZERO, |track| track.start);
+        self.rebuild_at(&sink, previous, start)?;
+        Ok(true)
+    }
+
+    /// Replaces the chapters of the current track, e.g. with a list of
+    /// its own for a mix without any in its tags, and sorts them by start.
+    /// Starts are times in the file.
+    pub fn set_chapters(&self, mut chapters: Vec<Chapter>) {
+        chapters.sort_by_key(|chapter| chapter.start);
+        let current = self.playlist.current();
+        let mut tracks = self.tracks.locked();
+        if let Some(track) = tracks.iter_mut().find(|track| track.id == current) {
+            track.chapters = chapters.into();
+        }
+    }
+
+    /// The current track's chapters, read from its tags unless set with
+    /// [`AudioPlayer::set_chapters`].
+    pub fn chapters(&self) -> Vec<Chapter> {
+        self.current_entry()
+            .map_or_else(Vec::new, |(_, track)| track.chapters.to_vec())
+    }
+
+    /// Index into [`AudioPlayer::chapters`] of the chapter playing now, or
+    /// where a seek is headed. `None` without chapters, or before the first.
+    pub fn current_chapter(&self) -> Option<usize> {
+        let (_, track) = self.current_entry()?;
+        chapter_at(&track.chapters, self.file_position())
+    }
+
+    /// Seeks to the start of the next chapter. Returns `false`, doing
+    /// nothing, in the last one.
+    pub fn next_chapter(&self) -> Result<bool, PlayerError> {
+        let Some((_, track)) = self.current_entry() else {
+            return Ok(false);
+        };
+        let position = self.file_position();
+        match track
+            .chapters
+            .iter()
+            .find(|chapter| chapter.start > position)
+        {
+            Some(next) => self.seek_to_chapter(next).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Seeks back to the start of the chapter playing, or to the previous
+    /// chapter within the first [`CHAPTER_RESTART`] of this one, as a CD
+    /// player does. Returns `false` when it only restarts the first chapter,
+    /// or when there are no chapters.
+    ///
+    /// [`CHAPTER_RESTART`]: crate::CHAPTER_RESTART
+    pub fn previous_chapter(&self) -> Result<bool, PlayerError> {
+        let Some((_, track)) = self.current_entry() else {
+            return Ok(false);
+        };
+        let position = self.file_position();
+        let Some(index) = chapter_at(&track.chapters, position) else {
+            return Ok(false);
+        };
+        let into = position.saturating_sub(track.chapters[index].start);
+        let target = match (into > CHAPTER_RESTART, index.checked_sub(1)) {
+            (false, Some(previous)) => previous,
+            _ => index,
+        };
+        self.seek_to_chapter(&track.chapters[target])?;
+        Ok(target != index || into > CHAPTER_RESTART)
+    }
+
+    fn seek_to_chapter(&self, chapter: &Chapter) -> Result<(), PlayerError> {
+        self.seek(chapter.start.saturating_sub(self.cue_start()))
+    }
+
+    /// The position in the current file, cue sheets aside, counting a seek
+    /// that hasn't landed yet as done.
+    fn file_position(&self) -> Duration {
+        match self.seeker.target() {
+            Some(target) => target + self.cue_start(),
+            None => self.clock.file_position(),
+        }
+    }
+
+    /// Plays the queue in a random order, without repeating a track until
+    /// all of them have played; under [`RepeatMode::All`] each cycle gets a
+    /// new order. Turning it on again draws a new order, leaving out the
+    /// track playing now. Turning it off carries on in queue order from the
+    /// current track.
+    pub fn set_shuffle(&self, enabled: bool) {
+        let sink = self.sink.locked();
+        let current = self.current_entry().map(|(_, track)| track.id);
+        {
+            let mut shuffle = self.shuffle.locked();
+            let ids = self
+                .tracks
+                .locked()
+                .iter()
+                .map(|track| track.id)
+                .collect::<Vec<_>>();
+            shuffle.set_enabled(enabled, &ids, current);
+        }
+        self.builder.albums.set_shuffled(enabled);
+        self.regroup_albums();
+        self.requeue(&sink);
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.shuffle.locked().is_enabled()
+    }
+
+    /// The current track, then the rest in the order they will play, with
+    /// shuffle taken into account: what a UI shows as "up next".
+    pub fn queue_order(&self) -> Vec<PathBuf> {
+        let Some((_, current)) = self.current_entry() else {
+            return Vec::new();
+        };
+        self.play_order_from(current.id)
+            .into_iter()
+            .map(|track| track.path)
+            .collect()
+    }
+
+    /// Everything queued, in queue order. Shuffle doesn't change this; see
+    /// [`AudioPlayer::queue_order`] for the order tracks will play in.
+    pub fn queue(&self) -> Vec<QueueItem> {
+        self.tracks.locked().iter().map(QueueItem::from).collect()
+    }
+
+    /// Takes entry `id` out of the queue. If it is playing, the track that
+    /// would have followed it plays from the start instead, or playback
+    /// stops if there is none. Returns `false` if no entry has that id.
+    pub fn remove(&self, id: u64) -> Result<bool, PlayerError> {
+        let sink = self.sink.locked();
+        if !self.tracks.locked().iter().any(|track| track.id == id) {
+            return Ok(false);
+        }
+        let current = self
+            .current_entry()
+            .is_some_and(|(_, track)| track.id == id);
+        let next = current
+            .then(|| self.play_order_from(id).get(1).map(|track| track.id))
+            .flatten();
+        self.shuffle.locked().remove(id);
+        self.tracks.locked().retain(|track| track.id != id);
+        self.regroup_albums();
+
+        let stopped = self.is_stopped.load(Ordering::Relaxed) || sink.empty();
+        let result = match (current, next) {
+            (false, _) => {
+                self.requeue(&sink);
+                Ok(())
+            }
+            (true, Some(next)) if stopped => {
+                self.playlist.set_current(next);
+                Ok(())
+            }
+            (true, Some(next)) => {
+                let index = self
+                    .tracks
+                    .locked()
+                    .iter()
+                    .position(|track| track.id == next);
+                self.rebuild_at(&sink, index.unwrap_or(0), Duration::ZERO)
+            }
+            (true, None) => {
+                drop(sink);
+                self.stop();
+                Ok(())
+            }
+        };
+        self.events.emit(PlayerEvent::QueueChanged);
+        result.map(|()| true)
+    }
+
+    /// Moves entry `id` to `index` in [`AudioPlayer::queue`], or last if
+    /// that is past the end. Whatever is lined up to play next follows the
+    /// new order. Returns `false` if no entry has that id.
+    pub fn move_item(&self, id: u64, index: usize) -> bool {
+        let sink = self.sink.locked();
+        {
+            let mut tracks = self.tracks.locked();
+            let Some(from) = tracks.iter().position(|track| track.id == id) else {
+                return false;
+            };
+            let track = tracks.remove(from);
+            let index = index.min(tracks.len());
+            tracks.insert(index, track);
+        }
+        self.regroup_albums();
+        self.requeue(&sink);
+        self.events.emit(PlayerEvent::QueueChanged);
+        true
+    }
+
+    /// Jumps to entry `id`, playing it from the start, and carries on from
+    /// there. Returns `false` if no entry has that id.
+    pub fn play_item(&self, id: u64) -> Result<bool, PlayerError> {
+        let sink = self.sink.locked();
+        let index = self.tracks.locked().iter().position(|track| track.id == id);
+        let Some(index) = index else {
+            return Ok(false);
+        };
+        self.rebuild_at(&sink, index, Duration::ZERO)?;
+        Ok(true)
+    }
+
+    /// Lines up the track after the current one afresh, once the queue or
+    /// its order has changed, so none that was already decoding plays out
+    /// of turn.
+    fn requeue(&self, sink: &Sink) {
+        if self.is_stopped.load(Ordering::Relaxed) || sink.empty() {
+            return;
+        }
+        let Some((_, current)) = self.current_entry() else {
+            return;
+        };
+        self.builder.line_up(
+            &self.tracks,
+            &self.playlist,
+            &self.shuffle,
+            current.id,
+            None,
+            true,
+        );
+        self.playlist.clear_wrap();
+        self.builder
+            .prepare_repeat(&self.tracks, &self.playlist, &self.shuffle, current.id);
+    }
+
+    fn play_order_from(&self, id: u64) -> Vec<Track> {
+        play_order_from(&self.shuffle, &self.tracks, id)
+    }
+
+    /// Moves to another track of the current cue sheet, starting at `start`
+    /// in the file.
+    fn jump_in_cue(&self, start: Duration) -> Result<(), PlayerError> {
+        let path = self.current_track();
+        let signals = self.events.signals();
+        self.seek_file(start, Some(Duration::ZERO), Duration::ZERO, move || {
+            if let Some(path) = path {
+                let _ = signals.send(Signal::Event(PlayerEvent::TrackStarted(path)));
+            }
+        })
+    }
+
+    /// Where the playing cue track starts in its file; zero without a sheet.
+    fn cue_start(&self) -> Duration {
+        self.clock
+            .cue_track()
+            .map_or(Duration::ZERO, |(cue, index)| cue.bounds(index).0)
+    }
+
+    fn current_entry(&self) -> Option<(usize, Track)> {
+        current_entry(&self.playlist, &self.tracks)
+    }
+
+    /// The point in the track playing now, in source time: how far into
+    /// the file, or its cue track, whatever the speed and tempo; see
+    /// [`AudioPlayer::get_playback_position`].
+    pub fn position(&self) -> Duration {
+        self.get_playback_position()
+    }
+
+    /// How long the current track has been playing by the wall clock,
+    /// pauses left out: half its [`AudioPlayer::position`] after playing
+    /// through at a speed of 2.0, say. Seeking moves the position and not
+    /// this.
+    pub fn elapsed_wall_time(&self) -> Duration {
+        self.clock.elapsed_wall_time()
+    }
+
+    /// Where playback is, or where it is headed while a seek hasn't landed,
+    /// in source time, and so unaffected by the speed and tempo. Never
+    /// waits for a lock, so it can be asked as often as needed.
+    pub fn get_playback_position(&self) -> Duration {
+        self.seeker
+            .target()
+            .unwrap_or_else(|| self.clock.position())
+    }
+
+    /// [`AudioPlayer::get_playback_position`] in frames of the current
+    /// track, `None` with nothing queued.
+    pub fn exact_position(&self) -> Option<Position> {
+        let rate = self.track_sample_rate()?;
+        Some(Position::from_duration(self.get_playback_position(), rate))
+    }
+
+    /// Whether a seek has been asked for and hasn't landed yet.
+    pub fn is_seeking(&self) -> bool {
+        self.seeker.target().is_some()
+    }
+
+    /// Returns a receiver for playback events. Every subscriber gets every
+    /// event from this point on; dropping the receiver unsubscribes.
+    ///
+    /// Events are sent from a thread of the player's own, never from the
+    /// audio thread.
+    pub fn subscribe(&self) -> mpsc::Receiver<PlayerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Sets how often [`PlayerEvent::Progress`] is sent while playing.
+    pub fn set_progress_interval(&self, interval: Duration) {
+        self.events.set_progress_interval(interval);
+    }
+
+    pub fn progress_interval(&self) -> Duration {
+        self.events.progress_interval()
+    }
+
+    /// Whether the last queued track has played out, or playback was stopped.
+    ///
+    /// A paused player is never finished, however close to the end it is.
+    pub fn is_finished(&self) -> bool {
+        if self.is_stopped.load(Ordering::Relaxed) {
+            return true;
+        }
+        // The playlist ends before the output chain has drained.
+        self.playlist.is_finished() && self.sink.locked().empty()
+    }
+
+    /// Blocks until [`AudioPlayer::is_finished`]. Time spent paused doesn't
+    /// count, and seeking or queueing more tracks moves the end accordingly.
+    pub fn wait_until_end(&self) {
+        while !self.is_finished() {
+            if self.playlist.wait_finished(Duration::from_millis(100)) {
+                thread::sleep(END_POLL);
+            }
+        }
+    }
+
+    /// Starts or resumes playback. After [`AudioPlayer::stop`] the track
+    /// restarts from the beginning.
+    pub fn play(&self) -> Result<(), PlayerError> {
+        let sink = self.sink.locked();
+        if self.is_stopped.load(Ordering::Relaxed) {
+            let index = self.current_entry().map_or(0, |(index, _)| index);
+            self.rebuild_at(&sink, index, Duration::ZERO)?;
+        }
+
+        sink.play();
+        self.start.open();
+        self.fade.set_ramp(self.fade_in());
+        self.fade.set_target(1.0);
+        if !self.clock.set_playing(true) {
+            self.events.emit(PlayerEvent::Resumed);
+        }
+        Ok(())
+    }
+
+    /// Starts playing so the first sample is heard at `instant`, by the
+    /// device's reckoning of its latency, with no fade in. The current
+    /// track is decoded ahead meanwhile; an `instant` already past, or
+    /// reached before that's done, starts as soon as it can.
+    /// [`PlayerEvent::ScheduledStart`] tells how close it came. Playing
+    /// already, it holds where it is until then.
+    ///
+    /// [`AudioPlayer::cancel_play_at`] or [`AudioPlayer::pause`] before
+    /// then leaves it paused, and [`AudioPlayer::play`] starts it now. To
+    /// start several players together, see [`AudioEngine::play_all_at`].
+    pub fn play_at(&self, instant: Instant) -> Result<(), PlayerError> {
+        self.prepare_start(instant)?;
+        self.start
+            .release(self.start.mix().frame_at(instant), instant);
+        Ok(())
+    }
+
+    /// Leaves a start [`AudioPlayer::play_at`] scheduled paused, if it
+    /// hasn't gone yet. Returns whether it hadn't.
+    pub fn cancel_play_at(&self) -> bool {
+        self.transport().cancel_start()
+    }
+
+    /// Holds playback back and lets it go at full volume, then waits for
+    /// the current track to be decoded ahead or for `deadline`, whichever
+    /// comes first, for a start at a set frame.
+    pub(crate) fn prepare_start(&self, deadline: Instant) -> Result<(), PlayerError> {
+        {
+            let sink = self.sink.locked();
+            self.start.hold();
+            if self.is_stopped.load(Ordering::Relaxed) {
+                let index = self.current_entry().map_or(0, |(index, _)| index);
+                if let Err(err) = self.rebuild_at(&sink, index, Duration::ZERO) {
+                    self.start.open();
+                    return Err(err);
+                }
+            }
+            sink.play();
+            self.fade.set_ramp(Duration::ZERO);
+            self.fade.set_target(1.0);
+        }
+        while !self.builder.buffering.is_ready() && Instant::now() < deadline {
+            thread::sleep(PREROLL_POLL);
+        }
+        Ok(())
+    }
+
+    /// Lets a start [`AudioPlayer::prepare_start`] held back go at mix
+    /// frame `frame`, meant to be heard at `instant`.
+    pub(crate) fn release_start(&self, frame: u64, instant: Instant) {
+        self.start.release(frame, instant);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.clock.is_playing()
+    }
+
+    /// Where playback stands: opening or buffering while it holds for a
+    /// file to be decoded ahead, then playing, paused, or ended once
+    /// stopped or played out. Each change is sent as
+    /// [`PlayerEvent::StateChanged`].
+    pub fn playback_state(&self) -> PlaybackState {
+        playback_state(
+            &self.is_stopped,
+            &self.playlist,
+            &self.sink,
+            &self.clock,
+            &self.builder.buffering,
+        )
+    }
+
+    /// How much of the current track is decoded ahead of playback, or
+    /// `None` if it isn't a file being decoded ahead, like a stream.
+    pub fn buffer_level(&self) -> Option<Duration> {
+        self.builder.buffering.level()
+    }
+
+    /// Sets how low the audio decoded ahead of a file runs before playback
+    /// holds to buffer, the [`PlaybackState::Buffering`] state, and how far
+    /// it fills before playback goes on; [`DEFAULT_LOW_WATERMARK`] and
+    /// [`DEFAULT_HIGH_WATERMARK`] to begin with. Files already open keep
+    /// the room they had. A local disk keeps up with either, so playback
+    /// never waits on it.
+    ///
+    /// [`DEFAULT_LOW_WATERMARK`]: crate::DEFAULT_LOW_WATERMARK
+    /// [`DEFAULT_HIGH_WATERMARK`]: crate::DEFAULT_HIGH_WATERMARK
+    pub fn set_buffer_watermarks(&self, low: Duration, high: Duration) -> Result<(), PlayerError> {
+        if high.is_zero() || high > MAX_WATERMARK {
+            return Err(PlayerError::InvalidArgument(format!(
+                "high watermark ({high:?}) must be above zero and at most {MAX_WATERMARK:?}"
+            )));
+        }
+        if low > high {
+            return Err(PlayerError::InvalidArgument(format!(
+                "low watermark ({low:?}) must not be above the high one ({high:?})"
+            )));
+        }
+        self.builder.buffering.set_watermarks(low, high);
+        Ok(())
+    }
+
+    /// The low and high watermarks; see [`AudioPlayer::set_buffer_watermarks`].
+    pub fn buffer_watermarks(&self) -> (Duration, Duration) {
+        self.builder.buffering.watermarks()
+    }
+
+    /// Whether playback was stopped, or ran off the end of the queue,
+    /// rather than paused.
+    pub fn is_stopped(&self) -> bool {
+        self.is_stopped.load(Ordering::Relaxed) || self.sink.locked().empty()
+    }
+
+    /// Fades out and pauses. Blocks for the fade-out time; the position keeps
+    /// counting until the audio has actually gone silent.
+    pub fn pause(&self) {
+        self.transport().pause(self.fade_out());
+    }
+
+    /// Sets how long `play` fades in and `pause` fades out. Zero switches instantly.
+    pub fn set_fade_durations(&self, fade_in: Duration, fade_out: Duration) {
+        *self.fade_in.locked() = fade_in;
+        *self.fade_out.locked() = fade_out;
+    }
+
+    pub fn fade_in(&self) -> Duration {
+        *self.fade_in.locked()
+    }
+
+    pub fn fade_out(&self) -> Duration {
+        *self.fade_out.locked()
+    }
+
+    /// Stops playback and drops the queued source. Position goes back to zero.
+    pub fn stop(&self) {
+        self.seeker.cancel();
+        self.transport().stop();
+    }
+
+    /// Pauses or stops, as `action` says, once playback has run for `after`.
+    /// Only time spent playing counts: pausing holds the timer and seeking
+    /// doesn't move it. Replaces any timer already set, and sends
+    /// [`PlayerEvent::SleepTimerFired`] when it goes off.
+    pub fn set_sleep_timer(&self, after: Duration, action: SleepAction) {
+        let transport = self.transport();
+        self.sleep_timer
+            .set(after, action, self.clock.clone(), move |action| {
+                let _ = transport
+                    .signals
+                    .send(Signal::Event(PlayerEvent::SleepTimerFired));
+                match action {
+                    SleepAction::Pause => {
+                        let fade_out = *transport.fade_out.locked();
+                        transport.pause(fade_out);
+                    }
+                    SleepAction::Stop => transport.stop(),
+                    SleepAction::FadeOutThenStop(fade) => {
+                        if transport.fade_out_and_pause(fade) {
+                            transport.stop();
+                        }
+                    }
+                }
+            });
+    }
+
+    pub fn clear_sleep_timer(&self) {
+        self.sleep_timer.clear();
+    }
+
+    /// Playing time left on the sleep timer, or `None` if none is set.
+    pub fn sleep_timer_remaining(&self) -> Option<Duration> {
+        self.sleep_timer.remaining()
+    }
+
+    /// With `strict` on, a file ends where it first fails to decode, as it
+    /// always used to; otherwise playback carries on at the next frame that
+    /// decodes, with silence in place of what was lost. Either way a
+    /// [`PlayerEvent::DecodeError`] says where, and what happened. Applies
+    /// from the next damage found.
+    pub fn set_strict_decoding(&self, strict: bool) {
+        self.builder.decoding.set_strict(strict);
+    }
+
+    pub fn strict_decoding(&self) -> bool {
+        self.builder.decoding.is_strict()
+    }
+
+    /// Which decoder files are opened with, from the next one opened. With
+    /// [`DecoderBackend::Auto`], the default, Symphonia opens whatever it
+    /// recognizes when built in, and rodio the rest.
+    pub fn set_decoder_backend(&self, backend: DecoderBackend) {
+        self.builder.decoding.set_backend(backend);
+    }
+
+    pub fn decoder_backend(&self) -> DecoderBackend {
+        self.builder.decoding.backend()
+    }
+
+    /// How closely tracks at another rate than the output's are resampled
+    /// to it, from the next one opened; [`ResampleQuality::High`] by
+    /// default. Tracks at the output's rate pass untouched.
+    pub fn set_resample_quality(&self, quality: ResampleQuality) {
+        self.builder.resampling.set_quality(quality);
+    }
+
+    pub fn resample_quality(&self) -> ResampleQuality {
+        self.builder.resampling.quality()
+    }
+
+    /// Skips long silences, such as the dead air before a hidden track, with
+    /// a [`PlayerEvent::SilenceSkipped`] for each. See
+    /// [`AudioPlayer::set_silence_threshold`] for what counts as silence.
+    /// Takes effect immediately.
+    pub fn set_skip_silence(&self, skip: bool) {
+        self.builder.silence.set_enabled(skip);
+    }
+
+    pub fn skip_silence(&self) -> bool {
+        self.builder.silence.is_enabled()
+    }
+
+    /// Audio counts as silence once its peak has stayed under `threshold_db`
+    /// dBFS for `window`, which plays as usual before the rest is skipped.
+    /// Defaults to [`DEFAULT_SILENCE_THRESHOLD_DB`](crate::DEFAULT_SILENCE_THRESHOLD_DB)
+    /// and [`DEFAULT_SILENCE_WINDOW`](crate::DEFAULT_SILENCE_WINDOW).
+    pub fn set_silence_threshold(&self, threshold_db: f32, window: Duration) {
+        self.builder.silence.set_threshold(threshold_db, window);
+    }
+
+    pub fn silence_threshold(&self) -> (f32, Duration) {
+        let silence = &self.builder.silence;
+        (silence.threshold_db(), silence.window())
+    }
+
+    /// Trims digital silence off the start and end of tracks, so playback
+    /// starts on the first audible sample and the next track follows on from
+    /// the last one. Off by default, which keeps playback bit-exact.
+    ///
+    /// The start of a file is scanned when it is queued, so that part only
+    /// applies to files queued afterwards; the end applies to tracks not yet
+    /// started. Positions, the duration and seek targets stay in file time:
+    /// a trimmed track starts at the length of its leading silence.
+    pub fn set_trim_silence(&self, trim: bool) {
+        self.builder.silence.set_trimming(trim);
+    }
+
+    pub fn trim_silence(&self) -> bool {
+        self.builder.silence.is_trimming()
+    }
+
+    /// Sets the level, in dBFS, under which the ends of a track count as
+    /// silence for [`AudioPlayer::set_trim_silence`]. Defaults to
+    /// [`DEFAULT_TRIM_THRESHOLD_DB`](crate::DEFAULT_TRIM_THRESHOLD_DB).
+    pub fn set_trim_threshold_db(&self, threshold_db: f32) {
+        self.builder.silence.set_trim_threshold_db(threshold_db);
+    }
+
+    pub fn trim_threshold_db(&self) -> f32 {
+        self.builder.silence.trim_threshold_db()
+    }
+
+    /// Sets how much of a track has to play before [`PlayerEvent::TrackPlayed`]:
+    /// `fraction` of its length, or `cap` if that comes first. Only time
+    /// actually played counts: seeking forward adds nothing and seeking back
+    /// takes nothing off. Starting the track over, by a repeat or a seek to
+    /// its start, starts the count over too. The fraction is clamped to
+    /// `0.0..=1.0`.
+    pub fn set_scrobble_threshold(&self, fraction: f32, cap: Duration) {
+        self.scrobble.set_threshold(fraction.clamp(0.0, 1.0), cap);
+    }
+
+    /// The fraction and cap set by [`AudioPlayer::set_scrobble_threshold`].
+    pub fn scrobble_threshold(&self) -> (f32, Duration) {
+        self.scrobble.threshold()
+    }
+
+    /// Keeps `bookmarks` up to date with where each file was left: on
+    /// pause, seek and stop, and every few seconds while playing. Streams and
+    /// standard input aren't bookmarked. Failing to save sends a
+    /// [`PlayerEvent::Warning`].
+    pub fn set_bookmarks(&self, bookmarks: Bookmarks) {
+        if self.bookmarks.locked().replace(bookmarks).is_some() {
+            return;
+        }
+        let events = self.subscribe();
+        let clock = Arc::downgrade(&self.clock);
+        let bookmarks = self.bookmarks.clone();
+        let signals = self.events.signals();
+        thread::spawn(move || {
+            let mut recorded = Instant::now();
+            // Ends along with the event thread, once the player is gone.
+            for event in events {
+                let due = match event {
+                    PlayerEvent::Progress(_) => recorded.elapsed() >= BOOKMARK_INTERVAL,
+                    PlayerEvent::Paused | PlayerEvent::Seeked(_) => true,
+                    _ => false,
+                };
+                let Some(clock) = clock.upgrade().filter(|_| due) else {
+                    continue;
+                };
+                record_bookmark(&clock, &bookmarks, &signals);
+                recorded = Instant::now();
+            }
+        });
+    }
+
+    pub fn bookmarks(&self) -> Option<Bookmarks> {
+        self.bookmarks.locked().clone()
+    }
+
+    /// Adds each file to `history` once it has played past the scrobble
+    /// threshold, see [`AudioPlayer::set_scrobble_threshold`], keeping how
+    /// long it went on to play, and saves it. Repeats of a track in a row
+    /// add to one entry. Failing to save sends a [`PlayerEvent::Warning`].
+    pub fn set_history(&self, history: History) {
+        if self.history.locked().replace(history).is_some() {
+            return;
+        }
+        let events = self.subscribe();
+        let clock = Arc::downgrade(&self.clock);
+        let history = self.history.clone();
+        let signals = self.events.signals();
+        thread::spawn(move || {
+            // The playthrough the newest entry is of, while it plays on.
+            let mut following = None;
+            // Ends along with the event thread, once the player is gone.
+            for event in events {
+                let Some(clock) = clock.upgrade() else {
+                    continue;
+                };
+                let mut history = history.locked();
+                let Some(history) = history.as_mut() else {
+                    continue;
+                };
+                match event {
+                    PlayerEvent::TrackPlayed { path, played, .. } => {
+                        let track = clock.current_track().filter(|track| {
+                            track.path == path && matches!(track.origin, Origin::File)
+                        });
+                        let Some(track) = track else {
+                            continue;
+                        };
+                        let metadata = &track.metadata;
+                        let (title, artist) = (metadata.title.clone(), metadata.artist.clone());
+                        history.record(path, title, artist, played);
+                        following = Some(clock.playthroughs());
+                        save_history(history, &signals);
+                    }
+                    PlayerEvent::Progress(_) if following == Some(clock.playthroughs()) => {
+                        history.set_played(clock.played());
+                    }
+                    PlayerEvent::Paused if following.is_some() => save_history(history, &signals),
+                    PlayerEvent::TrackStarted(_) if following.is_some() => {
+                        save_history(history, &signals);
+                        following = None;
+                    }
+                    _ => {}
+                }
+            }
+            if let (Some(history), Some(_)) = (history.locked().as_ref(), following) {
+                save_history(history, &signals);
+            }
+        });
+    }
+
+    /// Up to `limit` of the tracks played, newest first; empty without
+    /// [`AudioPlayer::set_history`].
+    pub fn history(&self, limit: usize) -> Vec<HistoryEntry> {
+        self.history
+            .locked()
+            .as_ref()
+            .map_or_else(Vec::new, |history| history.recent(limit))
+    }
+
+    /// Plays the track `index` places back in [`AudioPlayer::history`] once
+    /// more, queued after the current one. Returns `false` if the history
+    /// doesn't go back that far; a file that is gone fails to open.
+    pub fn play_from_history(&self, index: usize) -> Result<bool, PlayerError> {
+        let entry = self
+            .history
+            .locked()
+            .as_ref()
+            .and_then(|history| history.get(index));
+        let Some(entry) = entry else {
+            return Ok(false);
+        };
+        let at = self
+            .current_entry()
+            .map_or(usize::MAX, |(index, _)| index + 1);
+        let id = self.insert_at(at, entry.path)?;
+        self.play_item(id)?;
+        self.play()?;
+        Ok(true)
+    }
+
+    /// Seeks the current file to where its bookmark left it. Returns whether
+    /// there was one.
+    pub fn resume(&self) -> Result<bool, PlayerError> {
+        let Some((_, track)) = self.current_entry() else {
+            return Ok(false);
+        };
+        let position = match track.origin {
+            Origin::File => self
+                .bookmarks
+                .locked()
+                .as_ref()
+                .and_then(|bookmarks| bookmarks.position(&track.path)),
+            _ => None,
+        };
+        let Some(position) = position else {
+            return Ok(false);
+        };
+        let (clock, signals) = (self.clock.clone(), self.events.signals());
+        self.seek_file(position, None, Duration::ZERO, move || {
+            let _ = signals.send(Signal::Event(PlayerEvent::Seeked(clock.position())));
+        })?;
+        Ok(true)
+    }
+
+    /// Where playback is, the queue and the settings a listener changes, as
+    /// [`AudioPlayer::save_state`] keeps them.
+    pub fn state(&self) -> PlayerState {
+        self.snapshot().capture()
+    }
+
+    /// Saves [`AudioPlayer::state`] to `path`, for
+    /// [`AudioPlayer::restore_state`] to pick up after a restart.
+    pub fn save_state(&self, path: impl AsRef<Path>) -> Result<(), PlayerError> {
+        Ok(self.state().save(path)?)
+    }
+
+    /// Replaces the queue and settings with those saved at `path` by
+    /// [`AudioPlayer::save_state`], and seeks to where the saved track was.
+    /// The player is left paused. Files that have gone missing, or that
+    /// otherwise can't be opened, are skipped with a
+    /// [`PlayerEvent::Warning`]; if the saved track is one of them, the
+    /// first one queued is current instead, from the start.
+    pub fn restore_state(&self, path: impl AsRef<Path>) -> Result<(), PlayerError> {
+        let state = PlayerState::load(path)?;
+        self.stop();
+        for item in self.queue() {
+            self.remove(item.id)?;
+        }
+
+        self.set_volume_db(state.volume_db);
+        self.set_muted(state.muted);
+        self.set_eq_settings(state.eq);
+        self.set_eq_enabled(state.eq_enabled);
+        self.set_repeat(state.repeat);
+
+        let mut current = None;
+        for (index, path) in state.queue.iter().enumerate() {
+            let range = state.ranges.get(index).copied().flatten();
+            let queued = match path.to_str().filter(|_| is_url(path)) {
+                Some(url) => self.enqueue_stream(url),
+                None if range.is_some() => self.enqueue_file(path.clone(), None, range, None),
+                None => self.enqueue_path(path.clone(), None),
+            };
+            match queued {
+                Ok(id) if state.current == Some(index) => current = Some((id, true)),
+                Ok(_) => {}
+                Err(err) => self.events.emit(PlayerEvent::Warning(format!(
+                    "skipping {}: {err}",
+                    path.display()
+                ))),
+            }
+        }
+        self.set_shuffle(state.shuffle);
+
+        let first = self.queue().first().map(|item| (item.id, false));
+        let Some((id, resumed)) = current.or(first) else {
+            return Ok(());
+        };
+        self.play_item(id)?;
+        if resumed && !state.position.is_zero() {
+            self.seek(state.position)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the state to `path` every so often while playing, on pause and
+    /// seek, whenever the queue changes, and once more when the player is
+    /// dropped. Failing to save sends a [`PlayerEvent::Warning`].
+    pub fn set_autosave(&self, path: impl Into<PathBuf>) {
+        if self.autosave.locked().replace(path.into()).is_some() {
+            return;
+        }
+        let events = self.subscribe();
+        let snapshot = self.snapshot();
+        let autosave = self.autosave.clone();
+        let signals = self.events.signals();
+        thread::spawn(move || {
+            let mut saved = Instant::now();
+            // Ends along with the event thread, once the player is gone.
+            for event in events {
+                let due = match event {
+                    PlayerEvent::Progress(_) => saved.elapsed() >= AUTOSAVE_INTERVAL,
+                    PlayerEvent::Paused | PlayerEvent::Seeked(_) | PlayerEvent::QueueChanged => {
+                        true
+                    }
+                    _ => false,
+                };
+                if due {
+                    save_autosave(&snapshot, &autosave, &signals);
+                    saved = Instant::now();
+                }
+            }
+        });
+    }
+
+    /// Where [`AudioPlayer::set_autosave`] saves the state, if it was set.
+    pub fn autosave_path(&self) -> Option<PathBuf> {
+        self.autosave.locked().clone()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            tracks: self.tracks.clone(),
+            playlist: self.playlist.clone(),
+            clock: self.clock.clone(),
+            eq: self.eq.clone(),
+            shuffle: self.shuffle.clone(),
+            volume_db: self.volume_db.clone(),
+            muted: self.muted.clone(),
+        }
+    }
+
+    fn chain(&self) -> Chain {
+        Chain {
+            sink: self.sink.clone(),
+            tracks: self.tracks.clone(),
+            playlist: self.playlist.clone(),
+            clock: self.clock.clone(),
+            shuffle: self.shuffle.clone(),
+            is_stopped: self.is_stopped.clone(),
+            builder: self.builder.clone(),
+            tempo: self.tempo.clone(),
+            channels: self.channels.clone(),
+            reverb: self.reverb.clone(),
+            volume: self.volume.clone(),
+            ducking: self.ducking.clone(),
+            fade: self.fade.clone(),
+            limiter: self.limiter.clone(),
+            spectrum: self.spectrum.clone(),
+            pcm_taps: self.pcm_taps.clone(),
+            meter: self.meter.clone(),
+            start: self.start.clone(),
+        }
+    }
+
+    fn transport(&self) -> Transport {
+        Transport {
+            sink: self.sink.clone(),
+            playlist: self.playlist.clone(),
+            clock: self.clock.clone(),
+            fade: self.fade.clone(),
+            fade_out: self.fade_out.clone(),
+            is_stopped: self.is_stopped.clone(),
+            signals: self.events.signals(),
+            bookmarks: self.bookmarks.clone(),
+            start: self.start.clone(),
+        }
+    }
+
+    /// Sets the output volume in dB, clamped to `MIN_VOLUME_DB..=MAX_VOLUME_DB`.
+    /// The change is ramped in over a few milliseconds to avoid clicks.
+    pub fn set_volume_db(&self, db: f32) {
+        self.volume_db.store(db.clamp(MIN_VOLUME_DB, MAX_VOLUME_DB));
+        self.update_volume();
+    }
+
+    pub fn volume_db(&self) -> f32 {
+        self.volume_db.load()
+    }
+
+    /// Routes the left/right pair, e.g. to mono for a single speaker. Takes
+    /// effect immediately; mono sources are unaffected.
+    pub fn set_channel_mode(&self, mode: ChannelMode) {
+        self.channels.set_mode(mode);
+    }
+
+    pub fn channel_mode(&self) -> ChannelMode {
+        self.channels.mode()
+    }
+
+    /// Pans between -1.0 (full left) and 1.0 (full right), clamped. Changes
+    /// are smoothed over a few milliseconds; mono sources are spread to stereo.
+    pub fn set_balance(&self, balance: f32) {
+        self.channels.set_balance(balance);
+    }
+
+    pub fn balance(&self) -> f32 {
+        self.channels.balance()
+    }
+
+    /// Sets the stereo width, clamped to 0.0 (mono) ..= [`MAX_STEREO_WIDTH`]:
+    /// 1.0 leaves the signal as it is, higher widens it. Past 1.0 the level
+    /// comes down to make room, so nothing clips. Changes glide over a few
+    /// milliseconds; sources that aren't stereo are left alone.
+    ///
+    /// [`MAX_STEREO_WIDTH`]: crate::MAX_STEREO_WIDTH
+    pub fn set_stereo_width(&self, width: f32) {
+        self.builder.width.set_width(width);
+    }
+
+    pub fn stereo_width(&self) -> f32 {
+        self.builder.width.width()
+    }
+
+    /// Applies the stereo width before or after the EQ; after by default.
+    pub fn set_stereo_width_placement(&self, placement: WidthPlacement) {
+        self.builder.width.set_placement(placement);
+    }
+
+    pub fn stereo_width_placement(&self) -> WidthPlacement {
+        self.builder.width.placement()
+    }
+
+    /// Turns down what is panned to the center, usually the vocals, by
+    /// `amount` from 0.0 (off, the default) to 1.0, clamped: the mid signal
+    /// loses that much of its [`VOCAL_LOW_FREQUENCY`] to
+    /// [`VOCAL_HIGH_FREQUENCY`] band, and a make-up gain keeps the level
+    /// about even. It comes before the EQ, and a change glides in without a
+    /// click. Only stereo tracks are affected; others play as they are, with
+    /// a [`PlayerEvent::Warning`].
+    ///
+    /// [`VOCAL_LOW_FREQUENCY`]: crate::VOCAL_LOW_FREQUENCY
+    /// [`VOCAL_HIGH_FREQUENCY`]: crate::VOCAL_HIGH_FREQUENCY
+    pub fn set_vocal_reduction(&self, amount: f32) {
+        self.builder.vocal.set_amount(amount);
+    }
+
+    pub fn vocal_reduction(&self) -> f32 {
+        self.builder.vocal.amount()
+    }
+
+    /// Mutes or unmutes; unmuting restores the level set through `set_volume_db`.
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+        self.update_volume();
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    /// Plays the clip at `path` over the music at `volume_db`, whether or
+    /// not the music is playing, leaving its position and queue alone.
+    /// Overlays started while others play are mixed in with them. A clip
+    /// that can't be opened or decoded fails here, before anything changes.
+    pub fn play_overlay(&self, path: impl AsRef<Path>, volume_db: f32) -> Result<(), PlayerError> {
+        let clip = open_decoder(
+            path.as_ref(),
+            &Origin::File,
+            self.builder.decoding.backend(),
+        )?;
+        let volume = db_to_linear(volume_db.clamp(MIN_VOLUME_DB, MAX_VOLUME_DB));
+        self.engine
+            .mix(Overlay::new(clip, volume, self.ducking.clone()));
+        Ok(())
+    }
+
+    /// Turns the music down by `db` while overlays play, ramping over
+    /// [`OVERLAY_DUCK_RAMP`] each way; 0, the default, leaves it alone.
+    ///
+    /// [`OVERLAY_DUCK_RAMP`]: crate::OVERLAY_DUCK_RAMP
+    pub fn set_overlay_ducking(&self, db: f32) {
+        self.ducking.set_duck_db(db);
+    }
+
+    pub fn overlay_ducking(&self) -> f32 {
+        self.ducking.duck_db()
+    }
+
+    /// Turns the music down by `amount_db`, over `attack`, until the
+    /// returned guard is dropped, then brings it back over `release`: to dip
+    /// it under speech, say. The sign doesn't matter; -12 and 12 both duck
+    /// by 12 dB. The gain is ramped in the chain after the volume, which it
+    /// leaves as it was.
+    ///
+    /// Ducks overlap: the music stays as far down as the deepest one held,
+    /// overlays included, and only comes all the way back once every guard
+    /// is gone.
+    pub fn duck(&self, amount_db: f32, attack: Duration, release: Duration) -> DuckGuard {
+        self.ducking.duck(amount_db.abs(), attack, release)
+    }
+
+    /// Lets go of every [`AudioPlayer::duck`] at once, over the longest of
+    /// their releases. Overlays still duck the music while they play.
+    pub fn unduck(&self) {
+        self.ducking.unduck();
+    }
+
+    /// How far, in dB, the music is ducked now or on its way to, by
+    /// [`AudioPlayer::duck`] or overlays.
+    pub fn ducked_db(&self) -> f32 {
+        self.ducking.ducked_db()
+    }
+
+    /// Turns the peak limiter at the end of the chain on or off while playing.
+    pub fn set_limiter(&self, enabled: bool) {
+        self.limiter.set_enabled(enabled);
+    }
+
+    pub fn is_limiter_enabled(&self) -> bool {
+        self.limiter.is_enabled()
+    }
+
+    /// Shared limiter settings, for adjusting the threshold or release.
+    pub fn limiter(&self) -> Arc<LimiterControls> {
+        self.limiter.clone()
+    }
+
+    /// Current gain reduction of the limiter in dB, for metering.
+    pub fn limiter_reduction_db(&self) -> f32 {
+        self.limiter.gain_reduction_db()
+    }
+
+    /// Turns the reverb on with `settings`, changes them, or fades it out
+    /// with `None`. The mix and damping glide to new values; a new room size
+    /// starts the tail over.
+    pub fn set_reverb(&self, settings: Option<ReverbSettings>) {
+        self.reverb.set_settings(settings);
+    }
+
+    pub fn reverb(&self) -> Option<ReverbSettings> {
+        self.reverb.settings()
+    }
+
+    /// Turns the compressor after the EQ on with `settings`, changes them,
+    /// or turns it off with `None`. Changes glide into place rather than
+    /// jumping.
+    pub fn set_compressor(&self, settings: Option<CompressorSettings>) {
+        self.builder.compressor.set_settings(settings);
+    }
+
+    pub fn compressor(&self) -> Option<CompressorSettings> {
+        self.builder.compressor.settings()
+    }
+
+    /// Current gain reduction of the compressor in dB, for metering.
+    pub fn compressor_reduction_db(&self) -> f32 {
+        self.builder.compressor.current_gain_reduction()
+    }
+
+    /// Every stage of the EQ and effects as they are set up now, none left
+    /// out, to save with [`ChainConfig::save`] or apply to another player.
+    pub fn export_chain(&self) -> ChainConfig {
+        let compressor = self.compressor();
+        let reverb = self.reverb();
+        ChainConfig {
+            vocal_reduction: Some(self.vocal_reduction()),
+            eq: Some(EqStage {
+                enabled: self.eq_enabled(),
+                settings: self.eq_settings(),
+            }),
+            stereo_width: Some(WidthStage {
+                width: self.stereo_width(),
+                placement: self.stereo_width_placement(),
+            }),
+            tone: Some(ToneStage {
+                bass_db: self.bass_boost(),
+                treble_db: self.treble(),
+            }),
+            compressor: Some(CompressorStage {
+                enabled: compressor.is_some(),
+                settings: compressor.unwrap_or_default(),
+            }),
+            channels: Some(ChannelStage {
+                mode: self.channel_mode(),
+                balance: self.balance(),
+            }),
+            reverb: Some(ReverbStage {
+                enabled: reverb.is_some(),
+                settings: reverb.unwrap_or_default(),
+            }),
+            limiter: Some(LimiterStage {
+                enabled: self.limiter.is_enabled(),
+                threshold_db: self.limiter.threshold_db(),
+                release: self.limiter.release(),
+            }),
+        }
+    }
+
+    /// Sets up each stage `chain` has, leaving those it leaves out as they
+    /// are. Each changes as it would through its own setter, so while
+    /// playing the EQ and effects glide or crossfade to their new settings
+    /// and nothing is rebuilt.
+    ///
+    /// The whole chain is checked first, as [`ChainConfig::validate`] does
+    /// and the EQ against the current track's sample rate: a setting that
+    /// doesn't pass fails with [`PlayerError::InvalidChain`], naming its
+    /// stage and parameter, and nothing changes.
+    pub fn apply_chain(&self, chain: ChainConfig) -> Result<(), PlayerError> {
+        chain.validate()?;
+        if let Some(sample_rate) = self.track_sample_rate() {
+            chain.validate_rate(sample_rate)?;
+        }
+        if let Some(amount) = chain.vocal_reduction {
+            self.set_vocal_reduction(amount);
+        }
+        if let Some(eq) = chain.eq {
+            self.set_eq_settings(eq.settings);
+            self.set_eq_enabled(eq.enabled);
+        }
+        if let Some(width) = chain.stereo_width {
+            self.set_stereo_width(width.width);
+            self.set_stereo_width_placement(width.placement);
+        }
+        if let Some(tone) = chain.tone {
+            // Set together, and as validated: a bass cut, as the bass knob
+            // makes, would be clamped away by `set_bass_boost`.
+            self.builder.tone.set(tone.bass_db, tone.treble_db);
+        }
+        if let Some(compressor) = chain.compressor {
+            self.set_compressor(compressor.enabled.then_some(compressor.settings));
+        }
+        if let Some(channels) = chain.channels {
+            self.set_channel_mode(channels.mode);
+            self.set_balance(channels.balance);
+        }
+        if let Some(reverb) = chain.reverb {
+            self.set_reverb(reverb.enabled.then_some(reverb.settings));
+        }
+        if let Some(limiter) = chain.limiter {
+            self.limiter.set_threshold_db(limiter.threshold_db);
+            self.limiter.set_release(limiter.release);
+            self.limiter.set_enabled(limiter.enabled);
+        }
+        Ok(())
+    }
+
+    /// A tap on what this player plays, after its EQ and effects but before
+    /// the engine mixes it with others, holding the last `buffer_frames`
+    /// frames at the output device's channel count. It is read at its own
+    /// pace: one that falls behind misses the oldest frames, and playback
+    /// never waits for it. Any number can be open at once.
+    pub fn pcm_tap(&self, buffer_frames: usize) -> PcmTapReceiver {
+        let channels = self.engine.channels().max(1) as usize;
+        self.pcm_taps.add(buffer_frames.max(1) * channels)
+    }
+
+    /// Spectrum of what is playing, as `bands` log-spaced magnitudes in dB
+    /// down to `SPECTRUM_FLOOR_DB`. Falls away to the floor while paused.
+    pub fn spectrum(&self, bands: usize) -> Vec<f32> {
+        self.spectrum.spectrum(bands)
+    }
+
+    /// Peak and RMS level per output channel, in dBFS.
+    pub fn levels(&self) -> Vec<ChannelLevel> {
+        self.meter.levels()
+    }
+
+    /// Frames decoded and output, underruns, decode errors got past, seeks
+    /// and how long they took, files opened, and play time against audio
+    /// time, counted since the player was made or [`AudioPlayer::reset_stats`].
+    pub fn stats(&self) -> PlayerStats {
+        self.builder.stats.stats()
+    }
+
+    /// Sets the counts behind [`AudioPlayer::stats`] back to zero.
+    pub fn reset_stats(&self) {
+        self.builder.stats.reset();
+    }
+
+    /// How alike the left and right output are, from -1.0 to 1.0, over the
+    /// last [`METER_CORRELATION_WINDOW`]: near 1.0 folds down to mono
+    /// cleanly, below 0.0 parts cancel. Reads 0.0 while a channel is silent,
+    /// falls back there while paused, and is `None` for mono output. See
+    /// [`MeterControls::correlation`].
+    ///
+    /// [`METER_CORRELATION_WINDOW`]: crate::METER_CORRELATION_WINDOW
+    pub fn phase_correlation(&self) -> Option<f32> {
+        self.meter.correlation()
+    }
+
+    /// Whether the output has hit full scale since the last [`AudioPlayer::reset_clip`].
+    pub fn is_clipped(&self) -> bool {
+        self.meter.clipped()
+    }
+
+    pub fn reset_clip(&self) {
+        self.meter.reset_clip();
+    }
+
+    /// Shared meter settings, for adjusting the peak decay.
+    pub fn meter(&self) -> Arc<MeterControls> {
+        self.meter.clone()
+    }
+
+    fn update_volume(&self) {
+        let gain = if self.is_muted() {
+            0.0
+        } else {
+            db_to_linear(self.volume_db())
+        };
+        self.volume.set_target(gain);
+    }
+
+    /// Sets the playback rate, clamped to `MIN_SPEED..=MAX_SPEED`. Pitch
+    /// follows the rate; positions stay in source time.
+    pub fn set_speed(&self, rate: f32) {
+        let sink = self.sink.locked();
+        let rate = rate.clamp(MIN_SPEED, MAX_SPEED);
+        self.clock.set_speed(rate);
+        sink.set_speed(rate);
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.clock.speed()
+    }
+
+    /// Sets the tempo, clamped to `MIN_TEMPO..=MAX_TEMPO`, keeping the pitch.
+    /// It combines with [`AudioPlayer::set_speed`] and can change while
+    /// playing without a gap; positions stay in source time.
+    pub fn set_tempo(&self, ratio: f32) {
+        self.tempo.set_ratio(ratio.clamp(MIN_TEMPO, MAX_TEMPO));
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo.ratio()
+    }
+
+    /// Shifts the pitch by `semitones`, clamped to `±MAX_PITCH_SEMITONES`,
+    /// keeping the tempo. Changes glide; at 0.0 the audio is untouched.
+    pub fn set_pitch_semitones(&self, semitones: f32) {
+        self.tempo
+            .set_semitones(semitones.clamp(-MAX_PITCH_SEMITONES, MAX_PITCH_SEMITONES));
+    }
+
+    pub fn pitch_semitones(&self) -> f32 {
+        self.tempo.semitones()
+    }
+
+    /// Switches the equalizer in or out of the playing stream in place; the
+    /// position is untouched.
+    pub fn set_eq_enabled(&self, enabled: bool) {
+        self.eq.set_enabled(enabled);
+    }
+
+    pub fn eq_enabled(&self) -> bool {
+        self.eq.is_enabled()
+    }
+
+    /// Sets how long [`AudioPlayer::set_eq_enabled`] crossfades, avoiding a click.
+    pub fn set_eq_bypass_fade(&self, fade: Duration) {
+        self.eq.set_bypass_fade(fade);
+    }
+
+    pub fn eq_bypass_fade(&self) -> Duration {
+        self.eq.bypass_fade()
+    }
+
+    /// Sets how long EQ changes glide in, so dragging a gain sounds
+    /// continuous. See [`EqControls::set_smoothing`].
+    pub fn set_eq_smoothing(&self, smoothing: Duration) {
+        self.eq.set_smoothing(smoothing);
+    }
+
+    pub fn eq_smoothing(&self) -> Duration {
+        self.eq.smoothing()
+    }
+
+    /// Updates the EQ gains of the playing stream in place, without a
+    /// restart, one per band of the active layout. Any other number of
+    /// gains fails with [`PlayerError::InvalidEq`], leaving the EQ as it was.
+    pub fn set_eq_gains(&self, gains: &[f32]) -> Result<(), PlayerError> {
+        Ok(self.eq.set_gains(gains)?)
+    }
+
+    /// Gains of the active EQ bands, the same ones every rebuilt track
+    /// (after a seek, skip or repeat) is filtered with.
+    pub fn eq_gains(&self) -> Vec<f32> {
+        self.eq.gains()
+    }
+
+    /// Gives channel `channel` (0 is left) EQ gains of its own, one per band,
+    /// and unlinks the channels so they apply; the change glides in like any
+    /// other. The other channels keep the shared gains until given their
+    /// own, and a mono source plays with channel 0's. Fails for gains that
+    /// can't be filtered stably, leaving the EQ as it was.
+    pub fn set_eq_gains_channel(&self, channel: usize, gains: &[f32]) -> Result<(), PlayerError> {
+        Ok(self.eq.set_channel_gains(channel, gains)?)
+    }
+
+    /// The EQ gains channel `channel` is filtered with.
+    pub fn eq_gains_channel(&self, channel: usize) -> Vec<f32> {
+        self.eq.channel_gains(channel)
+    }
+
+    /// Puts every channel back on the shared EQ gains, or, unlinked, back on
+    /// the gains each was given with [`AudioPlayer::set_eq_gains_channel`].
+    /// Channels start out linked.
+    pub fn link_channels(&self, linked: bool) {
+        self.eq.set_linked(linked);
+    }
+
+    pub fn channels_linked(&self) -> bool {
+        self.eq.is_linked()
+    }
+
+    pub fn eq_settings(&self) -> EqSettings {
+        self.eq.settings()
+    }
+
+    /// Replaces the whole EQ setup, including the number of bands, while playing.
+    pub fn set_eq_settings(&self, settings: EqSettings) {
+        self.eq.set_settings(settings);
+    }
+
+    /// Switches to the EQ profile `settings`, crossfading from the one
+    /// playing over `transition`; see [`EqControls::apply_profile`].
+    pub fn apply_profile(&self, settings: &EqSettings, transition: Duration) {
+        self.eq.apply_profile(settings, transition);
+    }
+
+    /// Plays the EQ settings in the file at `path`, read as
+    /// [`EqSettings::load`] reads them, and reloads them whenever the file
+    /// changes, with a [`PlayerEvent::EqReloaded`]. A change that doesn't
+    /// read sends [`PlayerEvent::EqReloadFailed`] instead, and the settings
+    /// playing stay. The file is polled, and a burst of writes reloads once,
+    /// after the last. Replaces any file watched before; fails, watching
+    /// nothing, if the file doesn't read to begin with.
+    pub fn watch_eq_file(&self, path: impl Into<PathBuf>) -> Result<(), PlayerError> {
+        let path = path.into();
+        self.unwatch_eq_file();
+        self.set_eq_settings(EqSettings::load(&path)?);
+        let (eq, signals, file) = (self.eq.clone(), self.events.signals(), path.clone());
+        let watch = FileWatch::spawn(path, move || {
+            let event = match EqSettings::load(&file) {
+                Ok(settings) => {
+                    eq.set_settings(settings);
+                    PlayerEvent::EqReloaded
+                }
+                Err(err) => PlayerEvent::EqReloadFailed(err.to_string()),
+            };
+            let _ = signals.send(Signal::Event(event));
+        });
+        *self.eq_watch.locked() = Some(watch);
+        Ok(())
+    }
+
+    /// Stops watching the file [`AudioPlayer::watch_eq_file`] watches. The
+    /// settings last read from it keep playing.
+    pub fn unwatch_eq_file(&self) {
+        drop(self.eq_watch.locked().take());
+    }
+
+    /// The file [`AudioPlayer::watch_eq_file`] watches, if any.
+    pub fn watched_eq_file(&self) -> Option<PathBuf> {
+        self.eq_watch
+            .locked()
+            .as_ref()
+            .map(|watch| watch.path().to_path_buf())
+    }
+
+    /// Plays `settings` while playback is on the output device named
+    /// `device`, as [`AudioPlayer::output_device`] names it: now if it is,
+    /// and each time playback moves to it. A switch to another profile sends
+    /// [`PlayerEvent::EqProfileChanged`].
+    pub fn set_device_profile(&self, device: &str, settings: EqSettings) {
+        self.profiles.set(device, settings);
+        self.follow_device_profiles();
+    }
+
+    /// Forgets `device`'s profile; playing on it, the default profile takes
+    /// over, if there is one.
+    pub fn remove_device_profile(&self, device: &str) -> Option<EqSettings> {
+        let removed = self.profiles.remove(device);
+        self.follow_device_profiles();
+        removed
+    }
+
+    pub fn device_profile(&self, device: &str) -> Option<EqSettings> {
+        self.profiles.get(device, &self.eq)
+    }
+
+    /// Sets the profile for devices without one of their own. With none,
+    /// the EQ is left as it is on them.
+    pub fn set_default_profile(&self, settings: Option<EqSettings>) {
+        self.profiles.set_default(settings);
+        self.follow_device_profiles();
+    }
+
+    pub fn default_profile(&self) -> Option<EqSettings> {
+        self.profiles.default()
+    }
+
+    /// The profile playing, or `None` while none applies.
+    pub fn active_eq_profile(&self) -> Option<EqProfile> {
+        self.profiles.active()
+    }
+
+    /// With write-back on, changes made to the EQ on a device are kept in
+    /// its profile for the next time playback is on it; a device playing
+    /// the default profile is given one of its own. Off by default.
+    pub fn set_profile_write_back(&self, enabled: bool) {
+        self.profiles.set_write_back(enabled);
+    }
+
+    pub fn profile_write_back(&self) -> bool {
+        self.profiles.writes_back()
+    }
+
+    /// Plays the profile for the device playing now, and from the first
+    /// call on, switches profile each time playback moves to another.
+    fn follow_device_profiles(&self) {
+        if self.profiles.start_following() {
+            let events = self.subscribe();
+            let (profiles, eq) = (self.profiles.clone(), self.eq.clone());
+            let signals = self.events.signals();
+            thread::spawn(move || {
+                // Ends along with the event thread, once the player is gone.
+                for event in events {
+                    let PlayerEvent::DeviceChanged(device) = event else {
+                        continue;
+                    };
+                    if let Some(profile) = profiles.select(Some(&device), &eq) {
+                        let event = PlayerEvent::EqProfileChanged(profile);
+                        let _ = signals.send(Signal::Event(event));
+                    }
+                }
+            });
+        }
+        if let Some(profile) = self
+            .profiles
+            .select(self.output_device().as_deref(), &self.eq)
+        {
+            self.events.emit(PlayerEvent::EqProfileChanged(profile));
+        }
+    }
+
+    /// Compares EQ settings `a` and `b`, starting on `a`: each is given a
+    /// fixed preamp so both play as loud as the quieter of them would, and
+    /// [`AudioPlayer::toggle_ab`] switches between them. Replaces any
+    /// comparison before.
+    pub fn set_ab(&self, a: EqSettings, b: EqSettings) {
+        self.start_ab(AbComparison::new(a, b, false, self.eq.response_rate()));
+    }
+
+    /// Like [`AudioPlayer::set_ab`], but which of `a` and `b` plays as A is
+    /// left to chance, until [`AudioPlayer::reveal`].
+    pub fn set_ab_blind(&self, a: EqSettings, b: EqSettings) {
+        self.start_ab(AbComparison::new(a, b, true, self.eq.response_rate()));
+    }
+
+    fn start_ab(&self, comparison: AbComparison) {
+        self.eq.set_settings(comparison.settings().clone());
+        *self.ab.locked() = Some(comparison);
+    }
+
+    /// Switches the comparison to its other side, gliding over to its
+    /// settings as any EQ change does, so it doesn't click, and leaving the
+    /// position where it is. Sends a [`PlayerEvent::AbToggled`]. Fails if
+    /// no comparison is set.
+    pub fn toggle_ab(&self) -> Result<AB, PlayerError> {
+        let mut ab = self.ab.locked();
+        let comparison = ab
+            .as_mut()
+            .ok_or_else(|| PlayerError::InvalidArgument("no A/B comparison is set".into()))?;
+        let label = comparison.toggle();
+        self.eq.set_settings(comparison.settings().clone());
+        self.events.emit(PlayerEvent::AbToggled {
+            label,
+            at: SystemTime::now(),
+            position: self.get_playback_position(),
+        });
+        Ok(label)
+    }
+
+    /// The side of the comparison playing, if one is set.
+    pub fn current_ab(&self) -> Option<AB> {
+        self.ab.locked().as_ref().map(AbComparison::current)
+    }
+
+    /// Whether the comparison is blind and not yet revealed.
+    pub fn is_ab_blind(&self) -> bool {
+        self.ab
+            .locked()
+            .as_ref()
+            .is_some_and(AbComparison::is_blind)
+    }
+
+    /// The side the `a` settings given to [`AudioPlayer::set_ab_blind`] play
+    /// as, ending the blindness; the comparison goes on. `None` if no
+    /// comparison is set.
+    pub fn reveal(&self) -> Option<AB> {
+        self.ab.locked().as_mut().map(AbComparison::reveal)
+    }
+
+    /// Ends the comparison, leaving the EQ on the side that was playing.
+    pub fn clear_ab(&self) {
+        self.ab.locked().take();
+    }
+
+    pub fn eq_bands(&self) -> Vec<EqBand> {
+        self.eq.bands()
+    }
+
+    /// The gain of the EQ on channel `channel`, preamp included, at `points`
+    /// frequencies from 20 Hz to the Nyquist frequency, for drawing its
+    /// curve. See [`EqControls::frequency_response`].
+    pub fn eq_frequency_response(&self, channel: usize, points: usize) -> Vec<(f32, f32)> {
+        self.eq.frequency_response(channel, points)
+    }
+
+    /// Retunes band `index` of the playing EQ in place. Fails for an index
+    /// out of range, a Q or frequency at or below zero, a frequency at or
+    /// above the Nyquist frequency of the audio playing, or a band too narrow
+    /// or steep to filter stably at its sample rate.
+    pub fn set_eq_band(&self, index: usize, band: EqBand) -> Result<(), PlayerError> {
+        Ok(self.eq.set_band(index, band)?)
+    }
+
+    /// Adds a band to the playing EQ, returning its index; checked like
+    /// [`AudioPlayer::set_eq_band`].
+    pub fn add_eq_band(&self, band: EqBand) -> Result<usize, PlayerError> {
+        Ok(self.eq.add_band(band)?)
+    }
+
+    /// Changes the Q of EQ band `index` in place, recomputing only that
+    /// band's filter. Fails for an index out of range, a Q at or below zero,
+    /// or one too high to filter stably at the sample rate playing.
+    pub fn set_band_q(&self, index: usize, q: f32) -> Result<(), PlayerError> {
+        Ok(self.eq.set_band_q(index, q)?)
+    }
+
+    pub fn remove_eq_band(&self, index: usize) -> Result<EqBand, PlayerError> {
+        Ok(self.eq.remove_band(index)?)
+    }
+
+    pub fn set_preamp_db(&self, preamp_db: f32) {
+        self.eq.set_preamp_db(preamp_db);
+    }
+
+    /// Lets the EQ pick its own preamp so boosted bands don't clip; recomputed
+    /// whenever the gains change.
+    pub fn set_auto_headroom(&self, enabled: bool) {
+        self.eq.set_auto_headroom(enabled);
+    }
+
+    /// Switches to a built-in preset through the same live path as
+    /// `set_eq_gains`. On the ten octave bands it sets their Q back to the
+    /// preset's too; other layouts get its curve fitted to their bands and
+    /// keep their Q.
+    pub fn apply_preset(&self, preset: EqPreset) {
+        let mut settings = self.eq.settings();
+        if settings.frequencies() == FREQUENCIES {
+            settings.bands = preset.settings().bands;
+            self.set_eq_settings(settings);
+        } else {
+            // One gain per band, so they fit.
+            let _ = self.set_eq_gains(&preset.gains_at(&settings.frequencies()));
+        }
+    }
+
+    /// Boosts the bass with a low shelf at [`BASS_FREQUENCY`], from 0 to
+    /// [`MAX_TONE_DB`] dB. It sits after the EQ, on top of its bands rather
+    /// than changing them, and stays on while the EQ is bypassed. The level
+    /// is trimmed to make room for the boost, so it doesn't clip.
+    ///
+    /// [`BASS_FREQUENCY`]: crate::BASS_FREQUENCY
+    pub fn set_bass_boost(&self, db: f32) {
+        self.builder.tone.set_bass_db(db.clamp(0.0, MAX_TONE_DB));
+    }
+
+    pub fn bass_boost(&self) -> f32 {
+        self.builder.tone.bass_db()
+    }
+
+    /// Boosts or cuts the treble with a high shelf at [`TREBLE_FREQUENCY`],
+    /// by up to [`MAX_TONE_DB`] dB either way, alongside
+    /// [`AudioPlayer::set_bass_boost`].
+    ///
+    /// [`TREBLE_FREQUENCY`]: crate::TREBLE_FREQUENCY
+    pub fn set_treble(&self, db: f32) {
+        self.builder
+            .tone
+            .set_treble_db(db.clamp(-MAX_TONE_DB, MAX_TONE_DB));
+    }
+
+    pub fn treble(&self) -> f32 {
+        self.builder.tone.treble_db()
+    }
+
+    /// Turns the bass and treble knobs together, each clamped to
+    /// `±MAX_TONE_KNOB_DB`: the shelves of [`AudioPlayer::set_bass_boost`]
+    /// and [`AudioPlayer::set_treble`], here with the bass cutting too. The
+    /// change glides in while playing. The EQ and its preset are left as they
+    /// are, and with both at 0 the shelves are out of the chain altogether.
+    pub fn set_tone(&self, bass_db: f32, treble_db: f32) {
+        self.builder.tone.set(
+            bass_db.clamp(-MAX_TONE_KNOB_DB, MAX_TONE_KNOB_DB),
+            treble_db.clamp(-MAX_TONE_KNOB_DB, MAX_TONE_KNOB_DB),
+        );
+    }
+
+    /// The bass and treble gains in dB, however they were set.
+    pub fn tone(&self) -> (f32, f32) {
+        (self.bass_boost(), self.treble())
+    }
+
+    /// What is heard of channel `channel`: the gain of the EQ and the tone
+    /// shelves after it, with their preamps, at `points` frequencies from
+    /// 20 Hz to the Nyquist frequency. See
+    /// [`AudioPlayer::eq_frequency_response`] for the EQ alone.
+    pub fn frequency_response(&self, channel: usize, points: usize) -> Vec<(f32, f32)> {
+        self.builder.tone.response(&self.eq, channel, points)
+    }
+
+    /// Loops playback between `start` and `end` until the region is cleared.
+    ///
+    /// The section is decoded up front so each wrap is gapless. If playback
+    /// is already past `end` it jumps back to `start` straight away.
+    /// Both are within the current cue track, if the file has a cue sheet,
+    /// and taken to the nearest frame of the file; see
+    /// [`AudioPlayer::set_loop_region_exact`].
+    pub fn set_loop_region(&self, start: Duration, end: Duration) -> Result<(), PlayerError> {
+        let Some(rate) = self.track_sample_rate() else {
+            return Err(PlayerError::InvalidArgument("no track to loop".into()));
+        };
+        self.set_loop_region_exact(
+            Position::from_duration(start, rate),
+            Position::from_duration(end, rate),
+        )
+    }
+
+    /// Loops playback from frame `start` of the current track up to, and
+    /// not including, frame `end`, as [`AudioPlayer::set_loop_region`] does.
+    /// Both have to be at [`AudioPlayer::track_sample_rate`].
+    pub fn set_loop_region_exact(&self, start: Position, end: Position) -> Result<(), PlayerError> {
+        let Some((_, track)) = self.current_entry() else {
+            return Err(PlayerError::InvalidArgument("no track to loop".into()));
+        };
+        check_rate(&track, start)?;
+        check_rate(&track, end)?;
+        if end <= start {
+            return Err(PlayerError::InvalidArgument(format!(
+                "loop end ({end}) must come after its start ({start})"
+            )));
+        }
+        if let Some(duration) = self.exact_duration().filter(|&duration| end > duration) {
+            return Err(PlayerError::InvalidArgument(format!(
+                "loop end ({end}) is past the end of the track ({duration})"
+            )));
+        }
+
+        let offset = Position::from_duration(self.cue_start(), track.sample_rate);
+        let (start_frame, end_frame) = ((offset + start).as_frames(), (offset + end).as_frames());
+        // The loop is decoded afresh, from the start of the file rather
+        // than of the range queued.
+        let trimmed = track.range.map_or(Duration::ZERO, |range| range.start);
+        let skip = Position::from_duration(trimmed, track.sample_rate).as_frames() + start_frame;
+        let decoder = open_decoder(&track.path, &track.origin, self.builder.decoding.backend())?;
+        let channels = decoder.channels();
+        let samples = decoder
+            .skip(skip as usize * channels as usize)
+            .take((end - start).as_frames() as usize * channels as usize)
+            .collect();
+        self.looping.set_region(Some(LoopBuffer::new(
+            track.id,
+            start_frame,
+            end_frame,
+            channels,
+            track.sample_rate,
+            samples,
+        )));
+
+        if self
+            .exact_position()
+            .is_some_and(|position| position >= end)
+        {
+            self.seek_exact(start)?;
+        }
+        Ok(())
+    }
+
+    /// Plays the current track from `start`, fading in over `fade_in`, and
+    /// ends it at `end`, fading out over `fade_out` so the last sample heard
+    /// is the one at `end`; the queue carries on from there as it would at
+    /// the end of the track. The end is kept in samples as the track plays,
+    /// so pauses and underruns don't move it, and an `end` past the end of
+    /// the track is its end. A later segment replaces this one, and a seek
+    /// out of it lets it go. Both times are within the current cue track, if
+    /// the file has a cue sheet.
+    pub fn play_segment(
+        &self,
+        start: Duration,
+        end: Duration,
+        fade_in: Duration,
+        fade_out: Duration,
+    ) -> Result<(), PlayerError> {
+        let Some((_, track)) = self.current_entry() else {
+            return Err(PlayerError::InvalidArgument("no track to play".into()));
+        };
+        let end = self.duration().map_or(end, |duration| end.min(duration));
+        if end <= start {
+            return Err(PlayerError::InvalidArgument(format!(
+                "segment end ({end:?}) must come after its start ({start:?})"
+            )));
+        }
+        let length = end - start;
+        let offset = self.cue_start();
+        self.builder.segments.set(Some(Segment::new(
+            track.id,
+            offset + start,
+            offset + end,
+            fade_in.min(length),
+            fade_out.min(length),
+        )));
+        self.seek(start)?;
+        self.play()
+    }
+
+    /// Loops each track that has loop points in its tags, as game and
+    /// tracker music often does, from the end point back to the start point
+    /// on the sample and without a gap. A loop region set on a track stands
+    /// in for its tags. While looping, a track never ends, crossfade and
+    /// repeat or not, until it is skipped or this is turned off, which lets
+    /// the current pass play on to the end of the file; the position stays
+    /// within the file. Tracks without loop points play as ever.
+    ///
+    /// Each wrap replays the loop as recorded when it first played from its
+    /// start. If playback joined it partway, the first wrap seeks instead.
+    pub fn set_loop_from_metadata(&self, enabled: bool) {
+        self.looping.set_tagged(enabled);
+    }
+
+    pub fn loop_from_metadata(&self) -> bool {
+        self.looping.tagged()
+    }
+
+    /// Stops looping; playback runs on past the old end point.
+    pub fn clear_loop_region(&self) {
+        self.looping.set_region(None);
+    }
+
+    /// The loop region's ends in frames of the current track; see
+    /// [`AudioPlayer::set_loop_region_exact`].
+    pub fn exact_loop_region(&self) -> Option<(Position, Position)> {
+        let rate = self.track_sample_rate()?;
+        let offset = Position::from_duration(self.cue_start(), rate);
+        self.looping.region().map(|region| {
+            let frame = |frame| Position::new(frame, rate).saturating_sub(offset);
+            (frame(region.start_frame), frame(region.end_frame))
+        })
+    }
+
+    pub fn loop_region(&self) -> Option<(Duration, Duration)> {
+        let offset = self.cue_start();
+        self.looping.region().map(|region| {
+            (
+                region.start.saturating_sub(offset),
+                region.end.saturating_sub(offset),
+            )
+        })
+    }
+
+    /// Jumps to `position`, within the current cue track if there is one.
+    ///
+    /// Sources that can seek are moved in place; everything else is rebuilt
+    /// from the file and decoded up to `position`. Either way that happens
+    /// on a thread of the player's own: this returns straight away, with
+    /// [`PlayerEvent::Seeking`] sent, and [`PlayerEvent::Seeked`] follows
+    /// once playback is there. A seek asked for while an earlier one is
+    /// still waiting replaces it, and [`AudioPlayer::get_playback_position`]
+    /// reports the latest target meanwhile. Only a track that can't seek at
+    /// all fails here, with [`PlayerError::UnsupportedSeek`]; anything going
+    /// wrong later is sent as a [`PlayerEvent::Error`].
+    ///
+    /// A position past the duration is taken as the duration, and landing
+    /// there ends the track as if it had played through: the queue moves on,
+    /// honouring the repeat mode, or playback finishes. Without a known
+    /// duration a seek past the end either ends the track the same way or,
+    /// if the decoder refuses it, fails with a seek error.
+    pub fn seek(&self, position: Duration) -> Result<(), PlayerError> {
+        self.seek_settled(position, Duration::ZERO)
+    }
+
+    /// Seeks as a seek bar being dragged asks to, to `position` as
+    /// [`AudioPlayer::seek`] does, except that one following within
+    /// [`SCRUB_SETTLE`] replaces it, so only where the drag rests is sought.
+    /// [`AudioPlayer::get_playback_position`] reports the latest straight
+    /// away. A decoder that can't seek is moved forward in place; only
+    /// dragging back on one reopens the file.
+    pub fn scrub(&self, position: Duration) -> Result<(), PlayerError> {
+        self.seek_settled(position, SCRUB_SETTLE)
+    }
+
+    /// Plays [`SCRUB_PREVIEW`] of the track where [`AudioPlayer::scrub`]
+    /// settles while paused, faded in and out and mixed in over the output
+    /// as overlays are. Off by default.
+    pub fn set_scrub_preview(&self, enabled: bool) {
+        self.scrub_preview.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn scrub_preview(&self) -> bool {
+        self.scrub_preview.load(Ordering::Relaxed)
+    }
+
+    /// [`AudioPlayer::seek`], once `settle` goes by without another.
+    fn seek_settled(&self, position: Duration, settle: Duration) -> Result<(), PlayerError> {
+        self.ensure_seekable()?;
+        let duration = self.duration();
+        let position = duration.map_or(position, |duration| position.min(duration));
+        let signals = self.events.signals();
+        let send = move |event| {
+            let _ = signals.send(Signal::Event(event));
+        };
+        self.events.emit(PlayerEvent::Seeking(position));
+        if let (Some((cue, index)), true) = (self.clock.cue_track(), Some(position) == duration) {
+            // The end of a cue track is where the next one starts, which
+            // the clock would take for a jump rather than a handover.
+            if let Some(next) = cue.tracks.get(index + 1) {
+                let path = self.current_track();
+                return self.seek_file(next.start, Some(Duration::ZERO), settle, move || {
+                    send(PlayerEvent::Seeked(position));
+                    if let Some(path) = path {
+                        send(PlayerEvent::TrackEnded(path.clone()));
+                        send(PlayerEvent::TrackStarted(path));
+                    }
+                });
+            }
+        }
+        let preview = match settle.is_zero() {
+            true => None,
+            false => self.preview(position),
+        };
+        self.seek_file(
+            self.cue_start() + position,
+            Some(position),
+            settle,
+            move || {
+                send(PlayerEvent::Seeked(position));
+                if let Some(preview) = preview {
+                    preview();
+                }
+            },
+        )
+    }
+
+    /// What plays [`SCRUB_PREVIEW`] of the current track from `position`,
+    /// if previews are on and would be heard, and playback is paused by
+    /// the time it runs.
+    fn preview(&self, position: Duration) -> Option<impl FnOnce() + Send + 'static> {
+        if !self.scrub_preview() || self.is_muted() {
+            return None;
+        }
+        let (_, track) = self.current_entry()?;
+        if !matches!(track.origin, Origin::File) {
+            return None;
+        }
+        let start = track.range.map_or(Duration::ZERO, |range| range.start);
+        let range = TrackRange {
+            start: start + self.cue_start() + position,
+            end: None,
+        };
+        let (engine, clock, backend) = (
+            self.engine.clone(),
+            self.clock.clone(),
+            self.builder.decoding.backend(),
+        );
+        let volume = db_to_linear(self.volume_db());
+        Some(move || {
+            if clock.is_playing() {
+                return;
+            }
+            let Ok(decoder) = open_decoder(&track.path, &track.origin, backend) else {
+                return;
+            };
+            let mut clip = Trim::new(decoder, range)
+                .amplify(volume)
+                .fade_in(SCRUB_PREVIEW_FADE)
+                .take_duration(SCRUB_PREVIEW);
+            clip.set_filter_fadeout();
+            engine.mix(clip);
+        })
+    }
+
+    /// Jumps to frame `position` of the current track, as
+    /// [`AudioPlayer::seek`] does. It has to be at
+    /// [`AudioPlayer::track_sample_rate`].
+    pub fn seek_exact(&self, position: Position) -> Result<(), PlayerError> {
+        if let Some((_, track)) = self.current_entry() {
+            check_rate(&track, position)?;
+        }
+        self.seek(position.into())
+    }
+
+    /// Jumps to `position` in the current file, ignoring any cue sheet, on
+    /// the seek thread once `settle` goes by without another seek, and runs
+    /// `landed` once it has. `target` is what
+    /// [`AudioPlayer::get_playback_position`] reports until then. A seek
+    /// that fails is reported as a [`PlayerEvent::Error`], and one overtaken
+    /// by a change of track is dropped.
+    fn seek_file(
+        &self,
+        position: Duration,
+        target: Option<Duration>,
+        settle: Duration,
+        landed: impl FnOnce() + Send + 'static,
+    ) -> Result<(), PlayerError> {
+        self.ensure_seekable()?;
+        let chain = self.chain();
+        let signals = self.events.signals();
+        let id = self.playlist.current();
+        let ticket = self.builder.stats.seek_ticket();
+        self.seeker.request(settle, target, move || {
+            match chain.seek_file(id, position) {
+                Ok(true) => {
+                    ticket.landed();
+                    landed();
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    let _ = signals.send(Signal::Event(PlayerEvent::Error(err.to_string())));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Fails with [`PlayerError::UnsupportedSeek`] if the current track
+    /// can only be read once.
+    fn ensure_seekable(&self) -> Result<(), PlayerError> {
+        match self.current_entry() {
+            Some((_, track)) if matches!(track.origin, Origin::Stdin(_)) => {
+                Err(PlayerError::UnsupportedSeek)
+            }
+            Some((_, track)) if track.is_live() => Err(PlayerError::LiveStream),
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the position by `offset` seconds, backwards if negative.
+    /// See [`AudioPlayer::seek_forward`] and [`AudioPlayer::seek_backward`].
+    pub fn seek_by(&self, offset: i64) -> Result<(), PlayerError> {
+        let step = Duration::from_secs(offset.unsigned_abs());
+        if offset < 0 {
+            self.seek_backward(step)
+        } else {
+            self.seek_forward(step)
+        }
+    }
+
+    /// Skips ahead by `step`. Going past the end lands on the end, so the
+    /// track finishes as if it had played through.
+    pub fn seek_forward(&self, step: Duration) -> Result<(), PlayerError> {
+        self.seek(self.get_playback_position().saturating_add(step))
+    }
+
+    /// Goes back by `step`, stopping at the start of the track.
+    pub fn seek_backward(&self, step: Duration) -> Result<(), PlayerError> {
+        self.seek(self.get_playback_position().saturating_sub(step))
+    }
+
+    /// Restarts the sink at `position` in track `index`, queueing the rest of
+    /// the playlist behind it. Drops any seek still waiting, which was meant
+    /// for what played before.
+    fn rebuild_at(&self, sink: &Sink, index: usize, position: Duration) -> Result<(), PlayerError> {
+        self.seeker.cancel();
+        self.chain()
+            .rebuild_at(sink, index, position)
+            .inspect_err(|err| self.events.emit(PlayerEvent::Error(err.to_string())))
+    }
+
+    fn build_track(
+        &self,
+        decoder: impl Source<Item = f32> + Send + 'static,
+        track: &Track,
+    ) -> TrackSource {
+        self.builder.build(decoder, track)
+    }
+}
+
+impl Drop for AudioPlayer {
+    fn drop(&mut self) {
+        save_autosave(&self.snapshot(), &self.autosave, &self.events.signals());
+    }
+}
+
+/// What seeking and rebuilding playback act on, so the seek thread can do
+/// either without the player.
+#[derive(Clone)]
+struct Chain {
+    sink: Arc<Mutex<Sink>>,
+    tracks: Arc<Mutex<Vec<Track>>>,
+    playlist: Arc<PlaylistControls>,
+    clock: Arc<Clock>,
+    shuffle: Arc<Mutex<Shuffle>>,
+    is_stopped: Arc<AtomicBool>,
+    builder: TrackBuilder,
+    tempo: Arc<TempoControls>,
+    channels: Arc<ChannelControls>,
+    reverb: Arc<ReverbControls>,
+    volume: Arc<GainControls>,
+    ducking: Arc<DuckControls>,
+    fade: Arc<GainControls>,
+    limiter: Arc<LimiterControls>,
+    spectrum: Arc<SpectrumTap>,
+    pcm_taps: Arc<PcmTaps>,
+    meter: Arc<MeterControls>,
+    start: Arc<StartControls>,
+}
+
+impl Chain {
+    /// Jumps to `position` in the file of track `id`. Returns `false`,
+    /// doing nothing, if another track has taken over since.
+    fn seek_file(&self, id: u64, position: Duration) -> Result<bool, PlayerError> {
+        let sink = self.sink.locked();
+        let Some((index, track)) = current_entry(&self.playlist, &self.tracks) else {
+            return Ok(false);
+        };
+        if track.id != id {
+            return Ok(false);
+        }
+
+        if !self.is_stopped.load(Ordering::Relaxed) && !sink.empty() {
+            // The sink's speed stage scales seek targets by the rate, so undo that
+            // to land on `position` in source time.
+            let target = position
+                .saturating_sub(track.lead)
+                .div_f32(self.clock.speed());
+            match sink.try_seek(target) {
+                Ok(()) => {
+                    self.clock.set(position);
+                    return Ok(true);
+                }
+                Err(SeekError::NotSupported { .. }) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        self.rebuild_at(&sink, index, position)?;
+        Ok(true)
+    }
+
+    /// Restarts the sink at `position` in track `index`, queueing the rest of
+    /// the playlist behind it.
+    fn rebuild_at(&self, sink: &Sink, index: usize, position: Duration) -> Result<(), PlayerError> {
+        let was_playing = self.clock.is_playing();
+        let Some(track) = self.tracks.locked().get(index).cloned() else {
+            return Ok(());
+        };
+        // Only this track can fail the rebuild; the next is skipped if it
+        // won't open.
+        let decoder = self.builder.open_track(&track)?;
+
+        sink.stop();
+        self.clock.set_playing(false);
+
+        let mut source = self.builder.build(decoder, &track);
+        // The track's chain has already skipped its leading silence. A live
+        // stream picks up where it is now, its position counting on.
+        let offset = position.saturating_sub(track.lead);
+        let mut skip = if track.is_live() {
+            Duration::ZERO
+        } else {
+            offset
+        };
+        // Seeking where it can, as skipping would only run through what a
+        // file has decoded ahead so far.
+        if !skip.is_zero() && source.try_seek(skip).is_ok() {
+            skip = Duration::ZERO;
+        }
+        let source = Box::new(source.skip_duration(skip));
+        sink.append(self.build_output(Playlist::new(
+            track.id,
+            source,
+            track.duration,
+            offset,
+            self.playlist.clone(),
+        )));
+        // `append` only returns once the old playlist is gone, so it can no
+        // longer take tracks from the shared queue.
+        self.playlist.clear();
+        self.playlist.set_current(track.id);
+        self.builder.line_up(
+            &self.tracks,
+            &self.playlist,
+            &self.shuffle,
+            track.id,
+            None,
+            true,
+        );
+        self.is_stopped.store(false, Ordering::Relaxed);
+        self.clock.set(position);
+
+        if was_playing {
+            sink.play();
+            self.clock.set_playing(true);
+        }
+
+        Ok(())
+    }
+
+    /// Tempo and pitch, channel routing, volume, limiting and the analysis
+    /// taps act on the mixed playlist, so both sides of a crossfade share them.
+    fn build_output(&self, playlist: Playlist) -> impl Source<Item = f32> + Send {
+        let held = Hold::new(playlist, self.builder.buffering.clone());
+        let stretched = TimeStretch::new(held, self.tempo.clone());
+        let shifted = PitchShift::new(stretched, self.tempo.clone());
+        let mapped = ChannelMapper::new(shifted, self.channels.clone());
+        let balanced = Balance::new(mapped, self.channels.clone());
+        let reverb = Reverb::with_controls(balanced, self.reverb.clone());
+        let volume = Gain::new(reverb, self.volume.clone());
+        let ducked = Gain::new(volume, self.ducking.gain());
+        let limited =
+            Limiter::with_controls(Gain::new(ducked, self.fade.clone()), self.limiter.clone());
+        let tapped = Tap::new(PcmTap::new(limited, &self.pcm_taps), self.spectrum.clone());
+        let metered = Meter::new(tapped, self.meter.clone());
+        let counted = OutputCounter::new(
+            metered,
+            self.builder.stats.clone(),
+            self.clock.clone(),
+            self.builder.signals.clone(),
+        );
+        StartGate::new(counted, self.start.clone())
+    }
+}
+
+/// What pausing and stopping act on, so the sleep timer's thread can do
+/// either without the player.
+struct Transport {
+    sink: Arc<Mutex<Sink>>,
+    playlist: Arc<PlaylistControls>,
+    clock: Arc<Clock>,
+    fade: Arc<GainControls>,
+    fade_out: Arc<Mutex<Duration>>,
+    is_stopped: Arc<AtomicBool>,
+    signals: Sender<Signal>,
+    bookmarks: Arc<Mutex<Option<Bookmarks>>>,
+    start: Arc<StartControls>,
+}
+
+impl Transport {
+    fn pause(&self, fade_out: Duration) {
+        if self.cancel_start() {
+            return;
+        }
+        if self.fade_out_and_pause(fade_out) && self.clock.set_playing(false) {
+            let _ = self.signals.send(Signal::Event(PlayerEvent::Paused));
+        }
+    }
+
+    /// Fades out over `fade_out` and pauses the sink. Returns `false`,
+    /// leaving it playing, if a `play` came in during the fade.
+    fn fade_out_and_pause(&self, fade_out: Duration) -> bool {
+        self.fade.set_ramp(fade_out);
+        self.fade.set_target(0.0);
+        if self.clock.is_playing() {
+            thread::sleep(fade_out);
+        }
+
+        let sink = self.sink.locked();
+        // A `play` that came in during the fade wins.
+        if self.fade.target() != 0.0 {
+            return false;
+        }
+        sink.pause();
+        true
+    }
+
+    fn stop(&self) {
+        // Where it was, before that's lost.
+        record_bookmark(&self.clock, &self.bookmarks, &self.signals);
+        let sink = self.sink.locked();
+        // The playlist keeps running until the output notices the stop.
+        self.playlist.detach();
+        sink.stop();
+        sink.pause();
+        // Start silent so the next `play` fades in.
+        self.fade.set_ramp(Duration::ZERO);
+        self.fade.set_target(0.0);
+
+        self.start.open();
+
+        self.clock.set_playing(false);
+        self.is_stopped.store(true, Ordering::Relaxed);
+        self.clock.set(Duration::ZERO);
+    }
+
+    /// Pauses a start scheduled with [`AudioPlayer::play_at`] that hasn't
+    /// gone yet. Returns `false`, doing nothing, if there is none.
+    fn cancel_start(&self) -> bool {
+        let sink = self.sink.locked();
+        if !self.start.hold_waiting() {
+            return false;
+        }
+        sink.pause();
+        self.start.open();
+        if self.clock.set_playing(false) {
+            let _ = self.signals.send(Signal::Event(PlayerEvent::Paused));
+        }
+        true
+    }
+}
+
+/// Fails unless `position` counts frames at the rate of `track`.
+fn check_rate(track: &Track, position: Position) -> Result<(), PlayerError> {
+    match position.sample_rate() == track.sample_rate {
+        true => Ok(()),
+        false => Err(PlayerError::InvalidArgument(format!(
+            "position is at {} Hz and the track at {} Hz; convert it with Position::at_rate",
+            position.sample_rate(),
+            track.sample_rate
+        ))),
+    }
+}
+
+/// Fails unless `range` is a part of a file lasting `duration`, where that
+/// is known, with something in it.
+fn check_range(range: TrackRange, duration: Option<Duration>) -> Result<(), PlayerError> {
+    let invalid = |message: String| Err(PlayerError::InvalidArgument(message));
+    let TrackRange { start, end } = range;
+    if let Some(end) = end.filter(|&end| end <= start) {
+        return invalid(format!(
+            "range end ({end:?}) must come after its start ({start:?})"
+        ));
+    }
+    match (duration, end) {
+        (Some(duration), _) if start >= duration => invalid(format!(
+            "range start ({start:?}) is past the end of the file ({duration:?})"
+        )),
+        (Some(duration), Some(end)) if end > duration => invalid(format!(
+            "range end ({end:?}) is past the end of the file ({duration:?})"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Bookmarks where the current file is, if it is a file, and saves the
+/// bookmarks.
+/// Index of the chapter playing at `position`: the last to start by then.
+pub(crate) fn chapter_at(chapters: &[Chapter], position: Duration) -> Option<usize> {
+    chapters
+        .iter()
+        .rposition(|chapter| chapter.start <= position)
+}
+
+/// Track `id` and those that play after it, in playing order.
+fn play_order_from(shuffle: &Mutex<Shuffle>, tracks: &Mutex<Vec<Track>>, id: u64) -> Vec<Track> {
+    let shuffle = shuffle.locked();
+    let tracks = tracks.locked();
+    if !shuffle.is_enabled() {
+        let index = tracks.iter().position(|track| track.id == id);
+        return index.map_or_else(Vec::new, |index| tracks[index..].to_vec());
+    }
+    iter::once(id)
+        .chain(
+            shuffle
+                .upcoming()
+                .iter()
+                .copied()
+                .filter(|&upcoming| upcoming != id),
+        )
+        .filter_map(|id| tracks.iter().find(|track| track.id == id).cloned())
+        .collect()
+}
+
+fn current_entry(
+    playlist: &PlaylistControls,
+    tracks: &Mutex<Vec<Track>>,
+) -> Option<(usize, Track)> {
+    let current = playlist.current();
+    let tracks = tracks.locked();
+    tracks
+        .iter()
+        .position(|track| track.id == current)
+        .map(|index| (index, tracks[index].clone()))
+}
+
+/// Where playback stands, as [`AudioPlayer::playback_state`] says.
+fn playback_state(
+    is_stopped: &AtomicBool,
+    playlist: &PlaylistControls,
+    sink: &Mutex<Sink>,
+    clock: &Clock,
+    buffering: &BufferControls,
+) -> PlaybackState {
+    if is_stopped.load(Ordering::Relaxed) || (playlist.is_finished() && sink.locked().empty()) {
+        return PlaybackState::Ended;
+    }
+    if !clock.is_playing() {
+        return PlaybackState::Paused;
+    }
+    match buffering.is_holding() {
+        true if buffering.is_buffering() => PlaybackState::Buffering,
+        true => PlaybackState::Opening,
+        false => PlaybackState::Playing,
+    }
+}
+
+fn record_bookmark(clock: &Clock, bookmarks: &Mutex<Option<Bookmarks>>, signals: &Sender<Signal>) {
+    let mut bookmarks = bookmarks.locked();
+    let (Some(bookmarks), Some(track)) = (bookmarks.as_mut(), clock.current_track()) else {
+        return;
+    };
+    if !matches!(track.origin, Origin::File) {
+        return;
+    }
+    bookmarks.record(&track.path, clock.file_position(), track.duration);
+    if let Err(err) = bookmarks.save() {
+        let message = format!(
+            "can't save bookmarks to {}: {err}",
+            bookmarks.path().display()
+        );
+        let _ = signals.send(Signal::Event(PlayerEvent::Warning(message)));
+    }
+}
+
+fn save_history(history: &History, signals: &Sender<Signal>) {
+    if let Err(err) = history.save() {
+        let message = format!("can't save history to {}: {err}", history.path().display());
+        let _ = signals.send(Signal::Event(PlayerEvent::Warning(message)));
+    }
+}
+
+fn save_autosave(snapshot: &Snapshot, path: &Mutex<Option<PathBuf>>, signals: &Sender<Signal>) {
+    let Some(path) = path.locked().clone() else {
+        return;
+    };
+    if let Err(err) = snapshot.capture().save(&path) {
+        let message = format!("can't save the player state to {}: {err}", path.display());
+        let _ = signals.send(Signal::Event(PlayerEvent::Warning(message)));
+    }
+}
+
+/// What [`PlayerState`] is read from, shared with the autosave thread.
+#[derive(Clone)]
+struct Snapshot {
+    tracks: Arc<Mutex<Vec<Track>>>,
+    playlist: Arc<PlaylistControls>,
+    clock: Arc<Clock>,
+    eq: Arc<EqControls>,
+    shuffle: Arc<Mutex<Shuffle>>,
+    volume_db: Arc<AtomicF32>,
+    muted: Arc<AtomicBool>,
+}
+
+impl Snapshot {
+    fn capture(&self) -> PlayerState {
+        let current = self.clock.current_track().map(|track| track.id);
+        let position = self.clock.file_position();
+        let mut at = None;
+        let (queue, ranges) = self
+            .tracks
+            .locked()
+            .iter()
+            .filter(|track| !matches!(track.origin, Origin::Stdin(_) | Origin::Generated(_)))
+            .enumerate()
+            .map(|(index, track)| {
+                if Some(track.id) == current {
+                    at = Some(index);
+                }
+                (track.path.clone(), track.range)
+            })
+            .unzip();
+        PlayerState {
+            queue,
+            ranges,
+            current: at,
+            position: if at.is_some() {
+                position
+            } else {
+                Duration::ZERO
+            },
+            volume_db: self.volume_db.load(),
+            muted: self.muted.load(Ordering::Relaxed),
+            eq: self.eq.settings(),
+            eq_enabled: self.eq.is_enabled(),
+            repeat: self.playlist.repeat(),
+            shuffle: self.shuffle.locked().is_enabled(),
+        }
+    }
+}
+
+/// What the per-track chain is built from, shared with the event thread so
+/// it can prepare repeats while the player is idle.
+#[derive(Clone)]
+struct TrackBuilder {
+    eq: Arc<EqControls>,
+    compressor: Arc<CompressorControls>,
+    tone: Arc<ToneControls>,
+    width: Arc<WidthControls>,
+    vocal: Arc<VocalControls>,
+    looping: Arc<LoopControls>,
+    segments: Arc<SegmentControls>,
+    loudness: Arc<LoudnessControls>,
+    replay_gain: Arc<ReplayGainControls>,
+    albums: Arc<AlbumControls>,
+    silence: Arc<SilenceControls>,
+    decoding: Arc<DecodeControls>,
+    resampling: Arc<ResampleControls>,
+    stats: Arc<StatsControls>,
+    buffering: Arc<BufferControls>,
+    signals: Sender<Signal>,
+}
+
+impl TrackBuilder {
+    /// The per-track part of the chain: counting, segment end, loop, silence skipping, ReplayGain
+    /// or loudness normalization, vocal reduction, EQ with stereo width on either side,
+    /// bass and treble, compression, and resampling to the rate of the mix where
+    /// that differs.
+    fn build(
+        &self,
+        decoder: impl Source<Item = f32> + Send + 'static,
+        track: &Track,
+    ) -> TrackSource {
+        let decoder = DecodeCounter::new(decoder, self.stats.clone());
+        let decoder = SegmentEnd::new(decoder, &self.segments, track.id);
+        let decoder = Looper::new(
+            decoder,
+            self.looping.clone(),
+            track.id,
+            track.metadata.loop_points,
+        );
+        let decoder = SilenceSkip::new(
+            decoder,
+            self.silence.clone(),
+            self.signals.clone(),
+            track.lead,
+        );
+        let decoder = if self.albums.is_enabled() {
+            let gain = self.album_gain(track);
+            let controls = self.albums.controls(track.id, gain);
+            self.set_album_gain(track.id, gain);
+            Gain::new(decoder, controls).handing_over(self.albums.handover())
+        } else {
+            let normalizing = self.loudness.target().is_some();
+            let replay_gain = self
+                .replay_gain
+                .gain(&track.metadata.replay_gain, normalizing);
+            self.replay_gain.set_applied(track.id, replay_gain);
+            let gain = match replay_gain {
+                Some(gain) => Arc::new(GainControls::new(
+                    db_to_linear(gain.gain_db),
+                    Duration::ZERO,
+                )),
+                None => self.loudness.gain(track.id),
+            };
+            Gain::new(decoder, gain)
+        };
+        let decoder = VocalReduction::new(decoder, self.vocal.clone(), self.signals.clone());
+        let decoder = StereoWidth::new(decoder, self.width.clone(), WidthPlacement::BeforeEq);
+        let decoder = Equalizer::with_controls(decoder, self.eq.clone());
+        let decoder = StereoWidth::new(decoder, self.width.clone(), WidthPlacement::AfterEq);
+        let decoder = Tone::new(decoder, self.tone.clone(), self.eq.clone());
+        let decoder = Compressor::with_controls(decoder, self.compressor.clone());
+        let sample_rate = self.resampling.sample_rate();
+        if decoder.sample_rate() == sample_rate {
+            return Box::new(decoder);
+        }
+        let quality = self.resampling.quality();
+        Box::new(Resampler::new(decoder, quality, sample_rate))
+    }
+
+    /// The gain `track` plays at in album playback: its album run's, once
+    /// known, or else its own.
+    fn album_gain(&self, track: &Track) -> TrackGain {
+        let run = self.albums.run_of(track.id);
+        if let Some(gain) = run.and_then(|run| self.run_gain(&run)) {
+            return gain;
+        }
+        let normalizing = self.loudness.target().is_some();
+        match self
+            .replay_gain
+            .gain(&track.metadata.replay_gain, normalizing)
+        {
+            Some(gain) => gain,
+            None if normalizing => TrackGain {
+                mode: GainMode::Normalized,
+                gain_db: self.loudness.gain_db(track.id),
+            },
+            None => TrackGain::OFF,
+        }
+    }
+
+    /// The gain of album run `run`: the album ReplayGain, if on and every
+    /// track has one, or else its measured loudness taken to the target;
+    /// `None` until measured, or with neither on.
+    fn run_gain(&self, run: &AlbumRun) -> Option<TrackGain> {
+        if let Some(gain_db) = self.run_tagged_db(run) {
+            return Some(TrackGain {
+                mode: GainMode::ReplayGainAlbum,
+                gain_db,
+            });
+        }
+        Some(TrackGain {
+            mode: GainMode::AlbumNormalized,
+            gain_db: run.measured()?.gain_to(self.run_target()?),
+        })
+    }
+
+    /// The lowest album ReplayGain of the tracks of `run`, if on and every
+    /// one has one.
+    fn run_tagged_db(&self, run: &AlbumRun) -> Option<f32> {
+        if self.replay_gain.mode() == ReplayGainMode::Off {
+            return None;
+        }
+        run.tags
+            .iter()
+            .map(|tags| self.replay_gain.album_gain_db(tags))
+            .try_fold(f32::INFINITY, |lowest, gain_db| Some(lowest.min(gain_db?)))
+    }
+
+    /// The level a measured album run is brought to.
+    fn run_target(&self) -> Option<f32> {
+        self.loudness.target().or_else(|| {
+            (self.replay_gain.mode() != ReplayGainMode::Off)
+                .then(|| REPLAYGAIN_REFERENCE_LUFS + self.replay_gain.preamp_db())
+        })
+    }
+
+    /// Whether `run` has to be measured for its gain.
+    fn measures(&self, run: &AlbumRun) -> bool {
+        self.run_target().is_some() && self.run_tagged_db(run).is_none()
+    }
+
+    /// Moves every queued track over to its gain in album playback.
+    fn relevel(&self, tracks: &Mutex<Vec<Track>>) {
+        if !self.albums.is_enabled() {
+            return;
+        }
+        let tracks = tracks.locked().clone();
+        for track in &tracks {
+            self.set_album_gain(track.id, self.album_gain(track));
+        }
+    }
+
+    fn set_album_gain(&self, id: u64, gain: TrackGain) {
+        self.albums.set_gain(id, gain);
+        self.replay_gain
+            .set_applied(id, Some(gain).filter(TrackGain::is_replaygain));
+    }
+
+    /// Opens `track` for playing, from its cache once that is filled. A
+    /// file is otherwise decoded ahead of playback.
+    fn open_track(&self, track: &Track) -> Result<DecodedSource, PlayerError> {
+        let decoder = self.open_untrimmed(track)?;
+        Ok(match track.range {
+            Some(range) => Box::new(Trim::new(decoder, range)),
+            None => decoder,
+        })
+    }
+
+    /// [`TrackBuilder::open_track`] of the whole file.
+    fn open_untrimmed(&self, track: &Track) -> Result<DecodedSource, PlayerError> {
+        let Some(cache) = &track.cache else {
+            let decoder = self.open_decoder(&track.path, &track.origin)?;
+            return Ok(match track.origin {
+                Origin::File => Box::new(self.buffering.wrap(track.id, decoder)),
+                _ => decoder,
+            });
+        };
+        let decoder = match cache.status() {
+            CacheStatus::Ready => None,
+            _ => Some(self.open_decoder(&track.path, &track.origin)?),
+        };
+        Ok(Box::new(CachedSource::new(decoder, cache.clone())))
+    }
+
+    /// Opens `path` for playing, getting past damage in a file with a
+    /// [`PlayerEvent::DecodeError`] for each.
+    fn open_decoder(&self, path: &Path, origin: &Origin) -> Result<DecodedSource, PlayerError> {
+        match origin {
+            Origin::File => {
+                self.stats.add_decoder_opened();
+                let signals = Some(self.signals.clone());
+                Ok(Box::new(FaultTolerant::open(
+                    path,
+                    self.decoding.clone(),
+                    signals,
+                    Some(self.stats.clone()),
+                )?))
+            }
+            _ => open_decoder(path, origin, self.decoding.backend()),
+        }
+    }
+
+    /// Opens the track that plays after `current` and lines it up, so it
+    /// follows without a gap. Only that one is opened ahead, however long
+    /// the queue; the events thread lines up the next as each track starts.
+    /// What is lined up already stays unless `reopen`. `opened` is used if
+    /// it is the decoder of the track to line up. One that won't open is
+    /// skipped with a [`PlayerEvent::Warning`].
+    fn line_up(
+        &self,
+        tracks: &Mutex<Vec<Track>>,
+        playlist: &PlaylistControls,
+        shuffle: &Mutex<Shuffle>,
+        current: u64,
+        mut opened: Option<(u64, DecodedSource)>,
+        reopen: bool,
+    ) {
+        for track in play_order_from(shuffle, tracks, current).iter().skip(1) {
+            if !reopen && playlist.next_id() == Some(track.id) {
+                return;
+            }
+            let decoder = match opened.take_if(|(id, _)| *id == track.id) {
+                Some((_, decoder)) => Ok(decoder),
+                None => self.open_track(track),
+            };
+            match decoder {
+                Ok(decoder) => {
+                    let source = self.build(decoder, track);
+                    playlist.line_up(Some((track.id, source, track.duration)));
+                    return;
+                }
+                Err(err) => {
+                    let message = format!("skipping {}: {err}", track.path.display());
+                    let _ = self
+                        .signals
+                        .send(Signal::Event(PlayerEvent::Warning(message)));
+                }
+            }
+        }
+        playlist.line_up(None);
+    }
+
+    /// Opens whatever the repeat mode plays once track `id` ends, so the
+    /// playlist can carry on without a gap. A shuffled queue gets a new order
+    /// for each cycle.
+    fn prepare_repeat(
+        &self,
+        tracks: &Mutex<Vec<Track>>,
+        playlist: &PlaylistControls,
+        shuffle: &Mutex<Shuffle>,
+        id: u64,
+    ) {
+        let tracks = tracks.locked().clone();
+        match playlist.repeat() {
+            RepeatMode::Off => {}
+            RepeatMode::One => {
+                let Some(track) = tracks.iter().find(|track| track.id == id) else {
+                    return;
+                };
+                if let Ok(decoder) = self.open_track(track) {
+                    playlist.prepare_again(id, self.build(decoder, track), track.duration);
+                }
+            }
+            RepeatMode::All => {
+                let mut shuffle = shuffle.locked();
+                let order = if shuffle.is_enabled() {
+                    if !shuffle.upcoming().is_empty() || playlist.has_upcoming() {
+                        return;
+                    }
+                    let ids = tracks.iter().map(|track| track.id).collect::<Vec<_>>();
+                    let order = shuffle.next_cycle(&ids, id);
+                    order
+                        .iter()
+                        .filter_map(|&id| tracks.iter().find(|track| track.id == id).cloned())
+                        .collect()
+                } else {
+                    if tracks.last().is_none_or(|last| last.id != id) || playlist.has_upcoming() {
+                        return;
+                    }
+                    tracks
+                };
+                drop(shuffle);
+                // The rest are lined up one by one as the new cycle plays.
+                let first = order.iter().find_map(|track| {
+                    let decoder = self.open_track(track).ok()?;
+                    Some((track, decoder))
+                });
+                if let Some((track, decoder)) = first {
+                    playlist.prepare_wrap(track.id, self.build(decoder, track), track.duration);
+                }
+            }
+        }
+    }
+}
+
+type DecodedSource = Box<dyn Source<Item = f32> + Send>;
+
+/// Opens `path` for playing, a file with `backend`.
+fn open_decoder(
+    path: &Path,
+    origin: &Origin,
+    backend: DecoderBackend,
+) -> Result<DecodedSource, PlayerError> {
+    match origin {
+        Origin::File => decode::open_file(path, backend),
+        Origin::Http(download) => Ok(Box::new(StreamSource::open(download)?)),
+        Origin::Stdin(stdin) => Ok(Box::new(stdin.decoder()?)),
+        Origin::Generated(source) => Ok(source.open()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        equalizer::{BAND_COUNT, THIRD_OCTAVE_BAND_COUNT},
+        testing::{null_player, wait_for, FlacFile, WavFile},
+    };
+    use std::fs;
+
+    const LENGTH: Duration = Duration::from_secs(2);
+    /// Long enough for anything the player's threads do in answer to a call.
+    const WAIT: Duration = Duration::from_secs(3);
+
+    /// Plays the track at `file` from the start, its events from then on.
+    fn playing(file: &WavFile) -> (AudioPlayer, mpsc::Receiver<PlayerEvent>) {
+        let player = null_player();
+        player.enqueue(&file.path).unwrap();
+        let events = player.subscribe();
+        player.play().unwrap();
+        (player, events)
+    }
+
+    /// Seeks to `position` and checks the track ends there, the position
+    /// not going past its duration after.
+    fn assert_seek_ends_the_track(file: &WavFile, position: Duration) {
+        let (player, events) = playing(file);
+        assert_eq!(player.duration(), Some(LENGTH));
+        player.seek(position).unwrap();
+        assert_eq!(
+            wait_for(&events, WAIT, |event| matches!(
+                event,
+                PlayerEvent::Seeking(_)
+            )),
+            Some(PlayerEvent::Seeking(LENGTH))
+        );
+        assert!(player.get_playback_position() <= LENGTH);
+        assert_eq!(
+            wait_for(&events, WAIT, |event| matches!(
+                event,
+                PlayerEvent::TrackEnded(_)
+            )),
+            Some(PlayerEvent::TrackEnded(file.path.clone()))
+        );
+        for _ in 0..10 {
+            assert!(player.get_playback_position() <= LENGTH);
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(player.playback_state(), PlaybackState::Ended);
+    }
+
+    #[test]
+    fn the_position_only_moves_forward_while_the_eq_is_changed_from_other_threads() {
+        let file = WavFile::sine("position-under-eq-changes", LENGTH);
+        let (player, _events) = playing(&file);
+        let deadline = Instant::now() + WAIT;
+        while player.get_playback_position().is_zero() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let started = player.get_playback_position();
+        assert!(!started.is_zero(), "playback never started");
+        let player = Arc::new(player);
+        let threads = (0..4u32)
+            .map(|thread| {
+                let player = player.clone();
+                thread::spawn(move || {
+                    let (mut last, mut change) = (Duration::ZERO, 0u32);
+                    let until = Instant::now() + Duration::from_millis(500);
+                    while Instant::now() < until {
+                        change += 1;
+                        if thread % 2 == 0 {
+                            let gain = ((thread + change) % 25) as f32 - 12.0;
+                            player.set_eq_gains(&[gain; BAND_COUNT]).unwrap();
+                        }
+                        let position = player.get_playback_position();
+                        assert!(position >= last, "went back from {last:?} to {position:?}");
+                        assert!(position <= LENGTH);
+                        last = position;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(player.get_playback_position() > started);
+    }
+
+    #[test]
+    fn a_seek_past_the_end_ends_the_track_at_its_duration() {
+        let file = WavFile::sine("seek-past-end", LENGTH);
+        assert_seek_ends_the_track(&file, LENGTH + Duration::from_secs(10));
+    }
+
+    #[test]
+    fn a_seek_to_the_duration_ends_the_track() {
+        let file = WavFile::sine("seek-to-end", LENGTH);
+        assert_seek_ends_the_track(&file, LENGTH);
+    }
+
+    #[test]
+    fn a_seek_to_zero_plays_on_from_the_start() {
+        let file = WavFile::sine("seek-to-zero", LENGTH);
+        let (player, events) = playing(&file);
+        thread::sleep(Duration::from_millis(500));
+        assert!(player.get_playback_position() >= Duration::from_millis(300));
+        player.seek(Duration::ZERO).unwrap();
+        assert_eq!(
+            wait_for(&events, WAIT, |event| matches!(
+                event,
+                PlayerEvent::Seeked(_)
+            )),
+            Some(PlayerEvent::Seeked(Duration::ZERO))
+        );
+        assert!(player.get_playback_position() < Duration::from_millis(200));
+        assert!(player.is_playing());
+        thread::sleep(Duration::from_millis(300));
+        assert!(player.get_playback_position() >= Duration::from_millis(100));
+        assert!(events
+            .try_iter()
+            .all(|event| !matches!(event, PlayerEvent::TrackEnded(_))));
+    }
+
+    /// Samples the position and the wall time played every few
+    /// milliseconds until the track ends, or for `for_at_most`.
+    fn sample_until_ended(
+        player: &AudioPlayer,
+        events: &mpsc::Receiver<PlayerEvent>,
+        for_at_most: Duration,
+    ) -> Vec<(Duration, Duration)> {
+        let (mut samples, until) = (Vec::new(), Instant::now() + for_at_most);
+        while Instant::now() < until {
+            samples.push((player.position(), player.elapsed_wall_time()));
+            if events
+                .try_iter()
+                .any(|event| matches!(event, PlayerEvent::TrackEnded(_)))
+            {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        samples
+    }
+
+    #[test]
+    fn at_double_speed_the_position_reaches_the_duration_in_half_the_time() {
+        let file = WavFile::sine("double-speed", LENGTH);
+        let player = null_player();
+        player.enqueue(&file.path).unwrap();
+        player.set_speed(2.0);
+        let events = player.subscribe();
+        let started = Instant::now();
+        player.play().unwrap();
+        let samples = sample_until_ended(&player, &events, WAIT);
+        let took = started.elapsed();
+        assert!(
+            took > LENGTH / 2 - Duration::from_millis(150)
+                && took < LENGTH / 2 + Duration::from_millis(400),
+            "took {took:?} to play {LENGTH:?} at 2.0x"
+        );
+        let &(end, _) = samples.last().unwrap();
+        assert!(
+            end >= LENGTH - Duration::from_millis(100) && end <= LENGTH,
+            "ended at {end:?}"
+        );
+        // From once playback is under way to before it ends, so neither
+        // starting nor the end being noticed is counted.
+        let playing = samples
+            .iter()
+            .filter(|&&(position, _)| {
+                position >= Duration::from_millis(200)
+                    && position <= LENGTH - Duration::from_millis(200)
+            })
+            .collect::<Vec<_>>();
+        let (first, last) = (playing[0], playing[playing.len() - 1]);
+        let (position, wall) = (last.0 - first.0, last.1 - first.1);
+        let rate = position.as_secs_f64() / wall.as_secs_f64();
+        assert!(
+            (1.8..2.2).contains(&rate),
+            "{position:?} played in {wall:?} of wall time"
+        );
+    }
+
+    #[test]
+    fn changing_speed_midway_moves_neither_value_with_a_jump() {
+        let file = WavFile::sine("speed-change", LENGTH);
+        let (player, events) = playing(&file);
+        thread::sleep(Duration::from_millis(300));
+        player.set_speed(2.0);
+        let samples = sample_until_ended(&player, &events, Duration::from_millis(300));
+        player.set_speed(1.0);
+        let samples = [
+            samples,
+            sample_until_ended(&player, &events, Duration::from_millis(200)),
+        ]
+        .concat();
+        for pair in samples.windows(2) {
+            let [(position, wall), (next_position, next_wall)] = [pair[0], pair[1]];
+            assert!(
+                next_position >= position && next_position - position < Duration::from_millis(100),
+                "position went from {position:?} to {next_position:?}"
+            );
+            assert!(
+                next_wall >= wall && next_wall - wall < Duration::from_millis(50),
+                "wall time went from {wall:?} to {next_wall:?}"
+            );
+        }
+        let &(position, wall) = samples.last().unwrap();
+        // 300 ms at 1.0x, 300 at 2.0x and 200 at 1.0x.
+        assert!(
+            position > wall + Duration::from_millis(200),
+            "at {position:?} after {wall:?}"
+        );
+    }
+
+    #[test]
+    fn a_source_below_the_top_bands_nyquist_plays_with_either_layout() {
+        let file = WavFile::sine_at("low-rate", LENGTH, 22050);
+        for settings in [
+            EqSettings::default(),
+            EqSettings::third_octave(&[3.0; THIRD_OCTAVE_BAND_COUNT]),
+        ] {
+            let player = null_player();
+            player.set_eq_settings(settings);
+            player.enqueue(&file.path).unwrap();
+            let events = player.subscribe();
+            player.play().unwrap();
+            assert_eq!(
+                wait_for(&events, WAIT, |event| matches!(
+                    event,
+                    PlayerEvent::TrackEnded(_)
+                )),
+                Some(PlayerEvent::TrackEnded(file.path.clone()))
+            );
+        }
+    }
+
+    /// How many of this process's file descriptors are open on `path`, once
+    /// down to `expected` or after [`WAIT`]. The decoding-ahead thread may
+    /// hold a dropped decoder a moment longer.
+    #[cfg(target_os = "linux")]
+    fn open_fds(path: &Path, expected: usize) -> usize {
+        let count = || {
+            fs::read_dir("/proc/self/fd")
+                .unwrap()
+                .filter_map(|fd| fs::read_link(fd.ok()?.path()).ok())
+                .filter(|target| target == path)
+                .count()
+        };
+        let deadline = Instant::now() + WAIT;
+        loop {
+            let open = count();
+            if open <= expected || Instant::now() > deadline {
+                return open;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn a_long_playlist_keeps_only_the_playing_and_next_tracks_open() {
+        let file = WavFile::sine("long-queue", LENGTH);
+        let player = null_player();
+        // More entries than the usual limit of 1024 open files.
+        let text = format!("{}\n", file.path.display()).repeat(1200);
+        player
+            .enqueue_playlist(&playlist::Playlist::parse(&text))
+            .unwrap();
+        assert_eq!(player.queue().len(), 1200);
+        assert_eq!(open_fds(&file.path, 2), 2);
+        let events = player.subscribe();
+        player.play().unwrap();
+        player.seek(LENGTH - Duration::from_millis(100)).unwrap();
+        wait_for(&events, WAIT, |event| {
+            matches!(event, PlayerEvent::TrackEnded(_))
+        })
+        .unwrap();
+        // The third is opened as the second starts.
+        wait_for(&events, WAIT, |event| {
+            matches!(event, PlayerEvent::TrackStarted(_))
+        })
+        .unwrap();
+        assert_eq!(open_fds(&file.path, 2), 2);
+    }
+
+    #[test]
+    fn a_later_track_that_wont_open_is_skipped_not_failing_a_jump() {
+        let short = Duration::from_millis(300);
+        let files = ["skip-a", "skip-b", "skip-c"].map(|name| WavFile::sine(name, short));
+        let player = null_player();
+        for file in &files {
+            player.enqueue(&file.path).unwrap();
+        }
+        fs::remove_file(&files[2].path).unwrap();
+        let events = player.subscribe();
+        let second = player.queue()[1].id;
+        assert!(player.play_item(second).unwrap());
+        let skipped = format!("skipping {}", files[2].path.display());
+        assert!(wait_for(&events, WAIT, |event| matches!(
+            event,
+            PlayerEvent::Warning(message) if message.starts_with(&skipped)
+        ))
+        .is_some());
+        player.play().unwrap();
+        assert_eq!(
+            wait_for(&events, WAIT, |event| matches!(
+                event,
+                PlayerEvent::TrackEnded(_)
+            )),
+            Some(PlayerEvent::TrackEnded(files[1].path.clone()))
+        );
+        assert!(player.playlist.wait_finished(WAIT));
+    }
+
+    /// Scrubs 30 times in a second back and forth over the 20 s track at
+    /// `path`, and returns how many decoders were opened meanwhile, and
+    /// where it plays from after.
+    fn decoders_for_thirty_scrubs(path: &Path) -> (u64, Duration) {
+        let length = Duration::from_secs(20);
+        let player = null_player();
+        player.enqueue(path).unwrap();
+        player.play().unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let before = player.stats().decoders_opened;
+        for index in 0..30u32 {
+            player.scrub(length * (index * 7 % 19) / 20).unwrap();
+            thread::sleep(Duration::from_millis(30));
+        }
+        thread::sleep(Duration::from_millis(300));
+        (
+            player.stats().decoders_opened - before,
+            player.get_playback_position(),
+        )
+    }
+
+    /// Where the last of [`decoders_for_thirty_scrubs`] went: 29 * 7 % 19 =
+    /// 13 of 20 parts.
+    const LAST_SCRUB: Duration = Duration::from_secs(13);
+
+    #[test]
+    fn thirty_scrubs_in_a_second_make_no_decoder_where_it_seeks() {
+        let file = WavFile::sine("scrubs", Duration::from_secs(20));
+        let (opened, position) = decoders_for_thirty_scrubs(&file.path);
+        assert_eq!(opened, 0);
+        assert!(position >= LAST_SCRUB && position < LAST_SCRUB + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn thirty_scrubs_in_a_second_make_a_handful_of_decoders_where_it_cant_seek() {
+        let file = FlacFile::sine("scrubs", Duration::from_secs(20));
+        let (opened, position) = decoders_for_thirty_scrubs(&file.path);
+        assert!(opened <= 3, "{opened} decoders for 30 scrubs");
+        assert!(position >= LAST_SCRUB && position < LAST_SCRUB + Duration::from_secs(1));
+    }
+}
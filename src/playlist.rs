@@ -0,0 +1,144 @@
+use crate::format::{read_text, ParseError};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// One entry of a [`Playlist`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    /// A file, resolved against the playlist's directory by
+    /// [`Playlist::load`], or an `http://` or `https://` URL.
+    pub path: PathBuf,
+    /// From `#EXTINF`.
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// An M3U or extended M3U8 playlist.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Playlist {
+    /// In playing order.
+    pub entries: Vec<PlaylistEntry>,
+    /// Lines that were skipped, and why.
+    pub warnings: Vec<ParseError>,
+}
+
+impl Playlist {
+    /// Reads and parses the playlist at `path`. Only failing to read it is an
+    /// error; entries that can't be played, such as URLs other than
+    /// `http://` and `https://`, are skipped and listed in [`Playlist::warnings`]. Whether
+    /// the files exist is not checked here.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut playlist = Playlist::parse(&read_text(path)?);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for entry in &mut playlist.entries {
+            if !is_url(&entry.path) {
+                entry.path = dir.join(&entry.path);
+            }
+        }
+        Ok(playlist)
+    }
+
+    /// Parses playlist text, leaving relative paths as written.
+    pub fn parse(text: &str) -> Self {
+        let mut playlist = Playlist::default();
+        // The `#EXTINF` for the next entry.
+        let mut info = None;
+
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+                info = Some(parse_extinf(extinf));
+                continue;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (title, duration) = info.take().unwrap_or_default();
+            let path = match line.split_once("://") {
+                Some((scheme, _))
+                    if scheme.eq_ignore_ascii_case("http")
+                        || scheme.eq_ignore_ascii_case("https") =>
+                {
+                    PathBuf::from(line)
+                }
+                Some((scheme, rest)) if scheme.eq_ignore_ascii_case("file") => {
+                    // `file:///music/a.flac`, optionally with a host before the path.
+                    let path = rest.find('/').map_or(rest, |start| &rest[start..]);
+                    PathBuf::from(percent_decode(path))
+                }
+                Some((scheme, _)) => {
+                    let message = format!("can't play {scheme} URLs yet, skipping {line}");
+                    playlist.warnings.push(ParseError::new(index + 1, message));
+                    continue;
+                }
+                // Playlists written on Windows separate with backslashes.
+                None if !cfg!(windows) => PathBuf::from(line.replace('\\', "/")),
+                None => PathBuf::from(line),
+            };
+            playlist.entries.push(PlaylistEntry {
+                path,
+                title,
+                duration,
+            });
+        }
+        playlist
+    }
+}
+
+/// Whether `path` is a URL rather than a file.
+pub(crate) fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.contains("://"))
+}
+
+/// `<seconds>[ attributes],<title>`. Attribute values are quoted and may
+/// hold commas, so the title starts at the first comma outside quotes.
+fn parse_extinf(text: &str) -> (Option<String>, Option<Duration>) {
+    let mut quoted = false;
+    let comma = text.char_indices().find_map(|(index, c)| {
+        quoted ^= c == '"';
+        (c == ',' && !quoted).then_some(index)
+    });
+    let (head, title) = match comma {
+        Some(comma) => (&text[..comma], Some(text[comma + 1..].trim())),
+        None => (text, None),
+    };
+    // Unknown lengths are written as -1.
+    let duration = head
+        .split_whitespace()
+        .next()
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64);
+    let title = title.filter(|title| !title.is_empty()).map(str::to_string);
+    (title, duration)
+}
+
+/// Decodes `%xx` escapes, leaving malformed ones as they are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,220 @@
+//! Just enough PNG to write an 8-bit RGB image: each row filtered the way
+//! that leaves it smallest, and compressed with fixed-Huffman deflate.
+
+/// Bytes back a match can reach, and the longest a match can be.
+const WINDOW: usize = 32 * 1024;
+const MAX_MATCH: usize = 258;
+const MIN_MATCH: usize = 3;
+
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Encodes `width` by `height` pixels of `rgb`, three bytes each, row by
+/// row from the top.
+pub(crate) fn encode(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, deflate, adaptive filtering, no interlace.
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"IDAT", &zlib(&filter(width as usize * 3, rgb)));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Rows of `stride` bytes, each led by the filter that makes it sum to the
+/// least, reading its bytes as signed: none, the byte to the left, or the
+/// byte above.
+fn filter(stride: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut filtered = Vec::with_capacity(rgb.len() + rgb.len() / stride.max(1));
+    let mut candidates = [vec![0; stride], vec![0; stride], vec![0; stride]];
+    let mut previous: &[u8] = &[];
+    for row in rgb.chunks(stride.max(1)) {
+        for (i, &byte) in row.iter().enumerate() {
+            let left = if i >= 3 { row[i - 3] } else { 0 };
+            let up = previous.get(i).copied().unwrap_or(0);
+            candidates[0][i] = byte;
+            candidates[1][i] = byte.wrapping_sub(left);
+            candidates[2][i] = byte.wrapping_sub(up);
+        }
+        let cost = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|&byte| u64::from((byte as i8).unsigned_abs()))
+                .sum::<u64>()
+        };
+        let (kind, best) = candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, candidate)| cost(&candidate[..row.len()]))
+            .expect("three candidates");
+        filtered.push(kind as u8);
+        filtered.extend_from_slice(&best[..row.len()]);
+        previous = row;
+    }
+    filtered
+}
+
+/// `data` as a zlib stream.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // 32 KiB window, no dictionary, and the check bits that make that a
+    // multiple of 31.
+    bits.bytes.extend_from_slice(&[0x78, 0x01]);
+    deflate(data, &mut bits);
+    let mut stream = bits.finish();
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+/// Compresses `data` as one fixed-Huffman block, matching each position
+/// against the last one that began with the same three bytes.
+fn deflate(data: &[u8], bits: &mut BitWriter) {
+    // Last block, fixed codes.
+    bits.write(0b011, 3);
+    let mut heads = vec![usize::MAX; 1 << HASH_BITS];
+    let hash = |at: usize| {
+        let key =
+            u32::from(data[at]) << 16 | u32::from(data[at + 1]) << 8 | u32::from(data[at + 2]);
+        (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+    let mut at = 0;
+    while at < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+        if at + MIN_MATCH <= data.len() {
+            let slot = hash(at);
+            let candidate = heads[slot];
+            heads[slot] = at;
+            if candidate != usize::MAX && at - candidate <= WINDOW {
+                let longest = (data.len() - at).min(MAX_MATCH);
+                length = (0..longest)
+                    .take_while(|&i| data[candidate + i] == data[at + i])
+                    .count();
+                distance = at - candidate;
+            }
+        }
+        if length < MIN_MATCH {
+            literal(bits, u16::from(data[at]));
+            at += 1;
+            continue;
+        }
+        write_match(bits, length, distance);
+        // Index what the match covers, so later matches can reach into it.
+        for skipped in at + 1..(at + length).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+            heads[hash(skipped)] = skipped;
+        }
+        at += length;
+    }
+    literal(bits, 256);
+}
+
+/// Writes literal or length symbol `symbol` in the fixed code.
+fn literal(bits: &mut BitWriter, symbol: u16) {
+    let (code, length) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+    bits.write_code(u32::from(code), length);
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let index = LENGTH_BASES.partition_point(|&base| usize::from(base) <= length) - 1;
+    literal(bits, 257 + index as u16);
+    bits.write(
+        (length - usize::from(LENGTH_BASES[index])) as u32,
+        LENGTH_EXTRA[index].into(),
+    );
+    let index = DISTANCE_BASES.partition_point(|&base| usize::from(base) <= distance) - 1;
+    bits.write_code(index as u32, 5);
+    bits.write(
+        (distance - usize::from(DISTANCE_BASES[index])) as u32,
+        DISTANCE_EXTRA[index].into(),
+    );
+}
+
+/// Packs bits into bytes from the least significant end, as deflate does.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `count` bits of `value`, lowest first.
+    fn write(&mut self, value: u32, count: u32) {
+        self.pending |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.pending as u8);
+            self.pending >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code of `count` bits, which go highest first.
+    fn write_code(&mut self, code: u32, count: u32) {
+        self.write(code.reverse_bits() >> (32 - count), count);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.pending as u8);
+        }
+        self.bytes
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
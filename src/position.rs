@@ -0,0 +1,207 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::{Add, AddAssign, Sub, SubAssign},
+    time::Duration,
+};
+
+/// A point in a track, or a length of one, as a frame count at the track's
+/// sample rate, so sample-accurate work doesn't round through [`Duration`].
+///
+/// Positions at different rates compare by the time they stand for, but
+/// adding or subtracting them panics: take one to the other's rate with
+/// [`Position::at_rate`] first, or use [`Position::checked_add`] and
+/// [`Position::checked_sub`], which give `None` instead.
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    frame: u64,
+    sample_rate: u32,
+}
+
+impl Position {
+    /// Frame `frame` at `sample_rate`, which is taken as at least 1.
+    pub fn new(frame: u64, sample_rate: u32) -> Self {
+        Position {
+            frame,
+            sample_rate: sample_rate.max(1),
+        }
+    }
+
+    /// The start, at `sample_rate`.
+    pub fn zero(sample_rate: u32) -> Self {
+        Self::new(0, sample_rate)
+    }
+
+    /// The frame nearest `time` at `sample_rate`.
+    pub fn from_duration(time: Duration, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate.max(1);
+        let frame = (time.as_nanos() * sample_rate as u128 + 500_000_000) / 1_000_000_000;
+        Self::new(frame.min(u64::MAX as u128) as u64, sample_rate)
+    }
+
+    pub fn as_frames(self) -> u64 {
+        self.frame
+    }
+
+    pub fn sample_rate(self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn as_secs_f64(self) -> f64 {
+        self.frame as f64 / self.sample_rate as f64
+    }
+
+    /// The time of the frame, to the nanosecond below.
+    pub fn as_duration(self) -> Duration {
+        let nanos = self.frame as u128 * 1_000_000_000 / self.sample_rate as u128;
+        let secs = (nanos / 1_000_000_000).min(u64::MAX as u128) as u64;
+        Duration::new(secs, (nanos % 1_000_000_000) as u32)
+    }
+
+    /// The nearest frame at `sample_rate`, as when going from a file's
+    /// frames to those of the output.
+    pub fn at_rate(self, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate.max(1);
+        let scaled = self.frame as u128 * sample_rate as u128;
+        let frame = (scaled + self.sample_rate as u128 / 2) / self.sample_rate as u128;
+        Self::new(frame.min(u64::MAX as u128) as u64, sample_rate)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.frame == 0
+    }
+
+    /// `None` on overflow, or if the rates differ.
+    pub fn checked_add(self, other: Position) -> Option<Position> {
+        (self.sample_rate == other.sample_rate)
+            .then(|| self.frame.checked_add(other.frame))
+            .flatten()
+            .map(|frame| Self::new(frame, self.sample_rate))
+    }
+
+    /// `None` if `other` is later, or if the rates differ.
+    pub fn checked_sub(self, other: Position) -> Option<Position> {
+        (self.sample_rate == other.sample_rate)
+            .then(|| self.frame.checked_sub(other.frame))
+            .flatten()
+            .map(|frame| Self::new(frame, self.sample_rate))
+    }
+
+    /// Panics if the rates differ.
+    pub fn saturating_add(self, other: Position) -> Position {
+        self.assert_rate(other);
+        Self::new(self.frame.saturating_add(other.frame), self.sample_rate)
+    }
+
+    /// The start if `other` is later. Panics if the rates differ.
+    pub fn saturating_sub(self, other: Position) -> Position {
+        self.assert_rate(other);
+        Self::new(self.frame.saturating_sub(other.frame), self.sample_rate)
+    }
+
+    fn assert_rate(self, other: Position) {
+        assert_eq!(
+            self.sample_rate, other.sample_rate,
+            "positions at different sample rates; convert one with Position::at_rate"
+        );
+    }
+
+    /// The frame count and rate in lowest terms, which positions at the
+    /// same time share.
+    fn reduced(self) -> (u64, u32) {
+        let (mut a, mut b) = (self.frame, self.sample_rate as u64);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        (self.frame / a, (self.sample_rate as u64 / a) as u32)
+    }
+}
+
+impl From<Position> for Duration {
+    fn from(position: Position) -> Self {
+        position.as_duration()
+    }
+}
+
+impl PartialEq for Position {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Position {}
+
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Position {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let left = self.frame as u128 * other.sample_rate as u128;
+        let right = other.frame as u128 * self.sample_rate as u128;
+        left.cmp(&right)
+    }
+}
+
+impl Hash for Position {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.reduced().hash(state);
+    }
+}
+
+impl Add for Position {
+    type Output = Position;
+
+    /// Panics on overflow, or if the rates differ.
+    fn add(self, other: Position) -> Position {
+        self.assert_rate(other);
+        let frame = self.frame.checked_add(other.frame);
+        Self::new(
+            frame.expect("overflow when adding positions"),
+            self.sample_rate,
+        )
+    }
+}
+
+impl Sub for Position {
+    type Output = Position;
+
+    /// Panics if `other` is later, or if the rates differ.
+    fn sub(self, other: Position) -> Position {
+        self.assert_rate(other);
+        let frame = self.frame.checked_sub(other.frame);
+        Self::new(
+            frame.expect("overflow when subtracting positions"),
+            self.sample_rate,
+        )
+    }
+}
+
+impl AddAssign for Position {
+    fn add_assign(&mut self, other: Position) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Position {
+    fn sub_assign(&mut self, other: Position) {
+        *self = *self - other;
+    }
+}
+
+/// `mm:ss.mmm`, the milliseconds rounded down, the minutes going past 59.
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.frame as u128 * 1000 / self.sample_rate as u128;
+        write!(
+            f,
+            "{:02}:{:02}.{:03}",
+            millis / 60_000,
+            millis / 1000 % 60,
+            millis % 1000
+        )
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{
+    equalizer::{BAND_COUNT, DEFAULT_Q, FREQUENCIES, SHELF_Q},
+    settings::EqSettings,
+};
+use std::{fmt, str::FromStr};
+
+/// Built-in gain curves, defined on the ten octave bands and carried over to
+/// any other layout by [`EqPreset::gains_at`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EqPreset {
+    Flat,
+    Rock,
+    Pop,
+    Jazz,
+    Classical,
+    BassBoost,
+    Vocal,
+}
+
+impl EqPreset {
+    pub const ALL: [EqPreset; 7] = [
+        EqPreset::Flat,
+        EqPreset::Rock,
+        EqPreset::Pop,
+        EqPreset::Jazz,
+        EqPreset::Classical,
+        EqPreset::BassBoost,
+        EqPreset::Vocal,
+    ];
+
+    /// Per-band gains in dB, from 32 Hz up to 16 kHz.
+    pub fn gains(self) -> [f32; BAND_COUNT] {
+        match self {
+            EqPreset::Flat => [0.0; BAND_COUNT],
+            EqPreset::Rock => [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0],
+            EqPreset::Pop => [-1.0, 1.0, 3.0, 4.0, 4.0, 2.0, 0.0, -1.0, -1.0, -1.0],
+            EqPreset::Jazz => [3.0, 2.0, 1.0, 2.0, -1.5, -1.5, 0.0, 1.0, 2.0, 3.0],
+            EqPreset::Classical => [4.0, 3.0, 2.0, 1.0, -1.0, -1.0, 0.0, 2.0, 3.0, 4.0],
+            EqPreset::BassBoost => [7.0, 6.0, 5.0, 3.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            EqPreset::Vocal => [-2.0, -3.0, -3.0, 1.0, 3.5, 3.5, 3.0, 1.5, 0.0, -1.0],
+        }
+    }
+
+    /// Per-band Q, from 32 Hz up to 16 kHz, so a preset puts back band
+    /// widths changed since as well as gains.
+    pub fn q(self) -> [f32; BAND_COUNT] {
+        let mut q = [DEFAULT_Q; BAND_COUNT];
+        q[0] = SHELF_Q;
+        q[BAND_COUNT - 1] = SHELF_Q;
+        q
+    }
+
+    /// The whole ten-band setup of the preset, gains and Q.
+    pub fn settings(self) -> EqSettings {
+        let mut settings = EqSettings::from_gains(&self.gains());
+        settings.set_q(&self.q());
+        settings
+    }
+
+    /// The curve at each of `frequencies`, interpolated on a log frequency
+    /// scale between the octave bands and held flat beyond them.
+    pub fn gains_at(self, frequencies: &[f32]) -> Vec<f32> {
+        let gains = self.gains();
+        frequencies
+            .iter()
+            .map(|&frequency| {
+                let above = FREQUENCIES.partition_point(|&center| center < frequency);
+                match above {
+                    0 => gains[0],
+                    BAND_COUNT => gains[BAND_COUNT - 1],
+                    _ => {
+                        let (low, high) = (FREQUENCIES[above - 1], FREQUENCIES[above]);
+                        let t = (frequency / low).ln() / (high / low).ln();
+                        gains[above - 1] + (gains[above] - gains[above - 1]) * t
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EqPreset::Flat => "Flat",
+            EqPreset::Rock => "Rock",
+            EqPreset::Pop => "Pop",
+            EqPreset::Jazz => "Jazz",
+            EqPreset::Classical => "Classical",
+            EqPreset::BassBoost => "Bass Boost",
+            EqPreset::Vocal => "Vocal",
+        }
+    }
+}
+
+impl fmt::Display for EqPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for EqPreset {
+    type Err = String;
+
+    /// Looks a preset up by name, ignoring case, spaces, dashes and underscores.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalize = |name: &str| {
+            name.chars()
+                .filter(|c| !matches!(c, ' ' | '-' | '_'))
+                .collect::<String>()
+                .to_lowercase()
+        };
+        let wanted = normalize(s);
+        EqPreset::ALL
+            .into_iter()
+            .find(|preset| normalize(preset.name()) == wanted)
+            .ok_or_else(|| format!("unknown EQ preset '{s}'"))
+    }
+}
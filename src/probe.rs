@@ -0,0 +1,442 @@
+use crate::{
+    decode::{self, DecoderBackend},
+    error::PlayerError,
+    format::{json, Value},
+};
+use rodio::Source;
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+    time::Duration,
+};
+
+pub(crate) const HEADER_BYTES: u64 = 128 * 1024;
+
+/// What [`probe`] found out about a file's audio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamInfo {
+    /// "WAV", "AIFF", "FLAC", "Ogg", "MP3" or "MP4", when the headers say.
+    pub container: Option<&'static str>,
+    /// "PCM", "IEEE float", "FLAC", "Vorbis", "Opus", "MP3", "AAC" or
+    /// "ALAC", when the headers say, or Symphonia's name for another codec
+    /// it decodes.
+    pub codec: Option<&'static str>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Only lossless formats have one.
+    pub bits_per_sample: Option<u16>,
+    /// `None` when neither the decoder nor the headers tell, and counting
+    /// the samples failed too.
+    pub duration: Option<Duration>,
+    pub file_size: u64,
+    /// Average bits per second over the whole file, tags included.
+    pub bitrate: Option<u64>,
+}
+
+impl StreamInfo {
+    /// The info as a JSON object, the duration in seconds; unknowns are null.
+    pub fn to_json(&self) -> String {
+        let value = Value::table()
+            .with("container", self.container)
+            .with("codec", self.codec)
+            .with("sample_rate", u64::from(self.sample_rate))
+            .with("channels", u64::from(self.channels))
+            .with("bits_per_sample", self.bits_per_sample.map(u64::from))
+            .with("duration", self.duration.map(|d| d.as_secs_f64()))
+            .with("file_size", self.file_size)
+            .with("bitrate", self.bitrate);
+        json::to_string(&value)
+    }
+}
+
+/// Opens the file at `path` with the decoder, without any audio output, and
+/// reports its format. The duration is worked out as for playback, from the
+/// headers where the decoder can't say, so only files with neither decode in
+/// full.
+///
+/// Files the decoder can't read fail with [`PlayerError::Decode`].
+pub fn probe(path: impl AsRef<Path>) -> Result<StreamInfo, PlayerError> {
+    let path = path.as_ref();
+    let file_size = fs::metadata(path)?.len();
+    let decoder = decode::open_file(path, DecoderBackend::Auto)?;
+    let mut header = Vec::new();
+    File::open(path)?
+        .take(HEADER_BYTES)
+        .read_to_end(&mut header)?;
+    let format = identify(&header);
+    let track = decode::track_info(path).unwrap_or_default();
+    let duration = mp3_duration(&header, file_size)
+        .or_else(|| decoder.total_duration())
+        .or_else(|| probe_duration(path));
+    let bitrate = duration
+        .map(|duration| duration.as_secs_f64())
+        .filter(|&seconds| seconds > 0.0)
+        .map(|seconds| (file_size as f64 * 8.0 / seconds).round() as u64);
+    Ok(StreamInfo {
+        container: format.container,
+        codec: format.codec.or(track.codec),
+        sample_rate: decoder.sample_rate(),
+        channels: decoder.channels(),
+        bits_per_sample: format.bits_per_sample.or(track.bits_per_sample),
+        duration,
+        file_size,
+        bitrate,
+    })
+}
+
+#[derive(Default)]
+struct Format {
+    container: Option<&'static str>,
+    codec: Option<&'static str>,
+    bits_per_sample: Option<u16>,
+}
+
+/// Tells the format apart from the first bytes of the file.
+fn identify(header: &[u8]) -> Format {
+    let format = |container, codec| Format {
+        container: Some(container),
+        codec,
+        bits_per_sample: None,
+    };
+    let contains = |needle: &[u8]| header.windows(needle.len()).any(|window| window == needle);
+    match header.get(..4) {
+        Some(b"RIFF") => wav_format(header).unwrap_or_else(|| format("WAV", None)),
+        Some(b"FORM") => aiff_format(header).unwrap_or_else(|| format("AIFF", None)),
+        Some(b"fLaC") => Format {
+            bits_per_sample: flac_bits(header),
+            ..format("FLAC", Some("FLAC"))
+        },
+        Some(b"OggS") => {
+            let codec = if contains(b"OpusHead") {
+                Some("Opus")
+            } else if contains(b"\x01vorbis") {
+                Some("Vorbis")
+            } else if contains(b"\x7fFLAC") {
+                Some("FLAC")
+            } else {
+                None
+            };
+            format("Ogg", codec)
+        }
+        _ if header.get(4..8) == Some(b"ftyp") => {
+            // The sample description is only in reach when the moov box
+            // comes before the audio.
+            let codec = if contains(b"mp4a") {
+                Some("AAC")
+            } else if contains(b"alac") {
+                Some("ALAC")
+            } else {
+                None
+            };
+            format("MP4", codec)
+        }
+        _ if first_mp3_frame(header).is_some() => format("MP3", Some("MP3")),
+        _ => Format::default(),
+    }
+}
+
+fn wav_format(header: &[u8]) -> Option<Format> {
+    if header.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let mut at = 12;
+    while let (Some(id), Some(size)) = (header.get(at..at + 4), u32_le(header, at + 4)) {
+        let body = at + 8;
+        if id == b"fmt " {
+            let mut tag = u16_le(header, body)?;
+            // WAVE_FORMAT_EXTENSIBLE keeps the real tag in its subformat.
+            if tag == 0xfffe {
+                tag = u16_le(header, body + 24)?;
+            }
+            let codec = match tag {
+                1 => Some("PCM"),
+                3 => Some("IEEE float"),
+                0x55 => Some("MP3"),
+                _ => None,
+            };
+            return Some(Format {
+                container: Some("WAV"),
+                codec,
+                bits_per_sample: u16_le(header, body + 14).filter(|_| codec != Some("MP3")),
+            });
+        }
+        at = body + size as usize + (size as usize & 1);
+    }
+    None
+}
+
+fn aiff_format(header: &[u8]) -> Option<Format> {
+    if !matches!(header.get(8..12)?, b"AIFF" | b"AIFC") {
+        return None;
+    }
+    let mut at = 12;
+    while let (Some(id), Some(size)) = (header.get(at..at + 4), u32_be(header, at + 4)) {
+        let body = at + 8;
+        if id == b"COMM" {
+            let bits = header.get(body + 6..body + 8)?;
+            return Some(Format {
+                container: Some("AIFF"),
+                codec: Some("PCM"),
+                bits_per_sample: Some(u16::from_be_bytes([bits[0], bits[1]])),
+            });
+        }
+        at = body + size as usize + (size as usize & 1);
+    }
+    None
+}
+
+fn flac_bits(header: &[u8]) -> Option<u16> {
+    let info = header.get(8..8 + 34)?;
+    let packed = u64::from_be_bytes(info[10..18].try_into().ok()?);
+    Some(((packed >> 36) & 0x1f) as u16 + 1)
+}
+
+/// Works out how long the file at `path` plays for, for when the decoder
+/// can't say.
+///
+/// Reads the container headers (WAV, FLAC STREAMINFO, MP3 Xing/Info/VBRI or
+/// constant bitrate), then asks Symphonia if built in, and only then decodes
+/// the whole file counting samples.
+/// The file is opened separately for each step, so no reader used for
+/// playback is touched.
+pub(crate) fn probe_duration(path: &Path) -> Option<Duration> {
+    header_duration(path)
+        .or_else(|| decode::track_info(path)?.duration)
+        .or_else(|| count_samples(path))
+}
+
+/// Duration from the file's headers alone, without decoding anything.
+pub(crate) fn header_duration(path: &Path) -> Option<Duration> {
+    let (header, file_len) = read_header(path)?;
+    duration_from_header(&header, file_len)
+}
+
+/// The length of the file at `path` whose decoder `reported` one. An MP3's
+/// comes from its headers first, as rodio's count for one can be seconds
+/// out; otherwise the decoder's goes, then [`probe_duration`].
+pub(crate) fn file_duration(path: &Path, reported: Option<Duration>) -> Option<Duration> {
+    read_header(path)
+        .and_then(|(header, file_len)| mp3_duration(&header, file_len))
+        .or(reported)
+        .or_else(|| probe_duration(path))
+}
+
+/// [`duration_from_header`] of an MP3, or `None` for another format.
+pub(crate) fn mp3_duration(header: &[u8], total_len: u64) -> Option<Duration> {
+    (identify(header).container == Some("MP3"))
+        .then(|| mp3(header, total_len))
+        .flatten()
+}
+
+fn read_header(path: &Path) -> Option<(Vec<u8>, u64)> {
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(HEADER_BYTES)
+        .read_to_end(&mut header)
+        .ok()?;
+    Some((header, fs::metadata(path).ok()?.len()))
+}
+
+/// Like [`header_duration`], from the first bytes of a stream that is
+/// `total_len` bytes long.
+pub(crate) fn duration_from_header(header: &[u8], total_len: u64) -> Option<Duration> {
+    match header.get(..4)? {
+        b"RIFF" => wav(header),
+        b"fLaC" => flac(header),
+        _ => mp3(header, total_len),
+    }
+}
+
+fn count_samples(path: &Path) -> Option<Duration> {
+    let decoder = decode::open_file(path, DecoderBackend::Auto).ok()?;
+    let rate = decoder.sample_rate() as f64 * decoder.channels().max(1) as f64;
+    let samples = decoder.count();
+    (rate > 0.0).then(|| Duration::from_secs_f64(samples as f64 / rate))
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn wav(header: &[u8]) -> Option<Duration> {
+    if header.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let mut byte_rate = None;
+    let mut at = 12;
+    while let (Some(id), Some(size)) = (header.get(at..at + 4), u32_le(header, at + 4)) {
+        let body = at + 8;
+        match id {
+            b"fmt " => byte_rate = u32_le(header, body + 8),
+            b"data" => {
+                let byte_rate = byte_rate.filter(|&rate| rate > 0)?;
+                return Some(Duration::from_secs_f64(size as f64 / byte_rate as f64));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        at = body + size as usize + (size as usize & 1);
+    }
+    None
+}
+
+fn flac(header: &[u8]) -> Option<Duration> {
+    // The first metadata block is always STREAMINFO.
+    let info = header.get(8..8 + 34)?;
+    if header[4] & 0x7f != 0 {
+        return None;
+    }
+    let packed = u64::from_be_bytes(info[10..18].try_into().ok()?);
+    let sample_rate = (packed >> 44) as u32;
+    let total_samples = packed & 0xf_ffff_ffff;
+    (sample_rate > 0 && total_samples > 0)
+        .then(|| Duration::from_secs_f64(total_samples as f64 / sample_rate as f64))
+}
+
+/// Where the first MPEG audio frame starts, after any ID3v2 tag, and its
+/// header.
+fn first_mp3_frame(header: &[u8]) -> Option<(usize, Mp3Frame)> {
+    let mut start = 0;
+    if header.get(..3)? == b"ID3" {
+        let size = header
+            .get(6..10)?
+            .iter()
+            .fold(0usize, |size, &byte| (size << 7) | (byte & 0x7f) as usize);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        start = 10 + size + footer;
+    }
+
+    let offset = (start..header.len().saturating_sub(4))
+        .find(|&i| header[i] == 0xff && header[i + 1] & 0xe0 == 0xe0)?;
+    Some((offset, Mp3Frame::parse(&header[offset..])?))
+}
+
+fn mp3(header: &[u8], file_len: u64) -> Option<Duration> {
+    let (offset, frame) = first_mp3_frame(header)?;
+
+    let xing = offset + 4 + frame.side_info;
+    if let Some(tag) = header.get(xing..xing + 4) {
+        if tag == b"Xing" || tag == b"Info" {
+            let flags = u32_be(header, xing + 4)?;
+            if flags & 1 != 0 {
+                return Some(frame.duration_of(u32_be(header, xing + 8)? as u64));
+            }
+        }
+    }
+    let vbri = offset + 36;
+    if header.get(vbri..vbri + 4) == Some(b"VBRI") {
+        return Some(frame.duration_of(u32_be(header, vbri + 14)? as u64));
+    }
+
+    // No VBR header: assume a constant bitrate across the rest of the file.
+    let bytes = file_len.saturating_sub(offset as u64);
+    (frame.bitrate > 0).then(|| Duration::from_secs_f64(bytes as f64 * 8.0 / frame.bitrate as f64))
+}
+
+struct Mp3Frame {
+    sample_rate: u32,
+    bitrate: u32,
+    samples_per_frame: u32,
+    side_info: usize,
+}
+
+impl Mp3Frame {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let header = u32_be(bytes, 0)?;
+        let version = (header >> 19) & 0b11;
+        let layer = (header >> 17) & 0b11;
+        let bitrate_index = ((header >> 12) & 0xf) as usize;
+        let rate_index = ((header >> 10) & 0b11) as usize;
+        let mono = (header >> 6) & 0b11 == 0b11;
+
+        // Only layer III carries the duration headers parsed here.
+        if layer != 0b01 || rate_index == 3 || version == 0b01 {
+            return None;
+        }
+        let mpeg1 = version == 0b11;
+        let base_rate = [44100, 48000, 32000][rate_index];
+        let sample_rate = match version {
+            0b11 => base_rate,
+            0b10 => base_rate / 2,
+            _ => base_rate / 4,
+        };
+        let kbps: [u32; 15] = if mpeg1 {
+            [
+                0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+            ]
+        } else {
+            [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160]
+        };
+        Some(Mp3Frame {
+            sample_rate,
+            bitrate: *kbps.get(bitrate_index)? * 1000,
+            samples_per_frame: if mpeg1 { 1152 } else { 576 },
+            side_info: match (mpeg1, mono) {
+                (true, false) => 32,
+                (true, true) | (false, false) => 17,
+                (false, true) => 9,
+            },
+        })
+    }
+
+    fn duration_of(&self, frames: u64) -> Duration {
+        Duration::from_secs_f64(
+            frames as f64 * self.samples_per_frame as f64 / self.sample_rate as f64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, io, process};
+
+    #[test]
+    fn a_missing_file_fails_with_not_found() {
+        let path = env::temp_dir().join("fullyrustaudio-missing.flac");
+        let _ = fs::remove_file(&path);
+        match probe(&path) {
+            Err(PlayerError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+            other => panic!("expected an io error, got {other:?}"),
+        }
+        match decode::open_file(&path, DecoderBackend::Auto) {
+            Err(PlayerError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+            Err(err) => panic!("expected an io error, got {err:?}"),
+            Ok(_) => panic!("opened a file that doesn't exist"),
+        }
+    }
+
+    #[test]
+    fn a_file_that_isnt_audio_fails_to_decode() {
+        let path = env::temp_dir().join(format!("fullyrustaudio-{}.mp3", process::id()));
+        fs::write(&path, "this is a text file, not an mp3\n".repeat(64)).unwrap();
+        let probed = probe(&path);
+        let opened = decode::open_file(&path, DecoderBackend::Auto);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(probed, Err(PlayerError::Decode(_))), "{probed:?}");
+        assert!(matches!(opened, Err(PlayerError::Decode(_))));
+    }
+
+    #[test]
+    fn an_mp3_goes_by_its_xing_header_not_the_decoder() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("outaspace.mp3");
+        // 5751 frames of 1152 samples at 48 kHz.
+        let expected = 138.024;
+        let probed = probe(&path).unwrap().duration.unwrap();
+        assert!((probed.as_secs_f64() - expected).abs() < 0.01, "{probed:?}");
+        let reported = decode::open_file(&path, DecoderBackend::Auto)
+            .unwrap()
+            .total_duration();
+        assert_eq!(file_duration(&path, reported), Some(probed));
+    }
+}
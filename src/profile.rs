@@ -0,0 +1,156 @@
+//! EQ profiles per output device, switched to as playback moves from one
+//! device to another.
+
+use crate::{equalizer::EqControls, lock::Lock, settings::EqSettings};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+/// Which EQ profile plays; see
+/// [`AudioPlayer::active_eq_profile`](crate::AudioPlayer::active_eq_profile).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EqProfile {
+    /// The profile of the output device with this name.
+    Device(String),
+    /// The profile for devices without one of their own.
+    Default,
+}
+
+impl EqProfile {
+    pub fn name(&self) -> &str {
+        match self {
+            EqProfile::Device(name) => name,
+            EqProfile::Default => "default",
+        }
+    }
+}
+
+impl fmt::Display for EqProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Default)]
+struct Profiles {
+    devices: HashMap<String, EqSettings>,
+    default: Option<EqSettings>,
+    /// The device last selected for.
+    device: Option<String>,
+    /// The profile playing, and the settings it was applied with.
+    active: Option<(EqProfile, EqSettings)>,
+}
+
+/// The profile of each device, and which of them plays.
+#[derive(Default)]
+pub(crate) struct DeviceProfiles {
+    profiles: Mutex<Profiles>,
+    write_back: AtomicBool,
+    /// Whether something follows device changes yet.
+    followed: AtomicBool,
+}
+
+impl DeviceProfiles {
+    pub(crate) fn set(&self, device: &str, settings: EqSettings) {
+        self.profiles
+            .locked()
+            .devices
+            .insert(device.to_string(), settings);
+    }
+
+    pub(crate) fn remove(&self, device: &str) -> Option<EqSettings> {
+        self.profiles.locked().devices.remove(device)
+    }
+
+    /// The profile of `device`, with the EQ as it is now if write-back is on
+    /// and the profile is playing.
+    pub(crate) fn get(&self, device: &str, eq: &EqControls) -> Option<EqSettings> {
+        let profiles = self.profiles.locked();
+        match &profiles.active {
+            Some((EqProfile::Device(name), _)) if name == device && self.writes_back() => {
+                Some(eq.settings())
+            }
+            _ => profiles.devices.get(device).cloned(),
+        }
+    }
+
+    pub(crate) fn set_default(&self, settings: Option<EqSettings>) {
+        self.profiles.locked().default = settings;
+    }
+
+    pub(crate) fn default(&self) -> Option<EqSettings> {
+        self.profiles.locked().default.clone()
+    }
+
+    pub(crate) fn active(&self) -> Option<EqProfile> {
+        self.profiles
+            .locked()
+            .active
+            .as_ref()
+            .map(|(profile, _)| profile.clone())
+    }
+
+    pub(crate) fn writes_back(&self) -> bool {
+        self.write_back.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_write_back(&self, enabled: bool) {
+        self.write_back.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns `true` the first time only, for whoever is to start
+    /// following device changes.
+    pub(crate) fn start_following(&self) -> bool {
+        !self.followed.swap(true, Ordering::Relaxed)
+    }
+
+    /// Plays `device`'s profile, or the default one, through `eq` unless it
+    /// plays already. Leaving another device with write-back on first keeps
+    /// what the EQ was changed to there in that device's profile. Returns
+    /// the profile if it is another than before.
+    pub(crate) fn select(&self, device: Option<&str>, eq: &EqControls) -> Option<EqProfile> {
+        let mut profiles = self.profiles.locked();
+        if profiles.device.as_deref() != device {
+            let left = profiles.device.take();
+            if let (Some(left), true) = (left, self.writes_back()) {
+                let settings = eq.settings();
+                let changed = profiles
+                    .active
+                    .as_ref()
+                    .is_some_and(|(_, applied)| *applied != settings);
+                if changed {
+                    profiles.devices.insert(left, settings);
+                }
+            }
+            profiles.device = device.map(str::to_string);
+        }
+
+        let found = device.and_then(|device| Some((device, profiles.devices.get(device)?)));
+        let (profile, settings) = match (found, &profiles.default) {
+            (Some((device, settings)), _) => {
+                (EqProfile::Device(device.to_string()), settings.clone())
+            }
+            (None, Some(settings)) => (EqProfile::Default, settings.clone()),
+            // The EQ is left as it is.
+            (None, None) => {
+                profiles.active = None;
+                return None;
+            }
+        };
+        if profiles.active.as_ref() == Some(&(profile.clone(), settings.clone())) {
+            return None;
+        }
+        eq.set_settings(settings.clone());
+        let switched = profiles
+            .active
+            .as_ref()
+            .is_none_or(|(active, _)| *active != profile);
+        profiles.active = Some((profile.clone(), settings));
+        switched.then_some(profile)
+    }
+}
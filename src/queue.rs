@@ -0,0 +1,708 @@
+use crate::{
+    cache::TrackCache,
+    cue::CueSheet,
+    events::Signal,
+    http::Download,
+    lock::Lock,
+    metadata::{Chapter, TrackMetadata},
+    stdin::Stdin,
+    trim::TrackRange,
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    collections::VecDeque,
+    f32::consts::FRAC_PI_2,
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        mpsc::Sender,
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+pub(crate) type TrackSource = Box<dyn Source<Item = f32> + Send>;
+
+/// One entry of the play queue. `id` stays unique even if the same file is queued twice.
+#[derive(Debug, Clone)]
+pub(crate) struct Track {
+    pub(crate) id: u64,
+    pub(crate) path: PathBuf,
+    pub(crate) duration: Option<Duration>,
+    /// Of the file, which a [`Position`](crate::Position) in it counts
+    /// frames at.
+    pub(crate) sample_rate: u32,
+    pub(crate) metadata: Arc<TrackMetadata>,
+    pub(crate) origin: Origin,
+    /// Leading silence trimmed off the start, which the track's chain skips
+    /// over before playing anything.
+    pub(crate) lead: Duration,
+    /// Splits the file into the sheet's tracks.
+    pub(crate) cue: Option<Arc<CueSheet>>,
+    /// The part of the file played, which `duration` is the length of.
+    pub(crate) range: Option<TrackRange>,
+    /// From the file's tags unless set with
+    /// [`AudioPlayer::set_chapters`](crate::AudioPlayer::set_chapters).
+    pub(crate) chapters: Arc<[Chapter]>,
+    /// The whole file decoded into memory, for files small enough.
+    pub(crate) cache: Option<Arc<TrackCache>>,
+}
+
+impl Track {
+    /// Whether this is a live stream, such as internet radio.
+    pub(crate) fn is_live(&self) -> bool {
+        matches!(&self.origin, Origin::Http(download) if download.is_live())
+    }
+}
+
+/// One entry of [`AudioPlayer::queue`](crate::AudioPlayer::queue).
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueItem {
+    /// Stays the same for as long as the entry is queued, wherever it moves.
+    pub id: u64,
+    pub path: PathBuf,
+    pub duration: Option<Duration>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// The part of the file played, if queued through
+    /// [`AudioPlayer::enqueue_with_range`](crate::AudioPlayer::enqueue_with_range);
+    /// `duration` is its length.
+    pub range: Option<TrackRange>,
+}
+
+impl From<&Track> for QueueItem {
+    fn from(track: &Track) -> Self {
+        QueueItem {
+            id: track.id,
+            path: track.path.clone(),
+            duration: track.duration,
+            title: track.metadata.title.clone(),
+            artist: track.metadata.artist.clone(),
+            range: track.range,
+        }
+    }
+}
+
+/// Where a track's audio is read from.
+#[derive(Debug, Clone)]
+pub(crate) enum Origin {
+    File,
+    /// Fetched over HTTP; the track's `path` holds the URL.
+    Http(Arc<Download>),
+    /// Piped in; the track's `path` is [`crate::STDIN_PATH`].
+    Stdin(Arc<Stdin>),
+    /// Handed to [`AudioPlayer::play_source`](crate::AudioPlayer::play_source);
+    /// the track's `path` is the name it was given.
+    Generated(Replayable),
+}
+
+/// A copy of a source kept to start it over each time its track is opened.
+#[derive(Clone)]
+pub(crate) struct Replayable(Arc<dyn Fn() -> TrackSource + Send + Sync>);
+
+impl Replayable {
+    pub(crate) fn new<S>(source: S) -> Self
+    where
+        S: Source<Item = f32> + Clone + Send + 'static,
+    {
+        let source = Mutex::new(source);
+        Replayable(Arc::new(move || Box::new(source.locked().clone())))
+    }
+
+    pub(crate) fn open(&self) -> TrackSource {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for Replayable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Replayable")
+    }
+}
+
+/// What happens when a track ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatMode {
+    /// Play the queue once.
+    #[default]
+    Off,
+    /// Play the current track again.
+    One,
+    /// Go back to the first track after the last one.
+    All,
+}
+
+impl RepeatMode {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::One => "one",
+            RepeatMode::All => "all",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        [RepeatMode::Off, RepeatMode::One, RepeatMode::All]
+            .into_iter()
+            .find(|&repeat| repeat.name().eq_ignore_ascii_case(name))
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => RepeatMode::One,
+            2 => RepeatMode::All,
+            _ => RepeatMode::Off,
+        }
+    }
+}
+
+/// Sources ready to follow the current track. Only the next track is
+/// opened ahead; the rest of the queue is opened as playback reaches it.
+struct Upcoming {
+    queue: VecDeque<Playing>,
+    /// A fresh copy of the current track, for [`RepeatMode::One`].
+    again: Option<Playing>,
+    /// The first track from the top, for [`RepeatMode::All`].
+    wrap: Option<Playing>,
+}
+
+impl Upcoming {
+    /// Takes whatever plays after track `current`, provided `accept` agrees.
+    fn take_next(
+        &mut self,
+        repeat: RepeatMode,
+        current: u64,
+        accept: impl Fn(&Playing) -> bool,
+    ) -> Option<Playing> {
+        let again = self.again.as_ref().is_some_and(|again| again.id == current);
+        if repeat == RepeatMode::One && again {
+            return self.again.take().filter(&accept);
+        }
+        if let Some(next) = self.queue.front() {
+            return accept(next).then(|| self.queue.pop_front().unwrap());
+        }
+        if repeat == RepeatMode::All {
+            return self.wrap.take_if(|first| accept(first));
+        }
+        None
+    }
+}
+
+/// Tracks waiting to be played by a [`Playlist`], plus what it reports back.
+pub(crate) struct PlaylistControls {
+    upcoming: Mutex<Upcoming>,
+    repeat: AtomicU8,
+    crossfade_ns: AtomicU64,
+    /// One more than the id of the track a loop from its tags holds, or 0.
+    held: AtomicU64,
+    /// One more than the id of the track that last started playing in the
+    /// running [`Playlist`], or 0.
+    started: AtomicU64,
+    current: AtomicU64,
+    handovers: AtomicU64,
+    position_ns: AtomicU64,
+    played_ns: AtomicU64,
+    playthroughs: AtomicU64,
+    generation: AtomicU64,
+    signals: Sender<Signal>,
+    finished: Mutex<bool>,
+    finished_changed: Condvar,
+}
+
+impl PlaylistControls {
+    /// Track starts and ends are reported on `signals`.
+    pub(crate) fn new(signals: Sender<Signal>) -> Self {
+        PlaylistControls {
+            upcoming: Mutex::new(Upcoming {
+                queue: VecDeque::new(),
+                again: None,
+                wrap: None,
+            }),
+            repeat: AtomicU8::new(RepeatMode::Off as u8),
+            crossfade_ns: AtomicU64::new(0),
+            held: AtomicU64::new(0),
+            started: AtomicU64::new(0),
+            current: AtomicU64::new(0),
+            handovers: AtomicU64::new(0),
+            position_ns: AtomicU64::new(0),
+            played_ns: AtomicU64::new(0),
+            playthroughs: AtomicU64::new(0),
+            generation: AtomicU64::new(0),
+            signals,
+            finished: Mutex::new(false),
+            finished_changed: Condvar::new(),
+        }
+    }
+
+    /// Lines up `next` to follow the current track, in place of whatever
+    /// was waiting, leaving what is prepared for repeating alone.
+    pub(crate) fn line_up(&self, next: Option<(u64, TrackSource, Option<Duration>)>) {
+        self.upcoming.locked().queue = next
+            .map(|(id, source, duration)| Playing::new(id, source, duration, Duration::ZERO))
+            .into_iter()
+            .collect();
+    }
+
+    /// Id of the track lined up to follow the current one.
+    pub(crate) fn next_id(&self) -> Option<u64> {
+        self.upcoming.locked().queue.front().map(|next| next.id)
+    }
+
+    /// Drops the track prepared for [`RepeatMode::All`], which may no longer
+    /// be first once the queue has changed.
+    pub(crate) fn clear_wrap(&self) {
+        self.upcoming.locked().wrap = None;
+    }
+
+    /// Drops everything queued, including sources prepared for repeating.
+    pub(crate) fn clear(&self) {
+        let mut upcoming = self.upcoming.locked();
+        upcoming.queue.clear();
+        upcoming.again = None;
+        upcoming.wrap = None;
+    }
+
+    pub(crate) fn has_upcoming(&self) -> bool {
+        !self.upcoming.locked().queue.is_empty()
+    }
+
+    pub(crate) fn repeat(&self) -> RepeatMode {
+        RepeatMode::from_u8(self.repeat.load(Ordering::Relaxed))
+    }
+
+    /// Takes effect the next time a track ends.
+    pub(crate) fn set_repeat(&self, repeat: RepeatMode) {
+        self.repeat.store(repeat as u8, Ordering::Relaxed);
+    }
+
+    /// Readies `source` to replay track `id` under [`RepeatMode::One`].
+    pub(crate) fn prepare_again(&self, id: u64, source: TrackSource, duration: Option<Duration>) {
+        self.upcoming.locked().again = Some(Playing::new(id, source, duration, Duration::ZERO));
+    }
+
+    /// Readies `source`, the first track, to start the playlist over under
+    /// [`RepeatMode::All`].
+    pub(crate) fn prepare_wrap(&self, id: u64, source: TrackSource, duration: Option<Duration>) {
+        self.upcoming.locked().wrap = Some(Playing::new(id, source, duration, Duration::ZERO));
+    }
+
+    /// Marks track `id` as looping on past its length, or no longer, so no
+    /// crossfade starts out of it meanwhile.
+    pub(crate) fn hold(&self, id: u64, held: bool) {
+        match held {
+            true => self.held.store(id + 1, Ordering::Relaxed),
+            false => {
+                let _ = self
+                    .held
+                    .compare_exchange(id + 1, 0, Ordering::Relaxed, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn is_held(&self, id: u64) -> bool {
+        self.held.load(Ordering::Relaxed) == id + 1
+    }
+
+    pub(crate) fn crossfade(&self) -> Duration {
+        Duration::from_nanos(self.crossfade_ns.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_crossfade(&self, crossfade: Duration) {
+        self.crossfade_ns
+            .store(crossfade.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Id of the track that last started playing, which may be fading in
+    /// under the current one. `None` once a new [`Playlist`] has taken over
+    /// and is yet to start.
+    pub(crate) fn last_started(&self) -> Option<u64> {
+        self.started.load(Ordering::Acquire).checked_sub(1)
+    }
+
+    /// Id of the track that is currently dominant in the output.
+    pub(crate) fn current(&self) -> u64 {
+        self.current.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_current(&self, id: u64) {
+        self.current.store(id, Ordering::Release);
+    }
+
+    /// Counts track changes, including a track following itself.
+    pub(crate) fn handovers(&self) -> u64 {
+        self.handovers.load(Ordering::Acquire)
+    }
+
+    /// `fresh` unless the same track carries on where it was, as after a
+    /// seek that rebuilt it.
+    fn hand_over(&self, id: u64, fresh: bool) {
+        if fresh {
+            self.restart_playthrough();
+        }
+        self.set_current(id);
+        self.handovers.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// How long the current playthrough of the current track has actually
+    /// played, in source time. Seeks don't count and pauses hold it.
+    pub(crate) fn played(&self) -> Duration {
+        Duration::from_nanos(self.played_ns.load(Ordering::Acquire))
+    }
+
+    /// Counts playthroughs, each starting when a track starts from its
+    /// beginning, including a repeat of the same one.
+    pub(crate) fn playthroughs(&self) -> u64 {
+        self.playthroughs.load(Ordering::Acquire)
+    }
+
+    fn restart_playthrough(&self) {
+        self.played_ns.store(0, Ordering::Release);
+        self.playthroughs.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Position in the current track, counted in frames pulled by the
+    /// [`Playlist`]. Loop wraps are not taken out.
+    pub(crate) fn position(&self) -> Duration {
+        Duration::from_nanos(self.position_ns.load(Ordering::Acquire))
+    }
+
+    pub(crate) fn set_position(&self, position: Duration) {
+        self.position_ns
+            .store(position.as_nanos() as u64, Ordering::Release);
+    }
+
+    /// Stops the running [`Playlist`], if any, from reporting its position,
+    /// e.g. once it has been stopped but not yet dropped.
+    pub(crate) fn detach(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Whether a [`Playlist`] has played its last queued track to the end.
+    pub(crate) fn is_finished(&self) -> bool {
+        *self.finished.locked()
+    }
+
+    /// Blocks until the playlist finishes or `timeout` passes, returning [`PlaylistControls::is_finished`].
+    pub(crate) fn wait_finished(&self, timeout: Duration) -> bool {
+        let finished = self.finished.locked();
+        let (finished, _) = self
+            .finished_changed
+            .wait_timeout_while(finished, timeout, |finished| !*finished)
+            .unwrap_or_else(PoisonError::into_inner);
+        *finished
+    }
+
+    fn set_finished(&self, finished: bool) {
+        *self.finished.locked() = finished;
+        self.finished_changed.notify_all();
+    }
+
+    /// Sending on an unbounded channel never blocks the audio thread.
+    fn signal(&self, signal: Signal) {
+        let _ = self.signals.send(signal);
+    }
+}
+
+struct Playing {
+    id: u64,
+    source: TrackSource,
+    frames: u64,
+    total_frames: Option<u64>,
+    started: bool,
+}
+
+impl Playing {
+    /// `offset` is where in the track `source` starts, for sources built after a seek.
+    fn new(id: u64, source: TrackSource, duration: Option<Duration>, offset: Duration) -> Self {
+        let frames = |time: Duration| (time.as_secs_f64() * source.sample_rate() as f64) as u64;
+        let total_frames = duration
+            .or_else(|| source.total_duration().map(|rest| rest + offset))
+            .map(frames);
+        Playing {
+            id,
+            frames: frames(offset),
+            total_frames,
+            source,
+            started: false,
+        }
+    }
+
+    fn position(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / self.source.sample_rate().max(1) as f64)
+    }
+
+    fn start(&mut self, controls: &PlaylistControls) {
+        if !self.started {
+            self.started = true;
+            controls.started.store(self.id + 1, Ordering::Release);
+            controls.signal(Signal::Started(self.id));
+        }
+    }
+}
+
+struct Fade {
+    incoming: Playing,
+    frame: u64,
+    length: u64,
+    handed_over: bool,
+}
+
+/// Plays queued tracks back to back, overlapping them with an equal-power
+/// crossfade when one is configured.
+///
+/// Tracks are only crossfaded when they share a channel count and sample
+/// rate and the outgoing track knows its length; otherwise they follow each
+/// other gaplessly. The incoming track takes over position reporting once
+/// it is the louder of the two.
+pub(crate) struct Playlist {
+    controls: Arc<PlaylistControls>,
+    current: Option<Playing>,
+    fade: Option<Fade>,
+    channel: u16,
+    channels: u16,
+    generation: u64,
+}
+
+impl Playlist {
+    /// Starts with `source`, which begins `offset` into its track.
+    pub(crate) fn new(
+        id: u64,
+        source: TrackSource,
+        duration: Option<Duration>,
+        offset: Duration,
+        controls: Arc<PlaylistControls>,
+    ) -> Self {
+        let generation = controls.detach();
+        controls.started.store(0, Ordering::Release);
+        controls.hand_over(id, id != controls.current() || offset.is_zero());
+        controls.set_position(offset);
+        controls.set_finished(false);
+        Playlist {
+            channels: source.channels().max(1),
+            controls,
+            current: Some(Playing::new(id, source, duration, offset)),
+            fade: None,
+            channel: 0,
+            generation,
+        }
+    }
+
+    /// Publishes the position of whichever track is reported as current.
+    fn publish_position(&self) {
+        if self.controls.generation.load(Ordering::Acquire) != self.generation {
+            return;
+        }
+        let reported = match &self.fade {
+            Some(fade) if fade.handed_over => Some(&fade.incoming),
+            _ => self.current.as_ref(),
+        };
+        if let Some(reported) = reported {
+            self.controls.set_position(reported.position());
+        }
+    }
+
+    /// Adds a frame of whichever track is reported as current to the time played.
+    fn count_played(&self) {
+        if self.controls.generation.load(Ordering::Acquire) != self.generation {
+            return;
+        }
+        let reported = match &self.fade {
+            Some(fade) if fade.handed_over => Some(&fade.incoming),
+            _ => self.current.as_ref(),
+        };
+        if let Some(reported) = reported {
+            let frame_ns = 1e9 / reported.source.sample_rate().max(1) as f64;
+            self.controls
+                .played_ns
+                .fetch_add(frame_ns.round() as u64, Ordering::AcqRel);
+        }
+    }
+
+    fn advance(&mut self) {
+        let id = self.current.as_ref().map_or(0, |current| current.id);
+        let next = self
+            .controls
+            .upcoming
+            .locked()
+            .take_next(self.controls.repeat(), id, |_| true);
+        if let Some(ended) = &self.current {
+            self.controls.signal(Signal::Ended(ended.id));
+        }
+        self.current = next;
+        match &self.current {
+            Some(current) => {
+                self.controls.hand_over(current.id, true);
+                self.publish_position();
+            }
+            None => self.controls.set_finished(true),
+        }
+    }
+
+    fn maybe_start_fade(&mut self) {
+        let Some(current) = &self.current else {
+            return;
+        };
+        let crossfade = self.controls.crossfade();
+        let (Some(total), false) = (current.total_frames, crossfade.is_zero()) else {
+            return;
+        };
+        if self.controls.is_held(current.id) {
+            return;
+        }
+        let sample_rate = current.source.sample_rate();
+        let fade_frames = (crossfade.as_secs_f64() * sample_rate as f64) as u64;
+        let remaining = total.saturating_sub(current.frames);
+        if remaining == 0 || remaining > fade_frames {
+            return;
+        }
+
+        // A short incoming track shortens the fade so it can't run out first.
+        let length = |next: &Playing| {
+            next.total_frames
+                .map_or(remaining, |next_frames| remaining.min(next_frames))
+                .max(1)
+        };
+        let Ok(mut upcoming) = self.controls.upcoming.try_lock() else {
+            return;
+        };
+        let Some(incoming) = upcoming.take_next(self.controls.repeat(), current.id, |next| {
+            next.source.channels() == current.source.channels()
+                && next.source.sample_rate() == sample_rate
+                && remaining <= length(next)
+        }) else {
+            return;
+        };
+        drop(upcoming);
+        let length = length(&incoming);
+
+        self.fade = Some(Fade {
+            incoming,
+            frame: 0,
+            length,
+            handed_over: false,
+        });
+    }
+
+    fn next_frame(&mut self) {
+        if let Some(fade) = &mut self.fade {
+            if !fade.handed_over && fade.frame * 2 >= fade.length {
+                fade.handed_over = true;
+                self.controls.hand_over(fade.incoming.id, true);
+            }
+            if fade.frame >= fade.length {
+                let fade = self.fade.take().unwrap();
+                if let Some(ended) = self.current.replace(fade.incoming) {
+                    self.controls.signal(Signal::Ended(ended.id));
+                }
+            }
+        } else {
+            self.maybe_start_fade();
+        }
+        if let Some(current) = &mut self.current {
+            current.start(&self.controls);
+            self.channels = current.source.channels().max(1);
+        }
+        if let Some(fade) = &mut self.fade {
+            fade.incoming.start(&self.controls);
+        }
+    }
+}
+
+impl Iterator for Playlist {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.next_frame();
+        }
+
+        let sample = match (&mut self.current, &mut self.fade) {
+            (None, _) => return None,
+            (Some(current), Some(fade)) => {
+                let t = (fade.frame as f32 + 0.5) / fade.length as f32 * FRAC_PI_2;
+                let outgoing = current.source.next().unwrap_or(0.0);
+                let incoming = fade.incoming.source.next().unwrap_or(0.0);
+                outgoing * t.cos() + incoming * t.sin()
+            }
+            (Some(current), None) => match current.source.next() {
+                Some(sample) => sample,
+                None => {
+                    self.advance();
+                    self.channel = 0;
+                    return self.next();
+                }
+            },
+        };
+
+        self.channel += 1;
+        if self.channel >= self.channels {
+            self.channel = 0;
+            if let Some(current) = &mut self.current {
+                current.frames += 1;
+            }
+            if let Some(fade) = &mut self.fade {
+                fade.incoming.frames += 1;
+                fade.frame += 1;
+            }
+            self.count_played();
+            self.publish_position();
+        }
+        Some(sample)
+    }
+}
+
+impl Source for Playlist {
+    fn current_frame_len(&self) -> Option<usize> {
+        // Like rodio's own queue, never promise a format for longer than a
+        // short span, since the next track may differ.
+        let len = self
+            .current
+            .as_ref()
+            .and_then(|current| current.source.current_frame_len());
+        Some(len.map_or(512, |len| len.clamp(1, 512)))
+    }
+
+    fn channels(&self) -> u16 {
+        self.current
+            .as_ref()
+            .map_or(1, |current| current.source.channels())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.current
+            .as_ref()
+            .map_or(44100, |current| current.source.sample_rate())
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let Some(current) = &mut self.current else {
+            return Ok(());
+        };
+        if let Some(fade) = &mut self.fade {
+            fade.incoming.source.try_seek(Duration::ZERO)?;
+        }
+        current.source.try_seek(pos)?;
+        current.frames = (pos.as_secs_f64() * current.source.sample_rate() as f64) as u64;
+        if pos.is_zero() {
+            self.controls.restart_playthrough();
+        }
+        self.channel = 0;
+        if let Some(fade) = self.fade.take() {
+            // The seek lands in the outgoing track, so the rewound incoming
+            // one goes back to the front of the queue.
+            if fade.handed_over {
+                self.controls.set_current(current.id);
+            }
+            let mut incoming = fade.incoming;
+            incoming.frames = 0;
+            self.controls.upcoming.locked().queue.push_front(incoming);
+        }
+        self.publish_position();
+        Ok(())
+    }
+}
@@ -0,0 +1,760 @@
+use crate::{
+    decode::{self, DecoderBackend, FileDecoder},
+    error::PlayerError,
+    events::{PlayerEvent, Signal},
+    stats::StatsControls,
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    any::Any,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    time::Duration,
+};
+
+/// How far short of the length in its header a FLAC or WAV file may end
+/// before that counts as cut short.
+const END_SLACK: Duration = Duration::from_millis(500);
+
+/// Times in a row decoding is picked up again without getting far, before
+/// the file is given up on.
+const MAX_RESYNCS: u32 = 8;
+
+/// How far decoding has to get after picking up again to count as past the
+/// damage.
+const SAME_DAMAGE: Duration = Duration::from_secs(1);
+
+/// Places a frame could start at that are tried per resync.
+const MAX_CANDIDATES: usize = 64;
+
+/// How far past the damage to look for those places.
+const SCAN_WINDOW: usize = 256 * 1024;
+
+/// Whether decode errors end a file, and which decoder files are opened
+/// with, shared between the player and each [`FaultTolerant`] decoder.
+pub(crate) struct DecodeControls {
+    strict: AtomicBool,
+    backend: AtomicU8,
+}
+
+impl DecodeControls {
+    pub(crate) fn new() -> Self {
+        DecodeControls {
+            strict: AtomicBool::new(false),
+            backend: AtomicU8::new(DecoderBackend::Auto as u8),
+        }
+    }
+
+    pub(crate) fn is_strict(&self) -> bool {
+        self.strict.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_strict(&self, strict: bool) {
+        self.strict.store(strict, Ordering::Relaxed);
+    }
+
+    pub(crate) fn backend(&self) -> DecoderBackend {
+        DecoderBackend::from_u8(self.backend.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_backend(&self, backend: DecoderBackend) {
+        self.backend.store(backend as u8, Ordering::Relaxed);
+    }
+}
+
+/// Decodes a file, getting past frames the decoder can't.
+///
+/// The decoders stop at the first bad frame, and a few can panic on a
+/// truncated one. Either, before the decoder has read to the end of the
+/// audio, counts as damage: the file is opened again at the first place past
+/// it where a frame decodes, with silence in place of what was lost, so the
+/// track keeps about its length. FLAC, MP3 and WAV files can be picked up
+/// again this way; others, and any file while the controls are strict, end
+/// at the damage as they always have. A [`PlayerEvent::DecodeError`] says
+/// where each time, as it does for a FLAC or WAV file that ends well short
+/// of the length in its header.
+pub(crate) struct FaultTolerant {
+    path: PathBuf,
+    /// What the file was opened with, so it is picked up again with the same.
+    backend: DecoderBackend,
+    decoder: FileDecoder,
+    /// How far into the file the decoder has read, as an offset.
+    read: Arc<AtomicU64>,
+    /// Whether `decoder` has taken over past damage from the one first opened.
+    resumed: bool,
+    layout: Option<Layout>,
+    length: u64,
+    duration: Option<Duration>,
+    controls: Arc<DecodeControls>,
+    /// Where events go, and what counts damage got past, or nowhere for a
+    /// decoder filling a cache.
+    signals: Option<Sender<Signal>>,
+    stats: Option<Arc<StatsControls>>,
+    channels: u16,
+    sample_rate: u32,
+    /// Samples played so far, silence included.
+    played: u64,
+    /// Samples of silence left to play in place of a damaged stretch.
+    silence: u64,
+    /// The first sample of a resumed decoder, read to check it decodes.
+    pending: Option<f32>,
+    /// How far the file had been read, and played, at the last whole second.
+    mark: Option<(u64, Duration)>,
+    /// Where damage was last found, and how many times in a row decoding
+    /// has stopped again soon after.
+    damage: Option<Duration>,
+    failures: u32,
+    /// Whether the file is shorter than its header says, which the WAV
+    /// decoder makes up for with silence, and has yet to be reported.
+    cut_short: bool,
+    ended: bool,
+}
+
+impl FaultTolerant {
+    /// Opens the file at `path`, reporting damage to `signals` and counting
+    /// what it gets past in `stats`.
+    pub(crate) fn open(
+        path: &Path,
+        controls: Arc<DecodeControls>,
+        signals: Option<Sender<Signal>>,
+        stats: Option<Arc<StatsControls>>,
+    ) -> Result<Self, PlayerError> {
+        let mut file = File::open(path)?;
+        let layout = Layout::read(&mut file).ok().flatten();
+        let length = file.metadata()?.len();
+        let cut_short = layout
+            .as_ref()
+            .is_some_and(|layout| layout.audio_end != u64::MAX && layout.audio_end > length);
+        let read = Arc::new(AtomicU64::new(0));
+        let reader = Spliced::new(Vec::new(), file, 0, read.clone())?;
+        let backend = controls.backend().resolve(path);
+        let decoder = decode::open(reader, path, backend)?;
+        Ok(FaultTolerant {
+            path: path.to_path_buf(),
+            backend,
+            channels: decoder.channels(),
+            sample_rate: decoder.sample_rate(),
+            duration: decoder.total_duration(),
+            decoder,
+            read,
+            resumed: false,
+            layout,
+            length,
+            controls,
+            signals,
+            stats,
+            played: 0,
+            silence: 0,
+            pending: None,
+            mark: None,
+            damage: None,
+            failures: 0,
+            cut_short,
+            ended: false,
+        })
+    }
+
+    fn played_time(&self) -> Duration {
+        let frames = self.played / u64::from(self.channels.max(1));
+        Duration::from_secs_f64(frames as f64 / f64::from(self.sample_rate.max(1)))
+    }
+
+    /// Whether silence, or the sample read to check a resumed decoder, is
+    /// what plays next.
+    fn filling(&self) -> bool {
+        self.silence > 0 || self.pending.is_some()
+    }
+
+    fn report(&self, position: Duration, detail: String) {
+        if let Some(signals) = &self.signals {
+            let event = PlayerEvent::DecodeError { position, detail };
+            let _ = signals.send(Signal::Event(event));
+        }
+    }
+
+    /// The decoder has stopped, or failed with `panic`. Reports it if that
+    /// was before the end, and tries to carry on past the damage. Returns
+    /// whether it did.
+    fn stopped(&mut self, panic: Option<String>) -> bool {
+        let position = self.played_time();
+        let read = self.read.load(Ordering::Relaxed);
+        let Some(layout) = &self.layout else {
+            if let Some(panic) = panic {
+                self.report(position, format!("{panic}; the track ends here"));
+            }
+            return false;
+        };
+        if panic.is_none() && read >= layout.audio_end.min(self.length) {
+            // Cut short, rather than damaged, where the header can tell.
+            let short = self
+                .duration
+                .filter(|_| layout.exact_length())
+                .map_or(Duration::ZERO, |duration| duration.saturating_sub(position));
+            if short > END_SLACK {
+                let detail = format!(
+                    "the file ends {:.1} s before the length in its header",
+                    short.as_secs_f64()
+                );
+                self.report(position, detail);
+            }
+            return false;
+        }
+
+        if self
+            .damage
+            .is_some_and(|last| position < last + SAME_DAMAGE)
+        {
+            self.failures += 1;
+        } else {
+            self.failures = 0;
+        }
+        self.damage = Some(position);
+        let detail = panic.unwrap_or_else(|| "a frame failed to decode".to_string());
+        let resumed = match self.controls.is_strict() || self.failures >= MAX_RESYNCS {
+            true => None,
+            false => self.resync(position, read),
+        };
+        if let (Some(stats), Some(_)) = (&self.stats, resumed) {
+            stats.add_decode_error();
+        }
+        let detail = match resumed {
+            Some(gap) => format!("{detail}; playing on {:.1} s later", gap.as_secs_f64()),
+            None => format!("{detail}; the track ends here"),
+        };
+        self.report(position, detail);
+        resumed.is_some()
+    }
+
+    /// Marks how far the file has been read, and reports it once that is to
+    /// the end of one cut short.
+    fn passed_second(&mut self) {
+        let read = self.read.load(Ordering::Relaxed);
+        if !self.resumed {
+            self.mark = Some((read, self.played_time()));
+        }
+        if self.cut_short && read >= self.length {
+            self.cut_short = false;
+            let position = self.played_time();
+            let short = self
+                .duration
+                .map_or(Duration::ZERO, |duration| duration.saturating_sub(position));
+            let detail = format!(
+                "the file ends {:.1} s before the length in its header; the rest is silence",
+                short.as_secs_f64()
+            );
+            self.report(position, detail);
+        }
+    }
+
+    /// Opens the file again at the first frame that decodes past the
+    /// damage, found `position` into the track, queueing silence for what
+    /// lies between. Returns how long that is.
+    fn resync(&mut self, position: Duration, read: u64) -> Option<Duration> {
+        let layout = self.layout.as_ref()?;
+        let mut file = File::open(&self.path).ok()?;
+        let header = layout.header(&mut file).ok()?;
+        // How many bytes a second takes so far tells where the damage
+        // starts: a decoder may have read well past it looking for a frame.
+        let (bytes_per_second, damage) = match (self.mark, self.duration) {
+            (Some((offset, at)), _) => {
+                let rate = offset.saturating_sub(layout.audio_start) as f64 / at.as_secs_f64();
+                let since = (position.saturating_sub(at).as_secs_f64() * rate) as u64;
+                (rate, read.min(offset + since))
+            }
+            (None, Some(duration)) if !duration.is_zero() => {
+                let audio = layout.audio_end.min(self.length) - layout.audio_start;
+                (audio as f64 / duration.as_secs_f64(), read)
+            }
+            _ => return None,
+        };
+        for start in layout.candidates(&mut file, read).ok()? {
+            let Some((decoder, first)) = self.open_at(&header, start) else {
+                continue;
+            };
+            let gap = (start - damage) as f64 / bytes_per_second.max(1.0);
+            let gap = Duration::from_secs_f64(gap);
+            // Whatever of a frame was cut short is made up first, so the
+            // channels stay in step.
+            let channels = u64::from(self.channels.max(1));
+            let unfinished = (channels - self.played % channels) % channels;
+            let frames = (gap.as_secs_f64() * f64::from(self.sample_rate)) as u64;
+            self.silence = unfinished + frames * channels;
+            self.pending = Some(first);
+            self.decoder = decoder;
+            self.resumed = true;
+            return Some(gap);
+        }
+        None
+    }
+
+    /// A decoder for the file going from `start`, after `header`, with its
+    /// first sample, if a frame decodes there.
+    fn open_at(&self, header: &[u8], start: u64) -> Option<(FileDecoder, f32)> {
+        let file = File::open(&self.path).ok()?;
+        let reader = Spliced::new(header.to_vec(), file, start, self.read.clone()).ok()?;
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut decoder = decode::open(reader, &self.path, self.backend).ok()?;
+            let first = decoder.next()?;
+            Some((decoder, first))
+        }))
+        .ok()
+        .flatten()
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no reason given");
+    format!("the decoder failed: {message}")
+}
+
+impl Iterator for FaultTolerant {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.silence > 0 {
+                self.silence -= 1;
+                self.played += 1;
+                return Some(0.0);
+            }
+            if let Some(sample) = self.pending.take() {
+                self.played += 1;
+                return Some(sample);
+            }
+            if self.ended {
+                return None;
+            }
+            let panic = match panic::catch_unwind(AssertUnwindSafe(|| self.decoder.next())) {
+                Ok(Some(sample)) => {
+                    self.played += 1;
+                    let second = u64::from(self.channels) * u64::from(self.sample_rate);
+                    if self.played.is_multiple_of(second.max(1)) {
+                        self.passed_second();
+                    }
+                    return Some(sample);
+                }
+                Ok(None) => None,
+                Err(payload) => Some(panic_message(&*payload)),
+            };
+            self.channels = self.decoder.channels();
+            self.sample_rate = self.decoder.sample_rate();
+            if !self.stopped(panic) {
+                self.ended = true;
+            }
+        }
+    }
+}
+
+impl Source for FaultTolerant {
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.filling() {
+            true => Some(self.silence as usize + usize::from(self.pending.is_some())),
+            false => self.decoder.current_frame_len(),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self.filling() {
+            true => self.channels,
+            false => self.decoder.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self.filling() {
+            true => self.sample_rate,
+            false => self.decoder.sample_rate(),
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// A file picked up past damage is opened afresh to seek it, from the
+    /// start.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        if self.resumed {
+            return Err(SeekError::NotSupported {
+                underlying_source: std::any::type_name::<Self>(),
+            });
+        }
+        self.decoder.try_seek(pos)?;
+        let channels = u64::from(self.decoder.channels());
+        let frames = (pos.as_secs_f64() * f64::from(self.decoder.sample_rate())) as u64;
+        self.played = frames * channels;
+        self.mark = None;
+        self.damage = None;
+        self.failures = 0;
+        self.ended = false;
+        Ok(())
+    }
+}
+
+/// Where the frames of a file start and end, and what the file needs in
+/// front of a later one for its decoder to pick up there.
+struct Layout {
+    /// The bytes copied from the start of the file ahead of the frame
+    /// decoding picks up at.
+    header_end: u64,
+    audio_start: u64,
+    /// Where the audio ends, if something may follow it that the decoder
+    /// leaves unread.
+    audio_end: u64,
+    frames: Frames,
+}
+
+/// How to find the next place a frame could start.
+enum Frames {
+    /// At a FLAC frame's sync code.
+    Flac,
+    /// At an MPEG audio frame's sync word.
+    Mpeg,
+    /// At the start of each block of this many bytes, for PCM.
+    Blocks(u64),
+}
+
+impl Layout {
+    /// The layout of a FLAC, MP3 or WAV file, or `None` for anything else.
+    fn read(file: &mut File) -> io::Result<Option<Layout>> {
+        file.seek(SeekFrom::Start(0))?;
+        let mut magic = [0; 12];
+        file.read_exact(&mut magic)?;
+        if &magic[..4] == b"fLaC" {
+            // The metadata blocks, and so STREAMINFO, go ahead of the frames.
+            file.seek(SeekFrom::Start(4))?;
+            loop {
+                let mut block = [0; 4];
+                file.read_exact(&mut block)?;
+                let length = u32::from_be_bytes([0, block[1], block[2], block[3]]);
+                file.seek(SeekFrom::Current(i64::from(length)))?;
+                if block[0] & 0x80 != 0 {
+                    break;
+                }
+            }
+            let audio_start = file.stream_position()?;
+            return Ok(Some(Layout {
+                header_end: audio_start,
+                audio_start,
+                audio_end: u64::MAX,
+                frames: Frames::Flac,
+            }));
+        }
+        if &magic[..4] == b"RIFF" && &magic[8..] == b"WAVE" {
+            // The fmt chunk has the block size, and comes before the data.
+            let mut block_align = None;
+            loop {
+                let mut chunk = [0; 8];
+                file.read_exact(&mut chunk)?;
+                let length = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+                if &chunk[..4] == b"data" {
+                    let audio_start = file.stream_position()?;
+                    return Ok(block_align.map(|align| Layout {
+                        header_end: audio_start,
+                        audio_start,
+                        audio_end: audio_start + u64::from(length),
+                        frames: Frames::Blocks(align),
+                    }));
+                }
+                if &chunk[..4] == b"fmt " {
+                    let mut format = [0; 14];
+                    file.read_exact(&mut format)?;
+                    block_align = Some(u64::from(u16::from_le_bytes([format[12], format[13]])));
+                    file.seek(SeekFrom::Current(i64::from(length) - 14))?;
+                } else {
+                    file.seek(SeekFrom::Current(i64::from(length)))?;
+                }
+                // Chunks are padded to an even length.
+                if length % 2 == 1 {
+                    file.seek(SeekFrom::Current(1))?;
+                }
+            }
+        }
+        let audio_start = if &magic[..3] == b"ID3" {
+            let size = magic[6..10]
+                .iter()
+                .fold(0, |size, &byte| size << 7 | u64::from(byte & 0x7F));
+            let footer = if magic[5] & 0x10 != 0 { 10 } else { 0 };
+            10 + size + footer
+        } else if is_mpeg_sync(magic[0], magic[1]) {
+            0
+        } else {
+            return Ok(None);
+        };
+        // MPEG frames carry all a decoder needs.
+        Ok(Some(Layout {
+            header_end: 0,
+            audio_start,
+            audio_end: u64::MAX,
+            frames: Frames::Mpeg,
+        }))
+    }
+
+    /// Whether the header gives the length to the sample, rather than as
+    /// an estimate.
+    fn exact_length(&self) -> bool {
+        !matches!(self.frames, Frames::Mpeg)
+    }
+
+    fn header(&self, file: &mut File) -> io::Result<Vec<u8>> {
+        let mut header = vec![0; self.header_end as usize];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        Ok(header)
+    }
+
+    /// Offsets from `from` on where a frame could start, nearest first.
+    fn candidates(&self, file: &mut File, from: u64) -> io::Result<Vec<u64>> {
+        let from = from.max(self.audio_start);
+        if let Frames::Blocks(align) = self.frames {
+            let align = align.max(1);
+            let start = self.audio_start + (from - self.audio_start).div_ceil(align) * align;
+            return Ok(Vec::from_iter((start < self.audio_end).then_some(start)));
+        }
+        let mut window = Vec::with_capacity(SCAN_WINDOW);
+        file.seek(SeekFrom::Start(from))?;
+        file.take(SCAN_WINDOW as u64).read_to_end(&mut window)?;
+        let is_sync = match self.frames {
+            Frames::Flac => |first: u8, second: u8| first == 0xFF && second & 0xFE == 0xF8,
+            _ => is_mpeg_sync,
+        };
+        Ok(window
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| is_sync(pair[0], pair[1]))
+            .map(|(at, _)| from + at as u64)
+            .take(MAX_CANDIDATES)
+            .collect())
+    }
+}
+
+/// An MPEG audio frame sync word, with a layer that isn't the reserved one.
+fn is_mpeg_sync(first: u8, second: u8) -> bool {
+    first == 0xFF && second & 0xE0 == 0xE0 && second & 0x06 != 0
+}
+
+/// A file as if `header` were followed straight away by what is in it from
+/// `start` on, which a decoder then reads as frames following on from the
+/// header. Keeps `read` at the offset in the file reading has got to.
+struct Spliced {
+    header: Vec<u8>,
+    file: File,
+    start: u64,
+    length: u64,
+    position: u64,
+    read: Arc<AtomicU64>,
+}
+
+impl Spliced {
+    fn new(header: Vec<u8>, file: File, start: u64, read: Arc<AtomicU64>) -> io::Result<Self> {
+        let rest = file.metadata()?.len().saturating_sub(start);
+        Ok(Spliced {
+            length: header.len() as u64 + rest,
+            header,
+            file,
+            start,
+            position: 0,
+            read,
+        })
+    }
+}
+
+impl Read for Spliced {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let header = self.header.len() as u64;
+        let read = if self.position < header {
+            let from = &self.header[self.position as usize..];
+            let len = from.len().min(buf.len());
+            buf[..len].copy_from_slice(&from[..len]);
+            len
+        } else {
+            let offset = self.start + self.position - header;
+            self.file.seek(SeekFrom::Start(offset))?;
+            let len = self.file.read(buf)?;
+            self.read.store(offset + len as u64, Ordering::Relaxed);
+            len
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for Spliced {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+        };
+        self.position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FlacFile, WavFile};
+    use std::{env, fs, process, sync::mpsc};
+
+    /// A file in the temp dir removed once dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let path = env::temp_dir().join(format!("fullyrustaudio-{}-{name}", process::id()));
+            fs::write(&path, bytes).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Three seconds of [`FlacFile::sine`], named for `name`.
+    fn flac(name: &str) -> Vec<u8> {
+        let file = FlacFile::sine(name, Duration::from_secs(3));
+        fs::read(&file.path).unwrap()
+    }
+
+    /// The first 200 KB of the MP3 in the repo, about 11 s.
+    fn mp3() -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("outaspace.mp3");
+        let mut bytes = fs::read(path).unwrap();
+        bytes.truncate(200 * 1024);
+        bytes
+    }
+
+    /// Four seconds of [`WavFile::sine`], named for `name`.
+    fn wav(name: &str) -> Vec<u8> {
+        let file = WavFile::sine(name, Duration::from_secs(4));
+        fs::read(&file.path).unwrap()
+    }
+
+    /// Overwrites 4 KB a third of the way into `bytes` with noise that has
+    /// what looks like an MPEG frame header every 417 bytes, so neither the
+    /// FLAC nor the MP3 decoder can simply skip it.
+    fn damaged(mut bytes: Vec<u8>) -> Vec<u8> {
+        let at = bytes.len() / 3;
+        let mut noise = 12345u32;
+        for (index, byte) in bytes[at..at + 4096].iter_mut().enumerate() {
+            noise = noise.wrapping_mul(1664525).wrapping_add(1013904223);
+            *byte = match index % 417 {
+                0 => 0xFF,
+                1 => 0xFB,
+                _ => (noise >> 24) as u8,
+            };
+        }
+        bytes
+    }
+
+    /// Every sample of `file`, and the details of the decode errors
+    /// reported on the way.
+    fn decode_all(file: &TempFile, strict: bool) -> (Vec<f32>, Vec<String>) {
+        let controls = Arc::new(DecodeControls::new());
+        controls.set_strict(strict);
+        let (signals, received) = mpsc::channel();
+        let decoder = FaultTolerant::open(&file.0, controls, Some(signals), None).unwrap();
+        let samples = decoder.collect();
+        let errors = received
+            .try_iter()
+            .filter_map(|signal| match signal {
+                Signal::Event(PlayerEvent::DecodeError { detail, .. }) => Some(detail),
+                _ => None,
+            })
+            .collect();
+        (samples, errors)
+    }
+
+    /// The damaged file `name` made of `bytes`, and how many samples the
+    /// clean one decodes to.
+    fn damaged_file(name: &str, bytes: Vec<u8>) -> (TempFile, usize) {
+        let clean = TempFile::new(&format!("clean-{name}"), &bytes);
+        let length = decode::open_file(&clean.0, DecoderBackend::Auto)
+            .unwrap()
+            .count();
+        (TempFile::new(name, &damaged(bytes)), length)
+    }
+
+    #[test]
+    fn a_clean_file_passes_through_untouched() {
+        for (name, bytes) in [
+            ("clean.flac", flac("clean-source")),
+            ("clean.mp3", mp3()),
+            ("clean.wav", wav("clean-source")),
+        ] {
+            let file = TempFile::new(name, &bytes);
+            let plain = decode::open_file(&file.0, DecoderBackend::Auto)
+                .unwrap()
+                .collect::<Vec<_>>();
+            assert_eq!(decode_all(&file, false), (plain, Vec::new()), "{name}");
+        }
+    }
+
+    #[test]
+    fn damage_is_played_past_with_a_decode_error() {
+        for (name, bytes) in [
+            ("damaged.flac", flac("damaged-source")),
+            ("damaged.mp3", mp3()),
+        ] {
+            let (file, length) = damaged_file(name, bytes);
+            let (samples, errors) = decode_all(&file, false);
+            // About as long, with silence in place of what was lost.
+            let off = samples.len().abs_diff(length) as f64 / length as f64;
+            assert!(off < 0.1, "{name}: {} samples of {length}", samples.len());
+            assert_eq!(errors.len(), 1, "{name}: {errors:?}");
+            assert!(errors[0].contains("playing on"), "{name}: {errors:?}");
+        }
+    }
+
+    #[test]
+    fn strict_decoding_ends_at_the_damage() {
+        for (name, bytes) in [
+            ("strict.flac", flac("strict-source")),
+            ("strict.mp3", mp3()),
+        ] {
+            let (file, length) = damaged_file(name, bytes);
+            let (samples, errors) = decode_all(&file, true);
+            assert!(
+                samples.len() < length / 2,
+                "{name}: {} samples",
+                samples.len()
+            );
+            assert_eq!(errors.len(), 1, "{name}: {errors:?}");
+            assert!(
+                errors[0].ends_with("the track ends here"),
+                "{name}: {errors:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_wav_cut_short_plays_out_its_length_in_silence() {
+        let bytes = wav("cut-source");
+        let clean = TempFile::new("whole.wav", &bytes);
+        let length = decode_all(&clean, false).0.len();
+        let cut = TempFile::new("cut.wav", &bytes[..bytes.len() / 2]);
+        let (samples, errors) = decode_all(&cut, false);
+        assert_eq!(samples.len(), length);
+        assert!(samples[length * 3 / 4..]
+            .iter()
+            .all(|&sample| sample == 0.0));
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].ends_with("the rest is silence"), "{errors:?}");
+    }
+}
@@ -0,0 +1,322 @@
+//! Offline rendering: runs a file through the equalizer and writes the
+//! result to disk instead of playing it.
+//!
+//! Nothing here touches an output device, so rendering works headless and
+//! runs as fast as the file decodes. [`batch`] does a whole directory.
+
+use crate::{
+    decode::{self, DecoderBackend},
+    dither::{Dither, Ditherer},
+    equalizer::{EqControls, Equalizer},
+    gain::{db_to_linear, linear_to_db, Gain, GainControls},
+    limiter::Limiter,
+    lock::Lock,
+    settings::EqSettings,
+};
+use rodio::Source;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Extensions [`batch`] picks up, matched without regard to case.
+pub const BATCH_EXTENSIONS: [&str; 4] = ["flac", "mp3", "ogg", "wav"];
+
+pub type RenderError = Box<dyn Error + Send + Sync>;
+
+/// How a render is written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOptions {
+    /// 16 or 24.
+    pub bits_per_sample: u16,
+    /// Gain after the equalizer, in dB.
+    pub volume_db: f32,
+    /// Run the output through the limiter so EQ boosts can't clip.
+    pub limiter: bool,
+    /// Replace `output_path` if it already exists.
+    pub overwrite: bool,
+    /// What is added before rounding to `bits_per_sample`.
+    pub dither: Dither,
+    /// Seeds the dither noise, so the same render always writes the same
+    /// file.
+    pub dither_seed: u64,
+    /// What `input_path` is decoded with.
+    pub decoder: DecoderBackend,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            bits_per_sample: 16,
+            volume_db: 0.0,
+            limiter: false,
+            overwrite: false,
+            dither: Dither::Tpdf,
+            dither_seed: 0,
+            decoder: DecoderBackend::Auto,
+        }
+    }
+}
+
+/// What a finished render wrote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderStats {
+    /// Interleaved samples, i.e. frames times channels.
+    pub samples_written: u64,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Highest sample magnitude before quantization, in dBFS. Anything above
+    /// 0 was clipped in the file.
+    pub peak_db: f32,
+}
+
+/// Renders `input_path` through `settings` into a 16-bit WAV file, keeping
+/// its sample rate and channel count. Fails if `output_path` exists.
+pub fn to_wav(
+    input_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    settings: &EqSettings,
+) -> Result<RenderStats, RenderError> {
+    to_wav_with(input_path, output_path, settings, RenderOptions::default())
+}
+
+/// Like [`to_wav`], with the bit depth, volume, limiter, dither and
+/// overwrite behaviour set by `options`.
+pub fn to_wav_with(
+    input_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    settings: &EqSettings,
+    options: RenderOptions,
+) -> Result<RenderStats, RenderError> {
+    let bits = options.bits_per_sample;
+    if bits != 16 && bits != 24 {
+        return Err(format!("unsupported bit depth {bits}, expected 16 or 24").into());
+    }
+
+    let input_path = input_path.as_ref();
+    let backend = options.decoder.resolve(input_path);
+    let decoder = decode::open(File::open(input_path)?, input_path, backend)?;
+    // Bands above the file's Nyquist frequency are left flat, as in playback.
+    settings.validate_shape()?;
+    let equalizer = Equalizer::with_controls(decoder, Arc::new(EqControls::new(settings.clone())));
+    let channels = equalizer.channels();
+    let sample_rate = equalizer.sample_rate();
+    let volume = GainControls::new(db_to_linear(options.volume_db), Duration::ZERO);
+    let gained = Gain::new(equalizer, Arc::new(volume));
+    let source: Box<dyn Source<Item = f32>> = if options.limiter {
+        Box::new(Limiter::new(gained))
+    } else {
+        Box::new(gained)
+    };
+
+    let output_path = output_path.as_ref();
+    let file = if options.overwrite {
+        File::create(output_path)?
+    } else {
+        File::create_new(output_path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                format!("{} already exists", output_path.display()).into()
+            }
+            _ => RenderError::from(err),
+        })?
+    };
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: bits,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::new(BufWriter::new(file), spec)?;
+
+    let full_scale = ((1i32 << (bits - 1)) - 1) as f32;
+    let mut ditherer = Ditherer::new(options.dither, full_scale, channels, options.dither_seed);
+    let mut peak = 0.0f32;
+    let mut samples_written = 0;
+    for sample in source {
+        peak = peak.max(sample.abs());
+        writer.write_sample(ditherer.quantize(sample))?;
+        samples_written += 1;
+    }
+    writer.finalize()?;
+
+    Ok(RenderStats {
+        samples_written,
+        channels,
+        sample_rate,
+        peak_db: linear_to_db(peak),
+    })
+}
+
+/// How [`batch`] goes through a directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchOptions {
+    /// How each file is written. Without `overwrite`, files whose output
+    /// already exists are skipped.
+    pub render: RenderOptions,
+    /// Go into subdirectories too, mirroring them in the output directory.
+    pub recursive: bool,
+    /// How many files are rendered at once; one per CPU by default.
+    pub jobs: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            render: RenderOptions::default(),
+            recursive: false,
+            jobs: thread::available_parallelism().map_or(1, usize::from),
+        }
+    }
+}
+
+/// How one file of a [`batch`] went.
+#[derive(Debug)]
+pub enum BatchOutcome {
+    Rendered(RenderStats),
+    /// Its output was there already.
+    Skipped,
+    Failed(RenderError),
+}
+
+#[derive(Debug)]
+pub struct BatchFile {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub outcome: BatchOutcome,
+}
+
+/// Every file a [`batch`] found, in path order.
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    pub files: Vec<BatchFile>,
+}
+
+impl BatchSummary {
+    pub fn rendered(&self) -> usize {
+        self.count(|outcome| matches!(outcome, BatchOutcome::Rendered(_)))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|outcome| matches!(outcome, BatchOutcome::Skipped))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, BatchOutcome::Failed(_)))
+    }
+
+    fn count(&self, matches: impl Fn(&BatchOutcome) -> bool) -> usize {
+        self.files
+            .iter()
+            .filter(|file| matches(&file.outcome))
+            .count()
+    }
+}
+
+/// Renders every file in `input_dir` with one of the [`BATCH_EXTENSIONS`]
+/// through `settings`, as [`to_wav_with`] does, into a WAV file of the same
+/// name and relative path under `output_dir`. `progress` is called from
+/// the worker threads as each file finishes, with how many have so far and
+/// how many there are.
+///
+/// A file that fails is recorded in the summary and the rest carry on; only
+/// failing to list the input or create the output directory stops the run.
+pub fn batch(
+    input_dir: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+    settings: &EqSettings,
+    options: BatchOptions,
+    progress: impl Fn(usize, usize, &BatchFile) + Sync,
+) -> Result<BatchSummary, RenderError> {
+    let (input_dir, output_dir) = (input_dir.as_ref(), output_dir.as_ref());
+    fs::create_dir_all(output_dir)?;
+    // Outputs inside the input directory aren't inputs.
+    let skip = fs::canonicalize(output_dir)?;
+    let mut inputs = Vec::new();
+    collect_inputs(input_dir, &skip, options.recursive, &mut inputs)?;
+    inputs.sort();
+
+    let total = inputs.len();
+    let (next, done) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let results = Mutex::new(Vec::with_capacity(total));
+    thread::scope(|scope| {
+        for _ in 0..options.jobs.clamp(1, total.max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(input) = inputs.get(index) else {
+                    break;
+                };
+                let relative = input.strip_prefix(input_dir).unwrap_or(input);
+                let output = output_dir.join(relative).with_extension("wav");
+                let outcome = render_one(input, &output, settings, options.render);
+                let file = BatchFile {
+                    input: input.clone(),
+                    output,
+                    outcome,
+                };
+                progress(done.fetch_add(1, Ordering::Relaxed) + 1, total, &file);
+                results.locked().push((index, file));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+    results.sort_by_key(|&(index, _)| index);
+    Ok(BatchSummary {
+        files: results.into_iter().map(|(_, file)| file).collect(),
+    })
+}
+
+fn render_one(
+    input: &Path,
+    output: &Path,
+    settings: &EqSettings,
+    options: RenderOptions,
+) -> BatchOutcome {
+    if !options.overwrite && output.exists() {
+        return BatchOutcome::Skipped;
+    }
+    let rendered = output
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .map_err(RenderError::from)
+        .and_then(|()| to_wav_with(input, output, settings, options));
+    match rendered {
+        Ok(stats) => BatchOutcome::Rendered(stats),
+        Err(err) => BatchOutcome::Failed(err),
+    }
+}
+
+fn collect_inputs(
+    dir: &Path,
+    skip: &Path,
+    recursive: bool,
+    inputs: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive && fs::canonicalize(&path)? != skip {
+                collect_inputs(&path, skip, recursive, inputs)?;
+            }
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                BATCH_EXTENSIONS
+                    .iter()
+                    .any(|supported| ext.eq_ignore_ascii_case(supported))
+            })
+        {
+            inputs.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use crate::{
+    album::{GainMode, TrackGain},
+    atomic::AtomicF32,
+    gain::linear_to_db,
+    lock::Lock,
+    metadata::ReplayGainTags,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex,
+    },
+};
+
+/// Which of a file's ReplayGain tags it plays by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ReplayGainMode {
+    /// The tags are left alone.
+    #[default]
+    Off,
+    /// Each track at the reference level, by its track gain.
+    Track,
+    /// Each album at the reference level, its tracks as loud against each
+    /// other as they were mastered, by the album gain.
+    Album,
+}
+
+impl ReplayGainMode {
+    pub const ALL: [ReplayGainMode; 3] = [
+        ReplayGainMode::Off,
+        ReplayGainMode::Track,
+        ReplayGainMode::Album,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReplayGainMode::Off => "off",
+            ReplayGainMode::Track => "track",
+            ReplayGainMode::Album => "album",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ReplayGainMode::Track,
+            2 => ReplayGainMode::Album,
+            _ => ReplayGainMode::Off,
+        }
+    }
+}
+
+impl fmt::Display for ReplayGainMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ReplayGainMode {
+    type Err = String;
+
+    /// Looks a mode up by name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ReplayGainMode::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = ReplayGainMode::ALL.map(ReplayGainMode::name).join(", ");
+                format!("unknown ReplayGain mode '{s}', expected one of {names}")
+            })
+    }
+}
+
+/// The ReplayGain mode and levels, and the gain each track was opened with.
+pub(crate) struct ReplayGainControls {
+    mode: AtomicU8,
+    preamp_db: AtomicF32,
+    default_gain_db: AtomicF32,
+    applied: Mutex<HashMap<u64, TrackGain>>,
+}
+
+impl ReplayGainControls {
+    pub(crate) fn new() -> Self {
+        ReplayGainControls {
+            mode: AtomicU8::new(ReplayGainMode::Off as u8),
+            preamp_db: AtomicF32::new(0.0),
+            default_gain_db: AtomicF32::new(0.0),
+            applied: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn mode(&self) -> ReplayGainMode {
+        ReplayGainMode::from_u8(self.mode.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_mode(&self, mode: ReplayGainMode) {
+        self.mode.store(mode as u8, Ordering::Relaxed);
+    }
+
+    pub(crate) fn preamp_db(&self) -> f32 {
+        self.preamp_db.load()
+    }
+
+    pub(crate) fn set_preamp_db(&self, preamp_db: f32) {
+        self.preamp_db.store(preamp_db);
+    }
+
+    pub(crate) fn default_gain_db(&self) -> f32 {
+        self.default_gain_db.load()
+    }
+
+    pub(crate) fn set_default_gain_db(&self, gain_db: f32) {
+        self.default_gain_db.store(gain_db);
+    }
+
+    /// The gain a track with `tags` opens with, or `None` to leave it to
+    /// loudness normalization: with the mode off, or for a track without
+    /// tags while `normalizing`.
+    pub(crate) fn gain_db(&self, tags: &ReplayGainTags, normalizing: bool) -> Option<f32> {
+        self.gain(tags, normalizing).map(|gain| gain.gain_db)
+    }
+
+    /// Like [`ReplayGainControls::gain_db`], along with where the gain came
+    /// from.
+    pub(crate) fn gain(&self, tags: &ReplayGainTags, normalizing: bool) -> Option<TrackGain> {
+        let mode = self.mode();
+        match (mode, tagged_gain(mode, tags)) {
+            (ReplayGainMode::Off, _) => None,
+            (_, Some((gain_mode, gain_db))) => Some(TrackGain {
+                mode: gain_mode,
+                gain_db: below_peak(mode, tags, gain_db + self.preamp_db()),
+            }),
+            (_, None) if normalizing => None,
+            (_, None) => Some(TrackGain {
+                mode: GainMode::ReplayGainDefault,
+                gain_db: self.default_gain_db(),
+            }),
+        }
+    }
+
+    /// The album gain tagged in `tags`, plus the preamp and below the album
+    /// peak, whatever the mode.
+    pub(crate) fn album_gain_db(&self, tags: &ReplayGainTags) -> Option<f32> {
+        let gain_db = tags.album_gain_db? + self.preamp_db();
+        Some(match tags.album_peak {
+            Some(peak) => gain_db.min(-linear_to_db(peak)),
+            None => gain_db,
+        })
+    }
+
+    /// Records that track `id` was opened with `gain`.
+    pub(crate) fn set_applied(&self, id: u64, gain: Option<TrackGain>) {
+        let mut applied = self.applied.locked();
+        match gain {
+            Some(gain) => applied.insert(id, gain),
+            None => applied.remove(&id),
+        };
+    }
+
+    /// The gain in dB track `id` was last opened with.
+    pub(crate) fn applied(&self, id: u64) -> Option<f32> {
+        self.applied_gain(id).map(|gain| gain.gain_db)
+    }
+
+    /// What track `id` was last opened with.
+    pub(crate) fn applied_gain(&self, id: u64) -> Option<TrackGain> {
+        self.applied.locked().get(&id).copied()
+    }
+}
+
+/// The gain `mode` asks for, from the other one of the two where the file
+/// only has that, and which of the two it is.
+fn tagged_gain(mode: ReplayGainMode, tags: &ReplayGainTags) -> Option<(GainMode, f32)> {
+    let track = tags
+        .track_gain_db
+        .map(|gain_db| (GainMode::ReplayGainTrack, gain_db));
+    let album = tags
+        .album_gain_db
+        .map(|gain_db| (GainMode::ReplayGainAlbum, gain_db));
+    match mode {
+        ReplayGainMode::Off => None,
+        ReplayGainMode::Track => track.or(album),
+        ReplayGainMode::Album => album.or(track),
+    }
+}
+
+/// `gain_db` lowered where needed so the peak tagged for `mode` stays at or
+/// below full scale.
+fn below_peak(mode: ReplayGainMode, tags: &ReplayGainTags, gain_db: f32) -> f32 {
+    let peak = match mode {
+        ReplayGainMode::Album => tags.album_peak.or(tags.track_peak),
+        _ => tags.track_peak.or(tags.album_peak),
+    };
+    match peak {
+        Some(peak) => gain_db.min(-linear_to_db(peak)),
+        None => gain_db,
+    }
+}
@@ -0,0 +1,486 @@
+//! Sample rate conversion of each track to the rate of the mix, through a
+//! windowed-sinc filter in place of the mixer's linear interpolation, which
+//! aliases.
+
+use rodio::{source::SeekError, Source};
+use std::{
+    f64::consts::PI,
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+
+/// Most phases the filter is worked out at. A ratio of rates that needs
+/// more to be exact, unlike any of the usual ones, has the phases between
+/// them interpolated.
+const MAX_PHASES: u64 = 2048;
+
+/// Input frames read past before the history is moved back to the start.
+const COMPACT_FRAMES: usize = 4096;
+
+/// How closely a track is resampled to the rate of the mix, trading the
+/// work it takes against how far aliases and images are pushed down and
+/// how much of the top octave is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ResampleQuality {
+    /// Down 60 dB, flat to about 15 kHz at 44.1 kHz.
+    Fast,
+    /// Down 90 dB, flat to about 18 kHz at 44.1 kHz.
+    Medium,
+    /// Down 120 dB, flat to about 20 kHz at 44.1 kHz.
+    #[default]
+    High,
+}
+
+impl ResampleQuality {
+    pub const ALL: [ResampleQuality; 3] = [
+        ResampleQuality::Fast,
+        ResampleQuality::Medium,
+        ResampleQuality::High,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ResampleQuality::Fast => "fast",
+            ResampleQuality::Medium => "medium",
+            ResampleQuality::High => "high",
+        }
+    }
+
+    /// Filter taps at the lower of the two rates, and how far down the
+    /// stopband is in dB.
+    fn design(self) -> (usize, f64) {
+        match self {
+            ResampleQuality::Fast => (24, 60.0),
+            ResampleQuality::Medium => (64, 90.0),
+            ResampleQuality::High => (160, 120.0),
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ResampleQuality::Fast,
+            1 => ResampleQuality::Medium,
+            _ => ResampleQuality::High,
+        }
+    }
+}
+
+impl fmt::Display for ResampleQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ResampleQuality {
+    type Err = String;
+
+    /// Looks a quality up by name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ResampleQuality::ALL
+            .into_iter()
+            .find(|quality| quality.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = ResampleQuality::ALL.map(ResampleQuality::name).join(", ");
+                format!("unknown resample quality '{s}', expected one of {names}")
+            })
+    }
+}
+
+/// The rate tracks are resampled to, and how well, shared between the
+/// player and the tracks it opens.
+pub(crate) struct ResampleControls {
+    quality: AtomicU8,
+    /// The rate of the mix.
+    sample_rate: u32,
+}
+
+impl ResampleControls {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        ResampleControls {
+            quality: AtomicU8::new(ResampleQuality::default() as u8),
+            sample_rate,
+        }
+    }
+
+    pub(crate) fn quality(&self) -> ResampleQuality {
+        ResampleQuality::from_u8(self.quality.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_quality(&self, quality: ResampleQuality) {
+        self.quality.store(quality as u8, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// A polyphase filter bank: the Kaiser-windowed sinc lowpass for one pair
+/// of rates, at each phase an output sample can fall on between two input
+/// ones.
+struct Filter {
+    /// Input frames each output one is worked out from.
+    taps: usize,
+    /// Rows of `taps` coefficients for phases 0 to `phases`, the last one
+    /// a whole input frame on, for interpolating up to it.
+    coefficients: Vec<f32>,
+    phases: u64,
+    /// The ratio of the rates in lowest terms: `up` output frames take as
+    /// long as `down` input ones.
+    up: u64,
+    down: u64,
+}
+
+impl Filter {
+    fn new(from: u32, to: u32, quality: ResampleQuality) -> Self {
+        let (from, to) = (u64::from(from.max(1)), u64::from(to.max(1)));
+        let divisor = gcd(from, to);
+        let (up, down) = (to / divisor, from / divisor);
+        let phases = up.min(MAX_PHASES);
+
+        let (taps_at_base, attenuation) = quality.design();
+        // The filter scales to the lower rate, whose Nyquist frequency it
+        // cuts at, and the stopband starts there so nothing folds back.
+        let base = from.min(to) as f64 / from as f64;
+        let transition = (attenuation - 8.0) / (2.285 * 2.0 * PI * taps_at_base as f64);
+        let cutoff = base * (0.5 - transition / 2.0);
+        let taps = ((taps_at_base as f64 / base).ceil() as usize).next_multiple_of(2);
+        let half = taps as f64 / 2.0;
+        let beta = kaiser_beta(attenuation);
+
+        let mut coefficients = Vec::with_capacity((phases as usize + 1) * taps);
+        for phase in 0..=phases {
+            let offset = phase as f64 / phases as f64;
+            let row: Vec<f64> = (0..taps)
+                .map(|tap| {
+                    // How far in input frames the tap is from the output.
+                    let distance = offset + half - 1.0 - tap as f64;
+                    2.0 * cutoff * sinc(2.0 * cutoff * distance) * kaiser(distance / half, beta)
+                })
+                .collect();
+            // Each phase passes DC at exactly unity.
+            let sum: f64 = row.iter().sum();
+            coefficients.extend(row.iter().map(|c| (c / sum) as f32));
+        }
+        Filter {
+            taps,
+            coefficients,
+            phases,
+            up,
+            down,
+        }
+    }
+
+    fn row(&self, phase: u64) -> &[f32] {
+        let start = phase as usize * self.taps;
+        &self.coefficients[start..start + self.taps]
+    }
+}
+
+/// Resamples `source`, interleaved in any number of channels, to a fixed
+/// rate through a [`Filter`], so each track arrives at the mix already at
+/// its rate. It goes after the EQ, whose bands are tuned to the source's
+/// own rate.
+///
+/// The output starts and ends in step with the input, the filter's delay
+/// taken out, so positions counted in its frames are the source's. A change
+/// of format midway starts the filter afresh.
+pub(crate) struct Resampler<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    quality: ResampleQuality,
+    sample_rate: u32,
+    /// The format the filter is for.
+    from: u32,
+    channels: u16,
+    filter: Filter,
+    /// Input frames, one list per channel, oldest first: the filter's delay
+    /// in silence, then the source.
+    history: Vec<Vec<f32>>,
+    /// Where the window for the next output frame starts in `history`.
+    start: usize,
+    /// Where the next output frame falls past the start of the window, in
+    /// `up`ths of an input frame.
+    phase: u64,
+    /// Input frames read, and output frames made, since the start or the
+    /// last seek; once the source ends, output stops in step with it.
+    read: u64,
+    written: u64,
+    ended: bool,
+    /// The output frame being handed out, and the channel next.
+    frame: Vec<f32>,
+    channel: usize,
+    /// The filter taps blended for a phase between two rows.
+    blend: Vec<f32>,
+}
+
+impl<S> Resampler<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, quality: ResampleQuality, sample_rate: u32) -> Self {
+        let from = source.sample_rate();
+        let channels = source.channels().max(1);
+        let mut resampler = Resampler {
+            filter: Filter::new(from, sample_rate, quality),
+            source,
+            quality,
+            sample_rate: sample_rate.max(1),
+            from,
+            channels,
+            history: Vec::new(),
+            start: 0,
+            phase: 0,
+            read: 0,
+            written: 0,
+            ended: false,
+            frame: Vec::new(),
+            channel: 0,
+            blend: Vec::new(),
+        };
+        resampler.reset();
+        resampler
+    }
+
+    /// Starts the filter afresh at the source's current format.
+    fn reset(&mut self) {
+        let (from, channels) = (self.source.sample_rate(), self.source.channels().max(1));
+        if (from, channels) != (self.from, self.channels) {
+            self.filter = Filter::new(from, self.sample_rate, self.quality);
+            (self.from, self.channels) = (from, channels);
+        }
+        let delay = self.filter.taps / 2 - 1;
+        self.history = vec![vec![0.0; delay]; channels as usize];
+        self.start = 0;
+        self.phase = 0;
+        self.read = 0;
+        self.written = 0;
+        self.ended = false;
+        self.frame = vec![0.0; channels as usize];
+        self.channel = 0;
+    }
+
+    /// Reads one frame into the history, or silence past the end. A change
+    /// of format starts the filter afresh instead.
+    fn read_frame(&mut self) {
+        if !self.ended
+            && (self.source.sample_rate() != self.from
+                || self.source.channels().max(1) != self.channels)
+        {
+            self.reset();
+            return;
+        }
+        let mut whole = !self.ended;
+        for history in &mut self.history {
+            // A frame cut short ends the source, filled out with silence.
+            let sample = match whole {
+                true => self.source.next(),
+                false => None,
+            };
+            whole &= sample.is_some();
+            history.push(sample.unwrap_or(0.0));
+        }
+        match whole {
+            true => self.read += 1,
+            false => self.ended = true,
+        }
+    }
+
+    /// Works out the next output frame, or returns `false` at the end.
+    fn next_frame(&mut self) -> bool {
+        loop {
+            let Filter { up, down, .. } = self.filter;
+            if self.ended && self.written * down >= self.read * up {
+                return false;
+            }
+            let taps = self.filter.taps;
+            if self.history[0].len() < self.start + taps {
+                self.read_frame();
+                continue;
+            }
+
+            // The phase as a row, and how far on to the next one.
+            let position = self.phase * self.filter.phases;
+            let (row, rest) = (position / up, position % up);
+            let kernel = if rest == 0 {
+                self.filter.row(row)
+            } else {
+                let weight = rest as f32 / up as f32;
+                let (from, to) = (self.filter.row(row), self.filter.row(row + 1));
+                self.blend.clear();
+                self.blend
+                    .extend(from.iter().zip(to).map(|(a, b)| a + (b - a) * weight));
+                &self.blend
+            };
+            for (out, history) in self.frame.iter_mut().zip(&self.history) {
+                let window = &history[self.start..self.start + taps];
+                *out = window.iter().zip(kernel).map(|(x, c)| x * c).sum();
+            }
+
+            self.written += 1;
+            self.phase += down;
+            self.start += (self.phase / up) as usize;
+            self.phase %= up;
+            if self.start >= COMPACT_FRAMES {
+                for history in &mut self.history {
+                    history.drain(..self.start);
+                }
+                self.start = 0;
+            }
+            return true;
+        }
+    }
+}
+
+impl<S> Iterator for Resampler<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 && !self.next_frame() {
+            return None;
+        }
+        let sample = self.frame[self.channel];
+        self.channel = (self.channel + 1) % self.frame.len();
+        Some(sample)
+    }
+}
+
+impl<S> Source for Resampler<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.reset();
+        Ok(())
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The Kaiser window at `x`, from -1.0 to 1.0 across it.
+fn kaiser(x: f64, beta: f64) -> f64 {
+    bessel_i0(beta * (1.0 - x * x).max(0.0).sqrt()) / bessel_i0(beta)
+}
+
+/// Kaiser's beta for a stopband `attenuation` dB down.
+fn kaiser_beta(attenuation: f64) -> f64 {
+    match attenuation {
+        a if a > 50.0 => 0.1102 * (a - 8.7),
+        a if a >= 21.0 => 0.5842 * (a - 21.0).powf(0.4) + 0.07886 * (a - 21.0),
+        _ => 0.0,
+    }
+}
+
+/// The modified Bessel function of the first kind, order zero, by its
+/// series.
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term) = (1.0, 1.0);
+    let half = x / 2.0;
+    for k in 1..64 {
+        term *= (half / k as f64).powi(2);
+        sum += term;
+        if term < sum * 1e-17 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::{GeneratorSettings, SweptSine};
+
+    /// Least the High filter must take an image down by, short of the
+    /// 120 dB it is designed for, as the sums are in `f32`.
+    const HIGH_SUPPRESSION_DB: f64 = 100.0;
+
+    /// The level in dB of `sweep`, a second long at 48 kHz in stereo, after
+    /// conversion to 44.1 kHz at `quality`, against its level going in. The
+    /// first and last tenth of a second are left out, where the sweep
+    /// starting and stopping spreads over every frequency.
+    fn level_through(start: f32, end: f32, quality: ResampleQuality) -> f64 {
+        let settings = GeneratorSettings {
+            sample_rate: 48_000,
+            channels: 2,
+            amplitude: 0.5,
+            duration: Some(Duration::from_secs(1)),
+        };
+        let sweep = || SweptSine::new(start, end, Duration::from_secs(1), settings);
+        let rms = |samples: Vec<f32>| {
+            let edge = samples.len() / 10;
+            let kept = &samples[edge..samples.len() - edge];
+            (kept.iter().map(|&s| f64::from(s).powi(2)).sum::<f64>() / kept.len() as f64).sqrt()
+        };
+        let input = rms(sweep().collect());
+        let output = rms(Resampler::new(sweep(), quality, 44_100).collect());
+        20.0 * (output / input).log10()
+    }
+
+    #[test]
+    fn a_sweep_above_the_new_nyquist_is_suppressed_at_high_quality() {
+        // Everything from here to the old Nyquist frequency would fold back
+        // to 20.1-22.05 kHz.
+        let level = level_through(22_100.0, 23_900.0, ResampleQuality::High);
+        assert!(
+            level < -HIGH_SUPPRESSION_DB,
+            "images at {level:.1} dB, expected under -{HIGH_SUPPRESSION_DB} dB"
+        );
+    }
+
+    #[test]
+    fn a_sweep_below_the_passband_edge_goes_through_whole() {
+        for quality in ResampleQuality::ALL {
+            let level = level_through(20.0, 19_000.0, quality);
+            assert!(level.abs() < 0.1, "{quality}: {level:.2} dB");
+        }
+    }
+
+    #[test]
+    fn the_high_filter_suppresses_images_further_than_fast() {
+        let fast = level_through(22_100.0, 23_900.0, ResampleQuality::Fast);
+        let high = level_through(22_100.0, 23_900.0, ResampleQuality::High);
+        assert!(fast > -70.0 && fast < -40.0, "fast at {fast:.1} dB");
+        assert!(
+            high < fast - 40.0,
+            "high at {high:.1} dB, fast at {fast:.1} dB"
+        );
+    }
+}
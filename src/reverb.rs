@@ -0,0 +1,473 @@
+use crate::{atomic::AtomicF32, gain::db_to_linear, mailbox::Mailbox};
+use rodio::{source::SeekError, Source};
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Freeverb's comb filter delays, in frames at 44.1 kHz.
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+/// Freeverb's allpass delays, in frames at 44.1 kHz.
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+const TUNING_RATE: f64 = 44_100.0;
+/// Extra delay for each channel after the first, so their tails decorrelate.
+const STEREO_SPREAD: usize = 23;
+
+const INPUT_GAIN: f32 = 0.015;
+const WET_GAIN: f32 = 3.0;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+
+/// Time constant of the glide to a new mix or damping.
+const GLIDE: Duration = Duration::from_millis(20);
+
+/// Once the source has ended, the reverb carries on until its tail is
+/// under this level.
+const TAIL_FLOOR_DB: f32 = -60.0;
+
+/// Reverb settings, each from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReverbSettings {
+    /// Larger rooms have longer delays and a longer tail.
+    pub room_size: f32,
+    /// How quickly high frequencies die away in the tail.
+    pub damping: f32,
+    /// Share of reverb in the mix; 0 is only the dry signal, 1 only reverb.
+    pub wet: f32,
+}
+
+impl Default for ReverbSettings {
+    fn default() -> Self {
+        ReverbSettings {
+            room_size: 0.5,
+            damping: 0.5,
+            wet: 0.25,
+        }
+    }
+}
+
+/// Settings shared between a [`Reverb`] and the thread adjusting it.
+///
+/// The mix and damping glide to new values. A new room size needs new delay
+/// lines, which are allocated here rather than on the audio thread, so it
+/// takes effect a moment later with an empty tail.
+pub struct ReverbControls {
+    enabled: AtomicBool,
+    room_size: AtomicF32,
+    damping: AtomicF32,
+    wet: AtomicF32,
+    /// Channels and sample rate of the stage, packed, or 0 before it is built.
+    format: AtomicU64,
+    /// Delay lines built for a new room size, on their way to the stage.
+    tanks: Mailbox<Tank>,
+    /// The ones they replaced, on their way back to be dropped here too.
+    retired: Mailbox<Tank>,
+}
+
+impl ReverbControls {
+    /// Controls for `settings`, or for a reverb that is off with `None`.
+    pub fn new(settings: Option<ReverbSettings>) -> Self {
+        let controls = ReverbControls {
+            enabled: AtomicBool::new(false),
+            room_size: AtomicF32::new(0.0),
+            damping: AtomicF32::new(0.0),
+            wet: AtomicF32::new(0.0),
+            format: AtomicU64::new(0),
+            tanks: Mailbox::new(),
+            retired: Mailbox::new(),
+        };
+        controls.store(settings.unwrap_or_default());
+        controls
+            .enabled
+            .store(settings.is_some(), Ordering::Relaxed);
+        controls
+    }
+
+    /// The current settings, or `None` while the reverb is off.
+    pub fn settings(&self) -> Option<ReverbSettings> {
+        self.enabled
+            .load(Ordering::Relaxed)
+            .then(|| ReverbSettings {
+                room_size: self.room_size.load(),
+                damping: self.damping.load(),
+                wet: self.wet.load(),
+            })
+    }
+
+    /// Changes the settings, clamped to 0 to 1, or fades the reverb out with
+    /// `None`.
+    pub fn set_settings(&self, settings: Option<ReverbSettings>) {
+        if let Some(settings) = settings {
+            let room_size = settings.room_size.clamp(0.0, 1.0);
+            if room_size != self.room_size.load() {
+                self.room_size.store(room_size);
+                let format = self.format.load(Ordering::Acquire);
+                if format != 0 {
+                    let tank = Tank::new((format >> 32) as u16, format as u32, room_size);
+                    self.tanks.post(tank);
+                }
+                self.retired.take();
+            }
+            self.store(settings);
+        }
+        self.enabled.store(settings.is_some(), Ordering::Relaxed);
+    }
+
+    fn store(&self, settings: ReverbSettings) {
+        self.room_size.store(settings.room_size.clamp(0.0, 1.0));
+        self.damping.store(settings.damping.clamp(0.0, 1.0));
+        self.wet.store(settings.wet.clamp(0.0, 1.0));
+    }
+
+    fn set_format(&self, channels: u16, sample_rate: u32) {
+        let format = (channels as u64) << 32 | sample_rate as u64;
+        self.format.store(format, Ordering::Release);
+    }
+
+    /// Swaps in delay lines built for a new room size, if there are any for
+    /// this format, handing the old ones back to be dropped.
+    fn take_tank(&self, tank: &mut Tank) {
+        let Some(mut new) = self.tanks.take_boxed() else {
+            return;
+        };
+        if new.channels == tank.channels && new.sample_rate == tank.sample_rate {
+            mem::swap(&mut *new, tank);
+        }
+        self.retired.post_boxed(new);
+    }
+}
+
+struct Comb {
+    buffer: Box<[f32]>,
+    index: usize,
+    /// State of the lowpass damping the feedback.
+    store: f32,
+}
+
+impl Comb {
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.store = output * (1.0 - damping) + self.store * damping;
+        self.buffer[self.index] = input + self.store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Box<[f32]>,
+    index: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = input + delayed * ALLPASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+/// The delay lines of every channel, sized for one format and room size.
+struct Tank {
+    channels: u16,
+    sample_rate: u32,
+    room_size: f32,
+    /// Eight combs per channel, then four allpasses per channel.
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+    /// Frames in the longest comb, which the tail is checked over.
+    longest: usize,
+}
+
+impl Tank {
+    fn new(channels: u16, sample_rate: u32, room_size: f32) -> Self {
+        let channels = channels.max(1);
+        // Delays grow with the room, so a small one takes little memory.
+        let scale = sample_rate as f64 / TUNING_RATE * (0.3 + 0.7 * room_size as f64);
+        let frames = |tuning: usize, channel: u16| {
+            (((tuning + channel as usize * STEREO_SPREAD) as f64 * scale) as usize).max(1)
+        };
+        let buffer = |frames: usize| vec![0.0; frames].into_boxed_slice();
+        let combs = (0..channels)
+            .flat_map(|channel| COMB_TUNING.map(|tuning| frames(tuning, channel)))
+            .map(|frames| Comb {
+                buffer: buffer(frames),
+                index: 0,
+                store: 0.0,
+            })
+            .collect::<Vec<_>>();
+        let allpasses = (0..channels)
+            .flat_map(|channel| ALLPASS_TUNING.map(|tuning| frames(tuning, channel)))
+            .map(|frames| Allpass {
+                buffer: buffer(frames),
+                index: 0,
+            })
+            .collect();
+        Tank {
+            channels,
+            sample_rate,
+            room_size,
+            longest: combs
+                .iter()
+                .map(|comb| comb.buffer.len())
+                .max()
+                .unwrap_or(1),
+            combs,
+            allpasses,
+        }
+    }
+
+    fn clear(&mut self) {
+        for comb in &mut self.combs {
+            comb.buffer.fill(0.0);
+            comb.store = 0.0;
+        }
+        for allpass in &mut self.allpasses {
+            allpass.buffer.fill(0.0);
+        }
+    }
+
+    /// Runs `input` through the delay lines of `channel`.
+    fn process(&mut self, channel: usize, input: f32, damping: f32) -> f32 {
+        let feedback = 0.7 + 0.28 * self.room_size;
+        let combs = &mut self.combs[channel * COMB_TUNING.len()..][..COMB_TUNING.len()];
+        let mut output = combs
+            .iter_mut()
+            .map(|comb| comb.process(input, feedback, damping * 0.4))
+            .sum::<f32>();
+        let allpasses =
+            &mut self.allpasses[channel * ALLPASS_TUNING.len()..][..ALLPASS_TUNING.len()];
+        for allpass in allpasses {
+            output = allpass.process(output);
+        }
+        output
+    }
+}
+
+/// Freeverb-style reverb: eight parallel lowpass-feedback combs into four
+/// series allpasses, for each channel. Every channel is fed the mix of all
+/// of them, through delay lines of slightly different lengths, so a stereo
+/// tail comes out wide.
+///
+/// Once the source ends the tail plays out until it has decayed under
+/// -60 dB. Turned off, the reverb fades out and then passes the source
+/// through untouched. The delay lines are allocated up front, and again
+/// only if the source changes format.
+pub struct Reverb<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<ReverbControls>,
+    tank: Tank,
+    output: Vec<f32>,
+    position: usize,
+    /// Mix and damping, gliding towards the controls' values.
+    wet: f32,
+    damping: f32,
+    glide: f32,
+    /// Whether the reverb is being applied, or was until just now and is
+    /// still fading out.
+    active: bool,
+    ended: bool,
+    /// Loudest reverb sample so far in the current check of the tail, and
+    /// frames left in it.
+    tail_peak: f32,
+    tail_left: usize,
+    /// Channel of the next sample while passing the source through.
+    channel: u16,
+}
+
+impl<S> Reverb<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, settings: ReverbSettings) -> Self {
+        Self::with_controls(source, Arc::new(ReverbControls::new(Some(settings))))
+    }
+
+    pub fn with_controls(source: S, controls: Arc<ReverbControls>) -> Self {
+        let (channels, sample_rate) = (source.channels().max(1), source.sample_rate());
+        controls.set_format(channels, sample_rate);
+        let tank = Tank::new(channels, sample_rate, controls.room_size.load());
+        let mut reverb = Reverb {
+            source,
+            tank,
+            output: Vec::with_capacity(channels as usize),
+            position: 0,
+            wet: 0.0,
+            damping: controls.damping.load(),
+            glide: 0.0,
+            active: false,
+            ended: false,
+            tail_peak: 0.0,
+            tail_left: 0,
+            channel: 0,
+            controls,
+        };
+        reverb.start();
+        reverb
+    }
+
+    pub fn controls(&self) -> Arc<ReverbControls> {
+        self.controls.clone()
+    }
+
+    /// Starts over from silence in the source's current format, which only
+    /// allocates if that differs from the delay lines'.
+    fn start(&mut self) {
+        let (channels, sample_rate) = (self.source.channels().max(1), self.source.sample_rate());
+        if (channels, sample_rate) != (self.tank.channels, self.tank.sample_rate) {
+            self.controls.set_format(channels, sample_rate);
+            self.tank = Tank::new(channels, sample_rate, self.controls.room_size.load());
+            self.output = Vec::with_capacity(channels as usize);
+        } else {
+            self.tank.clear();
+        }
+        self.glide = 1.0 - (-1.0 / (GLIDE.as_secs_f32() * sample_rate.max(1) as f32)).exp();
+        self.output.clear();
+        self.position = 0;
+        self.tail_peak = 0.0;
+        self.tail_left = self.tank.longest;
+    }
+
+    /// Reads a frame from the source and mixes in the reverb, or carries
+    /// the tail on with silence once the source has ended. Returns `false`
+    /// once there is nothing left.
+    fn process_frame(&mut self) -> bool {
+        let channels = self.tank.channels as usize;
+        self.output.clear();
+        self.position = 0;
+        if !self.ended {
+            if (self.source.channels().max(1), self.source.sample_rate())
+                != (self.tank.channels, self.tank.sample_rate)
+            {
+                self.start();
+            }
+            for _ in 0..channels {
+                match self.source.next() {
+                    Some(sample) => self.output.push(sample),
+                    None => break,
+                }
+            }
+            self.ended = self.output.is_empty();
+        }
+        self.output.resize(channels, 0.0);
+        self.controls.take_tank(&mut self.tank);
+
+        let enabled = self.controls.enabled.load(Ordering::Relaxed);
+        let target = match enabled {
+            true => self.controls.wet.load(),
+            false => 0.0,
+        };
+        self.wet += (target - self.wet) * self.glide;
+        self.damping += (self.controls.damping.load() - self.damping) * self.glide;
+        if !enabled && self.wet < 1e-4 {
+            self.wet = 0.0;
+            self.active = false;
+            self.channel = 0;
+            return !self.ended;
+        }
+
+        let input = self.output.iter().sum::<f32>() / channels as f32 * 2.0 * INPUT_GAIN;
+        for channel in 0..channels {
+            let reverb = self.tank.process(channel, input, self.damping) * WET_GAIN * self.wet;
+            self.tail_peak = self.tail_peak.max(reverb.abs());
+            let dry = &mut self.output[channel];
+            *dry = *dry * (1.0 - self.wet) + reverb;
+        }
+
+        if self.ended {
+            self.tail_left = self.tail_left.saturating_sub(1);
+            if self.tail_left == 0 {
+                if self.tail_peak < db_to_linear(TAIL_FLOOR_DB) {
+                    return false;
+                }
+                self.tail_peak = 0.0;
+                self.tail_left = self.tank.longest;
+            }
+        }
+        true
+    }
+}
+
+impl<S> Iterator for Reverb<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(&sample) = self.output.get(self.position) {
+                self.position += 1;
+                return Some(sample);
+            }
+            if self.active {
+                if !self.process_frame() {
+                    return None;
+                }
+                continue;
+            }
+            if self.channel == 0 && self.controls.enabled.load(Ordering::Relaxed) {
+                self.start();
+                self.active = true;
+                continue;
+            }
+
+            let sample = self.source.next()?;
+            self.channel = (self.channel + 1) % self.source.channels().max(1);
+            return Some(sample);
+        }
+    }
+}
+
+impl<S> Source for Reverb<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        match (self.output.len() - self.position, self.active) {
+            (0, false) => self.source.current_frame_len(),
+            // At least one more frame comes in the same format.
+            (0, true) if self.ended => Some(self.tank.channels as usize),
+            (0, true) => self.source.current_frame_len(),
+            (waiting, _) => Some(waiting),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self.active || self.position < self.output.len() {
+            true => self.tank.channels,
+            false => self.source.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self.active || self.position < self.output.len() {
+            true => self.tank.sample_rate,
+            false => self.source.sample_rate(),
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.ended = false;
+        self.channel = 0;
+        if self.active {
+            self.start();
+        } else {
+            self.output.clear();
+            self.position = 0;
+        }
+        Ok(())
+    }
+}
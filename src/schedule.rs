@@ -0,0 +1,329 @@
+use crate::{
+    clock::Clock,
+    events::{PlayerEvent, Signal},
+    output::OutputTiming,
+    secondary::SecondaryControls,
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// What `at` holds while a start is held back with no frame to go at yet.
+const HELD: u64 = u64::MAX;
+
+/// Counts the frames of an engine's mix, and works out when each is heard
+/// from what the output device reports.
+pub(crate) struct MixClock {
+    /// Samples the mix has made.
+    mixed: Arc<AtomicU64>,
+    channels: u16,
+    sample_rate: u32,
+    timing: Arc<OutputTiming>,
+    /// For the primary output's delay.
+    outputs: Arc<SecondaryControls>,
+}
+
+impl MixClock {
+    pub(crate) fn new(
+        mixed: Arc<AtomicU64>,
+        channels: u16,
+        sample_rate: u32,
+        timing: Arc<OutputTiming>,
+        outputs: Arc<SecondaryControls>,
+    ) -> Self {
+        MixClock {
+            mixed,
+            channels: channels.max(1),
+            sample_rate: sample_rate.max(1),
+            timing,
+            outputs,
+        }
+    }
+
+    /// The frame being mixed now.
+    pub(crate) fn frame(&self) -> u64 {
+        self.mixed.load(Ordering::Relaxed) / self.channels as u64
+    }
+
+    /// The frame heard at `instant`, or the one being mixed now if that is
+    /// already past. Without word from the device yet, the output latency
+    /// is taken as nothing.
+    pub(crate) fn frame_at(&self, instant: Instant) -> u64 {
+        let now = self.frame();
+        let rate = self.sample_rate as f64;
+        let Some((anchor, heard)) = self.anchor() else {
+            let ahead = instant.saturating_duration_since(Instant::now());
+            return now + (ahead.as_secs_f64() * rate).round() as u64;
+        };
+        let offset = match instant.checked_duration_since(heard) {
+            Some(after) => after.as_secs_f64(),
+            None => -heard.duration_since(instant).as_secs_f64(),
+        };
+        let frame = anchor + (offset * rate).round() as i64;
+        (frame.max(0) as u64).max(now)
+    }
+
+    /// When frame `frame` is heard, going by the device.
+    fn heard(&self, frame: u64) -> Option<Instant> {
+        let (anchor, heard) = self.anchor()?;
+        let offset = (frame as i64 - anchor) as f64 / self.sample_rate as f64;
+        Some(match offset < 0.0 {
+            true => heard - Duration::from_secs_f64(-offset),
+            false => heard + Duration::from_secs_f64(offset),
+        })
+    }
+
+    /// A frame of the mix and when it is heard: the last the device said,
+    /// less the primary output's delay.
+    fn anchor(&self) -> Option<(i64, Instant)> {
+        let (samples, heard) = self.timing.anchor()?;
+        let delay = self.outputs.primary_delay().as_secs_f64() * self.sample_rate as f64;
+        let frame = (samples / self.channels as u64) as i64 - delay.round() as i64;
+        Some((frame, heard))
+    }
+}
+
+/// Passes an engine's mix through, counting the samples for its
+/// [`MixClock`].
+pub(crate) struct MixCount<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    mixed: Arc<AtomicU64>,
+}
+
+impl<S> MixCount<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, mixed: Arc<AtomicU64>) -> Self {
+        MixCount { source, mixed }
+    }
+}
+
+impl<S> Iterator for MixCount<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // Counted after, so what is pulled for this sample sees its index.
+        let sample = self.source.next()?;
+        self.mixed.fetch_add(1, Ordering::Relaxed);
+        Some(sample)
+    }
+}
+
+impl<S> Source for MixCount<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+/// A player's start waiting to be let go at a frame of the mix, shared
+/// between the player and its [`StartGate`].
+pub(crate) struct StartControls {
+    /// One more than the frame to start at, [`HELD`] while held without
+    /// one, or 0 when nothing waits.
+    at: AtomicU64,
+    /// When the start was asked for, in nanoseconds from `epoch`.
+    requested_ns: AtomicI64,
+    epoch: Instant,
+    mix: Arc<MixClock>,
+    /// The player's, started once playback goes.
+    clock: Arc<Clock>,
+    signals: Sender<Signal>,
+}
+
+impl StartControls {
+    pub(crate) fn new(mix: Arc<MixClock>, clock: Arc<Clock>, signals: Sender<Signal>) -> Self {
+        StartControls {
+            at: AtomicU64::new(0),
+            requested_ns: AtomicI64::new(0),
+            epoch: Instant::now(),
+            mix,
+            clock,
+            signals,
+        }
+    }
+
+    pub(crate) fn mix(&self) -> &MixClock {
+        &self.mix
+    }
+
+    /// Holds playback back until [`StartControls::release`] or
+    /// [`StartControls::open`].
+    pub(crate) fn hold(&self) {
+        self.at.store(HELD, Ordering::Release);
+    }
+
+    /// Lets playback go at mix frame `frame`, reporting how near it came
+    /// to being heard at `requested`.
+    pub(crate) fn release(&self, frame: u64, requested: Instant) {
+        let requested = match requested.checked_duration_since(self.epoch) {
+            Some(after) => after.as_nanos() as i64,
+            None => -(self.epoch.duration_since(requested).as_nanos() as i64),
+        };
+        self.requested_ns.store(requested, Ordering::Relaxed);
+        self.at.store(frame.min(HELD - 2) + 1, Ordering::Release);
+    }
+
+    /// Lets playback go straight away.
+    pub(crate) fn open(&self) {
+        self.at.store(0, Ordering::Release);
+    }
+
+    /// Holds a start that still waits, to be cancelled. Returns `false` if
+    /// none does.
+    pub(crate) fn hold_waiting(&self) -> bool {
+        self.at
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |at| {
+                (at != 0).then_some(HELD)
+            })
+            .is_ok()
+    }
+
+    fn requested(&self) -> Instant {
+        let ns = self.requested_ns.load(Ordering::Relaxed);
+        match ns < 0 {
+            true => self.epoch - Duration::from_nanos(ns.unsigned_abs()),
+            false => self.epoch + Duration::from_nanos(ns as u64),
+        }
+    }
+}
+
+/// Plays silence, without pulling the player's audio, while its start
+/// waits for a frame of the mix. Once it goes, the player's clock starts
+/// and [`PlayerEvent::ScheduledStart`] is sent.
+pub(crate) struct StartGate<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<StartControls>,
+    waiting: bool,
+    channel: u16,
+    channels: u16,
+}
+
+impl<S> StartGate<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<StartControls>) -> Self {
+        StartGate {
+            channels: source.channels().max(1),
+            source,
+            controls,
+            waiting: false,
+            channel: 0,
+        }
+    }
+
+    /// Whether the frame about to start waits.
+    fn waits(&self) -> bool {
+        let at = self.controls.at.load(Ordering::Acquire);
+        if at == 0 {
+            return false;
+        }
+        let frame = self.controls.mix.frame();
+        if at == HELD || frame + 1 < at {
+            return true;
+        }
+        // Only this one let go, should a hold or another start have come.
+        if self
+            .controls
+            .at
+            .compare_exchange(at, 0, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            let controls = &self.controls;
+            if !controls.clock.set_playing(true) {
+                let _ = controls.signals.send(Signal::Event(PlayerEvent::Resumed));
+            }
+            let requested = controls.requested();
+            let heard = controls.mix.heard(frame).unwrap_or_else(Instant::now);
+            let error_secs = match heard.checked_duration_since(requested) {
+                Some(late) => late.as_secs_f64(),
+                None => -requested.duration_since(heard).as_secs_f64(),
+            };
+            let event = PlayerEvent::ScheduledStart {
+                requested,
+                heard,
+                error_secs,
+            };
+            let _ = controls.signals.send(Signal::Event(event));
+        }
+        false
+    }
+}
+
+impl<S> Iterator for StartGate<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            self.waiting = self.waits();
+            self.channels = self.source.channels().max(1);
+        }
+        let sample = match self.waiting {
+            true => 0.0,
+            false => self.source.next()?,
+        };
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample)
+    }
+}
+
+impl<S> Source for StartGate<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.channel = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{atomic::AtomicF32, clock::Clock, events::PlayerEvent, lock::Lock};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Share of a track that has to play before [`PlayerEvent::TrackPlayed`], as Last.fm counts a listen.
+pub const DEFAULT_SCROBBLE_FRACTION: f32 = 0.5;
+/// Playing this long is enough however long the track is.
+pub const DEFAULT_SCROBBLE_CAP: Duration = Duration::from_secs(4 * 60);
+
+/// When a track counts as played, shared between the player and its event
+/// thread, which sends [`PlayerEvent::TrackPlayed`].
+pub(crate) struct ScrobbleControls {
+    fraction: AtomicF32,
+    cap_ns: AtomicU64,
+    /// The playthrough last reported, so each is reported once.
+    reported: Mutex<Option<u64>>,
+}
+
+impl ScrobbleControls {
+    pub(crate) fn new() -> Self {
+        ScrobbleControls {
+            fraction: AtomicF32::new(DEFAULT_SCROBBLE_FRACTION),
+            cap_ns: AtomicU64::new(DEFAULT_SCROBBLE_CAP.as_nanos() as u64),
+            reported: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn threshold(&self) -> (f32, Duration) {
+        let cap = Duration::from_nanos(self.cap_ns.load(Ordering::Relaxed));
+        (self.fraction.load(), cap)
+    }
+
+    pub(crate) fn set_threshold(&self, fraction: f32, cap: Duration) {
+        self.fraction.store(fraction);
+        self.cap_ns.store(cap.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// How much of a track lasting `duration` has to play.
+    fn needed(&self, duration: Duration) -> Duration {
+        let (fraction, cap) = self.threshold();
+        duration.mul_f32(fraction).min(cap)
+    }
+
+    /// The event for the current track if it has just crossed the threshold.
+    /// Tracks of unknown length are never reported.
+    pub(crate) fn check(&self, clock: &Clock) -> Option<PlayerEvent> {
+        let playthrough = clock.playthroughs();
+        let mut reported = self.reported.locked();
+        if *reported == Some(playthrough) {
+            return None;
+        }
+        let track = clock.current_track()?;
+        let duration = track.duration?;
+        let played = clock.played();
+        if played < self.needed(duration) {
+            return None;
+        }
+        *reported = Some(playthrough);
+        Some(PlayerEvent::TrackPlayed {
+            path: track.path,
+            played,
+            duration,
+        })
+    }
+
+    /// How long until the current track crosses the threshold while
+    /// playing at the current rate, if it hasn't yet.
+    pub(crate) fn until_due(&self, clock: &Clock) -> Option<Duration> {
+        if !clock.is_playing() || *self.reported.locked() == Some(clock.playthroughs()) {
+            return None;
+        }
+        let needed = self.needed(clock.current_track()?.duration?);
+        Some(needed.saturating_sub(clock.played()).div_f32(clock.rate()))
+    }
+}
@@ -0,0 +1,476 @@
+//! A second output device playing a copy of the mix, each of the two at a
+//! volume and delay of its own; see
+//! [`AudioEngine::add_secondary_output`](crate::AudioEngine::add_secondary_output).
+
+use crate::mailbox::Mailbox;
+use rodio::Source;
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// The longest either output can be held back by.
+pub const MAX_OUTPUT_DELAY: Duration = Duration::from_secs(2);
+
+/// How far the second device plays behind the first, besides its delay, to
+/// ride out the two asking for samples at different times.
+const CUSHION: Duration = Duration::from_millis(100);
+
+/// How far the cushion may drift before frames are dropped or doubled to
+/// bring it back.
+const DRIFT_SLACK: Duration = Duration::from_millis(20);
+
+/// How far over the cushion the second device may fall, as after a stall,
+/// before it skips ahead.
+const JUMP: Duration = Duration::from_millis(100);
+
+/// How long a delay change fades out and back in over.
+const DELAY_FADE: Duration = Duration::from_millis(10);
+
+/// Frames between drift corrections, one frame each.
+const DRIFT_EVERY: u32 = 1024;
+
+/// How much the ring between the two devices holds.
+const RING_LENGTH: Duration = Duration::from_secs(1);
+
+fn frames(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * sample_rate as f64).round() as usize
+}
+
+/// Whole frames handed from the first device's stream to the second's,
+/// without locking either; one writes and the other reads. A frame that
+/// doesn't fit is dropped.
+pub(crate) struct Ring {
+    samples: Box<[AtomicU32]>,
+    /// Samples written and read so far, counting on past the end.
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+impl Ring {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        let len = frames(RING_LENGTH, sample_rate).max(1) * channels.max(1) as usize;
+        Ring {
+            samples: (0..len).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    /// Samples written and not read yet.
+    fn filled(&self) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        written.wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+
+    fn slot(&self, position: usize) -> &AtomicU32 {
+        &self.samples[position % self.samples.len()]
+    }
+}
+
+/// The delay of each output and where the mix goes to the second.
+pub(crate) struct SecondaryControls {
+    primary_delay_ns: AtomicU64,
+    secondary_delay_ns: AtomicU64,
+    /// The ring for the tee to fill, `None` once the second output is gone.
+    rings: Mailbox<Option<Arc<Ring>>>,
+}
+
+impl SecondaryControls {
+    pub(crate) fn new() -> Self {
+        SecondaryControls {
+            primary_delay_ns: AtomicU64::new(0),
+            secondary_delay_ns: AtomicU64::new(0),
+            rings: Mailbox::new(),
+        }
+    }
+
+    pub(crate) fn primary_delay(&self) -> Duration {
+        Duration::from_nanos(self.primary_delay_ns.load(Ordering::Relaxed))
+    }
+
+    /// Clamped to [`MAX_OUTPUT_DELAY`].
+    pub(crate) fn set_primary_delay(&self, delay: Duration) {
+        let delay = delay.min(MAX_OUTPUT_DELAY).as_nanos() as u64;
+        self.primary_delay_ns.store(delay, Ordering::Relaxed);
+    }
+
+    pub(crate) fn secondary_delay(&self) -> Duration {
+        Duration::from_nanos(self.secondary_delay_ns.load(Ordering::Relaxed))
+    }
+
+    /// Clamped to [`MAX_OUTPUT_DELAY`].
+    pub(crate) fn set_secondary_delay(&self, delay: Duration) {
+        let delay = delay.min(MAX_OUTPUT_DELAY).as_nanos() as u64;
+        self.secondary_delay_ns.store(delay, Ordering::Relaxed);
+    }
+
+    /// A new ring for the tee to fill at `channels` and `sample_rate`, and
+    /// the source reading it out on the second device.
+    pub(crate) fn connect(self: &Arc<Self>, channels: u16, sample_rate: u32) -> Delay<Secondary> {
+        let ring = Arc::new(Ring::new(channels, sample_rate));
+        self.rings.post(Some(ring.clone()));
+        let reader = Secondary::new(ring, channels, sample_rate);
+        Delay::new(reader, self.clone(), false)
+    }
+
+    /// Stops the tee filling a ring.
+    pub(crate) fn disconnect(&self) {
+        self.rings.post(None);
+    }
+}
+
+/// Passes the mix through unchanged, copying it into the ring for the
+/// second output while there is one.
+pub(crate) struct Tee<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<SecondaryControls>,
+    ring: Option<Arc<Ring>>,
+    channels: u16,
+    channel: u16,
+    /// Where the next sample goes, and whether the frame it is in fits.
+    position: usize,
+    fits: bool,
+}
+
+impl<S> Tee<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<SecondaryControls>) -> Self {
+        let channels = source.channels().max(1);
+        Tee {
+            source,
+            controls,
+            ring: None,
+            channels,
+            channel: 0,
+            position: 0,
+            fits: false,
+        }
+    }
+}
+
+impl<S> Iterator for Tee<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+        if self.channel == 0 {
+            if let Some(ring) = self.controls.rings.take() {
+                self.ring = ring;
+            }
+            self.fits = self.ring.as_ref().is_some_and(|ring| {
+                self.position = ring.written.load(Ordering::Relaxed);
+                ring.filled() + self.channels as usize <= ring.samples.len()
+            });
+        }
+        if let (Some(ring), true) = (&self.ring, self.fits) {
+            ring.slot(self.position)
+                .store(sample.to_bits(), Ordering::Relaxed);
+            self.position = self.position.wrapping_add(1);
+            if self.channel + 1 == self.channels {
+                ring.written.store(self.position, Ordering::Release);
+            }
+        }
+        self.channel = (self.channel + 1) % self.channels;
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl<S> Source for Tee<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+/// What the second device plays next.
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    /// Silence, while the ring fills up to the cushion.
+    Priming,
+    Read,
+    /// The frame before again, to let the ring fill.
+    Repeat,
+}
+
+/// Reads the ring out on the second device, a cushion behind the first and
+/// kept there as the two devices' clocks drift apart.
+pub(crate) struct Secondary {
+    ring: Arc<Ring>,
+    channels: u16,
+    sample_rate: u32,
+    channel: u16,
+    step: Step,
+    /// Where the next sample is read from.
+    position: usize,
+    last: Vec<f32>,
+    /// Frames since the last drift correction.
+    since_correction: u32,
+    /// In samples.
+    cushion: usize,
+    slack: usize,
+    jump: usize,
+}
+
+impl Secondary {
+    fn new(ring: Arc<Ring>, channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1);
+        let samples = |duration| frames(duration, sample_rate) * channels as usize;
+        Secondary {
+            ring,
+            channels,
+            sample_rate,
+            channel: 0,
+            step: Step::Priming,
+            position: 0,
+            last: vec![0.0; channels as usize],
+            since_correction: 0,
+            cushion: samples(CUSHION),
+            slack: samples(DRIFT_SLACK),
+            jump: samples(JUMP),
+        }
+    }
+
+    /// Picks what the next frame is, skipping ahead in the ring as needed.
+    fn plan(&mut self) {
+        let channels = self.channels as usize;
+        let filled = self.ring.filled();
+        self.position = self.ring.read.load(Ordering::Relaxed);
+        let skip = |secondary: &mut Self, samples: usize| {
+            secondary.position = secondary.position.wrapping_add(samples);
+            secondary
+                .ring
+                .read
+                .store(secondary.position, Ordering::Release);
+        };
+        self.step = match self.step {
+            _ if filled < channels => Step::Priming,
+            Step::Priming if filled < self.cushion => Step::Priming,
+            _ if filled > self.cushion + self.jump => {
+                let over = filled - self.cushion;
+                skip(self, over - over % channels);
+                Step::Read
+            }
+            _ => {
+                self.since_correction += 1;
+                match self.since_correction >= DRIFT_EVERY {
+                    false => Step::Read,
+                    true => {
+                        self.since_correction = 0;
+                        if filled > self.cushion + self.slack && filled >= 2 * channels {
+                            skip(self, channels);
+                            Step::Read
+                        } else if filled + self.slack < self.cushion {
+                            Step::Repeat
+                        } else {
+                            Step::Read
+                        }
+                    }
+                }
+            }
+        };
+    }
+}
+
+impl Iterator for Secondary {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            self.plan();
+        }
+        let channel = self.channel as usize;
+        let sample = match self.step {
+            Step::Priming => 0.0,
+            Step::Repeat => self.last[channel],
+            Step::Read => {
+                let sample = f32::from_bits(self.ring.slot(self.position).load(Ordering::Relaxed));
+                self.position = self.position.wrapping_add(1);
+                self.last[channel] = sample;
+                sample
+            }
+        };
+        self.channel = (self.channel + 1) % self.channels;
+        if self.channel == 0 && self.step == Step::Read {
+            self.ring.read.store(self.position, Ordering::Release);
+        }
+        Some(sample)
+    }
+}
+
+impl Source for Secondary {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Holds `source` back by one output's delay, fading out and back in when
+/// it changes.
+pub(crate) struct Delay<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<SecondaryControls>,
+    primary: bool,
+    channels: u16,
+    sample_rate: u32,
+    /// The last [`MAX_OUTPUT_DELAY`] of the source, and where the next
+    /// sample goes in it.
+    history: Vec<f32>,
+    position: usize,
+    /// The delay played at, in samples.
+    delay: usize,
+    channel: u16,
+    /// The level of the frame playing.
+    gain: f32,
+    /// Frames left of a fade, out towards `fading_to` and then back in.
+    fade: usize,
+    fade_frames: usize,
+    fading_to: Option<usize>,
+}
+
+impl<S> Delay<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Delays `source` by the primary delay in `controls`, or the secondary.
+    pub(crate) fn new(source: S, controls: Arc<SecondaryControls>, primary: bool) -> Self {
+        let channels = source.channels().max(1);
+        let sample_rate = source.sample_rate().max(1);
+        let len = (frames(MAX_OUTPUT_DELAY, sample_rate) + 1) * channels as usize;
+        let mut delay = Delay {
+            source,
+            controls,
+            primary,
+            channels,
+            sample_rate,
+            history: vec![0.0; len],
+            position: 0,
+            delay: 0,
+            channel: 0,
+            gain: 1.0,
+            fade: 0,
+            fade_frames: frames(DELAY_FADE, sample_rate).max(1),
+            fading_to: None,
+        };
+        // Nothing has played yet to fade out of.
+        delay.delay = delay.wanted();
+        delay
+    }
+
+    /// The delay asked for, in samples.
+    fn wanted(&self) -> usize {
+        let delay = match self.primary {
+            true => self.controls.primary_delay(),
+            false => self.controls.secondary_delay(),
+        };
+        frames(delay, self.sample_rate) * self.channels as usize
+    }
+
+    /// How loud the next frame plays, fading out to a new delay and back
+    /// in once it has been moved to.
+    fn level(&mut self) -> f32 {
+        if self.fade == 0 {
+            let wanted = self.wanted();
+            if wanted == self.delay {
+                return 1.0;
+            }
+            self.fading_to = Some(wanted);
+            self.fade = 2 * self.fade_frames;
+        }
+        self.fade -= 1;
+        if self.fade == self.fade_frames {
+            if let Some(delay) = self.fading_to.take() {
+                self.delay = delay;
+            }
+        }
+        self.fade.abs_diff(self.fade_frames) as f32 / self.fade_frames as f32
+    }
+}
+
+impl<S> Iterator for Delay<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+        if self.channel == 0 {
+            self.gain = self.level();
+        }
+        let len = self.history.len();
+        self.history[self.position] = sample;
+        let delayed = self.history[(self.position + len - self.delay) % len];
+        self.position = (self.position + 1) % len;
+        self.channel = (self.channel + 1) % self.channels;
+        Some(delayed * self.gain)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl<S> Source for Delay<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
@@ -0,0 +1,132 @@
+use crate::lock::Lock;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The target while there is none.
+const NO_TARGET: u64 = u64::MAX;
+
+#[derive(Default)]
+struct State {
+    /// The latest seek asked for, which replaces any that hadn't started.
+    pending: Option<Job>,
+    /// When the pending seek may start, if it waits to settle.
+    due: Option<Instant>,
+    busy: bool,
+    closed: bool,
+}
+
+/// Runs seeks on a thread of its own, one at a time, so the caller doesn't
+/// wait while decoders are opened and wound forward. A seek asked for while
+/// an earlier one is still waiting replaces it, and one that waits to
+/// settle, as while scrubbing, starts only once no other has come in that
+/// long. Dropping it drops any seek that hasn't started.
+pub(crate) struct Seeker {
+    state: Arc<(Mutex<State>, Condvar)>,
+    /// Where the latest seek is headed, in nanoseconds, until it lands.
+    /// Only changed under the lock, but read without it.
+    target: Arc<AtomicU64>,
+}
+
+impl Seeker {
+    pub(crate) fn new() -> Self {
+        let state = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        let target = Arc::new(AtomicU64::new(NO_TARGET));
+        let shared = state.clone();
+        let landed = target.clone();
+        thread::spawn(move || {
+            let (state, changed) = &*shared;
+            let mut state = state.locked();
+            loop {
+                if state.closed {
+                    return;
+                }
+                if state.pending.is_none() {
+                    state = changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+                    continue;
+                }
+                let wait = state.due.map_or(Duration::ZERO, |due| {
+                    due.saturating_duration_since(Instant::now())
+                });
+                if !wait.is_zero() {
+                    state = changed
+                        .wait_timeout(state, wait)
+                        .map_or_else(|err| err.into_inner().0, |(state, _)| state);
+                    continue;
+                }
+                let Some(job) = state.pending.take() else {
+                    continue;
+                };
+                state.busy = true;
+                drop(state);
+                job();
+                state = shared.0.locked();
+                state.busy = false;
+                if state.pending.is_none() {
+                    landed.store(NO_TARGET, Ordering::Release);
+                }
+            }
+        });
+        Seeker { state, target }
+    }
+
+    /// Runs `seek` once the one in progress, if any, is done and `settle`
+    /// has gone by without another request replacing it. `target` is
+    /// reported by [`Seeker::target`] until it has.
+    pub(crate) fn request(
+        &self,
+        settle: Duration,
+        target: Option<Duration>,
+        seek: impl FnOnce() + Send + 'static,
+    ) {
+        let mut state = self.state.0.locked();
+        state.pending = Some(Box::new(seek));
+        state.due = (!settle.is_zero()).then(|| Instant::now() + settle);
+        self.set_target(target);
+        drop(state);
+        self.state.1.notify_all();
+    }
+
+    /// Drops a seek that hasn't started, e.g. because playback moved on to
+    /// another track first.
+    pub(crate) fn cancel(&self) {
+        let mut state = self.state.0.locked();
+        state.pending = None;
+        if !state.busy {
+            self.set_target(None);
+        }
+    }
+
+    /// Where the seek asked for last is headed, while it hasn't landed yet.
+    /// Never waits.
+    pub(crate) fn target(&self) -> Option<Duration> {
+        match self.target.load(Ordering::Acquire) {
+            NO_TARGET => None,
+            target => Some(Duration::from_nanos(target)),
+        }
+    }
+
+    fn set_target(&self, target: Option<Duration>) {
+        let target = target.map_or(NO_TARGET, |target| {
+            (target.as_nanos() as u64).min(NO_TARGET - 1)
+        });
+        self.target.store(target, Ordering::Release);
+    }
+}
+
+impl Drop for Seeker {
+    fn drop(&mut self) {
+        let mut state = self.state.0.locked();
+        state.closed = true;
+        state.pending = None;
+        drop(state);
+        self.state.1.notify_all();
+    }
+}
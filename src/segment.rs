@@ -0,0 +1,236 @@
+use crate::{
+    lock::Lock,
+    mailbox::{Broadcast, Mailbox},
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// A stretch of one track to play and then end at, fading in and out; see
+/// [`AudioPlayer::play_segment`](crate::AudioPlayer::play_segment).
+pub(crate) struct Segment {
+    track: u64,
+    /// In the file, cue sheet or not.
+    start: Duration,
+    end: Duration,
+    fade_in: Duration,
+    fade_out: Duration,
+    /// Set once a pass has reached the end, so a repeat plays in full.
+    ended: AtomicBool,
+}
+
+impl Segment {
+    pub(crate) fn new(
+        track: u64,
+        start: Duration,
+        end: Duration,
+        fade_in: Duration,
+        fade_out: Duration,
+    ) -> Self {
+        Segment {
+            track,
+            start,
+            end,
+            fade_in,
+            fade_out,
+            ended: AtomicBool::new(false),
+        }
+    }
+}
+
+/// The segment playing, shared between the player and each track's
+/// [`SegmentEnd`].
+pub(crate) struct SegmentControls {
+    segment: Mutex<Option<Arc<Segment>>>,
+    updates: Broadcast<Option<Arc<Segment>>>,
+}
+
+impl SegmentControls {
+    pub(crate) fn new() -> Self {
+        SegmentControls {
+            segment: Mutex::new(None),
+            updates: Broadcast::new(),
+        }
+    }
+
+    /// Replaces the segment before, if any.
+    pub(crate) fn set(&self, segment: Option<Segment>) {
+        let mut current = self.segment.locked();
+        *current = segment.map(Arc::new);
+        self.updates.post(&current);
+    }
+}
+
+/// A segment's frames at the rate of the stage playing it.
+struct Frames {
+    segment: Arc<Segment>,
+    start: u64,
+    end: u64,
+    fade_in: u64,
+    fade_out: u64,
+}
+
+impl Frames {
+    fn new(segment: Arc<Segment>, sample_rate: u32) -> Self {
+        let frame = |time: Duration| (time.as_secs_f64() * sample_rate as f64).round() as u64;
+        Frames {
+            start: frame(segment.start),
+            end: frame(segment.end),
+            fade_in: frame(segment.fade_in),
+            fade_out: frame(segment.fade_out),
+            segment,
+        }
+    }
+
+    /// The level of frame `frame`, ramping up from the start and down to
+    /// nothing at the end.
+    fn level(&self, frame: u64) -> f32 {
+        let ramp = |frames: u64, length: u64| match length {
+            0 => 1.0,
+            _ => (frames as f32 / length as f32).min(1.0),
+        };
+        ramp(frame.saturating_sub(self.start), self.fade_in)
+            * ramp(self.end.saturating_sub(frame), self.fade_out)
+    }
+}
+
+/// Plays the decoder and ends it where a segment of its track does, its
+/// frames counted so the end lands on the sample whatever the output does.
+///
+/// A segment takes effect once playback is in it, by a seek or by playing
+/// up to its start, and is let go by a seek out of it.
+pub(crate) struct SegmentEnd<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    track: u64,
+    updates: Arc<Mailbox<Option<Arc<Segment>>>>,
+    segment: Option<Frames>,
+    /// Whether playback is in the segment.
+    armed: bool,
+    frame: u64,
+    channel: u16,
+    channels: u16,
+    level: f32,
+}
+
+impl<S> SegmentEnd<S>
+where
+    S: Source<Item = f32>,
+{
+    /// `track` is the queue id of the decoded track; segments of other
+    /// tracks are ignored.
+    pub(crate) fn new(source: S, controls: &SegmentControls, track: u64) -> Self {
+        // Subscribed first, so a segment set meanwhile is still seen.
+        let updates = controls.updates.subscribe();
+        let mut stage = SegmentEnd {
+            channels: source.channels().max(1),
+            source,
+            track,
+            updates,
+            segment: None,
+            armed: false,
+            frame: 0,
+            channel: 0,
+            level: 1.0,
+        };
+        stage.take_segment(controls.segment.locked().clone());
+        stage
+    }
+
+    fn take_segment(&mut self, segment: Option<Arc<Segment>>) {
+        self.armed = false;
+        self.segment = segment
+            .filter(|segment| segment.track == self.track && !segment.ended.load(Ordering::Relaxed))
+            .map(|segment| Frames::new(segment, self.source.sample_rate()));
+    }
+
+    /// Works out the level of the next frame. Returns `false` at the end of
+    /// the segment.
+    fn next_frame(&mut self) -> bool {
+        if let Some(segment) = self.updates.take() {
+            self.take_segment(segment);
+        }
+        self.level = 1.0;
+        let Some(segment) = &self.segment else {
+            return true;
+        };
+        if self.frame == segment.start {
+            self.armed = true;
+        }
+        if !self.armed {
+            return true;
+        }
+        if self.frame >= segment.end {
+            segment.segment.ended.store(true, Ordering::Relaxed);
+            self.segment = None;
+            return false;
+        }
+        self.level = segment.level(self.frame);
+        true
+    }
+}
+
+impl<S> Iterator for SegmentEnd<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            self.channels = self.source.channels().max(1);
+            if !self.next_frame() {
+                return None;
+            }
+        }
+        let sample = self.source.next()?;
+        self.channel += 1;
+        if self.channel >= self.channels {
+            self.channel = 0;
+            self.frame += 1;
+        }
+        Some(sample * self.level)
+    }
+}
+
+impl<S> Source for SegmentEnd<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.frame = (pos.as_secs_f64() * self.source.sample_rate() as f64).round() as u64;
+        self.channel = 0;
+        // A segment set just before the seek to its start is here by now.
+        if let Some(segment) = self.updates.take() {
+            self.take_segment(segment);
+        }
+        if let Some(segment) = &self.segment {
+            self.armed = (segment.start..segment.end).contains(&self.frame);
+        }
+        Ok(())
+    }
+}
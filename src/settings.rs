@@ -0,0 +1,555 @@
+use crate::{
+    dsp::{BiquadFilter, FilterType},
+    equalizer::{
+        BAND_COUNT, DEFAULT_GAINS, DEFAULT_Q, FREQUENCIES, SHELF_Q, THIRD_OCTAVE_BAND_COUNT,
+        THIRD_OCTAVE_FREQUENCIES, THIRD_OCTAVE_Q,
+    },
+    format::{json, toml, ParseError, Value},
+};
+use std::{error::Error, fmt, fs, io, path::Path};
+
+/// One band of the equalizer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqBand {
+    pub kind: FilterType,
+    pub frequency: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+impl EqBand {
+    /// A peaking band with the default Q.
+    pub fn new(frequency: f32, gain_db: f32) -> Self {
+        EqBand {
+            kind: FilterType::Peaking,
+            frequency,
+            gain_db,
+            q: DEFAULT_Q,
+        }
+    }
+
+    /// Checks that the band makes a stable filter at `sample_rate`.
+    pub fn validate(&self, sample_rate: u32) -> Result<(), EqError> {
+        self.validate_shape()?;
+        if self.frequency >= sample_rate as f32 / 2.0 {
+            return Err(EqError::AboveNyquist {
+                frequency: self.frequency,
+                sample_rate,
+            });
+        }
+        let filter =
+            BiquadFilter::with_type(self.kind, self.frequency, self.q, self.gain_db, sample_rate);
+        if !filter.is_stable() {
+            return Err(EqError::Unstable {
+                frequency: self.frequency,
+                q: self.q,
+                sample_rate,
+            });
+        }
+        Ok(())
+    }
+
+    /// The checks that don't depend on the sample rate.
+    pub(crate) fn validate_shape(&self) -> Result<(), EqError> {
+        if !(self.frequency > 0.0 && self.frequency.is_finite()) {
+            return Err(EqError::Invalid(format!(
+                "band frequency must be above 0 Hz, got {}",
+                self.frequency
+            )));
+        }
+        if !(self.q > 0.0 && self.q.is_finite()) {
+            return Err(EqError::Invalid(format!(
+                "band Q must be above 0, got {}",
+                self.q
+            )));
+        }
+        if !self.gain_db.is_finite() {
+            return Err(EqError::Invalid(format!(
+                "band gain must be a finite number of dB, got {}",
+                self.gain_db
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A complete equalizer setup: any number of bands plus a preamp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EqSettings {
+    pub bands: Vec<EqBand>,
+    pub preamp_db: f32,
+    /// Derive the preamp from the peak boost of the bands, ignoring `preamp_db`.
+    pub auto_headroom: bool,
+    /// Every channel takes the bands' own gains. Off, the channels with an
+    /// entry in `channel_gains` take theirs instead.
+    pub linked: bool,
+    /// Gains of each channel, in band order, from the left; a channel with
+    /// none, or bands it has no gain for, keeps the bands' own.
+    pub channel_gains: Vec<Vec<f32>>,
+}
+
+impl Default for EqSettings {
+    fn default() -> Self {
+        EqSettings::from_gains(&DEFAULT_GAINS)
+    }
+}
+
+impl EqSettings {
+    /// Maps `gains` onto the standard ten octave bands: a low shelf at 32 Hz,
+    /// peaking bands in between and a high shelf at 16 kHz.
+    pub fn from_gains(gains: &[f32; BAND_COUNT]) -> Self {
+        let last = FREQUENCIES.len() - 1;
+        EqSettings {
+            bands: FREQUENCIES
+                .iter()
+                .zip(gains)
+                .enumerate()
+                .map(|(i, (&frequency, &gain_db))| match i {
+                    0 => EqBand {
+                        kind: FilterType::LowShelf,
+                        q: SHELF_Q,
+                        ..EqBand::new(frequency, gain_db)
+                    },
+                    i if i == last => EqBand {
+                        kind: FilterType::HighShelf,
+                        q: SHELF_Q,
+                        ..EqBand::new(frequency, gain_db)
+                    },
+                    _ => EqBand::new(frequency, gain_db),
+                })
+                .collect(),
+            preamp_db: 0.0,
+            auto_headroom: false,
+            linked: true,
+            channel_gains: Vec::new(),
+        }
+    }
+
+    /// Maps `gains` onto the 31 third-octave bands, all peaking.
+    pub fn third_octave(gains: &[f32; THIRD_OCTAVE_BAND_COUNT]) -> Self {
+        EqSettings {
+            bands: THIRD_OCTAVE_FREQUENCIES
+                .iter()
+                .zip(gains)
+                .map(|(&frequency, &gain_db)| EqBand {
+                    q: THIRD_OCTAVE_Q,
+                    ..EqBand::new(frequency, gain_db)
+                })
+                .collect(),
+            preamp_db: 0.0,
+            auto_headroom: false,
+            linked: true,
+            channel_gains: Vec::new(),
+        }
+    }
+
+    /// The octave layout for ten gains, the third-octave one for 31. Any
+    /// other number of gains fits neither, and fails.
+    pub fn for_gains(gains: &[f32]) -> Result<Self, EqError> {
+        if let Ok(gains) = gains.try_into() {
+            return Ok(EqSettings::from_gains(gains));
+        }
+        if let Ok(gains) = gains.try_into() {
+            return Ok(EqSettings::third_octave(gains));
+        }
+        Err(EqError::Invalid(format!(
+            "expected {BAND_COUNT} or {THIRD_OCTAVE_BAND_COUNT} EQ gains, got {}",
+            gains.len()
+        )))
+    }
+
+    pub fn gains(&self) -> Vec<f32> {
+        self.bands.iter().map(|band| band.gain_db).collect()
+    }
+
+    /// Band `index` as channel `channel` hears it.
+    pub fn channel_band(&self, channel: usize, index: usize) -> Option<EqBand> {
+        let band = *self.bands.get(index)?;
+        let gain_db = match self.linked {
+            true => None,
+            false => self
+                .channel_gains
+                .get(channel)
+                .and_then(|gains| gains.get(index))
+                .copied(),
+        };
+        Some(EqBand {
+            gain_db: gain_db.unwrap_or(band.gain_db),
+            ..band
+        })
+    }
+
+    /// The bands as channel `channel` hears them.
+    pub fn channel_bands(&self, channel: usize) -> Vec<EqBand> {
+        (0..self.bands.len())
+            .filter_map(|index| self.channel_band(channel, index))
+            .collect()
+    }
+
+    /// The gains channel `channel` hears, one per band.
+    pub fn channel_gains(&self, channel: usize) -> Vec<f32> {
+        self.channel_bands(channel)
+            .iter()
+            .map(|band| band.gain_db)
+            .collect()
+    }
+
+    pub fn q(&self) -> Vec<f32> {
+        self.bands.iter().map(|band| band.q).collect()
+    }
+
+    /// Updates the Q of each band in order; extra values are ignored.
+    pub fn set_q(&mut self, q: &[f32]) {
+        for (band, &q) in self.bands.iter_mut().zip(q) {
+            band.q = q;
+        }
+    }
+
+    pub fn frequencies(&self) -> Vec<f32> {
+        self.bands.iter().map(|band| band.frequency).collect()
+    }
+
+    /// Checks that every band can be realised at `sample_rate`, on every
+    /// channel with gains of its own too.
+    pub fn validate(&self, sample_rate: u32) -> Result<(), EqError> {
+        (0..self.channel_gains.len().max(1))
+            .flat_map(|channel| self.channel_bands(channel))
+            .try_for_each(|band| band.validate(sample_rate))
+    }
+
+    /// The checks of [`EqSettings::validate`] that don't depend on the
+    /// sample rate, for settings played at whatever rate a file has: bands
+    /// it can't carry are left flat rather than failing.
+    pub(crate) fn validate_shape(&self) -> Result<(), EqError> {
+        (0..self.channel_gains.len().max(1))
+            .flat_map(|channel| self.channel_bands(channel))
+            .try_for_each(|band| band.validate_shape())
+    }
+
+    /// Reads settings from a `.json` file, an AutoEq profile for `.txt`
+    /// (see [`EqSettings::from_autoeq`]), or TOML for any other extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, EqError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let value = if is_json(path) {
+            json::parse(&text)?
+        } else if has_extension(path, "txt") {
+            return Self::from_autoeq(&text);
+        } else {
+            toml::parse(&text)?
+        };
+        Self::from_value(&value)
+    }
+
+    /// Reads an AutoEq `ParametricEQ.txt` file; see [`EqSettings::from_autoeq`].
+    pub fn load_autoeq(path: impl AsRef<Path>) -> Result<Self, EqError> {
+        Self::from_autoeq(&fs::read_to_string(path)?)
+    }
+
+    /// Parses an AutoEq parametric profile, the Equalizer APO format of
+    /// lines like `Filter 3: ON PK Fc 2250 Hz Gain -3.2 dB Q 1.41` under a
+    /// `Preamp: -4.1 dB` header. `PK`, `LSC` and `HSC` filters (and `LS`,
+    /// `HS`, `LP`, `HP` and `NO`) become peaking, shelving, pass and notch
+    /// bands in file order, `OFF` ones are left out, and the preamp becomes
+    /// `preamp_db`. Anything else is an [`EqError::Parse`] naming its line.
+    pub fn from_autoeq(text: &str) -> Result<Self, EqError> {
+        let mut settings = EqSettings {
+            bands: Vec::new(),
+            preamp_db: 0.0,
+            auto_headroom: false,
+            linked: true,
+            channel_gains: Vec::new(),
+        };
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| EqError::Parse(ParseError::new(index + 1, message));
+            let (directive, rest) = line
+                .split_once(':')
+                .ok_or_else(|| error(format!("expected 'Preamp:' or 'Filter:', got '{line}'")))?;
+            let mut words = rest.split_whitespace();
+            match directive.split_whitespace().next() {
+                Some("Preamp") => {
+                    let gain = words.next().and_then(|gain| gain.parse::<f32>().ok());
+                    match (gain, words.next(), words.next()) {
+                        (Some(gain), Some("dB") | None, None) if gain.is_finite() => {
+                            settings.preamp_db += gain;
+                        }
+                        _ => return Err(error(format!("invalid preamp '{}'", rest.trim()))),
+                    }
+                }
+                Some("Filter") => {
+                    if let Some(band) = autoeq_filter(&mut words).map_err(error)? {
+                        settings.bands.push(band);
+                    }
+                }
+                _ => return Err(error(format!("unsupported line '{directive}:'"))),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Writes settings as JSON or TOML, picked by extension like [`EqSettings::load`].
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), EqError> {
+        let path = path.as_ref();
+        let value = self.to_value();
+        let text = if is_json(path) {
+            json::to_string(&value)
+        } else {
+            toml::to_string(&value)
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub(crate) fn to_value(&self) -> Value {
+        let bands = self
+            .bands
+            .iter()
+            .map(|band| {
+                Value::table()
+                    .with("type", band.kind.name())
+                    .with("frequency", band.frequency)
+                    .with("gain_db", band.gain_db)
+                    .with("q", band.q)
+            })
+            .collect::<Vec<_>>();
+        let channels = self
+            .channel_gains
+            .iter()
+            .map(|gains| {
+                let gains = gains
+                    .iter()
+                    .map(|&gain| Value::from(gain))
+                    .collect::<Vec<_>>();
+                Value::table().with("gains", gains)
+            })
+            .collect::<Vec<_>>();
+        Value::table()
+            .with("preamp_db", self.preamp_db)
+            .with("auto_headroom", self.auto_headroom)
+            .with("linked", self.linked)
+            .with("bands", bands)
+            // Left out while there is nothing in it, as in files from before.
+            .with("channels", (!channels.is_empty()).then_some(channels))
+    }
+
+    pub(crate) fn from_value(value: &Value) -> Result<Self, EqError> {
+        let field = |value: &Value, key: &str| -> Result<Option<f32>, EqError> {
+            match value.get(key) {
+                None => Ok(None),
+                Some(v) => v
+                    .as_f32()
+                    .map(Some)
+                    .ok_or_else(|| EqError::Invalid(format!("'{key}' must be a number"))),
+            }
+        };
+
+        let preamp_db = field(value, "preamp_db")?.unwrap_or(0.0);
+        let flag = |key: &str, default: bool| match value.get(key) {
+            None => Ok(default),
+            Some(v) => v
+                .as_bool()
+                .ok_or_else(|| EqError::Invalid(format!("'{key}' must be a boolean"))),
+        };
+        let auto_headroom = flag("auto_headroom", false)?;
+        let linked = flag("linked", true)?;
+        let channel_gains = match value.get("channels") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(channels) => channels
+                .as_array()
+                .ok_or_else(|| EqError::Invalid("'channels' must be a list".to_string()))?
+                .iter()
+                .enumerate()
+                .map(|(i, channel)| {
+                    channel
+                        .get("gains")
+                        .and_then(Value::as_array)
+                        .and_then(|gains| gains.iter().map(Value::as_f32).collect())
+                        .ok_or_else(|| {
+                            EqError::Invalid(format!(
+                                "channel {} needs a list of numbers 'gains'",
+                                i + 1
+                            ))
+                        })
+                })
+                .collect::<Result<_, EqError>>()?,
+        };
+        let bands = match value.get("bands") {
+            None => Vec::new(),
+            Some(bands) => bands
+                .as_array()
+                .ok_or_else(|| EqError::Invalid("'bands' must be a list".to_string()))?
+                .iter()
+                .enumerate()
+                .map(|(i, band)| {
+                    let required = |key: &str| {
+                        field(band, key)?.ok_or_else(|| {
+                            EqError::Invalid(format!("band {} is missing '{key}'", i + 1))
+                        })
+                    };
+                    let kind = match band.get("type") {
+                        None => FilterType::Peaking,
+                        Some(kind) => kind
+                            .as_str()
+                            .ok_or_else(|| {
+                                EqError::Invalid(format!("band {}: 'type' must be a string", i + 1))
+                            })?
+                            .parse()
+                            .map_err(|err| EqError::Invalid(format!("band {}: {err}", i + 1)))?,
+                    };
+                    Ok(EqBand {
+                        kind,
+                        frequency: required("frequency")?,
+                        gain_db: required("gain_db")?,
+                        q: field(band, "q")?.unwrap_or(DEFAULT_Q),
+                    })
+                })
+                .collect::<Result<_, EqError>>()?,
+        };
+
+        Ok(EqSettings {
+            bands,
+            preamp_db,
+            auto_headroom,
+            linked,
+            channel_gains,
+        })
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    has_extension(path, "json")
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// The band of the words after `Filter N:`, or `None` for one that is off.
+fn autoeq_filter<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<Option<EqBand>, String> {
+    match words.next() {
+        Some("ON") => {}
+        Some("OFF") => return Ok(None),
+        other => {
+            return Err(format!(
+                "expected ON or OFF, got '{}'",
+                other.unwrap_or_default()
+            ))
+        }
+    }
+    let name = words.next().ok_or("the filter has no type")?;
+    let (kind, gain_needed) = match name {
+        "PK" | "PEQ" => (FilterType::Peaking, true),
+        "LSC" | "LS" => (FilterType::LowShelf, true),
+        "HSC" | "HS" => (FilterType::HighShelf, true),
+        "LP" | "LPQ" => (FilterType::LowPass, false),
+        "HP" | "HPQ" => (FilterType::HighPass, false),
+        "NO" => (FilterType::Notch, false),
+        _ => return Err(format!("unsupported filter type '{name}'")),
+    };
+
+    let (mut frequency, mut gain_db, mut q) = (None, None, None);
+    while let Some(key) = words.next() {
+        let (slot, unit) = match key {
+            "Fc" => (&mut frequency, Some("Hz")),
+            "Gain" => (&mut gain_db, Some("dB")),
+            "Q" => (&mut q, None),
+            _ => return Err(format!("unexpected '{key}' in the filter")),
+        };
+        let value = words
+            .next()
+            .and_then(|value| value.parse::<f32>().ok())
+            .ok_or_else(|| format!("'{key}' needs a number"))?;
+        *slot = Some(value);
+        if let Some(unit) = unit {
+            if words.next() != Some(unit) {
+                return Err(format!("'{key}' must be followed by its unit, {unit}"));
+            }
+        }
+    }
+
+    let frequency = frequency.ok_or("the filter has no 'Fc'")?;
+    let gain_db = match gain_db {
+        Some(gain_db) => gain_db,
+        None if gain_needed => return Err(format!("the {name} filter has no 'Gain'")),
+        None => 0.0,
+    };
+    let shelf = matches!(kind, FilterType::LowShelf | FilterType::HighShelf);
+    let band = EqBand {
+        kind,
+        frequency,
+        gain_db,
+        q: q.unwrap_or(if shelf { SHELF_Q } else { DEFAULT_Q }),
+    };
+    band.validate_shape().map_err(|err| err.to_string())?;
+    Ok(Some(band))
+}
+
+#[derive(Debug)]
+pub enum EqError {
+    Io(io::Error),
+    Parse(ParseError),
+    Invalid(String),
+    AboveNyquist {
+        frequency: f32,
+        sample_rate: u32,
+    },
+    /// The band's filter would be numerically unstable at the sample rate.
+    Unstable {
+        frequency: f32,
+        q: f32,
+        sample_rate: u32,
+    },
+}
+
+impl fmt::Display for EqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EqError::Io(err) => write!(f, "{err}"),
+            EqError::Parse(err) => write!(f, "{err}"),
+            EqError::Invalid(message) => f.write_str(message),
+            EqError::AboveNyquist {
+                frequency,
+                sample_rate,
+            } => write!(
+                f,
+                "band at {frequency} Hz must be between 0 Hz and the Nyquist frequency ({} Hz) of a {sample_rate} Hz source",
+                sample_rate / 2
+            ),
+            EqError::Unstable {
+                frequency,
+                q,
+                sample_rate,
+            } => write!(
+                f,
+                "band at {frequency} Hz with Q {q} can't be filtered stably at {sample_rate} Hz"
+            ),
+        }
+    }
+}
+
+impl Error for EqError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EqError::Io(err) => Some(err),
+            EqError::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EqError {
+    fn from(err: io::Error) -> Self {
+        EqError::Io(err)
+    }
+}
+
+impl From<ParseError> for EqError {
+    fn from(err: ParseError) -> Self {
+        EqError::Parse(err)
+    }
+}
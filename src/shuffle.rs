@@ -0,0 +1,154 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many played tracks [`Shuffle::history`] remembers for `previous`.
+const MAX_HISTORY: usize = 1000;
+
+/// Shuffled play order and the history `previous` walks back through.
+///
+/// Tracks are drawn from a permutation of the queue rather than picked at
+/// random each time, so none repeats before every other one has played.
+pub(crate) struct Shuffle {
+    enabled: bool,
+    /// Ids still to play in this permutation, next first.
+    upcoming: Vec<u64>,
+    /// Ids in the order they started, the playing one last.
+    history: Vec<u64>,
+    rng: Rng,
+}
+
+impl Shuffle {
+    pub(crate) fn new() -> Self {
+        Shuffle {
+            enabled: false,
+            upcoming: Vec::new(),
+            history: Vec::new(),
+            rng: Rng::seeded(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turning shuffle on draws a fresh permutation of `ids` without
+    /// `current`, which is playing already.
+    pub(crate) fn set_enabled(&mut self, enabled: bool, ids: &[u64], current: Option<u64>) {
+        self.enabled = enabled;
+        self.upcoming.clear();
+        if enabled {
+            self.upcoming = ids
+                .iter()
+                .copied()
+                .filter(|&id| Some(id) != current)
+                .collect();
+            self.rng.shuffle(&mut self.upcoming);
+        }
+    }
+
+    /// Ids to play after the current track, next first.
+    pub(crate) fn upcoming(&self) -> &[u64] {
+        &self.upcoming
+    }
+
+    /// Records that track `id` started playing.
+    pub(crate) fn started(&mut self, id: u64) {
+        self.upcoming.retain(|&upcoming| upcoming != id);
+        // Seeks restart the same track; that isn't a new entry.
+        if self.history.last() != Some(&id) {
+            self.history.push(id);
+            if self.history.len() > MAX_HISTORY {
+                self.history.remove(0);
+            }
+        }
+    }
+
+    /// Starts another cycle over `ids` once the permutation has run out,
+    /// never opening with `last`, which just played.
+    pub(crate) fn next_cycle(&mut self, ids: &[u64], last: u64) -> &[u64] {
+        self.upcoming = ids.to_vec();
+        self.rng.shuffle(&mut self.upcoming);
+        if self.upcoming.len() > 1 && self.upcoming[0] == last {
+            let swap = 1 + self.rng.below(self.upcoming.len() - 1);
+            self.upcoming.swap(0, swap);
+        }
+        &self.upcoming
+    }
+
+    /// Steps back from the playing track, which goes back to the front of
+    /// the permutation, and returns the one that played before it.
+    pub(crate) fn back(&mut self) -> Option<u64> {
+        if self.history.len() < 2 {
+            return None;
+        }
+        let current = self.history.pop().unwrap();
+        self.upcoming.insert(0, current);
+        // It is recorded again once it starts.
+        self.history.pop()
+    }
+
+    /// Whether [`Shuffle::back`] has a track to go back to.
+    pub(crate) fn has_previous(&self) -> bool {
+        self.history.len() >= 2
+    }
+
+    /// Forgets track `id`, which has left the queue.
+    pub(crate) fn remove(&mut self, id: u64) {
+        self.upcoming.retain(|&upcoming| upcoming != id);
+        self.history.retain(|&played| played != id);
+    }
+
+    /// Adds a newly queued track at a random place among those still to
+    /// play, returning where. Does nothing unless shuffle is on.
+    pub(crate) fn insert(&mut self, id: u64) -> Option<usize> {
+        if !self.enabled {
+            return None;
+        }
+        let at = self.rng.below(self.upcoming.len() + 1);
+        self.upcoming.insert(at, id);
+        Some(at)
+    }
+}
+
+/// SplitMix64; plenty for shuffling a queue, or for noise.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    /// The same sequence for the same `seed`.
+    pub(crate) fn with_seed(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub(crate) fn seeded() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos() as u64);
+        hasher.write_u64(now);
+        Rng(hasher.finish())
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..bound`; `bound` must not be zero.
+    fn below(&mut self, bound: usize) -> usize {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as usize
+    }
+
+    /// Fisher–Yates.
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use crate::{
+    atomic::AtomicF32,
+    events::{PlayerEvent, Signal},
+    gain::db_to_linear,
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    time::Duration,
+};
+
+pub const DEFAULT_SILENCE_THRESHOLD_DB: f32 = -60.0;
+/// How long the level has to stay under the threshold before the rest of
+/// the silence is skipped.
+pub const DEFAULT_SILENCE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Level under which the start and end of a track count as silence when
+/// trimming them.
+pub const DEFAULT_TRIM_THRESHOLD_DB: f32 = -90.0;
+
+/// How far into a track to look for the first audible sample.
+const HEAD_SCAN: Duration = Duration::from_secs(10);
+
+/// Most silence held back while waiting to see whether the track ends;
+/// anything before that plays.
+const TRAIL_LOOKAHEAD: Duration = Duration::from_secs(10);
+
+/// Once skipping, anything this far under the threshold already counts as
+/// sound again, so passages hovering around the threshold aren't chopped.
+const HYSTERESIS_DB: f32 = 6.0;
+
+/// Length of the blocks whose peak level is measured.
+const BLOCK: Duration = Duration::from_millis(50);
+
+/// Silence skipping and trimming settings, shared between the player, its
+/// [`SilenceSkip`] stages and the clock, which adds skipped time back onto
+/// the position.
+pub(crate) struct SilenceControls {
+    enabled: AtomicBool,
+    threshold_db: AtomicF32,
+    window_ns: AtomicU64,
+    skipped_ns: AtomicU64,
+    trim: AtomicBool,
+    trim_threshold_db: AtomicF32,
+}
+
+impl SilenceControls {
+    pub(crate) fn new() -> Self {
+        SilenceControls {
+            enabled: AtomicBool::new(false),
+            threshold_db: AtomicF32::new(DEFAULT_SILENCE_THRESHOLD_DB),
+            window_ns: AtomicU64::new(DEFAULT_SILENCE_WINDOW.as_nanos() as u64),
+            skipped_ns: AtomicU64::new(0),
+            trim: AtomicBool::new(false),
+            trim_threshold_db: AtomicF32::new(DEFAULT_TRIM_THRESHOLD_DB),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) fn threshold_db(&self) -> f32 {
+        self.threshold_db.load()
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        Duration::from_nanos(self.window_ns.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_threshold(&self, threshold_db: f32, window: Duration) {
+        self.threshold_db.store(threshold_db);
+        self.window_ns
+            .store(window.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Total track time skipped as silence so far.
+    pub(crate) fn skipped(&self) -> Duration {
+        Duration::from_nanos(self.skipped_ns.load(Ordering::Acquire))
+    }
+
+    fn skip(&self, length: Duration) {
+        self.skipped_ns
+            .fetch_add(length.as_nanos() as u64, Ordering::Release);
+    }
+
+    pub(crate) fn is_trimming(&self) -> bool {
+        self.trim.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_trimming(&self, trim: bool) {
+        self.trim.store(trim, Ordering::Relaxed);
+    }
+
+    pub(crate) fn trim_threshold_db(&self) -> f32 {
+        self.trim_threshold_db.load()
+    }
+
+    pub(crate) fn set_trim_threshold_db(&self, threshold_db: f32) {
+        self.trim_threshold_db.store(threshold_db);
+    }
+}
+
+/// How much silence `source` starts with, looking at most [`HEAD_SCAN`]
+/// in. A source that ends before anything audible isn't trimmed at all.
+pub(crate) fn leading_silence(source: impl Source<Item = f32>, threshold_db: f32) -> Duration {
+    let threshold = db_to_linear(threshold_db);
+    let channels = source.channels().max(1) as u64;
+    let sample_rate = source.sample_rate().max(1) as u64;
+    let limit = HEAD_SCAN.as_secs() * sample_rate * channels;
+    let mut samples = 0;
+    for sample in source.take(limit as usize) {
+        if sample.abs() >= threshold {
+            let frames = samples / channels;
+            return Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+        }
+        samples += 1;
+    }
+    match samples == limit {
+        true => HEAD_SCAN,
+        false => Duration::ZERO,
+    }
+}
+
+/// Skips long stretches of silence in a track, such as the gap before a
+/// hidden track, and trims it off either end.
+///
+/// The peak level is measured over short blocks. Once it has stayed under
+/// the threshold for the window, which plays as usual, blocks are read and
+/// dropped until one comes in over the threshold less the hysteresis, or
+/// the track ends. That is all decoding the track would do anyway, only
+/// faster than real time.
+///
+/// Leading silence, measured when the track was queued, is read past before
+/// the first sample, and positions below this stage leave it out. Trailing
+/// silence under the trim threshold is held back, up to [`TRAIL_LOOKAHEAD`],
+/// and dropped if the track ends before anything louder, so the next one
+/// follows on from the last audible sample.
+pub(crate) struct SilenceSkip<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<SilenceControls>,
+    signals: Sender<Signal>,
+    block: Vec<f32>,
+    index: usize,
+    channels: u16,
+    sample_rate: u32,
+    /// Track frames read so far, counting from the start of the track.
+    frame: u64,
+    /// How many frames in a row have been under the threshold.
+    silent: u64,
+    /// Channel of the next sample while passing the source through.
+    channel: u16,
+    /// Leading silence skipped at the start; seek targets are shifted by it.
+    lead: Duration,
+    /// Whether trailing silence is trimmed.
+    trim: bool,
+    /// Silent frames held back in case the track ends with them.
+    pending: VecDeque<f32>,
+}
+
+impl<S> SilenceSkip<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Skips `lead` of the source straight away, and trims trailing silence
+    /// if the controls say so when the stage is built.
+    pub(crate) fn new(
+        source: S,
+        controls: Arc<SilenceControls>,
+        signals: Sender<Signal>,
+        lead: Duration,
+    ) -> Self {
+        let mut stage = SilenceSkip {
+            channels: source.channels().max(1),
+            sample_rate: source.sample_rate(),
+            source,
+            trim: controls.is_trimming(),
+            controls,
+            signals,
+            block: Vec::new(),
+            index: 0,
+            frame: 0,
+            silent: 0,
+            channel: 0,
+            lead,
+            pending: VecDeque::new(),
+        };
+        stage.skip_lead();
+        stage
+    }
+
+    fn skip_lead(&mut self) {
+        let frames = (self.lead.as_secs_f64() * self.sample_rate as f64).round() as u64;
+        for _ in 0..frames * self.channels as u64 {
+            if self.source.next().is_none() {
+                break;
+            }
+        }
+        self.frame = frames;
+    }
+
+    fn time(&self, frame: u64) -> Duration {
+        Duration::from_secs_f64(frame as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// Reads the next block of whole frames in the source's current format,
+    /// returning its peak. The block is empty once the source has ended.
+    fn read_block(&mut self) -> f32 {
+        self.channels = self.source.channels().max(1);
+        self.sample_rate = self.source.sample_rate();
+        let channels = self.channels as usize;
+        let frames = ((BLOCK.as_secs_f64() * self.sample_rate as f64) as usize).max(1);
+        self.block.clear();
+        self.index = 0;
+        let mut peak = 0.0f32;
+        'frames: for _ in 0..frames {
+            if self.source.channels().max(1) != self.channels
+                || self.source.sample_rate() != self.sample_rate
+            {
+                break;
+            }
+            for channel in 0..channels {
+                match self.source.next() {
+                    Some(sample) => {
+                        peak = peak.max(sample.abs());
+                        self.block.push(sample);
+                    }
+                    None => {
+                        self.block.truncate(self.block.len() - channel);
+                        break 'frames;
+                    }
+                }
+            }
+        }
+        self.frame += (self.block.len() / channels) as u64;
+        peak
+    }
+
+    /// Fills the next block, holding back silence that might turn out to
+    /// end the track.
+    fn refill(&mut self) {
+        if !self.trim {
+            self.fill();
+            return;
+        }
+        let threshold = db_to_linear(self.controls.trim_threshold_db());
+        let format = (self.channels, self.sample_rate);
+        loop {
+            let peak = self.fill();
+            if self.block.is_empty() {
+                // The track ended on silence.
+                self.pending.clear();
+                return;
+            }
+            if (self.channels, self.sample_rate) != format {
+                // So did the part of the stream in the old format.
+                self.pending.clear();
+            }
+            if peak >= threshold {
+                if !self.pending.is_empty() {
+                    self.pending.extend(self.block.drain(..));
+                    self.block.extend(self.pending.drain(..));
+                }
+                return;
+            }
+            self.pending.extend(self.block.drain(..));
+            let channels = self.channels as usize;
+            let most =
+                (TRAIL_LOOKAHEAD.as_secs_f64() * self.sample_rate as f64) as usize * channels;
+            if self.pending.len() > most {
+                let excess = self.pending.len() - most;
+                self.block.extend(self.pending.drain(..excess));
+                return;
+            }
+        }
+    }
+
+    /// Fills the next block, skipping what follows it if the silence has
+    /// now lasted the window, and returns the block's peak.
+    fn fill(&mut self) -> f32 {
+        if !self.controls.is_enabled() {
+            return self.read_block();
+        }
+        let threshold = db_to_linear(self.controls.threshold_db());
+        let peak = self.read_block();
+        let frames = (self.block.len() / self.channels as usize) as u64;
+        if peak >= threshold || frames == 0 {
+            self.silent = 0;
+            return peak;
+        }
+        self.silent += frames;
+        if self.time(self.silent) < self.controls.window() {
+            return peak;
+        }
+
+        self.silent = 0;
+        // This block is the first to go.
+        let from = self.frame - frames;
+        // Skip mode: drop blocks until something louder comes along.
+        let resume = threshold * db_to_linear(-HYSTERESIS_DB);
+        let (channels, sample_rate) = (self.channels, self.sample_rate);
+        let peak = loop {
+            let peak = self.read_block();
+            if self.block.is_empty()
+                || peak >= resume
+                || self.channels != channels
+                || self.sample_rate != sample_rate
+            {
+                break peak;
+            }
+        };
+        let to = self.frame - (self.block.len() / self.channels as usize) as u64;
+        if to > from {
+            let (from, to) = (self.time(from), self.time(to));
+            self.controls.skip(to - from);
+            let event = PlayerEvent::SilenceSkipped { from, to };
+            let _ = self.signals.send(Signal::Event(event));
+        }
+        peak
+    }
+}
+
+impl<S> Iterator for SilenceSkip<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(&sample) = self.block.get(self.index) {
+            self.index += 1;
+            return Some(sample);
+        }
+        if self.channel == 0 && (self.trim || self.controls.is_enabled()) {
+            self.refill();
+            let sample = *self.block.first()?;
+            self.index = 1;
+            return Some(sample);
+        }
+
+        if self.channel == 0 {
+            self.channels = self.source.channels().max(1);
+            self.sample_rate = self.source.sample_rate();
+            self.silent = 0;
+        }
+        let sample = self.source.next()?;
+        self.channel = (self.channel + 1) % self.channels;
+        if self.channel == 0 {
+            self.frame += 1;
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for SilenceSkip<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.block.len() - self.index {
+            0 => self.source.current_frame_len(),
+            waiting => Some(waiting),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self.index < self.block.len() {
+            true => self.channels,
+            false => self.source.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self.index < self.block.len() {
+            true => self.sample_rate,
+            false => self.source.sample_rate(),
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let pos = pos + self.lead;
+        self.source.try_seek(pos)?;
+        self.block.clear();
+        self.pending.clear();
+        self.index = 0;
+        self.channel = 0;
+        self.silent = 0;
+        self.frame = (pos.as_secs_f64() * self.source.sample_rate() as f64) as u64;
+        Ok(())
+    }
+}
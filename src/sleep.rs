@@ -0,0 +1,135 @@
+use crate::{clock::Clock, lock::Lock, suspend::SUSPEND_GAP};
+use std::{
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often a running sleep timer checks whether playback is still going.
+const TICK: Duration = Duration::from_millis(100);
+
+/// What [`AudioPlayer::set_sleep_timer`] does once its time is up.
+///
+/// [`AudioPlayer::set_sleep_timer`]: crate::AudioPlayer::set_sleep_timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepAction {
+    /// Pauses, fading out as [`AudioPlayer::pause`] does.
+    ///
+    /// [`AudioPlayer::pause`]: crate::AudioPlayer::pause
+    Pause,
+    Stop,
+    /// Fades out over the given time, then stops.
+    FadeOutThenStop(Duration),
+}
+
+struct Timer {
+    action: SleepAction,
+    /// Playing time left as of `counted_at`.
+    remaining: Duration,
+    /// When `remaining` was last brought up to date, if it has been counting
+    /// down since.
+    counted_at: Option<Instant>,
+}
+
+impl Timer {
+    fn remaining(&self) -> Duration {
+        let counted = self.counted_at.map_or(Duration::ZERO, |at| at.elapsed());
+        // It's brought up to date every tick, so far longer since is the
+        // system having been suspended, which plays nothing.
+        let counted = match counted < TICK + SUSPEND_GAP {
+            true => counted,
+            false => TICK,
+        };
+        self.remaining.saturating_sub(counted)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    timer: Option<Timer>,
+    /// Bumped by every set or clear, telling an older timer thread to exit.
+    generation: u64,
+}
+
+/// Counts down playing time on a thread of its own. Only time spent playing
+/// counts, so pausing holds the timer, and seeking doesn't affect it.
+/// Dropping it cancels the timer.
+#[derive(Default)]
+pub(crate) struct SleepTimer {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl SleepTimer {
+    /// Replaces any running timer. `fire` runs on the timer's thread once
+    /// `clock` has been playing for `after`.
+    pub(crate) fn set(
+        &self,
+        after: Duration,
+        action: SleepAction,
+        clock: Arc<Clock>,
+        fire: impl FnOnce(SleepAction) + Send + 'static,
+    ) {
+        let generation = {
+            let mut state = self.state.0.locked();
+            state.generation += 1;
+            state.timer = Some(Timer {
+                action,
+                remaining: after,
+                counted_at: None,
+            });
+            state.generation
+        };
+        self.state.1.notify_all();
+
+        let shared = self.state.clone();
+        thread::spawn(move || {
+            let (state, changed) = &*shared;
+            let mut state = state.locked();
+            loop {
+                if state.generation != generation {
+                    return;
+                }
+                let playing = clock.is_playing();
+                let Some(timer) = state.timer.as_mut() else {
+                    return;
+                };
+                timer.remaining = timer.remaining();
+                timer.counted_at = playing.then(Instant::now);
+                if timer.remaining.is_zero() {
+                    let action = timer.action;
+                    state.timer = None;
+                    drop(state);
+                    fire(action);
+                    return;
+                }
+                let wait = match playing {
+                    true => timer.remaining.min(TICK),
+                    false => TICK,
+                };
+                state = changed
+                    .wait_timeout(state, wait)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+        });
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.0.locked();
+        state.generation += 1;
+        state.timer = None;
+        drop(state);
+        self.state.1.notify_all();
+    }
+
+    /// Playing time left before the timer fires, or `None` if none is set.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.state.0.locked().timer.as_ref().map(Timer::remaining)
+    }
+}
+
+impl Drop for SleepTimer {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,534 @@
+//! Spectrogram images of whole files, e.g. for telling a lossy transcode
+//! passed off as FLAC by the shelf its encoder cut the highs off at.
+//!
+//! Files are decoded on their own and analysed as they decode, so memory
+//! stays the size of the image however long the file is.
+
+use crate::{
+    decode::{self, DecoderBackend},
+    png,
+    probe::file_duration,
+    spectrum::Fft,
+};
+use std::{
+    error::Error,
+    f32::consts::PI,
+    fmt,
+    fs::{self, File},
+    io,
+    path::Path,
+    str::FromStr,
+};
+
+pub type SpectrogramError = Box<dyn Error + Send + Sync>;
+
+/// The lowest frequency drawn; the top of the image is Nyquist.
+const LOWEST_HZ: f32 = 20.0;
+
+/// Room left of the plot for the frequency labels, and under it for the
+/// times, in pixels.
+const LABEL_WIDTH: u32 = 32;
+const LABEL_HEIGHT: u32 = 18;
+
+/// How big each pixel of the label font is drawn.
+const FONT_SCALE: u32 = 2;
+
+/// Least room between time labels, in pixels.
+const TIME_LABEL_SPACING: u32 = 80;
+
+/// Steps between time labels, in seconds, the smallest that fits taken.
+const TIME_STEPS: [u32; 14] = [
+    1, 2, 5, 10, 15, 30, 60, 120, 300, 600, 900, 1800, 3600, 7200,
+];
+
+/// Frequencies labelled, where they fall in the plot.
+const FREQUENCY_LABELS: [u32; 10] = [50, 100, 200, 500, 1000, 2000, 5000, 10_000, 20_000, 50_000];
+
+/// The ramp magnitudes are colored along, from the floor to 0 dB.
+const RAMP: [[u8; 3]; 8] = [
+    [0, 0, 4],
+    [40, 11, 84],
+    [101, 21, 110],
+    [159, 42, 99],
+    [212, 72, 66],
+    [245, 125, 21],
+    [250, 193, 39],
+    [252, 255, 164],
+];
+
+const BACKGROUND: [u8; 3] = [0, 0, 0];
+const TEXT: [u8; 3] = [200, 200, 200];
+
+/// The window each block of samples is shaped with before its transform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Window {
+    #[default]
+    Hann,
+    Hamming,
+    /// Lower side lobes than Hann, for a darker floor around loud tones,
+    /// at the cost of wider peaks.
+    Blackman,
+    /// No shaping: the sharpest peaks, and the most leakage.
+    Rectangular,
+}
+
+impl Window {
+    pub const ALL: [Window; 4] = [
+        Window::Hann,
+        Window::Hamming,
+        Window::Blackman,
+        Window::Rectangular,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Window::Hann => "hann",
+            Window::Hamming => "hamming",
+            Window::Blackman => "blackman",
+            Window::Rectangular => "rectangular",
+        }
+    }
+
+    /// The window over `n` samples.
+    fn coefficients(self, n: usize) -> Vec<f32> {
+        (0..n)
+            .map(|i| {
+                let phase = 2.0 * PI * i as f32 / n as f32;
+                match self {
+                    Window::Hann => 0.5 - 0.5 * phase.cos(),
+                    Window::Hamming => 0.54 - 0.46 * phase.cos(),
+                    Window::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+                    Window::Rectangular => 1.0,
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    /// Looks a window up by name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Window::ALL
+            .into_iter()
+            .find(|window| window.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = Window::ALL.map(Window::name).join(", ");
+                format!("unknown window '{s}', expected one of {names}")
+            })
+    }
+}
+
+/// How a spectrogram is worked out and drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectrogramOptions {
+    /// Samples per transform, a power of two from 16 to 65536: more gives
+    /// finer frequencies and coarser times.
+    pub fft_size: usize,
+    /// Samples from the start of one transform to the next.
+    pub hop: usize,
+    pub window: Window,
+    /// Size of the whole image, labels included, in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// The level drawn darkest, in dB below a full-scale sine; anything
+    /// quieter is drawn the same.
+    pub floor_db: f32,
+    /// Label the times along the bottom and the frequencies up the left.
+    pub labels: bool,
+    /// What the file is decoded with.
+    pub decoder: DecoderBackend,
+}
+
+impl Default for SpectrogramOptions {
+    fn default() -> Self {
+        SpectrogramOptions {
+            fft_size: 2048,
+            hop: 512,
+            window: Window::Hann,
+            width: 1200,
+            height: 600,
+            floor_db: -120.0,
+            labels: true,
+            decoder: DecoderBackend::Auto,
+        }
+    }
+}
+
+/// An 8-bit RGB image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageBuffer {
+    pub width: u32,
+    pub height: u32,
+    /// Three bytes a pixel, row by row from the top.
+    pub pixels: Vec<u8>,
+}
+
+impl ImageBuffer {
+    fn new(width: u32, height: u32, color: [u8; 3]) -> Self {
+        ImageBuffer {
+            width,
+            height,
+            pixels: color.repeat(width as usize * height as usize),
+        }
+    }
+
+    /// The pixel `x` from the left and `y` from the top.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        let at = (y as usize * self.width as usize + x as usize) * 3;
+        [self.pixels[at], self.pixels[at + 1], self.pixels[at + 2]]
+    }
+
+    fn put(&mut self, x: u32, y: u32, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let at = (y as usize * self.width as usize + x as usize) * 3;
+            self.pixels[at..at + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// The image as a PNG file.
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode(self.width, self.height, &self.pixels)
+    }
+
+    /// Writes the image to `path` as a PNG.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_png())
+    }
+}
+
+/// Draws the spectrogram of the file at `path`, downmixed to mono: time
+/// from left to right, and frequency from 20 Hz at the bottom to Nyquist at
+/// the top on a log scale.
+pub fn render(
+    path: impl AsRef<Path>,
+    options: SpectrogramOptions,
+) -> Result<ImageBuffer, SpectrogramError> {
+    let path = path.as_ref();
+    let SpectrogramOptions {
+        fft_size,
+        hop,
+        window,
+        width,
+        height,
+        floor_db,
+        labels,
+        decoder,
+    } = options;
+    if !fft_size.is_power_of_two() || !(16..=65536).contains(&fft_size) {
+        return Err(format!("FFT size {fft_size} isn't a power of two from 16 to 65536").into());
+    }
+    if hop == 0 {
+        return Err("the hop must be at least one sample".into());
+    }
+    if !(floor_db < 0.0 && floor_db.is_finite()) {
+        return Err(format!("dB floor {floor_db} isn't below 0").into());
+    }
+    let (left, bottom) = match labels {
+        true => (LABEL_WIDTH, LABEL_HEIGHT),
+        false => (0, 0),
+    };
+    let (plot_width, plot_height) = (width.saturating_sub(left), height.saturating_sub(bottom));
+    if plot_width == 0 || plot_height == 0 {
+        return Err(format!("a {width}x{height} image leaves no room for the plot").into());
+    }
+
+    let backend = decoder.resolve(path);
+    let mut decoded = decode::open(File::open(path)?, path, backend)?;
+    let sample_rate = decoded.sample_rate().max(1);
+    let channels = usize::from(decoded.channels().max(1));
+    let duration = file_duration(path, decoded.total_duration());
+    let mut total = duration.map_or(0, |duration| {
+        (duration.as_secs_f64() * f64::from(sample_rate)).round() as usize
+    });
+    if total == 0 {
+        // No length in the headers: count it with a first pass.
+        total = decode::open(File::open(path)?, path, backend)?.count() / channels;
+    }
+    let mut samples = std::iter::from_fn(move || {
+        let mut sum = decoded.next()?;
+        for _ in 1..channels {
+            sum += decoded.next().unwrap_or(0.0);
+        }
+        Some(sum / channels as f32)
+    });
+
+    let rows = Rows::new(plot_height, sample_rate, fft_size);
+    let mut analysis = Analysis::new(fft_size, window, plot_width, plot_height);
+    let mut block = Vec::with_capacity(fft_size);
+    let mut start = 0;
+    loop {
+        block.extend(samples.by_ref().take(fft_size - block.len()));
+        if block.len() < fft_size && (start > 0 || block.is_empty()) {
+            break;
+        }
+        let center = start + fft_size / 2;
+        let column = (center as u64 * u64::from(plot_width) / total.max(1) as u64)
+            .min(u64::from(plot_width) - 1);
+        analysis.add(&block, column as usize, &rows);
+        if block.len() < fft_size {
+            break;
+        }
+        block.drain(..hop.min(fft_size));
+        // A hop past the end of the block skips samples outright.
+        if hop > fft_size {
+            samples.by_ref().take(hop - fft_size).for_each(drop);
+        }
+        start += hop;
+    }
+
+    let mut image = ImageBuffer::new(width, height, BACKGROUND);
+    for (x, column) in analysis.columns().enumerate() {
+        for (y, &power) in column.iter().enumerate() {
+            let db = 10.0 * power.max(f32::MIN_POSITIVE).log10();
+            let level = ((db - floor_db) / -floor_db).clamp(0.0, 1.0);
+            image.put(left + x as u32, y as u32, ramp(level));
+        }
+    }
+    if labels {
+        let seconds = total as f64 / f64::from(sample_rate);
+        label_times(&mut image, left, plot_width, plot_height, seconds);
+        label_frequencies(&mut image, left, plot_height, sample_rate);
+    }
+    Ok(image)
+}
+
+/// How each row of the plot reads the bins of a transform.
+enum Row {
+    /// The loudest of these bins, inclusive, for a row spanning some.
+    Bins(usize, usize),
+    /// Between bin `.0` and the next, `.1` of the way, for a row narrower
+    /// than a bin.
+    Between(usize, f32),
+}
+
+struct Rows(Vec<Row>);
+
+impl Rows {
+    /// Rows from the top, log-spaced from Nyquist down to [`LOWEST_HZ`].
+    fn new(height: u32, sample_rate: u32, fft_size: usize) -> Self {
+        let bin_hz = sample_rate as f32 / fft_size as f32;
+        let nyquist = sample_rate as f32 / 2.0;
+        let ratio = (nyquist / LOWEST_HZ).max(1.0);
+        let last_bin = fft_size / 2;
+        let frequency = |from_bottom: f32| LOWEST_HZ * ratio.powf(from_bottom / height as f32);
+        let rows = (0..height)
+            .map(|y| {
+                let from_bottom = (height - 1 - y) as f32;
+                let low = frequency(from_bottom) / bin_hz;
+                let high = frequency(from_bottom + 1.0) / bin_hz;
+                let (first, last) = (low.ceil() as usize, (high.floor() as usize).min(last_bin));
+                if first <= last {
+                    return Row::Bins(first, last);
+                }
+                let middle = frequency(from_bottom + 0.5) / bin_hz;
+                let bin = (middle.floor() as usize).min(last_bin - 1);
+                Row::Between(bin, (middle - bin as f32).clamp(0.0, 1.0))
+            })
+            .collect();
+        Rows(rows)
+    }
+}
+
+/// The power in each pixel of the plot, summed over the transforms that
+/// fall in its column.
+struct Analysis {
+    fft: Fft,
+    window: Vec<f32>,
+    /// Scales squared magnitudes so a full-scale sine peaks at 1.0.
+    scale: f32,
+    real: Vec<f32>,
+    imag: Vec<f32>,
+    power: Vec<f32>,
+    height: usize,
+    sums: Vec<f32>,
+    counts: Vec<u32>,
+}
+
+impl Analysis {
+    fn new(fft_size: usize, window: Window, width: u32, height: u32) -> Self {
+        let window = window.coefficients(fft_size);
+        let scale = (2.0 / window.iter().sum::<f32>()).powi(2);
+        Analysis {
+            fft: Fft::new(fft_size),
+            window,
+            scale,
+            real: vec![0.0; fft_size],
+            imag: vec![0.0; fft_size],
+            power: vec![0.0; fft_size / 2 + 1],
+            height: height as usize,
+            sums: vec![0.0; width as usize * height as usize],
+            counts: vec![0; width as usize],
+        }
+    }
+
+    /// Adds the transform of `block`, zero-padded if short, to `column`.
+    fn add(&mut self, block: &[f32], column: usize, rows: &Rows) {
+        self.real.fill(0.0);
+        self.imag.fill(0.0);
+        for ((real, sample), window) in self.real.iter_mut().zip(block).zip(&self.window) {
+            *real = sample * window;
+        }
+        self.fft.process(&mut self.real, &mut self.imag);
+        for (bin, power) in self.power.iter_mut().enumerate() {
+            let (re, im) = (self.real[bin], self.imag[bin]);
+            *power = (re * re + im * im) * self.scale;
+        }
+        let sums = &mut self.sums[column * self.height..(column + 1) * self.height];
+        for (sum, row) in sums.iter_mut().zip(&rows.0) {
+            *sum += match *row {
+                Row::Bins(first, last) => self.power[first..=last]
+                    .iter()
+                    .fold(0.0f32, |loudest, &power| loudest.max(power)),
+                Row::Between(bin, along) => {
+                    self.power[bin] * (1.0 - along) + self.power[bin + 1] * along
+                }
+            };
+        }
+        self.counts[column] += 1;
+    }
+
+    /// Each column's mean power per row, from the top; a column no
+    /// transform fell in, as when the hop is wider than a column, takes the
+    /// nearest one before it.
+    fn columns(&self) -> impl Iterator<Item = Vec<f32>> + '_ {
+        let mut last = vec![0.0; self.height];
+        let first = self.counts.iter().position(|&count| count > 0);
+        if let Some(first) = first {
+            last = self.mean(first);
+        }
+        (0..self.counts.len()).map(move |column| {
+            if self.counts[column] > 0 {
+                last = self.mean(column);
+            }
+            last.clone()
+        })
+    }
+
+    fn mean(&self, column: usize) -> Vec<f32> {
+        let count = self.counts[column] as f32;
+        self.sums[column * self.height..(column + 1) * self.height]
+            .iter()
+            .map(|sum| sum / count)
+            .collect()
+    }
+}
+
+/// The color `level` of the way up [`RAMP`].
+fn ramp(level: f32) -> [u8; 3] {
+    let at = level * (RAMP.len() - 1) as f32;
+    let index = (at as usize).min(RAMP.len() - 2);
+    let along = at - index as f32;
+    let (low, high) = (RAMP[index], RAMP[index + 1]);
+    [0, 1, 2].map(|i| {
+        (f32::from(low[i]) + (f32::from(high[i]) - f32::from(low[i])) * along).round() as u8
+    })
+}
+
+/// Marks and labels times under the plot, `seconds` long and starting
+/// `left` pixels in.
+fn label_times(image: &mut ImageBuffer, left: u32, width: u32, height: u32, seconds: f64) {
+    if seconds <= 0.0 {
+        return;
+    }
+    let fits = (width / TIME_LABEL_SPACING).max(1);
+    let step = TIME_STEPS
+        .into_iter()
+        .find(|&step| seconds / f64::from(step) <= f64::from(fits))
+        .unwrap_or(TIME_STEPS[TIME_STEPS.len() - 1]);
+    let hours = seconds >= 3600.0;
+    for time in (0..=seconds as u32).step_by(step as usize) {
+        let x = left + (f64::from(time) / seconds * f64::from(width)) as u32;
+        for y in height..height + 3 {
+            image.put(x, y, TEXT);
+        }
+        let text = match hours {
+            true => format!("{}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60),
+            false => format!("{}:{:02}", time / 60, time % 60),
+        };
+        // Centered under the mark, but kept in the image.
+        let text_width = text_width(&text);
+        let x = x
+            .saturating_sub(text_width / 2)
+            .max(left)
+            .min(image.width.saturating_sub(text_width));
+        draw_text(image, x, height + 5, &text);
+    }
+}
+
+/// Marks and labels frequencies left of the plot, which is `height` tall.
+fn label_frequencies(image: &mut ImageBuffer, left: u32, height: u32, sample_rate: u32) {
+    let nyquist = sample_rate as f32 / 2.0;
+    let ratio = nyquist / LOWEST_HZ;
+    if ratio <= 1.0 {
+        return;
+    }
+    let glyph_height = 5 * FONT_SCALE;
+    for hz in FREQUENCY_LABELS {
+        let hz_f = hz as f32;
+        if hz_f <= LOWEST_HZ || hz_f >= nyquist {
+            continue;
+        }
+        let from_bottom = (hz_f / LOWEST_HZ).ln() / ratio.ln() * height as f32;
+        let y = height - 1 - (from_bottom as u32).min(height - 1);
+        for x in left.saturating_sub(3)..left {
+            image.put(x, y, TEXT);
+        }
+        let text = match hz {
+            0..=999 => hz.to_string(),
+            _ => format!("{}k", hz / 1000),
+        };
+        let x = left.saturating_sub(5 + text_width(&text));
+        let y = y
+            .saturating_sub(glyph_height / 2)
+            .min(height.saturating_sub(glyph_height));
+        draw_text(image, x, y, &text);
+    }
+}
+
+fn text_width(text: &str) -> u32 {
+    (text.len() as u32 * 4).saturating_sub(1) * FONT_SCALE
+}
+
+/// Draws `text` with its top left at `x`, `y`, in a 3 by 5 pixel font of
+/// digits, `:` and `k`.
+fn draw_text(image: &mut ImageBuffer, x: u32, y: u32, text: &str) {
+    for (i, c) in text.chars().enumerate() {
+        let rows = match c {
+            '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+            '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+            '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+            '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+            '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+            '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+            '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+            '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+            '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+            '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+            ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+            'k' => [0b100, 0b101, 0b110, 0b101, 0b101],
+            _ => [0; 5],
+        };
+        let left = x + i as u32 * 4 * FONT_SCALE;
+        for (row, bits) in rows.into_iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..FONT_SCALE {
+                    for dx in 0..FONT_SCALE {
+                        let px = left + column * FONT_SCALE + dx;
+                        image.put(px, y + row as u32 * FONT_SCALE + dy, TEXT);
+                    }
+                }
+            }
+        }
+    }
+}
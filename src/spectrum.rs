@@ -0,0 +1,267 @@
+use crate::lock::Lock;
+use rodio::{source::SeekError, Source};
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Samples analysed per spectrum. Must be a power of two.
+pub const SPECTRUM_SIZE: usize = 2048;
+
+/// Level reported for bands with nothing in them, in dB.
+pub const SPECTRUM_FLOOR_DB: f32 = -90.0;
+
+/// How fast bars fall once no new audio arrives, in dB per second.
+const DECAY_DB_PER_SEC: f32 = 60.0;
+
+const LOWEST_BAND_HZ: f32 = 20.0;
+
+/// Recent output, downmixed to mono, written by the audio thread without
+/// locking or allocating.
+pub(crate) struct SpectrumTap {
+    samples: Box<[AtomicU32]>,
+    written: AtomicUsize,
+    sample_rate: AtomicU32,
+    analysis: Mutex<Analysis>,
+}
+
+/// Reader-side state, so paused output can fall off smoothly.
+struct Analysis {
+    bands: Vec<f32>,
+    written: usize,
+    updated: Instant,
+}
+
+impl SpectrumTap {
+    pub(crate) fn new() -> Self {
+        SpectrumTap {
+            samples: (0..SPECTRUM_SIZE).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            sample_rate: AtomicU32::new(44100),
+            analysis: Mutex::new(Analysis {
+                bands: Vec::new(),
+                written: 0,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    fn write(&self, sample: f32) {
+        let index = self.written.load(Ordering::Relaxed);
+        self.samples[index % SPECTRUM_SIZE].store(sample.to_bits(), Ordering::Relaxed);
+        self.written.store(index.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Magnitudes in dB of `bands` log-spaced bands from 20 Hz up to
+    /// Nyquist, over the most recent [`SPECTRUM_SIZE`] samples. A full-scale
+    /// sine reads 0 dB.
+    pub(crate) fn spectrum(&self, bands: usize) -> Vec<f32> {
+        let mut analysis = self.analysis.locked();
+        let written = self.written.load(Ordering::Acquire);
+        let now = Instant::now();
+
+        if written == analysis.written && analysis.bands.len() == bands {
+            // Nothing new has played since the last call: let the bars fall
+            // instead of freezing on the last frame.
+            let decay = now.duration_since(analysis.updated).as_secs_f32() * DECAY_DB_PER_SEC;
+            for band in &mut analysis.bands {
+                *band = (*band - decay).max(SPECTRUM_FLOOR_DB);
+            }
+        } else {
+            analysis.bands = analyse(self.snapshot(written), self.sample_rate(), bands);
+            analysis.written = written;
+        }
+        analysis.updated = now;
+        analysis.bands.clone()
+    }
+
+    fn snapshot(&self, written: usize) -> Vec<f32> {
+        (0..SPECTRUM_SIZE)
+            .map(|i| {
+                let index = written.wrapping_add(i) % SPECTRUM_SIZE;
+                f32::from_bits(self.samples[index].load(Ordering::Relaxed))
+            })
+            .collect()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+}
+
+fn analyse(mut samples: Vec<f32>, sample_rate: u32, bands: usize) -> Vec<f32> {
+    let n = samples.len();
+    let mut window_sum = 0.0;
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos();
+        window_sum += window;
+        *sample *= window;
+    }
+    let mut imag = vec![0.0; n];
+    Fft::new(n).process(&mut samples, &mut imag);
+
+    // Scale so a full-scale sine peaks at 1.0.
+    let scale = 2.0 / window_sum;
+    let magnitudes = samples[..n / 2]
+        .iter()
+        .zip(&imag)
+        .map(|(re, im)| (re * re + im * im).sqrt() * scale)
+        .collect::<Vec<_>>();
+
+    let nyquist = sample_rate as f32 / 2.0;
+    let bin_hz = sample_rate as f32 / n as f32;
+    let ratio = (nyquist / LOWEST_BAND_HZ).max(1.0);
+    (0..bands)
+        .map(|band| {
+            let low = LOWEST_BAND_HZ * ratio.powf(band as f32 / bands as f32);
+            let high = LOWEST_BAND_HZ * ratio.powf((band + 1) as f32 / bands as f32);
+            let first = (low / bin_hz).round() as usize;
+            let last = ((high / bin_hz).round() as usize).max(first + 1);
+            let peak = magnitudes[first.min(n / 2 - 1)..last.min(n / 2)]
+                .iter()
+                .fold(0.0f32, |peak, &magnitude| peak.max(magnitude));
+            (20.0 * peak.log10()).max(SPECTRUM_FLOOR_DB)
+        })
+        .collect()
+}
+
+/// In-place radix-2 FFT of a fixed size, with its twiddle factors worked
+/// out once.
+pub(crate) struct Fft {
+    /// `(cos, sin)` of each angle a butterfly turns by: `-2πk/n` for `k`
+    /// up to `n / 2`.
+    twiddles: Vec<(f32, f32)>,
+}
+
+impl Fft {
+    /// An FFT of `n` points; `n` must be a power of two.
+    pub(crate) fn new(n: usize) -> Self {
+        let twiddles = (0..n / 2)
+            .map(|k| {
+                let (sin, cos) = (-2.0 * PI * k as f32 / n as f32).sin_cos();
+                (cos, sin)
+            })
+            .collect();
+        Fft { twiddles }
+    }
+
+    /// Transforms `real` and `imag`, each of the FFT's size, in place.
+    pub(crate) fn process(&self, real: &mut [f32], imag: &mut [f32]) {
+        let n = real.len();
+        let mut j = 0;
+        for i in 1..n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                real.swap(i, j);
+                imag.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= n {
+            let step = n / len;
+            for start in (0..n).step_by(len) {
+                for k in 0..len / 2 {
+                    let (cos, sin) = self.twiddles[k * step];
+                    let (a, b) = (start + k, start + k + len / 2);
+                    let re = real[b] * cos - imag[b] * sin;
+                    let im = real[b] * sin + imag[b] * cos;
+                    real[b] = real[a] - re;
+                    imag[b] = imag[a] - im;
+                    real[a] += re;
+                    imag[a] += im;
+                }
+            }
+            len <<= 1;
+        }
+    }
+}
+
+/// Passes audio through unchanged while copying a mono downmix into a [`SpectrumTap`].
+pub(crate) struct Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    tap: Arc<SpectrumTap>,
+    sum: f32,
+    channel: u16,
+    channels: u16,
+}
+
+impl<S> Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, tap: Arc<SpectrumTap>) -> Self {
+        Tap {
+            source,
+            tap,
+            sum: 0.0,
+            channel: 0,
+            channels: 1,
+        }
+    }
+}
+
+impl<S> Iterator for Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.channels = self.source.channels().max(1);
+            self.tap
+                .sample_rate
+                .store(self.source.sample_rate(), Ordering::Relaxed);
+        }
+        let sample = self.source.next()?;
+        self.sum += sample;
+        self.channel += 1;
+        if self.channel >= self.channels {
+            self.tap.write(self.sum / self.channels as f32);
+            self.sum = 0.0;
+            self.channel = 0;
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.sum = 0.0;
+        self.channel = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{
+    bookmark::data_dir,
+    format::{json, Value},
+    queue::RepeatMode,
+    settings::EqSettings,
+    trim::TrackRange,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// What a player needs to carry on where it was: the queue and where in it
+/// playback was, with the settings a listener changes as they go. Kept as
+/// JSON, and read back by [`AudioPlayer::restore_state`].
+///
+/// [`AudioPlayer::restore_state`]: crate::AudioPlayer::restore_state
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerState {
+    /// Files and stream URLs, in queue order. Cue sheets are kept as their
+    /// audio file, and standard input not at all.
+    pub queue: Vec<PathBuf>,
+    /// The part of its file each entry of `queue` plays, for those queued
+    /// through [`AudioPlayer::enqueue_with_range`]; as long as `queue`.
+    ///
+    /// [`AudioPlayer::enqueue_with_range`]: crate::AudioPlayer::enqueue_with_range
+    pub ranges: Vec<Option<TrackRange>>,
+    /// Index into `queue` of the track that was playing.
+    pub current: Option<usize>,
+    /// Where in that track.
+    pub position: Duration,
+    pub volume_db: f32,
+    pub muted: bool,
+    pub eq: EqSettings,
+    pub eq_enabled: bool,
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+}
+
+impl PlayerState {
+    /// The version of the file format [`PlayerState::save`] writes. Files
+    /// from older versions read with defaults for what they lack; newer ones
+    /// are refused rather than half understood.
+    pub const VERSION: u64 = 2;
+
+    /// `state.json` next to the bookmarks, in the user's data directory.
+    pub fn default_path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join("state.json"))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let value = json::parse(&text).map_err(|err| invalid(err.to_string()))?;
+        Self::from_value(&value).map_err(invalid)
+    }
+
+    /// Writes the state to `path`, creating its directory. The file is
+    /// replaced in one step, so a crash halfway leaves the last one whole.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        fs::write(&partial, json::to_string(&self.to_value()))?;
+        fs::rename(&partial, path)
+    }
+
+    fn to_value(&self) -> Value {
+        let queue = self
+            .queue
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let path = Value::from(path.to_string_lossy().into_owned());
+                match self.ranges.get(index).copied().flatten() {
+                    Some(range) => Value::table()
+                        .with("path", path)
+                        .with("start", range.start.as_secs_f64())
+                        .with("end", range.end.map(|end| end.as_secs_f64())),
+                    None => path,
+                }
+            })
+            .collect::<Vec<_>>();
+        Value::table()
+            .with("version", PlayerState::VERSION)
+            .with("queue", queue)
+            .with("current", self.current)
+            .with("position", self.position.as_secs_f64())
+            .with("volume_db", self.volume_db)
+            .with("muted", self.muted)
+            .with("eq", self.eq.to_value().with("enabled", self.eq_enabled))
+            .with("repeat", self.repeat.name())
+            .with("shuffle", self.shuffle)
+    }
+
+    fn from_value(value: &Value) -> Result<Self, String> {
+        let version = value
+            .get("version")
+            .and_then(Value::as_f64)
+            .ok_or("the state file has no version")?;
+        if version > PlayerState::VERSION as f64 {
+            return Err(format!(
+                "the state file is version {version}, newer than this player's {}",
+                PlayerState::VERSION
+            ));
+        }
+        let wrong = |key: &str, kind: &str| format!("'{key}' must be {kind}");
+        let number = |key: &str| match value.get(key) {
+            None => Ok(None),
+            Some(v) => v.as_f64().map(Some).ok_or_else(|| wrong(key, "a number")),
+        };
+        let flag = |key: &str| match value.get(key) {
+            None => Ok(false),
+            Some(v) => v.as_bool().ok_or_else(|| wrong(key, "a boolean")),
+        };
+
+        let entries: Vec<(PathBuf, Option<TrackRange>)> = match value.get("queue") {
+            None => Vec::new(),
+            Some(queue) => queue
+                .as_array()
+                .and_then(|entries| entries.iter().map(queue_entry).collect())
+                .ok_or_else(|| wrong("queue", "a list of paths and ranges of files"))?,
+        };
+        let (queue, ranges): (Vec<_>, Vec<_>) = entries.into_iter().unzip();
+        let current = number("current")?
+            .map(|index| index as usize)
+            .filter(|&index| index < queue.len());
+        let position = number("position")?
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .unwrap_or_default();
+        let (eq, eq_enabled) = match value.get("eq") {
+            None => (EqSettings::default(), true),
+            Some(eq) => {
+                let enabled = match eq.get("enabled") {
+                    None => true,
+                    Some(v) => v
+                        .as_bool()
+                        .ok_or_else(|| wrong("eq.enabled", "a boolean"))?,
+                };
+                let settings =
+                    EqSettings::from_value(eq).map_err(|err| format!("'eq' is invalid: {err}"))?;
+                (settings, enabled)
+            }
+        };
+        let repeat = match value.get("repeat") {
+            None => RepeatMode::Off,
+            Some(v) => v
+                .as_str()
+                .and_then(RepeatMode::from_name)
+                .ok_or_else(|| wrong("repeat", "\"off\", \"one\" or \"all\""))?,
+        };
+
+        Ok(PlayerState {
+            queue,
+            ranges,
+            current,
+            position,
+            volume_db: number("volume_db")?.unwrap_or(0.0) as f32,
+            muted: flag("muted")?,
+            eq,
+            eq_enabled,
+            repeat,
+            shuffle: flag("shuffle")?,
+        })
+    }
+}
+
+/// A path, or a table of one with the range of it played.
+fn queue_entry(entry: &Value) -> Option<(PathBuf, Option<TrackRange>)> {
+    if let Some(path) = entry.as_str() {
+        return Some((PathBuf::from(path), None));
+    }
+    let seconds = |value: &Value| Duration::try_from_secs_f64(value.as_f64()?).ok();
+    let path = PathBuf::from(entry.get("path")?.as_str()?);
+    let start = match entry.get("start") {
+        None => Duration::ZERO,
+        Some(start) => seconds(start)?,
+    };
+    let end = match entry.get("end") {
+        None | Some(Value::Null) => None,
+        Some(end) => Some(seconds(end)?),
+    };
+    Some((path, Some(TrackRange { start, end })))
+}
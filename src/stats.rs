@@ -0,0 +1,400 @@
+//! Counters for working out why playback stutters, kept up to date by the
+//! chain as it plays and read with
+//! [`AudioPlayer::stats`](crate::AudioPlayer::stats).
+
+use crate::{
+    clock::Clock,
+    events::{PlayerEvent, Signal},
+    format::{json, Value},
+    suspend::SUSPEND_GAP,
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Frames between updates of the shared counters, and between the times the
+/// output checks itself against the wall clock.
+const COUNT_FRAMES: u64 = 256;
+
+/// How far the output may fall behind the wall clock, past the back and
+/// forth of the device taking it a buffer at a time, before that counts as
+/// an underrun.
+const UNDERRUN_GAP: Duration = Duration::from_millis(100);
+
+/// How fast the device's clock may run slow against the wall clock, as a
+/// fraction, without that adding up to an underrun.
+const DRIFT_ALLOWANCE: f64 = 0.001;
+
+/// What [`AudioPlayer::stats`](crate::AudioPlayer::stats) has counted since
+/// the player was made or the stats were last reset.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PlayerStats {
+    /// Frames the decoders have handed to the per-track chain.
+    pub frames_decoded: u64,
+    /// Frames that went to the output, after any change of speed or tempo.
+    pub frames_output: u64,
+    /// Times the output fell behind while playing, each also reported by a
+    /// [`PlayerEvent::Underrun`].
+    pub underruns: u64,
+    /// Damaged stretches of a file that playback got past.
+    pub decode_errors: u64,
+    /// Seeks that landed.
+    pub seeks: u64,
+    /// Seeks that failed, or were dropped for a later one or a change of
+    /// track before they landed.
+    pub seeks_dropped: u64,
+    /// From asking for a seek until it landed, averaged over `seeks`.
+    pub average_seek_latency: Option<Duration>,
+    /// Files opened for playing, as a track comes up or a seek rebuilds
+    /// playback.
+    pub decoders_opened: u64,
+    /// Wall-clock time spent playing.
+    pub play_time: Duration,
+    /// The length of the audio output over that time.
+    pub audio_time: Duration,
+}
+
+impl PlayerStats {
+    /// Play time less audio time, in seconds: above zero when the output
+    /// fell behind the wall clock, as across underruns, and below when the
+    /// device takes audio faster than it plays it.
+    pub fn drift_secs(&self) -> f64 {
+        self.play_time.as_secs_f64() - self.audio_time.as_secs_f64()
+    }
+
+    /// The stats as a JSON object, times in seconds; an unknown average is
+    /// null.
+    pub fn to_json(&self) -> String {
+        let value = Value::table()
+            .with("frames_decoded", self.frames_decoded)
+            .with("frames_output", self.frames_output)
+            .with("underruns", self.underruns)
+            .with("decode_errors", self.decode_errors)
+            .with("seeks", self.seeks)
+            .with("seeks_dropped", self.seeks_dropped)
+            .with(
+                "average_seek_latency",
+                self.average_seek_latency
+                    .map(|latency| latency.as_secs_f64()),
+            )
+            .with("decoders_opened", self.decoders_opened)
+            .with("play_time", self.play_time.as_secs_f64())
+            .with("audio_time", self.audio_time.as_secs_f64())
+            .with("drift", self.drift_secs());
+        json::to_string(&value)
+    }
+}
+
+/// The counters behind [`PlayerStats`], shared by the player and the chain.
+pub(crate) struct StatsControls {
+    frames_decoded: AtomicU64,
+    frames_output: AtomicU64,
+    underruns: AtomicU64,
+    decode_errors: AtomicU64,
+    seeks: AtomicU64,
+    seeks_dropped: AtomicU64,
+    seek_ns: AtomicU64,
+    decoders_opened: AtomicU64,
+    play_ns: AtomicU64,
+    audio_ns: AtomicU64,
+}
+
+impl StatsControls {
+    pub(crate) fn new() -> Self {
+        StatsControls {
+            frames_decoded: AtomicU64::new(0),
+            frames_output: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            decode_errors: AtomicU64::new(0),
+            seeks: AtomicU64::new(0),
+            seeks_dropped: AtomicU64::new(0),
+            seek_ns: AtomicU64::new(0),
+            decoders_opened: AtomicU64::new(0),
+            play_ns: AtomicU64::new(0),
+            audio_ns: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> PlayerStats {
+        let seeks = self.seeks.load(Ordering::Relaxed);
+        let seek_ns = self.seek_ns.load(Ordering::Relaxed);
+        PlayerStats {
+            frames_decoded: self.frames_decoded.load(Ordering::Relaxed),
+            frames_output: self.frames_output.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            seeks,
+            seeks_dropped: self.seeks_dropped.load(Ordering::Relaxed),
+            average_seek_latency: (seeks > 0).then(|| Duration::from_nanos(seek_ns / seeks)),
+            decoders_opened: self.decoders_opened.load(Ordering::Relaxed),
+            play_time: Duration::from_nanos(self.play_ns.load(Ordering::Relaxed)),
+            audio_time: Duration::from_nanos(self.audio_ns.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Sets every counter back to zero.
+    pub(crate) fn reset(&self) {
+        for counter in [
+            &self.frames_decoded,
+            &self.frames_output,
+            &self.underruns,
+            &self.decode_errors,
+            &self.seeks,
+            &self.seeks_dropped,
+            &self.seek_ns,
+            &self.decoders_opened,
+            &self.play_ns,
+            &self.audio_ns,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn add_decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_decoder_opened(&self) {
+        self.decoders_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Starts timing a seek asked for now. Dropping the ticket without
+    /// [`SeekTicket::landed`] counts the seek as dropped.
+    pub(crate) fn seek_ticket(self: &Arc<Self>) -> SeekTicket {
+        SeekTicket {
+            stats: self.clone(),
+            asked: Instant::now(),
+            landed: false,
+        }
+    }
+}
+
+/// A seek on its way, from [`StatsControls::seek_ticket`].
+pub(crate) struct SeekTicket {
+    stats: Arc<StatsControls>,
+    asked: Instant,
+    landed: bool,
+}
+
+impl SeekTicket {
+    pub(crate) fn landed(mut self) {
+        self.landed = true;
+        let ns = self.asked.elapsed().as_nanos() as u64;
+        self.stats.seeks.fetch_add(1, Ordering::Relaxed);
+        self.stats.seek_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+}
+
+impl Drop for SeekTicket {
+    fn drop(&mut self) {
+        if !self.landed {
+            self.stats.seeks_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Counts the frames a decoder hands to the per-track chain.
+pub(crate) struct DecodeCounter<S> {
+    input: S,
+    stats: Arc<StatsControls>,
+    /// Samples not yet added to the shared count.
+    samples: u64,
+}
+
+impl<S: Source<Item = f32>> DecodeCounter<S> {
+    pub(crate) fn new(input: S, stats: Arc<StatsControls>) -> Self {
+        DecodeCounter {
+            input,
+            stats,
+            samples: 0,
+        }
+    }
+
+    fn flush(&mut self) {
+        let channels = u64::from(self.input.channels().max(1));
+        let frames = self.samples / channels;
+        self.stats
+            .frames_decoded
+            .fetch_add(frames, Ordering::Relaxed);
+        self.samples -= frames * channels;
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for DecodeCounter<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let Some(sample) = self.input.next() else {
+            self.flush();
+            return None;
+        };
+        self.samples += 1;
+        if self.samples >= COUNT_FRAMES * u64::from(self.input.channels().max(1)) {
+            self.flush();
+        }
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for DecodeCounter<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+/// Counts the frames that go to the output and the time they take, and
+/// tells an underrun by the output falling behind the wall clock while
+/// playing.
+///
+/// The device takes audio a buffer at a time, so how far behind the output
+/// is swings back and forth by up to a buffer; only a rise of more than
+/// [`UNDERRUN_GAP`] over the lowest it has been since the last underrun
+/// counts, the lowest creeping up by [`DRIFT_ALLOWANCE`] for a device that
+/// runs a little slow.
+pub(crate) struct OutputCounter<S> {
+    input: S,
+    stats: Arc<StatsControls>,
+    clock: Arc<Clock>,
+    signals: Sender<Signal>,
+    /// Samples not yet added to the shared count.
+    samples: u64,
+    /// When the output last checked the wall clock, and how many times the
+    /// clock had started or stopped by then.
+    checked: Option<(Instant, u64)>,
+    /// How far the output is behind the wall clock, and the lowest that has
+    /// been, in seconds from an arbitrary start.
+    behind: f64,
+    lowest: f64,
+}
+
+impl<S: Source<Item = f32>> OutputCounter<S> {
+    pub(crate) fn new(
+        input: S,
+        stats: Arc<StatsControls>,
+        clock: Arc<Clock>,
+        signals: Sender<Signal>,
+    ) -> Self {
+        OutputCounter {
+            input,
+            stats,
+            clock,
+            signals,
+            samples: 0,
+            checked: None,
+            behind: 0.0,
+            lowest: 0.0,
+        }
+    }
+
+    fn flush(&mut self) {
+        let channels = u64::from(self.input.channels().max(1));
+        let frames = self.samples / channels;
+        self.samples -= frames * channels;
+        let audio = frames as f64 / f64::from(self.input.sample_rate().max(1));
+        self.stats
+            .frames_output
+            .fetch_add(frames, Ordering::Relaxed);
+
+        let now = Instant::now();
+        let transitions = self.clock.transitions();
+        let checked = self.checked.replace((now, transitions));
+        // Time paused, or spent starting or stopping, is no gap, nor is a
+        // suspend the output hasn't told the clock about yet.
+        let Some((then, _)) =
+            checked.filter(|&(then, seen)| seen == transitions && now - then < SUSPEND_GAP)
+        else {
+            self.behind = 0.0;
+            self.lowest = 0.0;
+            return;
+        };
+        if !self.clock.is_playing() {
+            return;
+        }
+        let elapsed = now.duration_since(then).as_secs_f64();
+        self.stats
+            .play_ns
+            .fetch_add((elapsed * 1e9) as u64, Ordering::Relaxed);
+        self.stats
+            .audio_ns
+            .fetch_add((audio * 1e9) as u64, Ordering::Relaxed);
+        self.behind += elapsed - audio;
+        self.lowest = self.behind.min(self.lowest + elapsed * DRIFT_ALLOWANCE);
+        let gap = self.behind - self.lowest;
+        if gap > UNDERRUN_GAP.as_secs_f64() {
+            self.lowest = self.behind;
+            self.stats.underruns.fetch_add(1, Ordering::Relaxed);
+            let gap = Duration::from_secs_f64(gap);
+            let _ = self
+                .signals
+                .send(Signal::Event(PlayerEvent::Underrun { gap }));
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for OutputCounter<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let Some(sample) = self.input.next() else {
+            self.flush();
+            return None;
+        };
+        self.samples += 1;
+        if self.samples >= COUNT_FRAMES * u64::from(self.input.channels().max(1)) {
+            self.flush();
+        }
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for OutputCounter<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
@@ -0,0 +1,137 @@
+//! The status line the command-line player redraws while it plays.
+
+use crate::format_time;
+use crossterm::{
+    cursor, execute, queue,
+    style::Print,
+    terminal::{self, Clear, ClearType},
+};
+use fullyrustaudio::AudioPlayer;
+use std::{
+    io::{self, IsTerminal, Stdout, Write},
+    panic,
+};
+
+/// The range the peak meter shows, from here up to full scale.
+const METER_RANGE_DB: f32 = 48.0;
+const METER_CELLS: usize = 6;
+
+/// Narrower than this, the progress bar is left out.
+const MIN_BAR_WIDTH: usize = 8;
+
+/// Elapsed and total time, a progress bar or a live stream's title, the
+/// volume, any ReplayGain, whether EQ is on and a peak meter, rewritten in place on one line of standard output.
+/// Dropping it clears the line and shows the cursor again.
+pub struct StatusLine {
+    stdout: Stdout,
+}
+
+impl StatusLine {
+    /// A status line, unless standard output isn't a terminal.
+    pub fn new() -> Option<Self> {
+        let mut stdout = io::stdout();
+        if !stdout.is_terminal() {
+            return None;
+        }
+        execute!(stdout, cursor::Hide).ok()?;
+        // Give the cursor back after a panic on any thread, too.
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = execute!(io::stdout(), cursor::Show);
+            default_hook(info);
+        }));
+        Some(StatusLine { stdout })
+    }
+
+    pub fn draw(&mut self, player: &AudioPlayer) -> io::Result<()> {
+        // One column short of the width, so the line never wraps.
+        let width = terminal::size().map_or(80, |(columns, _)| usize::from(columns));
+        let line = status(player, width.saturating_sub(1));
+        queue!(
+            self.stdout,
+            cursor::MoveToColumn(0),
+            Print(line),
+            Clear(ClearType::UntilNewLine)
+        )?;
+        self.stdout.flush()
+    }
+
+    /// Empties the line, so a message can be printed in its place; the next
+    /// [`StatusLine::draw`] puts the status below it.
+    pub fn clear(&mut self) -> io::Result<()> {
+        execute!(
+            self.stdout,
+            cursor::MoveToColumn(0),
+            Clear(ClearType::CurrentLine)
+        )
+    }
+}
+
+impl Drop for StatusLine {
+    fn drop(&mut self) {
+        let _ = self.clear();
+        let _ = execute!(self.stdout, cursor::Show);
+    }
+}
+
+/// The status of `player` in at most `width` characters.
+fn status(player: &AudioPlayer, width: usize) -> String {
+    let state = if player.is_playing() { '▶' } else { '‖' };
+    let elapsed = player.get_playback_position();
+    let duration = player.duration();
+    let time = match duration {
+        Some(duration) => format!("{} / {}", format_time(elapsed), format_time(duration)),
+        None => format_time(elapsed),
+    };
+    let volume = match player.is_muted() {
+        true => "muted".to_string(),
+        false => format!("vol {:+.1} dB", player.volume_db()),
+    };
+    let replay_gain = player
+        .replaygain_db()
+        .map_or(String::new(), |gain_db| format!("  RG {gain_db:+.1} dB"));
+    let eq = if player.eq_enabled() { "on" } else { "off" };
+    let meter = meter(player);
+    let left = format!("{state} {time} ");
+    let right = format!(" {volume}{replay_gain}  EQ {eq}  {meter}");
+
+    let room = width.saturating_sub(left.chars().count() + right.chars().count() + 2);
+    let bar = match duration.filter(|duration| !duration.is_zero()) {
+        Some(duration) if room >= MIN_BAR_WIDTH => {
+            let fraction = (elapsed.as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0);
+            let filled = (fraction * room as f64).round() as usize;
+            format!("[{}{}]", "=".repeat(filled), "-".repeat(room - filled))
+        }
+        // A live stream has no end to show progress to, only what's on.
+        None => match player.stream_title() {
+            Some(title) => format!("{:<room$}", title.chars().take(room).collect::<String>()),
+            None => String::new(),
+        },
+        _ => String::new(),
+    };
+    format!("{left}{bar}{right}").chars().take(width).collect()
+}
+
+/// A bar per output channel for its peak level, with `CLIP` once the
+/// output has hit full scale.
+fn meter(player: &AudioPlayer) -> String {
+    let mut peaks = Vec::from_iter(player.levels().iter().map(|level| level.peak_db));
+    // Until audio reaches the output, so the line keeps its length.
+    if peaks.is_empty() {
+        let channels = player.stream_config().map_or(0, |config| config.channels);
+        peaks = vec![f32::NEG_INFINITY; usize::from(channels)];
+    }
+    let mut meter = peaks
+        .iter()
+        .map(|peak_db| {
+            let lit = (peak_db + METER_RANGE_DB) / METER_RANGE_DB * METER_CELLS as f32;
+            let lit = (lit.round().max(0.0) as usize).min(METER_CELLS);
+            format!("{}{}", "■".repeat(lit), "·".repeat(METER_CELLS - lit))
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    if player.is_clipped() {
+        meter.push_str(" CLIP");
+    }
+    meter
+}
@@ -0,0 +1,133 @@
+//! Playback from audio piped on standard input, as in
+//! `ffmpeg -i in.m4a -f flac - | fullyrustaudio -`.
+
+use crate::error::PlayerError;
+use rodio::{Decoder, Source};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// The path that stands for standard input.
+pub const STDIN_PATH: &str = "-";
+
+/// How much the decoder may read back over once it is running.
+const LOOKBEHIND: usize = 64 * 1024;
+
+/// Returned for a seek in, or a second run of, a source that can only be
+/// read once, such as standard input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UnseekableSource;
+
+impl fmt::Display for UnseekableSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the source can only be read once, so it can't seek")
+    }
+}
+
+impl Error for UnseekableSource {}
+
+/// Standard input as a track. Only the first [`Stdin::decoder`] gets the
+/// audio; there is no way to read it again.
+#[derive(Debug, Default)]
+pub(crate) struct Stdin {
+    taken: AtomicBool,
+}
+
+impl Stdin {
+    /// Blocks until enough has arrived to tell the format.
+    pub(crate) fn decoder(&self) -> Result<impl Source<Item = f32> + Send, PlayerError> {
+        if self.taken.swap(true, Ordering::Relaxed) {
+            return Err(PlayerError::UnsupportedSeek);
+        }
+        let probing = Arc::new(AtomicBool::new(true));
+        let decoder = Decoder::new(StdinReader {
+            buffer: Vec::new(),
+            start: 0,
+            position: 0,
+            probing: probing.clone(),
+        })?;
+        probing.store(false, Ordering::Relaxed);
+        Ok(decoder.convert_samples::<f32>())
+    }
+}
+
+/// Standard input as the decoder reads it. Telling the format rewinds to
+/// the start, so everything is kept until the decoder is built; after that
+/// only the last [`LOOKBEHIND`] bytes are.
+struct StdinReader {
+    buffer: Vec<u8>,
+    /// Offset in the stream of `buffer[0]`.
+    start: u64,
+    position: u64,
+    probing: Arc<AtomicBool>,
+}
+
+impl StdinReader {
+    fn end(&self) -> u64 {
+        self.start + self.buffer.len() as u64
+    }
+
+    /// Appends the next bytes from standard input, returning how many; 0 at
+    /// the end of the stream.
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0; 16 * 1024];
+        let read = loop {
+            match io::stdin().lock().read(&mut chunk) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                read => break read?,
+            }
+        };
+        self.buffer.extend_from_slice(&chunk[..read]);
+        if !self.probing.load(Ordering::Relaxed) && self.buffer.len() > 2 * LOOKBEHIND {
+            let drop = self.buffer.len() - LOOKBEHIND;
+            self.buffer.drain(..drop);
+            self.start += drop as u64;
+        }
+        Ok(read)
+    }
+}
+
+impl Read for StdinReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.end() && self.fill()? == 0 {
+            return Ok(0);
+        }
+        let offset = (self.position - self.start) as usize;
+        let len = buf.len().min(self.buffer.len() - offset);
+        buf[..len].copy_from_slice(&self.buffer[offset..offset + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for StdinReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let unseekable = || io::Error::new(io::ErrorKind::Unsupported, UnseekableSource);
+        let target = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => self
+                .position
+                .checked_add_signed(offset)
+                .ok_or_else(unseekable)?,
+            SeekFrom::End(_) => return Err(unseekable()),
+        };
+        if target < self.start {
+            return Err(unseekable());
+        }
+        // Going forward is just reading on; a target past the end of the
+        // stream stops at the end.
+        while target > self.end() {
+            if self.fill()? == 0 {
+                break;
+            }
+        }
+        self.position = target.min(self.end());
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant, SystemTime};
+
+/// How much longer than it should a wait has to take to count as the
+/// system having been suspended through it.
+pub(crate) const SUSPEND_GAP: Duration = Duration::from_secs(3);
+
+/// A suspend of the system, found on resuming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Suspend {
+    /// How long it lasted, going by whichever clock saw more of it.
+    pub(crate) length: Duration,
+    /// How much of it [`Instant`] counted: all of it on some platforms,
+    /// none on others.
+    pub(crate) counted: Duration,
+}
+
+/// The time now on the monotonic clock and the system clock: the system's
+/// own, or in tests ones made to jump as they do through a suspend.
+pub(crate) trait WallClock: Send {
+    fn now(&self) -> (Instant, SystemTime);
+}
+
+/// The system's clocks.
+pub(crate) struct SystemClock;
+
+impl WallClock for SystemClock {
+    fn now(&self) -> (Instant, SystemTime) {
+        (Instant::now(), SystemTime::now())
+    }
+}
+
+/// Tells when the system was suspended from a thread that wakes at a steady
+/// rate, by the time between wakes jumping. The monotonic clock goes on
+/// through a suspend on some platforms and stops on others, while the
+/// system clock always goes on, so a jump on either counts.
+pub(crate) struct SuspendWatch<C = SystemClock> {
+    clock: C,
+    instant: Instant,
+    system: SystemTime,
+}
+
+impl SuspendWatch {
+    pub(crate) fn new() -> Self {
+        SuspendWatch::with_clock(SystemClock)
+    }
+}
+
+impl<C: WallClock> SuspendWatch<C> {
+    pub(crate) fn with_clock(clock: C) -> Self {
+        let (instant, system) = clock.now();
+        SuspendWatch {
+            clock,
+            instant,
+            system,
+        }
+    }
+
+    /// When the watch last looked at the monotonic clock, going by its clock.
+    pub(crate) fn checked_at(&self) -> Instant {
+        self.instant
+    }
+
+    /// Checks on waking from a wait meant to take `expected`.
+    pub(crate) fn check(&mut self, expected: Duration) -> Option<Suspend> {
+        let (instant, system) = self.clock.now();
+        let counted = instant.saturating_duration_since(self.instant);
+        // A system clock set back is no suspend.
+        let passed = system.duration_since(self.system).unwrap_or_default();
+        self.instant = instant;
+        self.system = system;
+        let length = counted.max(passed).saturating_sub(expected);
+        (length >= SUSPEND_GAP).then(|| Suspend {
+            length,
+            counted: counted.saturating_sub(expected),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::JumpingClock;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn a_suspend_the_monotonic_clock_stopped_for_is_counted_by_the_system_clock() {
+        let clock = JumpingClock::default();
+        let mut watch = SuspendWatch::with_clock(clock.clone());
+        clock.jump(Duration::ZERO, HOUR);
+        let suspend = watch.check(Duration::ZERO).unwrap();
+        assert!(suspend.length >= HOUR && suspend.length < HOUR + SUSPEND_GAP);
+        assert!(suspend.counted < SUSPEND_GAP);
+    }
+
+    #[test]
+    fn a_suspend_both_clocks_went_on_through_is_counted_on_both() {
+        let clock = JumpingClock::default();
+        let mut watch = SuspendWatch::with_clock(clock.clone());
+        clock.jump(HOUR, HOUR);
+        let suspend = watch.check(Duration::ZERO).unwrap();
+        assert!(suspend.length >= HOUR && suspend.length < HOUR + SUSPEND_GAP);
+        assert!(suspend.counted >= HOUR && suspend.counted <= suspend.length);
+    }
+
+    #[test]
+    fn a_wait_that_ran_as_long_as_expected_is_no_suspend() {
+        let clock = JumpingClock::default();
+        let mut watch = SuspendWatch::with_clock(clock.clone());
+        clock.jump(HOUR, HOUR);
+        assert_eq!(watch.check(HOUR), None);
+        clock.jump(Duration::ZERO, SUSPEND_GAP / 2);
+        assert_eq!(watch.check(Duration::ZERO), None);
+    }
+}
@@ -0,0 +1,338 @@
+use crate::atomic::AtomicF32;
+use rodio::{source::SeekError, Source};
+use std::{f32::consts::PI, sync::Arc, time::Duration};
+
+/// Length of the overlapping segments the audio is cut into. Long enough to
+/// hold a couple of periods of a low voice, short enough not to smear
+/// syllables.
+const SEGMENT: Duration = Duration::from_millis(30);
+
+/// How far a segment may move from where the tempo puts it, to line up with
+/// the audio before it. Covers half a period of an 80 Hz voice.
+const SEEK_WINDOW: Duration = Duration::from_millis(8);
+
+/// Tempo ratios this close to 1.0 play the source untouched.
+const BYPASS: f32 = 1e-3;
+
+/// Tempo and pitch shared between the player and its [`TimeStretch`] and
+/// [`PitchShift`](crate::pitch::PitchShift) stages.
+pub(crate) struct TempoControls {
+    ratio: AtomicF32,
+    semitones: AtomicF32,
+}
+
+impl TempoControls {
+    pub(crate) fn new() -> Self {
+        TempoControls {
+            ratio: AtomicF32::new(1.0),
+            semitones: AtomicF32::new(0.0),
+        }
+    }
+
+    pub(crate) fn ratio(&self) -> f32 {
+        self.ratio.load()
+    }
+
+    /// Takes effect from the next segment, so changes while playing are seamless.
+    pub(crate) fn set_ratio(&self, ratio: f32) {
+        self.ratio.store(ratio);
+    }
+
+    pub(crate) fn semitones(&self) -> f32 {
+        self.semitones.load()
+    }
+
+    pub(crate) fn set_semitones(&self, semitones: f32) {
+        self.semitones.store(semitones);
+    }
+
+    /// How much faster than the source the pitch shifter reads; exactly 1.0
+    /// at 0 semitones.
+    pub(crate) fn pitch_factor(&self) -> f32 {
+        2f32.powf(self.semitones() / 12.0)
+    }
+
+    /// What the time stretch has to do so that after the pitch shifter's
+    /// resampling the audio plays at the tempo.
+    fn stretch(&self) -> f32 {
+        self.ratio() / self.pitch_factor()
+    }
+}
+
+/// Changes the tempo without changing pitch, using WSOLA: segments are read
+/// from the source at the tempo's pace and overlapped at a fixed pace, each
+/// one nudged to where it best continues the one before.
+///
+/// The stretch ratio is the tempo over the pitch factor, undoing the speed-up
+/// of the pitch shifter. At a ratio of 1.0 the source passes through
+/// untouched. Format changes between tracks are played out in the old format
+/// first, and a seek starts over. The source is read a segment or so ahead
+/// of what is heard.
+pub(crate) struct TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<TempoControls>,
+    stretching: bool,
+    channels: u16,
+    sample_rate: u32,
+    /// Frames per half segment, the output advance per segment.
+    hop: usize,
+    search: usize,
+    /// Fades a segment in over its first `hop` values and out over the rest.
+    window: Vec<f32>,
+    /// Interleaved source audio not yet played past.
+    input: Vec<f32>,
+    /// Frame in `input` where the second half of the last segment starts:
+    /// what would naturally follow the output so far.
+    tail: usize,
+    /// Frame in `input` where the next segment starts at exactly the tempo.
+    nominal: f64,
+    output: Vec<f32>,
+    output_index: usize,
+    /// Channel of the next sample while passing the source through.
+    channel: u16,
+    /// Whether the source has run out, so there is nothing left to stretch.
+    ended: bool,
+}
+
+impl<S> TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<TempoControls>) -> Self {
+        TimeStretch {
+            channels: source.channels().max(1),
+            sample_rate: source.sample_rate(),
+            source,
+            controls,
+            stretching: false,
+            hop: 0,
+            search: 0,
+            window: Vec::new(),
+            input: Vec::new(),
+            tail: 0,
+            nominal: 0.0,
+            output: Vec::new(),
+            output_index: 0,
+            channel: 0,
+            ended: false,
+        }
+    }
+
+    /// Starts stretching from the source's current position, in its current format.
+    fn start(&mut self, ratio: f32) {
+        self.channels = self.source.channels().max(1);
+        self.sample_rate = self.source.sample_rate();
+        let frames = |time: Duration| (time.as_secs_f64() * self.sample_rate as f64) as usize;
+        let hop = (frames(SEGMENT) / 2).max(1);
+        if hop != self.hop {
+            self.hop = hop;
+            self.window = (0..2 * hop)
+                .map(|i| (PI * i as f32 / (2 * hop) as f32).sin().powi(2))
+                .collect();
+        }
+        self.search = frames(SEEK_WINDOW);
+        self.stretching = true;
+        self.input.clear();
+        // As if a segment had just played up to where the source is now.
+        self.tail = 0;
+        self.nominal = (ratio as f64 - 1.0) * hop as f64;
+    }
+
+    /// Plays out what would follow the output so far and goes back to
+    /// passing the source through.
+    fn finish(&mut self) {
+        let channels = self.channels as usize;
+        let start = (self.tail * channels).min(self.input.len());
+        self.output.extend_from_slice(&self.input[start..]);
+        self.input.clear();
+        self.stretching = false;
+        self.channel = 0;
+    }
+
+    /// Reads whole frames until `input` holds `frames`. Returns `false` if the
+    /// source ends or changes format first.
+    fn fill(&mut self, frames: usize) -> bool {
+        let channels = self.channels as usize;
+        while self.input.len() < frames * channels {
+            if self.source.channels().max(1) != self.channels
+                || self.source.sample_rate() != self.sample_rate
+            {
+                return false;
+            }
+            for channel in 0..channels {
+                match self.source.next() {
+                    Some(sample) => self.input.push(sample),
+                    None => {
+                        self.ended = true;
+                        self.input.truncate(self.input.len() - channel);
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn mono(&self, frame: usize) -> f32 {
+        let channels = self.channels as usize;
+        self.input[frame * channels..(frame + 1) * channels]
+            .iter()
+            .sum()
+    }
+
+    /// How well a segment starting at frame `start` continues the output,
+    /// comparing every `step`th frame.
+    fn similarity(&self, start: usize, step: usize) -> f32 {
+        let (mut correlation, mut energy) = (0.0, 0.0);
+        for i in (0..self.hop).step_by(step) {
+            let candidate = self.mono(start + i);
+            correlation += candidate * self.mono(self.tail + i);
+            energy += candidate * candidate;
+        }
+        correlation / energy.max(1e-9).sqrt()
+    }
+
+    /// The start between `low` and `high` that best continues the output:
+    /// a coarse pass over every fourth candidate, then the ones around the
+    /// best of those.
+    fn best_start(&self, low: usize, high: usize) -> usize {
+        let best = |candidates: &mut dyn Iterator<Item = usize>, step| {
+            candidates
+                .map(|start| (start, self.similarity(start, step)))
+                .fold((low, f32::NEG_INFINITY), |best, candidate| {
+                    if candidate.1 > best.1 {
+                        candidate
+                    } else {
+                        best
+                    }
+                })
+                .0
+        };
+        let coarse = best(&mut (low..=high).step_by(4), 2);
+        let fine_low = coarse.saturating_sub(3).max(low);
+        best(&mut (fine_low..=(coarse + 3).min(high)), 1)
+    }
+
+    /// Overlaps the next segment onto the output, or finishes stretching if
+    /// the ratio is back to 1.0 or the source ran out.
+    fn step(&mut self) {
+        let ratio = self.controls.stretch();
+        if (ratio - 1.0).abs() < BYPASS {
+            self.finish();
+            return;
+        }
+        let (hop, search) = (self.hop, self.search);
+        let low = (self.nominal - search as f64).max(0.0) as usize;
+        let high = (self.nominal + search as f64).max(0.0) as usize;
+        // The new segment, and the second half after it that the next one lines up with.
+        if !self.fill((high + 2 * hop).max(self.tail + hop)) {
+            self.finish();
+            return;
+        }
+
+        let start = self.best_start(low, high);
+        let channels = self.channels as usize;
+        for i in 0..hop {
+            let (fade_out, fade_in) = (self.window[hop + i], self.window[i]);
+            let outgoing = (self.tail + i) * channels;
+            let incoming = (start + i) * channels;
+            for channel in 0..channels {
+                self.output.push(
+                    self.input[outgoing + channel] * fade_out
+                        + self.input[incoming + channel] * fade_in,
+                );
+            }
+        }
+        self.tail = start + hop;
+        self.nominal += hop as f64 * ratio as f64;
+
+        let played = self
+            .tail
+            .min((self.nominal - search as f64).max(0.0) as usize);
+        self.input.drain(..played * channels);
+        self.tail -= played;
+        self.nominal -= played as f64;
+    }
+}
+
+impl<S> Iterator for TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(&sample) = self.output.get(self.output_index) {
+                self.output_index += 1;
+                return Some(sample);
+            }
+            self.output.clear();
+            self.output_index = 0;
+
+            if self.stretching {
+                self.step();
+                continue;
+            }
+            if self.channel == 0 {
+                let ratio = self.controls.stretch();
+                if (ratio - 1.0).abs() >= BYPASS && !self.ended {
+                    self.start(ratio);
+                    continue;
+                }
+                self.channels = self.source.channels().max(1);
+            }
+            let sample = self.source.next()?;
+            self.channel = (self.channel + 1) % self.channels;
+            return Some(sample);
+        }
+    }
+}
+
+impl<S> Source for TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        match (self.output.len() - self.output_index, self.stretching) {
+            (0, false) => self.source.current_frame_len(),
+            // At least one more frame comes in the same format.
+            (0, true) => Some(self.channels as usize),
+            (waiting, _) => Some(waiting),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self.stretching || self.output_index < self.output.len() {
+            true => self.channels,
+            false => self.source.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self.stretching || self.output_index < self.output.len() {
+            true => self.sample_rate,
+            false => self.source.sample_rate(),
+        }
+    }
+
+    /// Depends on a tempo that may change while playing.
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// `pos` is in source time, whatever the tempo.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.input.clear();
+        self.output.clear();
+        self.output_index = 0;
+        self.stretching = false;
+        self.channel = 0;
+        self.ended = false;
+        Ok(())
+    }
+}
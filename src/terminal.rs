@@ -0,0 +1,144 @@
+//! Keyboard controls for the command-line player.
+
+use crate::status::StatusLine;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal,
+};
+use fullyrustaudio::AudioPlayer;
+use std::{
+    io, panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+const SEEK_STEP: Duration = Duration::from_secs(5);
+const VOLUME_STEP_DB: f32 = 2.0;
+
+/// How often the input thread checks whether it should exit, and the main
+/// thread whether playback has ended and redraws the status line.
+const POLL: Duration = Duration::from_millis(100);
+
+pub const KEYS: &str = "space: play/pause  ←/→: seek 5 s  ↑/↓: volume  e: toggle EQ  q: quit";
+
+/// Keeps the terminal in raw mode while alive.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        // Restore the terminal from a panic on any thread, not just the one
+        // holding the guard.
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = terminal::disable_raw_mode();
+            default_hook(info);
+        }));
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Reads keys on a thread of its own and applies them to `player` until the
+/// queue ends or `q` is pressed. `idle` is called from this thread at every
+/// [`POLL`] in between, as `status` is redrawn.
+pub fn run(
+    player: Arc<AudioPlayer>,
+    status: Option<StatusLine>,
+    idle: impl Fn(),
+) -> io::Result<()> {
+    let raw_mode = RawMode::enable()?;
+    // Errors from the keys are printed over the status line, not into it.
+    let status = Arc::new(Mutex::new(status));
+    let done = Arc::new(AtomicBool::new(false));
+    let (quit, quit_requested) = mpsc::channel();
+
+    let input = thread::spawn({
+        let player = player.clone();
+        let done = done.clone();
+        let status = status.clone();
+        move || -> io::Result<()> {
+            while !done.load(Ordering::Relaxed) {
+                if !event::poll(POLL)? {
+                    continue;
+                }
+                let Event::Key(key) = event::read()? else {
+                    continue;
+                };
+                if key.kind == KeyEventKind::Release {
+                    continue;
+                }
+                if is_quit(key) {
+                    let _ = quit.send(());
+                    break;
+                }
+                if let Err(err) = handle_key(&player, key.code) {
+                    let mut status = status.lock().unwrap_or_else(PoisonError::into_inner);
+                    if let Some(status) = status.as_mut() {
+                        status.clear()?;
+                    }
+                    // Raw mode needs the explicit carriage return.
+                    eprint!("{err}\r\n");
+                }
+            }
+            Ok(())
+        }
+    });
+
+    while !player.is_finished() {
+        idle();
+        let mut line = status.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(line) = line.as_mut() {
+            line.draw(&player)?;
+        }
+        drop(line);
+        match quit_requested.recv_timeout(POLL) {
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            _ => break,
+        }
+    }
+    done.store(true, Ordering::Relaxed);
+    let result = input.join().unwrap_or(Ok(()));
+    drop(status);
+    drop(raw_mode);
+
+    player.stop();
+    result
+}
+
+fn is_quit(key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        // Raw mode swallows the signal, so Ctrl+C has to be handled here.
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+fn handle_key(player: &AudioPlayer, code: KeyCode) -> Result<(), Box<dyn std::error::Error>> {
+    match code {
+        KeyCode::Char(' ') => {
+            if player.is_playing() {
+                player.pause();
+            } else {
+                player.play()?;
+            }
+        }
+        KeyCode::Left => player.seek_backward(SEEK_STEP)?,
+        KeyCode::Right => player.seek_forward(SEEK_STEP)?,
+        KeyCode::Up => player.set_volume_db(player.volume_db() + VOLUME_STEP_DB),
+        KeyCode::Down => player.set_volume_db(player.volume_db() - VOLUME_STEP_DB),
+        KeyCode::Char('e') => player.set_eq_enabled(!player.eq_enabled()),
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,255 @@
+//! Signals, measurements and players the unit tests share. The signals come
+//! from the seeded generators, so every run hears the same samples, and the
+//! players play through [`Backend::Null`], so no sound card is needed.
+
+use crate::{
+    backend::Backend,
+    engine::AudioEngine,
+    events::PlayerEvent,
+    generators::{GeneratorSettings, SineWave, WhiteNoise},
+    lock::Lock,
+    player::AudioPlayer,
+    suspend::WallClock,
+};
+use rodio::{buffer::SamplesBuffer, Source};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Samples at the start of a filtered signal left out of a measurement,
+/// while the filters settle: about 20 ms at 44.1 kHz.
+pub(crate) const SETTLE: usize = 1024;
+
+/// A second of a sine at `frequency`, peaking at -12 dBFS on every channel.
+pub(crate) fn sine(frequency: f32, sample_rate: u32, channels: u16) -> SamplesBuffer<f32> {
+    collect(SineWave::new(frequency, second(sample_rate, channels)))
+}
+
+/// A second of white noise from `seed`, the same on every channel.
+pub(crate) fn noise(seed: u64, sample_rate: u32, channels: u16) -> SamplesBuffer<f32> {
+    collect(WhiteNoise::new(seed, second(sample_rate, channels)))
+}
+
+/// A one-second signal in this format at the generators' level.
+pub(crate) fn second(sample_rate: u32, channels: u16) -> GeneratorSettings {
+    GeneratorSettings {
+        sample_rate,
+        channels,
+        duration: Some(Duration::from_secs(1)),
+        ..GeneratorSettings::default()
+    }
+}
+
+/// Plays all of `source` into a buffer of the same format.
+pub(crate) fn collect(source: impl Source<Item = f32>) -> SamplesBuffer<f32> {
+    let (channels, sample_rate) = (source.channels(), source.sample_rate());
+    SamplesBuffer::new(channels, sample_rate, source.collect::<Vec<_>>())
+}
+
+/// Samples of `channel` out of interleaved `samples` of `channels`.
+pub(crate) fn channel(samples: &[f32], channels: u16, channel: usize) -> Vec<f32> {
+    samples
+        .iter()
+        .skip(channel)
+        .step_by(usize::from(channels))
+        .copied()
+        .collect()
+}
+
+pub(crate) fn peak(samples: &[f32]) -> f32 {
+    samples
+        .iter()
+        .fold(0.0, |peak, sample| sample.abs().max(peak))
+}
+
+pub(crate) fn rms(samples: &[f32]) -> f32 {
+    let power = samples.iter().map(|&sample| sample * sample).sum::<f32>();
+    (power / samples.len().max(1) as f32).sqrt()
+}
+
+/// How much louder `output` is than `input` in dB, by RMS, leaving out the
+/// first `skip` samples of both.
+pub(crate) fn gain_db(input: &[f32], output: &[f32], skip: usize) -> f32 {
+    let input = rms(&input[skip.min(input.len())..]);
+    let output = rms(&output[skip.min(output.len())..]);
+    20.0 * (output / input).log10()
+}
+
+/// A WAV file in the temp dir for a player to open, removed once dropped.
+pub(crate) struct WavFile {
+    pub(crate) path: PathBuf,
+}
+
+impl WavFile {
+    /// `duration` of a 440 Hz sine as 16-bit stereo at 44.1 kHz, in a file
+    /// named for `name`, which no other test may use.
+    pub(crate) fn sine(name: &str, duration: Duration) -> Self {
+        WavFile::sine_at(name, duration, 44100)
+    }
+
+    /// [`WavFile::sine`] at `sample_rate`.
+    pub(crate) fn sine_at(name: &str, duration: Duration, sample_rate: u32) -> Self {
+        let settings = GeneratorSettings {
+            sample_rate,
+            channels: 2,
+            duration: Some(duration),
+            ..GeneratorSettings::default()
+        };
+        let path = env::temp_dir().join(format!("fullyrustaudio-{}-{name}.wav", process::id()));
+        let spec = hound::WavSpec {
+            channels: settings.channels,
+            sample_rate: settings.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in SineWave::new(440.0, settings) {
+            writer
+                .write_sample((sample * i16::MAX as f32) as i16)
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+        WavFile { path }
+    }
+}
+
+impl Drop for WavFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A FLAC file in the temp dir, removed once dropped. Its frames are
+/// stored verbatim, and rodio's FLAC decoder can't seek, so it stands in
+/// for any file that can't.
+pub(crate) struct FlacFile {
+    pub(crate) path: PathBuf,
+}
+
+impl FlacFile {
+    /// Samples per channel in each frame.
+    const BLOCK: usize = 4096;
+
+    /// `duration` of a 440 Hz sine as 16-bit stereo at 44.1 kHz, made up to
+    /// a whole frame with silence, in a file named for `name`, which no
+    /// other test may use.
+    pub(crate) fn sine(name: &str, duration: Duration) -> Self {
+        let settings = GeneratorSettings {
+            sample_rate: 44100,
+            channels: 2,
+            duration: Some(duration),
+            ..GeneratorSettings::default()
+        };
+        let samples = SineWave::new(440.0, settings)
+            .map(|sample| (sample * i16::MAX as f32) as i16)
+            .collect::<Vec<_>>();
+        let frames = samples.len().div_ceil(2 * Self::BLOCK);
+        let mut bytes = b"fLaC".to_vec();
+        // The last metadata block, STREAMINFO, 34 bytes long: the block
+        // sizes, unknown frame sizes, then 44.1 kHz, two channels, 16 bits
+        // and the length packed in 64 bits, and no MD5.
+        bytes.extend([0x80, 0, 0, 34]);
+        bytes.extend([(Self::BLOCK as u16).to_be_bytes(); 2].concat());
+        bytes.extend([0; 6]);
+        let length = (frames * Self::BLOCK) as u64;
+        bytes.extend(u64::to_be_bytes(44100 << 44 | 1 << 41 | 15 << 36 | length));
+        bytes.extend([0; 16]);
+        for (number, block) in samples.chunks(2 * Self::BLOCK).enumerate() {
+            let start = bytes.len();
+            // Fixed blocks of 4096 at 44.1 kHz, independent stereo, 16 bits,
+            // then the frame number coded as UTF-8 codes a character.
+            bytes.extend([0xFF, 0xF8, 0xC9, 0x18]);
+            let number = char::from_u32(number as u32).unwrap();
+            bytes.extend(number.to_string().as_bytes());
+            bytes.push(crc8(&bytes[start..]));
+            for channel in 0..2 {
+                // A verbatim subframe.
+                bytes.push(0x02);
+                for frame in 0..Self::BLOCK {
+                    let sample = block.get(frame * 2 + channel).copied().unwrap_or(0);
+                    bytes.extend(sample.to_be_bytes());
+                }
+            }
+            let crc = crc16(&bytes[start..]);
+            bytes.extend(crc.to_be_bytes());
+        }
+        let path = env::temp_dir().join(format!("fullyrustaudio-{}-{name}.flac", process::id()));
+        fs::write(&path, bytes).unwrap();
+        FlacFile { path }
+    }
+}
+
+impl Drop for FlacFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// FLAC's CRC-8 of a frame header.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x07,
+        })
+    })
+}
+
+/// FLAC's CRC-16 of a whole frame.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x8005,
+        })
+    })
+}
+
+/// An empty player on an engine of its own, playing into nothing.
+pub(crate) fn null_player() -> AudioPlayer {
+    AudioEngine::with_backend(Backend::Null, None)
+        .and_then(|engine| engine.new_player())
+        .unwrap()
+}
+
+/// The first of `events` that `wanted` picks within `timeout`.
+pub(crate) fn wait_for(
+    events: &Receiver<PlayerEvent>,
+    timeout: Duration,
+    wanted: impl Fn(&PlayerEvent) -> bool,
+) -> Option<PlayerEvent> {
+    let deadline = Instant::now() + timeout;
+    while let Ok(event) = events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        if wanted(&event) {
+            return Some(event);
+        }
+    }
+    None
+}
+
+/// The system's clocks, set on by however far they have been made to jump,
+/// as through a suspend. Clones jump together.
+#[derive(Clone, Default)]
+pub(crate) struct JumpingClock {
+    /// How far on the monotonic and the system clock are.
+    ahead: Arc<Mutex<(Duration, Duration)>>,
+}
+
+impl JumpingClock {
+    pub(crate) fn jump(&self, instant: Duration, system: Duration) {
+        let mut ahead = self.ahead.locked();
+        ahead.0 += instant;
+        ahead.1 += system;
+    }
+}
+
+impl WallClock for JumpingClock {
+    fn now(&self) -> (Instant, SystemTime) {
+        let (instant, system) = *self.ahead.locked();
+        (Instant::now() + instant, SystemTime::now() + system)
+    }
+}
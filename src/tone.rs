@@ -0,0 +1,355 @@
+use crate::{
+    atomic::AtomicF32,
+    dsp::{BiquadFilter, FilterType},
+    equalizer::{response, EqChange, EqControls, SHELF_Q},
+    gain::db_to_linear,
+    mailbox::Mailbox,
+    settings::EqSettings,
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Most [`AudioPlayer::set_bass_boost`] and [`AudioPlayer::set_treble`]
+/// take, in dB.
+///
+/// [`AudioPlayer::set_bass_boost`]: crate::AudioPlayer::set_bass_boost
+/// [`AudioPlayer::set_treble`]: crate::AudioPlayer::set_treble
+pub const MAX_TONE_DB: f32 = 12.0;
+/// Corner of the bass boost's low shelf.
+pub const BASS_FREQUENCY: f32 = 100.0;
+/// Corner of the treble's high shelf.
+pub const TREBLE_FREQUENCY: f32 = 8000.0;
+/// How far either knob of [`AudioPlayer::set_tone`] turns, in dB.
+///
+/// [`AudioPlayer::set_tone`]: crate::AudioPlayer::set_tone
+pub const MAX_TONE_KNOB_DB: f32 = 10.0;
+
+/// Time constant of the glide to a new preamp.
+const PREAMP_GLIDE: Duration = Duration::from_millis(20);
+/// How long a change to either shelf takes to glide in.
+const TONE_GLIDE: Duration = Duration::from_millis(30);
+
+/// Bass and treble shelves, kept apart from the EQ settings so a preset
+/// survives them, and shared by every track's [`Tone`] stage.
+pub(crate) struct ToneControls {
+    bass_db: AtomicF32,
+    treble_db: AtomicF32,
+    version: AtomicU64,
+}
+
+impl ToneControls {
+    pub(crate) fn new() -> Self {
+        ToneControls {
+            bass_db: AtomicF32::new(0.0),
+            treble_db: AtomicF32::new(0.0),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn bass_db(&self) -> f32 {
+        self.bass_db.load()
+    }
+
+    pub(crate) fn set_bass_db(&self, db: f32) {
+        self.bass_db.store(db);
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    pub(crate) fn treble_db(&self) -> f32 {
+        self.treble_db.load()
+    }
+
+    pub(crate) fn set_treble_db(&self, db: f32) {
+        self.treble_db.store(db);
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Sets both shelves as one change, so they glide together.
+    pub(crate) fn set(&self, bass_db: f32, treble_db: f32) {
+        self.bass_db.store(bass_db);
+        self.treble_db.store(treble_db);
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    /// The gain of `eq` then the shelves, trim included, at `points`
+    /// frequencies on the grid of [`EqControls::frequency_response`].
+    pub(crate) fn response(
+        &self,
+        eq: &EqControls,
+        channel: usize,
+        points: usize,
+    ) -> Vec<(f32, f32)> {
+        let sample_rate = eq.response_rate();
+        let mut shelves = [BiquadFilter::flat(), BiquadFilter::flat()];
+        tune(&mut shelves, self.bass_db(), self.treble_db(), sample_rate);
+        let trim_db = trim_db(eq, &eq.settings(), eq.is_enabled(), &shelves, sample_rate);
+        let tone = response(&shelves, -trim_db, sample_rate, points);
+        eq.frequency_response(channel, points)
+            .into_iter()
+            .zip(tone)
+            .map(|((frequency, eq_db), (_, tone_db))| (frequency, eq_db + tone_db))
+            .collect()
+    }
+}
+
+/// Sets `shelves` to boost or cut by `bass_db` and `treble_db`. A shelf the
+/// rate can't carry stays flat.
+fn tune(shelves: &mut [BiquadFilter; 2], bass_db: f32, treble_db: f32, sample_rate: u32) {
+    let nyquist = sample_rate as f32 / 2.0;
+    let settings = [
+        (FilterType::LowShelf, BASS_FREQUENCY, bass_db),
+        (FilterType::HighShelf, TREBLE_FREQUENCY, treble_db),
+    ];
+    for (filter, (kind, frequency, gain_db)) in shelves.iter_mut().zip(settings) {
+        let gain_db = if frequency < nyquist { gain_db } else { 0.0 };
+        filter.set_params(
+            kind,
+            frequency.min(nyquist * 0.9),
+            SHELF_Q,
+            gain_db,
+            sample_rate,
+        );
+    }
+}
+
+/// How far to turn the level down so `shelves` can't clip: by what they add
+/// to the peak of the EQ before them, playing `settings`. With the EQ's auto
+/// headroom on, that makes the pair peak at 0 dB together.
+fn trim_db(
+    eq: &EqControls,
+    settings: &EqSettings,
+    enabled: bool,
+    shelves: &[BiquadFilter; 2],
+    sample_rate: u32,
+) -> f32 {
+    let (alone, with_shelves) = eq.peaks_with(settings, enabled, shelves, sample_rate);
+    (with_shelves - alone.max(0.0)).max(0.0)
+}
+
+/// The bass and treble shelves, layered after the EQ whether or not it is
+/// on.
+///
+/// Like the EQ bands, a change glides in over [`TONE_GLIDE`], only
+/// recomputing the coefficients while the filters carry on. The preamp
+/// glides to make room for what the shelves add to the EQ's peak, so a
+/// boost can't clip; with both at 0 dB the source passes through untouched.
+pub(crate) struct Tone<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<ToneControls>,
+    version: u64,
+    eq: Arc<EqControls>,
+    /// The EQ's settings and on/off state the preamp was last sized for,
+    /// kept up to date through `eq_updates`.
+    eq_settings: Arc<EqSettings>,
+    eq_enabled: bool,
+    eq_updates: Arc<Mailbox<EqChange>>,
+    /// The bass and treble shelf of each channel.
+    chains: Vec<[BiquadFilter; 2]>,
+    /// The bass and treble gains the shelves are at, on their way to the
+    /// controls' while a change glides in.
+    tuned: (f32, f32),
+    /// Frames until `tuned` gets there; 0 when nothing is changing.
+    glide_left: u32,
+    sample_rate: u32,
+    flat: bool,
+    preamp: f32,
+    target_preamp: f32,
+    glide: f32,
+    channel: usize,
+}
+
+impl<S> Tone<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<ToneControls>, eq: Arc<EqControls>) -> Self {
+        let mut tone = Tone {
+            source,
+            version: controls.version.load(Ordering::Acquire),
+            tuned: (controls.bass_db(), controls.treble_db()),
+            controls,
+            eq_updates: eq.subscribe(),
+            eq_settings: Arc::new(eq.settings()),
+            eq_enabled: eq.is_enabled(),
+            eq,
+            chains: Vec::new(),
+            glide_left: 0,
+            sample_rate: 0,
+            flat: true,
+            preamp: 1.0,
+            target_preamp: 1.0,
+            glide: 1.0,
+            channel: 0,
+        };
+        tone.rebuild();
+        tone.preamp = tone.target_preamp;
+        tone
+    }
+
+    /// Allocates a pair of shelves per channel of the source.
+    fn rebuild(&mut self) {
+        let channels = self.source.channels().max(1) as usize;
+        self.chains = (0..channels)
+            .map(|_| [BiquadFilter::flat(), BiquadFilter::flat()])
+            .collect();
+        self.retune();
+    }
+
+    /// Tunes the shelves to the controls at once, as for a new rate.
+    fn retune(&mut self) {
+        let sample_rate = self.source.sample_rate().max(1);
+        self.sample_rate = sample_rate;
+        self.glide = 1.0 - (-1.0 / (PREAMP_GLIDE.as_secs_f32() * sample_rate as f32)).exp();
+        self.glide_left = 0;
+        self.tuned = (self.controls.bass_db(), self.controls.treble_db());
+        self.tune();
+        self.size_preamp();
+    }
+
+    /// Starts the shelves gliding to the controls, and sizes the preamp for
+    /// where they end up.
+    fn start_glide(&mut self) {
+        let frames = TONE_GLIDE.as_secs_f32() * self.sample_rate as f32;
+        if frames < 1.0 {
+            self.retune();
+            return;
+        }
+        self.glide_left = frames as u32;
+        self.size_preamp();
+    }
+
+    /// Moves the shelves one frame on towards the controls.
+    fn step_glide(&mut self) {
+        let left = self.glide_left as f32;
+        let (bass_db, treble_db) = (self.controls.bass_db(), self.controls.treble_db());
+        self.tuned.0 += (bass_db - self.tuned.0) / left;
+        self.tuned.1 += (treble_db - self.tuned.1) / left;
+        self.glide_left -= 1;
+        if self.glide_left == 0 {
+            self.tuned = (bass_db, treble_db);
+        }
+        self.tune();
+    }
+
+    fn tune(&mut self) {
+        let (bass_db, treble_db) = self.tuned;
+        for chain in &mut self.chains {
+            tune(chain, bass_db, treble_db, self.sample_rate);
+        }
+        let was_flat = self.flat;
+        let nyquist = self.sample_rate as f32 / 2.0;
+        self.flat = bass_db == 0.0 && (treble_db == 0.0 || TREBLE_FREQUENCY >= nyquist);
+        if was_flat && !self.flat {
+            self.chains
+                .iter_mut()
+                .flatten()
+                .for_each(BiquadFilter::reset);
+        }
+    }
+
+    /// Sets the preamp's target for the shelves the controls ask for, on top
+    /// of the EQ as it is now.
+    fn size_preamp(&mut self) {
+        let mut shelves = [BiquadFilter::flat(), BiquadFilter::flat()];
+        let (bass_db, treble_db) = (self.controls.bass_db(), self.controls.treble_db());
+        tune(&mut shelves, bass_db, treble_db, self.sample_rate);
+        self.eq_enabled = self.eq.is_enabled();
+        let trim_db = trim_db(
+            &self.eq,
+            &self.eq_settings,
+            self.eq_enabled,
+            &shelves,
+            self.sample_rate,
+        );
+        self.target_preamp = db_to_linear(-trim_db);
+    }
+}
+
+impl<S> Iterator for Tone<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            let version = self.controls.version.load(Ordering::Acquire);
+            if self.source.channels().max(1) as usize != self.chains.len() {
+                self.version = version;
+                self.rebuild();
+            } else if self.source.sample_rate() != self.sample_rate {
+                self.version = version;
+                self.retune();
+            } else if version != self.version {
+                self.version = version;
+                self.start_glide();
+            } else if let Some(change) = self.eq_updates.take() {
+                self.eq_settings = change.settings;
+                self.size_preamp();
+            } else if self.eq.is_enabled() != self.eq_enabled {
+                self.size_preamp();
+            }
+            if self.glide_left > 0 {
+                self.step_glide();
+            }
+            let preamp = self.preamp + (self.target_preamp - self.preamp) * self.glide;
+            // Close in, the step rounds away to nothing and it would stall.
+            self.preamp = match (preamp - self.target_preamp).abs() < 1e-5 || preamp == self.preamp
+            {
+                true => self.target_preamp,
+                false => preamp,
+            };
+        }
+
+        let sample = self.source.next()?;
+        let channel = self.channel;
+        self.channel = (channel + 1) % self.chains.len();
+        // Wait for the preamp to come back up before dropping the shelves,
+        // or the level would jump.
+        if self.flat && self.preamp == 1.0 {
+            return Some(sample);
+        }
+        let [bass, treble] = &mut self.chains[channel];
+        Some(treble.process(bass.process(sample * self.preamp)))
+    }
+}
+
+impl<S> Source for Tone<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.chains
+            .iter_mut()
+            .flatten()
+            .for_each(BiquadFilter::reset);
+        self.channel = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use rodio::{source::SeekError, Source};
+use std::time::Duration;
+
+/// The part of its file a queue entry plays; see
+/// [`AudioPlayer::enqueue_with_range`](crate::AudioPlayer::enqueue_with_range).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackRange {
+    /// Where in the file the entry starts.
+    pub start: Duration,
+    /// Where in the file it ends, or `None` for the end of the file.
+    pub end: Option<Duration>,
+}
+
+impl TrackRange {
+    /// How long the entry plays, out of a file lasting `duration`.
+    pub(crate) fn length(&self, duration: Option<Duration>) -> Option<Duration> {
+        self.end
+            .or(duration)
+            .map(|end| end.saturating_sub(self.start))
+    }
+}
+
+/// Plays only a [`TrackRange`] of the decoder, as if the file held nothing
+/// else: it starts at the range's start, ends at its end, and seeks and
+/// the duration are within it.
+pub(crate) struct Trim<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    range: TrackRange,
+    /// Samples left before the range's end.
+    remaining: Option<u64>,
+}
+
+impl<S> Trim<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Gets to the range's start by seeking, or else by decoding up to it.
+    pub(crate) fn new(mut source: S, range: TrackRange) -> Self {
+        if !range.start.is_zero() && source.try_seek(range.start).is_err() {
+            let skip = samples(&source, range.start);
+            for _ in (&mut source).take(skip as usize) {}
+        }
+        let mut trim = Trim {
+            source,
+            range,
+            remaining: None,
+        };
+        trim.remaining = trim.samples_to_end(Duration::ZERO);
+        trim
+    }
+
+    /// The samples from `position` in the range to its end.
+    fn samples_to_end(&self, position: Duration) -> Option<u64> {
+        let end = self.range.end?;
+        let left = end.saturating_sub(self.range.start + position);
+        Some(samples(&self.source, left))
+    }
+}
+
+/// Whole frames of `source` in `time`, counted in samples.
+fn samples<S: Source<Item = f32>>(source: &S, time: Duration) -> u64 {
+    let frames = (time.as_secs_f64() * source.sample_rate() as f64).round() as u64;
+    frames * source.channels().max(1) as u64
+}
+
+impl<S> Iterator for Trim<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.checked_sub(1)?;
+        }
+        self.source.next()
+    }
+}
+
+impl<S> Source for Trim<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        match (self.source.current_frame_len(), self.remaining) {
+            (Some(len), Some(remaining)) => Some(len.min(remaining as usize)),
+            (len, None) => len,
+            (None, remaining) => remaining.map(|remaining| remaining as usize),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.range.length(self.source.total_duration())
+    }
+
+    /// `position` is within the range, and taken as its end if past that.
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        let position = self
+            .total_duration()
+            .map_or(position, |length| position.min(length));
+        self.source.try_seek(self.range.start + position)?;
+        self.remaining = self.samples_to_end(position);
+        Ok(())
+    }
+}
@@ -0,0 +1,441 @@
+use crate::{
+    atomic::AtomicF32,
+    dsp::{BiquadFilter, FilterType},
+    events::{PlayerEvent, Signal},
+};
+use rodio::{source::SeekError, Source};
+use std::{
+    f32::consts::FRAC_1_SQRT_2,
+    sync::{mpsc::Sender, Arc},
+    time::Duration,
+};
+
+/// The band taken out of the center, so bass and cymbals stay in.
+pub const VOCAL_LOW_FREQUENCY: f32 = 200.0;
+pub const VOCAL_HIGH_FREQUENCY: f32 = 8000.0;
+
+/// How long a change of amount takes to settle, so switching mid-song
+/// doesn't click.
+const VOCAL_RAMP: Duration = Duration::from_millis(50);
+
+/// Time constant of the level the make-up gain follows.
+const MAKEUP_WINDOW: Duration = Duration::from_secs(1);
+/// The Q of each half of a [`Crossover`], and of its allpass.
+const BUTTERWORTH_Q: f32 = FRAC_1_SQRT_2;
+/// Most the make-up gain brings back, as a linear factor (+6 dB).
+const MAX_MAKEUP: f32 = 2.0;
+
+/// Vocal reduction shared between the player and each track's
+/// [`VocalReduction`] stage.
+pub(crate) struct VocalControls {
+    amount: AtomicF32,
+}
+
+impl VocalControls {
+    pub(crate) fn new() -> Self {
+        VocalControls {
+            amount: AtomicF32::new(0.0),
+        }
+    }
+
+    pub(crate) fn amount(&self) -> f32 {
+        self.amount.load()
+    }
+
+    /// Clamped to 0.0 (off) ..= 1.0.
+    pub(crate) fn set_amount(&self, amount: f32) {
+        self.amount.store(amount.clamp(0.0, 1.0));
+    }
+}
+
+/// Takes center-panned sound, where vocals usually sit, out of a stereo
+/// pair: the mid (sum) signal loses `amount` of what lies between
+/// [`VOCAL_LOW_FREQUENCY`] and [`VOCAL_HIGH_FREQUENCY`], and the side
+/// (difference) signal is left alone. A make-up gain, from the level of
+/// the band against the whole over [`MAKEUP_WINDOW`], keeps the loudness
+/// about where it was.
+///
+/// The band comes from Linkwitz-Riley crossovers, which add back up to an
+/// allpass; running the rest of the signal through the same allpass is what
+/// lets only the band go, so a sound panned hard to one side stays there.
+///
+/// Off, and for sources that aren't exactly two channels, the signal passes
+/// through untouched; the first time one of those plays with the reduction
+/// on, a [`PlayerEvent::Warning`] says so. The amount glides over
+/// [`VOCAL_RAMP`], and so does the fade between the untouched signal and the
+/// allpassed one when the stage comes on or goes off.
+pub(crate) struct VocalReduction<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    controls: Arc<VocalControls>,
+    signals: Sender<Signal>,
+    amount: f32,
+    /// How far the output is from the untouched signal to the processed
+    /// one: 1.0 while the reduction is on, gliding to 0.0 once it is off.
+    mix: f32,
+    /// The mid splits at each edge of the band.
+    lower: Crossover,
+    upper: Crossover,
+    /// Gives what is below the band the phase of the upper crossover, and
+    /// the side that of both.
+    low_phase: BiquadFilter,
+    side_phase: [BiquadFilter; 2],
+    /// The rate the filters are tuned for.
+    sample_rate: u32,
+    /// Running means of the pair's power, of the band times the pair, and
+    /// of the band's power: what the power coming out works out from for
+    /// any amount, so the make-up follows the amount without lag.
+    power: f32,
+    cross: f32,
+    band_power: f32,
+    warned: bool,
+    /// The right sample of the current frame, once computed.
+    right: Option<f32>,
+}
+
+impl<S> VocalReduction<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(source: S, controls: Arc<VocalControls>, signals: Sender<Signal>) -> Self {
+        let amount = controls.amount();
+        let mut stage = VocalReduction {
+            source,
+            amount,
+            mix: if amount > 0.0 { 1.0 } else { 0.0 },
+            controls,
+            signals,
+            lower: Crossover::new(),
+            upper: Crossover::new(),
+            low_phase: BiquadFilter::flat(),
+            side_phase: [BiquadFilter::flat(), BiquadFilter::flat()],
+            sample_rate: 0,
+            power: 0.0,
+            cross: 0.0,
+            band_power: 0.0,
+            warned: false,
+            right: None,
+        };
+        stage.retune();
+        stage
+    }
+
+    fn retune(&mut self) {
+        let sample_rate = self.source.sample_rate().max(1);
+        self.sample_rate = sample_rate;
+        let nyquist = sample_rate as f32 / 2.0;
+        let low = VOCAL_LOW_FREQUENCY.min(nyquist * 0.9);
+        let high = VOCAL_HIGH_FREQUENCY.min(nyquist * 0.9);
+        self.lower.tune(low, sample_rate);
+        self.upper.tune(high, sample_rate);
+        let [side_low, side_high] = &mut self.side_phase;
+        for (filter, frequency) in [
+            (&mut self.low_phase, high),
+            (side_low, low),
+            (side_high, high),
+        ] {
+            filter.set_params(
+                FilterType::Notch,
+                frequency,
+                BUTTERWORTH_Q,
+                0.0,
+                sample_rate,
+            );
+        }
+    }
+
+    /// Starts afresh, as when the reduction comes on.
+    fn reset(&mut self) {
+        self.lower.reset();
+        self.upper.reset();
+        self.low_phase.reset();
+        self.side_phase.iter_mut().for_each(BiquadFilter::reset);
+        self.power = 0.0;
+        self.cross = 0.0;
+        self.band_power = 0.0;
+    }
+
+    /// The gain that brings the pair back to the power it came in with,
+    /// once `amount` of the band has gone from both sides.
+    fn makeup(&self, amount: f32) -> f32 {
+        let power_out =
+            self.power - 2.0 * amount * self.cross + 2.0 * amount * amount * self.band_power;
+        if power_out <= f32::MIN_POSITIVE {
+            return 1.0;
+        }
+        (self.power / power_out).sqrt().clamp(1.0, MAX_MAKEUP)
+    }
+
+    fn warn(&mut self) {
+        if !self.warned {
+            self.warned = true;
+            let message = format!(
+                "vocal reduction needs a stereo source; this {}-channel track plays as it is",
+                self.source.channels()
+            );
+            let _ = self
+                .signals
+                .send(Signal::Event(PlayerEvent::Warning(message)));
+        }
+    }
+}
+
+impl<S> Iterator for VocalReduction<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        let target = self.controls.amount();
+        // Anything but a stereo pair passes through. Pairs are read whole,
+        // so a change of format always falls between them.
+        if self.source.channels() != 2 {
+            if target > 0.0 {
+                self.warn();
+            }
+            self.amount = 0.0;
+            self.mix = 0.0;
+            return self.source.next();
+        }
+
+        let mix_target = if target > 0.0 { 1.0 } else { 0.0 };
+        if target != self.amount || mix_target != self.mix {
+            if self.mix == 0.0 {
+                self.reset();
+            }
+            let step = 1.0 / (VOCAL_RAMP.as_secs_f32() * self.source.sample_rate().max(1) as f32);
+            self.amount += (target - self.amount).clamp(-step, step);
+            self.mix += (mix_target - self.mix).clamp(-step, step);
+        }
+        let left = self.source.next()?;
+        let Some(right) = self.source.next() else {
+            return Some(left);
+        };
+        if self.mix == 0.0 {
+            self.right = Some(right);
+            return Some(left);
+        }
+        if self.source.sample_rate() != self.sample_rate {
+            self.retune();
+        }
+
+        let mid = (left + right) * 0.5;
+        let side = (left - right) * 0.5;
+        let (below, rest) = self.lower.split(mid);
+        let (band, above) = self.upper.split(rest);
+        let mid = allpass(&mut self.low_phase, below) + band + above;
+        let side = self
+            .side_phase
+            .iter_mut()
+            .fold(side, |side, filter| allpass(filter, side));
+        let (pass_left, pass_right) = (mid + side, mid - side);
+
+        let follow = 1.0 / (MAKEUP_WINDOW.as_secs_f32() * self.sample_rate as f32);
+        self.power += (pass_left * pass_left + pass_right * pass_right - self.power) * follow;
+        self.cross += (band * (pass_left + pass_right) - self.cross) * follow;
+        self.band_power += (band * band - self.band_power) * follow;
+
+        // Taking the band out of the mid takes it out of both sides alike.
+        let gain = self.makeup(self.amount);
+        let band = band * self.amount;
+        let mix = |dry: f32, wet: f32| dry + (wet * gain - dry) * self.mix;
+        self.right = Some(mix(right, pass_right - band));
+        Some(mix(left, pass_left - band))
+    }
+}
+
+/// A fourth-order Linkwitz-Riley crossover: what is below and what is above
+/// its frequency, which add up to the input through a second-order allpass.
+struct Crossover {
+    low: [BiquadFilter; 2],
+    high: [BiquadFilter; 2],
+}
+
+impl Crossover {
+    fn new() -> Self {
+        Crossover {
+            low: [BiquadFilter::flat(), BiquadFilter::flat()],
+            high: [BiquadFilter::flat(), BiquadFilter::flat()],
+        }
+    }
+
+    fn tune(&mut self, frequency: f32, sample_rate: u32) {
+        for filter in &mut self.low {
+            filter.set_params(
+                FilterType::LowPass,
+                frequency,
+                BUTTERWORTH_Q,
+                0.0,
+                sample_rate,
+            );
+        }
+        for filter in &mut self.high {
+            filter.set_params(
+                FilterType::HighPass,
+                frequency,
+                BUTTERWORTH_Q,
+                0.0,
+                sample_rate,
+            );
+        }
+    }
+
+    fn reset(&mut self) {
+        self.low
+            .iter_mut()
+            .chain(&mut self.high)
+            .for_each(BiquadFilter::reset);
+    }
+
+    fn split(&mut self, input: f32) -> (f32, f32) {
+        let low = self
+            .low
+            .iter_mut()
+            .fold(input, |x, filter| filter.process(x));
+        let high = self
+            .high
+            .iter_mut()
+            .fold(input, |x, filter| filter.process(x));
+        (low, high)
+    }
+}
+
+/// The allpass a [`Crossover`] at the same frequency adds up to, from a
+/// notch tuned there: at this Q, one minus twice the band the notch takes.
+fn allpass(notch: &mut BiquadFilter, input: f32) -> f32 {
+    2.0 * notch.process(input) - input
+}
+
+impl<S> Source for VocalReduction<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        let pending = usize::from(self.right.is_some());
+        self.source.current_frame_len().map(|len| len + pending)
+    }
+
+    fn channels(&self) -> u16 {
+        match self.right {
+            Some(_) => 2,
+            None => self.source.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.right = None;
+        self.lower.reset();
+        self.upper.reset();
+        self.low_phase.reset();
+        self.side_phase.iter_mut().for_each(BiquadFilter::reset);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gain::linear_to_db,
+        generators::{GeneratorSettings, SineWave},
+    };
+    use rodio::buffer::SamplesBuffer;
+    use std::{f32::consts::TAU, sync::mpsc};
+
+    const RATE: u32 = 48000;
+    /// In the band, where the voice would be.
+    const CENTER: f32 = 1000.0;
+    /// Below it, where the make-up is all that may change it.
+    const PANNED: f32 = 60.0;
+
+    /// Three seconds of [`CENTER`] in both channels and [`PANNED`] on the
+    /// left alone, each at -12 dBFS.
+    fn mix() -> Vec<f32> {
+        let tone = |frequency| {
+            SineWave::new(
+                frequency,
+                GeneratorSettings {
+                    sample_rate: RATE,
+                    channels: 1,
+                    duration: Some(Duration::from_secs(3)),
+                    ..GeneratorSettings::default()
+                },
+            )
+        };
+        tone(CENTER)
+            .zip(tone(PANNED))
+            .flat_map(|(center, panned)| [center + panned, center])
+            .collect()
+    }
+
+    /// The amplitude of `frequency` in `channel` over the last second,
+    /// where the make-up has settled: a whole number of cycles of both tones.
+    fn level(samples: &[f32], channel: usize, frequency: f32) -> f32 {
+        let last = &samples[samples.len() - 2 * RATE as usize..];
+        let (mut sin, mut cos) = (0.0, 0.0);
+        for (index, frame) in last.chunks(2).enumerate() {
+            let phase = TAU * frequency * index as f32 / RATE as f32;
+            sin += frame[channel] * phase.sin();
+            cos += frame[channel] * phase.cos();
+        }
+        2.0 * (sin * sin + cos * cos).sqrt() / RATE as f32
+    }
+
+    fn reduced(samples: &[f32], amount: f32) -> Vec<f32> {
+        let controls = Arc::new(VocalControls::new());
+        controls.set_amount(amount);
+        let source = SamplesBuffer::new(2, RATE, samples.to_vec());
+        VocalReduction::new(source, controls, mpsc::channel().0).collect()
+    }
+
+    #[test]
+    fn the_center_goes_by_the_amount_and_a_panned_tone_stays_put() {
+        let dry = mix();
+        for (amount, center_db) in [(0.5, linear_to_db(0.5)), (1.0, f32::NEG_INFINITY)] {
+            let wet = reduced(&dry, amount);
+            let change = |channel, frequency| {
+                linear_to_db(level(&wet, channel, frequency) / level(&dry, channel, frequency))
+            };
+            let makeup_db = change(0, PANNED);
+            assert!(
+                (0.0..=6.1).contains(&makeup_db),
+                "at {amount}, the panned tone moved by {makeup_db} dB"
+            );
+            let stray = level(&wet, 1, PANNED) / level(&wet, 0, PANNED);
+            assert!(
+                linear_to_db(stray) < -40.0,
+                "at {amount}, {} dB of the panned tone crossed over",
+                linear_to_db(stray)
+            );
+            for channel in 0..2 {
+                // Relative to the panned tone, which the make-up lifts alike.
+                let center_change_db = change(channel, CENTER) - makeup_db;
+                match center_db.is_finite() {
+                    true => assert!(
+                        (center_change_db - center_db).abs() < 0.3,
+                        "at {amount}, the center went by {center_change_db} dB"
+                    ),
+                    false => assert!(
+                        center_change_db < -30.0,
+                        "at {amount}, the center went by {center_change_db} dB"
+                    ),
+                }
+            }
+        }
+    }
+}
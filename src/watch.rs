@@ -0,0 +1,292 @@
+use crate::render::BATCH_EXTENSIONS;
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often a watched file is looked at.
+const WATCH_POLL: Duration = Duration::from_millis(100);
+
+/// How long a file must stay as it is after a change before it counts, so
+/// an editor that writes it twice in a row only reloads it once.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How often a watched folder is scanned.
+const FOLDER_POLL: Duration = Duration::from_millis(500);
+
+/// The order files found together are queued in by
+/// [`AudioPlayer::watch_folder`](crate::AudioPlayer::watch_folder).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FolderOrder {
+    /// By path, so by name within a folder.
+    #[default]
+    Name,
+    /// Least recently modified first.
+    Modified,
+}
+
+/// How [`AudioPlayer::watch_folder`](crate::AudioPlayer::watch_folder)
+/// watches a folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderWatchOptions {
+    pub order: FolderOrder,
+    /// Watch the folders inside it too, all the way down.
+    pub recursive: bool,
+    /// The extensions of the files to queue, without the dot, in any case.
+    pub extensions: Vec<String>,
+    /// How long a new file must keep the same size and modification time
+    /// before it is queued, so one still being copied in isn't opened half
+    /// written.
+    pub settle: Duration,
+}
+
+impl Default for FolderWatchOptions {
+    fn default() -> Self {
+        FolderWatchOptions {
+            order: FolderOrder::Name,
+            recursive: false,
+            extensions: BATCH_EXTENSIONS.map(String::from).to_vec(),
+            settle: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Watches a file by polling its modification time and size on a thread of
+/// its own, for a change that has settled. Dropping it stops the thread and
+/// waits for it, so once it's gone no more changes are reported.
+pub(crate) struct FileWatch {
+    path: PathBuf,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileWatch {
+    /// Calls `changed` on the watch's thread each time the file at `path`
+    /// has changed and then stayed the same for [`WATCH_DEBOUNCE`]. While
+    /// the file is missing, as when an editor replaces it, nothing is
+    /// reported until it is back.
+    pub(crate) fn spawn(path: PathBuf, mut changed: impl FnMut() + Send + 'static) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let watched = path.clone();
+        let thread = thread::spawn(move || {
+            let mut seen = stamp(&watched);
+            let mut changed_at = None;
+            loop {
+                match stopped.recv_timeout(WATCH_POLL) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+                let now = stamp(&watched);
+                if now != seen {
+                    seen = now;
+                    changed_at = Some(Instant::now());
+                } else if seen.is_some()
+                    && changed_at.is_some_and(|at: Instant| at.elapsed() >= WATCH_DEBOUNCE)
+                {
+                    changed_at = None;
+                    changed();
+                }
+            }
+        });
+        FileWatch {
+            path,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FileWatch {
+    fn drop(&mut self) {
+        // Hanging up wakes the thread at once.
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Watches a folder by scanning it on a thread of its own, for files that
+/// appear and settle or that are deleted. Dropping it stops the thread and
+/// waits for it, as [`FileWatch`] does.
+pub(crate) struct FolderWatch {
+    path: PathBuf,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FolderWatch {
+    /// Calls `found` for each file in the folder at `path` that `options`
+    /// take, before returning for those that had settled already and on
+    /// the watch's thread for the rest and for any that appear after. Files
+    /// found together go in `options.order`. `gone` is called on the watch's
+    /// thread for a file that was found and has since been deleted. Hidden
+    /// files, as partial downloads often are, are left out. Fails if the
+    /// folder can't be read.
+    pub(crate) fn spawn(
+        path: PathBuf,
+        options: FolderWatchOptions,
+        mut found: impl FnMut(&Path) + Send + 'static,
+        mut gone: impl FnMut(&Path) + Send + 'static,
+    ) -> io::Result<Self> {
+        let mut files = HashMap::new();
+        scan(&path, &options, &mut files)?;
+        let now = SystemTime::now();
+        let (settled, settling): (Vec<_>, Vec<_>) = files.into_iter().partition(|(_, stamp)| {
+            stamp
+                .0
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age >= options.settle)
+        });
+        let mut known = HashSet::new();
+        for (file, _) in in_order(settled, options.order) {
+            found(&file);
+            known.insert(file);
+        }
+        // Files written to lately are given the whole settle time.
+        let mut settling = settling
+            .into_iter()
+            .map(|(file, stamp)| (file, (stamp, Instant::now())))
+            .collect::<HashMap<_, _>>();
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let watched = path.clone();
+        let thread = thread::spawn(move || {
+            let mut files = HashMap::new();
+            loop {
+                match stopped.recv_timeout(FOLDER_POLL) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+                files.clear();
+                // A folder that is there but can't be read is tried again; one
+                // that's gone takes its files with it.
+                if scan(&watched, &options, &mut files).is_err() && watched.exists() {
+                    continue;
+                }
+
+                known.retain(|file| {
+                    let kept = files.contains_key(file);
+                    if !kept {
+                        gone(file);
+                    }
+                    kept
+                });
+                settling.retain(|file, _| files.contains_key(file));
+                let mut ready = Vec::new();
+                for (file, stamp) in files.drain() {
+                    if known.contains(&file) {
+                        continue;
+                    }
+                    match settling.get_mut(&file) {
+                        Some((seen, since)) if *seen != stamp => {
+                            *seen = stamp;
+                            *since = Instant::now();
+                        }
+                        Some((_, since)) if since.elapsed() >= options.settle => {
+                            settling.remove(&file);
+                            ready.push((file, stamp));
+                        }
+                        Some(_) => {}
+                        None => {
+                            settling.insert(file, (stamp, Instant::now()));
+                        }
+                    }
+                }
+                for (file, _) in in_order(ready, options.order) {
+                    found(&file);
+                    known.insert(file);
+                }
+            }
+        });
+        Ok(FolderWatch {
+            path,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FolderWatch {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            // The player can be dropped from `found` or `gone`, on the
+            // thread itself, which then stops once they return.
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Adds the files in `dir` that `options` take to `files`, with their
+/// stamps. Folders inside it that can't be read are skipped.
+fn scan(
+    dir: &Path,
+    options: &FolderWatchOptions,
+    files: &mut HashMap<PathBuf, Stamp>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        if hidden {
+            continue;
+        } else if kind.is_dir() {
+            if options.recursive {
+                let _ = scan(&path, options, files);
+            }
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                options
+                    .extensions
+                    .iter()
+                    .any(|taken| ext.eq_ignore_ascii_case(taken))
+            })
+        {
+            if let Some(stamp) = stamp(&path) {
+                files.insert(path, stamp);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `files` in `order`, by path where that leaves them level.
+fn in_order(mut files: Vec<(PathBuf, Stamp)>, order: FolderOrder) -> Vec<(PathBuf, Stamp)> {
+    match order {
+        FolderOrder::Name => files.sort_by(|a, b| a.0.cmp(&b.0)),
+        FolderOrder::Modified => files.sort_by(|a, b| (a.1 .0, &a.0).cmp(&(b.1 .0, &b.0))),
+    }
+    files
+}
+
+/// A file's modification time and size.
+type Stamp = (Option<SystemTime>, u64);
+
+/// What tells one version of a file from the next, or `None` while it's
+/// missing.
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
@@ -0,0 +1,93 @@
+//! Whole-file waveform overviews, e.g. for drawing behind a seek bar.
+//!
+//! Files are decoded on their own, so generating an overview never touches
+//! the decoder a player is using. The result is plain data for the caller to
+//! cache however it likes.
+
+use crate::{
+    decode::{self, DecoderBackend},
+    probe::file_duration,
+};
+use std::{
+    error::Error,
+    fs::File,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+pub type WaveformError = Box<dyn Error + Send + Sync>;
+
+/// Reduces the file at `path`, downmixed to mono, to `buckets` (min, max) pairs.
+pub fn generate(path: impl AsRef<Path>, buckets: usize) -> Result<Vec<(f32, f32)>, WaveformError> {
+    generate_with_progress(path, buckets, |_| {})
+}
+
+/// Like [`generate`], calling `progress` with the fraction done, from 0.0 to 1.0.
+pub fn generate_with_progress(
+    path: impl AsRef<Path>,
+    buckets: usize,
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<(f32, f32)>, WaveformError> {
+    let path = path.as_ref();
+    let buckets = buckets.max(1);
+    let backend = DecoderBackend::Auto.resolve(path);
+    let decoder = decode::open(File::open(path)?, path, backend)?;
+    let channels = decoder.channels().max(1) as usize;
+    let sample_rate = decoder.sample_rate();
+    let frames = file_duration(path, decoder.total_duration())
+        .map(|duration| (duration.as_secs_f64() * sample_rate as f64).ceil() as usize);
+
+    let mut decoded = decoder;
+    let mut samples = std::iter::from_fn(move || {
+        let mut sum = decoded.next()?;
+        for _ in 1..channels {
+            sum += decoded.next().unwrap_or(0.0);
+        }
+        Some(sum / channels as f32)
+    });
+
+    let Some(frames) = frames.filter(|&frames| frames > 0) else {
+        // Unknown length: gather everything first, then split it evenly.
+        let all = samples.by_ref().collect::<Vec<_>>();
+        progress(1.0);
+        return Ok(reduce(&all, buckets));
+    };
+
+    let mut peaks = vec![(0.0f32, 0.0f32); buckets];
+    let step = (frames / 100).max(1);
+    for (index, sample) in samples.enumerate() {
+        let bucket = (index * buckets / frames).min(buckets - 1);
+        let (min, max) = &mut peaks[bucket];
+        *min = min.min(sample);
+        *max = max.max(sample);
+        if index % step == 0 {
+            progress((index as f32 / frames as f32).min(1.0));
+        }
+    }
+    progress(1.0);
+    Ok(peaks)
+}
+
+/// Runs [`generate_with_progress`] on a thread of its own.
+pub fn spawn(
+    path: impl Into<PathBuf>,
+    buckets: usize,
+    progress: impl FnMut(f32) + Send + 'static,
+) -> JoinHandle<Result<Vec<(f32, f32)>, WaveformError>> {
+    let path = path.into();
+    thread::spawn(move || generate_with_progress(path, buckets, progress))
+}
+
+fn reduce(samples: &[f32], buckets: usize) -> Vec<(f32, f32)> {
+    (0..buckets)
+        .map(|bucket| {
+            let start = bucket * samples.len() / buckets;
+            let end = ((bucket + 1) * samples.len() / buckets).max(start);
+            samples[start..end]
+                .iter()
+                .fold((0.0f32, 0.0f32), |(min, max), &sample| {
+                    (min.min(sample), max.max(sample))
+                })
+        })
+        .collect()
+}
@@ -0,0 +1,116 @@
+//! AutoEq `ParametricEQ.txt` profiles, laid out as AutoEq writes them
+//! (CRLF line endings and all, for the over-ear one), read into EQ settings.
+
+use fullyrustaudio::{EqBand, EqError, EqSettings, FilterType};
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> PathBuf {
+    [
+        env!("CARGO_MANIFEST_DIR"),
+        "tests",
+        "fixtures",
+        "autoeq",
+        name,
+    ]
+    .iter()
+    .collect()
+}
+
+fn band(kind: FilterType, frequency: f32, gain_db: f32, q: f32) -> EqBand {
+    EqBand {
+        kind,
+        frequency,
+        gain_db,
+        q,
+    }
+}
+
+fn peaking(frequency: f32, gain_db: f32, q: f32) -> EqBand {
+    band(FilterType::Peaking, frequency, gain_db, q)
+}
+
+/// The line of the parse error `result` fails with.
+fn error_line(result: Result<EqSettings, EqError>) -> usize {
+    match result {
+        Err(EqError::Parse(err)) => err.line,
+        other => panic!("expected a parse error, got {other:?}"),
+    }
+}
+
+#[test]
+fn a_profile_with_shelves_reads_in_file_order() {
+    let settings = EqSettings::load(fixture("over-ear ParametricEQ.txt")).unwrap();
+    assert_eq!(settings.preamp_db, -6.4);
+    assert!(!settings.auto_headroom);
+    assert_eq!(
+        settings.bands,
+        [
+            band(FilterType::LowShelf, 105.0, 5.8, 0.7),
+            peaking(164.0, -2.6, 0.51),
+            peaking(1241.0, 1.1, 1.93),
+            peaking(2636.0, -3.2, 2.41),
+            peaking(3916.0, 4.0, 3.12),
+            peaking(5367.0, -2.9, 4.35),
+            peaking(6864.0, 2.2, 3.86),
+            peaking(8104.0, -1.6, 5.4),
+            peaking(12187.0, -1.3, 1.31),
+            band(FilterType::HighShelf, 10000.0, 2.9, 0.7),
+        ]
+    );
+    settings.validate(44100).unwrap();
+}
+
+#[test]
+fn a_profile_of_peaking_filters_reads_in_file_order() {
+    let settings = EqSettings::load_autoeq(fixture("in-ear ParametricEQ.txt")).unwrap();
+    assert_eq!(settings.preamp_db, -4.1);
+    assert_eq!(
+        settings.bands,
+        [
+            peaking(21.0, -3.4, 0.96),
+            peaking(96.0, -2.2, 1.63),
+            peaking(2250.0, -3.2, 1.41),
+            peaking(3350.0, 4.1, 2.37),
+            peaking(8806.0, 5.9, 3.1),
+            peaking(14043.0, -2.1, 0.83),
+        ]
+    );
+    settings.validate(44100).unwrap();
+}
+
+#[test]
+fn filters_that_are_off_are_left_out() {
+    let settings = EqSettings::from_autoeq(
+        "Preamp: -2 dB\n\
+         Filter 1: ON PK Fc 100 Hz Gain 2 dB Q 1\n\
+         Filter 2: OFF PK Fc 200 Hz Gain 9 dB Q 1\n\
+         Filter 3: ON PK Fc 300 Hz Gain -2 dB Q 1\n",
+    )
+    .unwrap();
+    assert_eq!(
+        settings.bands,
+        [peaking(100.0, 2.0, 1.0), peaking(300.0, -2.0, 1.0)]
+    );
+}
+
+#[test]
+fn malformed_lines_fail_naming_their_line() {
+    let profile = |broken: &str| {
+        format!("Preamp: -4.1 dB\nFilter 1: ON PK Fc 96 Hz Gain -2.2 dB Q 1.63\n{broken}\n")
+    };
+    for broken in [
+        "Filter 2: ON XX Fc 2250 Hz Gain -3.2 dB Q 1.41",
+        "Filter 2: ON PK Fc 2250 Gain -3.2 dB Q 1.41",
+        "Filter 2: ON PK Fc loud Hz Gain -3.2 dB Q 1.41",
+        "Filter 2: ON PK Fc 2250 Hz Q 1.41",
+        "Filter 2: MAYBE PK Fc 2250 Hz Gain -3.2 dB Q 1.41",
+        "Loudness: on",
+    ] {
+        assert_eq!(
+            error_line(EqSettings::from_autoeq(&profile(broken))),
+            3,
+            "{broken}"
+        );
+    }
+    assert_eq!(error_line(EqSettings::from_autoeq("Preamp: a lot\n")), 1);
+}
@@ -0,0 +1,247 @@
+//! Calls a player from several threads at once, a random mix of transport,
+//! seeks, queue changes, EQ toggles and reads, to shake out deadlocks and
+//! panics. A thread that makes no progress for a while is reported as
+//! stuck, which fails the test. The player plays through
+//! [`Backend::Null`], so no sound card is needed.
+
+use fullyrustaudio::{AudioPlayer, Backend, EqSettings, RepeatMode};
+use std::{
+    env,
+    f32::consts::TAU,
+    fs,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const THREADS: usize = 8;
+/// Calls per thread; a pause waits out its fade, so more take long.
+const ITERATIONS: usize = 500;
+/// How long a thread may go without finishing a call before it counts as stuck.
+const STUCK: Duration = Duration::from_secs(10);
+
+/// The calls made, by the index [`Thread::op`] holds.
+const OPS: &[&str] = &[
+    "play",
+    "pause",
+    "stop",
+    "seek",
+    "scrub",
+    "get_playback_position",
+    "now_playing",
+    "set_eq_enabled",
+    "set_volume_db",
+    "next",
+    "previous",
+    "enqueue",
+    "remove",
+    "set_shuffle",
+    "queue",
+    "state",
+    "stats",
+    "set_speed",
+    "set_repeat",
+    "play_at",
+    "cancel_play_at",
+    "insert_at",
+];
+
+/// What one hammering thread is up to, for the watchdog.
+#[derive(Default)]
+struct Thread {
+    /// Calls finished so far.
+    done: AtomicUsize,
+    /// Index into [`OPS`] of the call under way.
+    op: AtomicUsize,
+    /// Set once it has made all its calls, or panicked.
+    finished: AtomicBool,
+}
+
+/// Sets [`Thread::finished`] however the thread ends.
+struct Finish(Arc<Thread>);
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        self.0.finished.store(true, Ordering::Release);
+    }
+}
+
+#[test]
+fn concurrent_calls_neither_deadlock_nor_panic() {
+    let path = tone();
+    let player = AudioPlayer::with_backend(&path, EqSettings::default(), Backend::Null, None)
+        .map(Arc::new)
+        .unwrap();
+    // Something to move between and take off again.
+    for _ in 0..3 {
+        player.enqueue(&path).unwrap();
+    }
+
+    let threads = (0..THREADS)
+        .map(|_| Arc::new(Thread::default()))
+        .collect::<Vec<_>>();
+    let workers = threads
+        .iter()
+        .enumerate()
+        .map(|(index, thread)| {
+            let (player, thread, path) = (player.clone(), thread.clone(), path.clone());
+            thread::spawn(move || {
+                let _finish = Finish(thread.clone());
+                hammer(&player, &thread, &path, index as u32);
+            })
+        })
+        .collect::<Vec<_>>();
+    watch(&threads);
+    let panicked = workers
+        .into_iter()
+        .map(thread::JoinHandle::join)
+        .filter(Result::is_err)
+        .count();
+    drop(player);
+    let _ = fs::remove_file(&path);
+    assert_eq!(panicked, 0, "{panicked} of {THREADS} threads panicked");
+}
+
+/// Writes two seconds of a 440 Hz tone to a WAV file of its own.
+fn tone() -> PathBuf {
+    let path = env::temp_dir().join(format!("fullyrustaudio-stress-{}.wav", process::id()));
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 44100,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for frame in 0..2 * spec.sample_rate {
+        let phase = TAU * 440.0 * frame as f32 / spec.sample_rate as f32;
+        let sample = (phase.sin() * 0.25 * i16::MAX as f32) as i16;
+        writer.write_sample(sample).unwrap();
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+    path
+}
+
+/// Makes [`ITERATIONS`] random calls on `player`.
+fn hammer(player: &AudioPlayer, thread: &Thread, path: &PathBuf, seed: u32) {
+    let mut random = XorShift((0x9e37_79b9 ^ seed.wrapping_mul(0x85eb_ca6b)) | 1);
+    for _ in 0..ITERATIONS {
+        let op = random.below(OPS.len() as u32) as usize;
+        thread.op.store(op, Ordering::Relaxed);
+        // Errors, such as seeking with nothing queued, are fine here;
+        // only hanging or panicking isn't.
+        match OPS[op] {
+            "play" => drop(player.play()),
+            "pause" => player.pause(),
+            "stop" => player.stop(),
+            "seek" => {
+                let duration = player.duration().unwrap_or(Duration::from_secs(1));
+                let at = duration.mul_f64(random.below(1_000) as f64 / 1_000.0);
+                drop(player.seek(at));
+            }
+            "scrub" => {
+                let duration = player.duration().unwrap_or(Duration::from_secs(1));
+                let at = duration.mul_f64(random.below(1_000) as f64 / 1_000.0);
+                drop(player.scrub(at));
+            }
+            "get_playback_position" => drop(player.get_playback_position()),
+            "now_playing" => {
+                if let Some(now) = player.now_playing() {
+                    assert!(
+                        now.duration.is_none_or(|duration| now.position <= duration),
+                        "position {:?} past duration {:?}",
+                        now.position,
+                        now.duration
+                    );
+                }
+            }
+            "set_eq_enabled" => player.set_eq_enabled(!player.eq_enabled()),
+            "set_volume_db" => player.set_volume_db(-(random.below(30) as f32)),
+            "next" => drop(player.next()),
+            "previous" => drop(player.previous()),
+            "enqueue" if player.queue().len() < 8 => drop(player.enqueue(path)),
+            "remove" => {
+                let queue = player.queue();
+                if queue.len() > 2 {
+                    let item = &queue[random.below(queue.len() as u32) as usize];
+                    drop(player.remove(item.id));
+                }
+            }
+            "set_shuffle" => player.set_shuffle(!player.is_shuffled()),
+            "queue" => drop(player.queue()),
+            "state" => drop(player.state()),
+            "stats" => drop(player.stats()),
+            "set_speed" => player.set_speed(0.5 + random.below(4) as f32 * 0.5),
+            "set_repeat" => player.set_repeat(match random.below(3) {
+                0 => RepeatMode::Off,
+                1 => RepeatMode::One,
+                _ => RepeatMode::All,
+            }),
+            "play_at" => {
+                let at = Instant::now() + Duration::from_millis(random.below(20).into());
+                drop(player.play_at(at));
+            }
+            "cancel_play_at" => drop(player.cancel_play_at()),
+            "insert_at" if player.queue().len() < 8 => {
+                drop(player.insert_at(random.below(4) as usize, path));
+            }
+            _ => {}
+        }
+        thread.done.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns once every thread has finished, or panics naming the call each
+/// is in if any goes [`STUCK`] without finishing one.
+fn watch(threads: &[Arc<Thread>]) {
+    let mut seen = vec![(0, Instant::now()); threads.len()];
+    while !threads
+        .iter()
+        .all(|thread| thread.finished.load(Ordering::Acquire))
+    {
+        thread::sleep(Duration::from_millis(100));
+        for (thread, (done, since)) in threads.iter().zip(&mut seen) {
+            let now = thread.done.load(Ordering::Relaxed);
+            if now != *done {
+                *done = now;
+                *since = Instant::now();
+            }
+        }
+        let stuck = threads.iter().zip(&seen).any(|(thread, (_, since))| {
+            !thread.finished.load(Ordering::Acquire) && since.elapsed() >= STUCK
+        });
+        if stuck {
+            let calls = threads
+                .iter()
+                .enumerate()
+                .map(
+                    |(index, thread)| match thread.finished.load(Ordering::Acquire) {
+                        true => format!("thread {index}: finished"),
+                        false => format!(
+                            "thread {index}: in {}",
+                            OPS[thread.op.load(Ordering::Relaxed)]
+                        ),
+                    },
+                )
+                .collect::<Vec<_>>();
+            panic!("stuck for {STUCK:?}: {}", calls.join(", "));
+        }
+    }
+}
+
+/// A small fast generator; each thread seeds its own.
+struct XorShift(u32);
+
+impl XorShift {
+    fn below(&mut self, bound: u32) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 % bound
+    }
+}